@@ -1,7 +1,8 @@
 use serde_json::{Map, Value};
 
 use rag_toolchain::clients::{
-    AnthropicChatCompletionClient, AnthropicModel, AsyncChatClient, PromptMessage,
+    AnthropicChatCompletionClient, AnthropicCompletionStream, AnthropicModel, AsyncChatClient,
+    AsyncStreamedChatClient, ChatCompletionStream, CompletionStreamValue, PromptMessage,
 };
 
 #[tokio::main]
@@ -29,4 +30,17 @@ async fn main() {
         .unwrap();
 
     println!("{:?}", reply.content());
+
+    // We can also stream the response back
+    let mut stream: AnthropicCompletionStream = client
+        .invoke_stream(vec![system_message, user_message])
+        .await
+        .unwrap();
+
+    while let Some(stream_value) = stream.next().await {
+        if let CompletionStreamValue::Message(msg) = stream_value.unwrap() {
+            print!("{}", msg.content());
+        }
+    }
+    println!();
 }
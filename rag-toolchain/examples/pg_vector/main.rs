@@ -1,23 +1,29 @@
 use rag_toolchain::chunkers::{Chunker, TokenChunker};
 use rag_toolchain::clients::{AsyncEmbeddingClient, OpenAIEmbeddingClient};
-use rag_toolchain::common::{Chunks, Embedding, OpenAIEmbeddingModel};
+use rag_toolchain::common::{Chunks, Document, Embedding, OpenAIEmbeddingModel};
+use rag_toolchain::loaders::{LoadSource, SingleFileSource};
 use rag_toolchain::stores::{EmbeddingStore, PostgresVectorStore};
 
 #[tokio::main]
 async fn main() {
     const EMBEDDING_MODEL: OpenAIEmbeddingModel = OpenAIEmbeddingModel::TextEmbeddingAda002;
 
-    // We read in the text from a file
-    let text = std::fs::read_to_string("examples/pg_vector/example_text.txt").unwrap();
+    // We load the file as a document, its metadata holds the path and when it was modified
+    let source = SingleFileSource::new("examples/pg_vector/example_text.txt");
+    let documents: Vec<Document> = source.load_documents().unwrap();
 
-    // Create a new chunker and generate the chunks
+    // Create a new chunker and generate the chunks, each chunk carries the metadata
+    // of its document so it is stored alongside the embedding
     let chunker = TokenChunker::try_new(
         std::num::NonZeroUsize::new(50).unwrap(),
         25,
         EMBEDDING_MODEL,
     )
     .unwrap();
-    let chunks: Chunks = chunker.generate_chunks(&text).unwrap();
+    let chunks: Chunks = documents
+        .iter()
+        .flat_map(|document| chunker.chunk_document(document).unwrap())
+        .collect();
 
     // I would check your store initialized before sending of embeddings to openai
     let store: PostgresVectorStore = PostgresVectorStore::try_new("embeddings", EMBEDDING_MODEL)
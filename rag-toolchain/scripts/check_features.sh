@@ -0,0 +1,55 @@
+#!/usr/bin/env bash
+# Checks that the crate compiles (including the unit tests) for each supported
+# feature combination. This catches cfg gates that have rotted because nothing
+# builds with that feature turned off. Run from the rag-toolchain directory.
+#
+# If cargo-hack is installed every feature combination is checked, otherwise
+# we fall back to the list of combinations below.
+set -euo pipefail
+
+cd "$(dirname "$0")/.."
+
+if cargo hack --version >/dev/null 2>&1; then
+    cargo hack check --feature-powerset --no-dev-deps --lib
+    cargo hack check --each-feature --lib --tests
+    exit 0
+fi
+
+FEATURE_SETS=(
+    ""
+    "pg_vector"
+    "openai"
+    "openai-embeddings"
+    "openai-chat"
+    "openai-stream"
+    "openai-embeddings,openai-chat"
+    "anthropic"
+    "anthropic-stream"
+    "ollama"
+    "pg_vector,ollama"
+    "cohere"
+    "pg_vector,cohere"
+    "bedrock"
+    "embedding-cache"
+    "pg_vector,openai-embeddings"
+    "pg_vector,openai-chat,openai-embeddings"
+    "analysis"
+    "html"
+    "sqlite_vec"
+    "pg_vector,sqlite_vec"
+    "serde"
+    "pg_vector,serde"
+    "tracing"
+    "pg_vector,openai,tracing"
+)
+
+for features in "${FEATURE_SETS[@]}"; do
+    echo "==> checking features: [${features}]"
+    cargo check --no-default-features --features "${features}" --lib --tests --examples
+done
+
+echo "==> checking features: [default]"
+cargo check --all-targets
+
+echo "==> checking features: [default,serde]"
+cargo check --all-targets --features serde
@@ -0,0 +1,570 @@
+use crate::common::{Chunk, Embedding};
+#[cfg(feature = "pg_vector")]
+use crate::stores::{PostgresVectorStore, PostgresVectorStoreError};
+use std::cmp::Reverse;
+use std::num::NonZeroUsize;
+use thiserror::Error;
+
+/// The seed used by [`KMeans`] unless [`KMeans::with_seed`] is called.
+pub const DEFAULT_SEED: u64 = 42;
+/// The number of iterations [`KMeans`] stops after if it has not converged.
+pub const DEFAULT_MAX_ITERATIONS: usize = 100;
+/// The number of representatives reported for each cluster.
+pub const DEFAULT_REPRESENTATIVES: usize = 3;
+
+/// # [`cluster_table`]
+///
+/// Gives a rough picture of what a vector table holds by sampling it and clustering the sample
+/// with [`KMeans`] using the default settings.
+///
+/// # Arguments
+/// * `store`: &[`PostgresVectorStore`] - The store whose table is clustered.
+/// * `k`: [`NonZeroUsize`] - The number of clusters.
+/// * `sample_size`: [`NonZeroUsize`] - The maximum number of rows to sample.
+///
+/// # Errors
+/// * [`ClusteringError::ScanError`] - If the table could not be read.
+/// * [`ClusteringError::NotEnoughEmbeddings`] - If the table has fewer than k rows.
+///
+/// # Returns
+/// * [`ClusterReport`] - The clusters found in the sample.
+///
+/// # Examples
+/// ```
+/// use rag_toolchain::analysis::*;
+/// use rag_toolchain::stores::*;
+/// use std::num::NonZeroUsize;
+///
+/// async fn explore(store: &PostgresVectorStore) {
+///     let k = NonZeroUsize::new(8).unwrap();
+///     let sample_size = NonZeroUsize::new(5000).unwrap();
+///     let report: ClusterReport = cluster_table(store, k, sample_size).await.unwrap();
+///     for cluster in report.clusters {
+///         println!("{} chunks like {:?}", cluster.size, cluster.representatives[0].chunk);
+///     }
+/// }
+/// ```
+#[cfg(feature = "pg_vector")]
+pub async fn cluster_table(
+    store: &PostgresVectorStore,
+    k: NonZeroUsize,
+    sample_size: NonZeroUsize,
+) -> Result<ClusterReport, ClusteringError> {
+    KMeans::new(k).cluster_table(store, sample_size).await
+}
+
+/// # [`KMeans`]
+///
+/// Lloyd's algorithm for k-means clustering of embeddings, seeded with k-means++. All of the
+/// randomness comes from the seed so the same embeddings and seed always give the same report.
+/// This is meant for offline exploration of a corpus and holds every vector in memory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct KMeans {
+    k: NonZeroUsize,
+    seed: u64,
+    max_iterations: usize,
+    representatives: usize,
+}
+
+impl KMeans {
+    /// # [`KMeans::new`]
+    ///
+    /// # Arguments
+    /// * `k`: [`NonZeroUsize`] - The number of clusters.
+    ///
+    /// # Returns
+    /// * [`KMeans`] - Using [`DEFAULT_SEED`], [`DEFAULT_MAX_ITERATIONS`] and [`DEFAULT_REPRESENTATIVES`].
+    pub fn new(k: NonZeroUsize) -> Self {
+        KMeans {
+            k,
+            seed: DEFAULT_SEED,
+            max_iterations: DEFAULT_MAX_ITERATIONS,
+            representatives: DEFAULT_REPRESENTATIVES,
+        }
+    }
+
+    /// # [`KMeans::with_seed`]
+    ///
+    /// # Arguments
+    /// * `seed`: [`u64`] - The seed for the initial centroids and the table sample.
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    /// # [`KMeans::with_max_iterations`]
+    ///
+    /// # Arguments
+    /// * `max_iterations`: [`NonZeroUsize`] - The number of iterations to stop after if the
+    ///   clusters have not converged.
+    pub fn with_max_iterations(mut self, max_iterations: NonZeroUsize) -> Self {
+        self.max_iterations = max_iterations.get();
+        self
+    }
+
+    /// # [`KMeans::with_representatives`]
+    ///
+    /// # Arguments
+    /// * `representatives`: [`usize`] - How many of the chunks closest to each centroid to report.
+    pub fn with_representatives(mut self, representatives: usize) -> Self {
+        self.representatives = representatives;
+        self
+    }
+
+    /// # [`KMeans::cluster_table`]
+    ///
+    /// Samples the store's table with [`PostgresVectorStore::sample`] and clusters the sample.
+    ///
+    /// # Arguments
+    /// * `store`: &[`PostgresVectorStore`] - The store whose table is clustered.
+    /// * `sample_size`: [`NonZeroUsize`] - The maximum number of rows to sample.
+    ///
+    /// # Errors
+    /// * [`ClusteringError::ScanError`] - If the table could not be read.
+    /// * [`ClusteringError::NotEnoughEmbeddings`] - If the table has fewer than k rows.
+    ///
+    /// # Returns
+    /// * [`ClusterReport`] - The clusters found in the sample.
+    #[cfg(feature = "pg_vector")]
+    pub async fn cluster_table(
+        &self,
+        store: &PostgresVectorStore,
+        sample_size: NonZeroUsize,
+    ) -> Result<ClusterReport, ClusteringError> {
+        let embeddings: Vec<Embedding> = store
+            .sample(sample_size, self.seed)
+            .await
+            .map_err(ClusteringError::ScanError)?;
+        self.fit(&embeddings)
+    }
+
+    /// # [`KMeans::fit`]
+    ///
+    /// # Arguments
+    /// * `embeddings`: &[[`Embedding`]] - The embeddings to cluster.
+    ///
+    /// # Errors
+    /// * [`ClusteringError::NotEnoughEmbeddings`] - If there are fewer embeddings than clusters.
+    /// * [`ClusteringError::DimensionMismatch`] - If the embeddings have different dimensions.
+    ///
+    /// # Returns
+    /// * [`ClusterReport`] - The clusters, largest first.
+    pub fn fit(&self, embeddings: &[Embedding]) -> Result<ClusterReport, ClusteringError> {
+        let k: usize = self.k.get();
+        if embeddings.len() < k {
+            Err(ClusteringError::NotEnoughEmbeddings {
+                k,
+                found: embeddings.len(),
+            })?
+        }
+        let vectors: Vec<Vec<f32>> = embeddings.iter().map(Embedding::vector).collect();
+        let expected: usize = vectors[0].len();
+        if let Some(vector) = vectors.iter().find(|vector| vector.len() != expected) {
+            Err(ClusteringError::DimensionMismatch {
+                expected,
+                found: vector.len(),
+            })?
+        }
+
+        let mut rng = SplitMix64::new(self.seed);
+        let mut centroids: Vec<Vec<f32>> = initial_centroids(&vectors, k, &mut rng);
+        let mut assignments: Vec<usize> = vec![usize::MAX; vectors.len()];
+        let mut iterations: usize = 0;
+        let mut converged: bool = false;
+        while iterations < self.max_iterations {
+            iterations += 1;
+            if !assign(&vectors, &centroids, &mut assignments) {
+                converged = true;
+                break;
+            }
+            centroids = update_centroids(&vectors, &centroids, &assignments);
+        }
+        if !converged {
+            // Keep the assignments consistent with the centroids that are reported
+            assign(&vectors, &centroids, &mut assignments);
+        }
+
+        let mut clusters: Vec<Cluster> = centroids
+            .into_iter()
+            .enumerate()
+            .map(|(cluster, centroid)| {
+                let mut members: Vec<(f32, usize)> = assignments
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, assigned)| **assigned == cluster)
+                    .map(|(index, _)| (squared_distance(&vectors[index], &centroid), index))
+                    .collect();
+                members.sort_by(|a, b| a.0.total_cmp(&b.0).then(a.1.cmp(&b.1)));
+                Cluster {
+                    size: members.len(),
+                    representatives: members
+                        .iter()
+                        .take(self.representatives)
+                        .map(|(distance, index)| Representative {
+                            chunk: embeddings[*index].chunk().clone(),
+                            distance: distance.sqrt(),
+                        })
+                        .collect(),
+                    centroid,
+                }
+            })
+            .collect();
+        // Stable so clusters of the same size keep their order
+        clusters.sort_by_key(|cluster| Reverse(cluster.size));
+
+        Ok(ClusterReport {
+            clusters,
+            sample_size: vectors.len(),
+            iterations,
+            converged,
+        })
+    }
+}
+
+/// # [`ClusterReport`]
+///
+/// The result of clustering a set of embeddings with [`KMeans`].
+///
+/// * `clusters` - the clusters, largest first.
+/// * `sample_size` - the number of embeddings that were clustered.
+/// * `iterations` - the number of iterations of Lloyd's algorithm that were run.
+/// * `converged` - whether the clusters stopped changing before the iteration limit.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ClusterReport {
+    pub clusters: Vec<Cluster>,
+    pub sample_size: usize,
+    pub iterations: usize,
+    pub converged: bool,
+}
+
+/// # [`Cluster`]
+///
+/// * `size` - the number of embeddings in the cluster.
+/// * `centroid` - the mean of the embeddings in the cluster.
+/// * `representatives` - the chunks closest to the centroid, closest first.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Cluster {
+    pub size: usize,
+    pub centroid: Vec<f32>,
+    pub representatives: Vec<Representative>,
+}
+
+/// # [`Representative`]
+///
+/// * `chunk` - the content and metadata of the chunk.
+/// * `distance` - the euclidean distance from the chunk's embedding to the centroid.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Representative {
+    pub chunk: Chunk,
+    pub distance: f32,
+}
+
+/// # [`ClusteringError`]
+///
+/// The errors that can occur when clustering embeddings.
+#[derive(Error, Debug)]
+pub enum ClusteringError {
+    /// There must be at least one embedding per cluster
+    #[error("Not Enough Embeddings: {k} clusters need at least {k} embeddings but found {found}")]
+    NotEnoughEmbeddings { k: usize, found: usize },
+    /// Every embedding must have the same dimension
+    #[error("Dimension Mismatch: expected {expected} but found {found}")]
+    DimensionMismatch { expected: usize, found: usize },
+    /// The table could not be sampled
+    #[cfg(feature = "pg_vector")]
+    #[error("Scan Error: {0}")]
+    ScanError(PostgresVectorStoreError),
+}
+
+/// # [`initial_centroids`]
+/// k-means++: the first centroid is picked at random and each following one is picked with
+/// probability proportional to its squared distance from the closest centroid so far.
+fn initial_centroids(vectors: &[Vec<f32>], k: usize, rng: &mut SplitMix64) -> Vec<Vec<f32>> {
+    let mut centroids: Vec<Vec<f32>> = Vec::with_capacity(k);
+    centroids.push(vectors[rng.next_index(vectors.len())].clone());
+    let mut closest: Vec<f32> = vectors
+        .iter()
+        .map(|vector| squared_distance(vector, &centroids[0]))
+        .collect();
+    while centroids.len() < k {
+        let total: f64 = closest.iter().map(|distance| f64::from(*distance)).sum();
+        let index: usize = if total > 0.0 {
+            let mut target: f64 = rng.next_f64() * total;
+            closest
+                .iter()
+                .position(|distance| {
+                    target -= f64::from(*distance);
+                    target < 0.0
+                })
+                .unwrap_or(vectors.len() - 1)
+        } else {
+            // Every vector is already a centroid
+            rng.next_index(vectors.len())
+        };
+        let centroid: Vec<f32> = vectors[index].clone();
+        for (distance, vector) in closest.iter_mut().zip(vectors) {
+            *distance = distance.min(squared_distance(vector, &centroid));
+        }
+        centroids.push(centroid);
+    }
+    centroids
+}
+
+/// # [`assign`]
+/// Assigns every vector to its closest centroid, the lowest index wins a tie.
+///
+/// # Returns
+/// * [`bool`] - whether any assignment changed.
+fn assign(vectors: &[Vec<f32>], centroids: &[Vec<f32>], assignments: &mut [usize]) -> bool {
+    let mut changed: bool = false;
+    for (vector, assignment) in vectors.iter().zip(assignments.iter_mut()) {
+        let closest: usize = nearest(vector, centroids).0;
+        if *assignment != closest {
+            *assignment = closest;
+            changed = true;
+        }
+    }
+    changed
+}
+
+/// # [`update_centroids`]
+/// Moves every centroid to the mean of its vectors. A centroid left with no vectors is moved
+/// onto the vector furthest from its own centroid so it can pick up members next iteration.
+fn update_centroids(
+    vectors: &[Vec<f32>],
+    centroids: &[Vec<f32>],
+    assignments: &[usize],
+) -> Vec<Vec<f32>> {
+    let dimensions: usize = vectors[0].len();
+    let mut sums: Vec<Vec<f32>> = vec![vec![0.0; dimensions]; centroids.len()];
+    let mut counts: Vec<usize> = vec![0; centroids.len()];
+    for (vector, assignment) in vectors.iter().zip(assignments) {
+        counts[*assignment] += 1;
+        for (sum, value) in sums[*assignment].iter_mut().zip(vector) {
+            *sum += value;
+        }
+    }
+    let mut taken: Vec<bool> = vec![false; vectors.len()];
+    for (cluster, sum) in sums.iter_mut().enumerate() {
+        if counts[cluster] > 0 {
+            sum.iter_mut()
+                .for_each(|value| *value /= counts[cluster] as f32);
+            continue;
+        }
+        let furthest: Option<usize> =
+            (0..vectors.len())
+                .filter(|index| !taken[*index])
+                .max_by(|a, b| {
+                    let distance = |index: usize| {
+                        squared_distance(&vectors[index], &centroids[assignments[index]])
+                    };
+                    distance(*a).total_cmp(&distance(*b)).then(b.cmp(a))
+                });
+        match furthest {
+            Some(index) => {
+                taken[index] = true;
+                sum.clone_from(&vectors[index]);
+            }
+            None => sum.clone_from(&centroids[cluster]),
+        }
+    }
+    sums
+}
+
+/// # [`nearest`]
+/// # Returns
+/// * ([`usize`], [`f32`]) - the index of the closest centroid and its squared distance.
+fn nearest(vector: &[f32], centroids: &[Vec<f32>]) -> (usize, f32) {
+    centroids
+        .iter()
+        .map(|centroid| squared_distance(vector, centroid))
+        .enumerate()
+        .fold((0, f32::INFINITY), |best, (index, distance)| {
+            if distance < best.1 {
+                (index, distance)
+            } else {
+                best
+            }
+        })
+}
+
+fn squared_distance(a: &[f32], b: &[f32]) -> f32 {
+    a.iter().zip(b).map(|(x, y)| (x - y) * (x - y)).sum()
+}
+
+/// # [`SplitMix64`]
+/// A small seeded random number generator so clustering needs no external dependencies.
+#[derive(Debug, Clone)]
+struct SplitMix64 {
+    state: u64,
+}
+
+impl SplitMix64 {
+    fn new(seed: u64) -> Self {
+        SplitMix64 { state: seed }
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z: u64 = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// A float in [0, 1) from the top 53 bits
+    fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    fn next_index(&mut self, len: usize) -> usize {
+        (self.next_f64() * len as f64) as usize % len
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    const BLOB_SIZE: usize = 50;
+
+    // Gaussian points around each centre using the Box-Muller transform
+    fn blobs(centres: &[Vec<f32>], std_dev: f64, seed: u64) -> Vec<Embedding> {
+        let mut rng = SplitMix64::new(seed);
+        let mut embeddings: Vec<Embedding> = Vec::new();
+        for (blob, centre) in centres.iter().enumerate() {
+            for point in 0..BLOB_SIZE {
+                let vector: Vec<f32> = centre
+                    .iter()
+                    .map(|value| {
+                        let u1: f64 = 1.0 - rng.next_f64();
+                        let u2: f64 = rng.next_f64();
+                        let normal: f64 =
+                            (-2.0 * u1.ln()).sqrt() * (2.0 * std::f64::consts::PI * u2).cos();
+                        value + (normal * std_dev) as f32
+                    })
+                    .collect();
+                let chunk = Chunk::new_with_metadata(
+                    format!("blob {} point {}", blob, point),
+                    serde_json::json!({ "blob": blob }),
+                );
+                embeddings.push(Embedding::new(chunk, vector));
+            }
+        }
+        embeddings
+    }
+
+    fn centres() -> Vec<Vec<f32>> {
+        vec![
+            vec![10.0, 0.0, 0.0, 0.0, 0.0, 0.0],
+            vec![0.0, 10.0, 0.0, 0.0, 0.0, 0.0],
+            vec![0.0, 0.0, 0.0, 0.0, 10.0, 0.0],
+            vec![-10.0, -10.0, 0.0, 0.0, 0.0, 0.0],
+        ]
+    }
+
+    fn k(k: usize) -> NonZeroUsize {
+        NonZeroUsize::new(k).unwrap()
+    }
+
+    #[test]
+    fn well_separated_blobs_are_recovered() {
+        let centres: Vec<Vec<f32>> = centres();
+        let embeddings: Vec<Embedding> = blobs(&centres, 1.0, 7);
+        let report: ClusterReport = KMeans::new(k(4)).fit(&embeddings).unwrap();
+        assert!(report.converged);
+        assert_eq!(report.sample_size, 4 * BLOB_SIZE);
+        assert_eq!(report.clusters.len(), 4);
+
+        let mut recovered: HashSet<u64> = HashSet::new();
+        for cluster in &report.clusters {
+            assert_eq!(cluster.size, BLOB_SIZE);
+            assert_eq!(cluster.representatives.len(), DEFAULT_REPRESENTATIVES);
+            // Every representative comes from the same blob, and the centroid sits on its centre
+            let blob: u64 = cluster.representatives[0].chunk.metadata()["blob"]
+                .as_u64()
+                .unwrap();
+            for representative in &cluster.representatives {
+                assert_eq!(representative.chunk.metadata()["blob"], blob);
+            }
+            assert!(squared_distance(&cluster.centroid, &centres[blob as usize]) < 0.5);
+            recovered.insert(blob);
+        }
+        assert_eq!(recovered.len(), 4);
+    }
+
+    #[test]
+    fn representatives_are_the_closest_to_the_centroid() {
+        let embeddings: Vec<Embedding> = blobs(&centres(), 1.0, 11);
+        let report: ClusterReport = KMeans::new(k(4))
+            .with_representatives(BLOB_SIZE + 1)
+            .fit(&embeddings)
+            .unwrap();
+        for cluster in report.clusters {
+            assert_eq!(cluster.representatives.len(), BLOB_SIZE);
+            let distances: Vec<f32> = cluster
+                .representatives
+                .iter()
+                .map(|representative| representative.distance)
+                .collect();
+            assert!(distances.windows(2).all(|pair| pair[0] <= pair[1]));
+        }
+    }
+
+    #[test]
+    fn the_same_seed_gives_the_same_report() {
+        let embeddings: Vec<Embedding> = blobs(&centres(), 4.0, 3);
+        let kmeans = KMeans::new(k(5)).with_seed(1234);
+        let first: ClusterReport = kmeans.fit(&embeddings).unwrap();
+        let second: ClusterReport = kmeans.fit(&embeddings).unwrap();
+        assert_eq!(first, second);
+    }
+
+    #[test]
+    fn duplicate_vectors_still_give_k_clusters() {
+        let embeddings: Vec<Embedding> = (0..6)
+            .map(|i| Embedding::new(Chunk::new(format!("{}", i)), vec![1.0, 1.0]))
+            .collect();
+        let report: ClusterReport = KMeans::new(k(3)).fit(&embeddings).unwrap();
+        assert_eq!(report.clusters.len(), 3);
+        let total: usize = report.clusters.iter().map(|cluster| cluster.size).sum();
+        assert_eq!(total, 6);
+    }
+
+    #[test]
+    fn iterations_are_capped() {
+        let embeddings: Vec<Embedding> = blobs(&centres(), 4.0, 5);
+        let report: ClusterReport = KMeans::new(k(4))
+            .with_max_iterations(k(1))
+            .fit(&embeddings)
+            .unwrap();
+        assert_eq!(report.iterations, 1);
+        assert!(!report.converged);
+        let total: usize = report.clusters.iter().map(|cluster| cluster.size).sum();
+        assert_eq!(total, embeddings.len());
+    }
+
+    #[test]
+    fn invalid_input_is_rejected() {
+        let embeddings: Vec<Embedding> = vec![
+            Embedding::new(Chunk::new("a"), vec![1.0, 2.0]),
+            Embedding::new(Chunk::new("b"), vec![1.0]),
+        ];
+        assert!(matches!(
+            KMeans::new(k(3)).fit(&embeddings),
+            Err(ClusteringError::NotEnoughEmbeddings { k: 3, found: 2 })
+        ));
+        assert!(matches!(
+            KMeans::new(k(2)).fit(&embeddings),
+            Err(ClusteringError::DimensionMismatch {
+                expected: 2,
+                found: 1
+            })
+        ));
+    }
+}
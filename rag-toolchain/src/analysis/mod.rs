@@ -0,0 +1,11 @@
+/// # Analysis
+/// This module contains offline tools for exploring what has been embedded, such as
+/// clustering a vector table to see the rough topics it holds before building prompts.
+mod kmeans;
+
+#[cfg(feature = "pg_vector")]
+pub use kmeans::cluster_table;
+pub use kmeans::{
+    Cluster, ClusterReport, ClusteringError, KMeans, Representative, DEFAULT_MAX_ITERATIONS,
+    DEFAULT_REPRESENTATIVES, DEFAULT_SEED,
+};
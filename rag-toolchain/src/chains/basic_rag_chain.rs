@@ -1,10 +1,20 @@
+use crate::retrievers::{AsyncReranker, FilteredRetriever, MetadataFilter, RetrievalStrategy};
 use crate::{
-    chains::{utils::build_prompt, RagChainError},
-    clients::{AsyncChatClient, AsyncStreamedChatClient, PromptMessage},
-    common::Chunks,
+    chains::{
+        timeouts::within,
+        utils::{build_prompts, resolve_system_prompt, validate_top_k},
+        ChainPhase, ChainReranker, ChainResponse, ChainTimeouts, ChunkPostProcessor, ContextBudget,
+        ModerationPolicy, NeighborExpansion, PromptTemplate, PromptVariables, RagChainError,
+        RagResponse, RetrievalLimit, StreamedRagResponse, TimedCompletionStream, Timings,
+    },
+    clients::{AsyncChatClient, AsyncStreamedChatClient, DetailedChatResponse, PromptMessage},
+    common::{Chunks, InvocationContext, ScoredChunk, TokenizerWrapper},
     retrievers::AsyncRetriever,
 };
+use std::future::Future;
 use std::num::NonZeroU32;
+use std::sync::Arc;
+use tokio::time::Instant;
 use typed_builder::TypedBuilder;
 
 /// # [`BasicRAGChain`]
@@ -16,6 +26,10 @@ use typed_builder::TypedBuilder;
 /// * `T` - The type of the chat client to be used
 /// * `U` - The type of the retriever to be used
 ///
+/// To rerank the supporting chunks give the builder a reranker, see
+/// [`BasicRAGChainBuilder::reranker`], the top_k the chain is invoked with then becomes the
+/// number of candidates and the reranker's top_n the number put in the prompt.
+///
 /// # Examples
 /// ```
 /// use rag_toolchain::clients::*;
@@ -56,12 +70,21 @@ use typed_builder::TypedBuilder;
 ///        PromptMessage::HumanMessage("what kind of alcohol does Morwenna drink".into());
 ///
 ///    let response = chain
-///        .invoke_chain(user_message, NonZeroU32::new(2).unwrap())
+///        .invoke_chain(user_message.clone(), NonZeroU32::new(2).unwrap())
+///        .await
+///        .unwrap();
+///
+///    // To cite the documents the answer was grounded on
+///    let response: RagResponse = chain
+///        .invoke_chain_with_sources(user_message, NonZeroU32::new(2).unwrap())
 ///        .await
 ///        .unwrap();
+///    for source in response.sources {
+///        println!("{:.2} {}", source.score, source.chunk.metadata());
+///    }
 /// }
 /// ```
-#[derive(Debug, TypedBuilder, Clone, PartialEq, Eq)]
+#[derive(Debug, TypedBuilder, Clone, PartialEq)]
 pub struct BasicRAGChain<T, U>
 where
     T: AsyncChatClient,
@@ -69,6 +92,68 @@ where
 {
     #[builder(default, setter(strip_option))]
     system_prompt: Option<PromptMessage>,
+    /// Resolves the `{{name}}` placeholders in the system prompt on each invocation
+    #[builder(default, setter(strip_option))]
+    prompt_variables: Option<PromptVariables>,
+    /// Supporting chunks scoring below this are dropped before the prompt is built,
+    /// see [`AsyncRetriever::retrieve_with_scores`]
+    #[builder(default, setter(strip_option))]
+    min_score: Option<f32>,
+    /// Combines the user prompt with the supporting chunks, see [`PromptTemplate`]
+    #[builder(default)]
+    prompt_template: PromptTemplate,
+    /// How the supporting chunks are picked, see [`RetrievalStrategy`]. The filtered
+    /// invocations always pick by similarity.
+    #[builder(default)]
+    retrieval_strategy: RetrievalStrategy,
+    /// When set an answer cut short by the token limit is returned as
+    /// [`RagChainError::Truncated`] rather than as if it were complete
+    #[builder(default)]
+    reject_truncated: bool,
+    /// How long retrieval and the chat client may each take on every invocation, see
+    /// [`ChainTimeouts`]. [`BasicRAGChain::invoke_chain_with_timeout`] replaces them.
+    #[builder(default, setter(into))]
+    timeouts: ChainTimeouts,
+    /// When set a top_k invocation retrieves top_k candidates but only packs as many into
+    /// the prompt, in relevance order, as fit in this many tokens. This is the same as invoking
+    /// with a [`ContextBudget`] of the size, which takes precedence when one is passed.
+    #[builder(default, setter(strip_option))]
+    max_context_tokens: Option<usize>,
+    /// Counts the tokens for a context budget in place of the chat client, such as the
+    /// tokenizer from [`crate::common::EmbeddingModelMetadata`]
+    #[builder(default, setter(transform = |tokenizer: Box<dyn TokenizerWrapper>| Some(Arc::from(tokenizer))))]
+    tokenizer: Option<Arc<dyn TokenizerWrapper>>,
+    /// Applied in order to the supporting chunks before the prompt is built
+    #[builder(via_mutators, mutators(
+        /// Adds a processor applied to the supporting chunks after any already added,
+        /// see [`ChunkPostProcessor`]
+        pub fn post_processor(&mut self, processor: impl ChunkPostProcessor + 'static) {
+            self.post_processors.push(Arc::new(processor));
+        }
+    ))]
+    post_processors: Vec<Arc<dyn ChunkPostProcessor>>,
+    /// Adds the chunks either side of each supporting chunk in its document, before the
+    /// post processors are applied, see [`NeighborExpansion`]
+    #[builder(default, setter(strip_option))]
+    neighbor_expansion: Option<NeighborExpansion>,
+    /// Moderates the user message before anything is retrieved for it, a flagged
+    /// message is refused with [`RagChainError::ContentFlagged`]
+    #[builder(default, setter(strip_option))]
+    moderation_policy: Option<ModerationPolicy>,
+    /// Reranks the retrieved chunks before the minimum score is applied
+    #[builder(via_mutators, mutators(
+        /// Reranks the supporting chunks after they are retrieved, the top_k the chain is
+        /// invoked with becomes the number of candidates and `top_n` the number kept,
+        /// see [`ChainReranker`]
+        pub fn reranker(
+            &mut self,
+            reranker: impl AsyncReranker<ErrorType: 'static> + 'static,
+            top_n: NonZeroU32,
+        ) {
+            self.reranker = Some(ChainReranker::new(reranker, top_n));
+        }
+    ))]
+    reranker: Option<ChainReranker>,
     chat_client: T,
     retriever: U,
 }
@@ -80,10 +165,10 @@ where
 {
     /// # [`BasicRAGChain::invoke_chain`]
     ///
-    /// function to execute the RAG chain given a user prompt and a top_k value.
+    /// function to execute the RAG chain given a user prompt and a top_k value or context budget.
     /// we take the supplied user prompt and retrieve supporting chunks from the retriever.
     /// those chunks are then used to build a new prompt which is then sent to the chat client.
-    /// the new prompt then becomes:
+    /// unless a [`PromptTemplate`] is set the new prompt then becomes:
     ///
     /// user prompt
     ///
@@ -97,39 +182,677 @@ where
     ///
     /// # Arguments
     /// * `user_message`: [`PromptMessage`] - the user prompt, this will be used to retrieve supporting chunks
-    /// * `top_k`: [`NonZeroU32`] - the number of supporting chunks to retrieve
+    /// * `limit`: impl [`Into<RetrievalLimit>`] - a [`std::num::NonZeroU32`] top_k of supporting chunks to retrieve,
+    ///   or a [`crate::chains::ContextBudget`] to include as many as fit in the budget
     ///
     /// # Errors
-    /// * [`RagChainError`] - if the chat client or retriever fails.
+    /// * [`RagChainError`] - if the chat client or retriever fails, top_k (or fetch_k) is larger than the retriever allows
+    ///   or a variable in the system prompt could not be resolved.
+    /// * [`RagChainError::Truncated`] - if the chain rejects truncated answers and the answer
+    ///   hit the token limit.
+    /// * [`RagChainError::ContentFlagged`] - if the moderation policy flagged the user message.
+    /// * [`RagChainError::Timeout`] - if the chain has timeouts and retrieval or the chat client
+    ///   did not finish in time.
     ///
     /// # Returns
     /// [`PromptMessage`] - the response from the chat client
     pub async fn invoke_chain(
         &self,
         user_message: PromptMessage,
-        top_k: NonZeroU32,
+        limit: impl Into<RetrievalLimit>,
+    ) -> Result<PromptMessage, RagChainError<T::ErrorType, U::ErrorType>> {
+        self.invoke_chain_with_timeout(user_message, limit, self.timeouts)
+            .await
+    }
+
+    /// # [`BasicRAGChain::invoke_chain_with_timeout`]
+    ///
+    /// The same as [`BasicRAGChain::invoke_chain`] but retrieval and the chat client each have
+    /// to finish within their timeout, in place of the timeouts the chain was built with.
+    /// A phase which runs out of time is dropped, cancelling any request it had in flight,
+    /// so a slow retriever or model can not hold up the caller. Moderating the user message
+    /// is not timed.
+    ///
+    /// # Examples
+    /// ```
+    /// use rag_toolchain::chains::*;
+    /// use rag_toolchain::clients::*;
+    /// use rag_toolchain::retrievers::*;
+    /// use std::num::NonZeroU32;
+    /// use std::time::Duration;
+    ///
+    /// async fn answer<T: AsyncChatClient, U: AsyncRetriever>(chain: &BasicRAGChain<T, U>) {
+    ///     let timeouts = ChainTimeouts::default()
+    ///         .with_retrieval(Duration::from_secs(2))
+    ///         .with_chat(Duration::from_secs(30));
+    ///     let question = PromptMessage::HumanMessage("What is the refund policy?".into());
+    ///     match chain.invoke_chain_with_timeout(question, NonZeroU32::new(4).unwrap(), timeouts).await {
+    ///         Err(RagChainError::Timeout { phase }) => println!("The {} phase was too slow", phase),
+    ///         _ => {}
+    ///     }
+    /// }
+    /// ```
+    ///
+    /// # Arguments
+    /// * `user_message`: [`PromptMessage`] - the user prompt, this will be used to retrieve supporting chunks
+    /// * `limit`: impl [`Into<RetrievalLimit>`] - a [`std::num::NonZeroU32`] top_k of supporting chunks to retrieve,
+    ///   or a [`crate::chains::ContextBudget`] to include as many as fit in the budget
+    /// * `timeouts`: impl [`Into<ChainTimeouts>`] - a [`std::time::Duration`] for every phase, or
+    ///   [`ChainTimeouts`] to set each phase separately
+    ///
+    /// # Errors
+    /// * [`RagChainError::Timeout`] - if retrieval or the chat client did not finish in time.
+    /// * [`RagChainError`] - any of the errors from [`BasicRAGChain::invoke_chain`].
+    ///
+    /// # Returns
+    /// [`PromptMessage`] - the response from the chat client
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(name = "basic_rag_chain.invoke_chain", skip_all)
+    )]
+    pub async fn invoke_chain_with_timeout(
+        &self,
+        user_message: PromptMessage,
+        limit: impl Into<RetrievalLimit>,
+        timeouts: impl Into<ChainTimeouts>,
     ) -> Result<PromptMessage, RagChainError<T::ErrorType, U::ErrorType>> {
         let content = user_message.content();
-        let chunks: Chunks = self
-            .retriever
-            .retrieve(content, top_k)
+        let output: PipelineOutput = self
+            .run_pipeline(
+                &user_message,
+                limit.into(),
+                |top_k| self.retrieve(content, top_k, None),
+                None,
+                timeouts.into(),
+            )
+            .await?;
+        Ok(output.response.message)
+    }
+
+    /// # [`BasicRAGChain::invoke_chain_with_sources`]
+    ///
+    /// The same as [`BasicRAGChain::invoke_chain`] but the supporting chunks included in the
+    /// prompt are returned with the response, so the answer can cite what it was grounded on.
+    /// The chunks are retrieved with [`AsyncRetriever::retrieve_with_scores`] so each source
+    /// carries its score.
+    ///
+    /// # Arguments
+    /// * `user_message`: [`PromptMessage`] - the user prompt, this will be used to retrieve supporting chunks
+    /// * `limit`: impl [`Into<RetrievalLimit>`] - a [`std::num::NonZeroU32`] top_k of supporting chunks to retrieve,
+    ///   or a [`crate::chains::ContextBudget`] to include as many as fit in the budget
+    ///
+    /// # Errors
+    /// * [`RagChainError`] - any of the errors from [`BasicRAGChain::invoke_chain`].
+    ///
+    /// # Returns
+    /// [`RagResponse`] - the response from the chat client and the sources included in the prompt
+    pub async fn invoke_chain_with_sources(
+        &self,
+        user_message: PromptMessage,
+        limit: impl Into<RetrievalLimit>,
+    ) -> Result<RagResponse, RagChainError<T::ErrorType, U::ErrorType>> {
+        let content = user_message.content();
+        let output: PipelineOutput = self
+            .run_pipeline(
+                &user_message,
+                limit.into(),
+                |top_k| self.retrieve_sources(content, top_k),
+                None,
+                self.timeouts,
+            )
+            .await?;
+
+        Ok(RagResponse {
+            message: output.response.message,
+            chunks_dropped: output.chunks_dropped,
+            sources: with_scores(output.included, &output.scores),
+        })
+    }
+
+    /// # [`BasicRAGChain::invoke_chain_with_context`]
+    ///
+    /// The same as [`BasicRAGChain::invoke_chain`] but the context is passed down to
+    /// the retriever and the chat client so the whole invocation can be correlated
+    /// by the request id. The response also carries the retrieval and generation times.
+    ///
+    /// # Arguments
+    /// * `user_message`: [`PromptMessage`] - the user prompt, this will be used to retrieve supporting chunks
+    /// * `limit`: impl [`Into<RetrievalLimit>`] - a [`std::num::NonZeroU32`] top_k of supporting chunks to retrieve,
+    ///   or a [`crate::chains::ContextBudget`] to include as many as fit in the budget
+    /// * `context`: &[`InvocationContext`] - the context of the invocation
+    ///
+    /// # Errors
+    /// * [`RagChainError`] - any of the errors from [`BasicRAGChain::invoke_chain`].
+    ///
+    /// # Returns
+    /// [`ChainResponse`] - the response from the chat client along with the request ids and timings
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            name = "basic_rag_chain.invoke_chain_with_context",
+            skip_all,
+            fields(request_id = %context.request_id())
+        )
+    )]
+    pub async fn invoke_chain_with_context(
+        &self,
+        user_message: PromptMessage,
+        limit: impl Into<RetrievalLimit>,
+        context: &InvocationContext,
+    ) -> Result<ChainResponse, RagChainError<T::ErrorType, U::ErrorType>> {
+        let content = user_message.content();
+        let output: PipelineOutput = self
+            .run_pipeline(
+                &user_message,
+                limit.into(),
+                |top_k| self.retrieve(content, top_k, Some(context)),
+                Some(context),
+                self.timeouts,
+            )
+            .await?;
+        let response: DetailedChatResponse = output.response;
+
+        Ok(ChainResponse {
+            message: response.message,
+            request_id: response.request_id,
+            provider_request_id: response.provider_request_id,
+            timings: output.timings,
+            chunks_used: output.included.len(),
+            chunks_dropped: output.chunks_dropped,
+            usage: response.usage,
+            finish_reason: response.finish_reason,
+            warnings: Vec::new(),
+        })
+    }
+
+    /// # [`BasicRAGChain::run_pipeline`]
+    ///
+    /// The steps every invocation shares. The user message is moderated, the supporting
+    /// chunks are retrieved, expanded with their neighbours and post processed, then the
+    /// prompts are built and sent to the chat client. Retrieval, including the neighbours,
+    /// and the chat client are each limited to their timeout.
+    ///
+    /// # Arguments
+    /// * `user_message`: &[`PromptMessage`] - the user prompt
+    /// * `limit`: [`RetrievalLimit`] - the limit the chain was invoked with
+    /// * `retrieve`: `F` - retrieves the given number of supporting chunks, with their scores
+    ///   when the invocation returns them
+    /// * `context`: [`Option<&InvocationContext>`] - passed to the chat client if present
+    /// * `timeouts`: [`ChainTimeouts`] - how long retrieval and the chat client may each take
+    ///
+    /// # Errors
+    /// * [`RagChainError`] - any of the errors from [`BasicRAGChain::invoke_chain_with_timeout`].
+    ///
+    /// # Returns
+    /// [`PipelineOutput`] - the response from the chat client and the chunks it was given
+    async fn run_pipeline<F, Fut, R>(
+        &self,
+        user_message: &PromptMessage,
+        limit: RetrievalLimit,
+        retrieve: F,
+        context: Option<&InvocationContext>,
+        timeouts: ChainTimeouts,
+    ) -> Result<PipelineOutput, RagChainError<T::ErrorType, U::ErrorType>>
+    where
+        F: FnOnce(NonZeroU32) -> Fut,
+        Fut: Future<Output = Result<R, RagChainError<T::ErrorType, U::ErrorType>>>,
+        R: Into<Retrieved>,
+    {
+        self.moderate(user_message).await?;
+        let limit: RetrievalLimit = self.packing_limit(limit);
+        validate_top_k(&self.retriever, limit.fetch_k())?;
+        let system_prompt: Option<PromptMessage> =
+            resolve_system_prompt(self.system_prompt.as_ref(), self.prompt_variables.as_ref())
+                .map_err(RagChainError::PromptVariableError::<T::ErrorType, U::ErrorType>)?;
+        // The prompt may be sensitive so it is only ever recorded at trace level
+        #[cfg(feature = "tracing")]
+        tracing::trace!(prompt = user_message.content(), "invoking chain");
+        let started = Instant::now();
+        let retrieval = async {
+            match retrieve(limit.fetch_k()).await?.into() {
+                Retrieved::Chunks(chunks) => {
+                    self.expand_neighbors(chunks).await.map(Retrieved::Chunks)
+                }
+                Retrieved::Scored(scored) => self
+                    .expand_neighbors_scored(scored)
+                    .await
+                    .map(Retrieved::Scored),
+            }
+            .map_err(RagChainError::RetrieverError::<T::ErrorType, U::ErrorType>)
+        };
+        let retrieved: Retrieved = within(timeouts.retrieval(), ChainPhase::Retrieval, retrieval)
             .await
-            .map_err(RagChainError::RetrieverError::<T::ErrorType, U::ErrorType>)?;
+            .map_err(|phase| RagChainError::Timeout { phase })??;
+        let (chunks, scores): (Chunks, Vec<f32>) = match retrieved {
+            Retrieved::Chunks(chunks) => (self.post_process(chunks), Vec::new()),
+            Retrieved::Scored(scored) => self.post_process_scored(scored),
+        };
+        let retrieved: usize = chunks.len();
+
+        let (prompts, included) = build_prompts(
+            system_prompt.as_ref(),
+            &self.prompt_template,
+            user_message,
+            chunks,
+            &limit,
+            |text| self.count_tokens(text),
+        );
+
+        let generation_started = Instant::now();
+        // Boxed as the chat clients' futures nest deeply enough to overflow the
+        // layout depth limit of a caller awaiting the chain
+        let response: DetailedChatResponse = within(
+            timeouts.chat(),
+            ChainPhase::Chat,
+            Box::pin(self.generate(prompts, context)),
+        )
+        .await
+        .map_err(|phase| RagChainError::Timeout { phase })??;
+
+        Ok(PipelineOutput {
+            response,
+            chunks_dropped: retrieved - included.len(),
+            included,
+            scores,
+            timings: Timings {
+                retrieval: Some(generation_started - started),
+                time_to_first_token: None,
+                generation: Some(generation_started.elapsed()),
+            },
+        })
+    }
+
+    /// # [`BasicRAGChain::packing_limit`]
+    ///
+    /// Turns a top_k into a [`ContextBudget`] retrieving top_k candidates when the chain
+    /// has a max_context_tokens, any other limit is returned as is.
+    fn packing_limit(&self, limit: RetrievalLimit) -> RetrievalLimit {
+        match (limit, self.max_context_tokens) {
+            (RetrievalLimit::TopK(top_k), Some(max_context_tokens)) => {
+                ContextBudget::new(max_context_tokens)
+                    .with_fetch_k(top_k)
+                    .into()
+            }
+            (limit, _) => limit,
+        }
+    }
+
+    /// Counts the tokens in the text with the tokenizer if one was set,
+    /// otherwise with the chat client.
+    fn count_tokens(&self, text: &str) -> usize {
+        match &self.tokenizer {
+            Some(tokenizer) => tokenizer.count_tokens(text),
+            None => self.chat_client.count_tokens(text),
+        }
+    }
+
+    /// Checks the user message with the moderation policy if one is set
+    async fn moderate(
+        &self,
+        user_message: &PromptMessage,
+    ) -> Result<(), RagChainError<T::ErrorType, U::ErrorType>> {
+        match &self.moderation_policy {
+            Some(moderation_policy) => Ok(moderation_policy.check(user_message).await?),
+            None => Ok(()),
+        }
+    }
+
+    /// # [`BasicRAGChain::retrieve`]
+    ///
+    /// Retrieves the supporting chunks, when a minimum score or reranker is set the chunks
+    /// are retrieved with their scores, reranked and any scoring below the minimum are dropped.
+    ///
+    /// # Arguments
+    /// * `text`: &[`str`] - the text to retrieve supporting chunks for
+    /// * `top_k`: [`NonZeroU32`] - the number of chunks to retrieve
+    /// * `context`: [`Option<&InvocationContext>`] - passed to the retriever if present
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(name = "basic_rag_chain.retrieve", skip_all, fields(top_k = top_k.get()))
+    )]
+    async fn retrieve(
+        &self,
+        text: &str,
+        top_k: NonZeroU32,
+        context: Option<&InvocationContext>,
+    ) -> Result<Chunks, RagChainError<T::ErrorType, U::ErrorType>> {
+        if let (None, RetrievalStrategy::Similarity, None) =
+            (self.min_score, self.retrieval_strategy, &self.reranker)
+        {
+            return match context {
+                Some(context) => {
+                    self.retriever
+                        .retrieve_with_context(text, top_k, context)
+                        .await
+                }
+                None => self.retriever.retrieve(text, top_k).await,
+            }
+            .map_err(RagChainError::RetrieverError);
+        }
+        let scored: Vec<ScoredChunk> = self.retrieve_scored(text, top_k, context).await?;
+        Ok(above_min_score(scored, self.min_score))
+    }
+
+    /// # [`BasicRAGChain::retrieve_scored`]
+    ///
+    /// Retrieves the supporting chunks with their scores using the chain's retrieval
+    /// strategy, then reranks them if the chain has a reranker. The context is not passed
+    /// to the retriever when picking by maximal marginal relevance.
+    ///
+    /// # Arguments
+    /// * `text`: &[`str`] - the text to retrieve supporting chunks for
+    /// * `top_k`: [`NonZeroU32`] - the number of chunks to retrieve
+    /// * `context`: [`Option<&InvocationContext>`] - passed to the retriever if present
+    async fn retrieve_scored(
+        &self,
+        text: &str,
+        top_k: NonZeroU32,
+        context: Option<&InvocationContext>,
+    ) -> Result<Vec<ScoredChunk>, RagChainError<T::ErrorType, U::ErrorType>> {
+        let scored: Vec<ScoredChunk> = match (self.retrieval_strategy, context) {
+            (RetrievalStrategy::Mmr { fetch_k, lambda }, _) => {
+                self.retriever
+                    .retrieve_mmr_with_scores(text, top_k, fetch_k.max(top_k), lambda)
+                    .await
+            }
+            (RetrievalStrategy::Similarity, Some(context)) => {
+                self.retriever
+                    .retrieve_with_scores_and_context(text, top_k, context)
+                    .await
+            }
+            (RetrievalStrategy::Similarity, None) => {
+                self.retriever.retrieve_with_scores(text, top_k).await
+            }
+        }
+        .map_err(RagChainError::RetrieverError)?;
+        self.rerank(text, scored).await
+    }
+
+    /// # [`BasicRAGChain::retrieve_sources`]
+    /// Retrieves the supporting chunks with their scores, see [`BasicRAGChain::retrieve_scored`],
+    /// dropping any scoring below the minimum.
+    async fn retrieve_sources(
+        &self,
+        text: &str,
+        top_k: NonZeroU32,
+    ) -> Result<Vec<ScoredChunk>, RagChainError<T::ErrorType, U::ErrorType>> {
+        let mut scored: Vec<ScoredChunk> = self.retrieve_scored(text, top_k, None).await?;
+        if let Some(min_score) = self.min_score {
+            scored.retain(|scored| scored.score >= min_score);
+        }
+        Ok(scored)
+    }
+
+    /// Reranks the retrieved chunks with the reranker if one is set
+    async fn rerank(
+        &self,
+        text: &str,
+        scored: Vec<ScoredChunk>,
+    ) -> Result<Vec<ScoredChunk>, RagChainError<T::ErrorType, U::ErrorType>> {
+        match &self.reranker {
+            Some(reranker) => reranker
+                .rerank(text, scored)
+                .await
+                .map_err(|error| RagChainError::RerankerError(error.to_string())),
+            None => Ok(scored),
+        }
+    }
 
-        let new_prompt: PromptMessage = build_prompt(&user_message, chunks);
+    /// # [`BasicRAGChain::expand_neighbors`]
+    /// Adds the neighbours of the supporting chunks if the chain has a [`NeighborExpansion`],
+    /// the neighbours are fetched from the retriever in a single call.
+    async fn expand_neighbors(&self, chunks: Chunks) -> Result<Chunks, U::ErrorType> {
+        let Some(expansion) = &self.neighbor_expansion else {
+            return Ok(chunks);
+        };
+        match expansion.neighbor_filter(&chunks) {
+            Some(filter) => {
+                let neighbors: Chunks = self.retriever.fetch_by_metadata(&filter).await?;
+                Ok(expansion.expand(chunks, neighbors))
+            }
+            None => Ok(chunks),
+        }
+    }
 
-        let prompts = match self.system_prompt.clone() {
-            None => vec![new_prompt],
-            Some(prompt) => vec![prompt, new_prompt],
+    /// # [`BasicRAGChain::expand_neighbors_scored`]
+    /// The same as [`BasicRAGChain::expand_neighbors`] for scored chunks, each neighbour
+    /// takes the score of the chunk it was expanded from.
+    async fn expand_neighbors_scored(
+        &self,
+        scored: Vec<ScoredChunk>,
+    ) -> Result<Vec<ScoredChunk>, U::ErrorType> {
+        let Some(expansion) = &self.neighbor_expansion else {
+            return Ok(scored);
         };
+        match expansion.neighbor_filter(scored.iter().map(|scored| &scored.chunk)) {
+            Some(filter) => {
+                let neighbors: Chunks = self.retriever.fetch_by_metadata(&filter).await?;
+                Ok(expansion.expand_scored(scored, neighbors))
+            }
+            None => Ok(scored),
+        }
+    }
 
-        let result = self
+    /// # [`BasicRAGChain::post_process`]
+    /// Applies each of the post processors to the supporting chunks in turn
+    fn post_process(&self, chunks: Chunks) -> Chunks {
+        self.post_processors
+            .iter()
+            .fold(chunks, |chunks, processor| processor.process(chunks))
+    }
+
+    /// # [`BasicRAGChain::post_process_scored`]
+    ///
+    /// Applies each of the post processors to the scored chunks in turn, see
+    /// [`ChunkPostProcessor::process_scored`] for how the chunks they return are scored.
+    ///
+    /// # Arguments
+    /// * `scored`: [`Vec<ScoredChunk>`] - the retrieved chunks, most relevant first
+    ///
+    /// # Returns
+    /// * ([`Chunks`], [`Vec<f32>`]) - the processed chunks and their scores
+    fn post_process_scored(&self, scored: Vec<ScoredChunk>) -> (Chunks, Vec<f32>) {
+        let processed: Vec<ScoredChunk> = self
+            .post_processors
+            .iter()
+            .fold(scored, |scored, processor| processor.process_scored(scored));
+        split_scores(processed)
+    }
+
+    /// # [`BasicRAGChain::generate`]
+    /// Sends the prompts to the chat client, separate from the chain so the call is timed
+    /// in its own span.
+    ///
+    /// # Arguments
+    /// * `prompts`: [`Vec<PromptMessage>`] - the system prompt, if any, and the user prompt
+    /// * `context`: [`Option<&InvocationContext>`] - passed to the chat client if present
+    ///
+    /// # Returns
+    /// [`DetailedChatResponse`] - the response, without any details when there was no context
+    /// and the chain accepts truncated answers
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(name = "basic_rag_chain.generate", skip_all, fields(messages = prompts.len()))
+    )]
+    async fn generate(
+        &self,
+        prompts: Vec<PromptMessage>,
+        context: Option<&InvocationContext>,
+    ) -> Result<DetailedChatResponse, RagChainError<T::ErrorType, U::ErrorType>> {
+        let context: InvocationContext = match (context, self.reject_truncated) {
+            (Some(context), _) => *context,
+            // Only the detailed response says why the model stopped
+            (None, true) => InvocationContext::new(),
+            (None, false) => {
+                let message: PromptMessage = self
+                    .chat_client
+                    .invoke(prompts)
+                    .await
+                    .map_err(RagChainError::ChatClientError)?;
+                return Ok(DetailedChatResponse {
+                    message,
+                    request_id: InvocationContext::new().request_id(),
+                    provider_request_id: None,
+                    system_fingerprint: None,
+                    usage: None,
+                    finish_reason: None,
+                });
+            }
+        };
+        let response: DetailedChatResponse = self
             .chat_client
-            .invoke(prompts)
+            .invoke_with_context(prompts, &context)
             .await
-            .map_err(RagChainError::ChatClientError::<T::ErrorType, U::ErrorType>)?;
+            .map_err(RagChainError::ChatClientError)?;
+        self.reject_if_truncated(response)
+    }
 
-        Ok(result)
+    /// # [`BasicRAGChain::reject_if_truncated`]
+    ///
+    /// # Errors
+    /// * [`RagChainError::Truncated`] - if the chain rejects truncated answers and the
+    ///   answer was cut short by the token limit.
+    fn reject_if_truncated(
+        &self,
+        response: DetailedChatResponse,
+    ) -> Result<DetailedChatResponse, RagChainError<T::ErrorType, U::ErrorType>> {
+        match self.reject_truncated && response.is_truncated() {
+            true => Err(RagChainError::Truncated(response.message)),
+            false => Ok(response),
+        }
+    }
+}
+
+/// Keeps the chunks scoring at least the minimum score, if there is one
+fn above_min_score(scored: Vec<ScoredChunk>, min_score: Option<f32>) -> Chunks {
+    scored
+        .into_iter()
+        .filter(|scored| min_score.is_none_or(|min_score| scored.score >= min_score))
+        .map(|scored| scored.chunk)
+        .collect()
+}
+
+/// Separates the chunks from their scores so the chunks can be fitted to the prompt
+fn split_scores(scored: Vec<ScoredChunk>) -> (Chunks, Vec<f32>) {
+    scored
+        .into_iter()
+        .map(|scored| (scored.chunk, scored.score))
+        .unzip()
+}
+
+/// Pairs the chunks included in the prompt back up with the scores they were retrieved with,
+/// fitting only ever drops chunks from the end so the scores line up
+fn with_scores(included: Chunks, scores: &[f32]) -> Vec<ScoredChunk> {
+    included
+        .into_iter()
+        .zip(scores.iter().copied())
+        .map(|(chunk, score)| ScoredChunk::new(chunk, score))
+        .collect()
+}
+
+impl<T, U> BasicRAGChain<T, U>
+where
+    T: AsyncChatClient,
+    U: FilteredRetriever,
+{
+    /// # [`BasicRAGChain::invoke_chain_with_filter`]
+    ///
+    /// The same as [`BasicRAGChain::invoke_chain`] but only chunks whose metadata matches
+    /// the filter are retrieved as supporting information.
+    ///
+    /// # Arguments
+    /// * `user_message`: [`PromptMessage`] - the user prompt, this will be used to retrieve supporting chunks
+    /// * `limit`: impl [`Into<RetrievalLimit>`] - a [`std::num::NonZeroU32`] top_k of supporting chunks to retrieve,
+    ///   or a [`crate::chains::ContextBudget`] to include as many as fit in the budget
+    /// * `filter`: &[`MetadataFilter`] - the filter the metadata of each supporting chunk must match
+    ///
+    /// # Errors
+    /// * [`RagChainError`] - any of the errors from [`BasicRAGChain::invoke_chain`].
+    ///
+    /// # Returns
+    /// [`PromptMessage`] - the response from the chat client
+    pub async fn invoke_chain_with_filter(
+        &self,
+        user_message: PromptMessage,
+        limit: impl Into<RetrievalLimit>,
+        filter: &MetadataFilter,
+    ) -> Result<PromptMessage, RagChainError<T::ErrorType, U::ErrorType>> {
+        let content = user_message.content();
+        let output: PipelineOutput = self
+            .run_pipeline(
+                &user_message,
+                limit.into(),
+                |top_k| self.retrieve_filtered(content, top_k, filter),
+                None,
+                self.timeouts,
+            )
+            .await?;
+        Ok(output.response.message)
+    }
+
+    /// # [`BasicRAGChain::retrieve_filtered`]
+    ///
+    /// Retrieves the supporting chunks whose metadata matches the filter, when a minimum
+    /// score or reranker is set the chunks are retrieved with their scores, reranked and
+    /// any scoring below the minimum are dropped.
+    ///
+    /// # Arguments
+    /// * `text`: &[`str`] - the text to retrieve supporting chunks for
+    /// * `top_k`: [`NonZeroU32`] - the number of chunks to retrieve
+    /// * `filter`: &[`MetadataFilter`] - the filter the metadata of each chunk must match
+    async fn retrieve_filtered(
+        &self,
+        text: &str,
+        top_k: NonZeroU32,
+        filter: &MetadataFilter,
+    ) -> Result<Chunks, RagChainError<T::ErrorType, U::ErrorType>> {
+        match (self.min_score, &self.reranker) {
+            (None, None) => self
+                .retriever
+                .retrieve_with_filter(text, top_k, filter)
+                .await
+                .map_err(RagChainError::RetrieverError),
+            (min_score, _) => {
+                let scored: Vec<ScoredChunk> = self
+                    .retriever
+                    .retrieve_with_filter_and_scores(text, top_k, filter)
+                    .await
+                    .map_err(RagChainError::RetrieverError)?;
+                Ok(above_min_score(self.rerank(text, scored).await?, min_score))
+            }
+        }
+    }
+}
+
+/// # [`PipelineOutput`]
+/// What [`BasicRAGChain::run_pipeline`] produced, each invocation returns the parts it needs.
+struct PipelineOutput {
+    response: DetailedChatResponse,
+    /// The supporting chunks included in the prompt
+    included: Chunks,
+    /// The scores of the included chunks, empty unless they were retrieved with scores
+    scores: Vec<f32>,
+    chunks_dropped: usize,
+    timings: Timings,
+}
+
+/// # [`Retrieved`]
+/// The supporting chunks as [`BasicRAGChain::run_pipeline`] retrieved them, the scores are
+/// only kept when the invocation returns them.
+enum Retrieved {
+    Chunks(Chunks),
+    Scored(Vec<ScoredChunk>),
+}
+
+impl From<Chunks> for Retrieved {
+    fn from(chunks: Chunks) -> Self {
+        Retrieved::Chunks(chunks)
+    }
+}
+
+impl From<Vec<ScoredChunk>> for Retrieved {
+    fn from(scored: Vec<ScoredChunk>) -> Self {
+        Retrieved::Scored(scored)
     }
 }
 
@@ -195,10 +918,22 @@ where
 {
     #[builder(default, setter(strip_option))]
     system_prompt: Option<PromptMessage>,
+    /// Resolves the `{{name}}` placeholders in the system prompt on each invocation
+    #[builder(default, setter(strip_option))]
+    prompt_variables: Option<PromptVariables>,
+    /// Combines the user prompt with the supporting chunks, see [`PromptTemplate`]
+    #[builder(default)]
+    prompt_template: PromptTemplate,
     chat_client: T,
     retriever: U,
 }
 
+/// The result of [`BasicStreamedRAGChain::invoke_chain_with_context`]
+type TimedStreamResult<T, U> = Result<
+    TimedCompletionStream<<T as AsyncStreamedChatClient>::Item>,
+    RagChainError<<T as AsyncStreamedChatClient>::ErrorType, <U as AsyncRetriever>::ErrorType>,
+>;
+
 impl<T, U> BasicStreamedRAGChain<T, U>
 where
     T: AsyncStreamedChatClient,
@@ -207,21 +942,28 @@ where
     pub async fn invoke_chain(
         &self,
         user_message: PromptMessage,
-        top_k: NonZeroU32,
+        limit: impl Into<RetrievalLimit>,
     ) -> Result<T::Item, RagChainError<T::ErrorType, U::ErrorType>> {
+        let limit: RetrievalLimit = limit.into();
+        validate_top_k(&self.retriever, limit.fetch_k())?;
+        let system_prompt: Option<PromptMessage> =
+            resolve_system_prompt(self.system_prompt.as_ref(), self.prompt_variables.as_ref())
+                .map_err(RagChainError::PromptVariableError::<T::ErrorType, U::ErrorType>)?;
         let content = user_message.content();
         let chunks: Chunks = self
             .retriever
-            .retrieve(content, top_k)
+            .retrieve(content, limit.fetch_k())
             .await
             .map_err(RagChainError::RetrieverError::<T::ErrorType, U::ErrorType>)?;
 
-        let new_prompt: PromptMessage = build_prompt(&user_message, chunks);
-
-        let prompts = match self.system_prompt.clone() {
-            None => vec![new_prompt],
-            Some(prompt) => vec![prompt, new_prompt],
-        };
+        let (prompts, _) = build_prompts(
+            system_prompt.as_ref(),
+            &self.prompt_template,
+            &user_message,
+            chunks,
+            &limit,
+            |text| self.chat_client.count_tokens(text),
+        );
 
         let result = self
             .chat_client
@@ -231,21 +973,160 @@ where
 
         Ok(result)
     }
+
+    /// # [`BasicStreamedRAGChain::invoke_chain_with_sources`]
+    ///
+    /// The same as [`BasicStreamedRAGChain::invoke_chain`] but the supporting chunks included
+    /// in the prompt are returned alongside the stream. Retrieval completes before streaming
+    /// begins so the sources can be shown while the answer is still arriving. The chunks are
+    /// retrieved with [`AsyncRetriever::retrieve_with_scores`] so each source carries its score.
+    ///
+    /// # Arguments
+    /// * `user_message`: [`PromptMessage`] - the user prompt, this will be used to retrieve supporting chunks
+    /// * `limit`: impl [`Into<RetrievalLimit>`] - a [`std::num::NonZeroU32`] top_k of supporting chunks to retrieve,
+    ///   or a [`crate::chains::ContextBudget`] to include as many as fit in the budget
+    ///
+    /// # Errors
+    /// * [`RagChainError`] - if the chat client or retriever fails, top_k (or fetch_k) is larger than the retriever allows
+    ///   or a variable in the system prompt could not be resolved.
+    ///
+    /// # Returns
+    /// [`StreamedRagResponse`] - the stream returned by the chat client and the sources included in the prompt
+    pub async fn invoke_chain_with_sources(
+        &self,
+        user_message: PromptMessage,
+        limit: impl Into<RetrievalLimit>,
+    ) -> Result<StreamedRagResponse<T::Item>, RagChainError<T::ErrorType, U::ErrorType>> {
+        let limit: RetrievalLimit = limit.into();
+        validate_top_k(&self.retriever, limit.fetch_k())?;
+        let system_prompt: Option<PromptMessage> =
+            resolve_system_prompt(self.system_prompt.as_ref(), self.prompt_variables.as_ref())
+                .map_err(RagChainError::PromptVariableError::<T::ErrorType, U::ErrorType>)?;
+        let scored: Vec<ScoredChunk> = self
+            .retriever
+            .retrieve_with_scores(user_message.content(), limit.fetch_k())
+            .await
+            .map_err(RagChainError::RetrieverError::<T::ErrorType, U::ErrorType>)?;
+        let (chunks, scores): (Chunks, Vec<f32>) = split_scores(scored);
+        let retrieved: usize = chunks.len();
+
+        let (prompts, included) = build_prompts(
+            system_prompt.as_ref(),
+            &self.prompt_template,
+            &user_message,
+            chunks,
+            &limit,
+            |text| self.chat_client.count_tokens(text),
+        );
+
+        let stream = self
+            .chat_client
+            .invoke_stream(prompts)
+            .await
+            .map_err(RagChainError::ChatClientError::<T::ErrorType, U::ErrorType>)?;
+
+        Ok(StreamedRagResponse {
+            stream,
+            chunks_dropped: retrieved - included.len(),
+            sources: with_scores(included, &scores),
+        })
+    }
+
+    /// # [`BasicStreamedRAGChain::invoke_chain_with_context`]
+    ///
+    /// The same as [`BasicStreamedRAGChain::invoke_chain`] but the context is passed
+    /// down to the retriever and the chat client. The stream is wrapped so the retrieval
+    /// time, time to first token, generation time and the number of chunks used can be read
+    /// from it, see [`TimedCompletionStream`].
+    ///
+    /// # Arguments
+    /// * `user_message`: [`PromptMessage`] - the user prompt, this will be used to retrieve supporting chunks
+    /// * `limit`: impl [`Into<RetrievalLimit>`] - a [`std::num::NonZeroU32`] top_k of supporting chunks to retrieve,
+    ///   or a [`crate::chains::ContextBudget`] to include as many as fit in the budget
+    /// * `context`: &[`InvocationContext`] - the context of the invocation
+    ///
+    /// # Errors
+    /// * [`RagChainError`] - if the chat client or retriever fails, top_k (or fetch_k) is larger than the retriever allows
+    ///   or a variable in the system prompt could not be resolved.
+    ///
+    /// # Returns
+    /// [`TimedCompletionStream`] - the stream returned by the chat client
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            name = "basic_streamed_rag_chain.invoke_chain_with_context",
+            skip_all,
+            fields(request_id = %context.request_id())
+        )
+    )]
+    pub async fn invoke_chain_with_context(
+        &self,
+        user_message: PromptMessage,
+        limit: impl Into<RetrievalLimit>,
+        context: &InvocationContext,
+    ) -> TimedStreamResult<T, U> {
+        let limit: RetrievalLimit = limit.into();
+        validate_top_k(&self.retriever, limit.fetch_k())?;
+        let system_prompt: Option<PromptMessage> =
+            resolve_system_prompt(self.system_prompt.as_ref(), self.prompt_variables.as_ref())
+                .map_err(RagChainError::PromptVariableError::<T::ErrorType, U::ErrorType>)?;
+        let started = Instant::now();
+        let content = user_message.content();
+        let chunks: Chunks = self
+            .retriever
+            .retrieve_with_context(content, limit.fetch_k(), context)
+            .await
+            .map_err(RagChainError::RetrieverError::<T::ErrorType, U::ErrorType>)?;
+        let retrieved: usize = chunks.len();
+
+        let (prompts, included) = build_prompts(
+            system_prompt.as_ref(),
+            &self.prompt_template,
+            &user_message,
+            chunks,
+            &limit,
+            |text| self.chat_client.count_tokens(text),
+        );
+
+        let generation_started = Instant::now();
+        let result = self
+            .chat_client
+            .invoke_stream_with_context(prompts, context)
+            .await
+            .map_err(RagChainError::ChatClientError::<T::ErrorType, U::ErrorType>)?;
+
+        Ok(TimedCompletionStream::new(
+            result,
+            started,
+            generation_started,
+            Some(generation_started - started),
+        )
+        .with_chunks(included.len(), retrieved - included.len()))
+    }
 }
 
 #[cfg(test)]
 mod basic_rag_chain_tests {
     use super::*;
+    use crate::chains::{ContextBudget, DeduplicateBySimilarity, GroupByMetadataKey};
     use crate::{
         clients::{
-            ChatCompletionStream, MockAsyncChatClient, MockAsyncStreamedChatClient,
-            MockChatCompletionStream,
+            ChatCompletionStream, DynAsyncChatClient, FinishReason, MessageBody, MessageMeta,
+            MockAsyncChatClient, MockAsyncStreamedChatClient, MockChatCompletionStream,
+            TokenCounter,
+        },
+        common::{Chunk, TokenUsage},
+        retrievers::{
+            DynAsyncRetriever, MockAsyncReranker, MockAsyncRetriever, RerankingRetriever,
         },
-        common::Chunk,
-        retrievers::MockAsyncRetriever,
     };
     use mockall::predicate::eq;
+    use serde_json::json;
+    use std::num::NonZeroU32;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::time::Duration;
     use std::vec;
+    use tokio::time::sleep;
 
     #[tokio::test]
     async fn test_chain_succeeds() {
@@ -293,35 +1174,1239 @@ mod basic_rag_chain_tests {
     }
 
     #[tokio::test]
-    async fn test_streamed_chain_succeeds() {
-        const SYSTEM_MESSAGE: &str = "you are a study buddy";
-        const USER_MESSAGE: &str = "please tell me about my lecture on operating systems";
-        const RAG_CHUNK_1: &str = "data point 1";
-        const RAG_CHUNK_2: &str = "data point 2";
-        let expected_user_message: String = format!(
-            "{}\n{}\n{}\n{}\n",
-            USER_MESSAGE, "Here is some supporting information:", RAG_CHUNK_1, RAG_CHUNK_2
-        );
-
-        let system_prompt = PromptMessage::SystemMessage(SYSTEM_MESSAGE.into());
-        let mut chat_client = MockAsyncStreamedChatClient::new();
+    async fn test_chain_with_boxed_client_and_retriever() {
+        let mut chat_client = MockAsyncChatClient::new();
         let mut retriever = MockAsyncRetriever::new();
         retriever
             .expect_retrieve()
-            .with(eq(USER_MESSAGE), eq(NonZeroU32::new(2).unwrap()))
-            .returning(|_, _| Ok(vec![Chunk::new(RAG_CHUNK_1), Chunk::new(RAG_CHUNK_2)]));
-
+            .returning(|_, _| Ok(vec![Chunk::new("data point")]));
         chat_client
-            .expect_invoke_stream()
-            .with(eq(vec![
-                system_prompt.clone(),
-                PromptMessage::HumanMessage(expected_user_message.into()),
-            ]))
-            .returning(move |_| {
-                let mut stream = MockChatCompletionStream::new();
-                stream
-                    .expect_next()
-                    .returning(|| Some(Ok(PromptMessage::AIMessage("mocked response".into()))));
+            .expect_invoke()
+            .returning(|_| Ok(PromptMessage::AIMessage("mocked response".into())));
+        chat_client
+            .expect_count_tokens()
+            .returning(|text| text.len());
+
+        let chain: BasicRAGChain<Box<dyn DynAsyncChatClient>, Box<dyn DynAsyncRetriever>> =
+            BasicRAGChain::builder()
+                .system_prompt(PromptMessage::SystemMessage("system".into()))
+                .chat_client(Box::new(chat_client) as Box<dyn DynAsyncChatClient>)
+                .retriever(Box::new(retriever) as Box<dyn DynAsyncRetriever>)
+                .build();
+
+        let result = chain
+            .invoke_chain(
+                PromptMessage::HumanMessage("question".into()),
+                NonZeroU32::new(1).unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(PromptMessage::AIMessage("mocked response".into()), result);
+    }
+
+    #[tokio::test]
+    async fn test_chain_sends_the_user_message_metadata_to_the_chat_client() {
+        const USER_MESSAGE: &str = "what is the leave policy";
+        let meta = MessageMeta::default().with_name("alice");
+        let mut chat_client = MockAsyncChatClient::new();
+        let mut retriever = MockAsyncRetriever::new();
+        retriever
+            .expect_retrieve()
+            .returning(|_, _| Ok(vec![Chunk::new("25 days a year")]));
+        chat_client
+            .expect_invoke()
+            .with(eq(vec![PromptMessage::HumanMessage(
+                MessageBody::from(format!(
+                    "{}\n{}\n{}\n",
+                    USER_MESSAGE, "Here is some supporting information:", "25 days a year"
+                ))
+                .with_meta(meta.clone()),
+            )]))
+            .returning(|_| Ok(PromptMessage::AIMessage("mocked response".into())));
+
+        let chain: BasicRAGChain<MockAsyncChatClient, MockAsyncRetriever> =
+            BasicRAGChain::builder()
+                .chat_client(chat_client)
+                .retriever(retriever)
+                .build();
+        let user_message =
+            PromptMessage::HumanMessage(MessageBody::from(USER_MESSAGE).with_meta(meta));
+        let response = chain
+            .invoke_chain(user_message, NonZeroU32::new(1).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response, PromptMessage::AIMessage("mocked response".into()));
+    }
+
+    #[tokio::test]
+    async fn test_chain_reranks_with_the_builder_reranker_before_the_min_score() {
+        const USER_MESSAGE: &str = "how long do refunds take";
+        let candidates = vec![
+            ScoredChunk::new(Chunk::new("shipping takes 3 days"), 0.9),
+            ScoredChunk::new(Chunk::new("refunds take 5 days"), 0.2),
+            ScoredChunk::new(Chunk::new("returns are free"), 0.1),
+        ];
+        let mut retriever = MockAsyncRetriever::new();
+        let retrieved = candidates.clone();
+        retriever.expect_retrieve().never();
+        retriever
+            .expect_retrieve_with_scores()
+            .with(eq(USER_MESSAGE), eq(NonZeroU32::new(3).unwrap()))
+            .returning(move |_, _| Ok(retrieved.clone()));
+        let mut reranker = MockAsyncReranker::new();
+        reranker
+            .expect_rerank()
+            .with(
+                eq(USER_MESSAGE),
+                eq(candidates),
+                eq(NonZeroU32::new(2).unwrap()),
+            )
+            .times(1)
+            .returning(|_, _, _| {
+                Ok(vec![
+                    ScoredChunk::new(Chunk::new("refunds take 5 days"), 0.95),
+                    ScoredChunk::new(Chunk::new("shipping takes 3 days"), 0.3),
+                ])
+            });
+        let mut chat_client = MockAsyncChatClient::new();
+        chat_client
+            .expect_invoke()
+            .with(eq(vec![PromptMessage::HumanMessage(
+                format!(
+                    "{}\n{}\n{}\n",
+                    USER_MESSAGE, "Here is some supporting information:", "refunds take 5 days"
+                )
+                .into(),
+            )]))
+            .returning(|_| Ok(PromptMessage::AIMessage("5 days".into())));
+
+        let chain: BasicRAGChain<MockAsyncChatClient, MockAsyncRetriever> =
+            BasicRAGChain::builder()
+                .min_score(0.5)
+                .reranker(reranker, NonZeroU32::new(2).unwrap())
+                .chat_client(chat_client)
+                .retriever(retriever)
+                .build();
+        let response = chain
+            .invoke_chain(
+                PromptMessage::HumanMessage(USER_MESSAGE.into()),
+                NonZeroU32::new(3).unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response, PromptMessage::AIMessage("5 days".into()));
+    }
+
+    #[tokio::test]
+    async fn test_chain_returns_reranker_errors() {
+        let mut retriever = MockAsyncRetriever::new();
+        retriever
+            .expect_retrieve_with_scores()
+            .returning(|_, _| Ok(vec![ScoredChunk::new(Chunk::new("a"), 0.9)]));
+        let mut reranker = MockAsyncReranker::new();
+        reranker
+            .expect_rerank()
+            .returning(|_, _, _| Err(std::io::Error::other("rerank failed")));
+        let mut chat_client = MockAsyncChatClient::new();
+        chat_client.expect_invoke().never();
+
+        let chain: BasicRAGChain<MockAsyncChatClient, MockAsyncRetriever> =
+            BasicRAGChain::builder()
+                .reranker(reranker, NonZeroU32::new(1).unwrap())
+                .chat_client(chat_client)
+                .retriever(retriever)
+                .build();
+        let error = chain
+            .invoke_chain_with_sources(
+                PromptMessage::HumanMessage("query".into()),
+                NonZeroU32::new(1).unwrap(),
+            )
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(error, RagChainError::RerankerError(message) if message == "rerank failed")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_chain_reranks_candidates_before_building_the_prompt() {
+        const USER_MESSAGE: &str = "how long do refunds take";
+        let candidates = vec![
+            ScoredChunk::new(Chunk::new("shipping takes 3 days"), 0.9),
+            ScoredChunk::new(Chunk::new("refunds take 5 days"), 0.8),
+        ];
+        let expected_user_message: String = format!(
+            "{}\n{}\n{}\n",
+            USER_MESSAGE, "Here is some supporting information:", "refunds take 5 days"
+        );
+        let mut retriever = MockAsyncRetriever::new();
+        let retrieved = candidates.clone();
+        retriever
+            .expect_retrieve_with_scores()
+            .with(eq(USER_MESSAGE), eq(NonZeroU32::new(2).unwrap()))
+            .returning(move |_, _| Ok(retrieved.clone()));
+        let mut reranker = MockAsyncReranker::new();
+        reranker
+            .expect_rerank()
+            .with(
+                eq(USER_MESSAGE),
+                eq(candidates),
+                eq(NonZeroU32::new(1).unwrap()),
+            )
+            .returning(|_, _, _| {
+                Ok(vec![ScoredChunk::new(
+                    Chunk::new("refunds take 5 days"),
+                    0.99,
+                )])
+            });
+        let mut chat_client = MockAsyncChatClient::new();
+        chat_client
+            .expect_invoke()
+            .with(eq(vec![PromptMessage::HumanMessage(
+                expected_user_message.into(),
+            )]))
+            .returning(|_| Ok(PromptMessage::AIMessage("5 days".into())));
+
+        let chain = BasicRAGChain::builder()
+            .chat_client(chat_client)
+            .retriever(RerankingRetriever::new(
+                retriever,
+                reranker,
+                NonZeroU32::new(1).unwrap(),
+            ))
+            .build();
+        let response = chain
+            .invoke_chain_with_sources(
+                PromptMessage::HumanMessage(USER_MESSAGE.into()),
+                NonZeroU32::new(2).unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.message, PromptMessage::AIMessage("5 days".into()));
+        assert_eq!(
+            response.sources,
+            vec![ScoredChunk::new(Chunk::new("refunds take 5 days"), 0.99)]
+        );
+    }
+
+    #[cfg(feature = "tracing")]
+    #[tokio::test]
+    async fn test_chain_spans_nest_retrieval_and_generation() {
+        use crate::common::span_recorder::SpanRecorder;
+        use tracing::Level;
+        const USER_MESSAGE: &str = "a question which should not be logged";
+        let mut chat_client = MockAsyncChatClient::new();
+        let mut retriever = MockAsyncRetriever::new();
+        retriever
+            .expect_retrieve()
+            .returning(|_, _| Ok(vec![Chunk::new("data point 1")]));
+        chat_client
+            .expect_invoke()
+            .returning(|_| Ok(PromptMessage::AIMessage("mocked response".into())));
+        let chain: BasicRAGChain<MockAsyncChatClient, MockAsyncRetriever> =
+            BasicRAGChain::builder()
+                .chat_client(chat_client)
+                .retriever(retriever)
+                .build();
+        let recorder = SpanRecorder::default();
+        let _guard = tracing::subscriber::set_default(recorder.clone());
+
+        chain
+            .invoke_chain(
+                PromptMessage::HumanMessage(USER_MESSAGE.into()),
+                NonZeroU32::new(2).unwrap(),
+            )
+            .await
+            .unwrap();
+
+        let hierarchy: Vec<(&str, Option<&str>)> = recorder
+            .spans()
+            .iter()
+            .map(|span| (span.name, span.parent))
+            .collect();
+        assert_eq!(
+            hierarchy,
+            vec![
+                ("basic_rag_chain.invoke_chain", None),
+                (
+                    "basic_rag_chain.retrieve",
+                    Some("basic_rag_chain.invoke_chain")
+                ),
+                (
+                    "basic_rag_chain.generate",
+                    Some("basic_rag_chain.invoke_chain")
+                ),
+            ]
+        );
+        assert_eq!(
+            recorder
+                .span("basic_rag_chain.retrieve")
+                .unwrap()
+                .field("top_k"),
+            Some("2")
+        );
+        // The prompt is only recorded at trace level
+        let prompt_events: Vec<Level> = recorder
+            .events()
+            .into_iter()
+            .filter(|event| event.fields.contains(USER_MESSAGE))
+            .map(|event| event.level)
+            .collect();
+        assert_eq!(prompt_events, vec![Level::TRACE]);
+    }
+
+    #[cfg(feature = "tracing")]
+    #[tokio::test]
+    async fn test_chain_span_records_the_request_id() {
+        use crate::common::span_recorder::SpanRecorder;
+        let mut chat_client = MockAsyncChatClient::new();
+        let mut retriever = MockAsyncRetriever::new();
+        retriever
+            .expect_retrieve()
+            .returning(|_, _| Ok(vec![Chunk::new("data point 1")]));
+        chat_client
+            .expect_invoke()
+            .returning(|_| Ok(PromptMessage::AIMessage("mocked response".into())));
+        let chain: BasicRAGChain<MockAsyncChatClient, MockAsyncRetriever> =
+            BasicRAGChain::builder()
+                .chat_client(chat_client)
+                .retriever(retriever)
+                .build();
+        let context = InvocationContext::new();
+        let recorder = SpanRecorder::default();
+        let _guard = tracing::subscriber::set_default(recorder.clone());
+
+        chain
+            .invoke_chain_with_context(
+                PromptMessage::HumanMessage("what".into()),
+                NonZeroU32::new(2).unwrap(),
+                &context,
+            )
+            .await
+            .unwrap();
+
+        let expected: String = context.request_id().to_string();
+        assert_eq!(
+            recorder
+                .span("basic_rag_chain.invoke_chain_with_context")
+                .unwrap()
+                .field("request_id"),
+            Some(expected.as_str())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_chain_uses_custom_prompt_template() {
+        const USER_MESSAGE: &str = "when is the exam";
+        let mut chat_client = MockAsyncChatClient::new();
+        let mut retriever = MockAsyncRetriever::new();
+
+        retriever.expect_retrieve().returning(|_, _| {
+            Ok(vec![
+                Chunk::new_with_metadata("the exam is in May", json!({"source": "syllabus.pdf"})),
+                Chunk::new_with_metadata("resits are in August", json!({"source": "faq.md"})),
+            ])
+        });
+        chat_client
+            .expect_invoke()
+            .with(eq(vec![PromptMessage::HumanMessage(
+                "Answer using only these sources:\n[1] the exam is in May (syllabus.pdf)\n[2] resits are in August (faq.md)\nQuestion: when is the exam"
+                    .into(),
+            )]))
+            .returning(|_| Ok(PromptMessage::AIMessage("mocked response".into())));
+
+        let template = PromptTemplate::new(
+            "Answer using only these sources:\n{{context}}Question: {{question}}",
+        )
+        .with_chunk_template("[{{index}}] {{content}} ({{metadata.source}})\n");
+        let chain: BasicRAGChain<MockAsyncChatClient, MockAsyncRetriever> =
+            BasicRAGChain::builder()
+                .prompt_template(template)
+                .chat_client(chat_client)
+                .retriever(retriever)
+                .build();
+
+        let result = chain
+            .invoke_chain(
+                PromptMessage::HumanMessage(USER_MESSAGE.into()),
+                NonZeroU32::new(2).unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(PromptMessage::AIMessage("mocked response".into()), result)
+    }
+
+    #[tokio::test]
+    async fn test_chain_drops_chunks_below_min_score() {
+        const USER_MESSAGE: &str = "please tell me about my lecture on operating systems";
+        let expected_user_message: String = format!(
+            "{}\n{}\n{}\n",
+            USER_MESSAGE, "Here is some supporting information:", "relevant"
+        );
+        let mut chat_client = MockAsyncChatClient::new();
+        let mut retriever = MockAsyncRetriever::new();
+
+        retriever.expect_retrieve().never();
+        retriever
+            .expect_retrieve_with_scores()
+            .with(eq(USER_MESSAGE), eq(NonZeroU32::new(3).unwrap()))
+            .returning(|_, _| {
+                Ok(vec![
+                    ScoredChunk::new(Chunk::new("relevant"), 0.82),
+                    ScoredChunk::new(Chunk::new("borderline"), 0.49),
+                    ScoredChunk::new(Chunk::new("unrelated"), 0.1),
+                ])
+            });
+        chat_client
+            .expect_invoke()
+            .with(eq(vec![PromptMessage::HumanMessage(
+                expected_user_message.into(),
+            )]))
+            .returning(|_| Ok(PromptMessage::AIMessage("mocked response".into())));
+
+        let chain: BasicRAGChain<MockAsyncChatClient, MockAsyncRetriever> =
+            BasicRAGChain::builder()
+                .chat_client(chat_client)
+                .retriever(retriever)
+                .min_score(0.5)
+                .build();
+        let response = chain
+            .invoke_chain_with_context(
+                PromptMessage::HumanMessage(USER_MESSAGE.into()),
+                NonZeroU32::new(3).unwrap(),
+                &InvocationContext::new(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.chunks_used, 1);
+    }
+
+    #[tokio::test]
+    async fn test_chain_picks_chunks_by_mmr() {
+        const USER_MESSAGE: &str = "please tell me about my lecture on operating systems";
+        let expected_user_message: String = format!(
+            "{}\n{}\n{}\n{}\n",
+            USER_MESSAGE, "Here is some supporting information:", "scheduling", "paging"
+        );
+        let mut chat_client = MockAsyncChatClient::new();
+        let mut retriever = MockAsyncRetriever::new();
+
+        retriever.expect_retrieve().never();
+        retriever.expect_retrieve_with_scores().never();
+        retriever
+            .expect_retrieve_mmr_with_scores()
+            .with(
+                eq(USER_MESSAGE),
+                eq(NonZeroU32::new(2).unwrap()),
+                eq(NonZeroU32::new(10).unwrap()),
+                eq(0.3),
+            )
+            .times(1)
+            .returning(|_, _, _, _| {
+                Ok(vec![
+                    ScoredChunk::new(Chunk::new("scheduling"), 0.9),
+                    ScoredChunk::new(Chunk::new("paging"), 0.6),
+                ])
+            });
+        chat_client
+            .expect_invoke()
+            .with(eq(vec![PromptMessage::HumanMessage(
+                expected_user_message.into(),
+            )]))
+            .returning(|_| Ok(PromptMessage::AIMessage("mocked response".into())));
+
+        let chain: BasicRAGChain<MockAsyncChatClient, MockAsyncRetriever> =
+            BasicRAGChain::builder()
+                .chat_client(chat_client)
+                .retriever(retriever)
+                .retrieval_strategy(RetrievalStrategy::Mmr {
+                    fetch_k: NonZeroU32::new(10).unwrap(),
+                    lambda: 0.3,
+                })
+                .build();
+        let response = chain
+            .invoke_chain(
+                PromptMessage::HumanMessage(USER_MESSAGE.into()),
+                NonZeroU32::new(2).unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response, PromptMessage::AIMessage("mocked response".into()));
+    }
+
+    #[tokio::test]
+    async fn test_chain_with_sources_returns_retrieved_chunks() {
+        const USER_MESSAGE: &str = "what is the leave policy";
+        let retrieved = vec![
+            ScoredChunk::new(
+                Chunk::new_with_metadata("25 days a year", json!({"source": "handbook.pdf"})),
+                0.91,
+            ),
+            ScoredChunk::new(
+                Chunk::new_with_metadata("ask your manager", json!({"source": "faq.md"})),
+                0.64,
+            ),
+            ScoredChunk::new(Chunk::new("the canteen menu"), 0.2),
+        ];
+        let mut chat_client = MockAsyncChatClient::new();
+        let mut retriever = MockAsyncRetriever::new();
+
+        retriever.expect_retrieve().never();
+        let returned = retrieved.clone();
+        retriever
+            .expect_retrieve_with_scores()
+            .with(eq(USER_MESSAGE), eq(NonZeroU32::new(3).unwrap()))
+            .returning(move |_, _| Ok(returned.clone()));
+        chat_client
+            .expect_invoke()
+            .with(eq(vec![PromptMessage::HumanMessage(
+                format!(
+                    "{}\n{}\n{}\n{}\n",
+                    USER_MESSAGE,
+                    "Here is some supporting information:",
+                    "25 days a year",
+                    "ask your manager"
+                )
+                .into(),
+            )]))
+            .returning(|_| Ok(PromptMessage::AIMessage("mocked response".into())));
+
+        let chain: BasicRAGChain<MockAsyncChatClient, MockAsyncRetriever> =
+            BasicRAGChain::builder()
+                .min_score(0.5)
+                .chat_client(chat_client)
+                .retriever(retriever)
+                .build();
+
+        let response = chain
+            .invoke_chain_with_sources(
+                PromptMessage::HumanMessage(USER_MESSAGE.into()),
+                NonZeroU32::new(3).unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            response.message,
+            PromptMessage::AIMessage("mocked response".into())
+        );
+        assert_eq!(response.sources, retrieved[..2]);
+    }
+
+    #[tokio::test]
+    async fn test_chain_with_sources_applies_post_processors() {
+        const USER_MESSAGE: &str = "what is the leave policy";
+        let handbook = |content: &str| Chunk::new_with_metadata(content, json!({"document_id": 1}));
+        let mut chat_client = MockAsyncChatClient::new();
+        let mut retriever = MockAsyncRetriever::new();
+
+        retriever
+            .expect_retrieve_with_scores()
+            .returning(move |_, _| {
+                Ok(vec![
+                    ScoredChunk::new(handbook("25 days a year"), 0.91),
+                    ScoredChunk::new(Chunk::new("ask your manager"), 0.8),
+                    ScoredChunk::new(Chunk::new("Ask your manager."), 0.7),
+                    ScoredChunk::new(handbook("plus bank holidays"), 0.6),
+                ])
+            });
+        chat_client
+            .expect_invoke()
+            .with(eq(vec![PromptMessage::HumanMessage(
+                format!(
+                    "{}\n{}\n{}\n{}\n",
+                    USER_MESSAGE,
+                    "Here is some supporting information:",
+                    "25 days a year plus bank holidays",
+                    "ask your manager"
+                )
+                .into(),
+            )]))
+            .returning(|_| Ok(PromptMessage::AIMessage("mocked response".into())));
+
+        let chain: BasicRAGChain<MockAsyncChatClient, MockAsyncRetriever> =
+            BasicRAGChain::builder()
+                .post_processor(DeduplicateBySimilarity::new(0.9))
+                .post_processor(GroupByMetadataKey::new("document_id").with_separator(" "))
+                .chat_client(chat_client)
+                .retriever(retriever)
+                .build();
+
+        let response = chain
+            .invoke_chain_with_sources(
+                PromptMessage::HumanMessage(USER_MESSAGE.into()),
+                NonZeroU32::new(4).unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            response.sources,
+            vec![
+                ScoredChunk::new(handbook("25 days a year plus bank holidays"), 0.91),
+                ScoredChunk::new(Chunk::new("ask your manager"), 0.8),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_post_processed_sources_keep_their_own_scores() {
+        let document = |content: &str, id: u32| {
+            Chunk::new_with_metadata(content, json!({ "document_id": id }))
+        };
+        let mut chat_client = MockAsyncChatClient::new();
+        let mut retriever = MockAsyncRetriever::new();
+
+        retriever
+            .expect_retrieve_with_scores()
+            .returning(move |_, _| {
+                Ok(vec![
+                    ScoredChunk::new(document("five days", 1), 0.9),
+                    ScoredChunk::new(document("refunds take five days", 2), 0.4),
+                ])
+            });
+        chat_client
+            .expect_invoke()
+            .returning(|_| Ok(PromptMessage::AIMessage("mocked response".into())));
+
+        let chain: BasicRAGChain<MockAsyncChatClient, MockAsyncRetriever> =
+            BasicRAGChain::builder()
+                .post_processor(GroupByMetadataKey::new("document_id"))
+                .chat_client(chat_client)
+                .retriever(retriever)
+                .build();
+
+        let response = chain
+            .invoke_chain_with_sources(
+                PromptMessage::HumanMessage("how long do refunds take".into()),
+                NonZeroU32::new(2).unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            response.sources,
+            vec![
+                ScoredChunk::new(document("five days", 1), 0.9),
+                ScoredChunk::new(document("refunds take five days", 2), 0.4),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_chain_expands_chunks_with_their_neighbors() {
+        use crate::chains::NeighborExpansion;
+
+        const USER_MESSAGE: &str = "how do I reset my password";
+        let section = |index: u64| {
+            Chunk::new_with_metadata(
+                format!("section {}", index),
+                json!({"document_id": "guide", "chunk_index": index}),
+            )
+        };
+        let mut chat_client = MockAsyncChatClient::new();
+        let mut retriever = MockAsyncRetriever::new();
+        retriever
+            .expect_retrieve()
+            .returning(move |_, _| Ok(vec![section(4), Chunk::new("unrelated")]));
+        retriever
+            .expect_fetch_by_metadata()
+            .withf(move |filter| {
+                (0..8)
+                    .filter(|index| filter.matches(section(*index).metadata()))
+                    .eq([3, 4, 5])
+            })
+            .times(1)
+            .returning(move |_| Ok(vec![section(5), section(3), section(4)]));
+        chat_client
+            .expect_invoke()
+            .with(eq(vec![PromptMessage::HumanMessage(
+                format!(
+                    "{}\n{}\n{}\n{}\n{}\n{}\n",
+                    USER_MESSAGE,
+                    "Here is some supporting information:",
+                    "section 3",
+                    "section 4",
+                    "section 5",
+                    "unrelated"
+                )
+                .into(),
+            )]))
+            .times(1)
+            .returning(|_| Ok(PromptMessage::AIMessage("mocked response".into())));
+
+        let chain: BasicRAGChain<MockAsyncChatClient, MockAsyncRetriever> =
+            BasicRAGChain::builder()
+                .neighbor_expansion(NeighborExpansion::new(NonZeroU32::new(1).unwrap()))
+                .chat_client(chat_client)
+                .retriever(retriever)
+                .build();
+        let response = chain
+            .invoke_chain(
+                PromptMessage::HumanMessage(USER_MESSAGE.into()),
+                NonZeroU32::new(2).unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response, PromptMessage::AIMessage("mocked response".into()));
+    }
+
+    #[tokio::test]
+    async fn test_chain_with_sources_scores_neighbors_as_their_chunk() {
+        use crate::chains::NeighborExpansion;
+
+        let section = |index: u64| {
+            Chunk::new_with_metadata(
+                format!("section {}", index),
+                json!({"document_id": "guide", "chunk_index": index}),
+            )
+        };
+        let mut chat_client = MockAsyncChatClient::new();
+        let mut retriever = MockAsyncRetriever::new();
+        retriever
+            .expect_retrieve_with_scores()
+            .returning(move |_, _| Ok(vec![ScoredChunk::new(section(0), 0.8)]));
+        retriever
+            .expect_fetch_by_metadata()
+            .times(1)
+            .returning(move |_| Ok(vec![section(0), section(1)]));
+        chat_client
+            .expect_invoke()
+            .returning(|_| Ok(PromptMessage::AIMessage("mocked response".into())));
+
+        let chain: BasicRAGChain<MockAsyncChatClient, MockAsyncRetriever> =
+            BasicRAGChain::builder()
+                .neighbor_expansion(NeighborExpansion::new(NonZeroU32::new(1).unwrap()))
+                .chat_client(chat_client)
+                .retriever(retriever)
+                .build();
+        let response = chain
+            .invoke_chain_with_sources(
+                PromptMessage::HumanMessage("question".into()),
+                NonZeroU32::new(1).unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            response.sources,
+            vec![
+                ScoredChunk::new(section(0), 0.8),
+                ScoredChunk::new(section(1), 0.8)
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_chain_does_not_fetch_neighbors_of_chunks_without_a_position() {
+        use crate::chains::NeighborExpansion;
+
+        let mut chat_client = MockAsyncChatClient::new();
+        let mut retriever = MockAsyncRetriever::new();
+        retriever
+            .expect_retrieve()
+            .returning(|_, _| Ok(vec![Chunk::new("data point 1")]));
+        retriever.expect_fetch_by_metadata().never();
+        chat_client
+            .expect_invoke()
+            .returning(|_| Ok(PromptMessage::AIMessage("mocked response".into())));
+
+        let chain: BasicRAGChain<MockAsyncChatClient, MockAsyncRetriever> =
+            BasicRAGChain::builder()
+                .neighbor_expansion(NeighborExpansion::new(NonZeroU32::new(2).unwrap()))
+                .chat_client(chat_client)
+                .retriever(retriever)
+                .build();
+        let result = chain
+            .invoke_chain(
+                PromptMessage::HumanMessage("question".into()),
+                NonZeroU32::new(1).unwrap(),
+            )
+            .await;
+        assert!(result.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_chain_with_sources_returns_chunks_fitted_to_budget() {
+        let mut chat_client = MockAsyncChatClient::new();
+        let mut retriever = MockAsyncRetriever::new();
+        retriever.expect_retrieve_with_scores().returning(|_, _| {
+            Ok(vec![
+                ScoredChunk::new(Chunk::new("one two"), 0.9),
+                ScoredChunk::new(Chunk::new("three four"), 0.8),
+            ])
+        });
+        chat_client
+            .expect_count_tokens()
+            .returning(|text| text.split_whitespace().count());
+        chat_client
+            .expect_invoke()
+            .returning(|_| Ok(PromptMessage::AIMessage("mocked response".into())));
+
+        let chain: BasicRAGChain<MockAsyncChatClient, MockAsyncRetriever> =
+            BasicRAGChain::builder()
+                .chat_client(chat_client)
+                .retriever(retriever)
+                .build();
+
+        // 13 for the question with the template, the message overhead and the reply priming
+        // leaves room for only the first chunk
+        let response = chain
+            .invoke_chain_with_sources(
+                PromptMessage::HumanMessage("what".into()),
+                ContextBudget::new(15),
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            response.sources,
+            vec![ScoredChunk::new(Chunk::new("one two"), 0.9)]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_chain_packs_top_k_into_max_context_tokens() {
+        let mut chat_client = MockAsyncChatClient::new();
+        let mut retriever = MockAsyncRetriever::new();
+        // Every chunk is 40 words long, far more than the budget has room for
+        retriever
+            .expect_retrieve_with_scores()
+            .with(eq("what"), eq(NonZeroU32::new(4).unwrap()))
+            .returning(|_, _| {
+                Ok((0..4)
+                    .map(|i| ScoredChunk::new(Chunk::new(format!("{i} ").repeat(40)), 0.9))
+                    .collect())
+            });
+        chat_client
+            .expect_count_tokens()
+            .returning(|text| text.split_whitespace().count());
+        chat_client
+            .expect_invoke()
+            .withf(|prompts| prompts[0].content().split_whitespace().count() == 86)
+            .returning(|_| Ok(PromptMessage::AIMessage("mocked response".into())));
+
+        let chain: BasicRAGChain<MockAsyncChatClient, MockAsyncRetriever> =
+            BasicRAGChain::builder()
+                .chat_client(chat_client)
+                .retriever(retriever)
+                .max_context_tokens(100)
+                .build();
+
+        // 13 for the question with the template and overhead leaves room for two of the chunks
+        let response = chain
+            .invoke_chain_with_sources(
+                PromptMessage::HumanMessage("what".into()),
+                NonZeroU32::new(4).unwrap(),
+            )
+            .await
+            .unwrap();
+        let included: Vec<String> = response
+            .sources
+            .iter()
+            .map(|source| source.chunk.content().to_string())
+            .collect();
+        assert_eq!(included, vec!["0 ".repeat(40), "1 ".repeat(40)]);
+        assert_eq!(response.chunks_dropped, 2);
+    }
+
+    #[tokio::test]
+    async fn test_chain_counts_context_with_the_tokenizer() {
+        // Counts every character as a token
+        struct CharTokenizer;
+
+        impl TokenizerWrapper for CharTokenizer {
+            fn tokenize(&self, text: &str) -> Option<Vec<String>> {
+                Some(text.chars().map(String::from).collect())
+            }
+        }
+
+        let mut chat_client = MockAsyncChatClient::new();
+        let mut retriever = MockAsyncRetriever::new();
+        retriever
+            .expect_retrieve()
+            .returning(|_, _| Ok(vec![Chunk::new("a".repeat(30)), Chunk::new("b".repeat(30))]));
+        chat_client.expect_count_tokens().never();
+        chat_client
+            .expect_invoke()
+            .returning(|_| Ok(PromptMessage::AIMessage("mocked response".into())));
+
+        let chain: BasicRAGChain<MockAsyncChatClient, MockAsyncRetriever> =
+            BasicRAGChain::builder()
+                .chat_client(chat_client)
+                .retriever(retriever)
+                .max_context_tokens(100)
+                .tokenizer(Box::new(CharTokenizer))
+                .build();
+
+        // The question with the template is 42 characters and each chunk 31 with its newline
+        let response = chain
+            .invoke_chain_with_context(
+                PromptMessage::HumanMessage("what".into()),
+                NonZeroU32::new(2).unwrap(),
+                &InvocationContext::new(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.chunks_used, 1);
+        assert_eq!(response.chunks_dropped, 1);
+    }
+
+    #[tokio::test]
+    async fn test_chain_with_filter_passes_filter_to_retriever() {
+        use crate::retrievers::{MetadataFilter, MockFilteredRetriever};
+
+        const USER_MESSAGE: &str = "what is the leave policy";
+        let filter = MetadataFilter::key("source").eq("handbook.pdf");
+        let mut chat_client = MockAsyncChatClient::new();
+        let mut retriever = MockFilteredRetriever::new();
+
+        retriever.expect_retrieve().never();
+        retriever
+            .expect_retrieve_with_filter()
+            .with(
+                eq(USER_MESSAGE),
+                eq(NonZeroU32::new(2).unwrap()),
+                eq(filter.clone()),
+            )
+            .returning(|_, _, _| Ok(vec![Chunk::new("25 days")]));
+        chat_client
+            .expect_invoke()
+            .withf(|prompts| prompts[0].content().contains("25 days"))
+            .returning(|_| Ok(PromptMessage::AIMessage("mocked response".into())));
+
+        let chain: BasicRAGChain<MockAsyncChatClient, MockFilteredRetriever> =
+            BasicRAGChain::builder()
+                .chat_client(chat_client)
+                .retriever(retriever)
+                .build();
+        let result = chain
+            .invoke_chain_with_filter(
+                PromptMessage::HumanMessage(USER_MESSAGE.into()),
+                NonZeroU32::new(2).unwrap(),
+                &filter,
+            )
+            .await
+            .unwrap();
+        assert_eq!(PromptMessage::AIMessage("mocked response".into()), result);
+    }
+
+    #[tokio::test]
+    async fn test_chain_with_context_carries_request_id() {
+        const USER_MESSAGE: &str = "please tell me about my lecture on operating systems";
+        let mut chat_client = MockAsyncChatClient::new();
+        let mut retriever = MockAsyncRetriever::new();
+
+        retriever
+            .expect_retrieve()
+            .with(eq(USER_MESSAGE), eq(NonZeroU32::new(2).unwrap()))
+            .returning(|_, _| Ok(vec![Chunk::new("data point 1")]));
+        chat_client
+            .expect_invoke()
+            .returning(|_| Ok(PromptMessage::AIMessage("mocked response".into())));
+
+        let chain: BasicRAGChain<MockAsyncChatClient, MockAsyncRetriever> =
+            BasicRAGChain::builder()
+                .chat_client(chat_client)
+                .retriever(retriever)
+                .build();
+
+        let context = InvocationContext::new();
+        let user_message = PromptMessage::HumanMessage(USER_MESSAGE.into());
+        let result = chain
+            .invoke_chain_with_context(user_message, NonZeroU32::new(2).unwrap(), &context)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            PromptMessage::AIMessage("mocked response".into()),
+            result.message
+        );
+        assert_eq!(context.request_id(), result.request_id);
+        assert_eq!(None, result.provider_request_id);
+        assert_eq!(None, result.usage);
+    }
+
+    #[tokio::test]
+    async fn test_chain_with_context_propagates_usage() {
+        let mut retriever = MockAsyncRetriever::new();
+        retriever
+            .expect_retrieve()
+            .returning(|_, _| Ok(vec![Chunk::new("data point 1")]));
+        let chain: BasicRAGChain<MeteredChatClient, MockAsyncRetriever> = BasicRAGChain::builder()
+            .chat_client(MeteredChatClient)
+            .retriever(retriever)
+            .build();
+
+        let user_message = PromptMessage::HumanMessage("question".into());
+        let result = chain
+            .invoke_chain_with_context(
+                user_message,
+                NonZeroU32::new(2).unwrap(),
+                &InvocationContext::new(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(Some(TokenUsage::new(30, 7)), result.usage);
+        assert_eq!(Some("req_1".to_string()), result.provider_request_id);
+        assert!(result.is_truncated());
+    }
+
+    #[tokio::test]
+    async fn test_chain_rejects_truncated_answers_when_asked() {
+        let mut retriever = MockAsyncRetriever::new();
+        retriever
+            .expect_retrieve()
+            .returning(|_, _| Ok(vec![Chunk::new("data point 1")]));
+        let chain: BasicRAGChain<MeteredChatClient, MockAsyncRetriever> = BasicRAGChain::builder()
+            .chat_client(MeteredChatClient)
+            .retriever(retriever)
+            .reject_truncated(true)
+            .build();
+
+        let user_message = PromptMessage::HumanMessage("question".into());
+        let top_k = NonZeroU32::new(2).unwrap();
+        let truncated = PromptMessage::AIMessage("response".into());
+        let result = chain.invoke_chain(user_message.clone(), top_k).await;
+        assert!(matches!(result, Err(RagChainError::Truncated(message)) if message == truncated));
+        let result = chain
+            .invoke_chain_with_context(user_message, top_k, &InvocationContext::new())
+            .await;
+        assert!(matches!(result, Err(RagChainError::Truncated(message)) if message == truncated));
+    }
+
+    #[tokio::test]
+    async fn test_chain_with_context_budget_reports_chunks_used() {
+        const USER_MESSAGE: &str = "what happened";
+        let mut chat_client = MockAsyncChatClient::new();
+        let mut retriever = MockAsyncRetriever::new();
+
+        retriever
+            .expect_retrieve()
+            .with(eq(USER_MESSAGE), eq(NonZeroU32::new(10).unwrap()))
+            .returning(|_, _| {
+                Ok(vec![
+                    Chunk::new("one two three"),
+                    Chunk::new("four five"),
+                    Chunk::new("six"),
+                ])
+            });
+        // One token per word, the question with the template is 7 and 14 with the message
+        // overhead and reply priming
+        chat_client
+            .expect_count_tokens()
+            .returning(|text| text.split_whitespace().count());
+        let expected_prompt = format!(
+            "{}\nHere is some supporting information:\none two three\nfour five\n",
+            USER_MESSAGE
+        );
+        chat_client
+            .expect_invoke()
+            .withf(move |prompts| prompts.len() == 1 && prompts[0].content() == expected_prompt)
+            .returning(|_| Ok(PromptMessage::AIMessage("mocked response".into())));
+
+        let chain: BasicRAGChain<MockAsyncChatClient, MockAsyncRetriever> =
+            BasicRAGChain::builder()
+                .chat_client(chat_client)
+                .retriever(retriever)
+                .build();
+
+        let budget = ContextBudget::new(19).with_fetch_k(NonZeroU32::new(10).unwrap());
+        let user_message = PromptMessage::HumanMessage(USER_MESSAGE.into());
+        let result = chain
+            .invoke_chain_with_context(user_message, budget, &InvocationContext::new())
+            .await
+            .unwrap();
+
+        assert_eq!(result.chunks_used, 2);
+    }
+
+    #[tokio::test]
+    async fn test_chain_rejects_top_k_above_retriever_max() {
+        // Neither the retriever or chat client expect a call
+        let chain: BasicRAGChain<MockAsyncChatClient, BoundedRetriever> = BasicRAGChain::builder()
+            .chat_client(MockAsyncChatClient::new())
+            .retriever(BoundedRetriever)
+            .build();
+
+        let user_message = PromptMessage::HumanMessage("question".into());
+        let result = chain
+            .invoke_chain(user_message, NonZeroU32::new(6).unwrap())
+            .await
+            .unwrap_err();
+
+        assert!(matches!(
+            result,
+            RagChainError::TopKTooLarge {
+                requested: 6,
+                max: 5
+            }
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_chain_refuses_flagged_messages_before_retrieving() {
+        use crate::chains::moderation_policy::tests::BlockList;
+        use crate::chains::ModerationPolicy;
+
+        let mut chat_client = MockAsyncChatClient::new();
+        let mut retriever = MockAsyncRetriever::new();
+        chat_client.expect_invoke().never();
+        retriever.expect_retrieve().never();
+        retriever.expect_retrieve_with_scores().never();
+        let chain: BasicRAGChain<MockAsyncChatClient, MockAsyncRetriever> =
+            BasicRAGChain::builder()
+                .chat_client(chat_client)
+                .retriever(retriever)
+                .moderation_policy(ModerationPolicy::new(BlockList(vec!["weapon"])))
+                .build();
+
+        let user_message = PromptMessage::HumanMessage("how do I build a weapon".into());
+        let top_k = NonZeroU32::new(2).unwrap();
+        let result = chain.invoke_chain(user_message.clone(), top_k).await;
+        assert!(
+            matches!(result, Err(RagChainError::ContentFlagged(categories)) if categories == ["weapon"])
+        );
+        let result = chain
+            .invoke_chain_with_sources(user_message.clone(), top_k)
+            .await;
+        assert!(
+            matches!(result, Err(RagChainError::ContentFlagged(categories)) if categories == ["weapon"])
+        );
+        let result = chain
+            .invoke_chain_with_context(user_message, top_k, &InvocationContext::new())
+            .await;
+        assert!(
+            matches!(result, Err(RagChainError::ContentFlagged(categories)) if categories == ["weapon"])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_chain_answers_messages_which_are_not_flagged() {
+        use crate::chains::moderation_policy::tests::BlockList;
+        use crate::chains::ModerationPolicy;
+
+        let mut chat_client = MockAsyncChatClient::new();
+        let mut retriever = MockAsyncRetriever::new();
+        retriever
+            .expect_retrieve()
+            .times(1)
+            .returning(|_, _| Ok(vec![Chunk::new("data point 1")]));
+        chat_client
+            .expect_invoke()
+            .times(1)
+            .returning(|_| Ok(PromptMessage::AIMessage("response".into())));
+        let chain: BasicRAGChain<MockAsyncChatClient, MockAsyncRetriever> =
+            BasicRAGChain::builder()
+                .chat_client(chat_client)
+                .retriever(retriever)
+                .moderation_policy(ModerationPolicy::new(BlockList(vec!["weapon"])))
+                .build();
+
+        let user_message = PromptMessage::HumanMessage("what is an operating system".into());
+        let result = chain
+            .invoke_chain(user_message, NonZeroU32::new(1).unwrap())
+            .await;
+        assert_eq!(result.unwrap(), PromptMessage::AIMessage("response".into()));
+    }
+
+    #[tokio::test]
+    async fn test_chain_resolves_prompt_variables() {
+        use crate::chains::{PromptVariables, UnresolvedVariableMode};
+        use std::collections::HashMap;
+
+        let mut chat_client = MockAsyncChatClient::new();
+        let mut retriever = MockAsyncRetriever::new();
+        retriever
+            .expect_retrieve()
+            .returning(|_, _| Ok(vec![Chunk::new("data point 1")]));
+        chat_client
+            .expect_invoke()
+            .withf(|prompts| {
+                prompts[0]
+                    == PromptMessage::SystemMessage(
+                        "help Sam on Monday, {{unknown}} stays and {{today}} is escaped".into(),
+                    )
+            })
+            .returning(|_| Ok(PromptMessage::AIMessage("mocked response".into())));
+
+        let variables = PromptVariables::new(HashMap::from([
+            ("username".to_string(), "Sam".to_string()),
+            ("today".to_string(), "Monday".to_string()),
+        ]))
+        .with_unresolved_mode(UnresolvedVariableMode::LeaveAsIs);
+        let chain: BasicRAGChain<MockAsyncChatClient, MockAsyncRetriever> =
+            BasicRAGChain::builder()
+                .system_prompt(PromptMessage::SystemMessage(
+                    "help {{username}} on {{today}}, {{unknown}} stays and \\{{today}} is escaped"
+                        .into(),
+                ))
+                .prompt_variables(variables)
+                .chat_client(chat_client)
+                .retriever(retriever)
+                .build();
+
+        let user_message = PromptMessage::HumanMessage("question".into());
+        chain
+            .invoke_chain(user_message, NonZeroU32::new(2).unwrap())
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_chain_rejects_unresolved_prompt_variables() {
+        use crate::chains::{PromptVariableError, PromptVariables};
+        use std::collections::HashMap;
+
+        // Neither the retriever or chat client expect a call
+        let chain: BasicStreamedRAGChain<MockAsyncStreamedChatClient, MockAsyncRetriever> =
+            BasicStreamedRAGChain::builder()
+                .system_prompt(PromptMessage::SystemMessage("today is {{today}}".into()))
+                .prompt_variables(PromptVariables::from_fn(HashMap::new))
+                .chat_client(MockAsyncStreamedChatClient::new())
+                .retriever(MockAsyncRetriever::new())
+                .build();
+
+        let user_message = PromptMessage::HumanMessage("question".into());
+        let result = chain
+            .invoke_chain(user_message, NonZeroU32::new(2).unwrap())
+            .await
+            .err()
+            .unwrap();
+        assert!(matches!(
+            result,
+            RagChainError::PromptVariableError(PromptVariableError::Unresolved(names))
+                if names == vec!["today".to_string()]
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_streamed_chain_succeeds() {
+        const SYSTEM_MESSAGE: &str = "you are a study buddy";
+        const USER_MESSAGE: &str = "please tell me about my lecture on operating systems";
+        const RAG_CHUNK_1: &str = "data point 1";
+        const RAG_CHUNK_2: &str = "data point 2";
+        let expected_user_message: String = format!(
+            "{}\n{}\n{}\n{}\n",
+            USER_MESSAGE, "Here is some supporting information:", RAG_CHUNK_1, RAG_CHUNK_2
+        );
+
+        let system_prompt = PromptMessage::SystemMessage(SYSTEM_MESSAGE.into());
+        let mut chat_client = MockAsyncStreamedChatClient::new();
+        let mut retriever = MockAsyncRetriever::new();
+        retriever
+            .expect_retrieve()
+            .with(eq(USER_MESSAGE), eq(NonZeroU32::new(2).unwrap()))
+            .returning(|_, _| Ok(vec![Chunk::new(RAG_CHUNK_1), Chunk::new(RAG_CHUNK_2)]));
+
+        chat_client
+            .expect_invoke_stream()
+            .with(eq(vec![
+                system_prompt.clone(),
+                PromptMessage::HumanMessage(expected_user_message.into()),
+            ]))
+            .returning(move |_| {
+                let mut stream = MockChatCompletionStream::new();
+                stream
+                    .expect_next()
+                    .returning(|| Some(Ok(PromptMessage::AIMessage("mocked response".into()))));
                 Ok(stream)
             });
 
@@ -344,4 +2429,431 @@ mod basic_rag_chain_tests {
             PromptMessage::AIMessage("mocked response".into())
         );
     }
+
+    #[tokio::test]
+    async fn test_streamed_chain_with_sources_returns_retrieved_chunks() {
+        const USER_MESSAGE: &str = "what is the leave policy";
+        let retrieved = vec![
+            ScoredChunk::new(Chunk::new("25 days a year"), 0.91),
+            ScoredChunk::new(Chunk::new("ask your manager"), 0.64),
+        ];
+        let mut chat_client = MockAsyncStreamedChatClient::new();
+        let mut retriever = MockAsyncRetriever::new();
+        retriever.expect_retrieve().never();
+        let returned = retrieved.clone();
+        retriever
+            .expect_retrieve_with_scores()
+            .with(eq(USER_MESSAGE), eq(NonZeroU32::new(2).unwrap()))
+            .returning(move |_, _| Ok(returned.clone()));
+        chat_client.expect_invoke_stream().returning(|_| {
+            let mut stream = MockChatCompletionStream::new();
+            stream
+                .expect_next()
+                .returning(|| Some(Ok(PromptMessage::AIMessage("mocked response".into()))));
+            Ok(stream)
+        });
+
+        let chain: BasicStreamedRAGChain<MockAsyncStreamedChatClient, MockAsyncRetriever> =
+            BasicStreamedRAGChain::builder()
+                .chat_client(chat_client)
+                .retriever(retriever)
+                .build();
+
+        let mut response = chain
+            .invoke_chain_with_sources(
+                PromptMessage::HumanMessage(USER_MESSAGE.into()),
+                NonZeroU32::new(2).unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.sources, retrieved);
+        assert_eq!(response.chunks_dropped, 0);
+        assert_eq!(
+            response.stream.next().await.unwrap().unwrap(),
+            PromptMessage::AIMessage("mocked response".into())
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_chain_with_context_records_timings() {
+        let chain: BasicRAGChain<SlowChatClient, SlowRetriever> = BasicRAGChain::builder()
+            .chat_client(SlowChatClient)
+            .retriever(SlowRetriever)
+            .build();
+
+        let user_message = PromptMessage::HumanMessage("question".into());
+        let result = chain
+            .invoke_chain_with_context(
+                user_message,
+                NonZeroU32::new(2).unwrap(),
+                &InvocationContext::new(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(
+            result.timings,
+            Timings {
+                retrieval: Some(RETRIEVAL_DELAY),
+                time_to_first_token: None,
+                generation: Some(GENERATION_DELAY),
+            }
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_streamed_chain_with_context_records_timings() {
+        let chain: BasicStreamedRAGChain<SlowChatClient, SlowRetriever> =
+            BasicStreamedRAGChain::builder()
+                .chat_client(SlowChatClient)
+                .retriever(SlowRetriever)
+                .build();
+
+        let user_message = PromptMessage::HumanMessage("question".into());
+        let mut stream = chain
+            .invoke_chain_with_context(
+                user_message,
+                NonZeroU32::new(2).unwrap(),
+                &InvocationContext::new(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(stream.timings().retrieval, Some(RETRIEVAL_DELAY));
+        assert_eq!(stream.chunks_used(), 1);
+        assert_eq!(stream.chunks_dropped(), 0);
+
+        let mut values: Vec<PromptMessage> = Vec::new();
+        while let Some(value) = stream.next().await {
+            values.push(value.unwrap());
+        }
+
+        // The connecting value does not count as the first token
+        assert_eq!(values.len(), 3);
+        assert_eq!(
+            stream.timings(),
+            Timings {
+                retrieval: Some(RETRIEVAL_DELAY),
+                time_to_first_token: Some(RETRIEVAL_DELAY + GENERATION_DELAY + TOKEN_DELAY),
+                generation: Some(GENERATION_DELAY + TOKEN_DELAY * 2),
+            }
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_chain_with_timeout_times_out_slow_retrieval() {
+        let chain: BasicRAGChain<SlowChatClient, SlowRetriever> = BasicRAGChain::builder()
+            .chat_client(SlowChatClient)
+            .retriever(SlowRetriever)
+            .build();
+
+        let user_message = PromptMessage::HumanMessage("question".into());
+        let timeouts = ChainTimeouts::default().with_retrieval(RETRIEVAL_DELAY / 4);
+        let result = chain
+            .invoke_chain_with_timeout(user_message, NonZeroU32::new(2).unwrap(), timeouts)
+            .await
+            .unwrap_err();
+
+        assert!(matches!(
+            result,
+            RagChainError::Timeout {
+                phase: ChainPhase::Retrieval
+            }
+        ));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_chain_with_timeout_times_out_slow_chat_client() {
+        let chain: BasicRAGChain<SlowChatClient, SlowRetriever> = BasicRAGChain::builder()
+            .chat_client(SlowChatClient)
+            .retriever(SlowRetriever)
+            .build();
+
+        // Each phase is timed separately, so retrieval's time does not count against the chat
+        let user_message = PromptMessage::HumanMessage("question".into());
+        let result = chain
+            .invoke_chain_with_timeout(
+                user_message.clone(),
+                NonZeroU32::new(2).unwrap(),
+                GENERATION_DELAY - Duration::from_millis(1),
+            )
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            result,
+            RagChainError::Timeout {
+                phase: ChainPhase::Chat
+            }
+        ));
+
+        let result = chain
+            .invoke_chain_with_timeout(user_message, NonZeroU32::new(2).unwrap(), GENERATION_DELAY)
+            .await
+            .unwrap();
+        assert_eq!(result, PromptMessage::AIMessage("response".into()));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_chain_timeouts_apply_to_every_invocation() {
+        let chain: BasicRAGChain<SlowChatClient, SlowRetriever> = BasicRAGChain::builder()
+            .chat_client(SlowChatClient)
+            .retriever(SlowRetriever)
+            .timeouts(ChainTimeouts::default().with_retrieval(RETRIEVAL_DELAY / 4))
+            .build();
+
+        let user_message = PromptMessage::HumanMessage("question".into());
+        let result = chain
+            .invoke_chain_with_sources(user_message.clone(), NonZeroU32::new(2).unwrap())
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            result,
+            RagChainError::Timeout {
+                phase: ChainPhase::Retrieval
+            }
+        ));
+
+        let result = chain
+            .invoke_chain_with_context(
+                user_message.clone(),
+                NonZeroU32::new(2).unwrap(),
+                &InvocationContext::new(),
+            )
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            result,
+            RagChainError::Timeout {
+                phase: ChainPhase::Retrieval
+            }
+        ));
+
+        // Timeouts passed with the invocation replace the chain's
+        let result = chain
+            .invoke_chain_with_timeout(
+                user_message,
+                NonZeroU32::new(2).unwrap(),
+                ChainTimeouts::default().with_chat(GENERATION_DELAY / 2),
+            )
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            result,
+            RagChainError::Timeout {
+                phase: ChainPhase::Chat
+            }
+        ));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_streamed_chain_closes_the_client_stream_when_dropped() {
+        let closed = Arc::new(AtomicBool::new(false));
+        let chain: BasicStreamedRAGChain<ClosingChatClient, SlowRetriever> =
+            BasicStreamedRAGChain::builder()
+                .chat_client(ClosingChatClient {
+                    closed: closed.clone(),
+                })
+                .retriever(SlowRetriever)
+                .build();
+
+        let user_message = PromptMessage::HumanMessage("question".into());
+        let mut stream = chain
+            .invoke_chain(user_message, NonZeroU32::new(2).unwrap())
+            .await
+            .unwrap();
+        stream.next().await.unwrap().unwrap();
+        assert!(!closed.load(Ordering::SeqCst));
+
+        drop(stream);
+        assert!(closed.load(Ordering::SeqCst));
+    }
+
+    const RETRIEVAL_DELAY: Duration = Duration::from_millis(40);
+    const GENERATION_DELAY: Duration = Duration::from_millis(300);
+    const TOKEN_DELAY: Duration = Duration::from_millis(25);
+
+    // Retriever and clients which sleep on the paused clock to simulate each stage
+    struct SlowRetriever;
+
+    impl AsyncRetriever for SlowRetriever {
+        type ErrorType = std::io::Error;
+
+        async fn retrieve(
+            &self,
+            _text: &str,
+            _top_k: NonZeroU32,
+        ) -> Result<Chunks, Self::ErrorType> {
+            sleep(RETRIEVAL_DELAY).await;
+            Ok(vec![Chunk::new("data point 1")])
+        }
+
+        async fn fetch_by_metadata(
+            &self,
+            _filter: &MetadataFilter,
+        ) -> Result<Chunks, Self::ErrorType> {
+            Ok(Vec::new())
+        }
+    }
+
+    struct SlowChatClient;
+
+    impl TokenCounter for SlowChatClient {}
+
+    impl AsyncChatClient for SlowChatClient {
+        type ErrorType = std::io::Error;
+
+        async fn invoke(
+            &self,
+            _prompt_messages: Vec<PromptMessage>,
+        ) -> Result<PromptMessage, Self::ErrorType> {
+            sleep(GENERATION_DELAY).await;
+            Ok(PromptMessage::AIMessage("response".into()))
+        }
+    }
+
+    impl AsyncStreamedChatClient for SlowChatClient {
+        type ErrorType = std::io::Error;
+        type Item = SlowStream;
+
+        async fn invoke_stream(
+            &self,
+            _prompt_messages: Vec<PromptMessage>,
+        ) -> Result<Self::Item, Self::ErrorType> {
+            Ok(SlowStream { remaining: 3 })
+        }
+    }
+
+    // Streams like SlowChatClient and records when its stream is dropped, as the provider
+    // streams close their connection on drop
+    struct ClosingChatClient {
+        closed: Arc<AtomicBool>,
+    }
+
+    impl TokenCounter for ClosingChatClient {}
+
+    impl AsyncStreamedChatClient for ClosingChatClient {
+        type ErrorType = std::io::Error;
+        type Item = ClosingStream;
+
+        async fn invoke_stream(
+            &self,
+            _prompt_messages: Vec<PromptMessage>,
+        ) -> Result<Self::Item, Self::ErrorType> {
+            Ok(ClosingStream {
+                inner: SlowStream { remaining: 3 },
+                closed: self.closed.clone(),
+            })
+        }
+    }
+
+    struct ClosingStream {
+        inner: SlowStream,
+        closed: Arc<AtomicBool>,
+    }
+
+    impl Drop for ClosingStream {
+        fn drop(&mut self) {
+            self.closed.store(true, Ordering::SeqCst);
+        }
+    }
+
+    impl ChatCompletionStream for ClosingStream {
+        type ErrorType = std::io::Error;
+        type Item = PromptMessage;
+
+        async fn next(&mut self) -> Option<Result<Self::Item, Self::ErrorType>> {
+            self.inner.next().await
+        }
+
+        fn is_token(item: &Self::Item) -> bool {
+            SlowStream::is_token(item)
+        }
+    }
+
+    // Chat client which reports token usage like the provider clients do,
+    // its answers always hit the token limit
+    struct MeteredChatClient;
+
+    impl TokenCounter for MeteredChatClient {}
+
+    impl AsyncChatClient for MeteredChatClient {
+        type ErrorType = std::io::Error;
+
+        async fn invoke(
+            &self,
+            _prompt_messages: Vec<PromptMessage>,
+        ) -> Result<PromptMessage, Self::ErrorType> {
+            Ok(PromptMessage::AIMessage("response".into()))
+        }
+
+        async fn invoke_with_context(
+            &self,
+            prompt_messages: Vec<PromptMessage>,
+            context: &InvocationContext,
+        ) -> Result<DetailedChatResponse, Self::ErrorType> {
+            Ok(DetailedChatResponse {
+                message: self.invoke(prompt_messages).await?,
+                request_id: context.request_id(),
+                provider_request_id: Some("req_1".into()),
+                system_fingerprint: None,
+                usage: Some(TokenUsage::new(30, 7)),
+                finish_reason: Some(FinishReason::Length),
+            })
+        }
+    }
+
+    // Yields a Connecting style value, then two tokens each after a delay
+    struct SlowStream {
+        remaining: usize,
+    }
+
+    impl ChatCompletionStream for SlowStream {
+        type ErrorType = std::io::Error;
+        type Item = PromptMessage;
+
+        async fn next(&mut self) -> Option<Result<Self::Item, Self::ErrorType>> {
+            match self.remaining {
+                0 => None,
+                3 => {
+                    self.remaining -= 1;
+                    sleep(GENERATION_DELAY).await;
+                    Some(Ok(PromptMessage::SystemMessage("connecting".into())))
+                }
+                _ => {
+                    self.remaining -= 1;
+                    sleep(TOKEN_DELAY).await;
+                    Some(Ok(PromptMessage::AIMessage("token".into())))
+                }
+            }
+        }
+
+        fn is_token(item: &Self::Item) -> bool {
+            matches!(item, PromptMessage::AIMessage(_))
+        }
+    }
+
+    // Retriever which only allows a top_k of 5 and fails the test if searched
+    struct BoundedRetriever;
+
+    impl AsyncRetriever for BoundedRetriever {
+        type ErrorType = std::io::Error;
+
+        async fn retrieve(
+            &self,
+            _text: &str,
+            _top_k: NonZeroU32,
+        ) -> Result<Chunks, Self::ErrorType> {
+            panic!("top_k should be rejected before retrieving");
+        }
+
+        async fn fetch_by_metadata(
+            &self,
+            _filter: &MetadataFilter,
+        ) -> Result<Chunks, Self::ErrorType> {
+            Ok(Vec::new())
+        }
+
+        fn max_top_k(&self) -> Option<NonZeroU32> {
+            NonZeroU32::new(5)
+        }
+    }
 }
@@ -1,9 +1,22 @@
 use crate::{
-    chains::ChainError,
-    clients::{AsyncChatClient, PromptMessage},
+    chains::{
+        history_policy::{summary_prompt, SUMMARY_PREFIX},
+        timeouts::within,
+        utils::resolve_system_prompt,
+        ChainError, ChainPhase, ChainResponse, ChainWarning, HistoryPolicy, ModerationPolicy,
+        PromptVariables, Timings,
+    },
+    clients::{
+        AsyncChatClient, AsyncStreamedChatClient, ChatCompletionStream, DetailedChatResponse,
+        FinishReason, PromptMessage, StreamedText,
+    },
+    common::{InvocationContext, TokenUsage, TokenizerWrapper},
 };
-use std::cell::RefCell;
+use std::fmt::{Debug, Formatter};
 use std::iter::once;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::Duration;
+use tokio::time::Instant;
 
 /// # [`ChatHistoryChain`]
 ///
@@ -11,6 +24,10 @@ use std::iter::once;
 /// that is then resent with each subsequent request to the chat client. So previous messages can be
 //  referenced in prompts and the LLM will be aware of it.
 ///
+/// The history is protected by an async aware lock so the chain can be shared across
+/// tasks, how concurrent invocations behave is set by the [`ConcurrencyMode`]. How much
+/// of the conversation is resent is set by the [`HistoryPolicy`].
+///
 /// * `T` - The type of the chat client to be used
 ///
 /// # Examples
@@ -34,13 +51,37 @@ use std::iter::once;
 /// }
 ///
 /// ```
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Clone)]
 pub struct ChatHistoryChain<T>
 where
     T: AsyncChatClient,
 {
     chat_history_buffer: ChatHistoryBuffer,
     chat_client: T,
+    concurrency_mode: ConcurrencyMode,
+    prompt_variables: Option<PromptVariables>,
+    history_policy: HistoryPolicy,
+    tokenizer: Option<Arc<dyn TokenizerWrapper>>,
+    moderation_policy: Option<ModerationPolicy>,
+}
+
+/// # [`ConcurrencyMode`]
+///
+/// Defines what happens when [`ChatHistoryChain::invoke_chain`] is called concurrently
+/// on the same chain (e.g. when the chain is shared behind an [`std::sync::Arc`]).
+///
+/// * [`ConcurrencyMode::Serialized`] - the history lock is held across the call to the chat client,
+///   so invocations run one after the other and each one sees the full history of the previous.
+/// * [`ConcurrencyMode::Interleaved`] - the history lock is only held while reading the history and
+///   appending the result. Invocations can run at the same time but will not see each others messages.
+///   Each user message and its response are always appended together as a pair.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum ConcurrencyMode {
+    #[default]
+    Serialized,
+    Interleaved,
 }
 
 impl<T> ChatHistoryChain<T>
@@ -49,29 +90,146 @@ where
 {
     /// # [`ChatHistoryChain::new`]
     ///
-    /// This constructor to create a new ChatHistoryChain.
+    /// This constructor to create a new ChatHistoryChain. Concurrent invocations
+    /// will use [`ConcurrencyMode::Serialized`].
     ///
     /// # Arguments
     /// * `chat_client`: `T` - The chat client to be used
     /// * `system_prompt`: [`PromptMessage`] - The system prompt, please use [`PromptMessage::SystemMessage`]
     pub fn new(chat_client: T, system_prompt: PromptMessage) -> Self {
-        let chat_history_buffer = ChatHistoryBuffer::new(system_prompt);
+        Self::new_with_concurrency_mode(chat_client, system_prompt, ConcurrencyMode::default())
+    }
+
+    /// # [`ChatHistoryChain::new_with_concurrency_mode`]
+    ///
+    /// This constructor to create a new ChatHistoryChain which defines how
+    /// concurrent invocations of the chain interact with the chat history.
+    ///
+    /// # Arguments
+    /// * `chat_client`: `T` - The chat client to be used
+    /// * `system_prompt`: [`PromptMessage`] - The system prompt, please use [`PromptMessage::SystemMessage`]
+    /// * `concurrency_mode`: [`ConcurrencyMode`] - whether concurrent invocations serialize or interleave
+    pub fn new_with_concurrency_mode(
+        chat_client: T,
+        system_prompt: PromptMessage,
+        concurrency_mode: ConcurrencyMode,
+    ) -> Self {
+        let chat_history_buffer = ChatHistoryBuffer::new(system_prompt, Vec::new());
         ChatHistoryChain {
             chat_history_buffer,
             chat_client,
+            concurrency_mode,
+            prompt_variables: None,
+            history_policy: HistoryPolicy::default(),
+            tokenizer: None,
+            moderation_policy: None,
         }
     }
 
+    /// # [`ChatHistoryChain::new_with_history`]
+    ///
+    /// This constructor to create a new ChatHistoryChain which carries on a previous
+    /// conversation, e.g. one saved from [`ChatHistoryChain::history_snapshot`]. The
+    /// snapshot starts with the old system prompt, this is replaced by the one given.
+    ///
+    /// # Arguments
+    /// * `chat_client`: `T` - The chat client to be used
+    /// * `system_prompt`: [`PromptMessage`] - The system prompt, please use [`PromptMessage::SystemMessage`]
+    /// * `messages`: [`Vec<PromptMessage>`] - the conversation so far, alternating between
+    ///   human and AI messages
+    pub fn new_with_history(
+        chat_client: T,
+        system_prompt: PromptMessage,
+        messages: Vec<PromptMessage>,
+    ) -> Self {
+        let conversation: Vec<PromptMessage> = messages
+            .into_iter()
+            .skip_while(|message| matches!(message, PromptMessage::SystemMessage(_)))
+            .collect();
+        let mut chain: ChatHistoryChain<T> = Self::new(chat_client, system_prompt.clone());
+        chain.chat_history_buffer = ChatHistoryBuffer::new(system_prompt, conversation);
+        chain
+    }
+
+    /// # [`ChatHistoryChain::new_with_policy`]
+    ///
+    /// This constructor to create a new ChatHistoryChain which limits how much of
+    /// the conversation is resent with each invocation, see [`HistoryPolicy`].
+    ///
+    /// # Arguments
+    /// * `chat_client`: `T` - The chat client to be used
+    /// * `system_prompt`: [`PromptMessage`] - The system prompt, please use [`PromptMessage::SystemMessage`]
+    /// * `history_policy`: [`HistoryPolicy`] - how the history is trimmed to fit the context window
+    pub fn new_with_policy(
+        chat_client: T,
+        system_prompt: PromptMessage,
+        history_policy: HistoryPolicy,
+    ) -> Self {
+        Self::new(chat_client, system_prompt).with_history_policy(history_policy)
+    }
+
+    /// # [`ChatHistoryChain::with_history_policy`]
+    ///
+    /// Sets how much of the conversation is resent with each invocation,
+    /// by default the whole conversation is. See [`HistoryPolicy`].
+    ///
+    /// # Arguments
+    /// * `history_policy`: [`HistoryPolicy`] - how the history is trimmed to fit the context window
+    pub fn with_history_policy(mut self, history_policy: HistoryPolicy) -> Self {
+        self.history_policy = history_policy;
+        self
+    }
+
+    /// # [`ChatHistoryChain::with_tokenizer`]
+    ///
+    /// Counts the tokens for a [`HistoryPolicy::TokenBudget`] with the given tokenizer,
+    /// by default they are counted with [`AsyncChatClient::count_tokens`].
+    ///
+    /// # Arguments
+    /// * `tokenizer`: [`Box<dyn TokenizerWrapper>`] - the tokenizer of the chat client's model
+    pub fn with_tokenizer(mut self, tokenizer: Box<dyn TokenizerWrapper>) -> Self {
+        self.tokenizer = Some(Arc::from(tokenizer));
+        self
+    }
+
+    /// # [`ChatHistoryChain::with_prompt_variables`]
+    ///
+    /// Resolves the `{{name}}` placeholders in the system prompt each time the chain is invoked.
+    /// The history keeps the system prompt with its placeholders so every invocation sees fresh values.
+    ///
+    /// # Arguments
+    /// * `prompt_variables`: [`PromptVariables`] - the values of the placeholders
+    pub fn with_prompt_variables(mut self, prompt_variables: PromptVariables) -> Self {
+        self.prompt_variables = Some(prompt_variables);
+        self
+    }
+
+    /// # [`ChatHistoryChain::with_moderation_policy`]
+    ///
+    /// Moderates each user message before it is sent, a flagged message is refused with
+    /// [`ChainError::ContentFlagged`] and is not added to the history.
+    ///
+    /// # Arguments
+    /// * `moderation_policy`: [`ModerationPolicy`] - checks each user message
+    pub fn with_moderation_policy(mut self, moderation_policy: ModerationPolicy) -> Self {
+        self.moderation_policy = Some(moderation_policy);
+        self
+    }
+
     /// # [`ChatHistoryChain::invoke_chain`]
     ///
     /// function to execute the ChatHistoryChain given a new user prompt.
     /// Each time this method is invoked, the user message is added to the chat history.
+    /// See [`ConcurrencyMode`] for how concurrent invocations are handled.
     ///
     /// # Arguments
     /// * `user_message`: [`PromptMessage`] - the user prompt that will be sent to the LLM along with the chat history.
     ///
     /// # Errors
     /// * [`ChainError::ChatClientError`] if the chat client invocation fails.
+    /// * [`ChainError::PromptVariableError`] if a variable in the system prompt could not be resolved.
+    /// * [`ChainError::ContentFlagged`] if the moderation policy flagged the user message.
+    /// * [`ChainError::ModerationError`] if the moderation policy could not check the user message.
     ///
     /// # Returns
     /// * [`PromptMessage::AIMessage`] - the response from the chat client.
@@ -79,60 +237,796 @@ where
         &self,
         user_message: PromptMessage,
     ) -> Result<PromptMessage, ChainError<T::ErrorType>> {
-        let history: Vec<PromptMessage> = self.chat_history_buffer.get_messages();
-        let history_with_prompt = history
-            .into_iter()
+        let (response, _details) = self.run_exchange(user_message, None, None).await?;
+        Ok(response)
+    }
+
+    /// # [`ChatHistoryChain::invoke_chain_with_timeout`]
+    ///
+    /// The same as [`ChatHistoryChain::invoke_chain`] but the chat client has to respond within
+    /// the timeout, which covers any summary of the history and any wait for the history in
+    /// [`ConcurrencyMode::Serialized`]. If it runs out the request is dropped, cancelling it,
+    /// and the exchange is not added to the history. Moderating the user message is not timed.
+    ///
+    /// # Arguments
+    /// * `user_message`: [`PromptMessage`] - the user prompt that will be sent to the LLM along with the chat history.
+    /// * `timeout`: [`Duration`] - how long the chat phase may take.
+    ///
+    /// # Errors
+    /// * [`ChainError::Timeout`] if the chat client did not respond in time.
+    /// * [`ChainError`] - any of the errors from [`ChatHistoryChain::invoke_chain`].
+    ///
+    /// # Returns
+    /// * [`PromptMessage::AIMessage`] - the response from the chat client.
+    pub async fn invoke_chain_with_timeout(
+        &self,
+        user_message: PromptMessage,
+        timeout: Duration,
+    ) -> Result<PromptMessage, ChainError<T::ErrorType>> {
+        let (response, _details) = self.run_exchange(user_message, None, Some(timeout)).await?;
+        Ok(response)
+    }
+
+    /// # [`ChatHistoryChain::invoke_chain_with_context`]
+    ///
+    /// The same as [`ChatHistoryChain::invoke_chain`] but the context is passed down
+    /// to the chat client so the invocation can be correlated by the request id.
+    ///
+    /// # Arguments
+    /// * `user_message`: [`PromptMessage`] - the user prompt that will be sent to the LLM along with the chat history.
+    /// * `context`: &[`InvocationContext`] - the context of the invocation.
+    ///
+    /// # Errors
+    /// * [`ChainError::ChatClientError`] if the chat client invocation fails.
+    /// * [`ChainError::PromptVariableError`] if a variable in the system prompt could not be resolved.
+    /// * [`ChainError::ContentFlagged`] if the moderation policy flagged the user message.
+    /// * [`ChainError::ModerationError`] if the moderation policy could not check the user message.
+    ///
+    /// # Returns
+    /// * [`ChainResponse`] - the response from the chat client along with the request ids
+    ///   and the generation time, which includes any wait for the history. If the history
+    ///   could not be summarized for a [`HistoryPolicy::Summarize`] this carries a
+    ///   [`ChainWarning::SummaryFailed`].
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            name = "chat_history_chain.invoke_chain_with_context",
+            skip_all,
+            fields(request_id = %context.request_id())
+        )
+    )]
+    pub async fn invoke_chain_with_context(
+        &self,
+        user_message: PromptMessage,
+        context: &InvocationContext,
+    ) -> Result<ChainResponse, ChainError<T::ErrorType>> {
+        let generation_started = Instant::now();
+        let (message, details) = self.run_exchange(user_message, Some(context), None).await?;
+        Ok(ChainResponse {
+            message,
+            request_id: context.request_id(),
+            provider_request_id: details.provider_request_id,
+            timings: Timings {
+                generation: Some(generation_started.elapsed()),
+                ..Timings::default()
+            },
+            chunks_used: 0,
+            chunks_dropped: 0,
+            usage: details.usage,
+            finish_reason: details.finish_reason,
+            warnings: details.warnings,
+        })
+    }
+
+    /// # [`ChatHistoryChain::history_snapshot`]
+    ///
+    /// Returns a clone of the current chat history including the system prompt.
+    /// In [`ConcurrencyMode::Serialized`] this waits for any in flight invocation to finish.
+    /// With the `serde` feature the history can be saved and later restored with
+    /// [`ChatHistoryChain::new_with_history`].
+    ///
+    /// # Returns
+    /// * [`Vec<PromptMessage>`] - the messages currently in the chat history.
+    pub async fn history_snapshot(&self) -> Vec<PromptMessage> {
+        self.chat_history_buffer.get_messages().await
+    }
+
+    /// # [`ChatHistoryChain::reset`]
+    ///
+    /// Clears the chat history leaving only the system prompt, this starts
+    /// a new conversation with the same chat client.
+    pub async fn reset(&self) {
+        self.chat_history_buffer.reset().await;
+    }
+
+    /// Counts the tokens in the text with the tokenizer if one was set,
+    /// otherwise with the chat client.
+    fn count_tokens(&self, text: &str) -> usize {
+        match &self.tokenizer {
+            Some(tokenizer) => tokenizer.count_tokens(text),
+            None => self.chat_client.count_tokens(text),
+        }
+    }
+
+    /// Returns where the part of the history which fits the [`HistoryPolicy`] starts,
+    /// see [`HistoryPolicy::first_kept_in_history`].
+    fn first_kept(
+        &self,
+        history: &[PromptMessage],
+        system_prompt: &PromptMessage,
+        user_message: Option<&PromptMessage>,
+    ) -> usize {
+        self.history_policy
+            .first_kept_in_history(history, system_prompt, user_message, |text| {
+                self.count_tokens(text)
+            })
+    }
+
+    /// Appends the exchange to the history and drops the messages which
+    /// no longer fit the [`HistoryPolicy`].
+    fn append_exchange(
+        &self,
+        messages: &mut Vec<PromptMessage>,
+        system_prompt: &PromptMessage,
+        user_message: PromptMessage,
+        response: PromptMessage,
+    ) {
+        messages.push(user_message);
+        messages.push(response);
+        let first_kept: usize = self.first_kept(messages, system_prompt, None);
+        messages.drain(1..first_kept);
+    }
+
+    /// Sends the user message along with the history and appends the exchange,
+    /// respecting the [`ConcurrencyMode`]. Returns the response along with the
+    /// details the provider returned. If the chat timeout runs out the exchange is
+    /// dropped without being added to the history.
+    async fn run_exchange(
+        &self,
+        user_message: PromptMessage,
+        context: Option<&InvocationContext>,
+        chat_timeout: Option<Duration>,
+    ) -> Result<(PromptMessage, ExchangeDetails), ChainError<T::ErrorType>> {
+        // A refused message is never sent or added to the history
+        if let Some(moderation_policy) = &self.moderation_policy {
+            moderation_policy.check(&user_message).await?;
+        }
+        // The history always starts with the system prompt, which is swapped for the resolved one
+        let system_prompt: Option<PromptMessage> = match &self.prompt_variables {
+            None => None,
+            Some(variables) => resolve_system_prompt(
+                Some(&self.chat_history_buffer.system_prompt),
+                Some(variables),
+            )
+            .map_err(ChainError::PromptVariableError)?,
+        };
+        let system_prompt: &PromptMessage = system_prompt
+            .as_ref()
+            .unwrap_or(&self.chat_history_buffer.system_prompt);
+        within(
+            chat_timeout,
+            ChainPhase::Chat,
+            self.exchange(user_message, system_prompt, context),
+        )
+        .await
+        .map_err(|phase| ChainError::Timeout { phase })?
+    }
+
+    /// Runs the exchange for [`ChatHistoryChain::run_exchange`] once the user message
+    /// has been moderated and the system prompt resolved.
+    async fn exchange(
+        &self,
+        user_message: PromptMessage,
+        system_prompt: &PromptMessage,
+        context: Option<&InvocationContext>,
+    ) -> Result<(PromptMessage, ExchangeDetails), ChainError<T::ErrorType>> {
+        match self.concurrency_mode {
+            ConcurrencyMode::Serialized => {
+                // Holding the turn across the call means no other invocation can
+                // read the history until this exchange has been appended.
+                let _turn = self.chat_history_buffer.take_turn().await;
+                let mut messages: Vec<PromptMessage> = self.chat_history_buffer.messages().clone();
+                let compaction: Option<Compaction> = self
+                    .compact(&messages, system_prompt, &user_message, context)
+                    .await;
+                if let Some(compaction) = &compaction {
+                    // The summary is kept even if this exchange then fails
+                    compaction.apply(&mut messages);
+                    compaction.apply(&mut self.chat_history_buffer.messages());
+                }
+                let first_kept: usize =
+                    self.first_kept(&messages, system_prompt, Some(&user_message));
+                let (response, mut details) = self
+                    .invoke_with_history(
+                        &messages[first_kept..],
+                        system_prompt,
+                        &user_message,
+                        context,
+                    )
+                    .await?;
+                self.append_exchange(&mut messages, system_prompt, user_message, response.clone());
+                *self.chat_history_buffer.messages() = messages;
+                details.warnings.extend(compaction.and_then(|c| c.warning));
+                Ok((response, details))
+            }
+            ConcurrencyMode::Interleaved => {
+                let mut history: Vec<PromptMessage> = self.chat_history_buffer.get_messages().await;
+                let compaction: Option<Compaction> = self
+                    .compact(&history, system_prompt, &user_message, context)
+                    .await;
+                if let Some(compaction) = &compaction {
+                    // The summary is kept even if this exchange then fails
+                    compaction.apply(&mut history);
+                    self.chat_history_buffer
+                        .update(|messages| compaction.apply(messages))
+                        .await;
+                }
+                let first_kept: usize =
+                    self.first_kept(&history, system_prompt, Some(&user_message));
+                let (response, mut details) = self
+                    .invoke_with_history(
+                        &history[first_kept..],
+                        system_prompt,
+                        &user_message,
+                        context,
+                    )
+                    .await?;
+                // The user message and its response are appended under a single lock
+                // so the pair is never split up.
+                self.chat_history_buffer
+                    .update(|messages| {
+                        self.append_exchange(
+                            messages,
+                            system_prompt,
+                            user_message,
+                            response.clone(),
+                        )
+                    })
+                    .await;
+                details.warnings.extend(compaction.and_then(|c| c.warning));
+                Ok((response, details))
+            }
+        }
+    }
+
+    /// Asks the chat client to summarize the older part of the history once it outgrows a
+    /// [`HistoryPolicy::Summarize`], see [`HistoryPolicy::summarized_until`]. If the summary can
+    /// not be generated the messages are dropped instead so the chain can still answer.
+    async fn compact(
+        &self,
+        history: &[PromptMessage],
+        system_prompt: &PromptMessage,
+        user_message: &PromptMessage,
+        context: Option<&InvocationContext>,
+    ) -> Option<Compaction> {
+        let end: usize =
+            self.history_policy
+                .summarized_until(history, system_prompt, user_message, |text| {
+                    self.count_tokens(text)
+                })?;
+        let replaced: Vec<PromptMessage> = history[1..end].to_vec();
+        let prompt: Vec<PromptMessage> = summary_prompt(&replaced);
+        let result: Result<PromptMessage, T::ErrorType> = match context {
+            None => self.chat_client.invoke(prompt).await,
+            Some(context) => self
+                .chat_client
+                .invoke_with_context(prompt, context)
+                .await
+                .map(|response| response.message),
+        };
+        let compaction = match result {
+            Ok(response) => Compaction {
+                replaced,
+                summary: Some(PromptMessage::SystemMessage(
+                    format!("{}{}", SUMMARY_PREFIX, response.content()).into(),
+                )),
+                warning: None,
+            },
+            Err(error) => Compaction {
+                replaced,
+                summary: None,
+                warning: Some(ChainWarning::SummaryFailed(error.to_string())),
+            },
+        };
+        Some(compaction)
+    }
+
+    /// Sends the system prompt, the conversation and the user message to the chat client.
+    /// The conversation should not include the system prompt.
+    async fn invoke_with_history(
+        &self,
+        conversation: &[PromptMessage],
+        system_prompt: &PromptMessage,
+        user_message: &PromptMessage,
+        context: Option<&InvocationContext>,
+    ) -> Result<(PromptMessage, ExchangeDetails), ChainError<T::ErrorType>> {
+        let history_with_prompt: Vec<PromptMessage> = once(system_prompt)
+            .chain(conversation)
+            .cloned()
             .chain(once(user_message.clone()))
             .collect();
-        let response = self
+        match context {
+            None => self
+                .chat_client
+                .invoke(history_with_prompt)
+                .await
+                .map(|response| (response, ExchangeDetails::default()))
+                .map_err(ChainError::ChatClientError),
+            Some(context) => self
+                .chat_client
+                .invoke_with_context(history_with_prompt, context)
+                .await
+                .map(|response: DetailedChatResponse| {
+                    let details = ExchangeDetails {
+                        provider_request_id: response.provider_request_id,
+                        usage: response.usage,
+                        finish_reason: response.finish_reason,
+                        warnings: Vec::new(),
+                    };
+                    (response.message, details)
+                })
+                .map_err(ChainError::ChatClientError),
+        }
+    }
+}
+
+impl<T> Debug for ChatHistoryChain<T>
+where
+    T: AsyncChatClient + Debug,
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ChatHistoryChain")
+            .field("chat_history_buffer", &self.chat_history_buffer)
+            .field("chat_client", &self.chat_client)
+            .field("concurrency_mode", &self.concurrency_mode)
+            .field("prompt_variables", &self.prompt_variables)
+            .field("history_policy", &self.history_policy)
+            .field("tokenizer", &self.tokenizer.is_some())
+            .field("moderation_policy", &self.moderation_policy)
+            .finish()
+    }
+}
+
+/// Chains are equal when they have the same history, chat client and settings,
+/// the tokenizer and moderation policy are only compared by whether they are set.
+impl<T> PartialEq for ChatHistoryChain<T>
+where
+    T: AsyncChatClient + PartialEq,
+{
+    fn eq(&self, other: &Self) -> bool {
+        self.chat_history_buffer == other.chat_history_buffer
+            && self.chat_client == other.chat_client
+            && self.concurrency_mode == other.concurrency_mode
+            && self.prompt_variables == other.prompt_variables
+            && self.history_policy == other.history_policy
+            && self.tokenizer.is_some() == other.tokenizer.is_some()
+            && self.moderation_policy.is_some() == other.moderation_policy.is_some()
+    }
+}
+
+impl<T> Eq for ChatHistoryChain<T> where T: AsyncChatClient + Eq {}
+
+/// # [`StreamedChatHistoryChain`]
+///
+/// The streamed equivalent of the [`ChatHistoryChain`]. The system prompt, the history and
+/// the new user message are sent to the streamed chat client and the stream is handed back
+/// wrapped in a [`ChatHistoryStream`]. Once the stream has been read to the end the user
+/// message and the full reply are appended to the history for the next invocation.
+///
+/// Concurrent invocations behave as [`ConcurrencyMode::Interleaved`], the history is only
+/// locked while it is read and while the exchange is appended.
+///
+/// * `T` - The type of the streamed chat client to be used
+///
+/// # Examples
+/// ```
+/// use rag_toolchain::clients::*;
+/// use rag_toolchain::chains::*;
+///
+/// async fn run_chain() {
+///     let system_prompt = PromptMessage::SystemMessage("You are a helpful assistant".into());
+///     let client = OpenAIChatCompletionClient::try_new(OpenAIModel::Gpt3Point5Turbo).unwrap();
+///     let chain = StreamedChatHistoryChain::new(client, system_prompt);
+///     let user_prompt = PromptMessage::HumanMessage("Please tell me about the weather".into());
+///     let mut stream = chain.invoke_chain(user_prompt).await.unwrap();
+///     while let Some(value) = stream.next().await {
+///         if let Ok(CompletionStreamValue::Message(message)) = value {
+///             print!("{}", message.content());
+///         }
+///     }
+///     // The reply is now part of the history sent with the next invocation
+///     println!("{:?}", chain.history_snapshot().await);
+/// }
+/// ```
+#[derive(Debug)]
+pub struct StreamedChatHistoryChain<T>
+where
+    T: AsyncStreamedChatClient,
+{
+    chat_history_buffer: Arc<ChatHistoryBuffer>,
+    chat_client: T,
+    history_policy: HistoryPolicy,
+    prompt_variables: Option<PromptVariables>,
+}
+
+impl<T> StreamedChatHistoryChain<T>
+where
+    T: AsyncStreamedChatClient,
+    <T::Item as ChatCompletionStream>::Item: StreamedText,
+{
+    /// # [`StreamedChatHistoryChain::new`]
+    ///
+    /// This constructor to create a new StreamedChatHistoryChain.
+    ///
+    /// # Arguments
+    /// * `chat_client`: `T` - The streamed chat client to be used
+    /// * `system_prompt`: [`PromptMessage`] - The system prompt, please use [`PromptMessage::SystemMessage`]
+    pub fn new(chat_client: T, system_prompt: PromptMessage) -> Self {
+        Self::new_with_history(chat_client, system_prompt, Vec::new())
+    }
+
+    /// # [`StreamedChatHistoryChain::new_with_history`]
+    ///
+    /// This constructor to create a new StreamedChatHistoryChain which carries on a previous
+    /// conversation, see [`ChatHistoryChain::new_with_history`].
+    ///
+    /// # Arguments
+    /// * `chat_client`: `T` - The streamed chat client to be used
+    /// * `system_prompt`: [`PromptMessage`] - The system prompt, please use [`PromptMessage::SystemMessage`]
+    /// * `messages`: [`Vec<PromptMessage>`] - the conversation so far, alternating between
+    ///   human and AI messages
+    pub fn new_with_history(
+        chat_client: T,
+        system_prompt: PromptMessage,
+        messages: Vec<PromptMessage>,
+    ) -> Self {
+        StreamedChatHistoryChain {
+            chat_history_buffer: Arc::new(ChatHistoryBuffer::new(system_prompt, messages)),
+            chat_client,
+            history_policy: HistoryPolicy::default(),
+            prompt_variables: None,
+        }
+    }
+
+    /// # [`StreamedChatHistoryChain::with_history_policy`]
+    ///
+    /// Sets how much of the conversation is resent with each invocation, by default the
+    /// whole conversation is. Messages which no longer fit are dropped from the history
+    /// when the next invocation is sent. See [`HistoryPolicy`].
+    ///
+    /// A streamed chat client can not be asked for a summary, so under
+    /// [`HistoryPolicy::Summarize`] the messages which would be summarized are dropped.
+    ///
+    /// # Arguments
+    /// * `history_policy`: [`HistoryPolicy`] - how the history is trimmed to fit the context window
+    pub fn with_history_policy(mut self, history_policy: HistoryPolicy) -> Self {
+        self.history_policy = history_policy;
+        self
+    }
+
+    /// # [`StreamedChatHistoryChain::with_prompt_variables`]
+    ///
+    /// Resolves the `{{name}}` placeholders in the system prompt each time the chain is invoked,
+    /// see [`ChatHistoryChain::with_prompt_variables`].
+    ///
+    /// # Arguments
+    /// * `prompt_variables`: [`PromptVariables`] - the values of the placeholders
+    pub fn with_prompt_variables(mut self, prompt_variables: PromptVariables) -> Self {
+        self.prompt_variables = Some(prompt_variables);
+        self
+    }
+
+    /// # [`StreamedChatHistoryChain::invoke_chain`]
+    ///
+    /// function to execute the StreamedChatHistoryChain given a new user prompt.
+    /// The exchange is only added to the history once the returned stream finishes,
+    /// if the stream fails or is dropped early the history is left unchanged.
+    ///
+    /// # Arguments
+    /// * `user_message`: [`PromptMessage`] - the user prompt that will be sent to the LLM along with the chat history.
+    ///
+    /// # Errors
+    /// * [`ChainError::ChatClientError`] if the chat client invocation fails.
+    /// * [`ChainError::PromptVariableError`] if a variable in the system prompt could not be resolved.
+    ///
+    /// # Returns
+    /// * [`ChatHistoryStream`] - the stream of the response from the chat client.
+    pub async fn invoke_chain(
+        &self,
+        user_message: PromptMessage,
+    ) -> Result<ChatHistoryStream<T::Item>, ChainError<T::ErrorType>> {
+        // The history keeps the template, only the prompt sent is resolved
+        let system_prompt: Option<PromptMessage> = match &self.prompt_variables {
+            None => None,
+            Some(variables) => resolve_system_prompt(
+                Some(&self.chat_history_buffer.system_prompt),
+                Some(variables),
+            )
+            .map_err(ChainError::PromptVariableError)?,
+        };
+        let system_prompt: &PromptMessage = system_prompt
+            .as_ref()
+            .unwrap_or(&self.chat_history_buffer.system_prompt);
+        let mut prompt_messages: Vec<PromptMessage> = self
+            .chat_history_buffer
+            .update(|messages| {
+                let count_tokens = |text: &str| self.chat_client.count_tokens(text);
+                let first_kept: usize = self.history_policy.first_kept_in_history(
+                    messages,
+                    system_prompt,
+                    Some(&user_message),
+                    count_tokens,
+                );
+                messages.drain(1..first_kept);
+                if let Some(end) = self.history_policy.summarized_until(
+                    messages,
+                    system_prompt,
+                    &user_message,
+                    count_tokens,
+                ) {
+                    messages.drain(1..end);
+                }
+                messages.clone()
+            })
+            .await;
+        prompt_messages[0] = system_prompt.clone();
+        prompt_messages.push(user_message.clone());
+        let stream: T::Item = self
             .chat_client
-            .invoke(history_with_prompt)
+            .invoke_stream(prompt_messages)
             .await
             .map_err(ChainError::ChatClientError)?;
-        self.chat_history_buffer.append(user_message);
-        self.chat_history_buffer.append(response.clone());
-        Ok(response)
+        Ok(ChatHistoryStream::new(
+            stream,
+            self.chat_history_buffer.clone(),
+            user_message,
+        ))
+    }
+
+    /// # [`StreamedChatHistoryChain::history_snapshot`]
+    ///
+    /// Returns a clone of the current chat history including the system prompt.
+    ///
+    /// # Returns
+    /// * [`Vec<PromptMessage>`] - the messages currently in the chat history.
+    pub async fn history_snapshot(&self) -> Vec<PromptMessage> {
+        self.chat_history_buffer.get_messages().await
+    }
+
+    /// # [`StreamedChatHistoryChain::reset`]
+    ///
+    /// Clears the chat history leaving only the system prompt, this starts
+    /// a new conversation with the same chat client.
+    pub async fn reset(&self) {
+        self.chat_history_buffer.reset().await;
+    }
+}
+
+/// # [`ChatHistoryStream`]
+///
+/// Wraps the stream returned by a chat client for a [`StreamedChatHistoryChain`]. Values are
+/// passed through untouched while the text they carry is collected, once the stream finishes
+/// the user message and the full reply are appended to the chain's history.
+///
+/// * `T` - The type of the wrapped stream
+#[derive(Debug)]
+pub struct ChatHistoryStream<T>
+where
+    T: ChatCompletionStream,
+{
+    stream: T,
+    chat_history_buffer: Arc<ChatHistoryBuffer>,
+    /// Taken when the exchange is appended, or the stream fails
+    user_message: Option<PromptMessage>,
+    reply: String,
+}
+
+impl<T> ChatHistoryStream<T>
+where
+    T: ChatCompletionStream,
+    T::Item: StreamedText,
+{
+    /// # [`ChatHistoryStream::new`]
+    ///
+    /// # Arguments
+    /// * `stream`: `T` - the stream returned by the chat client.
+    /// * `chat_history_buffer`: [`Arc<ChatHistoryBuffer>`] - the history the exchange is appended to.
+    /// * `user_message`: [`PromptMessage`] - the user message the stream is replying to.
+    fn new(
+        stream: T,
+        chat_history_buffer: Arc<ChatHistoryBuffer>,
+        user_message: PromptMessage,
+    ) -> Self {
+        ChatHistoryStream {
+            stream,
+            chat_history_buffer,
+            user_message: Some(user_message),
+            reply: String::new(),
+        }
+    }
+
+    /// # [`ChatHistoryStream::reply`]
+    ///
+    /// # Returns
+    /// * &[`str`] - the text of the reply read so far.
+    pub fn reply(&self) -> &str {
+        &self.reply
+    }
+}
+
+impl<T> ChatCompletionStream for ChatHistoryStream<T>
+where
+    T: ChatCompletionStream,
+    T::Item: StreamedText,
+{
+    type ErrorType = T::ErrorType;
+    type Item = T::Item;
+
+    async fn next(&mut self) -> Option<Result<Self::Item, Self::ErrorType>> {
+        let value = self.stream.next().await;
+        match &value {
+            Some(Ok(item)) => {
+                if let Some(text) = item.streamed_text() {
+                    self.reply.push_str(text);
+                }
+            }
+            // A partial reply is never added to the history
+            Some(Err(_)) => self.user_message = None,
+            None => {
+                if let Some(user_message) = self.user_message.take() {
+                    let reply = PromptMessage::AIMessage(self.reply.clone().into());
+                    self.chat_history_buffer
+                        .append_exchange(user_message, reply)
+                        .await;
+                }
+            }
+        }
+        value
+    }
+
+    fn is_token(item: &Self::Item) -> bool {
+        T::is_token(item)
+    }
+}
+
+/// The details the provider returned alongside the response to an exchange
+#[derive(Debug, Default)]
+struct ExchangeDetails {
+    provider_request_id: Option<String>,
+    usage: Option<TokenUsage>,
+    finish_reason: Option<FinishReason>,
+    warnings: Vec<ChainWarning>,
+}
+
+/// The older part of the history summarized for a [`HistoryPolicy::Summarize`]
+#[derive(Debug)]
+struct Compaction {
+    /// The messages after the system prompt which are replaced
+    replaced: Vec<PromptMessage>,
+    /// The summary replacing them, or none if they are dropped
+    summary: Option<PromptMessage>,
+    warning: Option<ChainWarning>,
+}
+
+impl Compaction {
+    /// Replaces the summarized messages in the history, unless another
+    /// invocation has already changed them.
+    fn apply(&self, messages: &mut Vec<PromptMessage>) {
+        let end: usize = self.replaced.len() + 1;
+        if messages.get(1..end) == Some(&self.replaced[..]) {
+            messages.splice(1..end, self.summary.clone());
+        }
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+/// The history is kept behind a lock which is never held across an await, so the buffer can
+/// be cloned and compared. Anything which changes the history first takes the turn, an async
+/// lock which [`ConcurrencyMode::Serialized`] holds for a whole exchange.
+#[derive(Debug)]
 struct ChatHistoryBuffer {
-    messages: RefCell<Vec<PromptMessage>>,
+    system_prompt: PromptMessage,
+    messages: Mutex<Vec<PromptMessage>>,
+    turn: tokio::sync::Mutex<()>,
 }
 
 impl ChatHistoryBuffer {
     /// [`ChatHistoryBuffer::new`]
     ///
-    /// Creates a new chat history buffer with a system prompt.
-    fn new(system_prompt: PromptMessage) -> Self {
+    /// Creates a new chat history buffer with a system prompt followed by the conversation.
+    fn new(system_prompt: PromptMessage, conversation: Vec<PromptMessage>) -> Self {
+        let messages: Vec<PromptMessage> =
+            once(system_prompt.clone()).chain(conversation).collect();
         ChatHistoryBuffer {
-            messages: RefCell::new(vec![system_prompt]),
+            messages: Mutex::new(messages),
+            turn: tokio::sync::Mutex::new(()),
+            system_prompt,
         }
     }
+
+    /// # [`ChatHistoryBuffer::messages`]
+    ///
+    /// Locks the messages, the guard must be dropped before the next await.
+    /// Take the turn first if the messages are going to be changed.
+    fn messages(&self) -> MutexGuard<'_, Vec<PromptMessage>> {
+        self.messages.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// # [`ChatHistoryBuffer::take_turn`]
+    ///
+    /// Waits for any other exchange to finish, giving exclusive access to change
+    /// the history until the guard is dropped.
+    async fn take_turn(&self) -> tokio::sync::MutexGuard<'_, ()> {
+        self.turn.lock().await
+    }
+
+    /// # [`ChatHistoryBuffer::update`]
+    ///
+    /// Takes the turn and changes the messages.
+    async fn update<R>(&self, change: impl FnOnce(&mut Vec<PromptMessage>) -> R) -> R {
+        let _turn = self.take_turn().await;
+        change(&mut self.messages())
+    }
+
     /// # [`ChatHistoryBuffer::get_messages`]
     ///
     /// return a clone of the messages in the buffer.
-    fn get_messages(&self) -> Vec<PromptMessage> {
-        let reference: &Vec<PromptMessage> = &self.messages.borrow();
-        reference.clone()
+    async fn get_messages(&self) -> Vec<PromptMessage> {
+        let _turn = self.take_turn().await;
+        self.messages().clone()
+    }
+
+    /// # [`ChatHistoryBuffer::append_exchange`]
+    ///
+    /// Appends a user message and its response to the chat history buffer
+    /// under a single lock so the pair is never split up.
+    async fn append_exchange(&self, user_message: PromptMessage, response: PromptMessage) {
+        self.update(|messages| {
+            messages.push(user_message);
+            messages.push(response);
+        })
+        .await;
     }
 
-    /// # [`ChatHistoryBuffer::append`]
+    /// # [`ChatHistoryBuffer::reset`]
     ///
-    /// Appends a message to the chat history buffer.
-    fn append(&self, message: PromptMessage) {
-        println!("Appending message: {:?}", message);
-        let reference: &mut Vec<PromptMessage> = &mut self.messages.borrow_mut();
-        reference.push(message);
+    /// Resets the buffer back to just the system prompt.
+    async fn reset(&self) {
+        self.update(|messages| *messages = vec![self.system_prompt.clone()])
+            .await;
+    }
+}
+
+impl Clone for ChatHistoryBuffer {
+    fn clone(&self) -> Self {
+        ChatHistoryBuffer {
+            system_prompt: self.system_prompt.clone(),
+            messages: Mutex::new(self.messages().clone()),
+            turn: tokio::sync::Mutex::new(()),
+        }
+    }
+}
+
+impl PartialEq for ChatHistoryBuffer {
+    fn eq(&self, other: &Self) -> bool {
+        // Locking the same buffer twice would deadlock
+        std::ptr::eq(self, other)
+            || (self.system_prompt == other.system_prompt && *self.messages() == *other.messages())
     }
 }
 
+impl Eq for ChatHistoryBuffer {}
+
 #[cfg(test)]
 mod chat_history_chain_tests {
     use super::*;
-    use crate::clients::MockAsyncChatClient;
+    use crate::clients::{
+        MockAsyncChatClient, MockAsyncStreamedChatClient, MockChatCompletionStream, TokenCounter,
+    };
     use lazy_static::lazy_static;
     use mockall::predicate::eq;
+    use std::num::NonZeroUsize;
+    use std::sync::Arc;
+    use std::time::Duration;
     use std::vec;
 
     lazy_static! {
@@ -178,4 +1072,734 @@ mod chat_history_chain_tests {
             .unwrap();
         assert_eq!(result2, AI_RESPONSE_2.clone());
     }
+
+    #[tokio::test]
+    async fn history_snapshot_and_reset() {
+        let mut chat_client = MockAsyncChatClient::new();
+        chat_client
+            .expect_invoke()
+            .with(eq(vec![SYSTEM_PROMPT.clone(), USER_PROMPT_1.clone()]))
+            .times(2)
+            .returning(|_| Ok(AI_RESPONSE.clone()));
+
+        let chain = ChatHistoryChain::new(chat_client, SYSTEM_PROMPT.clone());
+        assert_eq!(chain.history_snapshot().await, vec![SYSTEM_PROMPT.clone()]);
+        chain.invoke_chain(USER_PROMPT_1.clone()).await.unwrap();
+        assert_eq!(
+            chain.history_snapshot().await,
+            vec![
+                SYSTEM_PROMPT.clone(),
+                USER_PROMPT_1.clone(),
+                AI_RESPONSE.clone()
+            ]
+        );
+        chain.reset().await;
+        assert_eq!(chain.history_snapshot().await, vec![SYSTEM_PROMPT.clone()]);
+        // After a reset the next invocation only sees the system prompt
+        chain.invoke_chain(USER_PROMPT_1.clone()).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn flagged_messages_are_refused_without_calling_the_chat_client() {
+        use crate::chains::moderation_policy::tests::BlockList;
+
+        let mut chat_client = MockAsyncChatClient::new();
+        chat_client.expect_invoke().never();
+        let chain = ChatHistoryChain::new(chat_client, SYSTEM_PROMPT.clone())
+            .with_moderation_policy(ModerationPolicy::new(BlockList(vec!["user"])));
+
+        let result = chain.invoke_chain(USER_PROMPT_1.clone()).await;
+        assert!(
+            matches!(result, Err(ChainError::ContentFlagged(categories)) if categories == ["user"])
+        );
+        let result = chain
+            .invoke_chain_with_context(USER_PROMPT_1.clone(), &InvocationContext::new())
+            .await;
+        assert!(
+            matches!(result, Err(ChainError::ContentFlagged(categories)) if categories == ["user"])
+        );
+        // The refused message is not added to the history
+        assert_eq!(chain.history_snapshot().await, vec![SYSTEM_PROMPT.clone()]);
+    }
+
+    #[tokio::test]
+    async fn messages_which_are_not_flagged_are_answered() {
+        use crate::chains::moderation_policy::tests::BlockList;
+
+        let mut chat_client = MockAsyncChatClient::new();
+        chat_client
+            .expect_invoke()
+            .with(eq(vec![SYSTEM_PROMPT.clone(), USER_PROMPT_1.clone()]))
+            .times(1)
+            .returning(|_| Ok(AI_RESPONSE.clone()));
+        let chain = ChatHistoryChain::new(chat_client, SYSTEM_PROMPT.clone())
+            .with_moderation_policy(ModerationPolicy::new(BlockList(vec!["attack"])));
+
+        let result = chain.invoke_chain(USER_PROMPT_1.clone()).await;
+        assert_eq!(result.unwrap(), AI_RESPONSE.clone());
+    }
+
+    #[tokio::test]
+    async fn moderation_failures_are_returned() {
+        use crate::chains::moderation_policy::tests::Unavailable;
+        use crate::clients::ModerationError;
+
+        let mut chat_client = MockAsyncChatClient::new();
+        chat_client.expect_invoke().never();
+        let chain = ChatHistoryChain::new(chat_client, SYSTEM_PROMPT.clone())
+            .with_moderation_policy(ModerationPolicy::new(Unavailable));
+
+        let result = chain.invoke_chain(USER_PROMPT_1.clone()).await;
+        assert!(matches!(
+            result,
+            Err(ChainError::ModerationError(ModerationError(error))) if error == "service unavailable"
+        ));
+    }
+
+    #[tokio::test]
+    async fn invoke_chain_with_context_records_history() {
+        let mut chat_client = MockAsyncChatClient::new();
+        chat_client
+            .expect_invoke()
+            .with(eq(vec![SYSTEM_PROMPT.clone(), USER_PROMPT_1.clone()]))
+            .times(1)
+            .returning(|_| Ok(AI_RESPONSE.clone()));
+
+        let chain = ChatHistoryChain::new(chat_client, SYSTEM_PROMPT.clone());
+        let context = InvocationContext::new();
+        let response = chain
+            .invoke_chain_with_context(USER_PROMPT_1.clone(), &context)
+            .await
+            .unwrap();
+        assert_eq!(response.message, AI_RESPONSE.clone());
+        assert_eq!(response.request_id, context.request_id());
+        assert_eq!(
+            chain.history_snapshot().await,
+            vec![
+                SYSTEM_PROMPT.clone(),
+                USER_PROMPT_1.clone(),
+                AI_RESPONSE.clone()
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn prompt_variables_are_resolved_on_each_invocation() {
+        use crate::chains::{PromptVariableError, PromptVariables};
+        use std::collections::HashMap;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let template = PromptMessage::SystemMessage("invocation {{count}}".into());
+        let mut chat_client = MockAsyncChatClient::new();
+        for count in 1..=2 {
+            let system_prompt =
+                PromptMessage::SystemMessage(format!("invocation {}", count).into());
+            chat_client
+                .expect_invoke()
+                .withf(move |prompts| prompts[0] == system_prompt)
+                .times(1)
+                .returning(|_| Ok(AI_RESPONSE.clone()));
+        }
+        let invocations = Arc::new(AtomicUsize::new(0));
+        let counter = invocations.clone();
+        let variables = PromptVariables::from_fn(move || {
+            let count = counter.fetch_add(1, Ordering::SeqCst) + 1;
+            HashMap::from([("count".to_string(), count.to_string())])
+        });
+        let chain =
+            ChatHistoryChain::new(chat_client, template.clone()).with_prompt_variables(variables);
+
+        chain.invoke_chain(USER_PROMPT_1.clone()).await.unwrap();
+        chain.invoke_chain(USER_PROMPT_2.clone()).await.unwrap();
+        assert_eq!(invocations.load(Ordering::SeqCst), 2);
+        // The history keeps the template so the next invocation is resolved again
+        assert_eq!(chain.history_snapshot().await[0], template);
+
+        // Neither the chat client or the history are touched when a variable is missing
+        let chain = ChatHistoryChain::new(MockAsyncChatClient::new(), template.clone())
+            .with_prompt_variables(PromptVariables::new(HashMap::new()));
+        let result = chain.invoke_chain(USER_PROMPT_1.clone()).await.unwrap_err();
+        assert!(matches!(
+            result,
+            ChainError::PromptVariableError(PromptVariableError::Unresolved(names))
+                if names == vec!["count".to_string()]
+        ));
+        assert_eq!(chain.history_snapshot().await, vec![template]);
+    }
+
+    #[tokio::test]
+    async fn seeded_history_is_sent_with_the_new_system_prompt() {
+        let new_system_prompt = PromptMessage::SystemMessage("new system prompt".into());
+        let mut chat_client = MockAsyncChatClient::new();
+        chat_client
+            .expect_invoke()
+            .with(eq(vec![
+                new_system_prompt.clone(),
+                USER_PROMPT_1.clone(),
+                AI_RESPONSE.clone(),
+                USER_PROMPT_2.clone(),
+            ]))
+            .times(1)
+            .returning(|_| Ok(AI_RESPONSE_2.clone()));
+
+        // A snapshot from a previous chain still has the old system prompt at the start
+        let snapshot = vec![
+            SYSTEM_PROMPT.clone(),
+            USER_PROMPT_1.clone(),
+            AI_RESPONSE.clone(),
+        ];
+        let chain =
+            ChatHistoryChain::new_with_history(chat_client, new_system_prompt.clone(), snapshot);
+        chain.invoke_chain(USER_PROMPT_2.clone()).await.unwrap();
+        assert_eq!(
+            chain.history_snapshot().await,
+            vec![
+                new_system_prompt.clone(),
+                USER_PROMPT_1.clone(),
+                AI_RESPONSE.clone(),
+                USER_PROMPT_2.clone(),
+                AI_RESPONSE_2.clone()
+            ]
+        );
+        chain.reset().await;
+        assert_eq!(chain.history_snapshot().await, vec![new_system_prompt]);
+    }
+
+    #[tokio::test]
+    async fn sliding_window_sends_the_last_messages() {
+        let mut chat_client = MockAsyncChatClient::new();
+        chat_client
+            .expect_invoke()
+            .with(eq(vec![SYSTEM_PROMPT.clone(), USER_PROMPT_1.clone()]))
+            .times(1)
+            .returning(|_| Ok(AI_RESPONSE.clone()));
+        chat_client
+            .expect_invoke()
+            .with(eq(vec![
+                SYSTEM_PROMPT.clone(),
+                USER_PROMPT_1.clone(),
+                AI_RESPONSE.clone(),
+                USER_PROMPT_2.clone(),
+            ]))
+            .times(1)
+            .returning(|_| Ok(AI_RESPONSE_2.clone()));
+        // The first exchange has dropped out of the window
+        chat_client
+            .expect_invoke()
+            .with(eq(vec![
+                SYSTEM_PROMPT.clone(),
+                USER_PROMPT_2.clone(),
+                AI_RESPONSE_2.clone(),
+                USER_PROMPT_1.clone(),
+            ]))
+            .times(1)
+            .returning(|_| Ok(AI_RESPONSE.clone()));
+
+        let policy = HistoryPolicy::SlidingWindow(NonZeroUsize::new(2).unwrap());
+        let chain = ChatHistoryChain::new_with_policy(chat_client, SYSTEM_PROMPT.clone(), policy);
+        chain.invoke_chain(USER_PROMPT_1.clone()).await.unwrap();
+        chain.invoke_chain(USER_PROMPT_2.clone()).await.unwrap();
+        chain.invoke_chain(USER_PROMPT_1.clone()).await.unwrap();
+        assert_eq!(
+            chain.history_snapshot().await,
+            vec![
+                SYSTEM_PROMPT.clone(),
+                USER_PROMPT_1.clone(),
+                AI_RESPONSE.clone()
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn token_budget_drops_oldest_pairs_but_keeps_system_prompt() {
+        let long_answer = PromptMessage::AIMessage("one two three four five six".into());
+        let mut chat_client = MockAsyncChatClient::new();
+        chat_client
+            .expect_count_tokens()
+            .returning(|text| text.split_whitespace().count());
+        let first_answer = long_answer.clone();
+        chat_client
+            .expect_invoke()
+            .with(eq(vec![SYSTEM_PROMPT.clone(), USER_PROMPT_1.clone()]))
+            .times(1)
+            .returning(move |_| Ok(first_answer.clone()));
+        // Each message is 4 tokens more than its words with the role and overhead, so the
+        // system prompt, the first exchange and the user message with the reply priming are
+        // 6 + 16 + 7 + 3 tokens which is over the budget so the first exchange is dropped
+        chat_client
+            .expect_invoke()
+            .with(eq(vec![SYSTEM_PROMPT.clone(), USER_PROMPT_2.clone()]))
+            .times(1)
+            .returning(|_| Ok(AI_RESPONSE_2.clone()));
+
+        let chain = ChatHistoryChain::new_with_policy(
+            chat_client,
+            SYSTEM_PROMPT.clone(),
+            HistoryPolicy::TokenBudget(30),
+        );
+        chain.invoke_chain(USER_PROMPT_1.clone()).await.unwrap();
+        // The first exchange fits the budget on its own so is kept until it is crowded out
+        assert_eq!(chain.history_snapshot().await.len(), 3);
+        chain.invoke_chain(USER_PROMPT_2.clone()).await.unwrap();
+        assert_eq!(
+            chain.history_snapshot().await,
+            vec![
+                SYSTEM_PROMPT.clone(),
+                USER_PROMPT_2.clone(),
+                AI_RESPONSE_2.clone()
+            ]
+        );
+    }
+
+    fn summarizing_chain(
+        chat_client: MockAsyncChatClient,
+    ) -> ChatHistoryChain<MockAsyncChatClient> {
+        // The system prompt, both exchanges and the next user message are 14 words
+        let policy = HistoryPolicy::Summarize {
+            budget_tokens: 12,
+            keep_last_n: 2,
+        };
+        ChatHistoryChain::new_with_history(
+            chat_client,
+            SYSTEM_PROMPT.clone(),
+            vec![
+                USER_PROMPT_1.clone(),
+                AI_RESPONSE.clone(),
+                USER_PROMPT_2.clone(),
+                AI_RESPONSE_2.clone(),
+            ],
+        )
+        .with_history_policy(policy)
+    }
+
+    #[tokio::test]
+    async fn summarize_replaces_the_oldest_exchanges_with_a_summary() {
+        let summary =
+            PromptMessage::SystemMessage("Summary of earlier conversation: they said hello".into());
+        let mut chat_client = MockAsyncChatClient::new();
+        chat_client
+            .expect_count_tokens()
+            .returning(|text| text.split_whitespace().count());
+        chat_client
+            .expect_invoke()
+            .withf(|prompts| {
+                prompts.len() == 1
+                    && prompts[0]
+                        .content()
+                        .ends_with("User: user prompt\nAssistant: AI response")
+            })
+            .times(1)
+            .returning(|_| Ok(PromptMessage::AIMessage("they said hello".into())));
+        chat_client
+            .expect_invoke()
+            .with(eq(vec![
+                SYSTEM_PROMPT.clone(),
+                summary.clone(),
+                USER_PROMPT_2.clone(),
+                AI_RESPONSE_2.clone(),
+                USER_PROMPT_1.clone(),
+            ]))
+            .times(1)
+            .returning(|_| Ok(AI_RESPONSE.clone()));
+
+        let chain = summarizing_chain(chat_client);
+        chain.invoke_chain(USER_PROMPT_1.clone()).await.unwrap();
+        assert_eq!(
+            chain.history_snapshot().await,
+            vec![
+                SYSTEM_PROMPT.clone(),
+                summary,
+                USER_PROMPT_2.clone(),
+                AI_RESPONSE_2.clone(),
+                USER_PROMPT_1.clone(),
+                AI_RESPONSE.clone()
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn failed_summary_drops_the_oldest_exchanges_with_a_warning() {
+        let mut chat_client = MockAsyncChatClient::new();
+        chat_client
+            .expect_count_tokens()
+            .returning(|text| text.split_whitespace().count());
+        chat_client
+            .expect_invoke()
+            .withf(|prompts| prompts.len() == 1)
+            .times(1)
+            .returning(|_| Err(std::io::Error::other("rate limited")));
+        chat_client
+            .expect_invoke()
+            .with(eq(vec![
+                SYSTEM_PROMPT.clone(),
+                USER_PROMPT_2.clone(),
+                AI_RESPONSE_2.clone(),
+                USER_PROMPT_1.clone(),
+            ]))
+            .times(1)
+            .returning(|_| Ok(AI_RESPONSE.clone()));
+
+        let chain = summarizing_chain(chat_client);
+        let response = chain
+            .invoke_chain_with_context(USER_PROMPT_1.clone(), &InvocationContext::new())
+            .await
+            .unwrap();
+        assert_eq!(response.message, AI_RESPONSE.clone());
+        assert_eq!(
+            response.warnings,
+            vec![ChainWarning::SummaryFailed("rate limited".into())]
+        );
+        assert_eq!(chain.history_snapshot().await.len(), 5);
+    }
+
+    #[tokio::test]
+    async fn interleaved_chain_keeps_the_summary() {
+        let mut chat_client = MockAsyncChatClient::new();
+        chat_client
+            .expect_count_tokens()
+            .returning(|text| text.split_whitespace().count());
+        chat_client
+            .expect_invoke()
+            .withf(|prompts| prompts.len() == 1)
+            .times(1)
+            .returning(|_| Ok(PromptMessage::AIMessage("they said hello".into())));
+        // The summary is kept even though the exchange itself fails
+        chat_client
+            .expect_invoke()
+            .withf(|prompts| prompts.len() == 5)
+            .times(1)
+            .returning(|_| Err(std::io::Error::other("server error")));
+
+        let mut chain = summarizing_chain(chat_client);
+        chain.concurrency_mode = ConcurrencyMode::Interleaved;
+        chain.invoke_chain(USER_PROMPT_1.clone()).await.unwrap_err();
+        let history = chain.history_snapshot().await;
+        assert_eq!(history.len(), 4);
+        assert!(history[1].content().starts_with(SUMMARY_PREFIX));
+    }
+
+    #[tokio::test]
+    async fn token_budget_counts_with_the_tokenizer() {
+        use crate::common::{EmbeddingModel, OpenAIEmbeddingModel};
+
+        let mut chat_client = MockAsyncChatClient::new();
+        chat_client.expect_count_tokens().never();
+        chat_client
+            .expect_invoke()
+            .times(2)
+            .returning(|_| Ok(AI_RESPONSE.clone()));
+        let tokenizer = OpenAIEmbeddingModel::TextEmbedding3Small
+            .metadata()
+            .tokenizer;
+        let chain = ChatHistoryChain::new_with_policy(
+            chat_client,
+            SYSTEM_PROMPT.clone(),
+            HistoryPolicy::TokenBudget(1000),
+        )
+        .with_tokenizer(tokenizer);
+        chain.invoke_chain(USER_PROMPT_1.clone()).await.unwrap();
+        chain.invoke_chain(USER_PROMPT_2.clone()).await.unwrap();
+        assert_eq!(chain.history_snapshot().await.len(), 5);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn concurrent_invocations_serialize() {
+        let chain = Arc::new(ChatHistoryChain::new(
+            DelayedChatClient,
+            SYSTEM_PROMPT.clone(),
+        ));
+        let history = run_concurrently(chain.clone()).await;
+
+        let first_ordering = exchange_ordering(&["slow", "fast"]);
+        let second_ordering = exchange_ordering(&["fast", "slow"]);
+        assert!(history == first_ordering || history == second_ordering);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn concurrent_invocations_interleave() {
+        let chain = Arc::new(ChatHistoryChain::new_with_concurrency_mode(
+            DelayedChatClient,
+            SYSTEM_PROMPT.clone(),
+            ConcurrencyMode::Interleaved,
+        ));
+        let history = run_concurrently(chain.clone()).await;
+
+        // Neither call saw the other so each reply only counts its own message,
+        // the fast exchange finishes first and the pairs are never split.
+        let mut expected = vec![SYSTEM_PROMPT.clone()];
+        for content in ["fast", "slow"] {
+            expected.push(PromptMessage::HumanMessage((*content).into()));
+            expected.push(PromptMessage::AIMessage(
+                format!("{} after 2", content).into(),
+            ));
+        }
+        assert_eq!(history, expected);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn timed_out_exchanges_are_not_recorded() {
+        let chain = ChatHistoryChain::new(DelayedChatClient, SYSTEM_PROMPT.clone());
+        let result = chain
+            .invoke_chain_with_timeout(
+                PromptMessage::HumanMessage("slow".into()),
+                Duration::from_millis(50),
+            )
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            result,
+            ChainError::Timeout {
+                phase: ChainPhase::Chat
+            }
+        ));
+        assert_eq!(chain.history_snapshot().await, vec![SYSTEM_PROMPT.clone()]);
+
+        let response = chain
+            .invoke_chain_with_timeout(
+                PromptMessage::HumanMessage("fast".into()),
+                Duration::from_millis(50),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response, PromptMessage::AIMessage("fast after 2".into()));
+        assert_eq!(chain.history_snapshot().await, exchange_ordering(&["fast"]));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn cloned_chain_has_its_own_history() {
+        let chain = ChatHistoryChain::new(DelayedChatClient, SYSTEM_PROMPT.clone());
+        chain
+            .invoke_chain(PromptMessage::HumanMessage("fast".into()))
+            .await
+            .unwrap();
+        let cloned = chain.clone();
+        assert_eq!(cloned, chain);
+
+        cloned
+            .invoke_chain(PromptMessage::HumanMessage("slow".into()))
+            .await
+            .unwrap();
+        assert_ne!(cloned, chain);
+        assert_eq!(chain.history_snapshot().await, exchange_ordering(&["fast"]));
+        assert_eq!(
+            cloned.history_snapshot().await,
+            exchange_ordering(&["fast", "slow"])
+        );
+    }
+
+    // Spawns a slow and a fast invocation on the chain at the same time
+    // and returns the history once both have completed.
+    async fn run_concurrently(
+        chain: Arc<ChatHistoryChain<DelayedChatClient>>,
+    ) -> Vec<PromptMessage> {
+        let slow_chain = chain.clone();
+        let slow = tokio::spawn(async move {
+            slow_chain
+                .invoke_chain(PromptMessage::HumanMessage("slow".into()))
+                .await
+        });
+        let fast_chain = chain.clone();
+        let fast = tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            fast_chain
+                .invoke_chain(PromptMessage::HumanMessage("fast".into()))
+                .await
+        });
+        slow.await.unwrap().unwrap();
+        fast.await.unwrap().unwrap();
+        chain.history_snapshot().await
+    }
+
+    #[tokio::test]
+    async fn streamed_reply_is_recorded_once_the_stream_ends() {
+        let mut chat_client = MockAsyncStreamedChatClient::new();
+        chat_client
+            .expect_invoke_stream()
+            .with(eq(vec![SYSTEM_PROMPT.clone(), USER_PROMPT_1.clone()]))
+            .times(1)
+            .returning(|_| Ok(reply_stream(vec![Ok("AI "), Ok("response")])));
+        chat_client
+            .expect_invoke_stream()
+            .with(eq(vec![
+                SYSTEM_PROMPT.clone(),
+                USER_PROMPT_1.clone(),
+                AI_RESPONSE.clone(),
+                USER_PROMPT_2.clone(),
+            ]))
+            .times(1)
+            .returning(|_| Ok(reply_stream(vec![Ok("AI response 2")])));
+
+        let chain = StreamedChatHistoryChain::new(chat_client, SYSTEM_PROMPT.clone());
+        let mut stream = chain.invoke_chain(USER_PROMPT_1.clone()).await.unwrap();
+        assert_eq!(
+            stream.next().await.unwrap().unwrap(),
+            PromptMessage::AIMessage("AI ".into())
+        );
+        // Nothing is recorded until the stream has been read to the end
+        assert_eq!(chain.history_snapshot().await, vec![SYSTEM_PROMPT.clone()]);
+        while stream.next().await.is_some() {}
+        assert_eq!(stream.reply(), "AI response");
+        assert_eq!(
+            chain.history_snapshot().await,
+            vec![
+                SYSTEM_PROMPT.clone(),
+                USER_PROMPT_1.clone(),
+                AI_RESPONSE.clone()
+            ]
+        );
+
+        let mut stream = chain.invoke_chain(USER_PROMPT_2.clone()).await.unwrap();
+        while stream.next().await.is_some() {}
+        assert_eq!(
+            chain.history_snapshot().await,
+            vec![
+                SYSTEM_PROMPT.clone(),
+                USER_PROMPT_1.clone(),
+                AI_RESPONSE.clone(),
+                USER_PROMPT_2.clone(),
+                AI_RESPONSE_2.clone()
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn failed_stream_leaves_history_unchanged() {
+        let mut chat_client = MockAsyncStreamedChatClient::new();
+        chat_client
+            .expect_invoke_stream()
+            .returning(|_| Ok(reply_stream(vec![Ok("AI "), Err("connection reset")])));
+
+        let chain = StreamedChatHistoryChain::new(chat_client, SYSTEM_PROMPT.clone());
+        let mut stream = chain.invoke_chain(USER_PROMPT_1.clone()).await.unwrap();
+        assert!(stream.next().await.unwrap().is_ok());
+        assert!(stream.next().await.unwrap().is_err());
+        assert!(stream.next().await.is_none());
+        assert_eq!(chain.history_snapshot().await, vec![SYSTEM_PROMPT.clone()]);
+    }
+
+    #[tokio::test]
+    async fn streamed_chain_applies_history_policy() {
+        let mut chat_client = MockAsyncStreamedChatClient::new();
+        chat_client
+            .expect_invoke_stream()
+            .with(eq(vec![
+                SYSTEM_PROMPT.clone(),
+                USER_PROMPT_2.clone(),
+                AI_RESPONSE_2.clone(),
+                USER_PROMPT_1.clone(),
+            ]))
+            .times(1)
+            .returning(|_| Ok(reply_stream(vec![Ok("AI response")])));
+
+        let chain = StreamedChatHistoryChain::new_with_history(
+            chat_client,
+            SYSTEM_PROMPT.clone(),
+            vec![
+                USER_PROMPT_1.clone(),
+                AI_RESPONSE.clone(),
+                USER_PROMPT_2.clone(),
+                AI_RESPONSE_2.clone(),
+            ],
+        )
+        .with_history_policy(HistoryPolicy::SlidingWindow(NonZeroUsize::new(2).unwrap()));
+        let mut stream = chain.invoke_chain(USER_PROMPT_1.clone()).await.unwrap();
+        while stream.next().await.is_some() {}
+        assert_eq!(
+            chain.history_snapshot().await,
+            vec![
+                SYSTEM_PROMPT.clone(),
+                USER_PROMPT_2.clone(),
+                AI_RESPONSE_2.clone(),
+                USER_PROMPT_1.clone(),
+                AI_RESPONSE.clone()
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn streamed_chain_resolves_prompt_variables() {
+        use crate::chains::{PromptVariableError, PromptVariables};
+        use std::collections::HashMap;
+
+        let template = PromptMessage::SystemMessage("You are helping {{username}}".into());
+        let mut chat_client = MockAsyncStreamedChatClient::new();
+        chat_client
+            .expect_invoke_stream()
+            .with(eq(vec![
+                PromptMessage::SystemMessage("You are helping Ada".into()),
+                USER_PROMPT_1.clone(),
+            ]))
+            .times(1)
+            .returning(|_| Ok(reply_stream(vec![Ok("AI response")])));
+        let variables =
+            PromptVariables::new(HashMap::from([("username".to_string(), "Ada".to_string())]));
+        let chain = StreamedChatHistoryChain::new(chat_client, template.clone())
+            .with_prompt_variables(variables);
+
+        let mut stream = chain.invoke_chain(USER_PROMPT_1.clone()).await.unwrap();
+        while stream.next().await.is_some() {}
+        // The history keeps the template so the next invocation is resolved again
+        assert_eq!(
+            chain.history_snapshot().await,
+            vec![template.clone(), USER_PROMPT_1.clone(), AI_RESPONSE.clone()]
+        );
+
+        // Neither the chat client or the history are touched when a variable is missing
+        let chain =
+            StreamedChatHistoryChain::new(MockAsyncStreamedChatClient::new(), template.clone())
+                .with_prompt_variables(PromptVariables::new(HashMap::new()));
+        let result = chain.invoke_chain(USER_PROMPT_1.clone()).await;
+        assert!(matches!(
+            result,
+            Err(ChainError::PromptVariableError(PromptVariableError::Unresolved(names)))
+                if names == vec!["username".to_string()]
+        ));
+        assert_eq!(chain.history_snapshot().await, vec![template]);
+    }
+
+    // A stream which yields each of the given parts of a reply and then ends
+    fn reply_stream(parts: Vec<Result<&'static str, &'static str>>) -> MockChatCompletionStream {
+        let mut values = parts.into_iter().map(|part| {
+            part.map(|text| PromptMessage::AIMessage(text.into()))
+                .map_err(std::io::Error::other)
+        });
+        let mut stream = MockChatCompletionStream::new();
+        stream.expect_next().returning(move || values.next());
+        stream
+    }
+
+    // The history we expect when the exchanges happen one after the other
+    // in the given order, each reply counts the messages it was sent.
+    fn exchange_ordering(order: &[&str]) -> Vec<PromptMessage> {
+        let mut history = vec![SYSTEM_PROMPT.clone()];
+        for content in order {
+            history.push(PromptMessage::HumanMessage((*content).into()));
+            let reply = format!("{} after {}", content, history.len());
+            history.push(PromptMessage::AIMessage(reply.into()));
+        }
+        history
+    }
+
+    // Chat client which takes longer to reply to "slow" than to anything else
+    // and replies with the message along with the length of the history it was sent.
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    struct DelayedChatClient;
+
+    impl TokenCounter for DelayedChatClient {}
+
+    impl AsyncChatClient for DelayedChatClient {
+        type ErrorType = std::io::Error;
+
+        async fn invoke(
+            &self,
+            prompt_messages: Vec<PromptMessage>,
+        ) -> Result<PromptMessage, Self::ErrorType> {
+            let last = prompt_messages.last().unwrap().content().to_string();
+            let delay = if last == "slow" { 100 } else { 1 };
+            tokio::time::sleep(Duration::from_millis(delay)).await;
+            Ok(PromptMessage::AIMessage(
+                format!("{} after {}", last, prompt_messages.len()).into(),
+            ))
+        }
+    }
 }
@@ -0,0 +1,444 @@
+use crate::common::{Chunk, Chunks, ScoredChunk};
+use serde_json::Value;
+use std::collections::HashSet;
+use std::fmt::Debug;
+use std::ptr;
+
+/// # [`ChunkPostProcessor`]
+///
+/// Reworks the supporting chunks after they are retrieved and before they are put into the
+/// prompt, see [`crate::chains::BasicRAGChainBuilder::post_processor`]. The chunks are given
+/// most relevant first and a processor should keep that order, so the most relevant chunks
+/// are still the ones kept when the prompt has to be fitted to a budget.
+pub trait ChunkPostProcessor: Debug + Send + Sync {
+    /// # [`ChunkPostProcessor::process`]
+    ///
+    /// # Arguments
+    /// * `chunks`: [`Chunks`] - the retrieved chunks, most relevant first.
+    ///
+    /// # Returns
+    /// * [`Chunks`] - the chunks to put into the prompt, most relevant first.
+    fn process(&self, chunks: Chunks) -> Chunks;
+
+    /// # [`ChunkPostProcessor::process_scored`]
+    ///
+    /// Processes chunks along with their retrieval scores, used when a chain returns its
+    /// sources. By default the chunks are given to [`ChunkPostProcessor::process`] and each
+    /// chunk it returns unchanged keeps its score, any other chunk is given the lowest score
+    /// retrieved. Processors which change chunks should override this to score them.
+    ///
+    /// # Arguments
+    /// * `scored`: [`Vec<ScoredChunk>`] - the retrieved chunks, most relevant first.
+    ///
+    /// # Returns
+    /// * [`Vec<ScoredChunk>`] - the chunks to put into the prompt, most relevant first.
+    fn process_scored(&self, scored: Vec<ScoredChunk>) -> Vec<ScoredChunk> {
+        let lowest: f32 = scored
+            .iter()
+            .map(|scored| scored.score)
+            .fold(f32::INFINITY, f32::min);
+        let chunks: Chunks = scored.iter().map(|scored| scored.chunk.clone()).collect();
+        let mut unused: Vec<Option<f32>> = scored.iter().map(|scored| Some(scored.score)).collect();
+        self.process(chunks)
+            .into_iter()
+            .map(|chunk| {
+                let score: f32 = scored
+                    .iter()
+                    .position(|scored| scored.chunk == chunk)
+                    .and_then(|index| unused[index].take())
+                    .unwrap_or(lowest);
+                ScoredChunk::new(chunk, score)
+            })
+            .collect()
+    }
+}
+
+/// Processors have no general notion of equality, two are only equal if they are the same
+/// processor. This lets chains holding them still be compared.
+impl PartialEq for dyn ChunkPostProcessor {
+    fn eq(&self, other: &Self) -> bool {
+        ptr::addr_eq(self, other)
+    }
+}
+
+/// # [`DeduplicateBySimilarity`]
+///
+/// Drops chunks whose content overlaps a more relevant chunk by at least the threshold.
+/// The overlap of two chunks is the share of the distinct words in the shorter chunk which
+/// also appear in the other, ignoring case and punctuation at either end of a word. So a
+/// chunk which is contained in another overlaps it completely.
+///
+/// # Examples
+/// ```
+/// use rag_toolchain::chains::*;
+/// use rag_toolchain::common::*;
+///
+/// let deduplicate = DeduplicateBySimilarity::new(0.8);
+/// let chunks = vec![
+///     Chunk::new("The refund takes five working days."),
+///     Chunk::new("the refund takes five working days"),
+///     Chunk::new("Shipping is free over fifty pounds."),
+/// ];
+/// assert_eq!(
+///     deduplicate.process(chunks),
+///     vec![
+///         Chunk::new("The refund takes five working days."),
+///         Chunk::new("Shipping is free over fifty pounds."),
+///     ]
+/// );
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DeduplicateBySimilarity {
+    threshold: f32,
+}
+
+impl DeduplicateBySimilarity {
+    /// # [`DeduplicateBySimilarity::new`]
+    ///
+    /// # Arguments
+    /// * `threshold`: [`f32`] - from 0 to 1, chunks overlapping a kept chunk by at least
+    ///   this much are dropped. It is clamped to 0..=1.
+    ///
+    /// # Returns
+    /// * [`DeduplicateBySimilarity`] - the processor.
+    pub fn new(threshold: f32) -> Self {
+        DeduplicateBySimilarity {
+            threshold: threshold.clamp(0.0, 1.0),
+        }
+    }
+
+    /// Keeps the items whose chunk does not overlap the chunk of an item already kept
+    fn deduplicate<T>(&self, items: Vec<T>, chunk: impl Fn(&T) -> &Chunk) -> Vec<T> {
+        let mut kept: Vec<T> = Vec::with_capacity(items.len());
+        let mut kept_words: Vec<HashSet<String>> = Vec::with_capacity(items.len());
+        for item in items {
+            let words: HashSet<String> = words(chunk(&item).content());
+            if kept_words
+                .iter()
+                .any(|other| overlap(&words, other) >= self.threshold)
+            {
+                continue;
+            }
+            kept_words.push(words);
+            kept.push(item);
+        }
+        kept
+    }
+}
+
+impl ChunkPostProcessor for DeduplicateBySimilarity {
+    fn process(&self, chunks: Chunks) -> Chunks {
+        self.deduplicate(chunks, |chunk| chunk)
+    }
+
+    fn process_scored(&self, scored: Vec<ScoredChunk>) -> Vec<ScoredChunk> {
+        self.deduplicate(scored, |scored| &scored.chunk)
+    }
+}
+
+/// The distinct words of the text in lowercase without any punctuation at their ends
+fn words(text: &str) -> HashSet<String> {
+    text.split_whitespace()
+        .map(|word| word.trim_matches(|c: char| !c.is_alphanumeric()))
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect()
+}
+
+/// The share of the smaller set which is also in the other, two empty sets overlap completely
+fn overlap(a: &HashSet<String>, b: &HashSet<String>) -> f32 {
+    let smaller: usize = a.len().min(b.len());
+    if smaller == 0 {
+        return if a.len() == b.len() { 1.0 } else { 0.0 };
+    }
+    a.intersection(b).count() as f32 / smaller as f32
+}
+
+/// # [`GroupByMetadataKey`]
+///
+/// Merges the chunks which have the same value for a top level metadata key, such as the
+/// id of the document they came from, into one chunk. The merged chunk takes the place of
+/// the most relevant chunk in the group, its content is the content of each chunk in the
+/// group joined with the separator in the order they were retrieved and its metadata is the
+/// metadata of the most relevant chunk. Chunks without the key are left as they are.
+///
+/// # Examples
+/// ```
+/// use rag_toolchain::chains::*;
+/// use rag_toolchain::common::*;
+/// use serde_json::json;
+///
+/// let group = GroupByMetadataKey::new("document_id");
+/// let chunks = vec![
+///     Chunk::new_with_metadata("Refunds take five days.", json!({"document_id": 1})),
+///     Chunk::new_with_metadata("Shipping is free.", json!({"document_id": 2})),
+///     Chunk::new_with_metadata("Refunds are paid to the card.", json!({"document_id": 1})),
+/// ];
+/// assert_eq!(
+///     group.process(chunks),
+///     vec![
+///         Chunk::new_with_metadata(
+///             "Refunds take five days.\nRefunds are paid to the card.",
+///             json!({"document_id": 1})
+///         ),
+///         Chunk::new_with_metadata("Shipping is free.", json!({"document_id": 2})),
+///     ]
+/// );
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct GroupByMetadataKey {
+    key: String,
+    separator: String,
+}
+
+impl GroupByMetadataKey {
+    /// The separator used unless configured otherwise
+    pub const DEFAULT_SEPARATOR: &'static str = "\n";
+
+    /// # [`GroupByMetadataKey::new`]
+    ///
+    /// # Arguments
+    /// * `key`: impl [`Into<String>`] - the top level metadata key chunks are grouped by.
+    ///
+    /// # Returns
+    /// * [`GroupByMetadataKey`] - joining the chunks with [`GroupByMetadataKey::DEFAULT_SEPARATOR`].
+    pub fn new(key: impl Into<String>) -> Self {
+        GroupByMetadataKey {
+            key: key.into(),
+            separator: Self::DEFAULT_SEPARATOR.into(),
+        }
+    }
+
+    /// # [`GroupByMetadataKey::with_separator`]
+    ///
+    /// # Arguments
+    /// * `separator`: impl [`Into<String>`] - put between the content of the chunks in a group.
+    ///
+    /// # Returns
+    /// * [`GroupByMetadataKey`] - the processor with the separator set.
+    pub fn with_separator(mut self, separator: impl Into<String>) -> Self {
+        self.separator = separator.into();
+        self
+    }
+
+    /// Groups the items by the key value of their chunk, in the order the groups were first
+    /// seen. Items whose chunk does not have the key are each in a group of their own.
+    fn group<T>(&self, items: Vec<T>, chunk: impl Fn(&T) -> &Chunk) -> Vec<Vec<T>> {
+        let mut groups: Vec<(Option<Value>, Vec<T>)> = Vec::new();
+        for item in items {
+            let value: Option<Value> = chunk(&item).metadata().get(&self.key).cloned();
+            let group = value.as_ref().and_then(|value| {
+                groups
+                    .iter_mut()
+                    .find(|(key, _)| key.as_ref() == Some(value))
+            });
+            match group {
+                Some((_, members)) => members.push(item),
+                None => groups.push((value, vec![item])),
+            }
+        }
+        groups.into_iter().map(|(_, members)| members).collect()
+    }
+
+    /// Joins the content of the members of a group under the metadata of the first
+    fn merge(&self, members: &[&Chunk]) -> Chunk {
+        let content: String = members
+            .iter()
+            .map(|chunk| chunk.content())
+            .collect::<Vec<&str>>()
+            .join(&self.separator);
+        Chunk::new_with_metadata(content, members[0].metadata().clone())
+    }
+}
+
+impl ChunkPostProcessor for GroupByMetadataKey {
+    fn process(&self, chunks: Chunks) -> Chunks {
+        self.group(chunks, |chunk| chunk)
+            .into_iter()
+            .map(|mut members| {
+                if members.len() == 1 {
+                    return members.remove(0);
+                }
+                self.merge(&members.iter().collect::<Vec<&Chunk>>())
+            })
+            .collect()
+    }
+
+    /// A merged chunk is given the highest score of the chunks in its group
+    fn process_scored(&self, scored: Vec<ScoredChunk>) -> Vec<ScoredChunk> {
+        self.group(scored, |scored| &scored.chunk)
+            .into_iter()
+            .map(|mut members| {
+                if members.len() == 1 {
+                    return members.remove(0);
+                }
+                let score: f32 = members
+                    .iter()
+                    .map(|scored| scored.score)
+                    .fold(f32::NEG_INFINITY, f32::max);
+                let chunks: Vec<&Chunk> = members.iter().map(|scored| &scored.chunk).collect();
+                ScoredChunk::new(self.merge(&chunks), score)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::sync::Arc;
+
+    fn document(content: &str, id: u32) -> Chunk {
+        Chunk::new_with_metadata(content, json!({ "document_id": id }))
+    }
+
+    #[test]
+    fn deduplicate_keeps_the_most_relevant_of_overlapping_chunks() {
+        let chunks = vec![
+            Chunk::new("the kernel schedules processes by priority"),
+            Chunk::new("paging maps virtual memory to frames"),
+            Chunk::new("The kernel schedules processes by priority!"),
+            Chunk::new("the kernel schedules processes"),
+        ];
+        assert_eq!(
+            DeduplicateBySimilarity::new(0.9).process(chunks),
+            vec![
+                Chunk::new("the kernel schedules processes by priority"),
+                Chunk::new("paging maps virtual memory to frames"),
+            ]
+        );
+    }
+
+    #[test]
+    fn deduplicate_keeps_chunks_below_the_threshold() {
+        let chunks = vec![
+            Chunk::new("one two three four"),
+            Chunk::new("one two five six"),
+        ];
+        assert_eq!(
+            DeduplicateBySimilarity::new(0.6).process(chunks.clone()),
+            chunks
+        );
+        assert_eq!(
+            DeduplicateBySimilarity::new(0.5).process(chunks.clone()),
+            vec![Chunk::new("one two three four")]
+        );
+    }
+
+    #[test]
+    fn deduplicate_treats_empty_chunks_as_duplicates_of_each_other() {
+        let chunks = vec![Chunk::new(""), Chunk::new("words"), Chunk::new("  ")];
+        assert_eq!(
+            DeduplicateBySimilarity::new(1.0).process(chunks),
+            vec![Chunk::new(""), Chunk::new("words")]
+        );
+    }
+
+    #[test]
+    fn group_merges_chunks_in_order_of_their_most_relevant_member() {
+        let chunks = vec![
+            document("b1", 2),
+            document("a1", 1),
+            Chunk::new("loose"),
+            document("b2", 2),
+            document("a2", 1),
+            document("b3", 2),
+        ];
+        assert_eq!(
+            GroupByMetadataKey::new("document_id")
+                .with_separator(" | ")
+                .process(chunks),
+            vec![
+                document("b1 | b2 | b3", 2),
+                document("a1 | a2", 1),
+                Chunk::new("loose"),
+            ]
+        );
+    }
+
+    #[test]
+    fn group_leaves_chunks_without_the_key_alone() {
+        let chunks = vec![Chunk::new("first"), Chunk::new("second")];
+        assert_eq!(
+            GroupByMetadataKey::new("document_id").process(chunks.clone()),
+            chunks
+        );
+    }
+
+    #[test]
+    fn deduplicate_keeps_the_scores_of_the_chunks_it_keeps() {
+        let scored = vec![
+            ScoredChunk::new(Chunk::new("refunds take five days"), 0.9),
+            ScoredChunk::new(Chunk::new("Refunds take five days."), 0.8),
+            ScoredChunk::new(Chunk::new("five"), 0.7),
+            ScoredChunk::new(Chunk::new("shipping is free"), 0.6),
+        ];
+        assert_eq!(
+            DeduplicateBySimilarity::new(0.9).process_scored(scored),
+            vec![
+                ScoredChunk::new(Chunk::new("refunds take five days"), 0.9),
+                ScoredChunk::new(Chunk::new("shipping is free"), 0.6),
+            ]
+        );
+    }
+
+    #[test]
+    fn group_scores_merged_chunks_by_their_most_relevant_member() {
+        let scored = vec![
+            ScoredChunk::new(document("a1", 1), 0.9),
+            ScoredChunk::new(document("b1", 2), 0.8),
+            ScoredChunk::new(document("a2", 1), 0.7),
+        ];
+        assert_eq!(
+            GroupByMetadataKey::new("document_id").process_scored(scored),
+            vec![
+                ScoredChunk::new(document("a1\na2", 1), 0.9),
+                ScoredChunk::new(document("b1", 2), 0.8),
+            ]
+        );
+    }
+
+    #[derive(Debug)]
+    struct Reverse;
+
+    impl ChunkPostProcessor for Reverse {
+        fn process(&self, chunks: Chunks) -> Chunks {
+            chunks
+                .into_iter()
+                .rev()
+                .map(|chunk| match chunk.content() {
+                    "changed" => Chunk::new("rewritten"),
+                    _ => chunk,
+                })
+                .collect()
+        }
+    }
+
+    #[test]
+    fn by_default_only_unchanged_chunks_keep_their_scores() {
+        let scored = vec![
+            ScoredChunk::new(Chunk::new("same"), 0.9),
+            ScoredChunk::new(Chunk::new("changed"), 0.8),
+            ScoredChunk::new(Chunk::new("same"), 0.5),
+        ];
+        assert_eq!(
+            Reverse.process_scored(scored),
+            vec![
+                ScoredChunk::new(Chunk::new("same"), 0.9),
+                ScoredChunk::new(Chunk::new("rewritten"), 0.5),
+                ScoredChunk::new(Chunk::new("same"), 0.5),
+            ]
+        );
+    }
+
+    #[test]
+    fn processors_are_only_equal_to_themselves() {
+        let first: Arc<dyn ChunkPostProcessor> = Arc::new(DeduplicateBySimilarity::new(0.5));
+        let second: Arc<dyn ChunkPostProcessor> = Arc::new(DeduplicateBySimilarity::new(0.5));
+        assert!(first == first.clone());
+        assert!(first != second);
+    }
+}
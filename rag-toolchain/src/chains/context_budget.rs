@@ -0,0 +1,258 @@
+use crate::common::{Chunk, Chunks};
+use std::num::NonZeroU32;
+
+/// # [`ContextBudget`]
+///
+/// Instead of a fixed top_k the chain retrieves `fetch_k` candidates and adds them to the
+/// prompt in relevance order until the next chunk would take the prompt over
+/// `max_context_tokens`. Tokens are counted with the chat client's tokenizer and the budget
+/// covers the system prompt, the prompt template and the user question as well as the chunks.
+/// The overhead OpenAI adds to each message is included, see [`crate::clients::count_prompt_tokens`].
+///
+/// If the most relevant chunk does not fit on its own it is truncated to the space that is
+/// left, so a chunk is always included unless the prompt alone uses up the budget.
+///
+/// # Examples
+/// ```
+/// use rag_toolchain::chains::ContextBudget;
+/// use std::num::NonZeroU32;
+///
+/// let budget: ContextBudget = ContextBudget::new(4_000).with_fetch_k(NonZeroU32::new(50).unwrap());
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ContextBudget {
+    max_context_tokens: usize,
+    fetch_k: NonZeroU32,
+}
+
+impl ContextBudget {
+    /// The number of candidates retrieved unless configured otherwise
+    /// with [`ContextBudget::with_fetch_k`].
+    pub const DEFAULT_FETCH_K: NonZeroU32 = NonZeroU32::new(20).unwrap();
+
+    /// # [`ContextBudget::new`]
+    ///
+    /// # Arguments
+    /// * `max_context_tokens`: [`usize`] - the most tokens the prompt sent to the chat client can use.
+    ///
+    /// # Returns
+    /// * [`ContextBudget`] - a budget retrieving [`ContextBudget::DEFAULT_FETCH_K`] candidates.
+    pub fn new(max_context_tokens: usize) -> Self {
+        ContextBudget {
+            max_context_tokens,
+            fetch_k: Self::DEFAULT_FETCH_K,
+        }
+    }
+
+    /// # [`ContextBudget::with_fetch_k`]
+    ///
+    /// # Arguments
+    /// * `fetch_k`: [`NonZeroU32`] - the number of candidates to retrieve before fitting them to the budget.
+    ///
+    /// # Returns
+    /// * [`ContextBudget`] - the budget with the new number of candidates.
+    pub fn with_fetch_k(mut self, fetch_k: NonZeroU32) -> Self {
+        self.fetch_k = fetch_k;
+        self
+    }
+
+    /// # [`ContextBudget::max_context_tokens`]
+    ///
+    /// # Returns
+    /// * [`usize`] - the most tokens the prompt can use.
+    pub fn max_context_tokens(&self) -> usize {
+        self.max_context_tokens
+    }
+
+    /// # [`ContextBudget::fetch_k`]
+    ///
+    /// # Returns
+    /// * [`NonZeroU32`] - the number of candidates retrieved.
+    pub fn fetch_k(&self) -> NonZeroU32 {
+        self.fetch_k
+    }
+
+    /// # [`ContextBudget::fit`]
+    ///
+    /// Greedily takes chunks in order until the next one would exceed the budget.
+    ///
+    /// # Arguments
+    /// * `chunks`: [`Chunks`] - the candidates, most relevant first.
+    /// * `overhead`: [`usize`] - the tokens used by the prompt without any chunks.
+    /// * `count_tokens`: impl [`Fn(usize, &Chunk) -> usize`] - counts the tokens a chunk adds to
+    ///   the prompt given its position in the prompt.
+    ///
+    /// # Returns
+    /// * [`Chunks`] - the chunks which fit, the first may have been truncated.
+    pub(crate) fn fit(
+        &self,
+        chunks: Chunks,
+        overhead: usize,
+        count_tokens: impl Fn(usize, &Chunk) -> usize,
+    ) -> Chunks {
+        let mut remaining: usize = self.max_context_tokens.saturating_sub(overhead);
+        let mut fitted: Chunks = Vec::new();
+        for chunk in chunks {
+            let tokens: usize = count_tokens(fitted.len(), &chunk);
+            if tokens <= remaining {
+                remaining -= tokens;
+                fitted.push(chunk);
+                continue;
+            }
+            if fitted.is_empty() && remaining > 0 {
+                let prefix: &str = longest_prefix_within(chunk.content(), remaining, |prefix| {
+                    count_tokens(
+                        0,
+                        &Chunk::new_with_metadata(prefix, chunk.metadata().clone()),
+                    )
+                });
+                if !prefix.is_empty() {
+                    fitted.push(Chunk::new_with_metadata(prefix, chunk.metadata().clone()));
+                }
+            }
+            break;
+        }
+        fitted
+    }
+}
+
+/// # [`RetrievalLimit`]
+///
+/// How many supporting chunks a chain includes in the prompt, either a fixed top_k
+/// or as many as fit in a [`ContextBudget`]. A [`NonZeroU32`] converts into a top_k
+/// so existing calls with a top_k are unchanged.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum RetrievalLimit {
+    TopK(NonZeroU32),
+    Budget(ContextBudget),
+}
+
+impl RetrievalLimit {
+    /// # [`RetrievalLimit::fetch_k`]
+    ///
+    /// # Returns
+    /// * [`NonZeroU32`] - the number of chunks to retrieve.
+    pub fn fetch_k(&self) -> NonZeroU32 {
+        match self {
+            RetrievalLimit::TopK(top_k) => *top_k,
+            RetrievalLimit::Budget(budget) => budget.fetch_k(),
+        }
+    }
+}
+
+impl From<NonZeroU32> for RetrievalLimit {
+    fn from(top_k: NonZeroU32) -> Self {
+        RetrievalLimit::TopK(top_k)
+    }
+}
+
+impl From<ContextBudget> for RetrievalLimit {
+    fn from(budget: ContextBudget) -> Self {
+        RetrievalLimit::Budget(budget)
+    }
+}
+
+/// Binary searches the char boundaries for the longest prefix within the token limit,
+/// token counts only grow as text is added so the search is sound.
+fn longest_prefix_within(
+    text: &str,
+    max_tokens: usize,
+    count_tokens: impl Fn(&str) -> usize,
+) -> &str {
+    let boundaries: Vec<usize> = text
+        .char_indices()
+        .map(|(index, _)| index)
+        .skip(1)
+        .chain(std::iter::once(text.len()))
+        .collect();
+    let fitting: usize =
+        boundaries.partition_point(|&end| count_tokens(&text[..end]) <= max_tokens);
+    match fitting {
+        0 => "",
+        fitting => &text[..boundaries[fitting - 1]],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    // One token per character keeps the sizes easy to follow
+    fn chars(text: &str) -> usize {
+        text.chars().count()
+    }
+
+    fn chunk_chars(_: usize, chunk: &Chunk) -> usize {
+        chars(chunk.content())
+    }
+
+    fn chunks(sizes: &[usize]) -> Chunks {
+        sizes
+            .iter()
+            .enumerate()
+            .map(|(i, size)| Chunk::new(i.to_string().repeat(*size)))
+            .collect()
+    }
+
+    fn sizes(chunks: &Chunks) -> Vec<usize> {
+        chunks.iter().map(|chunk| chars(chunk.content())).collect()
+    }
+
+    #[test]
+    fn stops_at_the_first_chunk_that_does_not_fit() {
+        let budget = ContextBudget::new(20);
+        // 5 + 4 + 6 = 15 leaves 5, the 7 does not fit and the 1 after it is not considered
+        let fitted = budget.fit(chunks(&[4, 6, 7, 1]), 5, chunk_chars);
+        assert_eq!(sizes(&fitted), vec![4, 6]);
+    }
+
+    #[test]
+    fn chunks_exactly_filling_the_budget_are_included() {
+        let budget = ContextBudget::new(10);
+        let fitted = budget.fit(chunks(&[3, 4]), 3, chunk_chars);
+        assert_eq!(sizes(&fitted), vec![3, 4]);
+    }
+
+    #[test]
+    fn first_chunk_alone_exceeding_budget_is_truncated() {
+        let budget = ContextBudget::new(10);
+        let chunk = Chunk::new_with_metadata("abcdefghij", json!({"source": "a"}));
+        let fitted = budget.fit(vec![chunk, Chunk::new("k")], 4, chunk_chars);
+        assert_eq!(
+            fitted,
+            vec![Chunk::new_with_metadata("abcdef", json!({"source": "a"}))]
+        );
+    }
+
+    #[test]
+    fn nothing_is_included_when_the_prompt_uses_the_budget() {
+        let budget = ContextBudget::new(10);
+        assert!(budget.fit(chunks(&[1, 1]), 10, chunk_chars).is_empty());
+        assert!(budget.fit(chunks(&[1, 1]), 12, chunk_chars).is_empty());
+        assert!(budget.fit(Vec::new(), 0, chunk_chars).is_empty());
+    }
+
+    #[test]
+    fn truncation_respects_char_boundaries() {
+        // Every byte is a token so a multi byte char is never split
+        let bytes = |text: &str| text.len();
+        assert_eq!(longest_prefix_within("aéb", 2, bytes), "a");
+        assert_eq!(longest_prefix_within("aéb", 3, bytes), "aé");
+        assert_eq!(longest_prefix_within("éb", 1, bytes), "");
+    }
+
+    #[test]
+    fn top_k_converts_into_a_limit() {
+        let top_k = NonZeroU32::new(3).unwrap();
+        assert_eq!(RetrievalLimit::from(top_k).fetch_k(), top_k);
+        let budget = ContextBudget::new(100);
+        assert_eq!(
+            RetrievalLimit::from(budget).fetch_k(),
+            ContextBudget::DEFAULT_FETCH_K
+        );
+    }
+}
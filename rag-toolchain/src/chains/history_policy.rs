@@ -0,0 +1,384 @@
+use crate::clients::{count_message_tokens, PromptMessage, REPLY_PRIMING_TOKENS};
+use std::iter::once;
+use std::num::NonZeroUsize;
+
+/// # [`HistoryPolicy`]
+///
+/// Defines how much of the conversation a [`crate::chains::ChatHistoryChain`] resends with
+/// each invocation, so a long conversation does not outgrow the model's context window.
+/// The system prompt is always sent and messages are dropped oldest first as whole
+/// human / AI pairs. Dropped messages are removed from the history as well.
+///
+/// * [`HistoryPolicy::Unbounded`] - the whole conversation is resent every time.
+/// * [`HistoryPolicy::SlidingWindow`] - at most the last N messages of the conversation are
+///   sent along with the new user message. An odd N is rounded down to whole pairs.
+/// * [`HistoryPolicy::TokenBudget`] - the oldest pairs are dropped until the system prompt,
+///   the conversation and the new user message fit within the number of tokens. If the system
+///   prompt and user message do not fit on their own they are still sent without any history.
+///   The tokens are estimated with [`crate::clients::count_prompt_tokens`] so include the
+///   overhead of each message.
+/// * [`HistoryPolicy::Summarize`] - once the system prompt, the conversation and the new user
+///   message no longer fit within `budget_tokens` the chat client is asked to summarize all but
+///   the last `keep_last_n` messages. They are replaced in the history by a single
+///   [`PromptMessage::SystemMessage`] starting with [`SUMMARY_PREFIX`], which is summarized
+///   again along with the next oldest messages when the budget is next exceeded. An odd
+///   `keep_last_n` is rounded down to whole pairs. If the summary can not be generated the
+///   messages are dropped instead and the chain reports a [`crate::chains::ChainWarning`].
+///   A [`crate::chains::StreamedChatHistoryChain`] can not summarize so always drops them.
+///
+/// # Examples
+/// ```
+/// use rag_toolchain::chains::*;
+/// use rag_toolchain::clients::*;
+///
+/// async fn run_chain() {
+///     let system_prompt = PromptMessage::SystemMessage("You are a helpful assistant".into());
+///     let client = OpenAIChatCompletionClient::try_new(OpenAIModel::Gpt3Point5Turbo).unwrap();
+///     let chain = ChatHistoryChain::new_with_policy(client, system_prompt, HistoryPolicy::TokenBudget(6000));
+/// }
+/// ```
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum HistoryPolicy {
+    #[default]
+    Unbounded,
+    SlidingWindow(NonZeroUsize),
+    TokenBudget(usize),
+    Summarize {
+        budget_tokens: usize,
+        keep_last_n: usize,
+    },
+}
+
+/// The start of the message which replaces the summarized part of the conversation
+pub const SUMMARY_PREFIX: &str = "Summary of earlier conversation: ";
+
+/// The prompt asking the chat client to summarize the conversation, `{{conversation}}`
+/// is replaced with a transcript of the messages being summarized
+const SUMMARY_PROMPT: &str = "Summarize the conversation below so it can be carried on without \
+    it. Keep any names, facts, decisions and open questions and leave out small talk. Reply \
+    with only the summary.\n\n{{conversation}}";
+
+impl HistoryPolicy {
+    /// # [`HistoryPolicy::first_kept`]
+    ///
+    /// Works out how much of the conversation fits the policy. The conversation is made up
+    /// of human / AI pairs so the index returned is always the start of a pair.
+    ///
+    /// # Arguments
+    /// * `history`: &[`[PromptMessage]`] - the conversation without the system prompt.
+    /// * `reserved_tokens`: [`usize`] - the tokens used by the messages which are always sent.
+    /// * `count_tokens`: impl [`Fn(&str) -> usize`] - counts the tokens in text, each message
+    ///   is counted with [`count_message_tokens`].
+    ///
+    /// # Returns
+    /// * [`usize`] - the index of the first message to keep, the history's length if none fit.
+    pub(crate) fn first_kept(
+        &self,
+        history: &[PromptMessage],
+        reserved_tokens: usize,
+        count_tokens: impl Fn(&str) -> usize,
+    ) -> usize {
+        match self {
+            // The summary keeps the conversation within the budget so nothing is dropped here
+            HistoryPolicy::Unbounded | HistoryPolicy::Summarize { .. } => 0,
+            HistoryPolicy::SlidingWindow(window) => {
+                let start: usize = history.len().saturating_sub(window.get());
+                (start + start % 2).min(history.len())
+            }
+            HistoryPolicy::TokenBudget(budget) => {
+                let tokens: Vec<usize> = history
+                    .iter()
+                    .map(|message| count_message_tokens(message, &count_tokens))
+                    .collect();
+                let mut total: usize = reserved_tokens + tokens.iter().sum::<usize>();
+                let mut start: usize = 0;
+                while total > *budget && start < history.len() {
+                    total -= tokens[start..(start + 2).min(history.len())]
+                        .iter()
+                        .sum::<usize>();
+                    start += 2;
+                }
+                start.min(history.len())
+            }
+        }
+    }
+
+    /// # [`HistoryPolicy::first_kept_in_history`]
+    ///
+    /// The same as [`HistoryPolicy::first_kept`] for a chain's history, which starts with the
+    /// system prompt. The system prompt is always kept along with the user message if there is one.
+    ///
+    /// # Arguments
+    /// * `history`: &[`[PromptMessage]`] - the history including the system prompt.
+    /// * `system_prompt`: &[`PromptMessage`] - the system prompt which will be sent.
+    /// * `user_message`: [`Option<&PromptMessage>`] - the user message which will be sent.
+    /// * `count_tokens`: impl [`Fn(&str) -> usize`] - counts the tokens in text.
+    ///
+    /// # Returns
+    /// * [`usize`] - the index in the history of the first message to keep, never the system prompt.
+    pub(crate) fn first_kept_in_history(
+        &self,
+        history: &[PromptMessage],
+        system_prompt: &PromptMessage,
+        user_message: Option<&PromptMessage>,
+        count_tokens: impl Fn(&str) -> usize,
+    ) -> usize {
+        // Only a token budget needs the messages which are always sent counted
+        let reserved_tokens: usize = match self {
+            HistoryPolicy::TokenBudget(_) => {
+                once(system_prompt)
+                    .chain(user_message)
+                    .map(|message| count_message_tokens(message, &count_tokens))
+                    .sum::<usize>()
+                    + REPLY_PRIMING_TOKENS
+            }
+            _ => 0,
+        };
+        1 + self.first_kept(&history[1..], reserved_tokens, count_tokens)
+    }
+}
+
+impl HistoryPolicy {
+    /// # [`HistoryPolicy::summarized_until`]
+    ///
+    /// Works out which messages a [`HistoryPolicy::Summarize`] would summarize. They always
+    /// start after the system prompt and include any earlier summary.
+    ///
+    /// # Arguments
+    /// * `history`: &[`[PromptMessage]`] - the history including the system prompt.
+    /// * `system_prompt`: &[`PromptMessage`] - the system prompt which will be sent.
+    /// * `user_message`: &[`PromptMessage`] - the user message which will be sent.
+    /// * `count_tokens`: impl [`Fn(&str) -> usize`] - counts the tokens in text.
+    ///
+    /// # Returns
+    /// * [`Option<usize>`] - the index in the history after the last message to summarize, or
+    ///   [`None`] if the policy does not summarize, the history fits the budget or there is
+    ///   nothing but an earlier summary before the messages which are kept.
+    pub(crate) fn summarized_until(
+        &self,
+        history: &[PromptMessage],
+        system_prompt: &PromptMessage,
+        user_message: &PromptMessage,
+        count_tokens: impl Fn(&str) -> usize,
+    ) -> Option<usize> {
+        let HistoryPolicy::Summarize {
+            budget_tokens,
+            keep_last_n,
+        } = self
+        else {
+            return None;
+        };
+        let total: usize = once(system_prompt)
+            .chain(history.iter().skip(1))
+            .chain(once(user_message))
+            .map(|message| count_message_tokens(message, &count_tokens))
+            .sum::<usize>()
+            + REPLY_PRIMING_TOKENS;
+        if total <= *budget_tokens {
+            return None;
+        }
+        // The pairs of the conversation start after the earlier summary if there is one
+        let start: usize = if history.get(1).is_some_and(is_summary) {
+            2
+        } else {
+            1
+        };
+        let kept_from: usize = history.len().saturating_sub(*keep_last_n).max(start);
+        let end: usize = (kept_from + (kept_from - start) % 2).min(history.len());
+        (end > start).then_some(end)
+    }
+}
+
+/// # [`is_summary`]
+///
+/// # Returns
+/// * [`bool`] - whether the message is a summary of earlier conversation.
+pub(crate) fn is_summary(message: &PromptMessage) -> bool {
+    matches!(message, PromptMessage::SystemMessage(_))
+        && message.content().starts_with(SUMMARY_PREFIX)
+}
+
+/// # [`summary_prompt`]
+///
+/// # Arguments
+/// * `messages`: &[`[PromptMessage]`] - the messages to summarize, oldest first.
+///
+/// # Returns
+/// * [`Vec<PromptMessage>`] - the messages asking the chat client for the summary.
+pub(crate) fn summary_prompt(messages: &[PromptMessage]) -> Vec<PromptMessage> {
+    let transcript: Vec<String> = messages
+        .iter()
+        .map(|message| match message {
+            PromptMessage::SystemMessage(_) => message.content().to_string(),
+            PromptMessage::HumanMessage(_) | PromptMessage::MultiModalHumanMessage(_) => {
+                format!("User: {}", message.text())
+            }
+            PromptMessage::AIMessage(_) => format!("Assistant: {}", message.content()),
+        })
+        .collect();
+    let prompt: String = SUMMARY_PROMPT.replace("{{conversation}}", &transcript.join("\n"));
+    vec![PromptMessage::HumanMessage(prompt.into())]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unbounded_keeps_everything() {
+        let history: Vec<PromptMessage> = conversation(3);
+        assert_eq!(
+            HistoryPolicy::Unbounded.first_kept(&history, 1000, word_count),
+            0
+        );
+    }
+
+    #[test]
+    fn sliding_window_keeps_whole_pairs() {
+        let history: Vec<PromptMessage> = conversation(3);
+        assert_eq!(window(4).first_kept(&history, 0, word_count), 2);
+        // An odd window would start on an AI message so it is rounded down
+        assert_eq!(window(3).first_kept(&history, 0, word_count), 4);
+        assert_eq!(window(1).first_kept(&history, 0, word_count), 6);
+        assert_eq!(window(10).first_kept(&history, 0, word_count), 0);
+    }
+
+    #[test]
+    fn token_budget_drops_oldest_pairs_until_it_fits() {
+        // Each message is 5 tokens with its role and overhead, so reserving 3 leaves room
+        // for 2 pairs in a budget of 31
+        let history: Vec<PromptMessage> = conversation(3);
+        let policy: HistoryPolicy = HistoryPolicy::TokenBudget(31);
+        assert_eq!(policy.first_kept(&history, 3, word_count), 2);
+        assert_eq!(policy.first_kept(&history, 1, word_count), 0);
+    }
+
+    #[test]
+    fn token_budget_drops_everything_when_reserved_exceeds_budget() {
+        let history: Vec<PromptMessage> = conversation(2);
+        let policy: HistoryPolicy = HistoryPolicy::TokenBudget(5);
+        assert_eq!(policy.first_kept(&history, 10, word_count), 4);
+        assert_eq!(policy.first_kept(&[], 10, word_count), 0);
+    }
+
+    #[test]
+    fn token_budget_reserves_the_messages_always_sent() {
+        let history: Vec<PromptMessage> = with_system_prompt(conversation(2));
+        let user_message = PromptMessage::HumanMessage("next".into());
+        // The system prompt and user message are 5 tokens each and the reply priming 3
+        let first_kept = |budget: usize| {
+            HistoryPolicy::TokenBudget(budget).first_kept_in_history(
+                &history,
+                &history[0],
+                Some(&user_message),
+                word_count,
+            )
+        };
+        assert_eq!(first_kept(33), 1);
+        assert_eq!(first_kept(32), 3);
+        assert_eq!(first_kept(13), 5);
+    }
+
+    #[test]
+    fn summarize_keeps_everything_in_the_window() {
+        let history: Vec<PromptMessage> = conversation(3);
+        assert_eq!(summarize(1, 2).first_kept(&history, 100, word_count), 0);
+    }
+
+    #[test]
+    fn summarize_picks_all_but_the_last_pairs_once_over_budget() {
+        let history: Vec<PromptMessage> = with_system_prompt(conversation(3));
+        let system_prompt = &history[0];
+        let user_message = PromptMessage::HumanMessage("next".into());
+        // The system prompt, 6 messages and the user message are 5 tokens each with their role
+        // and overhead, with the reply priming they are 43 tokens
+        assert_eq!(
+            summarize(43, 2).summarized_until(&history, system_prompt, &user_message, word_count),
+            None
+        );
+        assert_eq!(
+            summarize(42, 2).summarized_until(&history, system_prompt, &user_message, word_count),
+            Some(5)
+        );
+        // An odd number kept is rounded down to whole pairs
+        assert_eq!(
+            summarize(42, 3).summarized_until(&history, system_prompt, &user_message, word_count),
+            Some(5)
+        );
+        assert_eq!(
+            summarize(42, 0).summarized_until(&history, system_prompt, &user_message, word_count),
+            Some(7)
+        );
+        assert_eq!(
+            summarize(42, 6).summarized_until(&history, system_prompt, &user_message, word_count),
+            None
+        );
+        assert_eq!(
+            window(2).summarized_until(&history, system_prompt, &user_message, word_count),
+            None
+        );
+    }
+
+    #[test]
+    fn an_earlier_summary_is_summarized_again_with_the_next_pairs() {
+        let summary =
+            PromptMessage::SystemMessage(format!("{}they said hi", SUMMARY_PREFIX).into());
+        let mut history: Vec<PromptMessage> = with_system_prompt(conversation(2));
+        history.insert(1, summary);
+        let system_prompt = &history[0];
+        let user_message = PromptMessage::HumanMessage("next".into());
+        assert_eq!(
+            summarize(1, 2).summarized_until(&history, system_prompt, &user_message, word_count),
+            Some(4)
+        );
+        // Only the summary comes before the kept messages so there is nothing new to summarize
+        assert_eq!(
+            summarize(1, 4).summarized_until(&history, system_prompt, &user_message, word_count),
+            None
+        );
+    }
+
+    #[test]
+    fn summary_prompt_holds_a_transcript() {
+        let prompt = summary_prompt(&conversation(1));
+        assert_eq!(prompt.len(), 1);
+        assert!(prompt[0]
+            .content()
+            .ends_with("\n\nUser: question0\nAssistant: answer0"));
+    }
+
+    fn summarize(budget_tokens: usize, keep_last_n: usize) -> HistoryPolicy {
+        HistoryPolicy::Summarize {
+            budget_tokens,
+            keep_last_n,
+        }
+    }
+
+    fn with_system_prompt(conversation: Vec<PromptMessage>) -> Vec<PromptMessage> {
+        once(PromptMessage::SystemMessage("system".into()))
+            .chain(conversation)
+            .collect()
+    }
+
+    fn window(size: usize) -> HistoryPolicy {
+        HistoryPolicy::SlidingWindow(NonZeroUsize::new(size).unwrap())
+    }
+
+    fn word_count(text: &str) -> usize {
+        text.split_whitespace().count()
+    }
+
+    // A conversation of the given number of exchanges where every message is one word
+    fn conversation(exchanges: usize) -> Vec<PromptMessage> {
+        (0..exchanges)
+            .flat_map(|i| {
+                [
+                    PromptMessage::HumanMessage(format!("question{}", i).into()),
+                    PromptMessage::AIMessage(format!("answer{}", i).into()),
+                ]
+            })
+            .collect()
+    }
+}
@@ -5,11 +5,37 @@
 /// hood for you.
 mod basic_rag_chain;
 mod chat_history_chain;
+mod chunk_post_processor;
+mod context_budget;
+mod history_policy;
+mod moderation_policy;
+mod neighbor_expansion;
+mod prompt_template;
+mod prompt_variables;
+mod reranking;
+mod timeouts;
+mod timings;
 mod types;
 mod utils;
 
 pub use basic_rag_chain::{
     BasicRAGChain, BasicRAGChainBuilder, BasicStreamedRAGChain, BasicStreamedRAGChainBuilder,
 };
-pub use chat_history_chain::ChatHistoryChain;
-pub use types::{ChainError, RagChainError};
+pub use chat_history_chain::{
+    ChatHistoryChain, ChatHistoryStream, ConcurrencyMode, StreamedChatHistoryChain,
+};
+pub use chunk_post_processor::{ChunkPostProcessor, DeduplicateBySimilarity, GroupByMetadataKey};
+pub use context_budget::{ContextBudget, RetrievalLimit};
+pub use history_policy::{HistoryPolicy, SUMMARY_PREFIX};
+pub use moderation_policy::ModerationPolicy;
+pub use neighbor_expansion::NeighborExpansion;
+pub use prompt_template::PromptTemplate;
+pub use prompt_variables::{PromptVariables, UnresolvedVariableMode};
+pub use reranking::ChainReranker;
+pub use timeouts::{ChainPhase, ChainTimeouts};
+pub use timings::{TimedCompletionStream, Timings};
+pub use types::{
+    ChainError, ChainResponse, ChainWarning, PromptVariableError, RagChainError, RagResponse,
+    StreamedRagResponse,
+};
+pub use utils::substitute_variables;
@@ -0,0 +1,246 @@
+use crate::chains::{ChainError, RagChainError};
+use crate::clients::{ContentPart, ModerationError, ModerationVerdict, Moderator, PromptMessage};
+use futures::future::try_join_all;
+use std::fmt::{self, Debug, Formatter};
+use std::sync::Arc;
+
+/// # [`ModerationPolicy`]
+///
+/// Runs the user message through a [`Moderator`] before a chain does anything else with it.
+/// A flagged message is refused with the categories it was flagged for, so nothing is
+/// retrieved for it and it is never sent to the chat client or added to the history.
+/// If the moderator fails the invocation fails with its error rather than letting the
+/// message through unchecked.
+///
+/// # Examples
+/// ```
+/// use rag_toolchain::chains::*;
+/// use rag_toolchain::clients::*;
+///
+/// fn with_moderation(chat_client: OpenAIChatCompletionClient) -> ChatHistoryChain<OpenAIChatCompletionClient> {
+///     let moderator = OpenAIModerationClient::try_new().unwrap();
+///     let system_prompt = PromptMessage::SystemMessage("You are a helpful assistant".into());
+///     ChatHistoryChain::new(chat_client, system_prompt)
+///         .with_moderation_policy(ModerationPolicy::new(moderator))
+/// }
+/// ```
+#[derive(Clone)]
+pub struct ModerationPolicy {
+    moderator: Arc<dyn Moderator>,
+}
+
+impl ModerationPolicy {
+    /// # [`ModerationPolicy::new`]
+    ///
+    /// # Arguments
+    /// * `moderator`: impl [`Moderator`] - checks each user message.
+    ///
+    /// # Returns
+    /// * [`ModerationPolicy`] - the policy.
+    pub fn new(moderator: impl Moderator + 'static) -> Self {
+        ModerationPolicy {
+            moderator: Arc::new(moderator),
+        }
+    }
+
+    /// Moderates each text part of the message on its own, so a part which is flagged is not
+    /// diluted by the others. A message with no text is still checked so the moderator decides
+    /// what an empty message means. The message is refused with the categories of every
+    /// flagged part.
+    pub(crate) async fn check(&self, message: &PromptMessage) -> Result<(), ModerationRefusal> {
+        let verdicts: Vec<ModerationVerdict> = try_join_all(
+            text_parts(message)
+                .into_iter()
+                .map(|text| self.moderator.moderate(text)),
+        )
+        .await
+        .map_err(ModerationRefusal::Failed)?;
+        let mut categories: Vec<String> = Vec::new();
+        for verdict in verdicts.iter().filter(|verdict| verdict.flagged) {
+            for category in &verdict.categories {
+                if !categories.contains(category) {
+                    categories.push(category.clone());
+                }
+            }
+        }
+        match verdicts.iter().any(|verdict| verdict.flagged) {
+            true => Err(ModerationRefusal::Flagged(categories)),
+            false => Ok(()),
+        }
+    }
+}
+
+/// The text parts of a message, a message without any is a single empty part
+fn text_parts(message: &PromptMessage) -> Vec<&str> {
+    let parts: Vec<&str> = match message {
+        PromptMessage::MultiModalHumanMessage(parts) => parts
+            .iter()
+            .filter_map(|part| match part {
+                ContentPart::Text(text) => Some(text.as_str()),
+                ContentPart::Image(_) => None,
+            })
+            .collect(),
+        _ => vec![message.content()],
+    };
+    match parts.is_empty() {
+        true => vec![""],
+        false => parts,
+    }
+}
+
+impl Debug for ModerationPolicy {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ModerationPolicy").finish_non_exhaustive()
+    }
+}
+
+/// Moderators have no general notion of equality, two policies are only equal if they
+/// share the same moderator. This lets chains holding them still be compared.
+impl PartialEq for ModerationPolicy {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.moderator, &other.moderator)
+    }
+}
+
+/// Why a [`ModerationPolicy`] stopped a message, converted into the error of the chain
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum ModerationRefusal {
+    Flagged(Vec<String>),
+    Failed(ModerationError),
+}
+
+impl<T, U> From<ModerationRefusal> for RagChainError<T, U>
+where
+    T: std::error::Error,
+    U: std::error::Error,
+{
+    fn from(refusal: ModerationRefusal) -> Self {
+        match refusal {
+            ModerationRefusal::Flagged(categories) => RagChainError::ContentFlagged(categories),
+            ModerationRefusal::Failed(error) => RagChainError::ModerationError(error),
+        }
+    }
+}
+
+impl<T> From<ModerationRefusal> for ChainError<T>
+where
+    T: std::error::Error,
+{
+    fn from(refusal: ModerationRefusal) -> Self {
+        match refusal {
+            ModerationRefusal::Flagged(categories) => ChainError::ContentFlagged(categories),
+            ModerationRefusal::Failed(error) => ChainError::ModerationError(error),
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::clients::{ImageSource, ModerationFuture};
+
+    /// Flags any text containing one of the words, with the word as the category
+    pub(crate) struct BlockList(pub Vec<&'static str>);
+
+    impl Moderator for BlockList {
+        fn moderate<'a>(&'a self, text: &'a str) -> ModerationFuture<'a> {
+            Box::pin(async move {
+                let matched: Vec<String> = self
+                    .0
+                    .iter()
+                    .filter(|word| text.contains(*word))
+                    .map(|word| word.to_string())
+                    .collect();
+                Ok(ModerationVerdict::new(matched))
+            })
+        }
+    }
+
+    /// Always fails, standing in for a moderation service which is down
+    pub(crate) struct Unavailable;
+
+    impl Moderator for Unavailable {
+        fn moderate<'a>(&'a self, _text: &'a str) -> ModerationFuture<'a> {
+            Box::pin(async { Err(ModerationError("service unavailable".into())) })
+        }
+    }
+
+    #[tokio::test]
+    async fn check_refuses_flagged_messages_with_their_categories() {
+        let policy = ModerationPolicy::new(BlockList(vec!["attack", "weapon"]));
+        let message = PromptMessage::HumanMessage("how do I attack with a weapon".into());
+        assert_eq!(
+            policy.check(&message).await,
+            Err(ModerationRefusal::Flagged(vec![
+                "attack".into(),
+                "weapon".into()
+            ]))
+        );
+    }
+
+    /// Flags only text which is exactly the word, so the parts have to be moderated apart
+    struct Exactly(&'static str);
+
+    impl Moderator for Exactly {
+        fn moderate<'a>(&'a self, text: &'a str) -> ModerationFuture<'a> {
+            let categories: Vec<String> = match text == self.0 {
+                true => vec![self.0.to_string()],
+                false => Vec::new(),
+            };
+            Box::pin(async move { Ok(ModerationVerdict::new(categories)) })
+        }
+    }
+
+    #[tokio::test]
+    async fn check_moderates_every_text_part_on_its_own() {
+        let policy = ModerationPolicy::new(Exactly("attack"));
+        let message = PromptMessage::MultiModalHumanMessage(vec![
+            ContentPart::Text("what is in this picture".into()),
+            ContentPart::Image(ImageSource::Url("https://example.com/image.png".into())),
+            ContentPart::Text("attack".into()),
+            ContentPart::Text("attack".into()),
+        ]);
+        assert_eq!(
+            policy.check(&message).await,
+            Err(ModerationRefusal::Flagged(vec!["attack".into()]))
+        );
+    }
+
+    #[tokio::test]
+    async fn check_moderates_a_message_without_text_as_empty() {
+        let policy = ModerationPolicy::new(BlockList(vec![""]));
+        let message = PromptMessage::MultiModalHumanMessage(vec![ContentPart::Image(
+            ImageSource::Url("https://example.com/image.png".into()),
+        )]);
+        assert_eq!(
+            policy.check(&message).await,
+            Err(ModerationRefusal::Flagged(vec!["".into()]))
+        );
+    }
+
+    #[tokio::test]
+    async fn check_allows_messages_which_are_not_flagged() {
+        let policy = ModerationPolicy::new(BlockList(vec!["attack"]));
+        let message = PromptMessage::HumanMessage("what is the weather like".into());
+        assert_eq!(policy.check(&message).await, Ok(()));
+    }
+
+    #[tokio::test]
+    async fn check_fails_when_the_moderator_fails() {
+        let policy = ModerationPolicy::new(Unavailable);
+        let message = PromptMessage::HumanMessage("hello".into());
+        assert_eq!(
+            policy.check(&message).await,
+            Err(ModerationRefusal::Failed(ModerationError(
+                "service unavailable".into()
+            )))
+        );
+    }
+
+    #[test]
+    fn policies_are_only_equal_when_they_share_a_moderator() {
+        let policy = ModerationPolicy::new(Unavailable);
+        assert_eq!(policy, policy.clone());
+        assert_ne!(policy, ModerationPolicy::new(Unavailable));
+    }
+}
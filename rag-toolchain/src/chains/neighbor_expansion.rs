@@ -0,0 +1,282 @@
+use crate::common::{Chunk, Chunks, ScoredChunk, CHUNK_INDEX_KEY, DOCUMENT_ID_KEY};
+use crate::retrievers::MetadataFilter;
+use serde_json::Value;
+use std::num::NonZeroU32;
+
+/// # [`NeighborExpansion`]
+///
+/// Adds the chunks either side of each retrieved chunk in its original document, so the
+/// prompt holds the surrounding passage rather than a fragment of it, see
+/// [`crate::chains::BasicRAGChainBuilder::neighbor_expansion`]. The neighbours are fetched
+/// with [`crate::retrievers::AsyncRetriever::fetch_by_metadata`] using the document id and
+/// chunk index in the metadata of each chunk, by default under [`DOCUMENT_ID_KEY`] and
+/// [`CHUNK_INDEX_KEY`]. Chunk documents with [`crate::chunkers::Chunker::chunk_document_indexed`]
+/// to stamp the index.
+///
+/// Each retrieved chunk is replaced by its window of chunks in document order, the windows
+/// keep the relevance order of the chunks they were expanded from and a chunk already in an
+/// earlier window is not repeated. A neighbour takes the score of the chunk it was expanded
+/// from. Chunks without both keys are left as they are.
+///
+/// # Examples
+/// ```
+/// use rag_toolchain::chains::*;
+/// use std::num::NonZeroU32;
+///
+/// // One chunk either side, with the path set by the loaders as the document id
+/// let expansion = NeighborExpansion::new(NonZeroU32::new(1).unwrap()).with_document_key("path");
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct NeighborExpansion {
+    window: NonZeroU32,
+    document_key: String,
+    index_key: String,
+}
+
+impl NeighborExpansion {
+    /// # [`NeighborExpansion::new`]
+    ///
+    /// # Arguments
+    /// * `window`: [`NonZeroU32`] - the number of chunks added before and after each chunk.
+    ///
+    /// # Returns
+    /// * [`NeighborExpansion`] - reading the keys [`DOCUMENT_ID_KEY`] and [`CHUNK_INDEX_KEY`].
+    pub fn new(window: NonZeroU32) -> Self {
+        NeighborExpansion {
+            window,
+            document_key: DOCUMENT_ID_KEY.into(),
+            index_key: CHUNK_INDEX_KEY.into(),
+        }
+    }
+
+    /// # [`NeighborExpansion::with_document_key`]
+    ///
+    /// # Arguments
+    /// * `key`: impl [`Into<String>`] - the top level metadata key holding the document id.
+    ///
+    /// # Returns
+    /// * [`NeighborExpansion`] - the expansion with the key set.
+    pub fn with_document_key(mut self, key: impl Into<String>) -> Self {
+        self.document_key = key.into();
+        self
+    }
+
+    /// # [`NeighborExpansion::with_index_key`]
+    ///
+    /// # Arguments
+    /// * `key`: impl [`Into<String>`] - the top level metadata key holding the chunk index.
+    ///
+    /// # Returns
+    /// * [`NeighborExpansion`] - the expansion with the key set.
+    pub fn with_index_key(mut self, key: impl Into<String>) -> Self {
+        self.index_key = key.into();
+        self
+    }
+
+    /// The document id and index of the chunk if its metadata has both
+    fn position<'a>(&self, chunk: &'a Chunk) -> Option<(&'a Value, u64)> {
+        let metadata: &Value = chunk.metadata();
+        let document: &Value = metadata.get(&self.document_key)?;
+        let index: u64 = metadata.get(&self.index_key)?.as_u64()?;
+        Some((document, index))
+    }
+
+    /// # [`NeighborExpansion::neighbor_filter`]
+    ///
+    /// # Returns
+    /// * [`Option<MetadataFilter>`] - matching the window of every chunk which has a position,
+    ///   or `None` if no chunk has one so there is nothing to fetch.
+    pub(crate) fn neighbor_filter<'a>(
+        &self,
+        chunks: impl IntoIterator<Item = &'a Chunk>,
+    ) -> Option<MetadataFilter> {
+        let window: u64 = self.window.get().into();
+        let windows: Vec<MetadataFilter> = chunks
+            .into_iter()
+            .filter_map(|chunk| self.position(chunk))
+            .map(|(document, index)| {
+                MetadataFilter::key(&self.document_key)
+                    .eq(document.clone())
+                    .and(
+                        MetadataFilter::key(&self.index_key)
+                            .gte(index.saturating_sub(window) as f64),
+                    )
+                    .and(
+                        MetadataFilter::key(&self.index_key)
+                            .lte(index.saturating_add(window) as f64),
+                    )
+            })
+            .collect();
+        match windows.is_empty() {
+            true => None,
+            false => Some(MetadataFilter::Or(windows)),
+        }
+    }
+
+    /// # [`NeighborExpansion::expand`]
+    ///
+    /// The same as [`NeighborExpansion::expand_scored`] for chunks without scores.
+    pub(crate) fn expand(&self, chunks: Chunks, neighbors: Chunks) -> Chunks {
+        // The scores are only carried along so they are discarded again
+        let scored: Vec<ScoredChunk> = chunks
+            .into_iter()
+            .map(|chunk| ScoredChunk::new(chunk, 0.0))
+            .collect();
+        self.expand_scored(scored, neighbors)
+            .into_iter()
+            .map(|scored| scored.chunk)
+            .collect()
+    }
+
+    /// # [`NeighborExpansion::expand_scored`]
+    ///
+    /// Replaces each chunk with its window from the fetched neighbours.
+    ///
+    /// # Arguments
+    /// * `scored`: [`Vec<ScoredChunk>`] - the retrieved chunks, most relevant first.
+    /// * `neighbors`: [`Chunks`] - the chunks fetched with [`NeighborExpansion::neighbor_filter`].
+    ///
+    /// # Returns
+    /// * [`Vec<ScoredChunk>`] - the windows of the chunks, most relevant first.
+    pub(crate) fn expand_scored(
+        &self,
+        scored: Vec<ScoredChunk>,
+        neighbors: Chunks,
+    ) -> Vec<ScoredChunk> {
+        let window: u64 = self.window.get().into();
+        let mut expanded: Vec<ScoredChunk> = Vec::with_capacity(scored.len());
+        // The position of every chunk added so far so no chunk is added twice
+        let mut added: Vec<(Value, u64)> = Vec::new();
+        for hit in scored {
+            let Some((document, index)) = self.position(&hit.chunk) else {
+                expanded.push(hit);
+                continue;
+            };
+            let document: Value = document.clone();
+            let range = index.saturating_sub(window)..=index.saturating_add(window);
+            let mut members: Vec<(u64, Chunk)> = neighbors
+                .iter()
+                .filter_map(|chunk| {
+                    let (other, other_index) = self.position(chunk)?;
+                    let in_window: bool = other == &document && range.contains(&other_index);
+                    // The retrieved chunk is used in place of its stored copy
+                    let is_hit: bool = other_index == index;
+                    (in_window && !is_hit).then(|| (other_index, chunk.clone()))
+                })
+                .collect();
+            members.push((index, hit.chunk));
+            members.sort_by_key(|(index, _)| *index);
+            members.dedup_by_key(|(index, _)| *index);
+            for (member_index, chunk) in members {
+                let position: (Value, u64) = (document.clone(), member_index);
+                if added.contains(&position) {
+                    continue;
+                }
+                added.push(position);
+                expanded.push(ScoredChunk::new(chunk, hit.score));
+            }
+        }
+        expanded
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn chunk(document: &str, index: u64) -> Chunk {
+        Chunk::new_with_metadata(
+            format!("{} {}", document, index),
+            json!({"document_id": document, "chunk_index": index}),
+        )
+    }
+
+    fn contents(chunks: &[Chunk]) -> Vec<&str> {
+        chunks.iter().map(|chunk| chunk.content()).collect()
+    }
+
+    fn expansion(window: u32) -> NeighborExpansion {
+        NeighborExpansion::new(NonZeroU32::new(window).unwrap())
+    }
+
+    #[test]
+    fn neighbor_filter_matches_the_window_of_each_chunk() {
+        let filter = expansion(2)
+            .neighbor_filter(&[chunk("a", 1), Chunk::new("no position"), chunk("b", 5)])
+            .unwrap();
+        let matched: Vec<u64> = (0..9)
+            .filter(|index| filter.matches(chunk("a", *index).metadata()))
+            .collect();
+        assert_eq!(matched, vec![0, 1, 2, 3]);
+        let matched: Vec<u64> = (0..9)
+            .filter(|index| filter.matches(chunk("b", *index).metadata()))
+            .collect();
+        assert_eq!(matched, vec![3, 4, 5, 6, 7]);
+        assert!(!filter.matches(chunk("c", 1).metadata()));
+        assert_eq!(expansion(1).neighbor_filter(&[Chunk::new("none")]), None);
+    }
+
+    #[test]
+    fn expand_replaces_each_chunk_with_its_window_in_document_order() {
+        let neighbors: Chunks = (0..6).map(|index| chunk("a", index)).collect();
+        let expanded = expansion(1).expand(vec![chunk("a", 4), chunk("a", 1)], neighbors);
+        assert_eq!(
+            contents(&expanded),
+            vec!["a 3", "a 4", "a 5", "a 0", "a 1", "a 2"]
+        );
+    }
+
+    #[test]
+    fn expand_does_not_repeat_overlapping_windows() {
+        let neighbors: Chunks = (0..6).map(|index| chunk("a", index)).collect();
+        let expanded = expansion(1).expand(vec![chunk("a", 2), chunk("a", 3)], neighbors);
+        assert_eq!(contents(&expanded), vec!["a 1", "a 2", "a 3", "a 4"]);
+    }
+
+    #[test]
+    fn expand_keeps_chunks_without_a_position_or_neighbors() {
+        let expanded = expansion(1).expand(
+            vec![Chunk::new("loose"), chunk("b", 0), chunk("c", 7)],
+            vec![chunk("b", 1)],
+        );
+        assert_eq!(contents(&expanded), vec!["loose", "b 0", "b 1", "c 7"]);
+    }
+
+    #[test]
+    fn expand_scored_gives_neighbors_the_score_of_their_chunk() {
+        let neighbors: Chunks = vec![chunk("a", 0), chunk("a", 2), chunk("b", 8)];
+        let expanded = expansion(1).expand_scored(
+            vec![
+                ScoredChunk::new(chunk("a", 1), 0.9),
+                ScoredChunk::new(chunk("b", 9), 0.4),
+            ],
+            neighbors,
+        );
+        let scores: Vec<(&str, f32)> = expanded
+            .iter()
+            .map(|scored| (scored.chunk.content(), scored.score))
+            .collect();
+        assert_eq!(
+            scores,
+            vec![
+                ("a 0", 0.9),
+                ("a 1", 0.9),
+                ("a 2", 0.9),
+                ("b 8", 0.4),
+                ("b 9", 0.4)
+            ]
+        );
+    }
+
+    #[test]
+    fn keys_can_be_configured() {
+        let path_chunk = |index: u64| {
+            Chunk::new_with_metadata(format!("{}", index), json!({"path": "a.md", "n": index}))
+        };
+        let expansion = expansion(1).with_document_key("path").with_index_key("n");
+        let expanded = expansion.expand(vec![path_chunk(1)], vec![path_chunk(0), path_chunk(2)]);
+        assert_eq!(contents(&expanded), vec!["0", "1", "2"]);
+    }
+}
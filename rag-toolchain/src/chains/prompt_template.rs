@@ -0,0 +1,260 @@
+use crate::chains::{utils::substitute_variables, PromptVariableError, UnresolvedVariableMode};
+use crate::clients::{ContentPart, MessageBody, PromptMessage};
+use crate::common::Chunk;
+use serde_json::Value;
+use std::collections::HashMap;
+
+/// # [`PromptTemplate`]
+///
+/// Controls how a RAG chain combines the user's question with the supporting chunks into the
+/// message sent to the chat client. The template is made of two parts:
+///
+/// * the prompt, where `{{question}}` is replaced with the user's question and `{{context}}`
+///   with the rendered chunks.
+/// * the chunk template, rendered once per chunk and concatenated. `{{content}}` is the text of
+///   the chunk, `{{index}}` its position starting from 1 and `{{metadata.key}}` the value of a
+///   top level key of its metadata. A metadata key the chunk does not have renders as nothing.
+///
+/// The placeholders follow the same rules as [`crate::chains::PromptVariables`], write `\{{` for
+/// literal braces. Placeholders with no value are left as they are and the question and chunks
+/// are never searched for placeholders themselves. Any images in the user's message are kept
+/// after the text.
+///
+/// The default reproduces the original prompt, the question followed by
+/// "Here is some supporting information:" and then each chunk on its own line.
+///
+/// # Examples
+/// ```
+/// use rag_toolchain::chains::*;
+/// use rag_toolchain::clients::*;
+/// use rag_toolchain::common::*;
+/// use serde_json::json;
+///
+/// let template = PromptTemplate::new("Context:\n{{context}}\nAnswer the question: {{question}}")
+///     .with_chunk_template("[{{index}}] ({{metadata.source}}) {{content}}\n");
+/// let question = PromptMessage::HumanMessage("What is the refund policy?".into());
+/// let chunks = vec![Chunk::new_with_metadata("Refunds take 5 days", json!({"source": "faq.md"}))];
+/// let prompt: PromptMessage = template.render(&question, &chunks);
+/// assert_eq!(
+///     prompt.content(),
+///     "Context:\n[1] (faq.md) Refunds take 5 days\n\nAnswer the question: What is the refund policy?"
+/// );
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PromptTemplate {
+    template: String,
+    chunk_template: String,
+}
+
+impl Default for PromptTemplate {
+    fn default() -> Self {
+        PromptTemplate {
+            template: Self::DEFAULT_TEMPLATE.into(),
+            chunk_template: Self::DEFAULT_CHUNK_TEMPLATE.into(),
+        }
+    }
+}
+
+impl PromptTemplate {
+    /// The prompt used unless configured otherwise
+    pub const DEFAULT_TEMPLATE: &'static str =
+        "{{question}}\nHere is some supporting information:\n{{context}}";
+    /// Each chunk on its own line, used unless configured otherwise
+    pub const DEFAULT_CHUNK_TEMPLATE: &'static str = "{{content}}\n";
+
+    /// # [`PromptTemplate::new`]
+    ///
+    /// # Arguments
+    /// * `template`: impl [`Into<String>`] - the prompt containing `{{question}}` and `{{context}}`.
+    ///
+    /// # Returns
+    /// * [`PromptTemplate`] - using [`PromptTemplate::DEFAULT_CHUNK_TEMPLATE`] for each chunk.
+    pub fn new(template: impl Into<String>) -> Self {
+        PromptTemplate {
+            template: template.into(),
+            chunk_template: Self::DEFAULT_CHUNK_TEMPLATE.into(),
+        }
+    }
+
+    /// # [`PromptTemplate::with_chunk_template`]
+    ///
+    /// # Arguments
+    /// * `chunk_template`: impl [`Into<String>`] - rendered for each chunk, see the type level docs
+    ///   for the placeholders. Include a separator such as a newline as chunks are concatenated.
+    ///
+    /// # Returns
+    /// * [`PromptTemplate`] - the template with the new chunk template.
+    pub fn with_chunk_template(mut self, chunk_template: impl Into<String>) -> Self {
+        self.chunk_template = chunk_template.into();
+        self
+    }
+
+    /// # [`PromptTemplate::render`]
+    ///
+    /// Builds the message sent to the chat client from the user's message and the chunks.
+    ///
+    /// # Arguments
+    /// * `user_message`: &[`PromptMessage`] - the user's message, its text is the question.
+    /// * `chunks`: &[`[Chunk]`] - the supporting chunks in the order they appear in the prompt.
+    ///
+    /// # Returns
+    /// * [`PromptMessage`] - a human message with the metadata of the user's, or a multi modal
+    ///   message if the user's had images.
+    pub fn render(&self, user_message: &PromptMessage, chunks: &[Chunk]) -> PromptMessage {
+        let context: String = chunks
+            .iter()
+            .enumerate()
+            .map(|(index, chunk)| self.render_chunk(index, chunk))
+            .collect();
+        let variables: HashMap<String, String> = HashMap::from([
+            ("question".to_string(), user_message.content().to_string()),
+            ("context".to_string(), context),
+        ]);
+        let text: String = substitute_or_leave(&self.template, &variables);
+        match user_message {
+            PromptMessage::MultiModalHumanMessage(parts) => {
+                let images = parts
+                    .iter()
+                    .filter(|part| matches!(part, ContentPart::Image(_)))
+                    .cloned();
+                PromptMessage::MultiModalHumanMessage(
+                    std::iter::once(ContentPart::Text(text))
+                        .chain(images)
+                        .collect(),
+                )
+            }
+            _ => PromptMessage::HumanMessage(MessageBody {
+                content: text,
+                meta: user_message.meta().cloned(),
+            }),
+        }
+    }
+
+    /// # [`PromptTemplate::render_chunk`]
+    ///
+    /// Renders a single chunk with the chunk template.
+    ///
+    /// # Arguments
+    /// * `index`: [`usize`] - the position of the chunk in the prompt starting from 0.
+    /// * `chunk`: &[`Chunk`] - the chunk to render.
+    ///
+    /// # Returns
+    /// * [`String`] - the chunk as it appears in the prompt.
+    pub(crate) fn render_chunk(&self, index: usize, chunk: &Chunk) -> String {
+        let mut variables: HashMap<String, String> = HashMap::from([
+            ("content".to_string(), chunk.content().to_string()),
+            ("index".to_string(), (index + 1).to_string()),
+        ]);
+        if let Value::Object(metadata) = chunk.metadata() {
+            variables.extend(metadata.iter().map(|(key, value)| {
+                let value: String = match value {
+                    Value::String(text) => text.clone(),
+                    other => other.to_string(),
+                };
+                (format!("metadata.{}", key), value)
+            }));
+        }
+        // Metadata keys the chunk does not have render as nothing
+        if let Err(PromptVariableError::Unresolved(names)) = substitute_variables(
+            &self.chunk_template,
+            &variables,
+            UnresolvedVariableMode::Error,
+        ) {
+            variables.extend(
+                names
+                    .into_iter()
+                    .filter(|name| name.starts_with("metadata."))
+                    .map(|name| (name, String::new())),
+            );
+        }
+        substitute_or_leave(&self.chunk_template, &variables)
+    }
+}
+
+fn substitute_or_leave(template: &str, variables: &HashMap<String, String>) -> String {
+    substitute_variables(template, variables, UnresolvedVariableMode::LeaveAsIs)
+        .unwrap_or_else(|_| template.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clients::{ImageSource, MessageMeta};
+    use serde_json::json;
+
+    #[test]
+    fn default_template_gives_original_prompt() {
+        const USER_MESSAGE: &str = "can you explain the data to me";
+        let user_prompt: PromptMessage = PromptMessage::HumanMessage(USER_MESSAGE.into());
+        let chunks = vec![Chunk::new("data point 1"), Chunk::new("data point 2")];
+        let response = PromptTemplate::default().render(&user_prompt, &chunks);
+        let expected_response: &str = "can you explain the data to me\nHere is some supporting information:\ndata point 1\ndata point 2\n";
+        assert!(matches!(response, PromptMessage::HumanMessage(_)));
+        assert_eq!(expected_response, response.content());
+    }
+
+    #[test]
+    fn rendered_message_keeps_the_metadata_of_the_user_message() {
+        let meta = MessageMeta::default().with_name("alice");
+        let user_prompt = PromptMessage::HumanMessage(
+            MessageBody::from("what is the leave policy").with_meta(meta.clone()),
+        );
+        let response = PromptTemplate::new("{{question}}").render(&user_prompt, &[]);
+        assert_eq!(
+            response,
+            PromptMessage::HumanMessage(
+                MessageBody::from("what is the leave policy").with_meta(meta)
+            )
+        );
+    }
+
+    #[test]
+    fn default_template_keeps_images() {
+        let image = ContentPart::Image(ImageSource::Url("https://example.com/a.png".into()));
+        let user_prompt = PromptMessage::MultiModalHumanMessage(vec![
+            ContentPart::Text("what does the screenshot show".into()),
+            image.clone(),
+        ]);
+        let response =
+            PromptTemplate::default().render(&user_prompt, &[Chunk::new("data point 1")]);
+        assert_eq!(
+            response,
+            PromptMessage::MultiModalHumanMessage(vec![
+                ContentPart::Text(
+                    "what does the screenshot show\nHere is some supporting information:\ndata point 1\n"
+                        .into()
+                ),
+                image,
+            ])
+        );
+    }
+
+    #[test]
+    fn custom_template_numbers_chunks_and_injects_metadata() {
+        let template = PromptTemplate::new("Sources:\n{{context}}Question: {{question}}")
+            .with_chunk_template(
+                "{{index}}. {{content}} [{{metadata.source}}, page {{metadata.page}}]\n",
+            );
+        let chunks = vec![
+            Chunk::new_with_metadata("first", json!({"source": "a.md", "page": 3})),
+            Chunk::new("second"),
+        ];
+        let question = PromptMessage::HumanMessage("why?".into());
+        assert_eq!(
+            template.render(&question, &chunks).content(),
+            "Sources:\n1. first [a.md, page 3]\n2. second [, page ]\nQuestion: why?"
+        );
+    }
+
+    #[test]
+    fn question_and_chunks_are_not_substituted() {
+        let template = PromptTemplate::new("{{question}} | {{context}} | {{unknown}}");
+        let question = PromptMessage::HumanMessage("what is {{context}}".into());
+        let chunks = vec![Chunk::new("uses {{question}}")];
+        assert_eq!(
+            template.render(&question, &chunks).content(),
+            "what is {{context}} | uses {{question}}\n | {{unknown}}"
+        );
+    }
+}
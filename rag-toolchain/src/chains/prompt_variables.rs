@@ -0,0 +1,147 @@
+use crate::chains::{utils::substitute_message, PromptVariableError};
+use crate::clients::PromptMessage;
+use std::collections::HashMap;
+use std::fmt::{Debug, Formatter};
+use std::sync::Arc;
+
+/// # [`PromptVariables`]
+///
+/// Values for the `{{name}}` placeholders in a chain's system prompt which are resolved each
+/// time the chain is invoked, for example `{{today}}` or `{{username}}`. The values either come
+/// from a fixed map or from a function which is called once per invocation.
+///
+/// A placeholder can be written as `{{name}}` or `{{ name }}`, names are made of letters,
+/// numbers, `_`, `-` and `.`. Write `\{{` for literal braces, the backslash is removed. What
+/// happens to a placeholder with no value is set by [`UnresolvedVariableMode`].
+///
+/// # Examples
+/// ```
+/// use rag_toolchain::chains::*;
+/// use rag_toolchain::clients::*;
+/// use std::collections::HashMap;
+///
+/// let variables = PromptVariables::from_fn(|| {
+///     HashMap::from([("today".to_string(), "Monday".to_string())])
+/// });
+/// let prompt = PromptMessage::SystemMessage("Today is {{today}}, write \\{{ for braces".into());
+/// let resolved: PromptMessage = variables.substitute(&prompt).unwrap();
+/// assert_eq!(resolved.content(), "Today is Monday, write {{ for braces");
+/// ```
+#[derive(Clone, PartialEq, Eq)]
+pub struct PromptVariables {
+    source: VariableSource,
+    mode: UnresolvedVariableMode,
+}
+
+/// The provider of the values, a function is only equal to itself.
+#[derive(Clone)]
+enum VariableSource {
+    Static(Arc<HashMap<String, String>>),
+    Dynamic(Arc<dyn Fn() -> HashMap<String, String> + Send + Sync>),
+}
+
+/// # [`UnresolvedVariableMode`]
+///
+/// Defines what happens to a placeholder which has no value.
+///
+/// * [`UnresolvedVariableMode::Error`] - the invocation fails with
+///   [`PromptVariableError::Unresolved`] before anything is sent.
+/// * [`UnresolvedVariableMode::LeaveAsIs`] - the placeholder is sent unchanged.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum UnresolvedVariableMode {
+    #[default]
+    Error,
+    LeaveAsIs,
+}
+
+impl PromptVariables {
+    /// # [`PromptVariables::new`]
+    ///
+    /// # Arguments
+    /// * `variables`: [`HashMap<String, String>`] - the same values are used for every invocation.
+    ///
+    /// # Returns
+    /// * [`PromptVariables`] - using [`UnresolvedVariableMode::Error`].
+    pub fn new(variables: HashMap<String, String>) -> Self {
+        PromptVariables {
+            source: VariableSource::Static(Arc::new(variables)),
+            mode: UnresolvedVariableMode::default(),
+        }
+    }
+
+    /// # [`PromptVariables::from_fn`]
+    ///
+    /// # Arguments
+    /// * `provider`: impl Fn() -> [`HashMap<String, String>`] - called once per invocation for
+    ///   the values.
+    ///
+    /// # Returns
+    /// * [`PromptVariables`] - using [`UnresolvedVariableMode::Error`].
+    pub fn from_fn(provider: impl Fn() -> HashMap<String, String> + Send + Sync + 'static) -> Self {
+        PromptVariables {
+            source: VariableSource::Dynamic(Arc::new(provider)),
+            mode: UnresolvedVariableMode::default(),
+        }
+    }
+
+    /// # [`PromptVariables::with_unresolved_mode`]
+    ///
+    /// # Arguments
+    /// * `mode`: [`UnresolvedVariableMode`] - what happens to placeholders with no value.
+    pub fn with_unresolved_mode(mut self, mode: UnresolvedVariableMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// # [`PromptVariables::substitute`]
+    ///
+    /// Resolves the values and substitutes them into the text of the message.
+    ///
+    /// # Arguments
+    /// * `message`: &[`PromptMessage`] - the message containing placeholders.
+    ///
+    /// # Errors
+    /// * [`PromptVariableError::Unresolved`] - in [`UnresolvedVariableMode::Error`] if any
+    ///   placeholder has no value.
+    ///
+    /// # Returns
+    /// * [`PromptMessage`] - the message with the placeholders replaced.
+    pub fn substitute(
+        &self,
+        message: &PromptMessage,
+    ) -> Result<PromptMessage, PromptVariableError> {
+        match &self.source {
+            VariableSource::Static(variables) => substitute_message(message, variables, self.mode),
+            VariableSource::Dynamic(provider) => {
+                substitute_message(message, &provider(), self.mode)
+            }
+        }
+    }
+}
+
+impl Debug for PromptVariables {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let source: &dyn Debug = match &self.source {
+            VariableSource::Static(variables) => variables,
+            VariableSource::Dynamic(_) => &"<fn>",
+        };
+        f.debug_struct("PromptVariables")
+            .field("source", source)
+            .field("mode", &self.mode)
+            .finish()
+    }
+}
+
+impl PartialEq for VariableSource {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (VariableSource::Static(a), VariableSource::Static(b)) => a == b,
+            (VariableSource::Dynamic(a), VariableSource::Dynamic(b)) => Arc::ptr_eq(a, b),
+            _ => false,
+        }
+    }
+}
+
+impl Eq for VariableSource {}
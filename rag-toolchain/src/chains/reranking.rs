@@ -0,0 +1,70 @@
+use crate::common::{DynError, ScoredChunk};
+use crate::retrievers::{AsyncReranker, DynAsyncReranker};
+use std::fmt::{self, Debug, Formatter};
+use std::num::NonZeroU32;
+use std::sync::Arc;
+
+/// # [`ChainReranker`]
+///
+/// Reranks the supporting chunks of a [`crate::chains::BasicRAGChain`] after they are
+/// retrieved and before any minimum score is applied, see
+/// [`crate::chains::BasicRAGChainBuilder::reranker`]. The top_k the chain is invoked with
+/// becomes the number of candidates and at most `top_n` of them are kept, scored by the
+/// reranker.
+#[derive(Clone)]
+pub struct ChainReranker {
+    reranker: Arc<dyn DynAsyncReranker>,
+    top_n: NonZeroU32,
+}
+
+impl ChainReranker {
+    /// # [`ChainReranker::new`]
+    ///
+    /// # Arguments
+    /// * `reranker`: impl [`AsyncReranker`] - reranks the candidates.
+    /// * `top_n`: [`NonZeroU32`] - the number of chunks kept after reranking.
+    ///
+    /// # Returns
+    /// * [`ChainReranker`] - the reranker.
+    pub fn new<K>(reranker: K, top_n: NonZeroU32) -> Self
+    where
+        K: AsyncReranker + 'static,
+        K::ErrorType: 'static,
+    {
+        ChainReranker {
+            reranker: Arc::new(reranker),
+            top_n,
+        }
+    }
+
+    /// Reranks the candidates, an empty set of candidates is never sent to the reranker
+    pub(crate) async fn rerank(
+        &self,
+        query: &str,
+        candidates: Vec<ScoredChunk>,
+    ) -> Result<Vec<ScoredChunk>, DynError> {
+        if candidates.is_empty() {
+            return Ok(candidates);
+        }
+        self.reranker
+            .dyn_rerank(query, candidates, self.top_n)
+            .await
+    }
+}
+
+impl Debug for ChainReranker {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ChainReranker")
+            .field("top_n", &self.top_n)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Rerankers have no general notion of equality, two are only equal if they share the
+/// same reranker and keep the same number of chunks. This lets chains holding them still
+/// be compared.
+impl PartialEq for ChainReranker {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.reranker, &other.reranker) && self.top_n == other.top_n
+    }
+}
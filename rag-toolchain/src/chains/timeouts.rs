@@ -0,0 +1,208 @@
+use std::fmt::{self, Display, Formatter};
+use std::future::Future;
+use std::time::Duration;
+
+/// # [`ChainPhase`]
+///
+/// The part of a chain invocation which ran out of time, see [`ChainTimeouts`].
+///
+/// * [`ChainPhase::Retrieval`] - retrieving the supporting chunks, including their neighbours.
+/// * [`ChainPhase::Chat`] - waiting for the chat client to respond.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum ChainPhase {
+    Retrieval,
+    Chat,
+}
+
+impl Display for ChainPhase {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            ChainPhase::Retrieval => f.write_str("retrieval"),
+            ChainPhase::Chat => f.write_str("chat"),
+        }
+    }
+}
+
+/// # [`ChainTimeouts`]
+///
+/// How long each phase of a chain invocation may take, a phase without a timeout can take
+/// as long as it needs. When a phase runs out of time its future is dropped, which cancels
+/// any request the client or retriever had in flight, and the chain returns a timeout error
+/// naming the [`ChainPhase`]. A [`Duration`] converts into the same timeout for every phase.
+///
+/// # Examples
+/// ```
+/// use rag_toolchain::chains::ChainTimeouts;
+/// use std::time::Duration;
+///
+/// let timeouts: ChainTimeouts = ChainTimeouts::default()
+///     .with_retrieval(Duration::from_secs(2))
+///     .with_chat(Duration::from_secs(30));
+/// ```
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ChainTimeouts {
+    #[cfg_attr(
+        feature = "serde",
+        serde(with = "crate::common::duration_millis::option")
+    )]
+    retrieval: Option<Duration>,
+    #[cfg_attr(
+        feature = "serde",
+        serde(with = "crate::common::duration_millis::option")
+    )]
+    chat: Option<Duration>,
+}
+
+impl ChainTimeouts {
+    /// # [`ChainTimeouts::new`]
+    ///
+    /// # Arguments
+    /// * `timeout`: [`Duration`] - how long each phase may take.
+    ///
+    /// # Returns
+    /// * [`ChainTimeouts`] - the same timeout for every phase.
+    pub fn new(timeout: Duration) -> Self {
+        ChainTimeouts {
+            retrieval: Some(timeout),
+            chat: Some(timeout),
+        }
+    }
+
+    /// # [`ChainTimeouts::with_retrieval`]
+    ///
+    /// # Arguments
+    /// * `timeout`: [`Duration`] - how long retrieving the supporting chunks may take.
+    ///
+    /// # Returns
+    /// * [`ChainTimeouts`] - the timeouts with the new retrieval timeout.
+    pub fn with_retrieval(mut self, timeout: Duration) -> Self {
+        self.retrieval = Some(timeout);
+        self
+    }
+
+    /// # [`ChainTimeouts::with_chat`]
+    ///
+    /// # Arguments
+    /// * `timeout`: [`Duration`] - how long the chat client may take to respond.
+    ///
+    /// # Returns
+    /// * [`ChainTimeouts`] - the timeouts with the new chat timeout.
+    pub fn with_chat(mut self, timeout: Duration) -> Self {
+        self.chat = Some(timeout);
+        self
+    }
+
+    /// # [`ChainTimeouts::retrieval`]
+    ///
+    /// # Returns
+    /// * [`Option<Duration>`] - the retrieval timeout, if there is one.
+    pub fn retrieval(&self) -> Option<Duration> {
+        self.retrieval
+    }
+
+    /// # [`ChainTimeouts::chat`]
+    ///
+    /// # Returns
+    /// * [`Option<Duration>`] - the chat timeout, if there is one.
+    pub fn chat(&self) -> Option<Duration> {
+        self.chat
+    }
+}
+
+impl From<Duration> for ChainTimeouts {
+    fn from(timeout: Duration) -> Self {
+        ChainTimeouts::new(timeout)
+    }
+}
+
+/// # [`within`]
+///
+/// Runs the future of a phase, dropping it if it has not finished within the timeout.
+///
+/// # Arguments
+/// * `timeout`: [`Option<Duration>`] - how long the phase may take, `None` waits for as long as it takes.
+/// * `phase`: [`ChainPhase`] - the phase the future belongs to.
+/// * `future`: `F` - the work of the phase.
+///
+/// # Errors
+/// * [`ChainPhase`] - the phase, if it ran out of time.
+pub(crate) async fn within<F: Future>(
+    timeout: Option<Duration>,
+    phase: ChainPhase,
+    future: F,
+) -> Result<F::Output, ChainPhase> {
+    match timeout {
+        None => Ok(future.await),
+        Some(timeout) => tokio::time::timeout(timeout, future)
+            .await
+            .map_err(|_| phase),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
+    // Records when it is dropped, as a reqwest future cancels its request
+    struct DropFlag(Arc<AtomicBool>);
+
+    impl Drop for DropFlag {
+        fn drop(&mut self) {
+            self.0.store(true, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn a_duration_converts_into_a_timeout_for_every_phase() {
+        let timeouts = ChainTimeouts::from(Duration::from_secs(5));
+        assert_eq!(timeouts.retrieval(), Some(Duration::from_secs(5)));
+        assert_eq!(timeouts.chat(), Some(Duration::from_secs(5)));
+        let timeouts = ChainTimeouts::default().with_chat(Duration::from_secs(1));
+        assert_eq!(timeouts.retrieval(), None);
+        assert_eq!(timeouts.chat(), Some(Duration::from_secs(1)));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn an_expired_phase_is_dropped() {
+        let dropped = Arc::new(AtomicBool::new(false));
+        let flag = DropFlag(dropped.clone());
+        let slow = async move {
+            tokio::time::sleep(Duration::from_secs(60)).await;
+            drop(flag);
+        };
+        let result = within(Some(Duration::from_secs(1)), ChainPhase::Chat, slow).await;
+        assert_eq!(result, Err(ChainPhase::Chat));
+        assert!(dropped.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn a_phase_without_a_timeout_runs_to_completion() {
+        let slow = async {
+            tokio::time::sleep(Duration::from_secs(600)).await;
+            1
+        };
+        assert_eq!(within(None, ChainPhase::Retrieval, slow).await, Ok(1));
+        let fast = async { 2 };
+        assert_eq!(
+            within(Some(Duration::from_secs(1)), ChainPhase::Retrieval, fast).await,
+            Ok(2)
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn timeouts_serialize_as_milliseconds() {
+        let timeouts = ChainTimeouts::default().with_chat(Duration::from_millis(1500));
+        let json = serde_json::to_value(timeouts).unwrap();
+        assert_eq!(json, serde_json::json!({"retrieval": null, "chat": 1500}));
+        assert_eq!(
+            serde_json::from_value::<ChainTimeouts>(json).unwrap(),
+            timeouts
+        );
+    }
+}
@@ -0,0 +1,216 @@
+use crate::clients::ChatCompletionStream;
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// # [`Timings`]
+///
+/// How long each stage of a chain invocation took. A stage the chain does not have,
+/// or has not reached yet, is left as `None`.
+///
+/// * `retrieval` - the time taken to retrieve the supporting chunks.
+/// * `time_to_first_token` - the time from the start of the invocation until the first
+///   token was yielded by the stream, this includes retrieval.
+/// * `generation` - the time from invoking the chat client until the full response was
+///   received, for a stream this is until the stream finishes.
+///
+/// With the `serde` feature the durations are written as whole milliseconds.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Timings {
+    #[cfg_attr(
+        feature = "serde",
+        serde(with = "crate::common::duration_millis::option")
+    )]
+    pub retrieval: Option<Duration>,
+    #[cfg_attr(
+        feature = "serde",
+        serde(with = "crate::common::duration_millis::option")
+    )]
+    pub time_to_first_token: Option<Duration>,
+    #[cfg_attr(
+        feature = "serde",
+        serde(with = "crate::common::duration_millis::option")
+    )]
+    pub generation: Option<Duration>,
+}
+
+/// # [`TimedCompletionStream`]
+///
+/// Wraps the stream returned by a chat client and records when the first token
+/// and the end of the stream were seen. Values are passed through untouched and once
+/// the first token has been seen nothing else is measured until the stream finishes.
+/// A RAG chain also records how many supporting chunks were included in the prompt.
+///
+/// * `T` - The type of the wrapped stream
+#[derive(Debug)]
+pub struct TimedCompletionStream<T>
+where
+    T: ChatCompletionStream,
+{
+    stream: T,
+    started: Instant,
+    generation_started: Instant,
+    timings: Timings,
+    chunks_used: usize,
+    chunks_dropped: usize,
+}
+
+impl<T> TimedCompletionStream<T>
+where
+    T: ChatCompletionStream,
+{
+    /// # [`TimedCompletionStream::new`]
+    ///
+    /// # Arguments
+    /// * `stream`: `T` - the stream returned by the chat client.
+    /// * `started`: [`Instant`] - when the chain invocation started.
+    /// * `generation_started`: [`Instant`] - when the chat client was invoked.
+    /// * `retrieval`: [`Option<Duration>`] - the time taken to retrieve the supporting chunks.
+    pub(crate) fn new(
+        stream: T,
+        started: Instant,
+        generation_started: Instant,
+        retrieval: Option<Duration>,
+    ) -> Self {
+        TimedCompletionStream {
+            stream,
+            started,
+            generation_started,
+            timings: Timings {
+                retrieval,
+                ..Timings::default()
+            },
+            chunks_used: 0,
+            chunks_dropped: 0,
+        }
+    }
+
+    /// # [`TimedCompletionStream::with_chunks`]
+    ///
+    /// # Arguments
+    /// * `chunks_used`: [`usize`] - the number of supporting chunks included in the prompt.
+    /// * `chunks_dropped`: [`usize`] - the number of retrieved chunks left out as they did not fit the context budget.
+    pub(crate) fn with_chunks(mut self, chunks_used: usize, chunks_dropped: usize) -> Self {
+        self.chunks_used = chunks_used;
+        self.chunks_dropped = chunks_dropped;
+        self
+    }
+
+    /// # [`TimedCompletionStream::timings`]
+    ///
+    /// # Returns
+    /// * [`Timings`] - the timings recorded so far, `time_to_first_token` and `generation`
+    ///   are only set once the first token has been read and the stream has finished.
+    pub fn timings(&self) -> Timings {
+        self.timings
+    }
+
+    /// # [`TimedCompletionStream::chunks_used`]
+    ///
+    /// # Returns
+    /// * [`usize`] - the number of supporting chunks included in the prompt.
+    pub fn chunks_used(&self) -> usize {
+        self.chunks_used
+    }
+
+    /// # [`TimedCompletionStream::chunks_dropped`]
+    ///
+    /// # Returns
+    /// * [`usize`] - the number of retrieved chunks left out as they did not fit the context budget.
+    pub fn chunks_dropped(&self) -> usize {
+        self.chunks_dropped
+    }
+
+    /// # [`TimedCompletionStream::into_inner`]
+    ///
+    /// # Returns
+    /// * `T` - the wrapped stream.
+    pub fn into_inner(self) -> T {
+        self.stream
+    }
+}
+
+impl<T> ChatCompletionStream for TimedCompletionStream<T>
+where
+    T: ChatCompletionStream,
+{
+    type ErrorType = T::ErrorType;
+    type Item = T::Item;
+
+    async fn next(&mut self) -> Option<Result<Self::Item, Self::ErrorType>> {
+        let value = self.stream.next().await;
+        match &value {
+            Some(Ok(item)) if self.timings.time_to_first_token.is_none() && T::is_token(item) => {
+                self.timings.time_to_first_token = Some(self.started.elapsed());
+            }
+            None if self.timings.generation.is_none() => {
+                self.timings.generation = Some(self.generation_started.elapsed());
+            }
+            _ => {}
+        }
+        value
+    }
+
+    fn is_token(item: &Self::Item) -> bool {
+        T::is_token(item)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clients::{MockChatCompletionStream, PromptMessage};
+    use tokio::time::sleep;
+
+    #[tokio::test(start_paused = true)]
+    async fn records_first_token_and_end_of_stream() {
+        let mut stream = MockChatCompletionStream::new();
+        let mut values = vec![
+            Some(Ok(PromptMessage::AIMessage("Hello".into()))),
+            Some(Ok(PromptMessage::AIMessage(" world".into()))),
+            None,
+        ]
+        .into_iter();
+        stream
+            .expect_next()
+            .returning(move || values.next().unwrap());
+
+        let started = Instant::now();
+        sleep(Duration::from_millis(20)).await;
+        let mut timed =
+            TimedCompletionStream::new(stream, started, Instant::now(), Some(Duration::ZERO));
+        assert_eq!(timed.timings().time_to_first_token, None);
+
+        sleep(Duration::from_millis(100)).await;
+        timed.next().await.unwrap().unwrap();
+        sleep(Duration::from_millis(50)).await;
+        timed.next().await.unwrap().unwrap();
+        assert_eq!(
+            timed.timings().time_to_first_token,
+            Some(Duration::from_millis(120))
+        );
+        assert_eq!(timed.timings().generation, None);
+
+        assert!(timed.next().await.is_none());
+        assert_eq!(
+            timed.timings(),
+            Timings {
+                retrieval: Some(Duration::ZERO),
+                time_to_first_token: Some(Duration::from_millis(120)),
+                generation: Some(Duration::from_millis(150)),
+            }
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn timings_serialize() {
+        let timings = Timings {
+            retrieval: Some(Duration::from_millis(5)),
+            time_to_first_token: None,
+            generation: Some(Duration::from_secs(1)),
+        };
+        let json = serde_json::to_string(&timings).unwrap();
+        assert_eq!(serde_json::from_str::<Timings>(&json).unwrap(), timings);
+    }
+}
@@ -1,4 +1,102 @@
+use crate::chains::{ChainPhase, Timings};
+use crate::clients::{FinishReason, ModerationError, PromptMessage};
+use crate::common::{ScoredChunk, TokenUsage};
 use thiserror::Error;
+use uuid::Uuid;
+
+/// # [`ChainResponse`]
+///
+/// The response from a chain invoked with an [`crate::common::InvocationContext`].
+/// Along with the message this carries the ids needed to correlate the invocation
+/// with logs, the database and the provider.
+///
+/// * `message` - the response from the chat client.
+/// * `request_id` - the request id from the context the chain was invoked with.
+/// * `provider_request_id` - the id the provider assigned to the request, if it returned one.
+/// * `timings` - how long each stage of the invocation took.
+/// * `chunks_used` - the number of supporting chunks included in the prompt.
+/// * `chunks_dropped` - the number of retrieved chunks left out as they did not fit the context budget.
+/// * `usage` - the tokens the chat client reported using for the request, if it returned them.
+/// * `finish_reason` - why the model stopped generating, if the chat client said.
+/// * `warnings` - anything which went wrong without stopping the chain from answering.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ChainResponse {
+    pub message: PromptMessage,
+    pub request_id: Uuid,
+    pub provider_request_id: Option<String>,
+    pub timings: Timings,
+    pub chunks_used: usize,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub chunks_dropped: usize,
+    pub usage: Option<TokenUsage>,
+    pub finish_reason: Option<FinishReason>,
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Vec::is_empty")
+    )]
+    pub warnings: Vec<ChainWarning>,
+}
+
+impl ChainResponse {
+    /// # [`ChainResponse::is_truncated`]
+    ///
+    /// # Returns
+    /// * [`bool`] - whether the message was cut short by the token limit.
+    pub fn is_truncated(&self) -> bool {
+        self.finish_reason
+            .as_ref()
+            .is_some_and(FinishReason::is_truncated)
+    }
+}
+
+/// # [`ChainWarning`]
+///
+/// Something which went wrong during an invocation which the chain recovered from,
+/// returned in [`ChainResponse::warnings`].
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum ChainWarning {
+    /// The older messages could not be summarized for a
+    /// [`crate::chains::HistoryPolicy::Summarize`] so they were dropped instead.
+    /// This holds the error from the chat client.
+    #[error("The history could not be summarized so older messages were dropped: {0}")]
+    SummaryFailed(String),
+}
+
+/// # [`RagResponse`]
+///
+/// The response from [`crate::chains::BasicRAGChain::invoke_chain_with_sources`], the
+/// message along with the supporting chunks it was grounded on so they can be cited.
+///
+/// * `message` - the response from the chat client.
+/// * `sources` - the chunks included in the prompt with their scores, most relevant first.
+///   When a context budget truncated the first chunk the truncated text is returned.
+/// * `chunks_dropped` - the number of retrieved chunks left out as they did not fit the context budget.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RagResponse {
+    pub message: PromptMessage,
+    pub sources: Vec<ScoredChunk>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub chunks_dropped: usize,
+}
+
+/// # [`StreamedRagResponse`]
+///
+/// The response from [`crate::chains::BasicStreamedRAGChain::invoke_chain_with_sources`].
+/// Retrieval has finished before the stream starts so the sources are available straight away.
+///
+/// * `stream` - the stream returned by the chat client.
+/// * `sources` - the chunks included in the prompt with their scores, most relevant first.
+/// * `chunks_dropped` - the number of retrieved chunks left out as they did not fit the context budget.
+#[derive(Debug)]
+pub struct StreamedRagResponse<S> {
+    pub stream: S,
+    pub sources: Vec<ScoredChunk>,
+    pub chunks_dropped: usize,
+}
 
 /// # [`RagChainError`]
 ///
@@ -18,6 +116,26 @@ where
     ChatClientError(T),
     #[error("Retriever Error: {0}")]
     RetrieverError(U),
+    /// The [`crate::chains::ChainReranker`] failed, this holds its error message
+    #[error("Reranker Error: {0}")]
+    RerankerError(String),
+    #[error("top_k of {requested} is larger than the maximum of {max}")]
+    TopKTooLarge { requested: u32, max: u32 },
+    #[error("Prompt Variable Error: {0}")]
+    PromptVariableError(PromptVariableError),
+    /// The answer hit the token limit, this holds the partial answer.
+    /// Only returned when the chain is built to reject truncated answers.
+    #[error("The answer was cut short by the token limit")]
+    Truncated(PromptMessage),
+    /// The [`crate::chains::ModerationPolicy`] flagged the user message so it was not
+    /// answered, this holds the categories it was flagged for.
+    #[error("The message was flagged by moderation: {}", .0.join(", "))]
+    ContentFlagged(Vec<String>),
+    #[error("Moderation Error: {0}")]
+    ModerationError(ModerationError),
+    /// The phase did not finish within its [`crate::chains::ChainTimeouts`]
+    #[error("The {phase} phase timed out")]
+    Timeout { phase: ChainPhase },
 }
 
 /// # [`ChainError`]
@@ -32,4 +150,26 @@ where
 {
     #[error("Chat Client Error: {0}")]
     ChatClientError(T),
+    #[error("Prompt Variable Error: {0}")]
+    PromptVariableError(PromptVariableError),
+    /// The [`crate::chains::ModerationPolicy`] flagged the user message so it was not
+    /// answered, this holds the categories it was flagged for.
+    #[error("The message was flagged by moderation: {}", .0.join(", "))]
+    ContentFlagged(Vec<String>),
+    #[error("Moderation Error: {0}")]
+    ModerationError(ModerationError),
+    /// The phase did not finish within its [`crate::chains::ChainTimeouts`]
+    #[error("The {phase} phase timed out")]
+    Timeout { phase: ChainPhase },
+}
+
+/// # [`PromptVariableError`]
+///
+/// This enum represents the errors that can occur when resolving the variables
+/// in a prompt, see [`crate::chains::PromptVariables`].
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum PromptVariableError {
+    /// The names of the placeholders which had no value
+    #[error("Unresolved Variables: {}", .0.join(", "))]
+    Unresolved(Vec<String>),
 }
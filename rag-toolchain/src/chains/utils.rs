@@ -1,29 +1,241 @@
-use crate::{clients::PromptMessage, common::Chunks};
+use crate::{
+    chains::{
+        PromptTemplate, PromptVariableError, PromptVariables, RagChainError, RetrievalLimit,
+        UnresolvedVariableMode,
+    },
+    clients::{count_prompt_tokens, ContentPart, MessageBody, PromptMessage},
+    common::Chunks,
+    retrievers::AsyncRetriever,
+};
+use std::collections::HashMap;
+use std::iter::once;
+use std::num::NonZeroU32;
 
 /// There are a number of utility functions that are used in the chains module.
 /// as the number of chains grows we will see specifc patterns emerge and these
 /// will be refactored into this module. Any function that is not taking a reference
 /// to &self should be placed here.
 
-/// # [`build_prompt`]
+/// # [`build_prompts`]
 ///
-/// function to builder the user prompt from the original user prompt and the retrieved
-/// supporting chunks.
+/// function to fit the retrieved chunks to the retrieval limit and build the messages
+/// sent to the chat client. For a [`RetrievalLimit::Budget`] the system prompt, the prompt
+/// template and the user prompt are counted against the budget before any chunks, along with
+/// the overhead of each message, see [`count_prompt_tokens`].
 ///
 /// # Arguments
-/// * `base_message` - the original user prompt
-/// * `chunks` - the supporting chunks retrieved from the retriever
+/// * `system_prompt` - the system prompt of the chain, if it has one
+/// * `prompt_template` - combines the user prompt and the chunks
+/// * `user_message` - the original user prompt
+/// * `chunks` - the supporting chunks retrieved from the retriever, most relevant first
+/// * `limit` - the retrieval limit the chain was invoked with
+/// * `count_tokens` - counts tokens with the tokenizer of the chat client
 ///
 /// # Returns
-/// [`PromptMessage`] - the new user prompt
-pub fn build_prompt(base_message: &PromptMessage, chunks: Chunks) -> PromptMessage {
-    let mut builder: String = String::new();
-    builder.push_str(base_message.content());
-    builder.push_str("\nHere is some supporting information:\n");
-    for chunk in chunks {
-        builder.push_str(&format!("{}\n", chunk.content()))
-    }
-    PromptMessage::HumanMessage(builder)
+/// ([`Vec<PromptMessage>`], [`Chunks`]) - the messages and the chunks included in them,
+/// the first may have been truncated to fit a budget
+pub(crate) fn build_prompts(
+    system_prompt: Option<&PromptMessage>,
+    prompt_template: &PromptTemplate,
+    user_message: &PromptMessage,
+    chunks: Chunks,
+    limit: &RetrievalLimit,
+    count_tokens: impl Fn(&str) -> usize,
+) -> (Vec<PromptMessage>, Chunks) {
+    #[cfg(feature = "tracing")]
+    let retrieved: usize = chunks.len();
+    let chunks: Chunks = match limit {
+        RetrievalLimit::TopK(_) => chunks,
+        RetrievalLimit::Budget(budget) => {
+            let without_chunks: Vec<PromptMessage> = system_prompt
+                .cloned()
+                .into_iter()
+                .chain(once(prompt_template.render(user_message, &[])))
+                .collect();
+            let overhead: usize = count_prompt_tokens(&without_chunks, &count_tokens);
+            budget.fit(chunks, overhead, |index, chunk| {
+                count_tokens(&prompt_template.render_chunk(index, chunk))
+            })
+        }
+    };
+    #[cfg(feature = "tracing")]
+    tracing::debug!(
+        included = chunks.len(),
+        dropped = retrieved - chunks.len(),
+        "packed supporting chunks into the prompt"
+    );
+    let new_prompt: PromptMessage = prompt_template.render(user_message, &chunks);
+    let prompts = match system_prompt.cloned() {
+        None => vec![new_prompt],
+        Some(prompt) => vec![prompt, new_prompt],
+    };
+    (prompts, chunks)
+}
+
+/// # [`validate_top_k`]
+///
+/// function to reject a top_k larger than the retriever allows before any work is done.
+///
+/// # Arguments
+/// * `retriever` - the retriever the chain will search with
+/// * `top_k` - the number of supporting chunks the caller asked for
+///
+/// # Errors
+/// * [`RagChainError::TopKTooLarge`] - if top_k is larger than the retrievers max_top_k
+pub fn validate_top_k<T, U>(
+    retriever: &U,
+    top_k: NonZeroU32,
+) -> Result<(), RagChainError<T, U::ErrorType>>
+where
+    T: std::error::Error,
+    U: AsyncRetriever,
+{
+    match retriever.max_top_k() {
+        Some(max) if top_k > max => Err(RagChainError::TopKTooLarge {
+            requested: top_k.get(),
+            max: max.get(),
+        }),
+        _ => Ok(()),
+    }
+}
+
+/// # [`substitute_variables`]
+///
+/// function to replace the `{{name}}` placeholders in a template with their values. Whitespace
+/// inside the braces is ignored and `\{{` is written out as a literal `{{`. Braces which do not
+/// wrap a valid name, such as JSON examples, are left alone and values are never searched for
+/// further placeholders.
+///
+/// # Arguments
+/// * `template` - the text containing placeholders
+/// * `variables` - the value of each placeholder by name
+/// * `mode` - what happens to placeholders with no value
+///
+/// # Errors
+/// * [`PromptVariableError::Unresolved`] - in [`UnresolvedVariableMode::Error`] with the name of
+///   every placeholder that has no value
+///
+/// # Returns
+/// [`String`] - the template with the placeholders replaced
+pub fn substitute_variables(
+    template: &str,
+    variables: &HashMap<String, String>,
+    mode: UnresolvedVariableMode,
+) -> Result<String, PromptVariableError> {
+    let mut output: String = String::with_capacity(template.len());
+    let mut unresolved: Vec<String> = Vec::new();
+    let mut rest: &str = template;
+    while let Some(start) = rest.find("{{") {
+        if rest[..start].ends_with('\\') {
+            output.push_str(&rest[..start - 1]);
+            output.push_str("{{");
+            rest = &rest[start + 2..];
+            continue;
+        }
+        output.push_str(&rest[..start]);
+        let inner: &str = &rest[start + 2..];
+        let Some(end) = inner.find("}}") else {
+            rest = &rest[start..];
+            break;
+        };
+        let name: &str = inner[..end].trim();
+        if !is_variable_name(name) {
+            output.push_str("{{");
+            rest = inner;
+            continue;
+        }
+        match variables.get(name) {
+            Some(value) => output.push_str(value),
+            None => {
+                if !unresolved.iter().any(|missing| missing == name) {
+                    unresolved.push(name.to_string());
+                }
+                output.push_str(&rest[start..start + end + 4]);
+            }
+        }
+        rest = &inner[end + 2..];
+    }
+    output.push_str(rest);
+    match mode {
+        UnresolvedVariableMode::Error if !unresolved.is_empty() => {
+            Err(PromptVariableError::Unresolved(unresolved))
+        }
+        _ => Ok(output),
+    }
+}
+
+fn is_variable_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_alphanumeric() || matches!(c, '_' | '-' | '.'))
+}
+
+/// # [`substitute_message`]
+///
+/// function to apply [`substitute_variables`] to every piece of text in a message.
+///
+/// # Arguments
+/// * `message` - the message containing placeholders
+/// * `variables` - the value of each placeholder by name
+/// * `mode` - what happens to placeholders with no value
+///
+/// # Errors
+/// * [`PromptVariableError::Unresolved`] - in [`UnresolvedVariableMode::Error`] if any
+///   placeholder has no value
+///
+/// # Returns
+/// [`PromptMessage`] - the message with the placeholders replaced
+pub fn substitute_message(
+    message: &PromptMessage,
+    variables: &HashMap<String, String>,
+    mode: UnresolvedVariableMode,
+) -> Result<PromptMessage, PromptVariableError> {
+    let substitute = |text: &str| substitute_variables(text, variables, mode);
+    // The metadata of the message is kept as it is
+    let substitute_body = |body: &MessageBody| -> Result<MessageBody, PromptVariableError> {
+        Ok(MessageBody {
+            content: substitute(&body.content)?,
+            meta: body.meta.clone(),
+        })
+    };
+    Ok(match message {
+        PromptMessage::SystemMessage(body) => PromptMessage::SystemMessage(substitute_body(body)?),
+        PromptMessage::HumanMessage(body) => PromptMessage::HumanMessage(substitute_body(body)?),
+        PromptMessage::AIMessage(body) => PromptMessage::AIMessage(substitute_body(body)?),
+        PromptMessage::MultiModalHumanMessage(parts) => PromptMessage::MultiModalHumanMessage(
+            parts
+                .iter()
+                .map(|part| match part {
+                    ContentPart::Text(text) => substitute(text).map(ContentPart::Text),
+                    ContentPart::Image(_) => Ok(part.clone()),
+                })
+                .collect::<Result<_, _>>()?,
+        ),
+    })
+}
+
+/// # [`resolve_system_prompt`]
+///
+/// function to resolve the variables in a chain's system prompt for a single invocation.
+///
+/// # Arguments
+/// * `system_prompt` - the system prompt of the chain, if it has one
+/// * `variables` - the prompt variables of the chain, if it has any
+///
+/// # Errors
+/// * [`PromptVariableError::Unresolved`] - if the variables require every placeholder to have a value
+///
+/// # Returns
+/// [`Option<PromptMessage>`] - the system prompt to send
+pub(crate) fn resolve_system_prompt(
+    system_prompt: Option<&PromptMessage>,
+    variables: Option<&PromptVariables>,
+) -> Result<Option<PromptMessage>, PromptVariableError> {
+    match (system_prompt, variables) {
+        (Some(prompt), Some(variables)) => variables.substitute(prompt).map(Some),
+        (prompt, _) => Ok(prompt.cloned()),
+    }
 }
 
 #[cfg(test)]
@@ -33,14 +245,138 @@ mod chains_utils_tests {
     use crate::common::Chunk;
 
     #[test]
-    fn build_prompt_gives_correct_output() {
-        const USER_MESSAGE: &str = "can you explain the data to me";
-        let user_prompt: PromptMessage = PromptMessage::HumanMessage(USER_MESSAGE.into());
-        let chunks = vec![Chunk::new("data point 1"), Chunk::new("data point 2")];
-        let response = build_prompt(&user_prompt, chunks);
-        let expected_response: &str = "can you explain the data to me\nHere is some supporting information:\ndata point 1\ndata point 2\n";
-        println!("{}", expected_response);
-        matches!(response, PromptMessage::HumanMessage(_));
-        assert_eq!(expected_response, response.content());
+    fn build_prompts_fits_chunks_to_budget() {
+        use crate::chains::ContextBudget;
+        let words = |text: &str| text.split_whitespace().count();
+        let system_prompt = PromptMessage::SystemMessage("be brief".into());
+        let user_prompt = PromptMessage::HumanMessage("what is it".into());
+        let chunks = vec![
+            Chunk::new("one two"),
+            Chunk::new("three four"),
+            Chunk::new("five six"),
+        ];
+        // 6 for the system prompt, 12 for the question with the template and 3 for the reply
+        // priming leaves room for two of the chunks
+        let budget: RetrievalLimit = ContextBudget::new(25).into();
+        let template = PromptTemplate::default();
+        let (prompts, included) = build_prompts(
+            Some(&system_prompt),
+            &template,
+            &user_prompt,
+            chunks.clone(),
+            &budget,
+            words,
+        );
+        assert_eq!(included, chunks[..2]);
+        assert_eq!(prompts[0], system_prompt);
+        assert_eq!(prompts[1], template.render(&user_prompt, &chunks[..2]));
+
+        let top_k: RetrievalLimit = NonZeroU32::new(3).unwrap().into();
+        let (prompts, included) =
+            build_prompts(None, &template, &user_prompt, chunks, &top_k, words);
+        assert_eq!(included.len(), 3);
+        assert_eq!(prompts.len(), 1);
+    }
+
+    fn variables() -> HashMap<String, String> {
+        HashMap::from([
+            ("today".to_string(), "Monday".to_string()),
+            ("username".to_string(), "{{today}}".to_string()),
+        ])
+    }
+
+    #[test]
+    fn substitute_variables_resolves_names() {
+        let result = substitute_variables(
+            "Hi {{username}}, today is {{ today }}. Bye {{username}}",
+            &variables(),
+            UnresolvedVariableMode::Error,
+        )
+        .unwrap();
+        // Values are inserted as they are, never substituted themselves
+        assert_eq!(result, "Hi {{today}}, today is Monday. Bye {{today}}");
+    }
+
+    #[test]
+    fn substitute_variables_reports_every_unresolved_name() {
+        let result = substitute_variables(
+            "{{location}} on {{today}} for {{team}} at {{location}}",
+            &variables(),
+            UnresolvedVariableMode::Error,
+        );
+        assert_eq!(
+            result,
+            Err(PromptVariableError::Unresolved(vec![
+                "location".to_string(),
+                "team".to_string()
+            ]))
+        );
+
+        let result = substitute_variables(
+            "{{location}} on {{today}} for {{ team }}",
+            &variables(),
+            UnresolvedVariableMode::LeaveAsIs,
+        );
+        assert_eq!(result.unwrap(), "{{location}} on Monday for {{ team }}");
+    }
+
+    #[test]
+    fn substitute_variables_keeps_escaped_and_non_variable_braces() {
+        let result = substitute_variables(
+            r#"\{{today}} is {{today}}, reply as {{"day": "{{today}}"}} or {{ }} {{today"#,
+            &variables(),
+            UnresolvedVariableMode::Error,
+        )
+        .unwrap();
+        assert_eq!(
+            result,
+            r#"{{today}} is Monday, reply as {{"day": "Monday"}} or {{ }} {{today"#
+        );
+        let result =
+            substitute_variables("{{{today}}}", &variables(), UnresolvedVariableMode::Error);
+        assert_eq!(result.unwrap(), "{{{today}}}");
+        assert_eq!(
+            substitute_variables("", &variables(), UnresolvedVariableMode::Error).unwrap(),
+            ""
+        );
+    }
+
+    #[test]
+    fn substitute_message_replaces_every_text_part() {
+        use crate::clients::ImageSource;
+        let image = ContentPart::Image(ImageSource::Url("https://example.com/a.png".into()));
+        let message = PromptMessage::MultiModalHumanMessage(vec![
+            ContentPart::Text("on {{today}}".into()),
+            image.clone(),
+            ContentPart::Text("and {{today}}".into()),
+        ]);
+        let result =
+            substitute_message(&message, &variables(), UnresolvedVariableMode::Error).unwrap();
+        assert_eq!(
+            result,
+            PromptMessage::MultiModalHumanMessage(vec![
+                ContentPart::Text("on Monday".into()),
+                image,
+                ContentPart::Text("and Monday".into()),
+            ])
+        );
+        let message = PromptMessage::SystemMessage("{{today}}".into());
+        let result =
+            substitute_message(&message, &variables(), UnresolvedVariableMode::Error).unwrap();
+        assert_eq!(result, PromptMessage::SystemMessage("Monday".into()));
+    }
+
+    #[test]
+    fn substitute_message_keeps_the_meta() {
+        use crate::clients::MessageMeta;
+        let meta = MessageMeta::default().with_name("alice");
+        let message =
+            PromptMessage::HumanMessage(MessageBody::from("on {{today}}").with_meta(meta.clone()));
+        let result =
+            substitute_message(&message, &variables(), UnresolvedVariableMode::Error).unwrap();
+        assert_eq!(
+            result,
+            PromptMessage::HumanMessage(MessageBody::from("on Monday").with_meta(meta))
+        );
     }
 }
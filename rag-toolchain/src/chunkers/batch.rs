@@ -0,0 +1,160 @@
+use crate::chunkers::Chunker;
+use crate::common::Chunks;
+use std::error::Error;
+use std::num::NonZeroUsize;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use thiserror::Error;
+
+/// # [`ChunkBatchError`]
+///
+/// Returned by [`Chunker::generate_chunks_batch`] when any of the documents could not be
+/// chunked. Every document is still chunked, so the chunks of the others are kept.
+#[derive(Error, Debug)]
+#[error("{} of {} documents could not be chunked", .failures.len(), .chunks.len())]
+pub struct ChunkBatchError<E: Error> {
+    /// The chunks of each document in input order, empty for the documents which failed
+    pub chunks: Vec<Chunks>,
+    /// The index of each document which failed along with its error, in input order
+    pub failures: Vec<(usize, E)>,
+}
+
+/// # [`chunk_in_parallel`]
+///
+/// Chunks the texts across one thread per available core. Each thread takes the next
+/// text which has not been started, so a few large documents do not hold up the rest.
+/// A panic while chunking is resumed on the calling thread.
+///
+/// # Arguments
+/// * `chunker`: &impl [`Chunker`] - The chunker shared by every thread
+/// * `texts`: &[`String`] - The documents to chunk
+///
+/// # Errors
+/// * [`ChunkBatchError`] - if any of the documents could not be chunked
+///
+/// # Returns
+/// * [`Vec<Chunks>`] - the chunks of each document in input order
+pub(crate) fn chunk_in_parallel<C>(
+    chunker: &C,
+    texts: &[String],
+) -> Result<Vec<Chunks>, ChunkBatchError<C::ErrorType>>
+where
+    C: Chunker + Sync + ?Sized,
+{
+    let workers: usize = thread::available_parallelism()
+        .map(NonZeroUsize::get)
+        .unwrap_or(1)
+        .min(texts.len());
+    let next: AtomicUsize = AtomicUsize::new(0);
+    let mut results: Vec<Option<Result<Chunks, C::ErrorType>>> =
+        (0..texts.len()).map(|_| None).collect();
+
+    thread::scope(|scope| {
+        let handles: Vec<_> = (0..workers)
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut done: Vec<(usize, Result<Chunks, C::ErrorType>)> = Vec::new();
+                    loop {
+                        let index: usize = next.fetch_add(1, Ordering::Relaxed);
+                        let Some(text) = texts.get(index) else {
+                            break;
+                        };
+                        done.push((index, chunker.generate_chunks(text)));
+                    }
+                    done
+                })
+            })
+            .collect();
+        for handle in handles {
+            let done = handle
+                .join()
+                .unwrap_or_else(|panic| std::panic::resume_unwind(panic));
+            for (index, result) in done {
+                results[index] = Some(result);
+            }
+        }
+    });
+
+    let mut chunks: Vec<Chunks> = Vec::with_capacity(texts.len());
+    let mut failures: Vec<(usize, C::ErrorType)> = Vec::new();
+    for (index, result) in results.into_iter().enumerate() {
+        // Every index below the length is taken by exactly one worker
+        match result.expect("every text is chunked") {
+            Ok(document) => chunks.push(document),
+            Err(error) => {
+                chunks.push(Chunks::new());
+                failures.push((index, error));
+            }
+        }
+    }
+    if failures.is_empty() {
+        Ok(chunks)
+    } else {
+        Err(ChunkBatchError { chunks, failures })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunkers::CharacterChunker;
+    use crate::common::Chunk;
+
+    /// Fails on any text containing "bad" so the error handling can be tested
+    struct FailingChunker;
+
+    #[derive(Error, Debug, PartialEq)]
+    #[error("bad document")]
+    struct BadDocument;
+
+    impl Chunker for FailingChunker {
+        type ErrorType = BadDocument;
+        fn chunk_iter<'a>(
+            &'a self,
+            raw_text: &'a str,
+        ) -> impl Iterator<Item = Result<Chunk, Self::ErrorType>> + 'a {
+            match raw_text.contains("bad") {
+                true => std::iter::once(Err(BadDocument)),
+                false => std::iter::once(Ok(Chunk::new(raw_text))),
+            }
+        }
+    }
+
+    #[test]
+    fn batch_keeps_the_input_order() {
+        let chunker = CharacterChunker::try_new(NonZeroUsize::new(3).unwrap(), 0).unwrap();
+        let texts: Vec<String> = (0..200).map(|i| format!("document {}", i)).collect();
+        let batch: Vec<Chunks> = chunker.generate_chunks_batch(&texts).unwrap();
+        let expected: Vec<Chunks> = texts
+            .iter()
+            .map(|text| chunker.generate_chunks(text).unwrap())
+            .collect();
+        assert_eq!(batch, expected);
+    }
+
+    #[test]
+    fn batch_of_no_texts_is_empty() {
+        let chunker = CharacterChunker::try_new(NonZeroUsize::new(3).unwrap(), 0).unwrap();
+        assert_eq!(
+            chunker.generate_chunks_batch(&[]).unwrap(),
+            Vec::<Chunks>::new()
+        );
+    }
+
+    #[test]
+    fn batch_collects_every_failure() {
+        let texts: Vec<String> = vec!["good".into(), "bad".into(), "fine".into(), "bad".into()];
+        let error = FailingChunker.generate_chunks_batch(&texts).unwrap_err();
+        assert_eq!(error.failures, vec![(1, BadDocument), (3, BadDocument)]);
+        assert_eq!(
+            error.chunks,
+            vec![
+                vec![Chunk::new("good")],
+                Vec::new(),
+                vec![Chunk::new("fine")],
+                Vec::new(),
+            ]
+        );
+        assert_eq!(error.to_string(), "2 of 4 documents could not be chunked");
+    }
+}
@@ -1,8 +1,15 @@
 use crate::chunkers::Chunker;
-use crate::common::{Chunk, Chunks};
+use crate::common::Chunk;
+use std::collections::VecDeque;
 use std::convert::Infallible;
 use std::num::NonZeroUsize;
+use std::ops::Range;
+use unicode_segmentation::{GraphemeIndices, UnicodeSegmentation};
 
+/// # [`CharacterChunker`]
+/// This struct does fixed size chunking based on the number of characters in each chunk.
+/// Characters are counted as grapheme clusters, so an emoji, a letter with combining
+/// accents or a CJK character counts as one and is never split between chunks.
 pub struct CharacterChunker {
     /// chunk_size: the number of characters in each chunk
     chunk_size: NonZeroUsize,
@@ -39,25 +46,67 @@ impl CharacterChunker {
 
 impl Chunker for CharacterChunker {
     type ErrorType = Infallible;
-    fn generate_chunks(&self, raw_text: &str) -> Result<Chunks, Self::ErrorType> {
-        let mut chunks: Chunks = Vec::new();
-        let chunk_size: usize = self.chunk_size.into();
-
-        let mut i = 0;
-        while i < raw_text.len() {
-            let end = std::cmp::min(i + chunk_size, raw_text.len());
-            let chunk: Chunk = Chunk::new(&raw_text[i..end]);
-            chunks.push(chunk);
-            i += chunk_size - self.chunk_overlap;
+    /// # [`CharacterChunker::chunk_iter`]
+    /// function to generate chunks from raw text on demand. Only the characters of the
+    /// chunk being built are held, so the text is never split up all at once.
+    ///
+    /// # Arguments
+    /// * `raw_text`: &[`str`] - The raw text to generate chunks from
+    ///
+    /// # Returns
+    /// impl [`Iterator<Item = Result<Chunk, Self::ErrorType>>`] - The generated chunks
+    fn chunk_iter<'a>(
+        &'a self,
+        raw_text: &'a str,
+    ) -> impl Iterator<Item = Result<Chunk, Self::ErrorType>> + 'a {
+        CharacterChunks {
+            raw_text,
+            characters: raw_text.grapheme_indices(true),
+            window: VecDeque::new(),
+            chunk_size: self.chunk_size.into(),
+            step: usize::from(self.chunk_size) - self.chunk_overlap,
         }
+        .map(Ok)
+    }
+}
+
+/// # [`CharacterChunks`]
+/// Walks the characters of the text once, keeping the byte range of each character in
+/// the current chunk. After each chunk the window moves on by the chunk size less the
+/// overlap, so the characters it shares with the next chunk are kept.
+struct CharacterChunks<'a> {
+    raw_text: &'a str,
+    characters: GraphemeIndices<'a>,
+    window: VecDeque<Range<usize>>,
+    chunk_size: usize,
+    step: usize,
+}
+
+impl Iterator for CharacterChunks<'_> {
+    type Item = Chunk;
 
-        Ok(chunks)
+    fn next(&mut self) -> Option<Chunk> {
+        while self.window.len() < self.chunk_size {
+            match self.characters.next() {
+                Some((offset, character)) => {
+                    self.window.push_back(offset..offset + character.len());
+                }
+                None => break,
+            }
+        }
+        let start: usize = self.window.front()?.start;
+        let end: usize = self.window.back()?.end;
+        // Fewer characters than the step are only left at the end of the text
+        let step: usize = self.step.min(self.window.len());
+        self.window.drain(..step);
+        Some(Chunk::new(&self.raw_text[start..end]))
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::common::Document;
 
     #[test]
     fn test_generate_chunks_with_valid_input() {
@@ -95,6 +144,160 @@ mod tests {
         assert_eq!(chunk_strings, Vec::<String>::new());
     }
 
+    #[test]
+    fn test_chunk_iter_is_lazy() {
+        let raw_text: String = "a".repeat(1_000);
+        let chunker: CharacterChunker =
+            CharacterChunker::try_new(NonZeroUsize::new(10).unwrap(), 0).unwrap();
+        let mut produced: usize = 0;
+        let first_chunks: Vec<Chunk> = chunker
+            .chunk_iter(&raw_text)
+            .map(Result::unwrap)
+            .inspect(|_| produced += 1)
+            .take(2)
+            .collect();
+        assert_eq!(first_chunks.len(), 2);
+        assert_eq!(produced, 2);
+        assert_eq!(chunker.generate_chunks(&raw_text).unwrap().len(), 100);
+    }
+
+    #[test]
+    fn test_chunk_iter_matches_splitting_at_once() {
+        let raw_text: String = "hé 👋🏽 日本語\r\n e\u{301}!".repeat(20);
+        for (chunk_size, chunk_overlap) in [(1, 0), (2, 1), (5, 0), (7, 6), (500, 3)] {
+            let chunker: CharacterChunker =
+                CharacterChunker::try_new(NonZeroUsize::new(chunk_size).unwrap(), chunk_overlap)
+                    .unwrap();
+            // How the chunks were built before the characters were walked lazily
+            let boundaries: Vec<usize> = raw_text
+                .grapheme_indices(true)
+                .map(|(offset, _)| offset)
+                .chain(std::iter::once(raw_text.len()))
+                .collect();
+            let characters: usize = boundaries.len() - 1;
+            let expected: Vec<Chunk> = (0..characters)
+                .step_by(chunk_size - chunk_overlap)
+                .map(|i| {
+                    let end = std::cmp::min(i + chunk_size, characters);
+                    Chunk::new(&raw_text[boundaries[i]..boundaries[end]])
+                })
+                .collect();
+            assert_eq!(chunker.generate_chunks(&raw_text).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn test_multi_megabyte_text_is_chunked_lazily() {
+        // Four characters and eleven bytes at a time
+        let raw_text: String = "ab👋🏽 ".repeat(500_000);
+        let chunker: CharacterChunker =
+            CharacterChunker::try_new(NonZeroUsize::new(1_000).unwrap(), 200).unwrap();
+        let mut chunks = chunker.chunk_iter(&raw_text).map(Result::unwrap);
+        assert_eq!(chunks.next().unwrap().content(), &raw_text[..2_750]);
+        assert_eq!(chunks.next().unwrap().content(), &raw_text[2_200..4_950]);
+        // Every 800 characters starts a chunk
+        assert_eq!(chunks.count(), 2_000_000 / 800 - 2);
+    }
+
+    #[test]
+    fn test_generate_chunks_never_splits_emoji() {
+        let raw_text: &str = "hi 👋🏽👨‍👩‍👧!";
+        let chunker: CharacterChunker =
+            CharacterChunker::try_new(NonZeroUsize::new(4).unwrap(), 1).unwrap();
+        let chunks = chunker.generate_chunks(raw_text).unwrap();
+        let chunk_strings: Vec<&str> = chunks.iter().map(|chunk| chunk.content()).collect();
+        assert_eq!(chunk_strings, vec!["hi 👋🏽", "👋🏽👨‍👩‍👧!"]);
+    }
+
+    #[test]
+    fn test_generate_chunks_keeps_combining_characters() {
+        // Each e is followed by a combining acute accent
+        let raw_text: &str = "e\u{301}e\u{301}e\u{301}";
+        let chunker: CharacterChunker =
+            CharacterChunker::try_new(NonZeroUsize::new(2).unwrap(), 0).unwrap();
+        let chunks = chunker.generate_chunks(raw_text).unwrap();
+        let chunk_strings: Vec<&str> = chunks.iter().map(|chunk| chunk.content()).collect();
+        assert_eq!(chunk_strings, vec!["e\u{301}e\u{301}", "e\u{301}"]);
+    }
+
+    #[test]
+    fn test_generate_chunks_with_cjk_text() {
+        let raw_text: &str = "日本語のテキスト";
+        let chunker: CharacterChunker =
+            CharacterChunker::try_new(NonZeroUsize::new(3).unwrap(), 1).unwrap();
+        let chunks = chunker.generate_chunks(raw_text).unwrap();
+        let chunk_strings: Vec<&str> = chunks.iter().map(|chunk| chunk.content()).collect();
+        assert_eq!(chunk_strings, vec!["日本語", "語のテ", "テキス", "スト"]);
+    }
+
+    #[test]
+    fn test_generate_chunks_with_metadata() {
+        let metadata = serde_json::json!({"source": "notes.txt"});
+        let chunker: CharacterChunker =
+            CharacterChunker::try_new(NonZeroUsize::new(4).unwrap(), 0).unwrap();
+        let chunks = chunker
+            .generate_chunks_with_metadata("abcdefgh", metadata.clone())
+            .unwrap();
+        assert_eq!(
+            chunks,
+            vec![
+                Chunk::new_with_metadata("abcd", metadata.clone()),
+                Chunk::new_with_metadata("efgh", metadata),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_chunk_document_carries_its_metadata() {
+        let metadata = serde_json::json!({"path": "notes.txt", "modified": 1700000000});
+        let chunker: CharacterChunker =
+            CharacterChunker::try_new(NonZeroUsize::new(4).unwrap(), 0).unwrap();
+        let document = Document::new_with_metadata("abcdefgh", metadata.clone());
+        assert_eq!(
+            chunker.chunk_document(&document).unwrap(),
+            vec![
+                Chunk::new_with_metadata("abcd", metadata.clone()),
+                Chunk::new_with_metadata("efgh", metadata),
+            ]
+        );
+        let chunks = chunker.chunk_document(&Document::new("abcd")).unwrap();
+        assert_eq!(chunks, vec![Chunk::new("abcd")]);
+    }
+
+    #[test]
+    fn test_chunk_document_indexed_stamps_each_chunk_index() {
+        let metadata = serde_json::json!({"document_id": "notes"});
+        let chunker: CharacterChunker =
+            CharacterChunker::try_new(NonZeroUsize::new(4).unwrap(), 0).unwrap();
+        let document = Document::new_with_metadata("abcdefgh", metadata);
+        let chunks = chunker.chunk_document_indexed(&document).unwrap();
+        assert_eq!(
+            chunks,
+            vec![
+                Chunk::new_with_metadata(
+                    "abcd",
+                    serde_json::json!({"document_id": "notes", "chunk_index": 0})
+                ),
+                Chunk::new_with_metadata(
+                    "efgh",
+                    serde_json::json!({"document_id": "notes", "chunk_index": 1})
+                ),
+            ]
+        );
+        assert_eq!(chunks[1].chunk_index(), Some(1));
+        assert_eq!(chunks[1].document_id(), Some(&serde_json::json!("notes")));
+        let chunks = chunker
+            .chunk_document_indexed(&Document::new("abcd"))
+            .unwrap();
+        assert_eq!(
+            chunks,
+            vec![Chunk::new_with_metadata(
+                "abcd",
+                serde_json::json!({"chunk_index": 0})
+            )]
+        );
+    }
+
     #[test]
     fn test_try_new_with_invalid_arguments() {
         let chunk_overlap: usize = 3;
@@ -0,0 +1,389 @@
+use crate::chunkers::traits::prepared_chunks;
+use crate::chunkers::Chunker;
+use crate::common::{Chunk, EmbeddingModel, EmbeddingModelMetadata, TokenizerWrapper};
+use std::num::NonZeroUsize;
+use std::ops::Range;
+use thiserror::Error;
+
+/// # [`ChunkSizeUnit`]
+/// What the sizes given to a [`ContentDefinedChunker`] are measured in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum ChunkSizeUnit {
+    Characters,
+    Tokens,
+}
+
+/// # [`ContentDefinedChunker`]
+/// This struct places chunk boundaries based on the content of the text rather than at fixed
+/// offsets. A rolling hash (gear hash) is kept over the text and a boundary is placed wherever
+/// the hash matches a pattern, so boundaries depend only on the text just before them. When a
+/// document is edited only the chunks around the edit change and the rest keep the same content,
+/// which means re-indexing only needs to embed the changed chunks.
+///
+/// Chunks are at least `min_size` and at most `max_size` long, averaging around `avg_size`. The
+/// max tokens of the embedding model is always a hard limit, chunks over it are split. As with
+/// [`crate::chunkers::CharacterChunker`] the chunks join back together into the original text.
+///
+/// # Examples
+/// ```
+/// use rag_toolchain::chunkers::*;
+/// use rag_toolchain::common::*;
+/// use std::num::NonZeroUsize;
+///
+/// fn generate_chunks(raw_text: &str) -> Chunks {
+///     let chunker = ContentDefinedChunker::try_new(
+///         128,
+///         NonZeroUsize::new(512).unwrap(),
+///         NonZeroUsize::new(2048).unwrap(),
+///         ChunkSizeUnit::Characters,
+///         OpenAIEmbeddingModel::TextEmbedding3Small,
+///     )
+///     .unwrap();
+///     chunker.generate_chunks(raw_text).unwrap()
+/// }
+/// ```
+pub struct ContentDefinedChunker {
+    /// min_size: No boundary is placed before a chunk is this long
+    min_size: usize,
+    /// max_size: A boundary is always placed once a chunk is this long
+    max_size: usize,
+    /// boundary_bits: The number of top bits of the hash which must be zero for a boundary
+    boundary_bits: u32,
+    /// unit: What the sizes are measured in
+    unit: ChunkSizeUnit,
+    /// max_tokens: The maximum number of tokens the embedding model accepts
+    max_tokens: usize,
+    /// tokenizer: The tokenizer of the embedding model
+    tokenizer: Box<dyn TokenizerWrapper>,
+}
+
+impl ContentDefinedChunker {
+    /// # [`ContentDefinedChunker::try_new`]
+    ///
+    /// # Arguments
+    /// * `min_size`: [`usize`] - The minimum size of each chunk, only the last chunk can be smaller
+    /// * `avg_size`: [`NonZeroUsize`] - The size chunks should average
+    /// * `max_size`: [`NonZeroUsize`] - The maximum size of each chunk
+    /// * `unit`: [`ChunkSizeUnit`] - Whether the sizes are in characters or tokens
+    /// * `embedding_model`: impl [`EmbeddingModel`] - The embedding model the chunks are for, this tells
+    ///   us what tokenizer to use and the maximum tokens in a chunk
+    ///
+    /// # Errors
+    /// * [`ContentDefinedChunkingError::InvalidChunkSize`] - The sizes must satisfy
+    ///   min_size <= avg_size <= max_size, and in tokens max_size can not be more than the
+    ///   embedding model allows
+    ///
+    /// # Returns
+    /// * [`ContentDefinedChunker`] - The chunker
+    pub fn try_new(
+        min_size: usize,
+        avg_size: NonZeroUsize,
+        max_size: NonZeroUsize,
+        unit: ChunkSizeUnit,
+        embedding_model: impl EmbeddingModel,
+    ) -> Result<Self, ContentDefinedChunkingError> {
+        let metadata: EmbeddingModelMetadata = embedding_model.metadata();
+        let (avg_size, max_size): (usize, usize) = (avg_size.into(), max_size.into());
+        if min_size > avg_size || avg_size > max_size {
+            Err(ContentDefinedChunkingError::InvalidChunkSize(
+                "Sizes must satisfy min_size <= avg_size <= max_size".to_string(),
+            ))?
+        }
+        if unit == ChunkSizeUnit::Tokens && max_size > metadata.max_tokens {
+            Err(ContentDefinedChunkingError::InvalidChunkSize(format!(
+                "Max size must be smaller than {}",
+                metadata.max_tokens
+            )))?
+        }
+        // Past min_size a boundary is expected every 2^boundary_bits units
+        let expected_gap: usize = (avg_size - min_size).max(2);
+        Ok(ContentDefinedChunker {
+            min_size,
+            max_size,
+            boundary_bits: expected_gap.ilog2(),
+            unit,
+            max_tokens: metadata.max_tokens,
+            tokenizer: metadata.tokenizer,
+        })
+    }
+
+    /// # [`ContentDefinedChunker::boundaries`]
+    /// Splits the units into chunks, each range is the units in a chunk.
+    fn boundaries<'a>(&self, units: impl Iterator<Item = &'a str>) -> Vec<Range<usize>> {
+        let mut ranges: Vec<Range<usize>> = Vec::new();
+        let mut hash: u64 = 0;
+        let mut start: usize = 0;
+        let mut end: usize = 0;
+        for unit in units {
+            // The hash is never reset so a boundary only depends on the last 64 units
+            hash = (hash << 1).wrapping_add(gear(unit));
+            end += 1;
+            let size: usize = end - start;
+            let at_pattern: bool = hash >> (u64::BITS - self.boundary_bits) == 0;
+            if (size >= self.min_size && at_pattern) || size >= self.max_size {
+                ranges.push(start..end);
+                start = end;
+            }
+        }
+        if start < end {
+            ranges.push(start..end);
+        }
+        ranges
+    }
+
+    /// # [`ContentDefinedChunker::tokenize`]
+    fn tokenize(&self, text: &str) -> Result<Vec<String>, ContentDefinedChunkingError> {
+        self.tokenizer.tokenize(text).ok_or_else(|| {
+            ContentDefinedChunkingError::TokenizationError("Unable to tokenize text".to_string())
+        })
+    }
+
+    /// # [`ContentDefinedChunker::enforce_max_tokens`]
+    /// Splits a chunk into pieces of at most the embedding model's max tokens.
+    fn enforce_max_tokens(&self, text: &str) -> Result<Vec<String>, ContentDefinedChunkingError> {
+        let tokens: Vec<String> = self.tokenize(text)?;
+        if tokens.len() <= self.max_tokens {
+            return Ok(vec![text.to_string()]);
+        }
+        Ok(tokens
+            .chunks(self.max_tokens)
+            .map(|piece| piece.concat())
+            .collect())
+    }
+}
+
+impl ContentDefinedChunker {
+    /// Splits the text into the content of each chunk
+    fn pieces(&self, raw_text: &str) -> Result<Vec<String>, ContentDefinedChunkingError> {
+        let pieces: Vec<String> = match self.unit {
+            ChunkSizeUnit::Characters => {
+                let offsets: Vec<usize> = raw_text
+                    .char_indices()
+                    .map(|(offset, _)| offset)
+                    .chain(std::iter::once(raw_text.len()))
+                    .collect();
+                let units = offsets.windows(2).map(|pair| &raw_text[pair[0]..pair[1]]);
+                let mut pieces: Vec<String> = Vec::new();
+                for range in self.boundaries(units) {
+                    let text: &str = &raw_text[offsets[range.start]..offsets[range.end]];
+                    pieces.extend(self.enforce_max_tokens(text)?);
+                }
+                pieces
+            }
+            ChunkSizeUnit::Tokens => {
+                let tokens: Vec<String> = self.tokenize(raw_text)?;
+                self.boundaries(tokens.iter().map(String::as_str))
+                    .into_iter()
+                    .map(|range| tokens[range].concat())
+                    .collect()
+            }
+        };
+        Ok(pieces)
+    }
+}
+
+impl Chunker for ContentDefinedChunker {
+    type ErrorType = ContentDefinedChunkingError;
+    /// # [`ContentDefinedChunker::chunk_iter`]
+    /// function to generate chunks from raw text. The boundaries are found up front
+    /// and each chunk is built when it is asked for.
+    ///
+    /// # Arguments
+    /// * `raw_text`: &[`str`] - The raw text to generate chunks from
+    ///
+    /// # Errors
+    /// * [`ContentDefinedChunkingError::TokenizationError`] - Unable to tokenize text
+    ///
+    /// # Returns
+    /// impl [`Iterator<Item = Result<Chunk, Self::ErrorType>>`] - The generated chunks
+    fn chunk_iter<'a>(
+        &'a self,
+        raw_text: &'a str,
+    ) -> impl Iterator<Item = Result<Chunk, Self::ErrorType>> + 'a {
+        prepared_chunks(
+            self.pieces(raw_text)
+                .map(|pieces| pieces.into_iter().map(Chunk::new)),
+        )
+    }
+}
+
+/// # [`gear`]
+/// The random value a unit adds to the rolling hash. FNV-1a of the unit is mixed
+/// with the splitmix64 finalizer so every bit of the result depends on the unit.
+fn gear(unit: &str) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in unit.bytes() {
+        hash ^= u64::from(byte);
+        hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+    hash = (hash ^ (hash >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    hash = (hash ^ (hash >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    hash ^ (hash >> 31)
+}
+
+/// # [`ContentDefinedChunkingError`]
+/// Custom error type representing errors that can occur during content defined chunking
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum ContentDefinedChunkingError {
+    #[error("{0}")]
+    InvalidChunkSize(String),
+    #[error("{0}")]
+    TokenizationError(String),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunkers::CharacterChunker;
+    use crate::common::Chunks;
+    use crate::common::OpenAIEmbeddingModel::TextEmbeddingAda002;
+    use std::collections::HashSet;
+
+    const WORDS: &[&str] = &[
+        "the",
+        "vector",
+        "store",
+        "holds",
+        "embeddings",
+        "for",
+        "each",
+        "chunk",
+        "of",
+        "text",
+        "and",
+        "a",
+        "retriever",
+        "finds",
+        "similar",
+        "content",
+        "when",
+        "asked",
+        "by",
+        "user",
+    ];
+
+    // A deterministic document of paragraphs made of pseudo random words
+    fn paragraph(seed: u64, words: usize) -> String {
+        let mut state: u64 = seed;
+        let mut paragraph: Vec<&str> = Vec::with_capacity(words);
+        for _ in 0..words {
+            state = state
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            paragraph.push(WORDS[(state >> 33) as usize % WORDS.len()]);
+        }
+        paragraph.join(" ") + ".\n\n"
+    }
+
+    fn chunker(min: usize, avg: usize, max: usize, unit: ChunkSizeUnit) -> ContentDefinedChunker {
+        ContentDefinedChunker::try_new(
+            min,
+            NonZeroUsize::new(avg).unwrap(),
+            NonZeroUsize::new(max).unwrap(),
+            unit,
+            TextEmbeddingAda002,
+        )
+        .unwrap()
+    }
+
+    fn contents(chunks: Chunks) -> Vec<String> {
+        chunks
+            .into_iter()
+            .map(|chunk| chunk.content().to_string())
+            .collect()
+    }
+
+    fn unchanged_fraction(before: &[String], after: &[String]) -> f64 {
+        let after: HashSet<&String> = after.iter().collect();
+        let unchanged: usize = before.iter().filter(|chunk| after.contains(chunk)).count();
+        unchanged as f64 / before.len() as f64
+    }
+
+    #[test]
+    fn inserting_a_paragraph_only_changes_nearby_chunks() {
+        let paragraphs: Vec<String> = (0..40).map(|seed| paragraph(seed, 60)).collect();
+        let original: String = paragraphs.concat();
+        let mut edited_paragraphs: Vec<String> = paragraphs.clone();
+        edited_paragraphs.insert(20, paragraph(1000, 60));
+        let edited: String = edited_paragraphs.concat();
+
+        let cdc = chunker(64, 256, 1024, ChunkSizeUnit::Characters);
+        let before: Vec<String> = contents(cdc.generate_chunks(&original).unwrap());
+        let after: Vec<String> = contents(cdc.generate_chunks(&edited).unwrap());
+        assert!(before.len() > 20);
+        assert!(unchanged_fraction(&before, &after) > 0.9);
+
+        // Fixed size chunking shifts every chunk after the insert
+        let fixed = CharacterChunker::try_new(NonZeroUsize::new(256).unwrap(), 0).unwrap();
+        let before: Vec<String> = contents(fixed.generate_chunks(&original).unwrap());
+        let after: Vec<String> = contents(fixed.generate_chunks(&edited).unwrap());
+        assert!(unchanged_fraction(&before, &after) < 0.6);
+    }
+
+    #[test]
+    fn chunks_join_back_into_the_text_within_the_size_limits() {
+        let text: String = (0..20).map(|seed| paragraph(seed, 50)).collect();
+        let chunks: Vec<String> = contents(
+            chunker(32, 128, 256, ChunkSizeUnit::Characters)
+                .generate_chunks(&text)
+                .unwrap(),
+        );
+        assert_eq!(chunks.concat(), text);
+        let (last, rest) = chunks.split_last().unwrap();
+        for chunk in rest {
+            let size: usize = chunk.chars().count();
+            assert!((32..=256).contains(&size), "chunk of {} characters", size);
+        }
+        assert!(last.chars().count() <= 256);
+    }
+
+    #[test]
+    fn sizes_can_be_in_tokens() {
+        let text: String = (0..10).map(|seed| paragraph(seed, 50)).collect();
+        let cdc = chunker(8, 32, 64, ChunkSizeUnit::Tokens);
+        let chunks: Vec<String> = contents(cdc.generate_chunks(&text).unwrap());
+        assert_eq!(chunks.concat(), text);
+        for chunk in chunks.iter().take(chunks.len() - 1) {
+            let tokens: usize = cdc.tokenize(chunk).unwrap().len();
+            assert!((8..=64).contains(&tokens), "chunk of {} tokens", tokens);
+        }
+    }
+
+    #[test]
+    fn chunks_over_the_model_max_tokens_are_split() {
+        // Repeated text never matches the boundary pattern so only max_size would split it
+        let text: String = "a ".repeat(20_000);
+        let cdc = chunker(0, 50_000, 100_000, ChunkSizeUnit::Characters);
+        let chunks: Vec<String> = contents(cdc.generate_chunks(&text).unwrap());
+        assert!(chunks.len() >= 3);
+        assert_eq!(chunks.concat(), text);
+        for chunk in chunks {
+            assert!(cdc.tokenize(&chunk).unwrap().len() <= 8192);
+        }
+    }
+
+    #[test]
+    fn empty_text_has_no_chunks() {
+        let cdc = chunker(1, 4, 8, ChunkSizeUnit::Characters);
+        assert!(cdc.generate_chunks("").unwrap().is_empty());
+    }
+
+    #[test]
+    fn invalid_sizes_are_rejected() {
+        let try_new = |min: usize, avg: usize, max: usize, unit: ChunkSizeUnit| {
+            ContentDefinedChunker::try_new(
+                min,
+                NonZeroUsize::new(avg).unwrap(),
+                NonZeroUsize::new(max).unwrap(),
+                unit,
+                TextEmbeddingAda002,
+            )
+        };
+        assert!(try_new(10, 5, 20, ChunkSizeUnit::Characters).is_err());
+        assert!(try_new(1, 30, 20, ChunkSizeUnit::Characters).is_err());
+        assert!(try_new(1, 100, 10_000, ChunkSizeUnit::Tokens).is_err());
+        assert!(try_new(1, 100, 10_000, ChunkSizeUnit::Characters).is_ok());
+    }
+}
@@ -0,0 +1,544 @@
+use crate::chunkers::traits::prepared_chunks;
+use crate::chunkers::{Chunker, TokenChunker, TokenChunkingError};
+use crate::common::{Chunk, EmbeddingModel, TokenizerWrapper};
+use serde_json::json;
+use std::num::NonZeroUsize;
+use std::ops::Range;
+use thiserror::Error;
+
+/// The metadata key holding the headings of a chunk joined with `" > "`, e.g. `"Installation > Linux"`
+pub const BREADCRUMB_KEY: &str = "breadcrumb";
+/// The metadata key holding the headings of a chunk as an array, outermost first
+pub const HEADINGS_KEY: &str = "headings";
+
+/// # [`HeadingLevel`]
+/// The deepest heading a [`MarkdownChunker`] starts a new section at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum HeadingLevel {
+    H1 = 1,
+    H2 = 2,
+    H3 = 3,
+    H4 = 4,
+    H5 = 5,
+    H6 = 6,
+}
+
+/// # [`MarkdownChunker`]
+/// This struct splits markdown into sections at its headings so each chunk comes from a single
+/// section. Headings deeper than the split level, by default [`HeadingLevel::H3`], stay inside
+/// their section. Lines inside fenced code blocks are never treated as headings. Only ATX
+/// headings (`# Title`) are recognised.
+///
+/// Sections larger than the chunk size are split between their paragraphs and code blocks,
+/// packing as many as fit into each chunk, so a code block is only ever cut when it is larger
+/// than a chunk on its own. Blocks which are too large are split with a [`TokenChunker`].
+///
+/// # Metadata
+/// Every chunk is created with [`Chunk::new_with_metadata`] and the metadata holds the headings
+/// of its section. Text before the first heading has no headings.
+/// * [`BREADCRUMB_KEY`] (`"breadcrumb"`) - the headings joined with `" > "`, e.g. `"Installation > Linux"`
+/// * [`HEADINGS_KEY`] (`"headings"`) - the headings as an array, e.g. `["Installation", "Linux"]`
+///
+/// These keys are stable, so chunks can be looked up by section once they are stored, e.g.
+/// with `MetadataFilter::key("breadcrumb").eq("Installation > Linux")`.
+///
+/// # Examples
+/// ```
+/// use rag_toolchain::chunkers::*;
+/// use rag_toolchain::common::*;
+/// use std::num::NonZeroUsize;
+///
+/// fn generate_chunks(raw_text: &str) -> Chunks {
+///     let chunker: MarkdownChunker = MarkdownChunker::try_new(
+///         NonZeroUsize::new(512).unwrap(),
+///         32,
+///         OpenAIEmbeddingModel::TextEmbedding3Small,
+///     )
+///     .unwrap()
+///     .with_split_level(HeadingLevel::H2);
+///     chunker.generate_chunks(raw_text).unwrap()
+/// }
+/// ```
+pub struct MarkdownChunker {
+    /// chunk_size: The most tokens in each chunk
+    chunk_size: NonZeroUsize,
+    /// split_level: The deepest heading which starts a new section
+    split_level: HeadingLevel,
+    /// token_chunker: Splits blocks which are too large on their own
+    token_chunker: TokenChunker,
+    /// tokenizer: The type of tokenizer
+    tokenizer: Box<dyn TokenizerWrapper>,
+}
+
+impl MarkdownChunker {
+    /// # [`MarkdownChunker::try_new`]
+    ///
+    /// # Arguments
+    /// * `chunk_size`: [`NonZeroUsize`] - The most tokens in each chunk
+    /// * `chunk_overlap`: [`usize`] - The number of tokens that overlap when a block is split
+    ///   with the [`TokenChunker`]
+    /// * `embedding_model`: impl [`EmbeddingModel`] - The embedding model to use, this tells us what tokenizer
+    ///   to use
+    ///
+    /// # Errors
+    /// * [`MarkdownChunkingError::TokenChunkingError`] - The chunk size is larger than the embedding model
+    ///   allows or the overlap is not smaller than the chunk size
+    ///
+    /// # Returns
+    /// * [`MarkdownChunker`] - The markdown chunker
+    pub fn try_new(
+        chunk_size: NonZeroUsize,
+        chunk_overlap: usize,
+        embedding_model: impl EmbeddingModel,
+    ) -> Result<Self, MarkdownChunkingError> {
+        let token_chunker = TokenChunker::try_new(chunk_size, chunk_overlap, &embedding_model)?;
+        Ok(MarkdownChunker {
+            chunk_size,
+            split_level: HeadingLevel::H3,
+            token_chunker,
+            tokenizer: embedding_model.metadata().tokenizer,
+        })
+    }
+
+    /// # [`MarkdownChunker::with_split_level`]
+    ///
+    /// # Arguments
+    /// * `split_level`: [`HeadingLevel`] - The deepest heading which starts a new section
+    ///
+    /// # Returns
+    /// * [`MarkdownChunker`] - The chunker with the split level set
+    pub fn with_split_level(mut self, split_level: HeadingLevel) -> Self {
+        self.split_level = split_level;
+        self
+    }
+
+    /// # [`MarkdownChunker::count_tokens`]
+    /// The number of tokens in the text.
+    fn count_tokens(&self, text: &str) -> Result<usize, MarkdownChunkingError> {
+        self.tokenizer
+            .tokenize(text)
+            .map(|tokens| tokens.len())
+            .ok_or_else(|| {
+                MarkdownChunkingError::TokenizationError("Unable to tokenize text".to_string())
+            })
+    }
+
+    /// # [`MarkdownChunker::split_section`]
+    /// Splits a section which is too large between its blocks, blocks which are too
+    /// large on their own are split on tokens.
+    ///
+    /// # Arguments
+    /// * `section`: &[`str`] - The text of the section
+    ///
+    /// # Errors
+    /// * [`MarkdownChunkingError::TokenizationError`] - Unable to tokenize text
+    ///
+    /// # Returns
+    /// * [`Vec<String>`] - The text of each chunk
+    fn split_section(&self, section: &str) -> Result<Vec<String>, MarkdownChunkingError> {
+        let chunk_size: usize = self.chunk_size.get();
+        let mut chunks: Vec<String> = Vec::new();
+        let mut current: Option<Range<usize>> = None;
+        for block in split_blocks(section) {
+            if let Some(range) = &current {
+                if self.count_tokens(&section[range.start..block.end])? <= chunk_size {
+                    current = Some(range.start..block.end);
+                    continue;
+                }
+                chunks.push(section[range.clone()].to_string());
+                current = None;
+            }
+            if self.count_tokens(&section[block.clone()])? <= chunk_size {
+                current = Some(block);
+                continue;
+            }
+            for piece in self.token_chunker.chunk_iter(&section[block]) {
+                chunks.push(piece?.content().to_string());
+            }
+        }
+        chunks.extend(current.map(|range| section[range].to_string()));
+        Ok(chunks)
+    }
+
+    /// Splits each section of the markdown into chunks carrying its headings
+    fn section_chunks(&self, raw_text: &str) -> Result<Vec<Chunk>, MarkdownChunkingError> {
+        let mut chunks: Vec<Chunk> = Vec::new();
+        for section in split_sections(raw_text, self.split_level) {
+            if !section.has_body {
+                continue;
+            }
+            let text: &str = raw_text[section.range].trim();
+            let contents: Vec<String> = if self.count_tokens(text)? <= self.chunk_size.get() {
+                vec![text.to_string()]
+            } else {
+                self.split_section(text)?
+            };
+            let metadata = json!({
+                BREADCRUMB_KEY: section.headings.join(" > "),
+                HEADINGS_KEY: section.headings,
+            });
+            chunks.extend(
+                contents
+                    .into_iter()
+                    .filter(|content| !content.trim().is_empty())
+                    .map(|content| Chunk::new_with_metadata(content.trim(), metadata.clone())),
+            );
+        }
+        Ok(chunks)
+    }
+}
+
+impl Chunker for MarkdownChunker {
+    type ErrorType = MarkdownChunkingError;
+    /// # [`MarkdownChunker::chunk_iter`]
+    /// function to generate chunks from raw markdown. The sections are split up front
+    /// and the chunks are handed out in order.
+    ///
+    /// # Arguments
+    /// * `raw_text`: &[`str`] - The raw markdown to generate chunks from
+    ///
+    /// # Errors
+    /// * [`MarkdownChunkingError::TokenizationError`] - Unable to tokenize text
+    ///
+    /// # Returns
+    /// impl [`Iterator<Item = Result<Chunk, Self::ErrorType>>`] - The generated chunks
+    fn chunk_iter<'a>(
+        &'a self,
+        raw_text: &'a str,
+    ) -> impl Iterator<Item = Result<Chunk, Self::ErrorType>> + 'a {
+        prepared_chunks(self.section_chunks(raw_text))
+    }
+}
+
+/// A section of markdown and the headings it sits under
+struct Section {
+    headings: Vec<String>,
+    range: Range<usize>,
+    /// Whether there is anything other than the heading in the section
+    has_body: bool,
+}
+
+/// # [`split_sections`]
+/// Splits the markdown at each heading up to the split level.
+///
+/// # Arguments
+/// * `text`: &[`str`] - The markdown to split
+/// * `split_level`: [`HeadingLevel`] - The deepest heading which starts a new section
+///
+/// # Returns
+/// * [`Vec<Section>`] - The sections in the order they appear
+fn split_sections(text: &str, split_level: HeadingLevel) -> Vec<Section> {
+    let mut sections: Vec<Section> = Vec::new();
+    let mut stack: Vec<(usize, String)> = Vec::new();
+    let mut current = Section {
+        headings: Vec::new(),
+        range: 0..0,
+        has_body: false,
+    };
+    let mut fence: Option<String> = None;
+    for (offset, line) in lines(text) {
+        if update_fence(&mut fence, line) {
+            current.has_body = true;
+            continue;
+        }
+        match parse_heading(line).filter(|(level, _)| *level <= split_level as usize) {
+            Some((level, title)) if fence.is_none() => {
+                current.range.end = offset;
+                sections.push(current);
+                stack.retain(|(outer, _)| *outer < level);
+                stack.push((level, title));
+                current = Section {
+                    headings: stack.iter().map(|(_, title)| title.clone()).collect(),
+                    range: offset..offset,
+                    has_body: false,
+                };
+            }
+            _ => current.has_body |= !line.trim().is_empty(),
+        }
+    }
+    current.range.end = text.len();
+    sections.push(current);
+    sections
+}
+
+/// # [`split_blocks`]
+/// Splits markdown into paragraphs at blank lines, keeping each fenced code block whole.
+///
+/// # Arguments
+/// * `text`: &[`str`] - The markdown to split
+///
+/// # Returns
+/// * [`Vec<Range<usize>>`] - The range of each block in the text
+fn split_blocks(text: &str) -> Vec<Range<usize>> {
+    let mut blocks: Vec<Range<usize>> = Vec::new();
+    let mut start: usize = 0;
+    let mut fence: Option<String> = None;
+    for (offset, line) in lines(text) {
+        let fence_changed: bool = update_fence(&mut fence, line);
+        if fence.is_none() && !fence_changed && line.trim().is_empty() {
+            push_block(text, start..offset, &mut blocks);
+            start = offset + line.len();
+        }
+    }
+    push_block(text, start..text.len(), &mut blocks);
+    blocks
+}
+
+/// Trims the trailing whitespace from the range and keeps it when anything is left
+fn push_block(text: &str, range: Range<usize>, blocks: &mut Vec<Range<usize>>) {
+    let end: usize = range.start + text[range.clone()].trim_end().len();
+    if !text[range.start..end].trim().is_empty() {
+        blocks.push(range.start..end);
+    }
+}
+
+/// Each line of the text with the offset it starts at, the line ending is kept
+fn lines(text: &str) -> impl Iterator<Item = (usize, &str)> {
+    text.split_inclusive('\n').scan(0, |offset, line| {
+        let start: usize = *offset;
+        *offset += line.len();
+        Some((start, line))
+    })
+}
+
+/// Opens or closes a fenced code block, returns whether the line is a fence
+fn update_fence(fence: &mut Option<String>, line: &str) -> bool {
+    let line: &str = line.trim();
+    let marker: String = match line.chars().next() {
+        Some(first @ ('`' | '~')) => line.chars().take_while(|c| *c == first).collect(),
+        _ => String::new(),
+    };
+    let is_fence: bool = marker.len() >= 3;
+    match fence {
+        None if is_fence => *fence = Some(marker),
+        // A fence is closed by the same character at least as many times with nothing after it
+        Some(open) if is_fence && marker.starts_with(open.as_str()) && line == marker => {
+            *fence = None
+        }
+        _ => return false,
+    }
+    true
+}
+
+/// Parses an ATX heading into its level and title
+fn parse_heading(line: &str) -> Option<(usize, String)> {
+    let indent: usize = line.len() - line.trim_start_matches(' ').len();
+    if indent > 3 {
+        return None;
+    }
+    let line: &str = line.trim();
+    let level: usize = line
+        .chars()
+        .take_while(|character| *character == '#')
+        .count();
+    let rest: &str = &line[level..];
+    if !(1..=6).contains(&level) || !(rest.is_empty() || rest.starts_with([' ', '\t'])) {
+        return None;
+    }
+    let title: &str = rest.trim().trim_end_matches('#').trim_end();
+    Some((level, title.to_string()))
+}
+
+/// # [`MarkdownChunkingError`]
+/// Custom error type representing errors that can occur during markdown chunking
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum MarkdownChunkingError {
+    #[error("{0}")]
+    TokenizationError(String),
+    #[error(transparent)]
+    TokenChunkingError(#[from] TokenChunkingError),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::Document;
+    use crate::common::OpenAIEmbeddingModel::TextEmbeddingAda002;
+    use serde_json::Value;
+
+    fn chunker(chunk_size: usize) -> MarkdownChunker {
+        MarkdownChunker::try_new(
+            NonZeroUsize::new(chunk_size).unwrap(),
+            0,
+            TextEmbeddingAda002,
+        )
+        .unwrap()
+    }
+
+    fn generate(chunker: &MarkdownChunker, raw_text: &str) -> Vec<(String, Value)> {
+        chunker
+            .generate_chunks(raw_text)
+            .unwrap()
+            .into_iter()
+            .map(|chunk| {
+                (
+                    chunk.content().to_string(),
+                    chunk.metadata()[BREADCRUMB_KEY].clone(),
+                )
+            })
+            .collect()
+    }
+
+    const DOCUMENT: &str = "Intro text.\n\
+        \n\
+        # Installation\n\
+        \n\
+        ## Linux\n\
+        Run the installer.\n\
+        ### Debian\n\
+        Use apt.\n\
+        ## Windows ##\n\
+        ```sh\n\
+        # not a heading\n\
+        ```\n\
+        # Usage\n\
+        Call it.\n";
+
+    #[test]
+    fn test_sections_are_split_at_headings() {
+        assert_eq!(
+            generate(&chunker(100), DOCUMENT),
+            vec![
+                ("Intro text.".to_string(), json!("")),
+                (
+                    "## Linux\nRun the installer.".to_string(),
+                    json!("Installation > Linux")
+                ),
+                (
+                    "### Debian\nUse apt.".to_string(),
+                    json!("Installation > Linux > Debian")
+                ),
+                (
+                    "## Windows ##\n```sh\n# not a heading\n```".to_string(),
+                    json!("Installation > Windows")
+                ),
+                ("# Usage\nCall it.".to_string(), json!("Usage")),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_deeper_headings_stay_in_their_section() {
+        let chunks = generate(&chunker(100).with_split_level(HeadingLevel::H1), DOCUMENT);
+        assert_eq!(chunks.len(), 3);
+        assert_eq!(chunks[1].1, json!("Installation"));
+        assert!(chunks[1].0.contains("### Debian\nUse apt."));
+        assert!(chunks[1].0.contains("# not a heading"));
+    }
+
+    #[test]
+    fn test_metadata_holds_the_headings() {
+        let chunks = chunker(100).generate_chunks(DOCUMENT).unwrap();
+        assert_eq!(
+            chunks[2].metadata(),
+            &json!({
+                "breadcrumb": "Installation > Linux > Debian",
+                "headings": ["Installation", "Linux", "Debian"],
+            })
+        );
+        assert_eq!(
+            chunks[0].metadata(),
+            &json!({"breadcrumb": "", "headings": []})
+        );
+    }
+
+    #[test]
+    fn test_metadata_is_added_alongside_the_headings() {
+        let chunks = chunker(100)
+            .generate_chunks_with_metadata(DOCUMENT, json!({"source": "README.md"}))
+            .unwrap();
+        assert_eq!(
+            chunks[2].metadata(),
+            &json!({
+                "source": "README.md",
+                "breadcrumb": "Installation > Linux > Debian",
+                "headings": ["Installation", "Linux", "Debian"],
+            })
+        );
+    }
+
+    #[test]
+    fn test_headings_win_over_metadata_with_the_same_keys() {
+        let chunks = chunker(100)
+            .generate_chunks_with_metadata(
+                DOCUMENT,
+                json!({"source": "README.md", "headings": ["stale"]}),
+            )
+            .unwrap();
+        assert_eq!(
+            chunks[2].metadata(),
+            &json!({
+                "source": "README.md",
+                "breadcrumb": "Installation > Linux > Debian",
+                "headings": ["Installation", "Linux", "Debian"],
+            })
+        );
+    }
+
+    #[test]
+    fn test_document_metadata_is_merged_with_the_headings() {
+        let document = Document::new_with_metadata(
+            DOCUMENT,
+            json!({"path": "README.md", "breadcrumb": "overwritten"}),
+        );
+        let chunks = chunker(100).chunk_document(&document).unwrap();
+        assert_eq!(
+            chunks[2].metadata(),
+            &json!({
+                "path": "README.md",
+                "breadcrumb": "Installation > Linux > Debian",
+                "headings": ["Installation", "Linux", "Debian"],
+            })
+        );
+    }
+
+    #[test]
+    fn test_large_sections_are_split_between_blocks() {
+        let raw_text: &str = "# Guide\n\
+            First paragraph here.\n\
+            \n\
+            ```rust\n\
+            let a = 1;\n\
+            \n\
+            let b = 2;\n\
+            ```\n\
+            \n\
+            one two three four five six seven eight nine ten eleven twelve\n";
+        let chunks = generate(&chunker(16), raw_text);
+        assert_eq!(
+            chunks
+                .iter()
+                .map(|(content, _)| content.as_str())
+                .collect::<Vec<&str>>(),
+            vec![
+                "# Guide\nFirst paragraph here.",
+                "```rust\nlet a = 1;\n\nlet b = 2;\n```",
+                "one two three four five six seven eight nine ten eleven twelve",
+            ]
+        );
+        let chunks = generate(&chunker(6), raw_text);
+        assert!(chunks.contains(&("one two three four five six".to_string(), json!("Guide"))));
+        assert!(chunks.iter().all(|(_, breadcrumb)| breadcrumb == "Guide"));
+    }
+
+    #[test]
+    fn test_generate_chunks_with_empty_string() {
+        assert!(chunker(10).generate_chunks("").unwrap().is_empty());
+        assert!(chunker(10)
+            .generate_chunks("# Only a heading\n")
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_try_new_with_invalid_arguments() {
+        assert!(matches!(
+            MarkdownChunker::try_new(NonZeroUsize::new(4).unwrap(), 4, TextEmbeddingAda002),
+            Err(MarkdownChunkingError::TokenChunkingError(
+                TokenChunkingError::ChunkOverlapTooLarge(_)
+            ))
+        ));
+    }
+}
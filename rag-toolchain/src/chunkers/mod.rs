@@ -1,7 +1,23 @@
+mod batch;
 mod character_chunker;
+mod content_defined_chunker;
+mod markdown_chunker;
+mod recursive_character_chunker;
+mod sentence_chunker;
 mod token_chunker;
 mod traits;
+pub use batch::ChunkBatchError;
 pub use character_chunker::CharacterChunker;
+pub use content_defined_chunker::{
+    ChunkSizeUnit, ContentDefinedChunker, ContentDefinedChunkingError,
+};
+pub use markdown_chunker::{
+    HeadingLevel, MarkdownChunker, MarkdownChunkingError, BREADCRUMB_KEY, HEADINGS_KEY,
+};
+pub use recursive_character_chunker::{
+    RecursiveCharacterChunker, RecursiveChunkingError, DEFAULT_SEPARATORS,
+};
+pub use sentence_chunker::{SentenceChunker, SentenceChunkingError};
 /// # Chunkers
 /// Module to contain all the methods of chunking allowing for
 /// prepping text before embedding and storing it.
@@ -0,0 +1,431 @@
+use crate::chunkers::traits::prepared_chunks;
+use crate::chunkers::Chunker;
+use crate::common::{Chunk, EmbeddingModel, EmbeddingModelMetadata, TokenizerWrapper};
+use std::collections::VecDeque;
+use std::num::NonZeroUsize;
+use thiserror::Error;
+
+/// The separators tried by default, from paragraphs down to single characters
+pub const DEFAULT_SEPARATORS: [&str; 4] = ["\n\n", "\n", " ", ""];
+
+/// # [`RecursiveCharacterChunker`]
+/// This struct splits text on a hierarchy of separators. The text is first split on the
+/// coarsest separator, by default paragraphs, and neighbouring pieces are merged back together
+/// while they fit in a chunk. Only the pieces which are still too large are split again on the
+/// next separator, so chunks break at the most natural point available. Text which contains none
+/// of the separators is split at a fixed size.
+///
+/// The size of a chunk is measured in characters, or in tokens when the chunker is created
+/// with an embedding model. Separators stay at the start of the piece after them and each chunk
+/// is trimmed of surrounding whitespace.
+///
+/// # Examples
+/// ```
+/// use rag_toolchain::chunkers::*;
+/// use rag_toolchain::common::*;
+/// use std::num::NonZeroUsize;
+///
+/// fn generate_chunks(raw_text: &str) -> Chunks {
+///     let chunker = RecursiveCharacterChunker::try_new_with_embedding_model(
+///         NonZeroUsize::new(256).unwrap(),
+///         32,
+///         OpenAIEmbeddingModel::TextEmbedding3Small,
+///     )
+///     .unwrap()
+///     .with_separators(vec!["\n\n".into(), ". ".into(), " ".into()]);
+///     chunker.generate_chunks(raw_text).unwrap()
+/// }
+/// ```
+pub struct RecursiveCharacterChunker {
+    /// separators: The separators to split on, coarsest first
+    separators: Vec<String>,
+    /// chunk_size: The maximum size of each chunk
+    chunk_size: NonZeroUsize,
+    /// chunk_overlap: The size shared between neighbouring chunks where possible
+    chunk_overlap: usize,
+    /// tokenizer: The tokenizer sizes are measured with, characters are counted when there is none
+    tokenizer: Option<Box<dyn TokenizerWrapper>>,
+}
+
+impl RecursiveCharacterChunker {
+    /// # [`RecursiveCharacterChunker::try_new`]
+    /// Creates a chunker which measures chunks in characters and splits on the
+    /// [`DEFAULT_SEPARATORS`].
+    ///
+    /// # Arguments
+    /// * `chunk_size`: [`NonZeroUsize`] - The maximum number of characters in each chunk
+    /// * `chunk_overlap`: [`usize`] - The number of characters shared between neighbouring chunks
+    ///
+    /// # Errors
+    /// * [`RecursiveChunkingError::ChunkOverlapTooLarge`] - Chunk overlap must be smaller than chunk size
+    ///
+    /// # Returns
+    /// * [`RecursiveCharacterChunker`] - The chunker
+    pub fn try_new(
+        chunk_size: NonZeroUsize,
+        chunk_overlap: usize,
+    ) -> Result<Self, RecursiveChunkingError> {
+        Self::validate_overlap(chunk_size, chunk_overlap)?;
+        Ok(RecursiveCharacterChunker {
+            separators: DEFAULT_SEPARATORS.map(String::from).to_vec(),
+            chunk_size,
+            chunk_overlap,
+            tokenizer: None,
+        })
+    }
+
+    /// # [`RecursiveCharacterChunker::try_new_with_embedding_model`]
+    /// Creates a chunker which measures chunks in tokens of the embedding model
+    /// and splits on the [`DEFAULT_SEPARATORS`].
+    ///
+    /// # Arguments
+    /// * `chunk_size`: [`NonZeroUsize`] - The maximum number of tokens in each chunk
+    /// * `chunk_overlap`: [`usize`] - The number of tokens shared between neighbouring chunks
+    /// * `embedding_model`: impl [`EmbeddingModel`] - The embedding model the chunks are for, this tells
+    ///   us what tokenizer to use and the maximum tokens in a chunk
+    ///
+    /// # Errors
+    /// * [`RecursiveChunkingError::InvalidChunkSize`] - Chunk size must be smaller than the maximum number of tokens
+    /// * [`RecursiveChunkingError::ChunkOverlapTooLarge`] - Chunk overlap must be smaller than chunk size
+    ///
+    /// # Returns
+    /// * [`RecursiveCharacterChunker`] - The chunker
+    pub fn try_new_with_embedding_model(
+        chunk_size: NonZeroUsize,
+        chunk_overlap: usize,
+        embedding_model: impl EmbeddingModel,
+    ) -> Result<Self, RecursiveChunkingError> {
+        let metadata: EmbeddingModelMetadata = embedding_model.metadata();
+        if chunk_size.get() > metadata.max_tokens {
+            Err(RecursiveChunkingError::InvalidChunkSize(format!(
+                "Chunk size must be smaller than {}",
+                metadata.max_tokens
+            )))?
+        }
+        Self::validate_overlap(chunk_size, chunk_overlap)?;
+        Ok(RecursiveCharacterChunker {
+            separators: DEFAULT_SEPARATORS.map(String::from).to_vec(),
+            chunk_size,
+            chunk_overlap,
+            tokenizer: Some(metadata.tokenizer),
+        })
+    }
+
+    /// # [`RecursiveCharacterChunker::with_separators`]
+    /// Replaces the separators which are split on.
+    ///
+    /// # Arguments
+    /// * `separators`: [`Vec<String>`] - The separators ordered from the coarsest to the finest.
+    ///   An empty separator splits into single characters, if there is none and a piece contains
+    ///   none of the separators it is still split at a fixed size.
+    ///
+    /// # Returns
+    /// * [`RecursiveCharacterChunker`] - The chunker with the new separators
+    pub fn with_separators(mut self, separators: Vec<String>) -> Self {
+        self.separators = separators;
+        self
+    }
+
+    // # [`RecursiveCharacterChunker::validate_overlap`]
+    // Chunk overlap must be smaller than chunk size otherwise chunks would never move forward
+    fn validate_overlap(
+        chunk_size: NonZeroUsize,
+        chunk_overlap: usize,
+    ) -> Result<(), RecursiveChunkingError> {
+        if chunk_overlap >= chunk_size.get() {
+            Err(RecursiveChunkingError::ChunkOverlapTooLarge(
+                "Chunk overlap must be smaller than chunk size".to_string(),
+            ))?
+        }
+        Ok(())
+    }
+
+    /// # [`RecursiveCharacterChunker::size`]
+    /// The size of the text in characters, or tokens when there is a tokenizer.
+    fn size(&self, text: &str) -> Result<usize, RecursiveChunkingError> {
+        match &self.tokenizer {
+            None => Ok(text.chars().count()),
+            Some(tokenizer) => tokenizer
+                .tokenize(text)
+                .map(|tokens| tokens.len())
+                .ok_or_else(|| {
+                    RecursiveChunkingError::TokenizationError("Unable to tokenize text".to_string())
+                }),
+        }
+    }
+
+    /// # [`RecursiveCharacterChunker::split`]
+    /// Splits the text on the first separator it contains, recursing into the finer
+    /// separators for any piece which is still too large.
+    ///
+    /// # Arguments
+    /// * `text`: &[`str`] - The text to split
+    /// * `separators`: &[`[String]`] - The separators which have not been tried yet
+    /// * `chunks`: &mut [`Vec<String>`] - Where the finished chunks are pushed
+    fn split(
+        &self,
+        text: &str,
+        separators: &[String],
+        chunks: &mut Vec<String>,
+    ) -> Result<(), RecursiveChunkingError> {
+        // Without a separator in the text we fall back to single characters
+        let position: Option<usize> = separators
+            .iter()
+            .position(|separator| separator.is_empty() || text.contains(separator.as_str()));
+        let (pieces, finer): (Vec<&str>, &[String]) = match position {
+            Some(index) if !separators[index].is_empty() => (
+                split_keeping_separator(text, &separators[index]),
+                &separators[index + 1..],
+            ),
+            _ => (split_characters(text), &[]),
+        };
+
+        let mut fitting: Vec<(&str, usize)> = Vec::new();
+        for piece in pieces {
+            let size: usize = self.size(piece)?;
+            if size <= self.chunk_size.get() || finer.is_empty() {
+                fitting.push((piece, size));
+                continue;
+            }
+            self.merge(&fitting, chunks);
+            fitting.clear();
+            self.split(piece, finer, chunks)?;
+        }
+        self.merge(&fitting, chunks);
+        Ok(())
+    }
+
+    /// # [`RecursiveCharacterChunker::merge`]
+    /// Joins neighbouring pieces into chunks of at most the chunk size. The pieces at the end
+    /// of a chunk are carried into the next one while they fit within the chunk overlap.
+    fn merge(&self, pieces: &[(&str, usize)], chunks: &mut Vec<String>) {
+        let chunk_size: usize = self.chunk_size.get();
+        let mut current: VecDeque<(&str, usize)> = VecDeque::new();
+        let mut total: usize = 0;
+        for &(piece, size) in pieces {
+            if total + size > chunk_size && !current.is_empty() {
+                push_chunk(&current, chunks);
+                while total > self.chunk_overlap || (total > 0 && total + size > chunk_size) {
+                    let (_, removed) = current.pop_front().unwrap();
+                    total -= removed;
+                }
+            }
+            current.push_back((piece, size));
+            total += size;
+        }
+        push_chunk(&current, chunks);
+    }
+}
+
+impl Chunker for RecursiveCharacterChunker {
+    type ErrorType = RecursiveChunkingError;
+    /// # [`RecursiveCharacterChunker::chunk_iter`]
+    /// function to generate chunks from raw text. The text is split up front
+    /// and each chunk is turned into a [`Chunk`] when it is asked for.
+    ///
+    /// # Arguments
+    /// * `raw_text`: &[`str`] - The raw text to generate chunks from
+    ///
+    /// # Errors
+    /// * [`RecursiveChunkingError::TokenizationError`] - Unable to tokenize text
+    ///
+    /// # Returns
+    /// impl [`Iterator<Item = Result<Chunk, Self::ErrorType>>`] - The generated chunks
+    fn chunk_iter<'a>(
+        &'a self,
+        raw_text: &'a str,
+    ) -> impl Iterator<Item = Result<Chunk, Self::ErrorType>> + 'a {
+        let mut chunks: Vec<String> = Vec::new();
+        let split = self.split(raw_text, &self.separators, &mut chunks);
+        prepared_chunks(split.map(|_| chunks.into_iter().map(Chunk::new)))
+    }
+}
+
+/// Splits the text before each separator so it stays at the start of the piece after it
+fn split_keeping_separator<'a>(text: &'a str, separator: &str) -> Vec<&'a str> {
+    let mut pieces: Vec<&str> = Vec::new();
+    let mut start: usize = 0;
+    for (offset, _) in text.match_indices(separator) {
+        if offset > start {
+            pieces.push(&text[start..offset]);
+            start = offset;
+        }
+    }
+    pieces.push(&text[start..]);
+    pieces
+}
+
+/// Splits the text into its characters
+fn split_characters(text: &str) -> Vec<&str> {
+    text.char_indices()
+        .map(|(offset, character)| &text[offset..offset + character.len_utf8()])
+        .collect()
+}
+
+/// Joins the pieces into a chunk, chunks which are only whitespace are dropped
+fn push_chunk(pieces: &VecDeque<(&str, usize)>, chunks: &mut Vec<String>) {
+    let chunk: String = pieces.iter().map(|(piece, _)| *piece).collect();
+    let chunk: &str = chunk.trim();
+    if !chunk.is_empty() {
+        chunks.push(chunk.to_string());
+    }
+}
+
+/// # [`RecursiveChunkingError`]
+/// Custom error type representing errors that can occur during recursive chunking
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum RecursiveChunkingError {
+    #[error("{0}")]
+    ChunkOverlapTooLarge(String),
+    #[error("{0}")]
+    TokenizationError(String),
+    #[error("{0}")]
+    InvalidChunkSize(String),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::OpenAIEmbeddingModel::TextEmbeddingAda002;
+
+    fn chunk_strings(chunker: &RecursiveCharacterChunker, raw_text: &str) -> Vec<String> {
+        chunker
+            .generate_chunks(raw_text)
+            .unwrap()
+            .into_iter()
+            .map(|chunk| chunk.content().to_string())
+            .collect()
+    }
+
+    fn chunker(chunk_size: usize, chunk_overlap: usize) -> RecursiveCharacterChunker {
+        RecursiveCharacterChunker::try_new(NonZeroUsize::new(chunk_size).unwrap(), chunk_overlap)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_paragraphs_are_kept_whole_when_they_fit() {
+        let raw_text: &str = "The first paragraph.\n\nThe second one.\n\nA third.";
+        assert_eq!(
+            chunk_strings(&chunker(40, 0), raw_text),
+            vec!["The first paragraph.\n\nThe second one.", "A third."]
+        );
+        assert_eq!(
+            chunk_strings(&chunker(22, 0), raw_text),
+            vec!["The first paragraph.", "The second one.", "A third."]
+        );
+    }
+
+    #[test]
+    fn test_only_large_pieces_are_split_on_finer_separators() {
+        let raw_text: &str = "Short one.\n\nthis paragraph is far too long to fit\n\nEnd.";
+        assert_eq!(
+            chunk_strings(&chunker(16, 0), raw_text),
+            vec![
+                "Short one.",
+                "this paragraph",
+                "is far too long",
+                "to fit",
+                "End."
+            ]
+        );
+    }
+
+    #[test]
+    fn test_text_without_separators_is_hard_split() {
+        assert_eq!(
+            chunk_strings(&chunker(4, 0), "abcdefghij"),
+            vec!["abcd", "efgh", "ij"]
+        );
+        // Even when the empty separator is not given
+        let chunker = chunker(4, 0).with_separators(vec!["\n\n".into()]);
+        assert_eq!(
+            chunk_strings(&chunker, "abcdefghij"),
+            vec!["abcd", "efgh", "ij"]
+        );
+    }
+
+    #[test]
+    fn test_chunks_overlap() {
+        assert_eq!(
+            chunk_strings(&chunker(5, 2), "a b c d e f"),
+            vec!["a b c", "c d", "d e", "e f"]
+        );
+        assert_eq!(
+            chunk_strings(&chunker(4, 2), "abcdefgh"),
+            vec!["abcd", "cdef", "efgh"]
+        );
+    }
+
+    #[test]
+    fn test_chunks_never_exceed_chunk_size() {
+        let raw_text: String = (0..200)
+            .map(|i| match i % 7 {
+                0 => "paragraph\n\n".to_string(),
+                3 => "line\n".to_string(),
+                _ => format!("word{} ", i),
+            })
+            .collect();
+        for (chunk_size, chunk_overlap) in [(10, 0), (25, 5), (60, 20)] {
+            let chunks = chunk_strings(&chunker(chunk_size, chunk_overlap), &raw_text);
+            assert!(!chunks.is_empty());
+            for chunk in chunks {
+                assert!(chunk.chars().count() <= chunk_size, "{chunk:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_chunks_are_measured_in_tokens_with_an_embedding_model() {
+        let chunker = RecursiveCharacterChunker::try_new_with_embedding_model(
+            NonZeroUsize::new(4).unwrap(),
+            0,
+            TextEmbeddingAda002,
+        )
+        .unwrap();
+        let raw_text: &str = "the vector store holds embeddings\n\nfor each chunk of text";
+        let chunks = chunk_strings(&chunker, raw_text);
+        assert_eq!(
+            chunks,
+            vec![
+                "the vector store holds",
+                "embeddings",
+                "for each chunk",
+                "of text"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_generate_chunks_with_empty_string() {
+        assert_eq!(chunk_strings(&chunker(4, 0), ""), Vec::<String>::new());
+        assert_eq!(
+            chunk_strings(&chunker(4, 0), " \n\n "),
+            Vec::<String>::new()
+        );
+    }
+
+    #[test]
+    fn test_try_new_with_invalid_arguments() {
+        let chunk_size: NonZeroUsize = NonZeroUsize::new(2).unwrap();
+        assert!(matches!(
+            RecursiveCharacterChunker::try_new(chunk_size, 2),
+            Err(RecursiveChunkingError::ChunkOverlapTooLarge(_))
+        ));
+        assert!(matches!(
+            RecursiveCharacterChunker::try_new_with_embedding_model(
+                chunk_size,
+                3,
+                TextEmbeddingAda002
+            ),
+            Err(RecursiveChunkingError::ChunkOverlapTooLarge(_))
+        ));
+        assert!(matches!(
+            RecursiveCharacterChunker::try_new_with_embedding_model(
+                NonZeroUsize::new(20000).unwrap(),
+                0,
+                TextEmbeddingAda002
+            ),
+            Err(RecursiveChunkingError::InvalidChunkSize(_))
+        ));
+    }
+}
@@ -0,0 +1,438 @@
+use crate::chunkers::traits::prepared_chunks;
+use crate::chunkers::Chunker;
+use crate::common::{Chunk, EmbeddingModel, EmbeddingModelMetadata, TokenizerWrapper};
+use std::collections::VecDeque;
+use std::num::NonZeroUsize;
+use std::ops::Range;
+use thiserror::Error;
+
+/// Abbreviations which end in a period without ending the sentence
+const ABBREVIATIONS: [&str; 14] = [
+    "mr", "mrs", "ms", "dr", "prof", "sr", "jr", "st", "vs", "fig", "approx", "inc", "ltd", "cf",
+];
+
+/// # [`SentenceChunker`]
+/// This struct splits text into sentences and packs whole sentences into chunks of at most
+/// a token budget, measured with the tokenizer of the embedding model. Sentences end at a
+/// period, question mark or exclamation mark followed by whitespace, or at a newline.
+/// Periods after common abbreviations such as "Dr." or "e.g." do not end a sentence.
+///
+/// A sentence which is larger than the budget on its own is split on tokens instead.
+/// Each chunk keeps the original text between its first and last sentence.
+///
+/// # Examples
+/// ```
+/// use rag_toolchain::chunkers::*;
+/// use rag_toolchain::common::*;
+/// use std::num::NonZeroUsize;
+///
+/// fn generate_chunks(raw_text: &str) -> Chunks {
+///     let chunker: SentenceChunker = SentenceChunker::try_new(
+///         NonZeroUsize::new(256).unwrap(),
+///         OpenAIEmbeddingModel::TextEmbedding3Small,
+///     )
+///     .unwrap()
+///     .with_sentence_overlap(1);
+///     chunker.generate_chunks(raw_text).unwrap()
+/// }
+/// ```
+pub struct SentenceChunker {
+    /// chunk_size: The most tokens in each chunk
+    chunk_size: NonZeroUsize,
+    /// sentence_overlap: The number of sentences repeated at the start of the next chunk
+    sentence_overlap: usize,
+    /// metadata: The metadata attached to every chunk
+    metadata: serde_json::Value,
+    /// tokenizer: The type of tokenizer
+    tokenizer: Box<dyn TokenizerWrapper>,
+}
+
+impl SentenceChunker {
+    /// # [`SentenceChunker::try_new`]
+    ///
+    /// # Arguments
+    /// * `chunk_size`: [`NonZeroUsize`] - The most tokens in each chunk
+    /// * `embedding_model`: impl [`EmbeddingModel`] - The embedding model to use, this tells us what tokenizer
+    ///   to use
+    ///
+    /// # Errors
+    /// * [`SentenceChunkingError::InvalidChunkSize`] - Chunk size must be smaller than the maximum number of tokens
+    ///
+    /// # Returns
+    /// * [`SentenceChunker`] - The sentence chunker
+    pub fn try_new(
+        chunk_size: NonZeroUsize,
+        embedding_model: impl EmbeddingModel,
+    ) -> Result<Self, SentenceChunkingError> {
+        let metadata: EmbeddingModelMetadata = embedding_model.metadata();
+        if chunk_size.get() > metadata.max_tokens {
+            Err(SentenceChunkingError::InvalidChunkSize(format!(
+                "Chunk size must be smaller than {}",
+                metadata.max_tokens
+            )))?
+        }
+        Ok(SentenceChunker {
+            chunk_size,
+            sentence_overlap: 0,
+            metadata: serde_json::Value::Null,
+            tokenizer: metadata.tokenizer,
+        })
+    }
+
+    /// # [`SentenceChunker::with_sentence_overlap`]
+    /// Repeats the last sentences of each chunk at the start of the next one. Fewer
+    /// sentences are repeated when they would not leave room for the next sentence.
+    ///
+    /// # Arguments
+    /// * `sentence_overlap`: [`usize`] - The number of sentences to repeat
+    ///
+    /// # Returns
+    /// * [`SentenceChunker`] - The chunker with the overlap set
+    pub fn with_sentence_overlap(mut self, sentence_overlap: usize) -> Self {
+        self.sentence_overlap = sentence_overlap;
+        self
+    }
+
+    /// # [`SentenceChunker::with_metadata`]
+    /// Attaches the same metadata to every chunk generated, such as the source document.
+    ///
+    /// # Arguments
+    /// * `metadata`: [`serde_json::Value`] - The metadata for each chunk
+    ///
+    /// # Returns
+    /// * [`SentenceChunker`] - The chunker with the metadata set
+    pub fn with_metadata(mut self, metadata: serde_json::Value) -> Self {
+        self.metadata = metadata;
+        self
+    }
+
+    /// # [`SentenceChunker::count_tokens`]
+    /// The number of tokens in the text.
+    fn count_tokens(&self, text: &str) -> Result<usize, SentenceChunkingError> {
+        Ok(self.tokenize(text)?.len())
+    }
+
+    fn tokenize(&self, text: &str) -> Result<Vec<String>, SentenceChunkingError> {
+        self.tokenizer.tokenize(text).ok_or_else(|| {
+            SentenceChunkingError::TokenizationError("Unable to tokenize text".to_string())
+        })
+    }
+
+    /// # [`SentenceChunker::pack`]
+    /// Packs whole sentences into chunks, a sentence larger than the chunk size is
+    /// split on tokens and is not carried into the next chunk.
+    ///
+    /// # Arguments
+    /// * `text`: &[`str`] - The text the sentences come from
+    /// * `sentences`: [`Vec<Range<usize>>`] - The byte ranges of each sentence
+    ///
+    /// # Errors
+    /// * [`SentenceChunkingError::TokenizationError`] - Unable to tokenize text
+    ///
+    /// # Returns
+    /// * [`Vec<String>`] - The text of each chunk
+    fn pack(
+        &self,
+        text: &str,
+        sentences: Vec<Range<usize>>,
+    ) -> Result<Vec<String>, SentenceChunkingError> {
+        let chunk_size: usize = self.chunk_size.get();
+        let mut chunks: Vec<String> = Vec::new();
+        let mut current: VecDeque<Range<usize>> = VecDeque::new();
+        for sentence in sentences {
+            let tokens: Vec<String> = self.tokenize(&text[sentence.clone()])?;
+            if tokens.len() > chunk_size {
+                if !current.is_empty() {
+                    chunks.push(span(text, &current));
+                }
+                current.clear();
+                chunks.extend(
+                    tokens
+                        .chunks(chunk_size)
+                        .map(|window| window.join("").trim().to_string()),
+                );
+                continue;
+            }
+
+            if let Some(first) = current.front() {
+                if self.count_tokens(&text[first.start..sentence.end])? > chunk_size {
+                    chunks.push(span(text, &current));
+                    while current.len() > self.sentence_overlap {
+                        current.pop_front();
+                    }
+                    while let Some(first) = current.front() {
+                        if self.count_tokens(&text[first.start..sentence.end])? <= chunk_size {
+                            break;
+                        }
+                        current.pop_front();
+                    }
+                }
+            }
+            current.push_back(sentence);
+        }
+        if !current.is_empty() {
+            chunks.push(span(text, &current));
+        }
+        Ok(chunks)
+    }
+}
+
+impl Chunker for SentenceChunker {
+    type ErrorType = SentenceChunkingError;
+    /// # [`SentenceChunker::chunk_iter`]
+    /// function to generate chunks from raw text. The sentences are packed up front
+    /// and each chunk is turned into a [`Chunk`] when it is asked for.
+    ///
+    /// # Arguments
+    /// * `raw_text`: &[`str`] - The raw text to generate chunks from
+    ///
+    /// # Errors
+    /// * [`SentenceChunkingError::TokenizationError`] - Unable to tokenize text
+    ///
+    /// # Returns
+    /// impl [`Iterator<Item = Result<Chunk, Self::ErrorType>>`] - The generated chunks
+    fn chunk_iter<'a>(
+        &'a self,
+        raw_text: &'a str,
+    ) -> impl Iterator<Item = Result<Chunk, Self::ErrorType>> + 'a {
+        let chunks = self
+            .pack(raw_text, split_sentences(raw_text))
+            .map(|chunks| {
+                chunks
+                    .into_iter()
+                    .filter(|chunk| !chunk.is_empty())
+                    .map(|chunk| Chunk::new_with_metadata(chunk, self.metadata.clone()))
+            });
+        prepared_chunks(chunks)
+    }
+}
+
+/// The text from the start of the first sentence to the end of the last
+fn span(text: &str, sentences: &VecDeque<Range<usize>>) -> String {
+    match (sentences.front(), sentences.back()) {
+        (Some(first), Some(last)) => text[first.start..last.end].to_string(),
+        _ => String::new(),
+    }
+}
+
+/// # [`split_sentences`]
+/// Finds the byte range of each sentence with the surrounding whitespace trimmed.
+///
+/// # Arguments
+/// * `text`: &[`str`] - The text to split
+///
+/// # Returns
+/// * [`Vec<Range<usize>>`] - The range of each sentence in the text
+fn split_sentences(text: &str) -> Vec<Range<usize>> {
+    let mut sentences: Vec<Range<usize>> = Vec::new();
+    let mut start: usize = 0;
+    let mut characters = text.char_indices().peekable();
+    while let Some((offset, character)) = characters.next() {
+        let end: usize = match character {
+            '\n' => offset,
+            '.' | '?' | '!' => {
+                // Closing quotes and brackets belong to the sentence they close
+                let mut end: usize = offset + character.len_utf8();
+                while let Some(&(next_offset, next)) = characters.peek() {
+                    if !matches!(next, '.' | '?' | '!' | '"' | '\'' | ')' | ']' | '”' | '’') {
+                        break;
+                    }
+                    end = next_offset + next.len_utf8();
+                    characters.next();
+                }
+                if characters
+                    .peek()
+                    .is_some_and(|(_, next)| !next.is_whitespace())
+                {
+                    continue;
+                }
+                if character == '.' && ends_with_abbreviation(&text[start..offset]) {
+                    continue;
+                }
+                end
+            }
+            _ => continue,
+        };
+        push_sentence(text, start..end, &mut sentences);
+        start = end;
+    }
+    push_sentence(text, start..text.len(), &mut sentences);
+    sentences
+}
+
+/// Whether the last word before a period is an abbreviation, an initial or
+/// has periods inside it like "e.g"
+fn ends_with_abbreviation(preceding: &str) -> bool {
+    let word: &str = preceding
+        .split_whitespace()
+        .last()
+        .unwrap_or_default()
+        .trim_start_matches(|character: char| !character.is_alphanumeric());
+    let mut characters = word.chars();
+    let is_initial: bool = matches!(
+        (characters.next(), characters.next()),
+        (Some(character), None) if character.is_alphabetic()
+    );
+    is_initial || word.contains('.') || ABBREVIATIONS.contains(&word.to_lowercase().as_str())
+}
+
+/// Trims the whitespace from the range and keeps it when anything is left
+fn push_sentence(text: &str, range: Range<usize>, sentences: &mut Vec<Range<usize>>) {
+    let sentence: &str = &text[range.clone()];
+    let trimmed: &str = sentence.trim_start();
+    let start: usize = range.start + sentence.len() - trimmed.len();
+    let end: usize = start + trimmed.trim_end().len();
+    if start < end {
+        sentences.push(start..end);
+    }
+}
+
+/// # [`SentenceChunkingError`]
+/// Custom error type representing errors that can occur during sentence chunking
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum SentenceChunkingError {
+    #[error("{0}")]
+    TokenizationError(String),
+    #[error("{0}")]
+    InvalidChunkSize(String),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::OpenAIEmbeddingModel::TextEmbeddingAda002;
+    use serde_json::json;
+
+    fn sentences(text: &str) -> Vec<&str> {
+        split_sentences(text)
+            .into_iter()
+            .map(|range| &text[range])
+            .collect()
+    }
+
+    fn chunker(chunk_size: usize) -> SentenceChunker {
+        SentenceChunker::try_new(NonZeroUsize::new(chunk_size).unwrap(), TextEmbeddingAda002)
+            .unwrap()
+    }
+
+    fn chunk_strings(chunker: &SentenceChunker, raw_text: &str) -> Vec<String> {
+        chunker
+            .generate_chunks(raw_text)
+            .unwrap()
+            .into_iter()
+            .map(|chunk| chunk.content().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_split_sentences() {
+        assert_eq!(
+            sentences("Is it raining? It is! Take a coat.\nA heading\n\n  Done."),
+            vec![
+                "Is it raining?",
+                "It is!",
+                "Take a coat.",
+                "A heading",
+                "Done."
+            ]
+        );
+        assert_eq!(
+            sentences("She said \"stop.\" Then (quietly.) we left"),
+            vec!["She said \"stop.\"", "Then (quietly.)", "we left"]
+        );
+    }
+
+    #[test]
+    fn test_split_sentences_with_abbreviations() {
+        assert_eq!(
+            sentences("Use a store, e.g. pgvector, for this. Ask Dr. Smith at 3.5 p.m. today."),
+            vec![
+                "Use a store, e.g. pgvector, for this.",
+                "Ask Dr. Smith at 3.5 p.m. today."
+            ]
+        );
+        assert_eq!(
+            sentences("Written by J. R. R. Tolkien. A classic."),
+            vec!["Written by J. R. R. Tolkien.", "A classic."]
+        );
+    }
+
+    #[test]
+    fn test_whole_sentences_are_packed_into_chunks() {
+        let raw_text: &str = "The cat sat. The dog ran. A bird flew away. The end.";
+        assert_eq!(
+            chunk_strings(&chunker(8), raw_text),
+            vec!["The cat sat. The dog ran.", "A bird flew away. The end."]
+        );
+        assert_eq!(chunk_strings(&chunker(100), raw_text), vec![raw_text]);
+    }
+
+    #[test]
+    fn test_chunks_never_exceed_chunk_size() {
+        let raw_text: String = (0..60)
+            .map(|i| {
+                format!(
+                    "Sentence number {i} talks about {}.\n",
+                    "words ".repeat(i % 9)
+                )
+            })
+            .collect();
+        let chunker = chunker(24).with_sentence_overlap(2);
+        let tokenizer = TextEmbeddingAda002.metadata().tokenizer;
+        for chunk in chunk_strings(&chunker, &raw_text) {
+            assert!(tokenizer.tokenize(&chunk).unwrap().len() <= 24, "{chunk:?}");
+        }
+    }
+
+    #[test]
+    fn test_sentences_overlap() {
+        let raw_text: &str = "The cat sat. The dog ran. A bird flew away. The end.";
+        assert_eq!(
+            chunk_strings(&chunker(9).with_sentence_overlap(1), raw_text),
+            vec![
+                "The cat sat. The dog ran.",
+                "The dog ran. A bird flew away.",
+                "A bird flew away. The end."
+            ]
+        );
+    }
+
+    #[test]
+    fn test_long_sentence_falls_back_to_tokens() {
+        let raw_text: &str = "Short one. this sentence is much too long for the budget. Short two.";
+        assert_eq!(
+            chunk_strings(&chunker(4).with_sentence_overlap(1), raw_text),
+            vec![
+                "Short one.",
+                "this sentence is much",
+                "too long for the",
+                "budget.",
+                "Short two."
+            ]
+        );
+    }
+
+    #[test]
+    fn test_metadata_is_attached_to_every_chunk() {
+        let chunker = chunker(4).with_metadata(json!({"source": "notes.txt"}));
+        let chunks = chunker.generate_chunks("First one. Second one.").unwrap();
+        assert_eq!(chunks.len(), 2);
+        for chunk in chunks {
+            assert_eq!(chunk.metadata(), &json!({"source": "notes.txt"}));
+        }
+    }
+
+    #[test]
+    fn test_generate_chunks_with_empty_string() {
+        assert_eq!(chunk_strings(&chunker(4), ""), Vec::<String>::new());
+        assert_eq!(chunk_strings(&chunker(4), " \n\n "), Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_try_new_with_invalid_chunk_size() {
+        assert!(matches!(
+            SentenceChunker::try_new(NonZeroUsize::new(20000).unwrap(), TextEmbeddingAda002),
+            Err(SentenceChunkingError::InvalidChunkSize(_))
+        ));
+    }
+}
@@ -1,9 +1,13 @@
-use crate::common::{Chunk, Chunks, EmbeddingModel, EmbeddingModelMetadata, TokenizerWrapper};
+use crate::common::{Chunk, EmbeddingModel, EmbeddingModelMetadata, TokenizerWrapper};
+use std::collections::VecDeque;
 use std::num::NonZeroUsize;
 use thiserror::Error;
 
 use super::traits::Chunker;
 
+/// How many bytes of the text are tokenized at a time
+const DEFAULT_WINDOW_BYTES: usize = 64 * 1024;
+
 /// # [`TokenChunker`]
 /// This struct allows you to do fixed size chunking based on the number
 /// of tokens in each chunk. We build around specific embedding models and based
@@ -40,6 +44,8 @@ pub struct TokenChunker {
     chunk_overlap: usize,
     /// tokenizer: The type of tokenizer
     tokenizer: Box<dyn TokenizerWrapper>,
+    /// window_bytes: How much of the text is tokenized at a time
+    window_bytes: usize,
 }
 
 impl TokenChunker {
@@ -68,6 +74,7 @@ impl TokenChunker {
             chunk_size,
             chunk_overlap,
             tokenizer: metadata.tokenizer,
+            window_bytes: DEFAULT_WINDOW_BYTES,
         };
         Ok(chunker)
     }
@@ -109,8 +116,10 @@ impl TokenChunker {
 
 impl Chunker for TokenChunker {
     type ErrorType = TokenChunkingError;
-    /// # [`TokenChunker::generate_chunks`]
-    /// function to generate chunks from raw text
+    /// # [`TokenChunker::chunk_iter`]
+    /// function to generate chunks from raw text on demand. The text is tokenized a
+    /// window at a time as the chunks are asked for, so the tokens of the whole text
+    /// are never held at once.
     ///
     /// # Arguments
     /// * `raw_text`: &[`str`] - The raw text to generate chunks from
@@ -119,25 +128,136 @@ impl Chunker for TokenChunker {
     /// * [`ChunkingError::TokenizationError`] - Unable to tokenize text
     ///
     /// # Returns
-    /// [`Chunks`] - The generated chunks
-    fn generate_chunks(&self, raw_text: &str) -> Result<Chunks, Self::ErrorType> {
-        // Generate token array from raw text
-        let tokens: Vec<String> = self.tokenizer.tokenize(raw_text).ok_or_else(|| {
-            TokenChunkingError::TokenizationError("Unable to tokenize text".to_string())
-        })?;
-
-        let chunk_size: usize = self.chunk_size.into();
-        let mut chunks: Chunks = Chunks::new();
-
-        let mut i = 0;
-        while i < tokens.len() {
-            let end = std::cmp::min(i + chunk_size, tokens.len());
-            let chunk: Chunk = Chunk::new(tokens[i..end].to_vec().join("").trim());
-            chunks.push(chunk);
-            i += chunk_size - self.chunk_overlap;
+    /// impl [`Iterator<Item = Result<Chunk, Self::ErrorType>>`] - The generated chunks
+    fn chunk_iter<'a>(
+        &'a self,
+        raw_text: &'a str,
+    ) -> impl Iterator<Item = Result<Chunk, Self::ErrorType>> + 'a {
+        TokenChunks {
+            tokens: TokenWindows {
+                tokenizer: self.tokenizer.as_ref(),
+                raw_text,
+                offset: 0,
+                window_bytes: self.window_bytes,
+            },
+            window: VecDeque::new(),
+            chunk_size: self.chunk_size.into(),
+            step: usize::from(self.chunk_size) - self.chunk_overlap,
+            failed: false,
         }
+    }
+}
+
+/// # [`TokenWindows`]
+/// Tokenizes the text a window of bytes at a time. Tokens in the last quarter of a
+/// window may have been split differently had the text carried on, so they are
+/// tokenized again at the start of the next window. This gives the same tokens as
+/// tokenizing the whole text unless a single run of letters or whitespace is longer
+/// than the margin, in which case the window is doubled until it is not.
+struct TokenWindows<'a> {
+    tokenizer: &'a dyn TokenizerWrapper,
+    raw_text: &'a str,
+    /// The byte offset of the first character which has not been tokenized
+    offset: usize,
+    window_bytes: usize,
+}
 
-        Ok(Chunks::from(chunks))
+impl TokenWindows<'_> {
+    /// # [`TokenWindows::next_tokens`]
+    ///
+    /// # Errors
+    /// * [`TokenChunkingError::TokenizationError`] - Unable to tokenize the window
+    ///
+    /// # Returns
+    /// * [`Option<Vec<String>>`] - the tokens of the next window, or None at the end of the text
+    fn next_tokens(&mut self) -> Result<Option<Vec<String>>, TokenChunkingError> {
+        let remaining: &str = &self.raw_text[self.offset..];
+        if remaining.is_empty() {
+            return Ok(None);
+        }
+        loop {
+            let end: usize = ceil_char_boundary(remaining, self.window_bytes);
+            let tokens: Vec<String> =
+                self.tokenizer.tokenize(&remaining[..end]).ok_or_else(|| {
+                    TokenChunkingError::TokenizationError("Unable to tokenize text".to_string())
+                })?;
+            let tokenized: usize = tokens.iter().map(String::len).sum();
+            // A tokenizer which does not give back the text it was given can not be
+            // windowed, so the rest of the text is tokenized at once
+            if end == remaining.len() || tokenized != end {
+                let tokens: Vec<String> = match end == remaining.len() {
+                    true => tokens,
+                    false => self.tokenizer.tokenize(remaining).ok_or_else(|| {
+                        TokenChunkingError::TokenizationError("Unable to tokenize text".to_string())
+                    })?,
+                };
+                self.offset = self.raw_text.len();
+                return Ok(Some(tokens));
+            }
+            let margin: usize = end - self.window_bytes / 4;
+            let mut kept: usize = 0;
+            let tokens: Vec<String> = tokens
+                .into_iter()
+                .take_while(|token| {
+                    kept += token.len();
+                    kept <= margin
+                })
+                .collect();
+            if tokens.is_empty() {
+                self.window_bytes *= 2;
+                continue;
+            }
+            self.offset += tokens.iter().map(String::len).sum::<usize>();
+            return Ok(Some(tokens));
+        }
+    }
+}
+
+/// The first char boundary at or after the index, or the end of the text
+fn ceil_char_boundary(text: &str, index: usize) -> usize {
+    (index..text.len())
+        .find(|&index| text.is_char_boundary(index))
+        .unwrap_or(text.len())
+}
+
+/// # [`TokenChunks`]
+/// Joins the tokens of each chunk as they are asked for, keeping only the tokens of
+/// the current chunk along with the rest of the window they were tokenized in.
+struct TokenChunks<'a> {
+    tokens: TokenWindows<'a>,
+    window: VecDeque<String>,
+    chunk_size: usize,
+    step: usize,
+    /// Set once an error is yielded so nothing is yielded after it
+    failed: bool,
+}
+
+impl Iterator for TokenChunks<'_> {
+    type Item = Result<Chunk, TokenChunkingError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+        while self.window.len() < self.chunk_size {
+            match self.tokens.next_tokens() {
+                Ok(Some(tokens)) => self.window.extend(tokens),
+                Ok(None) => break,
+                Err(error) => {
+                    self.failed = true;
+                    return Some(Err(error));
+                }
+            }
+        }
+        if self.window.is_empty() {
+            return None;
+        }
+        let end: usize = self.chunk_size.min(self.window.len());
+        let content: String = self.window.range(..end).map(String::as_str).collect();
+        // Fewer tokens than the step are only left at the end of the text
+        let step: usize = self.step.min(self.window.len());
+        self.window.drain(..step);
+        Some(Ok(Chunk::new(content.trim())))
     }
 }
 
@@ -157,7 +277,9 @@ pub enum TokenChunkingError {
 mod tests {
 
     use super::*;
+    use crate::common::Chunks;
     use crate::common::OpenAIEmbeddingModel::TextEmbeddingAda002;
+    use std::sync::{Arc, Mutex};
 
     #[test]
     fn test_generate_chunks_with_valid_input() {
@@ -194,6 +316,20 @@ mod tests {
         assert_eq!(chunks, Vec::<String>::new());
     }
 
+    #[test]
+    fn test_generate_chunks_with_metadata() {
+        let metadata = serde_json::json!({"source": "notes.txt", "page": 3});
+        let chunk_size: NonZeroUsize = NonZeroUsize::new(2).unwrap();
+        let chunker: TokenChunker =
+            TokenChunker::try_new(chunk_size, 0, TextEmbeddingAda002).unwrap();
+        let chunks: Chunks = chunker
+            .generate_chunks_with_metadata("This is a test string", metadata.clone())
+            .unwrap();
+        assert_eq!(chunks.len(), 3);
+        assert!(chunks.iter().all(|chunk| chunk.metadata() == &metadata));
+        assert_eq!(chunks[1].content(), "a test");
+    }
+
     #[test]
     fn test_generate_chunks_with_invalid_window_size() {
         let window_size: usize = 3;
@@ -207,4 +343,150 @@ mod tests {
         let chunk_size: NonZeroUsize = NonZeroUsize::new(20000).unwrap();
         assert!(TokenChunker::try_new(chunk_size, window_size, TextEmbeddingAda002).is_err());
     }
+
+    #[test]
+    fn test_windowed_chunks_match_tokenizing_at_once() {
+        let raw_text: String = generated_text(200);
+        for (chunk_size, chunk_overlap) in [(1, 0), (7, 3), (50, 0), (300, 299)] {
+            let mut chunker = chunker(chunk_size, chunk_overlap);
+            let expected: Vec<String> = chunk_at_once(&chunker, &raw_text);
+            // Small windows cut the text in many places
+            for window_bytes in [16, 255, 4096, DEFAULT_WINDOW_BYTES] {
+                chunker.window_bytes = window_bytes;
+                assert_eq!(contents(&chunker, &raw_text), expected, "{window_bytes}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_multi_megabyte_text_is_tokenized_a_window_at_a_time() {
+        let raw_text: String = generated_text(22_000);
+        assert!(raw_text.len() > 2 * 1024 * 1024);
+        let tokenized: Arc<Mutex<Vec<usize>>> = Arc::default();
+        let mut chunker = chunker(100, 10);
+        chunker.tokenizer = Box::new(RecordingTokenizer {
+            inner: chunker.tokenizer,
+            tokenized: tokenized.clone(),
+        });
+
+        // The first chunks only need the first window
+        let first: Vec<Chunk> = chunker
+            .chunk_iter(&raw_text)
+            .take(3)
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(first.len(), 3);
+        assert_eq!(tokenized.lock().unwrap().len(), 1);
+
+        tokenized.lock().unwrap().clear();
+        let chunks: usize = chunker.chunk_iter(&raw_text).map(Result::unwrap).count();
+        let tokenized = tokenized.lock().unwrap();
+        assert!(chunks > 5_000);
+        assert!(tokenized.len() > 30);
+        // A window ends at the first char boundary after its size
+        assert!(tokenized
+            .iter()
+            .all(|&bytes| bytes < DEFAULT_WINDOW_BYTES + 4));
+    }
+
+    #[test]
+    fn test_tokenization_errors_are_yielded_once_reached() {
+        let raw_text: String = format!("{}bad{}", "a ".repeat(100), "b ".repeat(100));
+        let mut chunker = chunker(10, 0);
+        chunker.tokenizer = Box::new(FailingTokenizer);
+        chunker.window_bytes = 64;
+        let chunks: Vec<Result<Chunk, TokenChunkingError>> =
+            chunker.chunk_iter(&raw_text).collect();
+        let (error, before) = chunks.split_last().unwrap();
+        assert!(before.len() >= 2);
+        assert!(before.iter().all(Result::is_ok));
+        assert_eq!(
+            *error,
+            Err(TokenChunkingError::TokenizationError(
+                "Unable to tokenize text".into()
+            ))
+        );
+        assert!(chunker.generate_chunks(&raw_text).is_err());
+    }
+
+    #[test]
+    fn test_tokenizers_dropping_text_are_not_windowed() {
+        let raw_text: String = generated_text(20);
+        let mut chunker = chunker(5, 1);
+        chunker.tokenizer = Box::new(WordTokenizer);
+        chunker.window_bytes = 64;
+        let expected: Vec<String> = chunk_at_once(&chunker, &raw_text);
+        assert_eq!(contents(&chunker, &raw_text), expected);
+    }
+
+    fn chunker(chunk_size: usize, chunk_overlap: usize) -> TokenChunker {
+        let chunk_size: NonZeroUsize = NonZeroUsize::new(chunk_size).unwrap();
+        TokenChunker::try_new(chunk_size, chunk_overlap, TextEmbeddingAda002).unwrap()
+    }
+
+    fn generated_text(paragraphs: usize) -> String {
+        (0..paragraphs)
+            .map(|i| {
+                format!(
+                    "Paragraph {i}: the café's   naïve owner said \"hello, world!\" at 10:{:02}pm.\n\n  It rained {} times.\t\r\n",
+                    i % 60,
+                    "very ".repeat(i % 7)
+                )
+            })
+            .collect()
+    }
+
+    fn contents(chunker: &TokenChunker, raw_text: &str) -> Vec<String> {
+        chunker
+            .chunk_iter(raw_text)
+            .map(|chunk| chunk.unwrap().content().to_string())
+            .collect()
+    }
+
+    // How the chunks were built before the text was tokenized in windows
+    fn chunk_at_once(chunker: &TokenChunker, raw_text: &str) -> Vec<String> {
+        let tokens: Vec<String> = chunker.tokenizer.tokenize(raw_text).unwrap();
+        let chunk_size: usize = chunker.chunk_size.into();
+        (0..tokens.len())
+            .step_by(chunk_size - chunker.chunk_overlap)
+            .map(|i| {
+                let end = std::cmp::min(i + chunk_size, tokens.len());
+                tokens[i..end].join("").trim().to_string()
+            })
+            .collect()
+    }
+
+    // Records the length of every text it is given
+    struct RecordingTokenizer {
+        inner: Box<dyn TokenizerWrapper>,
+        tokenized: Arc<Mutex<Vec<usize>>>,
+    }
+
+    impl TokenizerWrapper for RecordingTokenizer {
+        fn tokenize(&self, text: &str) -> Option<Vec<String>> {
+            self.tokenized.lock().unwrap().push(text.len());
+            self.inner.tokenize(text)
+        }
+    }
+
+    // Splits into characters and fails on any text containing "bad"
+    struct FailingTokenizer;
+
+    impl TokenizerWrapper for FailingTokenizer {
+        fn tokenize(&self, text: &str) -> Option<Vec<String>> {
+            match text.contains("bad") {
+                true => None,
+                false => Some(text.chars().map(String::from).collect()),
+            }
+        }
+    }
+
+    // Drops the whitespace between words so the tokens do not add up to the text
+    struct WordTokenizer;
+
+    impl TokenizerWrapper for WordTokenizer {
+        fn tokenize(&self, text: &str) -> Option<Vec<String>> {
+            Some(text.split_whitespace().map(String::from).collect())
+        }
+    }
 }
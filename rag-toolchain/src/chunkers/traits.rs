@@ -1,15 +1,205 @@
-use crate::common::{Chunk, Chunks};
+use crate::chunkers::batch::{chunk_in_parallel, ChunkBatchError};
+use crate::common::{Chunk, Chunks, Document, CHUNK_INDEX_KEY};
 use futures::Stream;
 use std::error::Error;
 
 pub trait Chunker {
-    type ErrorType: Error;
-    fn generate_chunks(&self, raw_text: &str) -> Result<Chunks, Self::ErrorType>;
+    type ErrorType: Error + Send + Sync;
+
+    /// # [`Chunker::generate_chunks`]
+    ///
+    /// Collects every chunk of the text, see [`Chunker::chunk_iter`].
+    fn generate_chunks(&self, raw_text: &str) -> Result<Chunks, Self::ErrorType> {
+        self.chunk_iter(raw_text).collect()
+    }
+
+    /// # [`Chunker::generate_chunks_with_metadata`]
+    ///
+    /// Collects every chunk of the text with the metadata attached to each one, such as
+    /// the source document the text came from. The metadata is merged as in
+    /// [`Chunker::chunk_document`], so the chunk's own keys such as markdown headings are
+    /// kept over the metadata's.
+    ///
+    /// # Arguments
+    /// * `raw_text`: &[`str`] - The raw text to generate chunks from
+    /// * `metadata`: [`serde_json::Value`] - The metadata to attach to every chunk
+    ///
+    /// # Errors
+    /// * [`Self::ErrorType`] - if the text could not be prepared for chunking
+    ///
+    /// # Returns
+    /// * [`Chunks`] - the chunks, each holding the metadata merged with its own
+    fn generate_chunks_with_metadata(
+        &self,
+        raw_text: &str,
+        metadata: serde_json::Value,
+    ) -> Result<Chunks, Self::ErrorType> {
+        self.chunk_iter(raw_text)
+            .map(|chunk| {
+                chunk.map(|chunk| {
+                    let metadata = merge_metadata(&metadata, chunk.metadata());
+                    Chunk::new_with_metadata(chunk.content(), metadata)
+                })
+            })
+            .collect()
+    }
+
+    /// # [`Chunker::chunk_document`]
+    ///
+    /// Chunks the text of a loaded [`Document`] carrying its metadata into every chunk.
+    /// When both the document and a chunk have object metadata the keys are merged, with
+    /// the chunk's own keys such as markdown headings kept over the document's.
+    ///
+    /// # Arguments
+    /// * `document`: &[`Document`] - The document to generate chunks from
+    ///
+    /// # Errors
+    /// * [`Self::ErrorType`] - if the text could not be chunked
+    ///
+    /// # Returns
+    /// * [`Chunks`] - the chunks, each holding the metadata of the document
+    fn chunk_document(&self, document: &Document) -> Result<Chunks, Self::ErrorType> {
+        self.chunk_iter(document.content())
+            .map(|chunk| {
+                chunk.map(|chunk| {
+                    let metadata = merge_metadata(document.metadata(), chunk.metadata());
+                    Chunk::new_with_metadata(chunk.content(), metadata)
+                })
+            })
+            .collect()
+    }
+
+    /// # [`Chunker::chunk_document_indexed`]
+    ///
+    /// The same as [`Chunker::chunk_document`] but each chunk is also stamped with its position
+    /// in the document under [`CHUNK_INDEX_KEY`], counting from 0. Along with a document id in
+    /// the document's metadata this lets a retrieved chunk be expanded with its neighbours, see
+    /// [`crate::chains::NeighborExpansion`]. Metadata which is neither an object nor null is
+    /// left as it is since there is nowhere to put the index.
+    ///
+    /// # Arguments
+    /// * `document`: &[`Document`] - The document to generate chunks from
+    ///
+    /// # Errors
+    /// * [`Self::ErrorType`] - if the text could not be chunked
+    ///
+    /// # Returns
+    /// * [`Chunks`] - the chunks, each holding the metadata of the document and its index
+    fn chunk_document_indexed(&self, document: &Document) -> Result<Chunks, Self::ErrorType> {
+        self.chunk_document(document).map(|chunks| {
+            chunks
+                .into_iter()
+                .enumerate()
+                .map(|(index, chunk)| {
+                    let metadata = with_chunk_index(chunk.metadata(), index);
+                    Chunk::new_with_metadata(chunk.content(), metadata)
+                })
+                .collect()
+        })
+    }
+
+    /// # [`Chunker::generate_chunks_batch`]
+    ///
+    /// Chunks many documents in parallel across the available cores. A document which
+    /// fails does not stop the others from being chunked.
+    ///
+    /// # Arguments
+    /// * `texts`: &[[`String`]] - The documents to chunk
+    ///
+    /// # Errors
+    /// * [`ChunkBatchError`] - if any of the documents could not be chunked, holding the
+    ///   error of each one along with the chunks of the rest
+    ///
+    /// # Returns
+    /// * [`Vec<Chunks>`] - the chunks of each document in input order
+    fn generate_chunks_batch(
+        &self,
+        texts: &[String],
+    ) -> Result<Vec<Chunks>, ChunkBatchError<Self::ErrorType>>
+    where
+        Self: Sync,
+    {
+        chunk_in_parallel(self, texts)
+    }
+
+    /// # [`Chunker::chunk_iter`]
+    ///
+    /// Produces the chunks of the text on demand, so a large text never has all of its
+    /// chunks in memory at once. A chunker which has to prepare the whole text up front
+    /// yields an error preparing it as the only item.
+    ///
+    /// # Arguments
+    /// * `raw_text`: &[`str`] - The raw text to generate chunks from
+    ///
+    /// # Errors
+    /// * [`Self::ErrorType`] - yielded if part of the text could not be chunked, nothing
+    ///   is yielded after an error
+    ///
+    /// # Returns
+    /// * impl [`Iterator<Item = Result<Chunk, Self::ErrorType>>`] - the chunks in the order
+    ///   they appear in the text
+    fn chunk_iter<'a>(
+        &'a self,
+        raw_text: &'a str,
+    ) -> impl Iterator<Item = Result<Chunk, Self::ErrorType>> + 'a;
+}
+
+/// # [`prepared_chunks`]
+///
+/// Yields the chunks of a chunker which prepares the whole text up front from
+/// [`Chunker::chunk_iter`], an error preparing them is yielded as the only item.
+pub(crate) fn prepared_chunks<I, E>(
+    prepared: Result<I, E>,
+) -> impl Iterator<Item = Result<Chunk, E>>
+where
+    I: IntoIterator<Item = Chunk>,
+{
+    let (chunks, error) = match prepared {
+        Ok(chunks) => (Some(chunks), None),
+        Err(error) => (None, Some(error)),
+    };
+    error
+        .map(Err)
+        .into_iter()
+        .chain(chunks.into_iter().flatten().map(Ok))
+}
+
+/// # [`merge_metadata`]
+///
+/// Merges the metadata of a document into the metadata of one of its chunks. Objects are
+/// merged with the chunk's keys winning, otherwise whichever is not null is kept and the
+/// document's metadata is preferred.
+fn merge_metadata(document: &serde_json::Value, chunk: &serde_json::Value) -> serde_json::Value {
+    match (document, chunk) {
+        (serde_json::Value::Object(document), serde_json::Value::Object(chunk)) => {
+            let mut merged = document.clone();
+            merged.extend(chunk.clone());
+            serde_json::Value::Object(merged)
+        }
+        (serde_json::Value::Null, chunk) => chunk.clone(),
+        (document, _) => document.clone(),
+    }
+}
+
+/// # [`with_chunk_index`]
+///
+/// Adds the index of a chunk to its metadata, null metadata becomes an object holding only
+/// the index.
+fn with_chunk_index(metadata: &serde_json::Value, index: usize) -> serde_json::Value {
+    match metadata {
+        serde_json::Value::Object(object) => {
+            let mut stamped = object.clone();
+            stamped.insert(CHUNK_INDEX_KEY.into(), index.into());
+            serde_json::Value::Object(stamped)
+        }
+        serde_json::Value::Null => serde_json::json!({ CHUNK_INDEX_KEY: index }),
+        metadata => metadata.clone(),
+    }
 }
 
 #[allow(unused)]
 pub trait StreamedChunker {
-    type ErrorType: Error;
+    type ErrorType: Error + Send + Sync;
     type CharacterStream: Stream<Item = std::io::Result<char>>;
     type ChunkStream: Stream<Item = Result<Chunk, Self::ErrorType>>;
     fn generate_chunks(&self, data_stream: Self::CharacterStream) -> Self::ChunkStream;
@@ -0,0 +1,116 @@
+use serde_json::{Map, Value};
+use std::env::VarError;
+use thiserror::Error;
+
+/// # [`AdditionalConfigError`]
+/// The errors that can occur when creating a chat client with additional config.
+#[derive(Error, Debug, PartialEq)]
+pub enum AdditionalConfigError {
+    /// The additional config sets keys the client sets itself, sending both would give
+    /// the request duplicate keys. Holds the offending keys.
+    #[error("Additional config cannot set the reserved keys: {}", .0.join(", "))]
+    ReservedKeys(Vec<String>),
+    #[error("Environment Variable Error: {0}")]
+    EnvVarError(VarError),
+}
+
+/// # [`check_reserved_keys`]
+///
+/// # Arguments
+/// * `additional_config`: &[`Map<String, Value>`] - the config sent alongside the request.
+/// * `reserved`: &[`[&str]`] - the keys the client sets in every request.
+///
+/// # Errors
+/// * [`AdditionalConfigError::ReservedKeys`] - listing every reserved key in the config.
+pub(crate) fn check_reserved_keys(
+    additional_config: &Map<String, Value>,
+    reserved: &[&str],
+) -> Result<(), AdditionalConfigError> {
+    let conflicting: Vec<String> = reserved
+        .iter()
+        .filter(|key| additional_config.contains_key(**key))
+        .map(|key| key.to_string())
+        .collect();
+    match conflicting.is_empty() {
+        true => Ok(()),
+        false => Err(AdditionalConfigError::ReservedKeys(conflicting)),
+    }
+}
+
+/// # [`deep_merge`]
+///
+/// Writes the source into the config, objects under the same key are merged key by key
+/// so setting one entry of an object such as `metadata` keeps the others. Any other value
+/// replaces the one in the config.
+///
+/// # Arguments
+/// * `config`: &mut [`Map<String, Value>`] - the config to merge into.
+/// * `source`: [`Map<String, Value>`] - the values to write.
+pub(crate) fn deep_merge(config: &mut Map<String, Value>, source: Map<String, Value>) {
+    for (key, value) in source {
+        match (config.get_mut(&key), value) {
+            (Some(Value::Object(existing)), Value::Object(value)) => deep_merge(existing, value),
+            (_, value) => {
+                config.insert(key, value);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn map(value: Value) -> Map<String, Value> {
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn check_reserved_keys_lists_every_conflict() {
+        let config = map(json!({"stream": true, "temperature": 0.5, "model": "other"}));
+        assert_eq!(
+            check_reserved_keys(&config, &["model", "messages", "stream"]),
+            Err(AdditionalConfigError::ReservedKeys(vec![
+                "model".into(),
+                "stream".into()
+            ]))
+        );
+        assert_eq!(check_reserved_keys(&config, &["messages"]), Ok(()));
+    }
+
+    #[test]
+    fn deep_merge_merges_nested_objects() {
+        let mut config = map(json!({
+            "metadata": {"user_id": "a", "nested": {"x": 1}},
+            "temperature": 1.0
+        }));
+        deep_merge(
+            &mut config,
+            map(json!({
+                "metadata": {"nested": {"y": 2}, "team": "b"},
+                "temperature": 0.5
+            })),
+        );
+        assert_eq!(
+            Value::Object(config),
+            json!({
+                "metadata": {"user_id": "a", "nested": {"x": 1, "y": 2}, "team": "b"},
+                "temperature": 0.5
+            })
+        );
+    }
+
+    #[test]
+    fn deep_merge_replaces_values_of_a_different_type() {
+        let mut config = map(json!({"stop": ["a"], "metadata": "none"}));
+        deep_merge(
+            &mut config,
+            map(json!({"stop": ["b"], "metadata": {"user_id": "a"}})),
+        );
+        assert_eq!(
+            Value::Object(config),
+            json!({"stop": ["b"], "metadata": {"user_id": "a"}})
+        );
+    }
+}
@@ -1,26 +1,46 @@
 use crate::clients::anthropic::model::errors::{AnthropicError, AnthropicErrorBody};
+use crate::clients::secrets::{
+    CachedSecret, EnvSecretProvider, SecretProvider, SecretString, DEFAULT_SECRET_TTL,
+};
+use crate::clients::{HttpConfig, HttpConfigError, RequestContext};
+use crate::common::Clock;
 
 use dotenv::dotenv;
 use reqwest::header::{HeaderValue, CONTENT_TYPE};
 use reqwest::{Client, RequestBuilder, Response, StatusCode};
+#[cfg(feature = "anthropic-stream")]
+use reqwest_eventsource::{EventSource, RequestBuilderExt};
 use serde::de::DeserializeOwned;
 use serde::Serialize;
 use std::env;
 use std::env::VarError;
+use std::sync::Arc;
+use std::time::Instant;
+
+#[cfg(test)]
+use crate::clients::cassette::RecordingHttpClient;
 
 const API_KEY_HEADER: &str = "x-api-key";
 const API_VERSION_HEADER: &str = "anthropic-version";
 const API_VERSION: &str = "2023-06-01";
+/// The name of the secret holding the API key
+const API_KEY_SECRET: &str = "ANTHROPIC_API_KEY";
 
 #[derive(Debug)]
 pub struct AnthropicHttpClient {
     client: Client,
-    api_key: String,
+    /// Set when the client was given by the caller, so it is kept when the timeouts change
+    custom_client: bool,
+    api_key: CachedSecret,
+    http_config: HttpConfig,
+    #[cfg(test)]
+    recorder: Option<Arc<RecordingHttpClient>>,
 }
 
 impl AnthropicHttpClient {
     /// # [`AnthropicHttpClient::try_new`]
-    /// Must have the ANTHROPIC_API_KEY environment variable set
+    /// Must have the ANTHROPIC_API_KEY environment variable set. The variable is read
+    /// again once the cached key expires so a rotated key is picked up.
     ///
     /// # Errors
     /// * [`VarError`] - If the ANTHROPIC_API_KEY environment variable is not set
@@ -29,23 +49,87 @@ impl AnthropicHttpClient {
     /// * [`AnthropicHttpClient`] - The newly created AnthropicHttpClient
     pub fn try_new() -> Result<AnthropicHttpClient, VarError> {
         dotenv().ok();
-        let api_key: String = match env::var::<String>("ANTHROPIC_API_KEY".into()) {
-            Ok(api_key) => api_key,
-            Err(e) => return Err(e),
-        };
-        let client: Client = Client::new();
-        Ok(AnthropicHttpClient { api_key, client })
+        let api_key: String = env::var::<String>(API_KEY_SECRET.into())?;
+        let mut client = Self::new_with_secret_provider(Arc::new(EnvSecretProvider));
+        client.api_key = client
+            .api_key
+            .with_initial_value(SecretString::new(api_key));
+        Ok(client)
+    }
+
+    /// # [`AnthropicHttpClient::new_with_secret_provider`]
+    /// The API key is fetched from the provider when the first request is sent
+    /// and cached for [`DEFAULT_SECRET_TTL`].
+    ///
+    /// # Arguments
+    /// * `provider` - Where the ANTHROPIC_API_KEY secret is fetched from
+    ///
+    /// # Returns
+    /// * [`AnthropicHttpClient`] - The newly created AnthropicHttpClient
+    pub fn new_with_secret_provider(provider: Arc<dyn SecretProvider>) -> AnthropicHttpClient {
+        AnthropicHttpClient {
+            api_key: CachedSecret::new(provider, API_KEY_SECRET, DEFAULT_SECRET_TTL),
+            http_config: HttpConfig::default(),
+            client: HttpConfig::default_client(),
+            custom_client: false,
+            #[cfg(test)]
+            recorder: None,
+        }
+    }
+
+    /// # [`AnthropicHttpClient::set_http_config`]
+    /// Sets the timeouts requests are sent with, by default these are [`HttpConfig::default`].
+    /// The connect timeout is not applied to a client given to [`AnthropicHttpClient::set_http_client`].
+    ///
+    /// # Arguments
+    /// * `http_config` - The timeouts to send requests with
+    ///
+    /// # Errors
+    /// * [`HttpConfigError::ClientBuildError`] - if the client could not be built, the config is not changed
+    pub fn set_http_config(&mut self, http_config: HttpConfig) -> Result<(), HttpConfigError> {
+        if !self.custom_client {
+            self.client = http_config.build_client()?;
+        }
+        self.http_config = http_config;
+        Ok(())
+    }
+
+    /// # [`AnthropicHttpClient::set_http_client`]
+    /// Sends every request, including streamed ones, with the given client. Use this to
+    /// send requests through a proxy or to trust a custom certificate. The client keeps
+    /// its own connect timeout, the request timeout from [`HttpConfig`] is still applied.
+    ///
+    /// # Arguments
+    /// * `client` - The preconfigured client to send requests with
+    pub fn set_http_client(&mut self, client: Client) {
+        self.client = client;
+        self.custom_client = true;
+    }
+
+    /// # [`AnthropicHttpClient::set_clock`]
+    /// Sets the clock the cached API key expires with, by default this is
+    /// [`crate::common::SystemClock`].
+    ///
+    /// # Arguments
+    /// * `clock` - The clock to measure time with
+    pub fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        self.api_key.set_clock(clock);
     }
 
     /// # [`AnthropicHttpClient::send_request`]
-    /// Sends a request to the Anthropic API and returns the response
+    /// Sends a request to the Anthropic API and returns the response. If Anthropic rejects
+    /// the API key with a 401 the key is fetched again and, if it has changed, the request
+    /// is retried once with the new key.
     ///
     /// # Arguments
     /// * `body` - The body of the request
     /// * `url` - The url to send the request to
     ///
     /// # Errors
+    /// * [`AnthropicError::ErrorFetchingApiKey`] - if the API key could not be fetched
+    /// * [`AnthropicError::Request`] - wraps any of the errors below with the [`RequestContext`]
     /// * [`AnthropicError::ErrorSendingRequest`] - if request.send() errors
+    /// * [`AnthropicError::Timeout`] - if the request took longer than the timeouts in the [`HttpConfig`]
     /// * [`AnthropicError::ErrorGettingResponseBody`] - if response.text() errors
     /// * [`AnthropicError::ErrorDeserializingResponseBody`] - if serde_json::from_str() errors
     /// * [`AnthropicError`] - if the response code is not 200 this can be any of the associates status
@@ -58,11 +142,81 @@ impl AnthropicHttpClient {
         T: Serialize,
         U: DeserializeOwned,
     {
-        let request = self.build_requeset(body, url);
-        let response: reqwest::Response = request
-            .send()
+        let api_key: SecretString = self
+            .api_key
+            .get()
+            .await
+            .map_err(AnthropicError::ErrorFetchingApiKey)?;
+        let started: Instant = Instant::now();
+        let mut attempt: u32 = 1;
+        let result: Result<U, AnthropicError> = async {
+            match self.send(self.build_requeset(&body, url, &api_key)).await {
+                Err(AnthropicError::CODE401(error_body)) => {
+                    let refreshed: SecretString = self
+                        .api_key
+                        .refresh(&api_key)
+                        .await
+                        .map_err(AnthropicError::ErrorFetchingApiKey)?;
+                    if refreshed == api_key {
+                        return Err(AnthropicError::CODE401(error_body));
+                    }
+                    attempt += 1;
+                    self.send(self.build_requeset(&body, url, &refreshed)).await
+                }
+                result => result,
+            }
+        }
+        .await;
+        result.map_err(|error| {
+            error.with_context(RequestContext::new(url, &body, started.elapsed(), attempt))
+        })
+    }
+
+    /// # [`AnthropicHttpClient::send_stream_request`]
+    ///
+    /// Sends a request to the Anthropic API and returns the response as an EventSource
+    /// this will be used for the streaming implementations that use SSE. A 401 is only
+    /// reported once the stream is read so these requests are not retried with a new key.
+    ///
+    /// # Arguments
+    /// * `body` - The body of the request, this should have stream set to true
+    /// * `url` - The url to send the request to
+    ///
+    /// # Errors
+    /// * [`AnthropicError::ErrorFetchingApiKey`] - if the API key could not be fetched
+    /// * [`AnthropicError::ErrorSendingRequest`] - if the request could not be turned into an EventSource
+    ///
+    /// # Returns
+    /// [`EventSource`] - The stream of events sent by Anthropic
+    #[cfg(feature = "anthropic-stream")]
+    pub async fn send_stream_request<T>(
+        &self,
+        body: T,
+        url: &str,
+    ) -> Result<EventSource, AnthropicError>
+    where
+        T: Serialize,
+    {
+        let api_key: SecretString = self
+            .api_key
+            .get()
             .await
-            .map_err(|error| AnthropicError::ErrorSendingRequest(error.to_string()))?;
+            .map_err(AnthropicError::ErrorFetchingApiKey)?;
+        self.build_requeset(&body, url, &api_key)
+            .eventsource()
+            .map_err(|error| AnthropicError::ErrorSendingRequest(error.to_string()))
+    }
+
+    /// # [`AnthropicHttpClient::send`]
+    ///
+    /// Sends the built request, mapping any error status codes and
+    /// deserializing the body of a successful response.
+    async fn send<U>(&self, request: RequestBuilder) -> Result<U, AnthropicError>
+    where
+        U: DeserializeOwned,
+    {
+        let request: RequestBuilder = request.timeout(self.http_config.timeout);
+        let response: reqwest::Response = self.execute(request).await?;
 
         let status_code: StatusCode = response.status();
 
@@ -71,28 +225,57 @@ impl AnthropicHttpClient {
             return Err(mapped_error);
         }
 
-        let response_body: String = response
-            .text()
-            .await
-            .map_err(|error| AnthropicError::ErrorGettingResponseBody(error.to_string()))?;
+        let response_body: String = response.text().await.map_err(|error| {
+            AnthropicError::from_reqwest(error, AnthropicError::ErrorGettingResponseBody)
+        })?;
 
         serde_json::from_str(&response_body).map_err(|error| {
             AnthropicError::ErrorDeserializingResponseBody(status_code.as_u16(), error.to_string())
         })
     }
 
+    /// # [`AnthropicHttpClient::set_recorder`]
+    ///
+    /// Test only hook which routes all requests through a cassette instead of the network.
+    #[cfg(test)]
+    pub fn set_recorder(&mut self, recorder: Arc<RecordingHttpClient>) {
+        self.recorder = Some(recorder);
+    }
+
+    /// # [`AnthropicHttpClient::execute`]
+    ///
+    /// Sends the request, unless a recorder has been set in which case
+    /// the recorder decides whether to replay or record the interaction.
+    async fn execute(&self, request: RequestBuilder) -> Result<Response, AnthropicError> {
+        #[cfg(test)]
+        if let Some(recorder) = &self.recorder {
+            return recorder
+                .execute(&self.client, request)
+                .await
+                .map_err(AnthropicError::ErrorSendingRequest);
+        }
+        request.send().await.map_err(|error| {
+            AnthropicError::from_reqwest(error, AnthropicError::ErrorSendingRequest)
+        })
+    }
+
     /// # [`AnthropicHttpClient::build_requeset`]
     ///
     /// Helper method to build a request with the correct headers and body
     /// We are required to set a speceific API version on each request
-    fn build_requeset<T>(&self, request_body: T, url: &str) -> RequestBuilder
+    fn build_requeset<T>(
+        &self,
+        request_body: &T,
+        url: &str,
+        api_key: &SecretString,
+    ) -> RequestBuilder
     where
         T: Serialize,
     {
         let content_type = HeaderValue::from_static("application/json");
         self.client
             .post(url)
-            .header(API_KEY_HEADER, self.api_key.clone())
+            .header(API_KEY_HEADER, api_key.expose_secret())
             .header(API_VERSION_HEADER, API_VERSION)
             .header(CONTENT_TYPE, content_type)
             .json(&request_body)
@@ -107,7 +290,7 @@ impl AnthropicHttpClient {
     ///
     /// # Returns
     /// [`AnthropicError`] - The error type that maps to the response code
-    async fn handle_error_response(response: Response) -> AnthropicError {
+    pub(crate) async fn handle_error_response(response: Response) -> AnthropicError {
         // Map response objects into some form of enum error
         let status_code = response.status().as_u16();
         let body_text = match response.text().await {
@@ -138,8 +321,12 @@ impl AnthropicHttpClient {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::clients::secrets::tests::{MissingProvider, ScriptedProvider};
+    use crate::clients::secrets::SecretError;
     use mockito::{Mock, Server, ServerGuard};
     use serde::{Deserialize, Serialize};
+    use std::sync::atomic::Ordering;
+    use std::time::Duration;
 
     const ERROR_RESPONSE: &'static str = r#"
     {
@@ -151,11 +338,20 @@ mod tests {
     } 
     "#;
 
-    #[test]
-    fn missing_env_var_returns_error() {
-        std::env::remove_var("ANTHROPIC_API_KEY");
-        let error = AnthropicHttpClient::try_new().unwrap_err();
-        assert_eq!(VarError::NotPresent, error);
+    #[tokio::test]
+    async fn missing_api_key_returns_error() {
+        let client = AnthropicHttpClient::new_with_secret_provider(Arc::new(MissingProvider));
+        let body = RequestBody {
+            message: "hello".into(),
+        };
+        let error = client
+            .send_request::<RequestBody, RequestBody>(body, "http://localhost")
+            .await
+            .unwrap_err();
+        assert_eq!(
+            error,
+            AnthropicError::ErrorFetchingApiKey(SecretError::NotFound(API_KEY_SECRET.into()))
+        );
     }
 
     #[tokio::test]
@@ -239,7 +435,36 @@ mod tests {
             "expected value at line 1 column 1".into(),
         );
         mock.assert();
-        assert_eq!(expected_error, error);
+        assert_eq!(&expected_error, error.kind());
+    }
+
+    #[tokio::test]
+    async fn slow_response_times_out() {
+        let body = RequestBody {
+            message: "hello".into(),
+        };
+        let (mut client, mut server) = with_mocked_client().await;
+        client
+            .set_http_config(HttpConfig {
+                timeout: Duration::from_millis(100),
+                ..HttpConfig::default()
+            })
+            .unwrap();
+        // The request is only counted once the delayed body is written, so it is not asserted
+        let _mock = server
+            .mock("POST", "/")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_chunked_body(|writer| {
+                std::thread::sleep(Duration::from_millis(500));
+                writer.write_all(br#"{"message": "hello"}"#)
+            })
+            .create();
+        let error = client
+            .send_request::<RequestBody, RequestBody>(body, &server.url())
+            .await
+            .unwrap_err();
+        assert!(matches!(error.kind(), AnthropicError::Timeout(_)));
     }
 
     // Helper method to assert all known status codes are mapped correctly
@@ -254,11 +479,40 @@ mod tests {
             .await
             .unwrap_err();
         mock.assert();
-        assert_eq!(expected_error, error);
+        assert_eq!(&expected_error, error.kind());
     }
 
     // Method which mocks the response the server will give. this
     // allows us to stub the requests instead of sending them to OpenAI
+    #[tokio::test]
+    async fn rejected_key_is_refreshed_and_retried_once() {
+        let mut server = Server::new_async().await;
+        let provider = ScriptedProvider::new(vec!["old key", "new key"]);
+        let client = AnthropicHttpClient::new_with_secret_provider(provider.clone());
+        let rejected = server
+            .mock("POST", "/")
+            .match_header(API_KEY_HEADER, "old key")
+            .with_status(401)
+            .with_header("content-type", "application/json")
+            .with_body(ERROR_RESPONSE)
+            .create();
+        let accepted = server
+            .mock("POST", "/")
+            .match_header(API_KEY_HEADER, "new key")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(r#"{"message": "hello"}"#)
+            .create();
+        let body = RequestBody {
+            message: "hello".into(),
+        };
+        let response: RequestBody = client.send_request(body, &server.url()).await.unwrap();
+        assert_eq!(response.message, "hello");
+        rejected.assert();
+        accepted.assert();
+        assert_eq!(provider.fetches.load(Ordering::SeqCst), 2);
+    }
+
     fn with_mocked_request(
         server: &mut ServerGuard,
         status_code: usize,
@@ -275,9 +529,9 @@ mod tests {
     // This methods returns a client which is pointing at the mocked url
     // and the mock server which we can orchestrate the stubbings on.
     async fn with_mocked_client() -> (AnthropicHttpClient, ServerGuard) {
-        std::env::set_var("ANTHROPIC_API_KEY", "fake key");
         let server = Server::new_async().await;
-        let client = AnthropicHttpClient::try_new().unwrap();
+        let client =
+            AnthropicHttpClient::new_with_secret_provider(ScriptedProvider::new(vec!["fake key"]));
         (client, server)
     }
 
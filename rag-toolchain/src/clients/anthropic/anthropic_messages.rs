@@ -1,17 +1,82 @@
 use std::env::VarError;
+use std::sync::Arc;
 
 use crate::clients::anthropic::model::chat_completions::{
-    Content, MessagesRequest, MessagesResponse,
+    AnthropicStopReason, Content, MessagesRequest, MessagesResponse,
 };
-use crate::clients::{AsyncChatClient, PromptMessage};
+use crate::clients::{
+    check_reserved_keys, deep_merge, AdditionalConfigError, AsyncChatClient, DetailedChatResponse,
+    FinishReason, HttpConfig, HttpConfigError, ModelCapabilities, PromptMessage, SecretProvider,
+    TokenCounter,
+};
+#[cfg(feature = "anthropic-stream")]
+use crate::clients::{AsyncStreamedChatClient, ChatCompletionStream, CompletionStreamValue};
+use crate::common::{Clock, InvocationContext, TokenUsage};
 
 use super::model::chat_completions::{AnthropicModel, Message, Role};
+#[cfg(feature = "anthropic-stream")]
+use super::model::chat_completions::{ContentDelta, MessagesStreamEvent};
+#[cfg(feature = "anthropic-stream")]
+use super::model::errors::AnthropicErrorBody;
 use super::{anthropic_core::AnthropicHttpClient, model::errors::AnthropicError};
 
+#[cfg(feature = "anthropic-stream")]
+use futures::StreamExt;
+#[cfg(feature = "anthropic-stream")]
+use reqwest_eventsource::{Event, EventSource};
 use serde_json::{Map, Value};
+use tiktoken_rs::cl100k_base_singleton;
 
 const ANTHROPIC_MESSAGES_URL: &str = "https://api.anthropic.com/v1/messages";
 
+/// # [`SystemPromptMode`]
+///
+/// How the system messages passed to [`AnthropicChatCompletionClient::invoke`] are sent.
+///
+/// * [`SystemPromptMode::Concatenated`] - the default, the system messages are joined into a
+///   single string with each message followed by a new line.
+/// * [`SystemPromptMode::Blocks`] - each system message is sent as its own content block, this
+///   allows prompt caching to be applied to individual blocks. A single system message is still
+///   sent as a plain string.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum SystemPromptMode {
+    #[default]
+    Concatenated,
+    Blocks,
+}
+
+/// # [`AnthropicResponse`]
+///
+/// The full response from [`AnthropicChatCompletionClient::invoke_detailed`].
+///
+/// * `id` - the id Anthropic gave the message.
+/// * `message` - the text of every text block joined together as a [`PromptMessage::AIMessage`].
+/// * `content` - every content block in the order they were returned.
+/// * `stop_reason` - why generation stopped, [`None`] if Anthropic did not say.
+/// * `stop_sequence` - the stop sequence which was generated, if that is why it stopped.
+/// * `usage` - the tokens used by the request.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AnthropicResponse {
+    pub id: String,
+    pub message: PromptMessage,
+    pub content: Vec<Content>,
+    pub stop_reason: Option<AnthropicStopReason>,
+    pub stop_sequence: Option<String>,
+    pub usage: TokenUsage,
+}
+
+impl AnthropicResponse {
+    /// # [`AnthropicResponse::is_truncated`]
+    ///
+    /// # Returns
+    /// * [`bool`] - true if generation stopped because it reached max_tokens.
+    pub fn is_truncated(&self) -> bool {
+        self.stop_reason == Some(AnthropicStopReason::MaxTokens)
+    }
+}
+
 /// # [`AnthropicChatCompletionClient`]
 /// Allows for interacting with the Anthropic models via the messages API.
 ///
@@ -55,6 +120,8 @@ pub struct AnthropicChatCompletionClient {
     /// This is a required field on the messages API.
     /// Please refer to the API documentation for more information.
     max_tokens: u32,
+    system_prompt_mode: SystemPromptMode,
+    context_window: usize,
 }
 
 impl AnthropicChatCompletionClient {
@@ -62,6 +129,13 @@ impl AnthropicChatCompletionClient {
         System prompts should be included within the system field of the request.
         This error means that it was attempted to be included in the messages field.
     "#;
+    const NO_TEXT_RESPONSE_ERROR: &'static str =
+        "Expected a text block in the response but received none";
+    /// Images are resized to fit roughly 1.15 megapixels, which is around 1600 tokens
+    const IMAGE_TOKEN_ESTIMATE: usize = 1_600;
+    /// The keys the client sets in every request so cannot be in the additional config
+    pub const RESERVED_CONFIG_KEYS: &[&str] =
+        &["model", "messages", "max_tokens", "system", "stream"];
     /// # [`AnthropicChatCompletionClient::try_new`]
     ///
     /// This method creates a new instance of the AnthropicChatCompletionClient. All optional
@@ -70,7 +144,7 @@ impl AnthropicChatCompletionClient {
     /// # Arguments
     /// * `model`: [`AnthropicModel`] - The model to use for the chat completion.
     /// * `max_tokens`: [`u32`] - The maximum number of tokens to generate in the response.
-    ///                           See the API documentation for more information.
+    ///   See the API documentation for more information.
     ///
     /// # Errors
     /// [`VarError`] - This error is returned when the ANTHROPIC_API_KEY environment variable is not set.
@@ -82,26 +156,82 @@ impl AnthropicChatCompletionClient {
         Ok(AnthropicChatCompletionClient {
             url: ANTHROPIC_MESSAGES_URL.to_string(),
             client,
+            context_window: model.context_window(),
             model,
             additional_config: None,
             max_tokens,
+            system_prompt_mode: SystemPromptMode::default(),
         })
     }
 
+    /// # [`AnthropicChatCompletionClient::try_new_with_config`]
+    ///
+    /// This method creates a new instance of the AnthropicChatCompletionClient whose requests
+    /// use the given timeouts, see [`HttpConfig`].
+    ///
+    /// # Arguments
+    /// * `model`: [`AnthropicModel`] - The model to use for the chat completion.
+    /// * `max_tokens`: [`u32`] - The maximum number of tokens to generate in the response.
+    /// * `http_config`: [`HttpConfig`] - The request and connect timeouts.
+    ///
+    /// # Errors
+    /// [`HttpConfigError::EnvVarError`] - This error is returned when the ANTHROPIC_API_KEY environment variable is not set.
+    /// [`HttpConfigError::ClientBuildError`] - This error is returned when the HTTP client could not be built.
+    ///
+    /// # Returns
+    /// [`AnthropicChatCompletionClient`] - The client to interact with the Anthropic API.
+    pub fn try_new_with_config(
+        model: AnthropicModel,
+        max_tokens: u32,
+        http_config: HttpConfig,
+    ) -> Result<Self, HttpConfigError> {
+        Self::try_new(model, max_tokens)
+            .map_err(HttpConfigError::EnvVarError)?
+            .with_http_config(http_config)
+    }
+
+    /// # [`AnthropicChatCompletionClient::try_new_with_http_client`]
+    ///
+    /// This method creates a new instance of the AnthropicChatCompletionClient which sends
+    /// every request, including streamed ones, with the given client. Use this to send
+    /// requests through a proxy or to trust a custom certificate.
+    ///
+    /// # Arguments
+    /// * `model`: [`AnthropicModel`] - The model to use for the chat completion.
+    /// * `max_tokens`: [`u32`] - The maximum number of tokens to generate in the response.
+    /// * `http_client`: [`reqwest::Client`] - The preconfigured client to send requests with.
+    ///
+    /// # Errors
+    /// [`VarError`] - This error is returned when the ANTHROPIC_API_KEY environment variable is not set.
+    ///
+    /// # Returns
+    /// [`AnthropicChatCompletionClient`] - The client to interact with the Anthropic API.
+    pub fn try_new_with_http_client(
+        model: AnthropicModel,
+        max_tokens: u32,
+        http_client: reqwest::Client,
+    ) -> Result<Self, VarError> {
+        Ok(Self::try_new(model, max_tokens)?.with_http_client(http_client))
+    }
+
     /// # [`AnthropicChatCompletionClient::try_new_with_additional_config`]
     ///
     /// This method creates a new instance of the AnthropicChatCompletionClient. All optional
-    /// inference parameters will be set to their default values on Anthropic's end.
+    /// inference parameters will be set to their default values on Anthropic's end. The keys the
+    /// client sets itself, see [`AnthropicChatCompletionClient::RESERVED_CONFIG_KEYS`], cannot be
+    /// in the additional config.
     ///
     /// # Arguments
     /// * `model`: [`AnthropicModel`] - The model to use for the chat completion.
     /// * `max_tokens`: [`u32`] - The maximum number of tokens to generate in the response.
-    ///                           See the API documentation for more information.
+    ///   See the API documentation for more information.
     /// * `additional_config`: [`Map<String, Value>`] - Additional configuration to pass to the API.
-    ///                        See the API documentation for more information.
-    ///                        Examples of this can be temperature, top_p, etc.  
+    ///   See the API documentation for more information.
+    ///   Examples of this can be temperature, top_p, etc.
+    ///
     /// # Errors
-    /// [`VarError`] - This error is returned when the ANTHROPIC_API_KEY environment variable is not set.
+    /// * [`AdditionalConfigError::ReservedKeys`] - If the additional config sets any of the reserved keys.
+    /// * [`AdditionalConfigError::EnvVarError`] - If the ANTHROPIC_API_KEY environment variable is not set.
     ///
     /// # Returns
     /// [`AnthropicChatCompletionClient`] - The client to interact with the Anthropic API.
@@ -109,17 +239,338 @@ impl AnthropicChatCompletionClient {
         model: AnthropicModel,
         max_tokens: u32,
         additional_config: Map<String, Value>,
-    ) -> Result<Self, VarError> {
-        let client: AnthropicHttpClient = AnthropicHttpClient::try_new()?;
+    ) -> Result<Self, AdditionalConfigError> {
+        check_reserved_keys(&additional_config, Self::RESERVED_CONFIG_KEYS)?;
+        let client: AnthropicHttpClient =
+            AnthropicHttpClient::try_new().map_err(AdditionalConfigError::EnvVarError)?;
         Ok(AnthropicChatCompletionClient {
             url: ANTHROPIC_MESSAGES_URL.to_string(),
             client,
+            context_window: model.context_window(),
             model,
             additional_config: Some(additional_config),
             max_tokens,
+            system_prompt_mode: SystemPromptMode::default(),
+        })
+    }
+
+    /// # [`AnthropicChatCompletionClient::new_with_secret_provider`]
+    ///
+    /// This method creates a new instance of the AnthropicChatCompletionClient which fetches
+    /// the ANTHROPIC_API_KEY secret from the provider instead of the environment,
+    /// see [`SecretProvider`].
+    ///
+    /// # Arguments
+    /// * `model`: [`AnthropicModel`] - The model to use for the chat completion.
+    /// * `max_tokens`: [`u32`] - The maximum number of tokens to generate in the response.
+    /// * `provider`: impl [`SecretProvider`] - Where the API key is fetched from.
+    ///
+    /// # Returns
+    /// [`AnthropicChatCompletionClient`] - The client to interact with the Anthropic API.
+    pub fn new_with_secret_provider(
+        model: AnthropicModel,
+        max_tokens: u32,
+        provider: impl SecretProvider + 'static,
+    ) -> Self {
+        AnthropicChatCompletionClient {
+            url: ANTHROPIC_MESSAGES_URL.to_string(),
+            client: AnthropicHttpClient::new_with_secret_provider(Arc::new(provider)),
+            context_window: model.context_window(),
+            model,
+            additional_config: None,
+            max_tokens,
+            system_prompt_mode: SystemPromptMode::default(),
+        }
+    }
+
+    /// # [`AnthropicChatCompletionClient::with_system_prompt_mode`]
+    ///
+    /// Sets how system messages are sent, see [`SystemPromptMode`].
+    ///
+    /// # Arguments
+    /// * `system_prompt_mode`: [`SystemPromptMode`] - whether to send blocks or a single string.
+    ///
+    /// # Returns
+    /// [`AnthropicChatCompletionClient`] - the client with the new mode.
+    pub fn with_system_prompt_mode(mut self, system_prompt_mode: SystemPromptMode) -> Self {
+        self.system_prompt_mode = system_prompt_mode;
+        self
+    }
+
+    /// # [`AnthropicChatCompletionClient::with_additional_config`]
+    ///
+    /// Merges the config into the additional config sent with every request. Objects under
+    /// the same key, such as `metadata`, are merged key by key so the entries already set are
+    /// kept, any other value replaces the one already set.
+    ///
+    /// # Arguments
+    /// * `additional_config`: [`Map<String, Value>`] - the config to merge in.
+    ///
+    /// # Errors
+    /// * [`AdditionalConfigError::ReservedKeys`] - if the config sets any of the reserved keys,
+    ///   see [`AnthropicChatCompletionClient::RESERVED_CONFIG_KEYS`].
+    ///
+    /// # Returns
+    /// [`AnthropicChatCompletionClient`] - the client sending the merged config.
+    pub fn with_additional_config(
+        mut self,
+        additional_config: Map<String, Value>,
+    ) -> Result<Self, AdditionalConfigError> {
+        check_reserved_keys(&additional_config, Self::RESERVED_CONFIG_KEYS)?;
+        deep_merge(
+            self.additional_config.get_or_insert_with(Map::new),
+            additional_config,
+        );
+        Ok(self)
+    }
+
+    /// # [`AnthropicChatCompletionClient::with_clock`]
+    ///
+    /// Sets the clock the cached API key expires with,
+    /// by default this is [`crate::common::SystemClock`]. Pass a `MockClock` (with the
+    /// `test-utils` feature) to control time in tests.
+    ///
+    /// # Arguments
+    /// * `clock`: [`Arc<dyn Clock>`] - the clock to measure time with.
+    ///
+    /// # Returns
+    /// [`AnthropicChatCompletionClient`] - the client measuring time with the clock.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.client.set_clock(clock);
+        self
+    }
+
+    /// # [`AnthropicChatCompletionClient::with_http_config`]
+    ///
+    /// Sets the request and connect timeouts, by default these are
+    /// [`crate::clients::DEFAULT_REQUEST_TIMEOUT`] and [`crate::clients::DEFAULT_CONNECT_TIMEOUT`].
+    /// Streamed requests only use the connect timeout.
+    ///
+    /// # Arguments
+    /// * `http_config`: [`HttpConfig`] - the request and connect timeouts.
+    ///
+    /// # Errors
+    /// * [`HttpConfigError::ClientBuildError`] - if the HTTP client could not be built.
+    ///
+    /// # Returns
+    /// [`AnthropicChatCompletionClient`] - the client with the timeouts set.
+    pub fn with_http_config(mut self, http_config: HttpConfig) -> Result<Self, HttpConfigError> {
+        self.client.set_http_config(http_config)?;
+        Ok(self)
+    }
+
+    /// # [`AnthropicChatCompletionClient::with_http_client`]
+    ///
+    /// Sends every request, including streamed ones, with the given client rather than one
+    /// built from the [`HttpConfig`]. The request timeout is still applied.
+    ///
+    /// # Arguments
+    /// * `http_client`: [`reqwest::Client`] - the preconfigured client to send requests with.
+    ///
+    /// # Returns
+    /// [`AnthropicChatCompletionClient`] - the client sending requests with the given client.
+    pub fn with_http_client(mut self, http_client: reqwest::Client) -> Self {
+        self.client.set_http_client(http_client);
+        self
+    }
+
+    /// # [`AnthropicChatCompletionClient::with_context_window`]
+    ///
+    /// Overrides the context window the request size is checked against,
+    /// by default this is [`AnthropicModel::context_window`].
+    ///
+    /// # Arguments
+    /// * `context_window`: [`usize`] - the number of tokens the input and response must fit within.
+    ///
+    /// # Returns
+    /// [`AnthropicChatCompletionClient`] - the client with the new context window.
+    pub fn with_context_window(mut self, context_window: usize) -> Self {
+        self.context_window = context_window;
+        self
+    }
+
+    /// # [`AnthropicChatCompletionClient::invoke_detailed`]
+    ///
+    /// The same as [`AnthropicChatCompletionClient::invoke`] but returns every content block
+    /// along with why generation stopped and the token usage. Use this to find out whether the
+    /// output was cut short by max_tokens so it can be continued.
+    ///
+    /// # Arguments
+    /// * `prompt_messages`: [`Vec<PromptMessage>`] - The list of messages to send to the API.
+    ///
+    /// # Errors
+    /// * [`AnthropicError`] - for the same reasons as [`AnthropicChatCompletionClient::invoke`].
+    ///
+    /// # Returns
+    /// [`AnthropicResponse`] - The response from the API.
+    pub async fn invoke_detailed(
+        &self,
+        prompt_messages: Vec<PromptMessage>,
+    ) -> Result<AnthropicResponse, AnthropicError> {
+        let request: MessagesRequest = self.build_request(prompt_messages, false)?;
+        let response: MessagesResponse = self.client.send_request(request, &self.url).await?;
+        Ok(AnthropicResponse {
+            message: Self::text_message(&response)?,
+            usage: TokenUsage::from(&response.usage),
+            id: response.id,
+            content: response.content,
+            stop_reason: response.stop_reason,
+            stop_sequence: response.stop_sequence,
+        })
+    }
+
+    /// # [`AnthropicChatCompletionClient::build_system`]
+    ///
+    /// Helper method to turn the system messages into the system field of the request.
+    fn build_system(&self, system_messages: Vec<String>) -> Vec<Content> {
+        match self.system_prompt_mode {
+            SystemPromptMode::Blocks => system_messages
+                .into_iter()
+                .map(|text| Content::Text { text })
+                .collect(),
+            SystemPromptMode::Concatenated if system_messages.is_empty() => Vec::new(),
+            SystemPromptMode::Concatenated => {
+                let text: String = system_messages
+                    .iter()
+                    .map(|message| format!("{}\n", message))
+                    .collect();
+                vec![Content::Text { text }]
+            }
+        }
+    }
+
+    /// # [`AnthropicChatCompletionClient::check_context_window`]
+    ///
+    /// Guards against requests which cannot fit in the context window. Anthropic does not
+    /// publish its tokenizer so the input is estimated with the cl100k tokenizer, this is
+    /// close enough to catch a system prompt which has grown far too large.
+    ///
+    /// # Errors
+    /// [`AnthropicError::ContextWindowExceeded`] - if the estimated input tokens plus max_tokens
+    /// are larger than the context window.
+    fn check_context_window(
+        &self,
+        system: &[Content],
+        messages: &[Message],
+    ) -> Result<(), AnthropicError> {
+        let tokenizer = cl100k_base_singleton();
+        let tokenizer = tokenizer.lock();
+        let input_tokens: usize = system
+            .iter()
+            .chain(messages.iter().flat_map(|message| message.content.iter()))
+            .map(|content| match content {
+                Content::Text { text } => tokenizer.encode_with_special_tokens(text).len(),
+                Content::Image { .. } => Self::IMAGE_TOKEN_ESTIMATE,
+                Content::Unknown => 0,
+            })
+            .sum();
+
+        if input_tokens + self.max_tokens as usize > self.context_window {
+            return Err(AnthropicError::ContextWindowExceeded {
+                input_tokens,
+                max_tokens: self.max_tokens,
+                context_window: self.context_window,
+            });
+        }
+        Ok(())
+    }
+
+    /// # [`AnthropicChatCompletionClient::check_options`]
+    ///
+    /// Rejects a max_tokens, additional config or message content the model does not support,
+    /// see [`AnthropicModel::capabilities`].
+    ///
+    /// # Arguments
+    /// * `prompt_messages`: &[`[PromptMessage]`] - the messages that will be sent.
+    ///
+    /// # Errors
+    /// [`AnthropicError::UnsupportedOption`] - if an option or content is not supported by the model.
+    fn check_options(&self, prompt_messages: &[PromptMessage]) -> Result<(), AnthropicError> {
+        let capabilities: ModelCapabilities = self.model.capabilities();
+        let unsupported: Option<String> = capabilities
+            .unsupported_max_tokens("max_tokens", self.max_tokens.into())
+            .or_else(|| {
+                self.additional_config
+                    .as_ref()
+                    .and_then(|config| capabilities.unsupported_option(config))
+            })
+            .or_else(|| capabilities.unsupported_content(prompt_messages));
+        match unsupported {
+            None => Ok(()),
+            Some(option) => Err(AnthropicError::UnsupportedOption {
+                model: self.model.to_string(),
+                option,
+            }),
+        }
+    }
+
+    /// # [`AnthropicChatCompletionClient::build_request`]
+    ///
+    /// Checks the options and context window then builds the request body, this is shared
+    /// between the streamed and non streamed invocations.
+    ///
+    /// # Arguments
+    /// * `prompt_messages`: [`Vec<PromptMessage>`] - The list of messages to send to the API.
+    /// * `stream`: [`bool`] - Whether the response should be streamed.
+    ///
+    /// # Errors
+    /// * [`AnthropicError::UnsupportedOption`] - If max_tokens or the additional config is not supported by the model.
+    /// * [`AnthropicError::ContextWindowExceeded`] - If the request would not fit in the context window.
+    ///
+    /// # Returns
+    /// [`MessagesRequest`] - The request to send to the API.
+    fn build_request(
+        &self,
+        prompt_messages: Vec<PromptMessage>,
+        stream: bool,
+    ) -> Result<MessagesRequest, AnthropicError> {
+        self.check_options(&prompt_messages)?;
+        let mut system_messages: Vec<String> = Vec::new();
+        let mut anthropic_messages = Vec::new();
+
+        for prompt_message in prompt_messages {
+            match prompt_message {
+                PromptMessage::SystemMessage(message) => {
+                    system_messages.push(message.content);
+                }
+                _ => {
+                    let anthropic_message =
+                        Self::map_prompt_message_to_anthropic_message(prompt_message)?;
+                    anthropic_messages.push(anthropic_message);
+                }
+            }
+        }
+
+        let system: Vec<Content> = self.build_system(system_messages);
+        self.check_context_window(&system, &anthropic_messages)?;
+
+        Ok(MessagesRequest {
+            messages: anthropic_messages,
+            system,
+            model: self.model.clone(),
+            max_tokens: self.max_tokens,
+            stream,
+            additional_config: self.additional_config.clone(),
         })
     }
 
+    /// # [`AnthropicChatCompletionClient::text_message`]
+    ///
+    /// Helper method to join the text of every text block in the response into one message,
+    /// any other blocks are skipped.
+    ///
+    /// # Errors
+    /// [`AnthropicError::Undefined`] - if the response has no text blocks.
+    fn text_message(response: &MessagesResponse) -> Result<PromptMessage, AnthropicError> {
+        match response.text() {
+            Some(text) => Ok(PromptMessage::AIMessage(text.into())),
+            None => Err(AnthropicError::Undefined(
+                200,
+                AnthropicChatCompletionClient::NO_TEXT_RESPONSE_ERROR.to_string(),
+            )),
+        }
+    }
+
     /// # [`AnthropicChatCompletionClient::map_prompt_message_to_anthropic_message`]
     ///
     /// Helper method to map the prompt message to the Anthropic message. We work on
@@ -144,16 +595,26 @@ impl AnthropicChatCompletionClient {
             )),
             PromptMessage::AIMessage(message) => Ok(Message {
                 role: Role::Assistant,
-                content: vec![Content::Text { text: message }],
+                content: vec![Content::Text {
+                    text: message.content,
+                }],
             }),
             PromptMessage::HumanMessage(message) => Ok(Message {
                 role: Role::User,
-                content: vec![Content::Text { text: message }],
+                content: vec![Content::Text {
+                    text: message.content,
+                }],
+            }),
+            PromptMessage::MultiModalHumanMessage(parts) => Ok(Message {
+                role: Role::User,
+                content: parts.into_iter().map(Content::from).collect(),
             }),
         }
     }
 }
 
+impl TokenCounter for AnthropicChatCompletionClient {}
+
 impl AsyncChatClient for AnthropicChatCompletionClient {
     type ErrorType = AnthropicError;
 
@@ -165,6 +626,8 @@ impl AsyncChatClient for AnthropicChatCompletionClient {
     /// * `prompt_messages`: [`Vec<PromptMessage>`] - The list of messages to send to the API.
     ///
     /// # Errors
+    /// * [`AnthropicError::UnsupportedOption`] - If max_tokens or the additional config is not supported by the model.
+    /// * [`AnthropicError::ContextWindowExceeded`] - If the request would not fit in the context window.
     /// * [`AnthropicError`] - This error is returned when the API returns an error.
     ///
     /// # Returns
@@ -173,44 +636,239 @@ impl AsyncChatClient for AnthropicChatCompletionClient {
         &self,
         prompt_messages: Vec<PromptMessage>,
     ) -> Result<PromptMessage, Self::ErrorType> {
-        let mut system_message_content = String::new();
-        let mut anthropic_messages = Vec::new();
+        let request: MessagesRequest = self.build_request(prompt_messages, false)?;
+        let response: MessagesResponse = self.client.send_request(request, &self.url).await?;
+        Self::text_message(&response)
+    }
 
-        for prompt_message in prompt_messages {
-            match prompt_message {
-                PromptMessage::SystemMessage(content) => {
-                    system_message_content.push_str(&content);
-                    system_message_content.push('\n');
+    /// # [`AnthropicChatCompletionClient::invoke_with_context`]
+    ///
+    /// The same as [`AnthropicChatCompletionClient::invoke`] but the response carries
+    /// the request id from the context and the token usage Anthropic reported.
+    ///
+    /// # Arguments
+    /// * `prompt_messages`: [`Vec<PromptMessage>`] - The list of messages to send to the API.
+    /// * `context`: &[`InvocationContext`] - the context of the invocation.
+    ///
+    /// # Errors
+    /// * [`AnthropicError`] - for the same reasons as [`AnthropicChatCompletionClient::invoke`].
+    ///
+    /// # Returns
+    /// [`DetailedChatResponse`] - The response from the API along with the request id and usage.
+    async fn invoke_with_context(
+        &self,
+        prompt_messages: Vec<PromptMessage>,
+        context: &InvocationContext,
+    ) -> Result<DetailedChatResponse, Self::ErrorType> {
+        let request: MessagesRequest = self.build_request(prompt_messages, false)?;
+        let response: MessagesResponse = self.client.send_request(request, &self.url).await?;
+        Ok(DetailedChatResponse {
+            message: Self::text_message(&response)?,
+            request_id: context.request_id(),
+            provider_request_id: None,
+            system_fingerprint: None,
+            usage: Some(TokenUsage::from(&response.usage)),
+            finish_reason: response.stop_reason.as_ref().map(FinishReason::from),
+        })
+    }
+}
+
+#[cfg(feature = "anthropic-stream")]
+impl AsyncStreamedChatClient for AnthropicChatCompletionClient {
+    type ErrorType = AnthropicError;
+    type Item = AnthropicCompletionStream;
+
+    /// # [`AnthropicChatCompletionClient::invoke_stream`]
+    ///
+    /// Function to send a list of [`PromptMessage`] to the Anthropic API and stream the response.
+    ///
+    /// # Arguments
+    /// * `prompt_messages`: [`Vec<PromptMessage>`] - The list of messages to send to the API.
+    ///
+    /// # Errors
+    /// * [`AnthropicError::UnsupportedOption`] - If max_tokens or the additional config is not supported by the model.
+    /// * [`AnthropicError::ContextWindowExceeded`] - If the request would not fit in the context window.
+    /// * [`AnthropicError::ErrorSendingRequest`] - If the request could not be sent.
+    ///
+    /// # Returns
+    /// [`AnthropicCompletionStream`] - The stream of the response from the API.
+    async fn invoke_stream(
+        &self,
+        prompt_messages: Vec<PromptMessage>,
+    ) -> Result<Self::Item, Self::ErrorType> {
+        let request: MessagesRequest = self.build_request(prompt_messages, true)?;
+        let event_source: EventSource = self.client.send_stream_request(request, &self.url).await?;
+        Ok(AnthropicCompletionStream::new(event_source))
+    }
+}
+
+/// [`AnthropicCompletionStream`]
+///
+/// This struct wraps the EventSource and parses the streamed
+/// message events into prompt messages on demand.
+#[cfg(feature = "anthropic-stream")]
+pub struct AnthropicCompletionStream {
+    event_source: EventSource,
+    message_id: Option<String>,
+}
+
+#[cfg(feature = "anthropic-stream")]
+impl AnthropicCompletionStream {
+    /// # [`AnthropicCompletionStream::new`]
+    ///
+    /// This struct just wraps the EventSource when from the
+    /// context of streaming chat completions.
+    pub fn new(event_source: EventSource) -> Self {
+        Self {
+            event_source,
+            message_id: None,
+        }
+    }
+
+    /// # [`AnthropicCompletionStream::message_id`]
+    ///
+    /// The id Anthropic assigned to the message, taken from the message_start event.
+    ///
+    /// # Returns
+    /// * [`Option<&str>`] - the message id, `None` until the message_start event has been received.
+    pub fn message_id(&self) -> Option<&str> {
+        self.message_id.as_deref()
+    }
+
+    /// # [`AnthropicCompletionStream::fail`]
+    ///
+    /// Closes the stream so no further events are read and returns the error.
+    fn fail(
+        &mut self,
+        error: AnthropicError,
+    ) -> Option<Result<CompletionStreamValue, AnthropicError>> {
+        self.event_source.close();
+        Some(Err(error))
+    }
+}
+
+/// Dropping the stream part way through, such as when a chain's stream is dropped, closes
+/// the connection so Anthropic stops generating and the event source can not reconnect.
+#[cfg(feature = "anthropic-stream")]
+impl Drop for AnthropicCompletionStream {
+    fn drop(&mut self) {
+        self.event_source.close();
+    }
+}
+
+#[cfg(feature = "anthropic-stream")]
+impl ChatCompletionStream for AnthropicCompletionStream {
+    type ErrorType = AnthropicError;
+    type Item = CompletionStreamValue;
+
+    /// # [`ChatCompletionStream::next`]
+    ///
+    /// Method to iterate over the completion stream. Note it blocks until the next
+    /// text delta is received, the other events are read past. The stream finishes
+    /// on the message_stop event.
+    ///
+    /// # Examples
+    /// ```
+    /// use rag_toolchain::clients::*;
+    ///
+    /// async fn stream_chat_completions(client: AnthropicChatCompletionClient) {
+    ///     let user_message: PromptMessage = PromptMessage::HumanMessage("Please ask me a question".into());
+    ///     let mut stream: AnthropicCompletionStream = client.invoke_stream(vec![user_message]).await.unwrap();
+    ///     while let Some(response) = stream.next().await {
+    ///         match response {
+    ///            Ok(CompletionStreamValue::Connecting) => {},
+    ///            Ok(CompletionStreamValue::Message(msg)) => {
+    ///                 println!("{:?}", msg.content());
+    ///            }
+    ///            Ok(CompletionStreamValue::Finished(_)) => {},
+    ///            Err(e) => {
+    ///                 println!("{:?}", e);
+    ///                 break;
+    ///            }
+    ///         }
+    ///     }
+    /// }
+    /// ```
+    ///
+    /// # Errors
+    /// * [`AnthropicError`] - the status code error if the request was rejected, or the error type
+    ///   of an error event sent part way through the stream.
+    /// * [`AnthropicError::ErrorReadingStream`] - if there was an error reading from the stream.
+    /// * [`AnthropicError::ErrorDeserializingResponseBody`] - if an event could not be deserialized.
+    ///
+    /// # Returns
+    /// * [`Option<Result<CompletionStreamValue, AnthropicError>>`] - the response from the chat client.
+    ///   None represents the stream is finished.
+    async fn next(&mut self) -> Option<Result<Self::Item, Self::ErrorType>> {
+        loop {
+            let event: Event = match self.event_source.next().await? {
+                Ok(event) => event,
+                Err(reqwest_eventsource::Error::InvalidStatusCode(_, response)) => {
+                    let error: AnthropicError =
+                        AnthropicHttpClient::handle_error_response(response).await;
+                    return self.fail(error);
                 }
-                _ => {
-                    let anthropic_message =
-                        Self::map_prompt_message_to_anthropic_message(prompt_message)?;
-                    anthropic_messages.push(anthropic_message);
+                Err(error) => {
+                    return self.fail(AnthropicError::ErrorReadingStream(error.to_string()));
                 }
-            }
-        }
+            };
 
-        let request: MessagesRequest = MessagesRequest {
-            messages: anthropic_messages,
-            system: system_message_content,
-            model: self.model.clone(),
-            max_tokens: self.max_tokens,
-            additional_config: self.additional_config.clone(),
-        };
+            let data: String = match event {
+                Event::Open => return Some(Ok(CompletionStreamValue::Connecting)),
+                Event::Message(message) => message.data,
+            };
 
-        let response: MessagesResponse = self.client.send_request(request, &self.url).await?;
-        let response_message = match response.content[0] {
-            Content::Text { ref text } => PromptMessage::AIMessage(text.clone()),
-        };
+            let stream_event: MessagesStreamEvent = match serde_json::from_str(&data) {
+                Ok(stream_event) => stream_event,
+                Err(error) => {
+                    return self.fail(AnthropicError::ErrorDeserializingResponseBody(
+                        200,
+                        error.to_string(),
+                    ));
+                }
+            };
 
-        Ok(response_message)
+            match stream_event {
+                MessagesStreamEvent::MessageStart { message } => {
+                    self.message_id = Some(message.id);
+                }
+                MessagesStreamEvent::ContentBlockDelta {
+                    delta: ContentDelta::TextDelta { text },
+                    ..
+                } => {
+                    return Some(Ok(CompletionStreamValue::Message(
+                        PromptMessage::AIMessage(text.into()),
+                    )));
+                }
+                MessagesStreamEvent::MessageStop => {
+                    self.event_source.close();
+                    return None;
+                }
+                MessagesStreamEvent::Error { error } => {
+                    let error_body = AnthropicErrorBody {
+                        r#type: "error".into(),
+                        error,
+                    };
+                    return self.fail(AnthropicError::from_error_body(error_body));
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn is_token(item: &Self::Item) -> bool {
+        matches!(item, CompletionStreamValue::Message(_))
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use mockito::{Mock, Server, ServerGuard};
+    use crate::clients::cassette::RecordingHttpClient;
+    use crate::clients::http_config::tests::with_custom_http_client;
+    use crate::clients::secrets::tests::ScriptedProvider;
+    use mockito::{Matcher, Mock, Server, ServerGuard};
+    use std::sync::Arc;
 
     const CHAT_MESSAGE_RESPONSE: &str = r#"
     {
@@ -233,6 +891,31 @@ mod tests {
     }
     "#;
 
+    const TWO_BLOCK_RESPONSE: &str = r#"
+    {
+        "id": "msg_01XFDUDYJgAACzvnptvVoYEL",
+        "type": "message",
+        "role": "assistant",
+        "content": [
+            {
+                "type": "text",
+                "text": "Hello! "
+            },
+            {
+                "type": "text",
+                "text": "How can I help?"
+            }
+        ],
+        "model": "claude-3-5-sonnet-20240620",
+        "stop_reason": "max_tokens",
+        "stop_sequence": null,
+        "usage": {
+            "input_tokens": 12,
+            "output_tokens": 1024
+        }
+    }
+    "#;
+
     const ERROR_RESPONSE: &'static str = r#"
     {
         "type": "error",
@@ -246,31 +929,125 @@ mod tests {
     #[tokio::test]
     async fn invoke_correct_response_succeeds() {
         let (client, mut server) = with_mocked_client(None).await;
-        let mock = with_mocked_request(&mut server, 200, &CHAT_MESSAGE_RESPONSE);
+        let mock = with_mocked_request(&mut server, 200, CHAT_MESSAGE_RESPONSE);
 
         let response = client
             .invoke(vec![
-                PromptMessage::SystemMessage("You are a comedian".to_string()),
-                PromptMessage::HumanMessage("Hello, Claude".to_string()),
+                PromptMessage::SystemMessage("You are a comedian".into()),
+                PromptMessage::HumanMessage("Hello, Claude".into()),
             ])
             .await
             .unwrap();
 
-        let expected_response = PromptMessage::AIMessage("Hello!".to_string());
+        let expected_response = PromptMessage::AIMessage("Hello!".into());
         mock.assert();
         assert_eq!(response, expected_response);
     }
 
+    #[tokio::test]
+    async fn invoke_with_context_reports_usage() {
+        let (client, mut server) = with_mocked_client(None).await;
+        let mock = with_mocked_request(&mut server, 200, CHAT_MESSAGE_RESPONSE);
+        let context = InvocationContext::new();
+
+        let response = client
+            .invoke_with_context(
+                vec![PromptMessage::HumanMessage("Hello, Claude".into())],
+                &context,
+            )
+            .await
+            .unwrap();
+
+        mock.assert();
+        assert_eq!(response.message, PromptMessage::AIMessage("Hello!".into()));
+        assert_eq!(response.request_id, context.request_id());
+        assert_eq!(response.usage, Some(TokenUsage::new(12, 6)));
+        assert_eq!(response.usage.unwrap().total_tokens(), 18);
+        assert_eq!(response.finish_reason, Some(FinishReason::Stop));
+    }
+
+    #[tokio::test]
+    async fn invoke_joins_every_text_block() {
+        let (client, mut server) = with_mocked_client(None).await;
+        let mock = with_mocked_request(&mut server, 200, TWO_BLOCK_RESPONSE);
+
+        let response = client
+            .invoke(vec![PromptMessage::HumanMessage("Hello, Claude".into())])
+            .await
+            .unwrap();
+
+        mock.assert();
+        assert_eq!(
+            response,
+            PromptMessage::AIMessage("Hello! How can I help?".into())
+        );
+    }
+
+    #[tokio::test]
+    async fn invoke_detailed_returns_blocks_and_stop_reason() {
+        let (client, mut server) = with_mocked_client(None).await;
+        let mock = with_mocked_request(&mut server, 200, TWO_BLOCK_RESPONSE);
+
+        let response = client
+            .invoke_detailed(vec![PromptMessage::HumanMessage("Hello, Claude".into())])
+            .await
+            .unwrap();
+
+        mock.assert();
+        assert_eq!(response.id, "msg_01XFDUDYJgAACzvnptvVoYEL");
+        assert_eq!(
+            response.content,
+            vec![
+                Content::Text {
+                    text: "Hello! ".into()
+                },
+                Content::Text {
+                    text: "How can I help?".into()
+                },
+            ]
+        );
+        assert_eq!(response.stop_reason, Some(AnthropicStopReason::MaxTokens));
+        assert!(response.is_truncated());
+        assert_eq!(response.stop_sequence, None);
+        assert_eq!(response.usage, TokenUsage::new(12, 1024));
+    }
+
+    #[tokio::test]
+    async fn invoke_detailed_reports_the_stop_sequence() {
+        let (client, mut server) = with_mocked_client(None).await;
+        let body = CHAT_MESSAGE_RESPONSE
+            .replace(
+                r#""stop_reason": "end_turn""#,
+                r#""stop_reason": "stop_sequence""#,
+            )
+            .replace(r#""stop_sequence": null"#, r#""stop_sequence": "END""#);
+        let mock = with_mocked_request(&mut server, 200, &body);
+
+        let response = client
+            .invoke_detailed(vec![PromptMessage::HumanMessage("Hello, Claude".into())])
+            .await
+            .unwrap();
+
+        mock.assert();
+        assert_eq!(response.message, PromptMessage::AIMessage("Hello!".into()));
+        assert_eq!(
+            response.stop_reason,
+            Some(AnthropicStopReason::StopSequence)
+        );
+        assert_eq!(response.stop_sequence.as_deref(), Some("END"));
+        assert!(!response.is_truncated());
+    }
+
     #[tokio::test]
     async fn invoke_error_response_maps_correctly() {
         let additonal_config = Map::new();
         let (client, mut server) = with_mocked_client(Some(additonal_config)).await;
-        let mock = with_mocked_request(&mut server, 404, &ERROR_RESPONSE);
+        let mock = with_mocked_request(&mut server, 404, ERROR_RESPONSE);
 
         let response = client
             .invoke(vec![
-                PromptMessage::SystemMessage("You are a comedian".to_string()),
-                PromptMessage::HumanMessage("Hello, Claude".to_string()),
+                PromptMessage::SystemMessage("You are a comedian".into()),
+                PromptMessage::HumanMessage("Hello, Claude".into()),
             ])
             .await
             .unwrap_err();
@@ -278,12 +1055,124 @@ mod tests {
         let expected_reponse =
             AnthropicError::CODE404(serde_json::from_str(ERROR_RESPONSE).unwrap());
         mock.assert();
-        assert_eq!(response, expected_reponse);
+        assert_eq!(response.kind(), &expected_reponse);
+    }
+
+    #[tokio::test]
+    async fn invoke_sends_system_messages_as_blocks() {
+        let (client, mut server) = with_mocked_client(None).await;
+        let client = client.with_system_prompt_mode(SystemPromptMode::Blocks);
+        let mock = server
+            .mock("POST", "/")
+            .match_body(Matcher::PartialJson(serde_json::json!({
+                "system": [
+                    {"type": "text", "text": "You are a comedian"},
+                    {"type": "text", "text": "Keep it short"}
+                ]
+            })))
+            .with_status(200)
+            .with_header("Content-Type", "application/json")
+            .with_body(CHAT_MESSAGE_RESPONSE)
+            .create();
+
+        client
+            .invoke(vec![
+                PromptMessage::SystemMessage("You are a comedian".into()),
+                PromptMessage::SystemMessage("Keep it short".into()),
+                PromptMessage::HumanMessage("Hello, Claude".into()),
+            ])
+            .await
+            .unwrap();
+        mock.assert();
+    }
+
+    #[tokio::test]
+    async fn invoke_concatenates_system_messages_by_default() {
+        let (client, mut server) = with_mocked_client(None).await;
+        let mock = server
+            .mock("POST", "/")
+            .match_body(Matcher::PartialJson(serde_json::json!({
+                "system": "You are a comedian\nKeep it short\n"
+            })))
+            .with_status(200)
+            .with_header("Content-Type", "application/json")
+            .with_body(CHAT_MESSAGE_RESPONSE)
+            .create();
+
+        client
+            .invoke(vec![
+                PromptMessage::SystemMessage("You are a comedian".into()),
+                PromptMessage::SystemMessage("Keep it short".into()),
+                PromptMessage::HumanMessage("Hello, Claude".into()),
+            ])
+            .await
+            .unwrap();
+        mock.assert();
+    }
+
+    #[tokio::test]
+    async fn invoke_exceeding_context_window_is_not_sent() {
+        let (client, mut server) = with_mocked_client(None).await;
+        let client = client.with_context_window(1030);
+        let mock = with_mocked_request(&mut server, 200, CHAT_MESSAGE_RESPONSE).expect(0);
+
+        let response = client
+            .invoke(vec![
+                PromptMessage::SystemMessage("You are a comedian ".repeat(10).into()),
+                PromptMessage::HumanMessage("Hello, Claude".into()),
+            ])
+            .await
+            .unwrap_err();
+
+        mock.assert();
+        assert!(matches!(
+            response,
+            AnthropicError::ContextWindowExceeded {
+                max_tokens: 1024,
+                context_window: 1030,
+                ..
+            }
+        ));
+    }
+
+    #[tokio::test]
+    async fn invoke_with_unsupported_option_is_not_sent() {
+        let (client, mut server) = with_mocked_client(None).await;
+        let mock = with_mocked_request(&mut server, 200, CHAT_MESSAGE_RESPONSE).expect(0);
+        let mut client = client;
+        client.model = AnthropicModel::Claude3Haiku;
+        client.max_tokens = 8192;
+
+        let response = client
+            .invoke(vec![PromptMessage::HumanMessage("Hello, Claude".into())])
+            .await
+            .unwrap_err();
+
+        mock.assert();
+        assert_eq!(
+            response,
+            AnthropicError::UnsupportedOption {
+                model: "claude-3-haiku-20240307".into(),
+                option: "max_tokens of 8192 (the maximum output is 4096)".into()
+            }
+        );
+
+        let mut config: Map<String, Value> = Map::new();
+        config.insert("logprobs".into(), true.into());
+        let (client, _server) = with_mocked_client(Some(config)).await;
+        let response = client
+            .invoke(vec![PromptMessage::HumanMessage("Hello, Claude".into())])
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            response,
+            AnthropicError::UnsupportedOption { option, .. } if option == "logprobs"
+        ));
     }
 
     #[test]
     fn map_prompt_message_to_anthropic_message_with_system_message_returns_error() {
-        let system_message = PromptMessage::SystemMessage("Hello".to_string());
+        let system_message = PromptMessage::SystemMessage("Hello".into());
         let response =
             AnthropicChatCompletionClient::map_prompt_message_to_anthropic_message(system_message)
                 .unwrap_err();
@@ -296,7 +1185,7 @@ mod tests {
 
     #[test]
     fn map_prompt_message_to_anthropic_message_with_human_message_returns_message() {
-        let human_message = PromptMessage::HumanMessage("Hello".to_string());
+        let human_message = PromptMessage::HumanMessage("Hello".into());
         let response =
             AnthropicChatCompletionClient::map_prompt_message_to_anthropic_message(human_message)
                 .unwrap();
@@ -311,7 +1200,7 @@ mod tests {
 
     #[test]
     fn map_prompt_message_to_anthropic_message_with_ai_message_returns_message() {
-        let ai_message = PromptMessage::AIMessage("Hello".to_string());
+        let ai_message = PromptMessage::AIMessage("Hello".into());
         let response =
             AnthropicChatCompletionClient::map_prompt_message_to_anthropic_message(ai_message)
                 .unwrap();
@@ -324,6 +1213,338 @@ mod tests {
         assert_eq!(response, expected_response);
     }
 
+    #[test]
+    fn try_new_with_additional_config_rejects_each_reserved_key() {
+        for key in ["model", "messages", "max_tokens", "system", "stream"] {
+            let mut config: Map<String, Value> = Map::new();
+            config.insert(key.into(), Value::Null);
+            config.insert("temperature".into(), 0.5.into());
+            let result = AnthropicChatCompletionClient::try_new_with_additional_config(
+                AnthropicModel::Claude3Point5Sonnet,
+                1024,
+                config,
+            );
+            assert_eq!(
+                result.err(),
+                Some(AdditionalConfigError::ReservedKeys(vec![key.into()]))
+            );
+        }
+    }
+
+    #[test]
+    fn with_additional_config_merges_nested_objects() {
+        let config =
+            |value: Value| -> Map<String, Value> { serde_json::from_value(value).unwrap() };
+        let client = AnthropicChatCompletionClient::new_with_secret_provider(
+            AnthropicModel::Claude3Point5Sonnet,
+            1024,
+            ScriptedProvider::new(vec!["fake key"]),
+        )
+        .with_additional_config(config(serde_json::json!({
+            "metadata": {"user_id": "abc"},
+            "temperature": 0.5
+        })))
+        .unwrap()
+        .with_additional_config(config(serde_json::json!({
+            "metadata": {"session": "xyz"},
+            "temperature": 1.0
+        })))
+        .unwrap();
+        assert_eq!(
+            client.additional_config.map(Value::Object),
+            Some(serde_json::json!({
+                "metadata": {"user_id": "abc", "session": "xyz"},
+                "temperature": 1.0
+            }))
+        );
+    }
+
+    #[test]
+    fn with_additional_config_rejects_reserved_keys() {
+        let result = AnthropicChatCompletionClient::new_with_secret_provider(
+            AnthropicModel::Claude3Point5Sonnet,
+            1024,
+            ScriptedProvider::new(vec!["fake key"]),
+        )
+        .with_additional_config(Map::from_iter([("max_tokens".to_string(), 10.into())]));
+        assert_eq!(
+            result.err(),
+            Some(AdditionalConfigError::ReservedKeys(vec![
+                "max_tokens".into()
+            ]))
+        );
+    }
+
+    #[tokio::test]
+    async fn invoke_replays_cassette() {
+        let recorder = Arc::new(RecordingHttpClient::load("anthropic_messages"));
+        let model = AnthropicModel::Claude3Point5Sonnet;
+        // A real key is only needed to record the cassette again
+        let mut client = match std::env::var("ANTHROPIC_API_KEY") {
+            Ok(_) => AnthropicChatCompletionClient::try_new(model, 1024).unwrap(),
+            Err(_) => AnthropicChatCompletionClient::new_with_secret_provider(
+                model,
+                1024,
+                ScriptedProvider::new(vec!["fake key"]),
+            ),
+        };
+        client.client.set_recorder(recorder.clone());
+
+        let response = client
+            .invoke(vec![
+                PromptMessage::SystemMessage("You are a comedian".into()),
+                PromptMessage::HumanMessage("Hello, Claude".into()),
+            ])
+            .await
+            .unwrap();
+
+        let expected_response = PromptMessage::AIMessage(
+            "Hello! Why did the scarecrow win an award? Because he was outstanding in his field."
+                .to_string()
+                .into(),
+        );
+        assert_eq!(response, expected_response);
+        assert_eq!(recorder.unused_interactions(), 0);
+    }
+
+    #[cfg(feature = "anthropic-stream")]
+    const STREAMED_MESSAGE_START: &str = "event: message_start\ndata: {\"type\":\"message_start\",\"message\":{\"id\":\"msg_01XFDUDYJgAACzvnptvVoYEL\",\"type\":\"message\",\"role\":\"assistant\",\"content\":[],\"model\":\"claude-3-5-sonnet-20240620\",\"stop_reason\":null,\"stop_sequence\":null,\"usage\":{\"input_tokens\":12,\"output_tokens\":1}}}\n\nevent: content_block_start\ndata: {\"type\":\"content_block_start\",\"index\":0,\"content_block\":{\"type\":\"text\",\"text\":\"\"}}\n\nevent: ping\ndata: {\"type\":\"ping\"}\n\n";
+
+    #[cfg(feature = "anthropic-stream")]
+    const STREAMED_MESSAGE_STOP: &str = "event: content_block_stop\ndata: {\"type\":\"content_block_stop\",\"index\":0}\n\nevent: message_delta\ndata: {\"type\":\"message_delta\",\"delta\":{\"stop_reason\":\"end_turn\",\"stop_sequence\":null},\"usage\":{\"output_tokens\":6}}\n\nevent: message_stop\ndata: {\"type\":\"message_stop\"}\n\n";
+
+    // Builds the body of a streamed message with a text delta for each of the given texts
+    #[cfg(feature = "anthropic-stream")]
+    fn streamed_text_deltas(texts: &[&str]) -> String {
+        texts
+            .iter()
+            .map(|text| {
+                let data = serde_json::json!({
+                    "type": "content_block_delta",
+                    "index": 0,
+                    "delta": {"type": "text_delta", "text": text}
+                });
+                format!("event: content_block_delta\ndata: {}\n\n", data)
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn injected_http_client_sends_requests() {
+        let (client, mut server) = with_mocked_client(None).await;
+        let client = client.with_http_client(with_custom_http_client());
+        let mock = server
+            .mock("POST", "/")
+            .match_header("X-Proxy-Authorization", "Basic proxy")
+            .with_status(200)
+            .with_header("Content-Type", "application/json")
+            .with_body(CHAT_MESSAGE_RESPONSE)
+            .create();
+        let prompt = PromptMessage::HumanMessage("Hello, Claude".into());
+        client.invoke(vec![prompt]).await.unwrap();
+        mock.assert();
+    }
+
+    #[cfg(feature = "anthropic-stream")]
+    #[tokio::test]
+    async fn injected_http_client_sends_streamed_requests() {
+        let (client, mut server) = with_mocked_client(None).await;
+        let client = client.with_http_client(with_custom_http_client());
+        let body = format!(
+            "{}{}{}",
+            STREAMED_MESSAGE_START,
+            streamed_text_deltas(&["Hello"]),
+            STREAMED_MESSAGE_STOP
+        );
+        let mock = server
+            .mock("POST", "/")
+            .match_header("X-Proxy-Authorization", "Basic proxy")
+            .with_status(200)
+            .with_header("Content-Type", "text/event-stream")
+            .with_body(body)
+            .create();
+        let prompt = PromptMessage::HumanMessage("Hello, Claude".into());
+        let mut stream = client.invoke_stream(vec![prompt]).await.unwrap();
+        while let Some(value) = stream.next().await {
+            value.unwrap();
+        }
+        mock.assert();
+    }
+
+    #[cfg(feature = "anthropic-stream")]
+    #[tokio::test]
+    async fn invoke_stream_correct_response_succeeds() {
+        let (client, mut server) = with_mocked_client(None).await;
+        let body = format!(
+            "{}{}{}",
+            STREAMED_MESSAGE_START,
+            streamed_text_deltas(&["Hello", "!"]),
+            STREAMED_MESSAGE_STOP
+        );
+        let mock = server
+            .mock("POST", "/")
+            .match_body(Matcher::PartialJson(serde_json::json!({"stream": true})))
+            .with_status(200)
+            .with_header("Content-Type", "text/event-stream")
+            .with_body(body)
+            .create();
+
+        let mut stream = client
+            .invoke_stream(vec![
+                PromptMessage::SystemMessage("You are a comedian".into()),
+                PromptMessage::HumanMessage("Hello, Claude".into()),
+            ])
+            .await
+            .unwrap();
+
+        let mut values = Vec::new();
+        while let Some(value) = stream.next().await {
+            values.push(value.unwrap());
+        }
+        mock.assert();
+        assert_eq!(
+            values,
+            vec![
+                CompletionStreamValue::Connecting,
+                CompletionStreamValue::Message(PromptMessage::AIMessage("Hello".into())),
+                CompletionStreamValue::Message(PromptMessage::AIMessage("!".into())),
+            ]
+        );
+        assert_eq!(stream.message_id(), Some("msg_01XFDUDYJgAACzvnptvVoYEL"));
+        assert!(stream.next().await.is_none());
+    }
+
+    #[cfg(feature = "anthropic-stream")]
+    #[tokio::test]
+    async fn invoke_stream_error_event_maps_correctly() {
+        let (client, mut server) = with_mocked_client(None).await;
+        let body = format!(
+            "{}{}event: error\ndata: {}\n\n",
+            STREAMED_MESSAGE_START,
+            streamed_text_deltas(&["Hel"]),
+            r#"{"type":"error","error":{"type":"overloaded_error","message":"Overloaded"}}"#
+        );
+        let mock = server
+            .mock("POST", "/")
+            .with_status(200)
+            .with_header("Content-Type", "text/event-stream")
+            .with_body(body)
+            .create();
+
+        let prompt = PromptMessage::HumanMessage("Hello, Claude".into());
+        let mut stream = client.invoke_stream(vec![prompt]).await.unwrap();
+        assert_eq!(
+            stream.next().await.unwrap().unwrap(),
+            CompletionStreamValue::Connecting
+        );
+        assert_eq!(
+            stream.next().await.unwrap().unwrap(),
+            CompletionStreamValue::Message(PromptMessage::AIMessage("Hel".into()))
+        );
+        let expected_error = AnthropicError::CODE503(
+            serde_json::from_str(
+                r#"{"type":"error","error":{"type":"overloaded_error","message":"Overloaded"}}"#,
+            )
+            .unwrap(),
+        );
+        assert_eq!(stream.next().await.unwrap().unwrap_err(), expected_error);
+        assert!(stream.next().await.is_none());
+        mock.assert();
+    }
+
+    #[cfg(feature = "anthropic-stream")]
+    #[tokio::test]
+    async fn invoke_stream_error_response_maps_correctly() {
+        let (client, mut server) = with_mocked_client(None).await;
+        let mock = with_mocked_request(&mut server, 404, ERROR_RESPONSE);
+
+        let prompt = PromptMessage::HumanMessage("Hello, Claude".into());
+        let mut stream = client.invoke_stream(vec![prompt]).await.unwrap();
+        let expected_error = AnthropicError::CODE404(serde_json::from_str(ERROR_RESPONSE).unwrap());
+        assert_eq!(stream.next().await.unwrap().unwrap_err(), expected_error);
+        assert!(stream.next().await.is_none());
+        mock.assert();
+    }
+
+    #[cfg(feature = "anthropic-stream")]
+    #[tokio::test]
+    async fn invoke_stream_exceeding_context_window_is_not_sent() {
+        let (client, mut server) = with_mocked_client(None).await;
+        let client = client.with_context_window(1030);
+        let mock = with_mocked_request(&mut server, 200, STREAMED_MESSAGE_STOP).expect(0);
+
+        let result = client
+            .invoke_stream(vec![PromptMessage::HumanMessage(
+                "Hello, Claude ".repeat(10).into(),
+            )])
+            .await;
+
+        mock.assert();
+        assert!(matches!(
+            result,
+            Err(AnthropicError::ContextWindowExceeded { .. })
+        ));
+    }
+
+    #[cfg(feature = "anthropic-stream")]
+    #[tokio::test]
+    async fn invoke_stream_through_rag_chain() {
+        use crate::chains::BasicStreamedRAGChain;
+        use crate::common::Chunk;
+        use crate::retrievers::MockAsyncRetriever;
+        use std::num::NonZeroU32;
+
+        let (client, mut server) = with_mocked_client(None).await;
+        let body = format!(
+            "{}{}{}",
+            STREAMED_MESSAGE_START,
+            streamed_text_deltas(&["It is about processes"]),
+            STREAMED_MESSAGE_STOP
+        );
+        let mock = server
+            .mock("POST", "/")
+            .match_body(Matcher::PartialJson(serde_json::json!({
+                "system": "You are a study buddy\n",
+                "messages": [{
+                    "role": "user",
+                    "content": [{
+                        "type": "text",
+                        "text": "What was the lecture about\nHere is some supporting information:\nprocesses and threads\n"
+                    }]
+                }],
+                "stream": true
+            })))
+            .with_status(200)
+            .with_header("Content-Type", "text/event-stream")
+            .with_body(body)
+            .create();
+        let mut retriever = MockAsyncRetriever::new();
+        retriever
+            .expect_retrieve()
+            .returning(|_, _| Ok(vec![Chunk::new("processes and threads")]));
+        let chain = BasicStreamedRAGChain::builder()
+            .system_prompt(PromptMessage::SystemMessage("You are a study buddy".into()))
+            .chat_client(client)
+            .retriever(retriever)
+            .build();
+
+        let mut stream = chain
+            .invoke_chain(
+                PromptMessage::HumanMessage("What was the lecture about".into()),
+                NonZeroU32::new(1).unwrap(),
+            )
+            .await
+            .unwrap();
+        let mut text = String::new();
+        while let Some(value) = stream.next().await {
+            if let CompletionStreamValue::Message(message) = value.unwrap() {
+                text.push_str(message.content());
+            }
+        }
+        mock.assert();
+        assert_eq!(text, "It is about processes");
+    }
+
     // Method which mocks the response the server will give. this
     // allows us to stub the requests instead of sending them to OpenAI
     fn with_mocked_request(
@@ -344,17 +1565,15 @@ mod tests {
     async fn with_mocked_client(
         config: Option<Map<String, Value>>,
     ) -> (AnthropicChatCompletionClient, ServerGuard) {
-        std::env::set_var("ANTHROPIC_API_KEY", "fake key");
         let server = Server::new_async().await;
         let url = server.url();
         let model = AnthropicModel::Claude3Point5Sonnet;
-        let mut client = match config {
-            Some(config) => {
-                AnthropicChatCompletionClient::try_new_with_additional_config(model, 1024, config)
-                    .unwrap()
-            }
-            None => AnthropicChatCompletionClient::try_new(model, 1024).unwrap(),
-        };
+        let mut client = AnthropicChatCompletionClient::new_with_secret_provider(
+            model,
+            1024,
+            ScriptedProvider::new(vec!["fake key"]),
+        );
+        client.additional_config = config;
         client.url = url;
         (client, server)
     }
@@ -6,7 +6,16 @@ mod anthropic_messages;
 mod model;
 
 #[cfg(feature = "anthropic")]
-pub use anthropic_messages::AnthropicChatCompletionClient;
+pub use anthropic_messages::{AnthropicChatCompletionClient, AnthropicResponse, SystemPromptMode};
+
+#[cfg(feature = "anthropic-stream")]
+pub use anthropic_messages::AnthropicCompletionStream;
 
 #[cfg(feature = "anthropic")]
-pub use model::{chat_completions::AnthropicModel, errors::AnthropicError};
+pub use model::{
+    chat_completions::{
+        AnthropicModel, AnthropicStopReason, Content as AnthropicContentBlock,
+        ImageBlockSource as AnthropicImageSource,
+    },
+    errors::AnthropicError,
+};
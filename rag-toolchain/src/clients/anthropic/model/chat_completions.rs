@@ -1,20 +1,68 @@
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use serde_json::{Map, Value};
+use std::convert::Infallible;
+use std::fmt::{self, Display, Formatter};
+use std::str::FromStr;
 use typed_builder::TypedBuilder;
 
+#[cfg(feature = "anthropic-stream")]
+use super::errors::AnthropicErrorDetails;
+use crate::clients::types::{ContentPart, FinishReason, ImageSource};
+use crate::common::TokenUsage;
+
 #[derive(Debug, Serialize, Deserialize, PartialEq, TypedBuilder)]
 #[serde(rename_all = "snake_case")]
 pub struct MessagesRequest {
     pub messages: Vec<Message>,
-    #[serde(skip_serializing_if = "String::is_empty")]
-    pub system: String,
+    /// A single text block is sent as a plain string, anything else as a list of blocks
+    #[builder(default)]
+    #[serde(
+        default,
+        skip_serializing_if = "Vec::is_empty",
+        serialize_with = "serialize_system",
+        deserialize_with = "deserialize_system"
+    )]
+    pub system: Vec<Content>,
     pub model: AnthropicModel,
     pub max_tokens: u32,
+    /// Only sent when true so non streamed requests are unchanged
+    #[builder(default)]
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub stream: bool,
     #[builder(default, setter(strip_option))]
     #[serde(flatten)]
     pub additional_config: Option<Map<String, Value>>,
 }
 
+/// The system field accepts either a string or a list of content blocks
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum SystemField {
+    Text(String),
+    Blocks(Vec<Content>),
+}
+
+fn serialize_system<S>(system: &[Content], serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    match system {
+        [Content::Text { text }] => serializer.serialize_str(text),
+        blocks => blocks.serialize(serializer),
+    }
+}
+
+fn deserialize_system<'de, D>(deserializer: D) -> Result<Vec<Content>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Ok(match SystemField::deserialize(deserializer)? {
+        SystemField::Text(text) if text.is_empty() => Vec::new(),
+        SystemField::Text(text) => vec![Content::Text { text }],
+        SystemField::Blocks(blocks) => blocks,
+    })
+}
+
 #[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
 pub struct MessagesResponse {
     pub id: String,
@@ -22,22 +70,54 @@ pub struct MessagesResponse {
     pub role: Role,
     pub content: Vec<Content>,
     pub model: String,
-    pub stop_reason: Option<StopReason>,
+    #[serde(default)]
+    pub stop_reason: Option<AnthropicStopReason>,
+    #[serde(default)]
     pub stop_sequence: Option<String>,
     pub usage: Usage,
 }
 
+impl MessagesResponse {
+    /// # [`MessagesResponse::text`]
+    ///
+    /// # Returns
+    /// * [`Option<String>`] - the text of every text block joined together, or [`None`]
+    ///   if the response has no text blocks.
+    pub fn text(&self) -> Option<String> {
+        let texts: Vec<&str> = self
+            .content
+            .iter()
+            .filter_map(|block| match block {
+                Content::Text { text } => Some(text.as_str()),
+                _ => None,
+            })
+            .collect();
+        if texts.is_empty() {
+            return None;
+        }
+        Some(texts.concat())
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
 pub struct Usage {
     pub input_tokens: usize,
     pub output_tokens: usize,
 }
 
+impl From<&Usage> for TokenUsage {
+    fn from(usage: &Usage) -> Self {
+        TokenUsage::new(usage.input_tokens, usage.output_tokens)
+    }
+}
+
 /// # [`AnthropicModel`]
 ///
 /// A list of model's available to use in the Anthropic API.
 /// note these may have effects on what values are available for config
-/// such as max_tokens.
+/// such as max_tokens. Any other model, such as a newer release or dated snapshot, can be
+/// used with [`AnthropicModel::Custom`] which is sent exactly as given. Parsing a name with
+/// [`str::parse`] gives the listed model if there is one and a custom model otherwise.
 #[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
 #[serde(rename_all = "snake_case")]
 pub enum AnthropicModel {
@@ -49,21 +129,116 @@ pub enum AnthropicModel {
     Claude3Sonnet,
     #[serde(rename = "claude-3-haiku-20240307")]
     Claude3Haiku,
+    #[serde(untagged)]
+    Custom(String),
+}
+
+impl AnthropicModel {
+    /// # [`AnthropicModel::as_str`]
+    ///
+    /// # Returns
+    /// * &[`str`] - the model name sent to Anthropic.
+    pub fn as_str(&self) -> &str {
+        match self {
+            AnthropicModel::Claude3Point5Sonnet => "claude-3-5-sonnet-20240620",
+            AnthropicModel::Claude3Opus => "claude-3-opus-20240229",
+            AnthropicModel::Claude3Sonnet => "claude-3-sonnet-20240229",
+            AnthropicModel::Claude3Haiku => "claude-3-haiku-20240307",
+            AnthropicModel::Custom(name) => name,
+        }
+    }
+
+    /// # [`AnthropicModel::context_window`]
+    ///
+    /// The number of tokens the model can attend to, this is shared
+    /// between the input and the max_tokens of the response.
+    pub fn context_window(&self) -> usize {
+        self.capabilities().max_context
+    }
+}
+
+impl Display for AnthropicModel {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
 }
 
+impl FromStr for AnthropicModel {
+    type Err = Infallible;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        Ok(match name {
+            "claude-3-5-sonnet-20240620" => AnthropicModel::Claude3Point5Sonnet,
+            "claude-3-opus-20240229" => AnthropicModel::Claude3Opus,
+            "claude-3-sonnet-20240229" => AnthropicModel::Claude3Sonnet,
+            "claude-3-haiku-20240307" => AnthropicModel::Claude3Haiku,
+            custom => AnthropicModel::Custom(custom.into()),
+        })
+    }
+}
+
+/// # [`AnthropicStopReason`]
+///
+/// Why the model stopped generating, a response stopped by [`AnthropicStopReason::MaxTokens`]
+/// was cut short and can be continued by sending it back as an assistant message.
+/// Reasons added to the API later are deserialized as [`AnthropicStopReason::Unknown`].
 #[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
 #[serde(rename_all = "snake_case")]
-pub enum StopReason {
+pub enum AnthropicStopReason {
     EndTurn,
     MaxTokens,
     StopSequence,
+    ToolUse,
+    #[serde(other)]
+    Unknown,
+}
+
+impl From<&AnthropicStopReason> for FinishReason {
+    fn from(reason: &AnthropicStopReason) -> Self {
+        match reason {
+            AnthropicStopReason::EndTurn | AnthropicStopReason::StopSequence => FinishReason::Stop,
+            AnthropicStopReason::MaxTokens => FinishReason::Length,
+            AnthropicStopReason::ToolUse => FinishReason::ToolCalls,
+            AnthropicStopReason::Unknown => FinishReason::Other("unknown".into()),
+        }
+    }
 }
 
+/// A content block of a message, blocks this library does not handle such as tool use
+/// are deserialized as [`Content::Unknown`].
 #[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
 #[serde(rename_all = "snake_case", tag = "type")]
 pub enum Content {
-    // Note only support of text here. Images need to be added later
-    Text { text: String },
+    Text {
+        text: String,
+    },
+    Image {
+        source: ImageBlockSource,
+    },
+    #[serde(other)]
+    Unknown,
+}
+
+/// See <https://docs.anthropic.com/en/docs/build-with-claude/vision>
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+#[serde(rename_all = "snake_case", tag = "type")]
+pub enum ImageBlockSource {
+    Base64 { media_type: String, data: String },
+    Url { url: String },
+}
+
+impl From<ContentPart> for Content {
+    fn from(part: ContentPart) -> Self {
+        match part {
+            ContentPart::Text(text) => Content::Text { text },
+            ContentPart::Image(ImageSource::Url(url)) => Content::Image {
+                source: ImageBlockSource::Url { url },
+            },
+            ContentPart::Image(ImageSource::Base64 { media_type, data }) => Content::Image {
+                source: ImageBlockSource::Base64 { media_type, data },
+            },
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
@@ -80,6 +255,51 @@ pub enum Role {
     Assistant,
 }
 
+/// The events sent when a message is streamed, new event types may be added
+/// so any we do not know about are deserialized as [`MessagesStreamEvent::Unknown`].
+/// See <https://docs.anthropic.com/en/api/messages-streaming>
+#[cfg(feature = "anthropic-stream")]
+#[derive(Debug, Deserialize, PartialEq, Clone)]
+#[serde(rename_all = "snake_case", tag = "type")]
+pub enum MessagesStreamEvent {
+    MessageStart {
+        message: MessageStart,
+    },
+    ContentBlockStart,
+    ContentBlockDelta {
+        index: usize,
+        delta: ContentDelta,
+    },
+    ContentBlockStop,
+    MessageDelta,
+    MessageStop,
+    Ping,
+    Error {
+        error: AnthropicErrorDetails,
+    },
+    #[serde(other)]
+    Unknown,
+}
+
+#[cfg(feature = "anthropic-stream")]
+#[derive(Debug, Deserialize, PartialEq, Eq, Clone)]
+pub struct MessageStart {
+    pub id: String,
+    pub model: String,
+}
+
+#[cfg(feature = "anthropic-stream")]
+#[derive(Debug, Deserialize, PartialEq, Eq, Clone)]
+#[serde(rename_all = "snake_case", tag = "type")]
+pub enum ContentDelta {
+    TextDelta {
+        text: String,
+    },
+    /// Deltas for tool use and thinking blocks which are not surfaced as text
+    #[serde(other)]
+    Other,
+}
+
 #[cfg(test)]
 mod request_model_tests {
     use super::*;
@@ -131,9 +351,10 @@ mod request_model_tests {
                     }],
                 },
             ],
-            system: "".to_string(),
+            system: vec![],
             model: AnthropicModel::Claude3Point5Sonnet,
             max_tokens: 1024,
+            stream: false,
             additional_config: Some(additional_config),
         };
 
@@ -141,6 +362,72 @@ mod request_model_tests {
         assert_eq!(request_json, CHAT_MESSAGE_REQUEST);
     }
 
+    #[test]
+    fn test_serialize_single_system_block_as_string() {
+        let request = MessagesRequest::builder()
+            .messages(vec![])
+            .system(vec![Content::Text {
+                text: "You are a comedian".into(),
+            }])
+            .model(AnthropicModel::Claude3Haiku)
+            .max_tokens(10)
+            .build();
+        let request_json = serde_json::to_string(&request).unwrap();
+        assert_eq!(
+            request_json,
+            r#"{"messages":[],"system":"You are a comedian","model":"claude-3-haiku-20240307","max_tokens":10}"#
+        );
+        let round_trip: MessagesRequest = serde_json::from_str(&request_json).unwrap();
+        assert_eq!(round_trip.system, request.system);
+    }
+
+    #[test]
+    fn test_serialize_multiple_system_blocks_as_list() {
+        let request = MessagesRequest::builder()
+            .messages(vec![])
+            .system(vec![
+                Content::Text {
+                    text: "You are a comedian".into(),
+                },
+                Content::Text {
+                    text: "Keep it short".into(),
+                },
+            ])
+            .model(AnthropicModel::Claude3Haiku)
+            .max_tokens(10)
+            .build();
+        let request_json = serde_json::to_string(&request).unwrap();
+        assert_eq!(
+            request_json,
+            r#"{"messages":[],"system":[{"type":"text","text":"You are a comedian"},{"type":"text","text":"Keep it short"}],"model":"claude-3-haiku-20240307","max_tokens":10}"#
+        );
+        let round_trip: MessagesRequest = serde_json::from_str(&request_json).unwrap();
+        assert_eq!(round_trip.system, request.system);
+    }
+
+    #[test]
+    fn test_serialize_image_blocks() {
+        let message = Message {
+            role: Role::User,
+            content: vec![
+                ContentPart::Image(ImageSource::Base64 {
+                    media_type: "image/png".into(),
+                    data: "iVBORw0KGgo=".into(),
+                })
+                .into(),
+                ContentPart::Image(ImageSource::Url("https://example.com/a.png".into())).into(),
+                ContentPart::Text("What is in these images?".into()).into(),
+            ],
+        };
+        let message_json = serde_json::to_string(&message).unwrap();
+        assert_eq!(
+            message_json,
+            r#"{"role":"user","content":[{"type":"image","source":{"type":"base64","media_type":"image/png","data":"iVBORw0KGgo="}},{"type":"image","source":{"type":"url","url":"https://example.com/a.png"}},{"type":"text","text":"What is in these images?"}]}"#
+        );
+        let round_trip: Message = serde_json::from_str(&message_json).unwrap();
+        assert_eq!(round_trip, message);
+    }
+
     #[test]
     fn test_deserialize_chat_message_response() {
         let response: MessagesResponse = serde_json::from_str(CHAT_MESSAGE_RESPONSE).unwrap();
@@ -153,7 +440,7 @@ mod request_model_tests {
                 text: "Hello!".to_string(),
             }],
             model: "claude-3-5-sonnet-20240620".to_string(),
-            stop_reason: Some(StopReason::EndTurn),
+            stop_reason: Some(AnthropicStopReason::EndTurn),
             stop_sequence: None,
             usage: Usage {
                 input_tokens: 12,
@@ -163,4 +450,138 @@ mod request_model_tests {
 
         assert_eq!(response, expected_response);
     }
+
+    #[test]
+    fn test_deserialize_response_with_unknown_blocks_and_no_stop_reason() {
+        let response: MessagesResponse = serde_json::from_str(
+            r#"{
+                "id": "msg_1",
+                "type": "message",
+                "role": "assistant",
+                "content": [
+                    {"type": "text", "text": "Let me check. "},
+                    {"type": "tool_use", "id": "toolu_1", "name": "search", "input": {}},
+                    {"type": "text", "text": "Done."}
+                ],
+                "model": "claude-3-5-sonnet-20240620",
+                "usage": {"input_tokens": 1, "output_tokens": 2}
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(response.content[1], Content::Unknown);
+        assert_eq!(response.stop_reason, None);
+        assert_eq!(response.stop_sequence, None);
+        assert_eq!(response.text(), Some("Let me check. Done.".into()));
+        let stop_reason: AnthropicStopReason = serde_json::from_str(r#""pause_turn""#).unwrap();
+        assert_eq!(stop_reason, AnthropicStopReason::Unknown);
+    }
+
+    #[cfg(feature = "anthropic-stream")]
+    #[test]
+    fn test_deserialize_stream_events() {
+        let events: Vec<MessagesStreamEvent> = [
+            r#"{"type":"message_start","message":{"id":"msg_1","type":"message","role":"assistant","content":[],"model":"claude-3-5-sonnet-20240620","stop_reason":null,"stop_sequence":null,"usage":{"input_tokens":25,"output_tokens":1}}}"#,
+            r#"{"type":"content_block_start","index":0,"content_block":{"type":"text","text":""}}"#,
+            r#"{"type":"ping"}"#,
+            r#"{"type":"content_block_delta","index":0,"delta":{"type":"text_delta","text":"Hello"}}"#,
+            r#"{"type":"content_block_delta","index":1,"delta":{"type":"input_json_delta","partial_json":"{"}}"#,
+            r#"{"type":"content_block_stop","index":0}"#,
+            r#"{"type":"message_delta","delta":{"stop_reason":"end_turn","stop_sequence":null},"usage":{"output_tokens":15}}"#,
+            r#"{"type":"message_stop"}"#,
+            r#"{"type":"error","error":{"type":"overloaded_error","message":"Overloaded"}}"#,
+            r#"{"type":"some_new_event","index":0}"#,
+        ]
+        .iter()
+        .map(|event| serde_json::from_str(event).unwrap())
+        .collect();
+
+        let expected_events = vec![
+            MessagesStreamEvent::MessageStart {
+                message: MessageStart {
+                    id: "msg_1".into(),
+                    model: "claude-3-5-sonnet-20240620".into(),
+                },
+            },
+            MessagesStreamEvent::ContentBlockStart,
+            MessagesStreamEvent::Ping,
+            MessagesStreamEvent::ContentBlockDelta {
+                index: 0,
+                delta: ContentDelta::TextDelta {
+                    text: "Hello".into(),
+                },
+            },
+            MessagesStreamEvent::ContentBlockDelta {
+                index: 1,
+                delta: ContentDelta::Other,
+            },
+            MessagesStreamEvent::ContentBlockStop,
+            MessagesStreamEvent::MessageDelta,
+            MessagesStreamEvent::MessageStop,
+            MessagesStreamEvent::Error {
+                error: AnthropicErrorDetails {
+                    r#type: "overloaded_error".into(),
+                    message: "Overloaded".into(),
+                },
+            },
+            MessagesStreamEvent::Unknown,
+        ];
+        assert_eq!(events, expected_events);
+    }
+
+    #[test]
+    fn test_serialize_stream_flag_only_when_set() {
+        let request = MessagesRequest::builder()
+            .messages(vec![])
+            .model(AnthropicModel::Claude3Haiku)
+            .max_tokens(10)
+            .stream(true)
+            .build();
+        let request_json = serde_json::to_string(&request).unwrap();
+        assert_eq!(
+            request_json,
+            r#"{"messages":[],"model":"claude-3-haiku-20240307","max_tokens":10,"stream":true}"#
+        );
+    }
+
+    #[test]
+    fn test_model_names_agree_between_serde_and_display() {
+        let models = [
+            AnthropicModel::Claude3Point5Sonnet,
+            AnthropicModel::Claude3Opus,
+            AnthropicModel::Claude3Sonnet,
+            AnthropicModel::Claude3Haiku,
+        ];
+        for model in models {
+            let name: Value = serde_json::to_value(&model).unwrap();
+            assert_eq!(name, Value::String(model.to_string()));
+            assert_eq!(
+                serde_json::from_value::<AnthropicModel>(name).unwrap(),
+                model
+            );
+            assert_eq!(model.to_string().parse::<AnthropicModel>().unwrap(), model);
+        }
+    }
+
+    #[test]
+    fn test_serialize_custom_model_untagged() {
+        let request = MessagesRequest::builder()
+            .messages(vec![])
+            .model(AnthropicModel::Custom("claude-3-5-haiku-20241022".into()))
+            .max_tokens(10)
+            .build();
+        let request_json = serde_json::to_string(&request).unwrap();
+        assert_eq!(
+            request_json,
+            r#"{"messages":[],"model":"claude-3-5-haiku-20241022","max_tokens":10}"#
+        );
+        let read_back: MessagesRequest = serde_json::from_str(&request_json).unwrap();
+        assert_eq!(read_back.model, request.model);
+        assert_eq!(
+            "claude-3-5-haiku-20241022"
+                .parse::<AnthropicModel>()
+                .unwrap(),
+            AnthropicModel::Custom("claude-3-5-haiku-20241022".into())
+        );
+    }
 }
@@ -1,6 +1,8 @@
 use serde::Deserialize;
 use thiserror::Error;
 
+use crate::clients::{RequestContext, SecretError};
+
 #[derive(Debug, Deserialize, PartialEq, Clone)]
 pub struct AnthropicErrorBody {
     pub r#type: String,
@@ -50,10 +52,107 @@ pub enum AnthropicError {
     /// # Carries underlying error that may have occurred during sending the request
     #[error("Error sending request: {0}")]
     ErrorSendingRequest(String),
+    /// # The request or connection took longer than the timeout in the [`crate::clients::HttpConfig`]
+    #[error("Timeout: {0}")]
+    Timeout(String),
     /// # Carries underlying error that may have occured when trying to get the response body
     #[error("Error getting response body: {0}")]
     ErrorGettingResponseBody(String),
     // # Carries underlying error and the status code
     #[error("Error deserializining response body: status code = {0}, error = {1}")]
     ErrorDeserializingResponseBody(u16, String),
+    /// # Carries underlying error if something went wrong when reading from a stream
+    #[error("Error reading stream: {0}")]
+    ErrorReadingStream(String),
+    /// # The estimated input tokens plus max_tokens do not fit in the context window, the request was not sent.
+    #[error("Context window exceeded: an estimated {input_tokens} input tokens plus max_tokens of {max_tokens} is larger than the context window of {context_window}")]
+    ContextWindowExceeded {
+        input_tokens: usize,
+        max_tokens: u32,
+        context_window: usize,
+    },
+    /// # The additional config or max_tokens sets an option the model does not support, the request was not sent.
+    #[error("Unsupported option: {option} is not supported by {model}")]
+    UnsupportedOption { model: String, option: String },
+    /// # The API key could not be fetched from the secret provider, the request was not sent.
+    #[error("Error fetching API key: {0}")]
+    ErrorFetchingApiKey(SecretError),
+    /// # An error from a request sent to Anthropic, with the context of the request
+    /// Use [`AnthropicError::kind`] to match on the underlying error.
+    #[error("{source} ({context})")]
+    Request {
+        context: RequestContext,
+        source: Box<AnthropicError>,
+    },
+}
+
+impl AnthropicError {
+    /// # [`AnthropicError::kind`]
+    ///
+    /// # Returns
+    /// * &[`AnthropicError`] - the underlying error without any [`RequestContext`].
+    pub fn kind(&self) -> &AnthropicError {
+        match self {
+            AnthropicError::Request { source, .. } => source.kind(),
+            error => error,
+        }
+    }
+
+    /// # [`AnthropicError::context`]
+    ///
+    /// # Returns
+    /// * [`Option<&RequestContext>`] - the context of the request the error came from,
+    ///   [`None`] if the error happened before a request was sent.
+    pub fn context(&self) -> Option<&RequestContext> {
+        match self {
+            AnthropicError::Request { context, .. } => Some(context),
+            _ => None,
+        }
+    }
+
+    /// # [`AnthropicError::from_reqwest`]
+    ///
+    /// Maps a reqwest error to [`AnthropicError::Timeout`] if it timed out, otherwise to the
+    /// error made by `otherwise`.
+    pub(crate) fn from_reqwest(error: reqwest::Error, otherwise: fn(String) -> Self) -> Self {
+        if error.is_timeout() {
+            return AnthropicError::Timeout(error.to_string());
+        }
+        otherwise(error.to_string())
+    }
+
+    /// # [`AnthropicError::from_error_body`]
+    ///
+    /// Maps an error without a status code, such as an error event part way through a stream,
+    /// onto the variant for its error type. See <https://docs.anthropic.com/en/api/errors>.
+    ///
+    /// # Arguments
+    /// * `error_body`: [`AnthropicErrorBody`] - the error sent by Anthropic.
+    ///
+    /// # Returns
+    /// * [`AnthropicError`] - the error which maps to the error type.
+    #[cfg(feature = "anthropic-stream")]
+    pub(crate) fn from_error_body(error_body: AnthropicErrorBody) -> Self {
+        match error_body.error.r#type.as_str() {
+            "invalid_request_error" => AnthropicError::CODE400(error_body),
+            "authentication_error" => AnthropicError::CODE401(error_body),
+            "permission_error" => AnthropicError::CODE403(error_body),
+            "not_found_error" => AnthropicError::CODE404(error_body),
+            "request_too_large" => AnthropicError::CODE413(error_body),
+            "rate_limit_error" => AnthropicError::CODE429(error_body),
+            "api_error" => AnthropicError::CODE500(error_body),
+            "overloaded_error" => AnthropicError::CODE503(error_body),
+            _ => AnthropicError::Undefined(200, format!("{:?}", error_body)),
+        }
+    }
+
+    /// # [`AnthropicError::with_context`]
+    ///
+    /// Attaches the context of the request to the error.
+    pub(crate) fn with_context(self, context: RequestContext) -> Self {
+        AnthropicError::Request {
+            context,
+            source: Box::new(self),
+        }
+    }
 }
@@ -0,0 +1,475 @@
+use crate::clients::bedrock::bedrock_core::BedrockHttpClient;
+use crate::clients::bedrock::model::converse::{
+    BedrockModel, Content, ConverseRequest, ConverseResponse, ImageBlock, ImageBlockSource,
+    InferenceConfig, Message, Role, SystemContent,
+};
+use crate::clients::bedrock::model::errors::BedrockError;
+use crate::clients::{
+    AsyncChatClient, ContentPart, DetailedChatResponse, FinishReason, ImageSource, PromptMessage,
+    TokenCounter,
+};
+use crate::common::{InvocationContext, TokenUsage};
+
+use aws_credential_types::provider::ProvideCredentials;
+
+/// # [`BedrockChatCompletionClient`]
+/// Allows for interacting with the Anthropic models hosted on AWS Bedrock via the
+/// Converse API. Requests are signed with SigV4 using the standard AWS credentials.
+///
+/// # Examples
+/// ```
+/// use rag_toolchain::clients::*;
+///
+/// async fn generate_completion() {
+///     let client: BedrockChatCompletionClient =
+///         BedrockChatCompletionClient::try_new(BedrockModel::Claude3Haiku, 1024)
+///             .await
+///             .unwrap()
+///             .with_temperature(0.5);
+///
+///     let system_message: PromptMessage =
+///         PromptMessage::SystemMessage("You only reply in a bullet point list".into());
+///     let user_message: PromptMessage = PromptMessage::HumanMessage("How does the water flow".into());
+///
+///     let reply = client
+///         .invoke(vec![system_message, user_message])
+///         .await
+///         .unwrap();
+///     println!("{:?}", reply.content());
+/// }
+/// ```
+/// # Required Environment
+/// A region and credentials the AWS SDK can find, e.g. AWS_REGION, AWS_ACCESS_KEY_ID and
+/// AWS_SECRET_ACCESS_KEY or a profile in the shared config files.
+pub struct BedrockChatCompletionClient {
+    url: String,
+    client: BedrockHttpClient,
+    model: BedrockModel,
+    inference_config: InferenceConfig,
+}
+
+impl BedrockChatCompletionClient {
+    const NO_TEXT_RESPONSE_ERROR: &'static str =
+        "Expected a text block in the response but received none";
+
+    /// # [`BedrockChatCompletionClient::try_new`]
+    ///
+    /// This method creates a new instance of the BedrockChatCompletionClient, the region
+    /// and credentials are loaded from the standard AWS environment and profile chain.
+    ///
+    /// # Arguments
+    /// * `model`: [`BedrockModel`] - The model to use for the chat completion.
+    /// * `max_tokens`: [`u32`] - The maximum number of tokens to generate in the response.
+    ///
+    /// # Errors
+    /// [`BedrockError::ErrorLoadingCredentials`] - If no region or credentials could be found.
+    ///
+    /// # Returns
+    /// [`BedrockChatCompletionClient`] - The client to interact with Bedrock.
+    pub async fn try_new(model: BedrockModel, max_tokens: u32) -> Result<Self, BedrockError> {
+        let client: BedrockHttpClient = BedrockHttpClient::try_new().await?;
+        Ok(Self::new_with_client(client, model, max_tokens))
+    }
+
+    /// # [`BedrockChatCompletionClient::new_with_credentials`]
+    ///
+    /// This method creates a new instance of the BedrockChatCompletionClient for the given
+    /// region which signs requests with the given credentials.
+    ///
+    /// # Arguments
+    /// * `model`: [`BedrockModel`] - The model to use for the chat completion.
+    /// * `max_tokens`: [`u32`] - The maximum number of tokens to generate in the response.
+    /// * `region`: impl [`Into<String>`] - The region the model is invoked in e.g. `us-east-1`.
+    /// * `credentials`: impl [`ProvideCredentials`] - Where the credentials are fetched from,
+    ///   this can be a fixed [`aws_credential_types::Credentials`].
+    ///
+    /// # Returns
+    /// [`BedrockChatCompletionClient`] - The client to interact with Bedrock.
+    pub fn new_with_credentials(
+        model: BedrockModel,
+        max_tokens: u32,
+        region: impl Into<String>,
+        credentials: impl ProvideCredentials + 'static,
+    ) -> Self {
+        let client = BedrockHttpClient::new_with_credentials(region, credentials);
+        Self::new_with_client(client, model, max_tokens)
+    }
+
+    fn new_with_client(client: BedrockHttpClient, model: BedrockModel, max_tokens: u32) -> Self {
+        BedrockChatCompletionClient {
+            url: converse_url(client.region(), &model),
+            client,
+            model,
+            inference_config: InferenceConfig {
+                max_tokens: Some(max_tokens),
+                ..InferenceConfig::default()
+            },
+        }
+    }
+
+    /// # [`BedrockChatCompletionClient::with_temperature`]
+    ///
+    /// # Arguments
+    /// * `temperature`: [`f32`] - The randomness of the response, from 0 to 1.
+    ///
+    /// # Returns
+    /// [`BedrockChatCompletionClient`] - The client with the temperature set.
+    pub fn with_temperature(mut self, temperature: f32) -> Self {
+        self.inference_config.temperature = Some(temperature);
+        self
+    }
+
+    /// # [`BedrockChatCompletionClient::with_top_p`]
+    ///
+    /// # Arguments
+    /// * `top_p`: [`f32`] - The cumulative probability of the tokens sampled from.
+    ///
+    /// # Returns
+    /// [`BedrockChatCompletionClient`] - The client with top_p set.
+    pub fn with_top_p(mut self, top_p: f32) -> Self {
+        self.inference_config.top_p = Some(top_p);
+        self
+    }
+
+    /// # [`BedrockChatCompletionClient::with_stop_sequences`]
+    ///
+    /// # Arguments
+    /// * `stop_sequences`: [`Vec<String>`] - Generation stops when any of these is generated.
+    ///
+    /// # Returns
+    /// [`BedrockChatCompletionClient`] - The client with the stop sequences set.
+    pub fn with_stop_sequences(mut self, stop_sequences: Vec<String>) -> Self {
+        self.inference_config.stop_sequences = stop_sequences;
+        self
+    }
+
+    /// # [`BedrockChatCompletionClient::model`]
+    ///
+    /// # Returns
+    /// &[`BedrockModel`] - The model requests are sent to.
+    pub fn model(&self) -> &BedrockModel {
+        &self.model
+    }
+
+    /// # [`BedrockChatCompletionClient::build_request`]
+    ///
+    /// Maps the prompt messages to the Converse request, system messages are
+    /// moved into the system field of the request.
+    ///
+    /// # Errors
+    /// [`BedrockError::UnsupportedContent`] - If a message has an image url.
+    fn build_request(
+        &self,
+        prompt_messages: Vec<PromptMessage>,
+    ) -> Result<ConverseRequest, BedrockError> {
+        let mut system: Vec<SystemContent> = Vec::new();
+        let mut messages: Vec<Message> = Vec::new();
+        for prompt_message in prompt_messages {
+            match prompt_message {
+                PromptMessage::SystemMessage(message) => system.push(SystemContent {
+                    text: message.content,
+                }),
+                PromptMessage::HumanMessage(message) => messages.push(Message {
+                    role: Role::User,
+                    content: vec![Content::Text(message.content)],
+                }),
+                PromptMessage::AIMessage(message) => messages.push(Message {
+                    role: Role::Assistant,
+                    content: vec![Content::Text(message.content)],
+                }),
+                PromptMessage::MultiModalHumanMessage(parts) => messages.push(Message {
+                    role: Role::User,
+                    content: parts
+                        .into_iter()
+                        .map(map_content_part)
+                        .collect::<Result<_, _>>()?,
+                }),
+            }
+        }
+        Ok(ConverseRequest {
+            messages,
+            system,
+            inference_config: self.inference_config.clone(),
+        })
+    }
+
+    /// # [`BedrockChatCompletionClient::text_message`]
+    ///
+    /// Helper method to join the text of every text block in the response into one message.
+    ///
+    /// # Errors
+    /// [`BedrockError::Undefined`] - if the response has no text blocks.
+    fn text_message(response: &ConverseResponse) -> Result<PromptMessage, BedrockError> {
+        match response.text() {
+            Some(text) => Ok(PromptMessage::AIMessage(text.into())),
+            None => Err(BedrockError::Undefined(
+                200,
+                Self::NO_TEXT_RESPONSE_ERROR.to_string(),
+            )),
+        }
+    }
+}
+
+impl TokenCounter for BedrockChatCompletionClient {}
+
+impl AsyncChatClient for BedrockChatCompletionClient {
+    type ErrorType = BedrockError;
+
+    /// # [`BedrockChatCompletionClient::invoke`]
+    ///
+    /// Function to send a list of [`PromptMessage`] to Bedrock and receive a response.
+    ///
+    /// # Arguments
+    /// * `prompt_messages`: [`Vec<PromptMessage>`] - The list of messages to send to the API.
+    ///
+    /// # Errors
+    /// * [`BedrockError::UnsupportedContent`] - If a message has an image url.
+    /// * [`BedrockError`] - This error is returned when the credentials could not be loaded
+    ///   or Bedrock returns an error.
+    ///
+    /// # Returns
+    /// [`PromptMessage::AIMessage`] - The response from the API.
+    async fn invoke(
+        &self,
+        prompt_messages: Vec<PromptMessage>,
+    ) -> Result<PromptMessage, Self::ErrorType> {
+        let request: ConverseRequest = self.build_request(prompt_messages)?;
+        let response: ConverseResponse = self.client.send_request(request, &self.url).await?;
+        Self::text_message(&response)
+    }
+
+    /// # [`BedrockChatCompletionClient::invoke_with_context`]
+    ///
+    /// The same as [`BedrockChatCompletionClient::invoke`] but the response carries
+    /// the request id from the context and the token usage Bedrock reported.
+    ///
+    /// # Arguments
+    /// * `prompt_messages`: [`Vec<PromptMessage>`] - The list of messages to send to the API.
+    /// * `context`: &[`InvocationContext`] - the context of the invocation.
+    ///
+    /// # Errors
+    /// * [`BedrockError`] - for the same reasons as [`BedrockChatCompletionClient::invoke`].
+    ///
+    /// # Returns
+    /// [`DetailedChatResponse`] - The response from the API along with the request id and usage.
+    async fn invoke_with_context(
+        &self,
+        prompt_messages: Vec<PromptMessage>,
+        context: &InvocationContext,
+    ) -> Result<DetailedChatResponse, Self::ErrorType> {
+        let request: ConverseRequest = self.build_request(prompt_messages)?;
+        let response: ConverseResponse = self.client.send_request(request, &self.url).await?;
+        Ok(DetailedChatResponse {
+            message: Self::text_message(&response)?,
+            request_id: context.request_id(),
+            provider_request_id: None,
+            system_fingerprint: None,
+            usage: Some(TokenUsage::from(&response.usage)),
+            finish_reason: response.stop_reason.as_ref().map(FinishReason::from),
+        })
+    }
+}
+
+/// Bedrock reads images from the request only, the format is the
+/// mime type without the `image/` prefix.
+fn map_content_part(part: ContentPart) -> Result<Content, BedrockError> {
+    match part {
+        ContentPart::Text(text) => Ok(Content::Text(text)),
+        ContentPart::Image(ImageSource::Base64 { media_type, data }) => {
+            Ok(Content::Image(ImageBlock {
+                format: media_type
+                    .strip_prefix("image/")
+                    .unwrap_or(&media_type)
+                    .to_string(),
+                source: ImageBlockSource { bytes: data },
+            }))
+        }
+        ContentPart::Image(ImageSource::Url(url)) => {
+            Err(BedrockError::UnsupportedContent(format!(
+                "image urls are not supported, send the image as base64: {}",
+                url
+            )))
+        }
+    }
+}
+
+/// The model id is a single path segment so the `:` in the version and
+/// the `/` in an ARN are percent encoded.
+fn converse_url(region: &str, model: &BedrockModel) -> String {
+    let model_id: String = model.as_str().replace(':', "%3A").replace('/', "%2F");
+    format!(
+        "https://bedrock-runtime.{}.amazonaws.com/model/{}/converse",
+        region, model_id
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aws_credential_types::Credentials;
+    use mockito::{Matcher, Mock, Server, ServerGuard};
+    use serde_json::json;
+
+    const CONVERSE_RESPONSE: &str = r#"
+    {
+        "output": {
+            "message": {
+                "role": "assistant",
+                "content": [{"text": "Hello!"}]
+            }
+        },
+        "stopReason": "max_tokens",
+        "usage": {"inputTokens": 12, "outputTokens": 6, "totalTokens": 18},
+        "metrics": {"latencyMs": 250}
+    }
+    "#;
+
+    #[test]
+    fn url_encodes_the_model_id() {
+        assert_eq!(
+            converse_url("us-east-1", &BedrockModel::Claude3Haiku),
+            "https://bedrock-runtime.us-east-1.amazonaws.com/model/anthropic.claude-3-haiku-20240307-v1%3A0/converse"
+        );
+    }
+
+    #[tokio::test]
+    async fn invoke_maps_messages_to_converse() {
+        let (client, mut server) = with_mocked_client().await;
+        let expected_body = json!({
+            "system": [{"text": "You are a comedian"}],
+            "messages": [
+                {"role": "user", "content": [{"text": "Hello, Claude"}]},
+                {"role": "assistant", "content": [{"text": "Hi"}]},
+                {"role": "user", "content": [
+                    {"text": "What is this?"},
+                    {"image": {"format": "png", "source": {"bytes": "aGVsbG8="}}}
+                ]}
+            ],
+            "inferenceConfig": {"maxTokens": 1024, "temperature": 0.5}
+        });
+        let mock = with_mocked_request(&mut server, 200, CONVERSE_RESPONSE)
+            .match_body(Matcher::Json(expected_body))
+            .create();
+
+        let response = client
+            .with_temperature(0.5)
+            .invoke(vec![
+                PromptMessage::SystemMessage("You are a comedian".into()),
+                PromptMessage::HumanMessage("Hello, Claude".into()),
+                PromptMessage::AIMessage("Hi".into()),
+                PromptMessage::MultiModalHumanMessage(vec![
+                    ContentPart::Text("What is this?".into()),
+                    ContentPart::Image(ImageSource::Base64 {
+                        media_type: "image/png".into(),
+                        data: "aGVsbG8=".into(),
+                    }),
+                ]),
+            ])
+            .await
+            .unwrap();
+
+        mock.assert();
+        assert_eq!(response, PromptMessage::AIMessage("Hello!".into()));
+    }
+
+    #[tokio::test]
+    async fn invoke_with_context_reports_usage() {
+        let (client, mut server) = with_mocked_client().await;
+        let mock = with_mocked_request(&mut server, 200, CONVERSE_RESPONSE).create();
+        let context = InvocationContext::new();
+
+        let response = client
+            .invoke_with_context(vec![PromptMessage::HumanMessage("Hello".into())], &context)
+            .await
+            .unwrap();
+
+        mock.assert();
+        assert_eq!(response.request_id, context.request_id());
+        assert_eq!(response.usage, Some(TokenUsage::new(12, 6)));
+        assert_eq!(response.finish_reason, Some(FinishReason::Length));
+    }
+
+    #[tokio::test]
+    async fn image_urls_are_never_sent() {
+        let (client, mut server) = with_mocked_client().await;
+        let mock = with_mocked_request(&mut server, 200, CONVERSE_RESPONSE)
+            .expect(0)
+            .create();
+
+        let error = client
+            .invoke(vec![PromptMessage::MultiModalHumanMessage(vec![
+                ContentPart::Image(ImageSource::Url("https://example.com/cat.png".into())),
+            ])])
+            .await
+            .unwrap_err();
+
+        mock.assert();
+        assert!(matches!(error, BedrockError::UnsupportedContent(_)));
+    }
+
+    #[tokio::test]
+    async fn throttling_is_returned() {
+        let (client, mut server) = with_mocked_client().await;
+        let mock = with_mocked_request(&mut server, 429, r#"{"message": "Too many requests"}"#)
+            .with_header(
+                "x-amzn-ErrorType",
+                "ThrottlingException:http://internal.amazon.com/coral/com.amazon.bedrock/",
+            )
+            .create();
+
+        let error = client
+            .invoke(vec![PromptMessage::HumanMessage("Hello".into())])
+            .await
+            .unwrap_err();
+
+        mock.assert();
+        assert!(matches!(error.kind(), BedrockError::Throttling(_)));
+        assert_eq!(
+            error.context().unwrap().endpoint,
+            "/model/anthropic.claude-3-haiku-20240307-v1%3A0/converse"
+        );
+    }
+
+    fn with_mocked_request(
+        server: &mut ServerGuard,
+        status_code: usize,
+        response_body: &str,
+    ) -> Mock {
+        server
+            .mock(
+                "POST",
+                "/model/anthropic.claude-3-haiku-20240307-v1%3A0/converse",
+            )
+            .match_header(
+                "authorization",
+                Matcher::Regex(r"^AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/\d{8}/us-east-1/bedrock/aws4_request, ".into()),
+            )
+            .with_status(status_code)
+            .with_header("content-type", "application/json")
+            .with_body(response_body)
+    }
+
+    // This methods returns a client which is pointing at the mocked url
+    // and the mock server which we can orchestrate the stubbings on.
+    async fn with_mocked_client() -> (BedrockChatCompletionClient, ServerGuard) {
+        let server = Server::new_async().await;
+        let credentials = Credentials::new(
+            "AKIDEXAMPLE",
+            "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY",
+            None,
+            None,
+            "test",
+        );
+        let mut client = BedrockChatCompletionClient::new_with_credentials(
+            BedrockModel::Claude3Haiku,
+            1024,
+            "us-east-1",
+            credentials,
+        );
+        client.url = format!(
+            "{}/model/anthropic.claude-3-haiku-20240307-v1%3A0/converse",
+            server.url()
+        );
+        (client, server)
+    }
+}
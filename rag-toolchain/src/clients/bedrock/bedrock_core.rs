@@ -0,0 +1,433 @@
+use crate::clients::bedrock::model::errors::{BedrockError, BedrockErrorBody};
+use crate::clients::RequestContext;
+
+use aws_config::{BehaviorVersion, SdkConfig};
+use aws_credential_types::provider::{ProvideCredentials, SharedCredentialsProvider};
+use aws_credential_types::Credentials;
+use aws_sigv4::http_request::{sign, SignableBody, SignableRequest, SigningSettings};
+use aws_sigv4::sign::v4;
+use reqwest::header::CONTENT_TYPE;
+use reqwest::{Client, RequestBuilder, Response, StatusCode};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::time::{Instant, SystemTime};
+
+/// The name requests to the Bedrock runtime are signed for
+const SIGNING_NAME: &str = "bedrock";
+/// The header Bedrock names the exception in, e.g. `ThrottlingException:http://...`
+const ERROR_TYPE_HEADER: &str = "x-amzn-errortype";
+
+#[derive(Debug)]
+pub struct BedrockHttpClient {
+    client: Client,
+    region: String,
+    credentials: SharedCredentialsProvider,
+}
+
+impl BedrockHttpClient {
+    /// # [`BedrockHttpClient::try_new`]
+    /// Loads the region and credentials from the standard AWS chain, this is the
+    /// environment, the shared config and credentials files, then the container
+    /// and instance metadata.
+    ///
+    /// # Errors
+    /// * [`BedrockError::ErrorLoadingCredentials`] - If no region or credentials provider is found
+    ///
+    /// # Returns
+    /// * [`BedrockHttpClient`] - The newly created BedrockHttpClient
+    pub async fn try_new() -> Result<BedrockHttpClient, BedrockError> {
+        let config: SdkConfig = aws_config::load_defaults(BehaviorVersion::latest()).await;
+        let region: String = config
+            .region()
+            .map(ToString::to_string)
+            .ok_or_else(|| BedrockError::ErrorLoadingCredentials("no region was found".into()))?;
+        let credentials: SharedCredentialsProvider =
+            config.credentials_provider().ok_or_else(|| {
+                BedrockError::ErrorLoadingCredentials("no credentials provider was found".into())
+            })?;
+        Ok(BedrockHttpClient {
+            client: Client::new(),
+            region,
+            credentials,
+        })
+    }
+
+    /// # [`BedrockHttpClient::new_with_credentials`]
+    ///
+    /// # Arguments
+    /// * `region` - The region requests are sent to and signed for
+    /// * `credentials` - Where the credentials requests are signed with are fetched from,
+    ///   this can be a fixed [`Credentials`]
+    ///
+    /// # Returns
+    /// * [`BedrockHttpClient`] - The newly created BedrockHttpClient
+    pub fn new_with_credentials(
+        region: impl Into<String>,
+        credentials: impl ProvideCredentials + 'static,
+    ) -> BedrockHttpClient {
+        BedrockHttpClient {
+            client: Client::new(),
+            region: region.into(),
+            credentials: SharedCredentialsProvider::new(credentials),
+        }
+    }
+
+    /// # [`BedrockHttpClient::region`]
+    ///
+    /// # Returns
+    /// * &[`str`] - the region requests are sent to.
+    pub fn region(&self) -> &str {
+        &self.region
+    }
+
+    /// # [`BedrockHttpClient::send_request`]
+    /// Signs and sends a request to Bedrock and returns the deserialized response.
+    /// The credentials are fetched for every request so temporary credentials are
+    /// refreshed by the provider before they expire.
+    ///
+    /// # Arguments
+    /// * `body` - The body of the request
+    /// * `url` - The url to send the request to
+    ///
+    /// # Errors
+    /// * [`BedrockError::ErrorLoadingCredentials`] - if the credentials could not be fetched
+    /// * [`BedrockError::ErrorSigningRequest`] - if the request could not be signed
+    /// * [`BedrockError::Request`] - wraps any of the errors below with the [`RequestContext`]
+    /// * [`BedrockError::ErrorSendingRequest`] - if request.send() errors
+    /// * [`BedrockError::ErrorGettingResponseBody`] - if response.text() errors
+    /// * [`BedrockError::ErrorDeserializingResponseBody`] - if serde_json::from_str() errors
+    /// * [`BedrockError`] - if the response code is not 200 this can be any of the mapped
+    ///   exceptions or [`BedrockError::Undefined`]
+    ///
+    /// # Returns
+    /// [`U`] - The deserialized response from Bedrock
+    pub async fn send_request<T, U>(&self, body: T, url: &str) -> Result<U, BedrockError>
+    where
+        T: Serialize,
+        U: DeserializeOwned,
+    {
+        let credentials: Credentials = self
+            .credentials
+            .provide_credentials()
+            .await
+            .map_err(|error| BedrockError::ErrorLoadingCredentials(error.to_string()))?;
+        let request: RequestBuilder = self.build_request(&body, url, credentials)?;
+        let started: Instant = Instant::now();
+        self.send(request).await.map_err(|error| {
+            error.with_context(RequestContext::new(url, &body, started.elapsed(), 1))
+        })
+    }
+
+    /// # [`BedrockHttpClient::send`]
+    ///
+    /// Sends the built request, mapping any error responses and
+    /// deserializing the body of a successful response.
+    async fn send<U>(&self, request: RequestBuilder) -> Result<U, BedrockError>
+    where
+        U: DeserializeOwned,
+    {
+        let response: Response = request
+            .send()
+            .await
+            .map_err(|error| BedrockError::ErrorSendingRequest(error.to_string()))?;
+        let status_code: StatusCode = response.status();
+        if !status_code.is_success() {
+            return Err(Self::handle_error_response(response).await);
+        }
+        let response_body: String = response
+            .text()
+            .await
+            .map_err(|error| BedrockError::ErrorGettingResponseBody(error.to_string()))?;
+        serde_json::from_str(&response_body).map_err(|error| {
+            BedrockError::ErrorDeserializingResponseBody(status_code.as_u16(), error.to_string())
+        })
+    }
+
+    /// # [`BedrockHttpClient::build_request`]
+    ///
+    /// Helper method to build a request with the body and the SigV4 signature headers,
+    /// the body is serialized once so the bytes sent are the bytes which were signed.
+    fn build_request<T>(
+        &self,
+        request_body: &T,
+        url: &str,
+        credentials: Credentials,
+    ) -> Result<RequestBuilder, BedrockError>
+    where
+        T: Serialize,
+    {
+        let body: Vec<u8> = serde_json::to_vec(request_body)
+            .map_err(|error| BedrockError::ErrorSigningRequest(error.to_string()))?;
+        let content_type: &str = "application/json";
+        let identity = credentials.into();
+        let signing_params = v4::SigningParams::builder()
+            .identity(&identity)
+            .region(&self.region)
+            .name(SIGNING_NAME)
+            .time(SystemTime::now())
+            .settings(SigningSettings::default())
+            .build()
+            .map_err(|error| BedrockError::ErrorSigningRequest(error.to_string()))?
+            .into();
+        let signable_request = SignableRequest::new(
+            "POST",
+            url,
+            std::iter::once((CONTENT_TYPE.as_str(), content_type)),
+            SignableBody::Bytes(&body),
+        )
+        .map_err(|error| BedrockError::ErrorSigningRequest(error.to_string()))?;
+        let (instructions, _signature) = sign(signable_request, &signing_params)
+            .map_err(|error| BedrockError::ErrorSigningRequest(error.to_string()))?
+            .into_parts();
+
+        let mut request: RequestBuilder = self
+            .client
+            .post(url)
+            .header(CONTENT_TYPE, content_type)
+            .body(body);
+        for (name, value) in instructions.headers() {
+            request = request.header(name, value);
+        }
+        Ok(request)
+    }
+
+    /// # [`BedrockHttpClient::handle_error_response`]
+    ///
+    /// Explicit error mapping between Bedrock exceptions and error types. The exception
+    /// is named in the `x-amzn-ErrorType` header, the status code is used if it is missing.
+    ///
+    /// # Arguments
+    /// `response` - The reqwest response from Bedrock
+    ///
+    /// # Returns
+    /// [`BedrockError`] - The error type that maps to the exception
+    async fn handle_error_response(response: Response) -> BedrockError {
+        let status_code = response.status().as_u16();
+        let error_type: Option<String> = response
+            .headers()
+            .get(ERROR_TYPE_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.split(':').next())
+            .map(String::from);
+        let body_text = match response.text().await {
+            Ok(text) => text,
+            Err(e) => return BedrockError::Undefined(status_code, e.to_string()),
+        };
+
+        let error_body: BedrockErrorBody = match serde_json::from_str(&body_text) {
+            Ok(error_body) => error_body,
+            Err(e) => {
+                return BedrockError::ErrorDeserializingResponseBody(status_code, e.to_string())
+            }
+        };
+        match (error_type.as_deref(), status_code) {
+            (Some("ValidationException"), _) | (None, 400) => BedrockError::Validation(error_body),
+            (Some("AccessDeniedException"), _) | (None, 403) => {
+                BedrockError::AccessDenied(error_body)
+            }
+            (Some("ResourceNotFoundException"), _) | (None, 404) => {
+                BedrockError::ResourceNotFound(error_body)
+            }
+            (Some("ModelTimeoutException"), _) | (None, 408) => {
+                BedrockError::ModelTimeout(error_body)
+            }
+            (Some("ModelErrorException"), _) | (None, 424) => BedrockError::ModelError(error_body),
+            (Some("ThrottlingException"), _) | (None, 429) => BedrockError::Throttling(error_body),
+            (Some("ModelNotReadyException"), _) => BedrockError::ModelNotReady(error_body),
+            (Some("InternalServerException"), _) | (None, 500) => {
+                BedrockError::InternalServer(error_body)
+            }
+            (Some("ServiceUnavailableException"), _) | (None, 503) => {
+                BedrockError::ServiceUnavailable(error_body)
+            }
+            (_, undefined) => BedrockError::Undefined(undefined, body_text),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mockito::{Matcher, Mock, Server, ServerGuard};
+    use serde::{Deserialize, Serialize};
+
+    const ERROR_RESPONSE: &str =
+        r#"{"message": "The security token included in the request is invalid"}"#;
+
+    #[tokio::test]
+    async fn requests_are_signed() {
+        let (client, mut server) = with_mocked_client().await;
+        let mock = server
+            .mock("POST", "/")
+            .match_header(
+                "authorization",
+                Matcher::Regex(
+                    r"^AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/\d{8}/eu-west-2/bedrock/aws4_request, SignedHeaders=content-type;host;x-amz-date;x-amz-security-token, Signature=[0-9a-f]{64}$"
+                        .into(),
+                ),
+            )
+            .match_header("x-amz-date", Matcher::Regex(r"^\d{8}T\d{6}Z$".into()))
+            .match_header("x-amz-security-token", "session token")
+            .match_body(Matcher::Json(serde_json::json!({"model": "claude"})))
+            .with_status(200)
+            .with_body(r#"{"model": "claude"}"#)
+            .create();
+        let response: RequestBody = client
+            .send_request(request_body(), &server.url())
+            .await
+            .unwrap();
+        mock.assert();
+        assert_eq!(response.model, "claude");
+    }
+
+    #[tokio::test]
+    async fn error_types_map_correctly() {
+        let error_body: BedrockErrorBody = serde_json::from_str(ERROR_RESPONSE).unwrap();
+        let expected: Vec<(usize, &str, BedrockError)> = vec![
+            (
+                400,
+                "ValidationException",
+                BedrockError::Validation(error_body.clone()),
+            ),
+            (
+                403,
+                "AccessDeniedException",
+                BedrockError::AccessDenied(error_body.clone()),
+            ),
+            (
+                404,
+                "ResourceNotFoundException",
+                BedrockError::ResourceNotFound(error_body.clone()),
+            ),
+            (
+                408,
+                "ModelTimeoutException",
+                BedrockError::ModelTimeout(error_body.clone()),
+            ),
+            (
+                424,
+                "ModelErrorException",
+                BedrockError::ModelError(error_body.clone()),
+            ),
+            (
+                429,
+                "ThrottlingException",
+                BedrockError::Throttling(error_body.clone()),
+            ),
+            (
+                429,
+                "ModelNotReadyException",
+                BedrockError::ModelNotReady(error_body.clone()),
+            ),
+            (
+                500,
+                "InternalServerException",
+                BedrockError::InternalServer(error_body.clone()),
+            ),
+            (
+                503,
+                "ServiceUnavailableException",
+                BedrockError::ServiceUnavailable(error_body),
+            ),
+            (
+                469,
+                "SomeNewException",
+                BedrockError::Undefined(469, ERROR_RESPONSE.into()),
+            ),
+        ];
+        for (status_code, error_type, expected_error) in expected {
+            let header = format!(
+                "{}:http://internal.amazon.com/coral/com.amazon.bedrock/",
+                error_type
+            );
+            assert_error_mapping(status_code, Some(&header), expected_error).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn status_codes_map_without_error_type() {
+        let error_body: BedrockErrorBody = serde_json::from_str(ERROR_RESPONSE).unwrap();
+        let expected: Vec<(usize, BedrockError)> = vec![
+            (400, BedrockError::Validation(error_body.clone())),
+            (403, BedrockError::AccessDenied(error_body.clone())),
+            (429, BedrockError::Throttling(error_body.clone())),
+            (503, BedrockError::ServiceUnavailable(error_body)),
+            (469, BedrockError::Undefined(469, ERROR_RESPONSE.into())),
+        ];
+        for (status_code, expected_error) in expected {
+            assert_error_mapping(status_code, None, expected_error).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn error_deserializing_response_body_maps_correctly() {
+        let (client, mut server) = with_mocked_client().await;
+        let mock = server
+            .mock("POST", "/")
+            .with_status(200)
+            .with_body("some invalid response")
+            .create();
+        let error = client
+            .send_request::<RequestBody, RequestBody>(request_body(), &server.url())
+            .await
+            .unwrap_err();
+        mock.assert();
+        assert_eq!(
+            &BedrockError::ErrorDeserializingResponseBody(
+                200,
+                "expected value at line 1 column 1".into()
+            ),
+            error.kind()
+        );
+    }
+
+    // Helper method to assert an error response is mapped correctly
+    async fn assert_error_mapping(
+        status_code: usize,
+        error_type: Option<&str>,
+        expected_error: BedrockError,
+    ) {
+        let (client, mut server) = with_mocked_client().await;
+        let mut mock: Mock = server
+            .mock("POST", "/")
+            .with_status(status_code)
+            .with_body(ERROR_RESPONSE);
+        if let Some(error_type) = error_type {
+            mock = mock.with_header("x-amzn-ErrorType", error_type);
+        }
+        let mock = mock.create();
+        let error: BedrockError = client
+            .send_request::<RequestBody, RequestBody>(request_body(), &server.url())
+            .await
+            .unwrap_err();
+        mock.assert();
+        assert_eq!(&expected_error, error.kind());
+        assert!(error.context().is_some());
+    }
+
+    // This methods returns a client which signs with fixed credentials
+    // and the mock server which we can orchestrate the stubbings on.
+    async fn with_mocked_client() -> (BedrockHttpClient, ServerGuard) {
+        let server = Server::new_async().await;
+        let credentials = Credentials::new(
+            "AKIDEXAMPLE",
+            "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY",
+            Some("session token".into()),
+            None,
+            "test",
+        );
+        let client = BedrockHttpClient::new_with_credentials("eu-west-2", credentials);
+        (client, server)
+    }
+
+    fn request_body() -> RequestBody {
+        RequestBody {
+            model: "claude".into(),
+        }
+    }
+
+    #[derive(Debug, Serialize, Deserialize)]
+    struct RequestBody {
+        model: String,
+    }
+}
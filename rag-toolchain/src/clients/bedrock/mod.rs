@@ -0,0 +1,7 @@
+mod bedrock_converse;
+mod bedrock_core;
+mod model;
+
+pub use bedrock_converse::BedrockChatCompletionClient;
+pub use model::converse::{BedrockModel, BedrockStopReason};
+pub use model::errors::{BedrockError, BedrockErrorBody};
@@ -0,0 +1,263 @@
+use serde::{Deserialize, Serialize};
+use std::convert::Infallible;
+use std::fmt::{self, Display, Formatter};
+use std::str::FromStr;
+
+use crate::clients::types::FinishReason;
+use crate::common::TokenUsage;
+
+/// See <https://docs.aws.amazon.com/bedrock/latest/APIReference/API_runtime_Converse.html>
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ConverseRequest {
+    pub messages: Vec<Message>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub system: Vec<SystemContent>,
+    pub inference_config: InferenceConfig,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct InferenceConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_tokens: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f32>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub stop_sequences: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub struct SystemContent {
+    pub text: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub struct Message {
+    pub role: Role,
+    pub content: Vec<Content>,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    User,
+    Assistant,
+}
+
+/// A content block of a message, blocks this library does not handle such as tool use
+/// are deserialized as [`Content::Unknown`].
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+#[serde(rename_all = "camelCase")]
+pub enum Content {
+    Text(String),
+    Image(ImageBlock),
+    #[serde(untagged)]
+    Unknown(serde_json::Value),
+}
+
+/// The format is the image type without the `image/` prefix e.g. `png`
+/// and the bytes are base64 encoded.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub struct ImageBlock {
+    pub format: String,
+    pub source: ImageBlockSource,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub struct ImageBlockSource {
+    pub bytes: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ConverseResponse {
+    pub output: Output,
+    #[serde(default)]
+    pub stop_reason: Option<BedrockStopReason>,
+    pub usage: Usage,
+}
+
+impl ConverseResponse {
+    /// # [`ConverseResponse::text`]
+    ///
+    /// # Returns
+    /// * [`Option<String>`] - the text of every text block joined together, or [`None`]
+    ///   if the response has no text blocks.
+    pub fn text(&self) -> Option<String> {
+        let texts: Vec<&str> = self
+            .output
+            .message
+            .content
+            .iter()
+            .filter_map(|block| match block {
+                Content::Text(text) => Some(text.as_str()),
+                _ => None,
+            })
+            .collect();
+        if texts.is_empty() {
+            return None;
+        }
+        Some(texts.concat())
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub struct Output {
+    pub message: Message,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Usage {
+    pub input_tokens: usize,
+    pub output_tokens: usize,
+}
+
+impl From<&Usage> for TokenUsage {
+    fn from(usage: &Usage) -> Self {
+        TokenUsage::new(usage.input_tokens, usage.output_tokens)
+    }
+}
+
+/// # [`BedrockStopReason`]
+///
+/// Why the model stopped generating, reasons added to the API later are
+/// deserialized as [`BedrockStopReason::Unknown`].
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+#[serde(rename_all = "snake_case")]
+pub enum BedrockStopReason {
+    EndTurn,
+    ToolUse,
+    MaxTokens,
+    StopSequence,
+    GuardrailIntervened,
+    ContentFiltered,
+    #[serde(other)]
+    Unknown,
+}
+
+impl From<&BedrockStopReason> for FinishReason {
+    fn from(reason: &BedrockStopReason) -> Self {
+        match reason {
+            BedrockStopReason::EndTurn | BedrockStopReason::StopSequence => FinishReason::Stop,
+            BedrockStopReason::MaxTokens => FinishReason::Length,
+            BedrockStopReason::ToolUse => FinishReason::ToolCalls,
+            BedrockStopReason::GuardrailIntervened | BedrockStopReason::ContentFiltered => {
+                FinishReason::ContentFilter
+            }
+            BedrockStopReason::Unknown => FinishReason::Other("unknown".into()),
+        }
+    }
+}
+
+/// # [`BedrockModel`]
+///
+/// The Anthropic models available through Bedrock. Any other model id, inference profile
+/// or ARN can be used with [`BedrockModel::Custom`] which is sent exactly as given.
+/// Parsing an id with [`str::parse`] gives the listed model if there is one and a custom
+/// model otherwise.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub enum BedrockModel {
+    #[serde(rename = "anthropic.claude-3-5-sonnet-20240620-v1:0")]
+    Claude3Point5Sonnet,
+    #[serde(rename = "anthropic.claude-3-opus-20240229-v1:0")]
+    Claude3Opus,
+    #[serde(rename = "anthropic.claude-3-sonnet-20240229-v1:0")]
+    Claude3Sonnet,
+    #[serde(rename = "anthropic.claude-3-haiku-20240307-v1:0")]
+    Claude3Haiku,
+    #[serde(untagged)]
+    Custom(String),
+}
+
+impl BedrockModel {
+    /// # [`BedrockModel::as_str`]
+    ///
+    /// # Returns
+    /// * &[`str`] - the model id sent to Bedrock.
+    pub fn as_str(&self) -> &str {
+        match self {
+            BedrockModel::Claude3Point5Sonnet => "anthropic.claude-3-5-sonnet-20240620-v1:0",
+            BedrockModel::Claude3Opus => "anthropic.claude-3-opus-20240229-v1:0",
+            BedrockModel::Claude3Sonnet => "anthropic.claude-3-sonnet-20240229-v1:0",
+            BedrockModel::Claude3Haiku => "anthropic.claude-3-haiku-20240307-v1:0",
+            BedrockModel::Custom(id) => id,
+        }
+    }
+}
+
+impl Display for BedrockModel {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for BedrockModel {
+    type Err = Infallible;
+
+    fn from_str(id: &str) -> Result<Self, Self::Err> {
+        Ok(match id {
+            "anthropic.claude-3-5-sonnet-20240620-v1:0" => BedrockModel::Claude3Point5Sonnet,
+            "anthropic.claude-3-opus-20240229-v1:0" => BedrockModel::Claude3Opus,
+            "anthropic.claude-3-sonnet-20240229-v1:0" => BedrockModel::Claude3Sonnet,
+            "anthropic.claude-3-haiku-20240307-v1:0" => BedrockModel::Claude3Haiku,
+            custom => BedrockModel::Custom(custom.into()),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn response_deserializes() {
+        let response: ConverseResponse = serde_json::from_value(json!({
+            "output": {
+                "message": {
+                    "role": "assistant",
+                    "content": [
+                        {"text": "Water flows "},
+                        {"toolUse": {"toolUseId": "1", "name": "search", "input": {}}},
+                        {"text": "downhill"}
+                    ]
+                }
+            },
+            "stopReason": "end_turn",
+            "usage": {"inputTokens": 12, "outputTokens": 4, "totalTokens": 16},
+            "metrics": {"latencyMs": 320}
+        }))
+        .unwrap();
+        assert_eq!(response.text(), Some("Water flows downhill".into()));
+        assert_eq!(response.stop_reason, Some(BedrockStopReason::EndTurn));
+        assert_eq!(TokenUsage::from(&response.usage), TokenUsage::new(12, 4));
+    }
+
+    #[test]
+    fn unknown_stop_reasons_deserialize() {
+        let reason: BedrockStopReason = serde_json::from_value(json!("some_new_reason")).unwrap();
+        assert_eq!(reason, BedrockStopReason::Unknown);
+        assert_eq!(
+            FinishReason::from(&BedrockStopReason::GuardrailIntervened),
+            FinishReason::ContentFilter
+        );
+    }
+
+    #[test]
+    fn model_ids_round_trip() {
+        for model in [
+            BedrockModel::Claude3Point5Sonnet,
+            BedrockModel::Claude3Opus,
+            BedrockModel::Claude3Sonnet,
+            BedrockModel::Claude3Haiku,
+            BedrockModel::Custom("us.anthropic.claude-3-haiku-20240307-v1:0".into()),
+        ] {
+            assert_eq!(model.as_str().parse::<BedrockModel>().unwrap(), model);
+            assert_eq!(serde_json::to_value(&model).unwrap(), json!(model.as_str()));
+        }
+    }
+}
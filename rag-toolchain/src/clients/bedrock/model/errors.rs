@@ -0,0 +1,109 @@
+use serde::Deserialize;
+use thiserror::Error;
+
+use crate::clients::RequestContext;
+
+/// The body Bedrock sends with any error, it only carries a message
+#[derive(Debug, Deserialize, PartialEq, Clone)]
+pub struct BedrockErrorBody {
+    #[serde(alias = "Message")]
+    pub message: String,
+}
+
+/// # [`BedrockError`]
+///
+/// This error type largely mirrors the exceptions listed here
+/// <https://docs.aws.amazon.com/bedrock/latest/APIReference/API_runtime_Converse.html#API_runtime_Converse_Errors>.
+#[derive(Error, Debug, PartialEq, Clone)]
+pub enum BedrockError {
+    /// # The request was malformed or not supported by the model e.g. max_tokens is too large.
+    #[error("Validation Error: {0:?}")]
+    Validation(BedrockErrorBody),
+    /// # The credentials do not have access to the model, or access to it has not been requested.
+    #[error("Access Denied Error: {0:?}")]
+    AccessDenied(BedrockErrorBody),
+    /// # The model does not exist in the region.
+    #[error("Resource Not Found Error: {0:?}")]
+    ResourceNotFound(BedrockErrorBody),
+    /// # Too many requests have been sent, slow down.
+    #[error("Throttling Error: {0:?}")]
+    Throttling(BedrockErrorBody),
+    /// # The model took too long to respond.
+    #[error("Model Timeout Error: {0:?}")]
+    ModelTimeout(BedrockErrorBody),
+    /// # The model failed while processing the request.
+    #[error("Model Error: {0:?}")]
+    ModelError(BedrockErrorBody),
+    /// # The model is still being loaded, retry the request later.
+    #[error("Model Not Ready Error: {0:?}")]
+    ModelNotReady(BedrockErrorBody),
+    /// # An unexpected error has occurred internal to Bedrock.
+    #[error("Internal Server Error: {0:?}")]
+    InternalServer(BedrockErrorBody),
+    /// # Bedrock is temporarily unavailable.
+    #[error("Service Unavailable Error: {0:?}")]
+    ServiceUnavailable(BedrockErrorBody),
+    /// # Missed cases for error codes, includes Status Code and Error Body as a string. These can also represent internal logic errors.
+    #[error("Undefined Error. This should not happen, if this is a missed error please report it: https://github.com/JackMatthewRimmer/rust-rag-toolchain: status code = {0}, error = {1}")]
+    Undefined(u16, String),
+    /// # Carries underlying error that may have occurred during sending the request
+    #[error("Error sending request: {0}")]
+    ErrorSendingRequest(String),
+    /// # Carries underlying error that may have occured when trying to get the response body
+    #[error("Error getting response body: {0}")]
+    ErrorGettingResponseBody(String),
+    // # Carries underlying error and the status code
+    #[error("Error deserializining response body: status code = {0}, error = {1}")]
+    ErrorDeserializingResponseBody(u16, String),
+    /// # The AWS credentials could not be loaded, the request was not sent.
+    #[error("Error loading credentials: {0}")]
+    ErrorLoadingCredentials(String),
+    /// # The messages have content Bedrock cannot be sent e.g. an image url, the request was not sent.
+    #[error("Unsupported content: {0}")]
+    UnsupportedContent(String),
+    /// # The request could not be signed, the request was not sent.
+    #[error("Error signing request: {0}")]
+    ErrorSigningRequest(String),
+    /// # An error from a request sent to Bedrock, with the context of the request
+    /// Use [`BedrockError::kind`] to match on the underlying error.
+    #[error("{source} ({context})")]
+    Request {
+        context: RequestContext,
+        source: Box<BedrockError>,
+    },
+}
+
+impl BedrockError {
+    /// # [`BedrockError::kind`]
+    ///
+    /// # Returns
+    /// * &[`BedrockError`] - the underlying error without any [`RequestContext`].
+    pub fn kind(&self) -> &BedrockError {
+        match self {
+            BedrockError::Request { source, .. } => source.kind(),
+            error => error,
+        }
+    }
+
+    /// # [`BedrockError::context`]
+    ///
+    /// # Returns
+    /// * [`Option<&RequestContext>`] - the context of the request the error came from,
+    ///   [`None`] if the error happened before a request was sent.
+    pub fn context(&self) -> Option<&RequestContext> {
+        match self {
+            BedrockError::Request { context, .. } => Some(context),
+            _ => None,
+        }
+    }
+
+    /// # [`BedrockError::with_context`]
+    ///
+    /// Attaches the context of the request to the error.
+    pub(crate) fn with_context(self, context: RequestContext) -> Self {
+        BedrockError::Request {
+            context,
+            source: Box::new(self),
+        }
+    }
+}
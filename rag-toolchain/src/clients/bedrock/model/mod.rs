@@ -0,0 +1,2 @@
+pub mod converse;
+pub mod errors;
@@ -0,0 +1,340 @@
+#[cfg(feature = "anthropic")]
+use crate::clients::AnthropicModel;
+#[cfg(feature = "openai-chat")]
+use crate::clients::OpenAIModel;
+use crate::clients::PromptMessage;
+use serde_json::{Map, Value};
+
+/// # [`ModelCapabilities`]
+///
+/// What a model accepts, used to reject a request the provider would fail with a 400
+/// before it is sent. Every model's capabilities live in this file so adding a model
+/// is a one place change.
+///
+/// * `supports_temperature` - whether sampling options such as `temperature` and `top_p` can be set.
+/// * `supports_json_mode` - whether `response_format` can ask for JSON output.
+/// * `supports_tools` - whether `tools` or `functions` can be passed.
+/// * `supports_logprobs` - whether `logprobs` can be requested.
+/// * `supports_vision` - whether messages can contain images.
+/// * `max_context` - the number of tokens the model can attend to.
+/// * `max_output` - the maximum number of tokens the model can generate in a response.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ModelCapabilities {
+    pub supports_temperature: bool,
+    pub supports_json_mode: bool,
+    pub supports_tools: bool,
+    pub supports_logprobs: bool,
+    pub supports_vision: bool,
+    pub max_context: usize,
+    pub max_output: usize,
+}
+
+#[cfg(feature = "openai-chat")]
+impl OpenAIModel {
+    /// # [`OpenAIModel::capabilities`]
+    ///
+    /// # Returns
+    /// * [`ModelCapabilities`] - what options the model accepts and its token limits.
+    pub fn capabilities(&self) -> ModelCapabilities {
+        match self {
+            OpenAIModel::Gpt4oMini | OpenAIModel::Gpt4o => ModelCapabilities {
+                supports_temperature: true,
+                supports_json_mode: true,
+                supports_tools: true,
+                supports_logprobs: true,
+                supports_vision: true,
+                max_context: 128_000,
+                max_output: 16_384,
+            },
+            OpenAIModel::Gpt4Turbo => ModelCapabilities {
+                supports_temperature: true,
+                supports_json_mode: true,
+                supports_tools: true,
+                supports_logprobs: true,
+                supports_vision: true,
+                max_context: 128_000,
+                max_output: 4_096,
+            },
+            OpenAIModel::Gpt4 => ModelCapabilities {
+                supports_temperature: true,
+                supports_json_mode: false,
+                supports_tools: true,
+                supports_logprobs: true,
+                supports_vision: false,
+                max_context: 8_192,
+                max_output: 8_192,
+            },
+            OpenAIModel::Gpt3Point5Turbo => ModelCapabilities {
+                supports_temperature: true,
+                supports_json_mode: true,
+                supports_tools: true,
+                supports_logprobs: true,
+                supports_vision: false,
+                max_context: 16_385,
+                max_output: 4_096,
+            },
+            OpenAIModel::O1 | OpenAIModel::O3Mini => ModelCapabilities {
+                supports_temperature: false,
+                supports_json_mode: true,
+                supports_tools: true,
+                supports_logprobs: false,
+                supports_vision: *self == OpenAIModel::O1,
+                max_context: 200_000,
+                max_output: 100_000,
+            },
+            OpenAIModel::O1Mini => ModelCapabilities {
+                supports_temperature: false,
+                supports_json_mode: false,
+                supports_tools: false,
+                supports_logprobs: false,
+                supports_vision: false,
+                max_context: 128_000,
+                max_output: 65_536,
+            },
+            OpenAIModel::Custom(_) => ModelCapabilities::UNKNOWN,
+        }
+    }
+}
+
+#[cfg(feature = "anthropic")]
+impl AnthropicModel {
+    /// # [`AnthropicModel::capabilities`]
+    ///
+    /// # Returns
+    /// * [`ModelCapabilities`] - what options the model accepts and its token limits.
+    pub fn capabilities(&self) -> ModelCapabilities {
+        let max_output: usize = match self {
+            AnthropicModel::Claude3Point5Sonnet => 8_192,
+            AnthropicModel::Claude3Opus
+            | AnthropicModel::Claude3Sonnet
+            | AnthropicModel::Claude3Haiku => 4_096,
+            // Every Claude model has a 200k context, the output limit is left to Anthropic
+            AnthropicModel::Custom(_) => 200_000,
+        };
+        ModelCapabilities {
+            supports_temperature: true,
+            supports_json_mode: false,
+            supports_tools: true,
+            supports_logprobs: false,
+            supports_vision: true,
+            max_context: 200_000,
+            max_output,
+        }
+    }
+}
+
+impl ModelCapabilities {
+    /// The capabilities of a custom model. Nothing is known about it so every option is
+    /// allowed and left for the provider to validate.
+    pub const UNKNOWN: ModelCapabilities = ModelCapabilities {
+        supports_temperature: true,
+        supports_json_mode: true,
+        supports_tools: true,
+        supports_logprobs: true,
+        supports_vision: true,
+        max_context: 128_000,
+        max_output: usize::MAX,
+    };
+
+    /// # [`ModelCapabilities::unsupported_option`]
+    ///
+    /// Checks the additional config of a client against the capabilities.
+    /// Keys which are not covered by a capability are left for the provider to validate.
+    ///
+    /// # Arguments
+    /// * `additional_config`: &[`Map<String, Value>`] - the config sent alongside the request.
+    ///
+    /// # Returns
+    /// * [`Option<String>`] - a description of the first unsupported option, if there is one.
+    pub(crate) fn unsupported_option(
+        &self,
+        additional_config: &Map<String, Value>,
+    ) -> Option<String> {
+        additional_config.iter().find_map(|(key, value)| {
+            let supported: bool = match key.as_str() {
+                "temperature" | "top_p" => self.supports_temperature,
+                "response_format" => !is_json_mode(value) || self.supports_json_mode,
+                "tools" | "tool_choice" | "functions" | "function_call" => self.supports_tools,
+                "logprobs" | "top_logprobs" => {
+                    value == &Value::Bool(false) || self.supports_logprobs
+                }
+                "max_tokens" | "max_completion_tokens" => {
+                    return value
+                        .as_u64()
+                        .and_then(|max_tokens| self.unsupported_max_tokens(key, max_tokens));
+                }
+                _ => true,
+            };
+            (!supported).then(|| key.clone())
+        })
+    }
+
+    /// # [`ModelCapabilities::unsupported_content`]
+    ///
+    /// Checks the messages of a request contain nothing the model cannot read.
+    ///
+    /// # Arguments
+    /// * `messages`: &[`[PromptMessage]`] - the messages to be sent.
+    ///
+    /// # Returns
+    /// * [`Option<String>`] - a description of the unsupported content, if there is any.
+    pub(crate) fn unsupported_content(&self, messages: &[PromptMessage]) -> Option<String> {
+        (!self.supports_vision && messages.iter().any(PromptMessage::has_images))
+            .then(|| "image content".into())
+    }
+
+    /// # [`ModelCapabilities::unsupported_max_tokens`]
+    ///
+    /// Checks a max tokens option against the maximum output of the model.
+    ///
+    /// # Returns
+    /// * [`Option<String>`] - a description of the option if it is too large.
+    pub(crate) fn unsupported_max_tokens(&self, option: &str, max_tokens: u64) -> Option<String> {
+        (max_tokens > self.max_output as u64).then(|| {
+            format!(
+                "{} of {} (the maximum output is {})",
+                option, max_tokens, self.max_output
+            )
+        })
+    }
+}
+
+fn is_json_mode(response_format: &Value) -> bool {
+    matches!(
+        response_format.get("type").and_then(Value::as_str),
+        Some("json_object" | "json_schema")
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const FULL: ModelCapabilities = ModelCapabilities {
+        supports_temperature: true,
+        supports_json_mode: true,
+        supports_tools: true,
+        supports_logprobs: true,
+        supports_vision: true,
+        max_context: 1_000,
+        max_output: 100,
+    };
+    const NONE: ModelCapabilities = ModelCapabilities {
+        supports_temperature: false,
+        supports_json_mode: false,
+        supports_tools: false,
+        supports_logprobs: false,
+        supports_vision: false,
+        max_context: 1_000,
+        max_output: 100,
+    };
+
+    fn config(value: Value) -> Map<String, Value> {
+        value.as_object().unwrap().clone()
+    }
+
+    #[test]
+    fn temperature_requires_support() {
+        let temperature = config(json!({"temperature": 0.5}));
+        assert_eq!(FULL.unsupported_option(&temperature), None);
+        assert_eq!(
+            NONE.unsupported_option(&temperature),
+            Some("temperature".into())
+        );
+        let top_p = config(json!({"top_p": 0.5}));
+        assert_eq!(NONE.unsupported_option(&top_p), Some("top_p".into()));
+    }
+
+    #[test]
+    fn json_mode_requires_support() {
+        let json_mode = config(json!({"response_format": {"type": "json_object"}}));
+        assert_eq!(FULL.unsupported_option(&json_mode), None);
+        assert_eq!(
+            NONE.unsupported_option(&json_mode),
+            Some("response_format".into())
+        );
+        let text = config(json!({"response_format": {"type": "text"}}));
+        assert_eq!(NONE.unsupported_option(&text), None);
+    }
+
+    #[test]
+    fn tools_require_support() {
+        let tools = config(json!({"tools": []}));
+        assert_eq!(FULL.unsupported_option(&tools), None);
+        assert_eq!(NONE.unsupported_option(&tools), Some("tools".into()));
+    }
+
+    #[test]
+    fn logprobs_require_support() {
+        let enabled = config(json!({"logprobs": true}));
+        assert_eq!(FULL.unsupported_option(&enabled), None);
+        assert_eq!(NONE.unsupported_option(&enabled), Some("logprobs".into()));
+        let disabled = config(json!({"logprobs": false}));
+        assert_eq!(NONE.unsupported_option(&disabled), None);
+    }
+
+    #[test]
+    fn max_tokens_must_fit_max_output() {
+        let within = config(json!({"max_tokens": 100}));
+        assert_eq!(NONE.unsupported_option(&within), None);
+        let above = config(json!({"max_completion_tokens": 101}));
+        assert_eq!(
+            NONE.unsupported_option(&above),
+            Some("max_completion_tokens of 101 (the maximum output is 100)".into())
+        );
+    }
+
+    #[test]
+    fn unknown_options_are_left_to_the_provider() {
+        let unknown = config(json!({"seed": 1, "user": "someone"}));
+        assert_eq!(NONE.unsupported_option(&unknown), None);
+    }
+
+    #[test]
+    fn images_require_vision() {
+        use crate::clients::{ContentPart, ImageSource};
+        let messages = vec![
+            PromptMessage::HumanMessage("plain text is always fine".into()),
+            PromptMessage::MultiModalHumanMessage(vec![ContentPart::Text("only text".into())]),
+        ];
+        assert_eq!(NONE.unsupported_content(&messages), None);
+        let image = PromptMessage::MultiModalHumanMessage(vec![ContentPart::Image(
+            ImageSource::Url("https://example.com/a.png".into()),
+        )]);
+        assert_eq!(FULL.unsupported_content(std::slice::from_ref(&image)), None);
+        assert_eq!(
+            NONE.unsupported_content(&[image]),
+            Some("image content".into())
+        );
+    }
+
+    #[cfg(feature = "openai-chat")]
+    #[test]
+    fn reasoning_models_reject_sampling_options() {
+        for model in [OpenAIModel::O1, OpenAIModel::O1Mini, OpenAIModel::O3Mini] {
+            assert!(!model.capabilities().supports_temperature);
+            assert!(!model.capabilities().supports_logprobs);
+        }
+        assert!(OpenAIModel::Gpt4o.capabilities().supports_temperature);
+        assert!(!OpenAIModel::Gpt4.capabilities().supports_json_mode);
+    }
+
+    #[cfg(feature = "openai-chat")]
+    #[test]
+    fn custom_models_leave_options_to_the_provider() {
+        let capabilities = OpenAIModel::Custom("ft:gpt-4o-mini:org::id".into()).capabilities();
+        assert_eq!(capabilities, ModelCapabilities::UNKNOWN);
+        let options = config(json!({"temperature": 0.5, "max_tokens": 1_000_000}));
+        assert_eq!(capabilities.unsupported_option(&options), None);
+    }
+
+    #[cfg(feature = "anthropic")]
+    #[test]
+    fn anthropic_context_window_comes_from_capabilities() {
+        let model = AnthropicModel::Claude3Haiku;
+        assert_eq!(model.context_window(), model.capabilities().max_context);
+        assert_eq!(model.capabilities().max_output, 4_096);
+    }
+}
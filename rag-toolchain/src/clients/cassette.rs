@@ -0,0 +1,301 @@
+use reqwest::{Client, RequestBuilder, Response};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+/// Setting this environment variable to "1" switches every cassette into record mode.
+/// This should only be done by a maintainer with real API keys set, as requests will be
+/// sent to the live provider APIs and the fixtures on disk overwritten with the responses.
+const RECORD_ENV_VAR: &str = "RAG_TOOLCHAIN_RECORD_CASSETTES";
+
+/// # [`RecordingMode`]
+///
+/// * [`RecordingMode::Replay`] - serve responses from the fixture file, never touching the network.
+/// * [`RecordingMode::Record`] - send requests to the live API and save the responses to the fixture file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum RecordingMode {
+    Replay,
+    Record,
+}
+
+/// # [`Cassette`]
+///
+/// The on disk fixture format. A cassette is an ordered list of request/response pairs.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Cassette {
+    pub interactions: Vec<Interaction>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Interaction {
+    pub request: RecordedRequest,
+    pub response: RecordedResponse,
+}
+
+/// # [`RecordedRequest`]
+///
+/// We match requests on the method, path and a hash of the body. The body is
+/// hashed after being re-serialized with sorted keys so that field ordering
+/// in our request structs does not invalidate the fixtures.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RecordedRequest {
+    pub method: String,
+    pub path: String,
+    pub body_sha256: String,
+}
+
+/// # [`RecordedResponse`]
+///
+/// JSON bodies are stored as JSON so the fixtures stay readable, anything
+/// else (e.g. SSE streams) is stored as a plain string.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RecordedResponse {
+    pub status: u16,
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+    pub body: Value,
+}
+
+/// # [`RecordingHttpClient`]
+///
+/// Test only layer which sits between the provider HTTP cores and reqwest. In replay mode
+/// requests are answered deterministically from a cassette. In record mode requests are sent
+/// to the live API and the responses are captured. Interactions with the same key are served
+/// in the order they were recorded, this allows for flows such as a 429 followed by a success.
+#[derive(Debug)]
+pub struct RecordingHttpClient {
+    path: PathBuf,
+    mode: RecordingMode,
+    state: Mutex<CassetteState>,
+}
+
+#[derive(Debug, Default)]
+struct CassetteState {
+    cassette: Cassette,
+    used: Vec<bool>,
+}
+
+impl RecordingHttpClient {
+    /// # [`RecordingHttpClient::load`]
+    ///
+    /// Loads a cassette from the `tests/cassettes` directory. The mode is decided
+    /// by the [`RECORD_ENV_VAR`] environment variable and defaults to replay.
+    ///
+    /// # Arguments
+    /// * `name`: &[`str`] - the file name of the cassette without the extension.
+    ///
+    /// # Panics
+    /// In replay mode if the cassette does not exist or cannot be parsed.
+    pub fn load(name: &str) -> Self {
+        let path: PathBuf = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("tests")
+            .join("cassettes")
+            .join(format!("{}.json", name));
+        let mode = match std::env::var(RECORD_ENV_VAR).as_deref() {
+            Ok("1") => RecordingMode::Record,
+            _ => RecordingMode::Replay,
+        };
+        let cassette: Cassette = match mode {
+            RecordingMode::Record => Cassette::default(),
+            RecordingMode::Replay => {
+                let raw = std::fs::read_to_string(&path)
+                    .unwrap_or_else(|e| panic!("failed to read cassette {:?}: {}", path, e));
+                serde_json::from_str(&raw)
+                    .unwrap_or_else(|e| panic!("failed to parse cassette {:?}: {}", path, e))
+            }
+        };
+        Self::new(path, mode, cassette)
+    }
+
+    pub fn new(path: PathBuf, mode: RecordingMode, cassette: Cassette) -> Self {
+        let used = vec![false; cassette.interactions.len()];
+        RecordingHttpClient {
+            path,
+            mode,
+            state: Mutex::new(CassetteState { cassette, used }),
+        }
+    }
+
+    /// # [`RecordingHttpClient::execute`]
+    ///
+    /// Either replays or records the request depending on the mode.
+    ///
+    /// # Errors
+    /// * [`String`] - if no matching interaction is left in the cassette or
+    ///   the live request failed. The provider cores map this into their send error.
+    pub async fn execute(
+        &self,
+        client: &Client,
+        request: RequestBuilder,
+    ) -> Result<Response, String> {
+        let request = request.build().map_err(|e| e.to_string())?;
+        let body: &[u8] = request
+            .body()
+            .and_then(|body| body.as_bytes())
+            .unwrap_or_default();
+        let key = RecordedRequest {
+            method: request.method().to_string(),
+            path: request.url().path().to_string(),
+            body_sha256: hash_body(body),
+        };
+
+        match self.mode {
+            RecordingMode::Replay => {
+                let recorded = self.next_match(&key)?;
+                Ok(to_response(recorded))
+            }
+            RecordingMode::Record => {
+                let response = client.execute(request).await.map_err(|e| e.to_string())?;
+                let recorded = from_response(response).await?;
+                self.record(key, recorded.clone())?;
+                Ok(to_response(recorded))
+            }
+        }
+    }
+
+    /// # [`RecordingHttpClient::unused_interactions`]
+    ///
+    /// The number of interactions that have not been replayed, useful to assert that
+    /// a flow made every request it was expected to.
+    pub fn unused_interactions(&self) -> usize {
+        let state = self.state.lock().unwrap();
+        state.used.iter().filter(|used| !**used).count()
+    }
+
+    fn next_match(&self, key: &RecordedRequest) -> Result<RecordedResponse, String> {
+        let mut state = self.state.lock().map_err(|e| e.to_string())?;
+        let CassetteState { cassette, used } = &mut *state;
+        let position = cassette
+            .interactions
+            .iter()
+            .enumerate()
+            .position(|(index, interaction)| !used[index] && interaction.request == *key)
+            .ok_or_else(|| format!("no recorded interaction left matching {:?}", key))?;
+        used[position] = true;
+        Ok(cassette.interactions[position].response.clone())
+    }
+
+    fn record(&self, request: RecordedRequest, response: RecordedResponse) -> Result<(), String> {
+        let mut state = self.state.lock().map_err(|e| e.to_string())?;
+        state
+            .cassette
+            .interactions
+            .push(Interaction { request, response });
+        state.used.push(true);
+        let serialized =
+            serde_json::to_string_pretty(&state.cassette).map_err(|e| e.to_string())?;
+        if let Some(parent) = self.path.parent() {
+            std::fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+        }
+        std::fs::write(&self.path, serialized).map_err(|e| e.to_string())
+    }
+}
+
+/// # [`hash_body`]
+///
+/// Hashes the request body. JSON bodies are canonicalized first (serde_json sorts object keys).
+pub fn hash_body(body: &[u8]) -> String {
+    let canonical: Vec<u8> = match serde_json::from_slice::<Value>(body) {
+        Ok(value) => value.to_string().into_bytes(),
+        Err(_) => body.to_vec(),
+    };
+    format!("{:x}", Sha256::digest(&canonical))
+}
+
+fn to_response(recorded: RecordedResponse) -> Response {
+    let body: String = match recorded.body {
+        Value::String(text) => text,
+        other => other.to_string(),
+    };
+    let mut builder = http::Response::builder().status(recorded.status);
+    for (name, value) in recorded.headers.iter() {
+        builder = builder.header(name, value);
+    }
+    // The recorded status and headers were valid when captured so this cannot fail
+    Response::from(builder.body(body).unwrap())
+}
+
+async fn from_response(response: Response) -> Result<RecordedResponse, String> {
+    let status = response.status().as_u16();
+    let headers: BTreeMap<String, String> = response
+        .headers()
+        .iter()
+        .filter(|(name, _)| {
+            matches!(
+                name.as_str(),
+                "content-type" | "retry-after" | "x-request-id"
+            )
+        })
+        .filter_map(|(name, value)| Some((name.to_string(), value.to_str().ok()?.to_string())))
+        .collect();
+    let text = response.text().await.map_err(|e| e.to_string())?;
+    let body: Value = serde_json::from_str(&text).unwrap_or(Value::String(text));
+    Ok(RecordedResponse {
+        status,
+        headers,
+        body,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn interaction(path: &str, body: &Value, status: u16, response: Value) -> Interaction {
+        Interaction {
+            request: RecordedRequest {
+                method: "POST".into(),
+                path: path.into(),
+                body_sha256: hash_body(body.to_string().as_bytes()),
+            },
+            response: RecordedResponse {
+                status,
+                headers: BTreeMap::new(),
+                body: response,
+            },
+        }
+    }
+
+    #[test]
+    fn hash_body_ignores_key_order() {
+        let a = hash_body(br#"{"model":"gpt-4","input":"hello"}"#);
+        let b = hash_body(br#"{"input":"hello","model":"gpt-4"}"#);
+        assert_eq!(a, b);
+        assert_ne!(a, hash_body(br#"{"input":"goodbye","model":"gpt-4"}"#));
+    }
+
+    #[tokio::test]
+    async fn replay_serves_interactions_in_order_and_matches_body() {
+        let body = json!({"input": "hello"});
+        let cassette = Cassette {
+            interactions: vec![
+                interaction("/v1/test", &body, 429, json!({"attempt": 1})),
+                interaction("/v1/test", &body, 200, json!({"attempt": 2})),
+            ],
+        };
+        let recorder = RecordingHttpClient::new(PathBuf::new(), RecordingMode::Replay, cassette);
+        let client = Client::new();
+
+        let request = || client.post("https://example.com/v1/test").json(&body);
+        let first = recorder.execute(&client, request()).await.unwrap();
+        assert_eq!(first.status().as_u16(), 429);
+        let second = recorder.execute(&client, request()).await.unwrap();
+        assert_eq!(second.status().as_u16(), 200);
+        assert_eq!(second.text().await.unwrap(), r#"{"attempt":2}"#);
+        assert_eq!(recorder.unused_interactions(), 0);
+
+        // Nothing left to serve
+        assert!(recorder.execute(&client, request()).await.is_err());
+        // Different body never matches
+        let other = client
+            .post("https://example.com/v1/test")
+            .json(&json!({"input": "other"}));
+        assert!(recorder.execute(&client, other).await.is_err());
+    }
+}
@@ -0,0 +1,342 @@
+use crate::clients::cohere::model::errors::{CohereError, CohereErrorBody};
+use crate::clients::secrets::{
+    CachedSecret, EnvSecretProvider, SecretProvider, SecretString, DEFAULT_SECRET_TTL,
+};
+use crate::clients::RequestContext;
+
+use dotenv::dotenv;
+use reqwest::header::{HeaderValue, AUTHORIZATION, CONTENT_TYPE};
+use reqwest::{Client, RequestBuilder, Response, StatusCode};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::env;
+use std::env::VarError;
+use std::sync::Arc;
+use std::time::Instant;
+
+/// The name of the secret holding the API key
+const API_KEY_SECRET: &str = "COHERE_API_KEY";
+
+#[derive(Debug)]
+pub struct CohereHttpClient {
+    client: Client,
+    api_key: CachedSecret,
+}
+
+impl CohereHttpClient {
+    /// # [`CohereHttpClient::try_new`]
+    /// Must have the COHERE_API_KEY environment variable set. The variable is read
+    /// again once the cached key expires so a rotated key is picked up.
+    ///
+    /// # Errors
+    /// * [`VarError`] - If the COHERE_API_KEY environment variable is not set
+    ///
+    /// # Returns
+    /// * [`CohereHttpClient`] - The newly created CohereHttpClient
+    pub fn try_new() -> Result<CohereHttpClient, VarError> {
+        dotenv().ok();
+        let api_key: String = env::var(API_KEY_SECRET)?;
+        let mut client = Self::new_with_secret_provider(Arc::new(EnvSecretProvider));
+        client.api_key = client
+            .api_key
+            .with_initial_value(SecretString::new(api_key));
+        Ok(client)
+    }
+
+    /// # [`CohereHttpClient::new_with_secret_provider`]
+    /// The API key is fetched from the provider when the first request is sent
+    /// and cached for [`DEFAULT_SECRET_TTL`].
+    ///
+    /// # Arguments
+    /// * `provider` - Where the COHERE_API_KEY secret is fetched from
+    ///
+    /// # Returns
+    /// * [`CohereHttpClient`] - The newly created CohereHttpClient
+    pub fn new_with_secret_provider(provider: Arc<dyn SecretProvider>) -> CohereHttpClient {
+        CohereHttpClient {
+            client: Client::new(),
+            api_key: CachedSecret::new(provider, API_KEY_SECRET, DEFAULT_SECRET_TTL),
+        }
+    }
+
+    /// # [`CohereHttpClient::send_request`]
+    /// Sends a request to the Cohere API and returns the deserialized response. If Cohere
+    /// rejects the API key with a 401 the key is fetched again and, if it has changed, the
+    /// request is retried once with the new key.
+    ///
+    /// # Arguments
+    /// * `body` - The body of the request
+    /// * `url` - The url to send the request to
+    ///
+    /// # Errors
+    /// * [`CohereError::ErrorFetchingApiKey`] - if the API key could not be fetched
+    /// * [`CohereError::Request`] - wraps any of the errors below with the [`RequestContext`]
+    /// * [`CohereError::ErrorSendingRequest`] - if request.send() errors
+    /// * [`CohereError::ErrorGettingResponseBody`] - if response.text() errors
+    /// * [`CohereError::ErrorDeserializingResponseBody`] - if serde_json::from_str() errors
+    /// * [`CohereError`] - if the response code is not 200 this can be any of the associated status
+    ///   code errors or [`CohereError::Undefined`]
+    ///
+    /// # Returns
+    /// [`U`] - The deserialized response from Cohere
+    pub async fn send_request<T, U>(&self, body: T, url: &str) -> Result<U, CohereError>
+    where
+        T: Serialize,
+        U: DeserializeOwned,
+    {
+        let api_key: SecretString = self
+            .api_key
+            .get()
+            .await
+            .map_err(CohereError::ErrorFetchingApiKey)?;
+        let started: Instant = Instant::now();
+        let mut attempt: u32 = 1;
+        let result: Result<U, CohereError> = async {
+            match self.send(self.build_request(&body, url, &api_key)).await {
+                Err(CohereError::CODE401(error_body)) => {
+                    let refreshed: SecretString = self
+                        .api_key
+                        .refresh(&api_key)
+                        .await
+                        .map_err(CohereError::ErrorFetchingApiKey)?;
+                    if refreshed == api_key {
+                        return Err(CohereError::CODE401(error_body));
+                    }
+                    attempt += 1;
+                    self.send(self.build_request(&body, url, &refreshed)).await
+                }
+                result => result,
+            }
+        }
+        .await;
+        result.map_err(|error| {
+            error.with_context(RequestContext::new(url, &body, started.elapsed(), attempt))
+        })
+    }
+
+    /// # [`CohereHttpClient::send`]
+    ///
+    /// Sends the built request, mapping any error status codes and
+    /// deserializing the body of a successful response.
+    async fn send<U>(&self, request: RequestBuilder) -> Result<U, CohereError>
+    where
+        U: DeserializeOwned,
+    {
+        let response: Response = request
+            .send()
+            .await
+            .map_err(|error| CohereError::ErrorSendingRequest(error.to_string()))?;
+        let status_code: StatusCode = response.status();
+        if !status_code.is_success() {
+            return Err(Self::handle_error_response(response).await);
+        }
+        let response_body: String = response
+            .text()
+            .await
+            .map_err(|error| CohereError::ErrorGettingResponseBody(error.to_string()))?;
+        serde_json::from_str(&response_body).map_err(|error| {
+            CohereError::ErrorDeserializingResponseBody(status_code.as_u16(), error.to_string())
+        })
+    }
+
+    /// # [`CohereHttpClient::build_request`]
+    ///
+    /// Helper method to build a request with the correct headers and body
+    fn build_request<T>(
+        &self,
+        request_body: &T,
+        url: &str,
+        api_key: &SecretString,
+    ) -> RequestBuilder
+    where
+        T: Serialize,
+    {
+        let content_type = HeaderValue::from_static("application/json");
+        self.client
+            .post(url)
+            .header(AUTHORIZATION, format!("Bearer {}", api_key.expose_secret()))
+            .header(CONTENT_TYPE, content_type)
+            .json(&request_body)
+    }
+
+    /// # [`CohereHttpClient::handle_error_response`]
+    ///
+    /// Explicit error mapping between response codes and error types
+    ///
+    /// # Arguments
+    /// `response` - The reqwest response from Cohere
+    ///
+    /// # Returns
+    /// [`CohereError`] - The error type that maps to the response code
+    async fn handle_error_response(response: Response) -> CohereError {
+        let status_code = response.status().as_u16();
+        let body_text = match response.text().await {
+            Ok(text) => text,
+            Err(e) => return CohereError::Undefined(status_code, e.to_string()),
+        };
+
+        let error_body: CohereErrorBody = match serde_json::from_str(&body_text) {
+            Ok(error_body) => error_body,
+            Err(e) => {
+                return CohereError::ErrorDeserializingResponseBody(status_code, e.to_string())
+            }
+        };
+        match status_code {
+            400 => CohereError::CODE400(error_body),
+            401 => CohereError::CODE401(error_body),
+            402 => CohereError::CODE402(error_body),
+            404 => CohereError::CODE404(error_body),
+            422 => CohereError::CODE422(error_body),
+            429 => CohereError::CODE429(error_body),
+            500 => CohereError::CODE500(error_body),
+            503 => CohereError::CODE503(error_body),
+            undefined => CohereError::Undefined(undefined, body_text),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clients::secrets::tests::{MissingProvider, ScriptedProvider};
+    use crate::clients::secrets::SecretError;
+    use mockito::{Mock, Server, ServerGuard};
+    use serde::{Deserialize, Serialize};
+    use std::sync::atomic::Ordering;
+
+    const ERROR_RESPONSE: &str = r#"{"id": "5e2d1f0c", "message": "model 'embed-v9' not found"}"#;
+
+    #[tokio::test]
+    async fn missing_api_key_returns_error() {
+        let client = CohereHttpClient::new_with_secret_provider(Arc::new(MissingProvider));
+        let error = client
+            .send_request::<RequestBody, RequestBody>(request_body(), "http://localhost")
+            .await
+            .unwrap_err();
+        assert_eq!(
+            error,
+            CohereError::ErrorFetchingApiKey(SecretError::NotFound(API_KEY_SECRET.into()))
+        );
+    }
+
+    #[tokio::test]
+    async fn status_codes_map_correctly() {
+        let error_body: CohereErrorBody = serde_json::from_str(ERROR_RESPONSE).unwrap();
+        let expected: Vec<(usize, CohereError)> = vec![
+            (400, CohereError::CODE400(error_body.clone())),
+            (402, CohereError::CODE402(error_body.clone())),
+            (404, CohereError::CODE404(error_body.clone())),
+            (422, CohereError::CODE422(error_body.clone())),
+            (429, CohereError::CODE429(error_body.clone())),
+            (500, CohereError::CODE500(error_body.clone())),
+            (503, CohereError::CODE503(error_body)),
+            (469, CohereError::Undefined(469, ERROR_RESPONSE.into())),
+        ];
+        for (status_code, expected_error) in expected {
+            assert_status_mapping(status_code, expected_error).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn unchanged_key_rejected_maps_to_401() {
+        let error_body: CohereErrorBody = serde_json::from_str(ERROR_RESPONSE).unwrap();
+        assert_status_mapping(401, CohereError::CODE401(error_body)).await;
+    }
+
+    #[tokio::test]
+    async fn error_deserializing_response_body_maps_correctly() {
+        let status_code: u16 = 200;
+        let (client, mut server) = with_mocked_client().await;
+        let mock = with_mocked_request(&mut server, status_code.into(), "some invalid response");
+        let error = client
+            .send_request::<RequestBody, RequestBody>(request_body(), &server.url())
+            .await
+            .unwrap_err();
+        // The error here is the message from serde
+        let expected_error = CohereError::ErrorDeserializingResponseBody(
+            status_code,
+            "expected value at line 1 column 1".into(),
+        );
+        mock.assert();
+        assert_eq!(&expected_error, error.kind());
+    }
+
+    #[tokio::test]
+    async fn rejected_key_is_refreshed_and_retried_once() {
+        let mut server = Server::new_async().await;
+        let provider = ScriptedProvider::new(vec!["old key", "new key"]);
+        let client = CohereHttpClient::new_with_secret_provider(provider.clone());
+        let rejected = server
+            .mock("POST", "/")
+            .match_header("authorization", "Bearer old key")
+            .with_status(401)
+            .with_header("content-type", "application/json")
+            .with_body(ERROR_RESPONSE)
+            .create();
+        let accepted = server
+            .mock("POST", "/")
+            .match_header("authorization", "Bearer new key")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(r#"{"model": "embed-english-v3.0"}"#)
+            .create();
+        let response: RequestBody = client
+            .send_request(request_body(), &server.url())
+            .await
+            .unwrap();
+        assert_eq!(response.model, "embed-english-v3.0");
+        rejected.assert();
+        accepted.assert();
+        assert_eq!(provider.fetches.load(Ordering::SeqCst), 2);
+    }
+
+    // Helper method to assert all known status codes are mapped correctly
+    async fn assert_status_mapping(status_code: usize, expected_error: CohereError) {
+        let (client, mut server) = with_mocked_client().await;
+        let mock = with_mocked_request(&mut server, status_code, ERROR_RESPONSE);
+        let error: CohereError = client
+            .send_request::<RequestBody, RequestBody>(request_body(), &server.url())
+            .await
+            .unwrap_err();
+        mock.assert();
+        assert_eq!(&expected_error, error.kind());
+        assert_eq!(
+            error.context().unwrap().model.as_deref(),
+            Some("embed-english-v3.0")
+        );
+    }
+
+    fn with_mocked_request(
+        server: &mut ServerGuard,
+        status_code: usize,
+        response_body: &str,
+    ) -> Mock {
+        server
+            .mock("POST", "/")
+            .match_header("authorization", "Bearer fake key")
+            .with_status(status_code)
+            .with_header("content-type", "application/json")
+            .with_body(response_body)
+            .create()
+    }
+
+    // This methods returns a client which is pointing at the mocked url
+    // and the mock server which we can orchestrate the stubbings on.
+    async fn with_mocked_client() -> (CohereHttpClient, ServerGuard) {
+        let server = Server::new_async().await;
+        let client =
+            CohereHttpClient::new_with_secret_provider(ScriptedProvider::new(vec!["fake key"]));
+        (client, server)
+    }
+
+    fn request_body() -> RequestBody {
+        RequestBody {
+            model: "embed-english-v3.0".into(),
+        }
+    }
+
+    #[derive(Debug, Serialize, Deserialize)]
+    struct RequestBody {
+        model: String,
+    }
+}
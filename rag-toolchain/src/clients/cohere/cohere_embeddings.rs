@@ -0,0 +1,462 @@
+use crate::clients::cohere::cohere_core::CohereHttpClient;
+use crate::clients::cohere::model::embeddings::{CohereInputType, EmbedRequest, EmbedResponse};
+use crate::clients::cohere::model::errors::CohereError;
+use crate::clients::traits::AsyncEmbeddingClient;
+use crate::clients::{EmbeddingTaskType, SecretProvider};
+use crate::common::{
+    Chunk, Chunks, CohereEmbeddingModel, Embedding, EmbeddingModel, EmbeddingModelMetadata,
+};
+use std::env::VarError;
+use std::sync::Arc;
+
+const COHERE_EMBED_URL: &str = "https://api.cohere.com/v2/embed";
+/// The most texts Cohere accepts in one request
+const MAX_TEXTS_PER_REQUEST: usize = 96;
+
+/// # [`CohereEmbeddingClient`]
+/// Allows for interacting with the Cohere API to generate embeddings.
+///
+/// Cohere embeds text differently depending on its [`CohereInputType`]. The client embeds
+/// documents by default, a client for queries shares the connection and API key of the
+/// one it is cloned from so the store and the retriever can each use the right one.
+///
+/// # Examples
+/// ```
+/// use rag_toolchain::common::*;
+/// use rag_toolchain::clients::*;
+/// use rag_toolchain::retrievers::*;
+/// use rag_toolchain::stores::*;
+/// async fn store_and_retrieve(chunks: Chunks) {
+///     let model: CohereEmbeddingModel = CohereEmbeddingModel::EmbedEnglishV3;
+///     let documents: CohereEmbeddingClient = CohereEmbeddingClient::try_new(model).unwrap();
+///     let queries: CohereEmbeddingClient =
+///         documents.clone().with_input_type(CohereInputType::SearchQuery);
+///
+///     let store: PostgresVectorStore = PostgresVectorStore::try_new("embeddings", model)
+///         .await
+///         .unwrap();
+///     let embeddings: Vec<Embedding> = documents.generate_embeddings(chunks).await.unwrap();
+///     store.store_batch(embeddings).await.unwrap();
+///
+///     let retriever: PostgresVectorRetriever<CohereEmbeddingClient> =
+///         store.as_retriever(queries, DistanceFunction::Cosine);
+/// }
+/// ```
+/// # Required Environment Variables
+/// COHERE_API_KEY: The API key to use for the Cohere API
+#[derive(Debug, Clone)]
+pub struct CohereEmbeddingClient {
+    url: String,
+    client: Arc<CohereHttpClient>,
+    embedding_model: CohereEmbeddingModel,
+    input_type: CohereInputType,
+}
+
+impl CohereEmbeddingClient {
+    /// # [`CohereEmbeddingClient::try_new`]
+    /// Constructor to create a new CohereEmbeddingClient which embeds text as
+    /// [`CohereInputType::SearchDocument`].
+    /// This will fail if the COHERE_API_KEY environment variable is not set.
+    ///
+    /// # Arguments
+    /// * `embedding_model`: [`CohereEmbeddingModel`] - The model to use for the embeddings
+    ///
+    /// # Errors
+    /// * [`VarError`] - If the COHERE_API_KEY environment variable is not set.
+    ///
+    /// # Returns
+    /// * [`CohereEmbeddingClient`] - The newly created CohereEmbeddingClient
+    pub fn try_new(
+        embedding_model: CohereEmbeddingModel,
+    ) -> Result<CohereEmbeddingClient, VarError> {
+        let client: CohereHttpClient = CohereHttpClient::try_new()?;
+        Ok(CohereEmbeddingClient {
+            url: COHERE_EMBED_URL.into(),
+            client: Arc::new(client),
+            embedding_model,
+            input_type: CohereInputType::default(),
+        })
+    }
+
+    /// # [`CohereEmbeddingClient::new_with_secret_provider`]
+    /// Constructor to create a new CohereEmbeddingClient which fetches the COHERE_API_KEY
+    /// secret from the provider instead of the environment, see [`SecretProvider`].
+    ///
+    /// # Arguments
+    /// * `embedding_model`: [`CohereEmbeddingModel`] - The model to use for the embeddings
+    /// * `provider`: impl [`SecretProvider`] - Where the API key is fetched from
+    ///
+    /// # Returns
+    /// * [`CohereEmbeddingClient`] - The newly created CohereEmbeddingClient
+    pub fn new_with_secret_provider(
+        embedding_model: CohereEmbeddingModel,
+        provider: impl SecretProvider + 'static,
+    ) -> CohereEmbeddingClient {
+        CohereEmbeddingClient {
+            url: COHERE_EMBED_URL.into(),
+            client: Arc::new(CohereHttpClient::new_with_secret_provider(Arc::new(
+                provider,
+            ))),
+            embedding_model,
+            input_type: CohereInputType::default(),
+        }
+    }
+
+    /// # [`CohereEmbeddingClient::with_input_type`]
+    /// Sets what the embedded text will be used for, defaults to
+    /// [`CohereInputType::SearchDocument`].
+    ///
+    /// # Arguments
+    /// * `input_type`: [`CohereInputType`] - The input type sent with each request
+    ///
+    /// # Returns
+    /// * [`CohereEmbeddingClient`] - The client with the input type set
+    pub fn with_input_type(mut self, input_type: CohereInputType) -> Self {
+        self.input_type = input_type;
+        self
+    }
+
+    /// # [`CohereEmbeddingClient::input_type`]
+    ///
+    /// # Returns
+    /// * [`CohereInputType`] - The input type sent with each request
+    pub fn input_type(&self) -> CohereInputType {
+        self.input_type
+    }
+
+    /// # [`CohereEmbeddingClient::input_type_for`]
+    /// The search input type matching the task, unless the client was set to embed for
+    /// classification or clustering in which case that is kept.
+    fn input_type_for(&self, task: EmbeddingTaskType) -> CohereInputType {
+        match (self.input_type, task) {
+            (
+                CohereInputType::SearchDocument | CohereInputType::SearchQuery,
+                EmbeddingTaskType::Document,
+            ) => CohereInputType::SearchDocument,
+            (
+                CohereInputType::SearchDocument | CohereInputType::SearchQuery,
+                EmbeddingTaskType::Query,
+            ) => CohereInputType::SearchQuery,
+            (other, _) => other,
+        }
+    }
+
+    /// # [`CohereEmbeddingClient::generate_embedding_for_input`]
+    /// Embeds a single chunk with the input type.
+    ///
+    /// # Errors
+    /// * [`CohereError`] - If the request to Cohere fails.
+    async fn generate_embedding_for_input(
+        &self,
+        text: Chunk,
+        input_type: CohereInputType,
+    ) -> Result<Embedding, CohereError> {
+        self.embed_batch(vec![text], input_type)
+            .await?
+            .pop()
+            .ok_or_else(|| CohereError::Undefined(200, "Cohere returned no embeddings".into()))
+    }
+
+    /// # [`CohereEmbeddingClient::embed_all`]
+    /// Sends the chunks in batches of at most [`MAX_TEXTS_PER_REQUEST`], one after the other.
+    ///
+    /// # Errors
+    /// * [`CohereError`] - If any request to Cohere fails.
+    async fn embed_all(
+        &self,
+        text: Chunks,
+        input_type: CohereInputType,
+    ) -> Result<Vec<Embedding>, CohereError> {
+        let mut embeddings: Vec<Embedding> = Vec::with_capacity(text.len());
+        for batch in text.chunks(MAX_TEXTS_PER_REQUEST) {
+            embeddings.extend(self.embed_batch(batch.to_vec(), input_type).await?);
+        }
+        Ok(embeddings)
+    }
+
+    /// # [`CohereEmbeddingClient::embed_batch`]
+    /// Sends a single request for at most [`MAX_TEXTS_PER_REQUEST`] chunks.
+    ///
+    /// # Errors
+    /// * [`CohereError`] - If the request to Cohere fails.
+    async fn embed_batch(
+        &self,
+        chunks: Chunks,
+        input_type: CohereInputType,
+    ) -> Result<Vec<Embedding>, CohereError> {
+        let request_body = EmbedRequest {
+            model: self.embedding_model,
+            texts: chunks
+                .iter()
+                .map(|chunk| chunk.content().to_string())
+                .collect(),
+            input_type,
+            embedding_types: vec!["float".into()],
+        };
+        let response: EmbedResponse = self.client.send_request(request_body, &self.url).await?;
+        Ok(response
+            .embeddings
+            .float
+            .into_iter()
+            .zip(chunks)
+            .map(|(vector, chunk)| Embedding::new(chunk, vector))
+            .collect())
+    }
+}
+
+impl AsyncEmbeddingClient for CohereEmbeddingClient {
+    type ErrorType = CohereError;
+
+    /// # [`CohereEmbeddingClient::generate_embeddings`]
+    /// Function to generate embeddings for [`Chunks`]. Cohere accepts at most 96 texts
+    /// per request so larger inputs are sent as several requests one after the other.
+    ///
+    /// # Arguments
+    /// * `text`: [`Chunks`] - The text chunks/strings to generate an embeddings for.
+    ///
+    /// # Errors
+    /// * [`CohereError`] - If any request to Cohere fails.
+    ///
+    /// # Returns
+    /// * [`Vec<Embedding>`] - pairs of the original text and the embedding that was generated.
+    async fn generate_embeddings(&self, text: Chunks) -> Result<Vec<Embedding>, CohereError> {
+        self.embed_all(text, self.input_type).await
+    }
+
+    /// # [`CohereEmbeddingClient::generate_embedding`]
+    /// Function to generate an embedding for a [`Chunk`].
+    ///
+    /// # Arguments
+    /// * `text`: [`Chunk`] - The text chunk/string to generate an embedding for.
+    ///
+    /// # Errors
+    /// * [`CohereError`] - If the request to Cohere fails.
+    ///
+    /// # Returns
+    /// * [`Embedding`] - the generated embedding
+    async fn generate_embedding(&self, text: Chunk) -> Result<Embedding, CohereError> {
+        self.generate_embedding_for_input(text, self.input_type)
+            .await
+    }
+
+    /// # [`CohereEmbeddingClient::generate_embedding_for`]
+    /// Embeds the chunk as [`CohereInputType::SearchDocument`] or
+    /// [`CohereInputType::SearchQuery`] to match the task. A client set to embed for
+    /// classification or clustering keeps its input type.
+    ///
+    /// # Errors
+    /// * [`CohereError`] - If the request to Cohere fails.
+    async fn generate_embedding_for(
+        &self,
+        text: Chunk,
+        task: EmbeddingTaskType,
+    ) -> Result<Embedding, CohereError> {
+        self.generate_embedding_for_input(text, self.input_type_for(task))
+            .await
+    }
+
+    /// # [`CohereEmbeddingClient::generate_embeddings_for`]
+    /// Embeds the chunks with the input type matching the task, see
+    /// [`CohereEmbeddingClient::generate_embedding_for`].
+    ///
+    /// # Errors
+    /// * [`CohereError`] - If any request to Cohere fails.
+    async fn generate_embeddings_for(
+        &self,
+        text: Chunks,
+        task: EmbeddingTaskType,
+    ) -> Result<Vec<Embedding>, CohereError> {
+        self.embed_all(text, self.input_type_for(task)).await
+    }
+
+    /// # [`CohereEmbeddingClient::dimensions`]
+    ///
+    /// # Returns
+    /// * [`Option<usize>`] - the dimensions of the model the client uses.
+    fn dimensions(&self) -> Option<usize> {
+        Some(self.embedding_model.metadata().dimensions)
+    }
+}
+
+/// The metadata of the model the client uses, so a store can be created with `&client`.
+impl EmbeddingModel for CohereEmbeddingClient {
+    fn metadata(&self) -> EmbeddingModelMetadata {
+        self.embedding_model.metadata()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clients::secrets::tests::ScriptedProvider;
+    use mockito::{Matcher, Mock, Server, ServerGuard};
+    use serde_json::json;
+
+    const EMBED_RESPONSE: &str = r#"
+    {
+        "id": "da6e531f-54c6-4a73-bf92-f60566d8d753",
+        "embeddings": {
+            "float": [
+                [0.016296387, -0.008354187, -0.04699707],
+                [-0.0113220215, 0.0513916, 0.012664795]
+            ]
+        },
+        "texts": ["Test-0", "Test-1"],
+        "meta": {
+            "api_version": {"version": "2"},
+            "billed_units": {"input_tokens": 4}
+        }
+    }
+    "#;
+
+    #[tokio::test]
+    async fn generate_embeddings_sends_document_input_type_by_default() {
+        let (client, mut server) = with_mocked_client().await;
+        let expected_body = json!({
+            "model": "embed-english-v3.0",
+            "texts": ["Test-0", "Test-1"],
+            "input_type": "search_document",
+            "embedding_types": ["float"]
+        });
+        let mock = with_mocked_request(&mut server, 200, EMBED_RESPONSE)
+            .match_body(Matcher::Json(expected_body))
+            .create();
+        let chunks: Chunks = vec![Chunk::new("Test-0"), Chunk::new("Test-1")];
+        let embeddings = client.generate_embeddings(chunks).await.unwrap();
+        mock.assert();
+        assert_eq!(embeddings.len(), 2);
+        assert_eq!(*embeddings[0].chunk(), Chunk::new("Test-0"));
+        assert_eq!(
+            embeddings[1].vector(),
+            vec![-0.0113220215, 0.0513916, 0.012664795]
+        );
+    }
+
+    #[tokio::test]
+    async fn query_client_sends_query_input_type() {
+        let (documents, mut server) = with_mocked_client().await;
+        let queries = documents
+            .clone()
+            .with_input_type(CohereInputType::SearchQuery);
+        let response = r#"{"id": "1", "embeddings": {"float": [[0.1, 0.2, 0.3]]}}"#;
+        let mock = with_mocked_request(&mut server, 200, response)
+            .match_body(Matcher::PartialJson(json!({"input_type": "search_query"})))
+            .create();
+        let embedding = queries
+            .generate_embedding(Chunk::new("what is rust"))
+            .await
+            .unwrap();
+        mock.assert();
+        assert_eq!(embedding.vector(), vec![0.1, 0.2, 0.3]);
+        assert_eq!(documents.input_type(), CohereInputType::SearchDocument);
+    }
+
+    #[tokio::test]
+    async fn query_task_sends_query_input_type() {
+        let (documents, mut server) = with_mocked_client().await;
+        let response = r#"{"id": "1", "embeddings": {"float": [[0.1, 0.2, 0.3]]}}"#;
+        let mock = with_mocked_request(&mut server, 200, response)
+            .match_body(Matcher::PartialJson(json!({"input_type": "search_query"})))
+            .create();
+        documents
+            .generate_embedding_for(Chunk::new("what is rust"), EmbeddingTaskType::Query)
+            .await
+            .unwrap();
+        mock.assert();
+    }
+
+    #[tokio::test]
+    async fn tasks_keep_a_non_search_input_type() {
+        let (client, _server) = with_mocked_client().await;
+        let clustering = client.with_input_type(CohereInputType::Clustering);
+        assert_eq!(
+            clustering.input_type_for(EmbeddingTaskType::Query),
+            CohereInputType::Clustering
+        );
+        let queries = clustering.with_input_type(CohereInputType::SearchQuery);
+        assert_eq!(
+            queries.input_type_for(EmbeddingTaskType::Document),
+            CohereInputType::SearchDocument
+        );
+    }
+
+    #[tokio::test]
+    async fn large_inputs_are_split_into_requests_of_96() {
+        let (client, mut server) = with_mocked_client().await;
+        let vectors =
+            |count: usize| json!({"id": "1", "embeddings": {"float": vec![[0.5]; count]}});
+        let first = with_mocked_request(&mut server, 200, &vectors(96).to_string())
+            .match_body(Matcher::PartialJson(json!({"texts": vec!["text"; 96]})))
+            .create();
+        let second = with_mocked_request(&mut server, 200, &vectors(4).to_string())
+            .match_body(Matcher::PartialJson(json!({"texts": vec!["text"; 4]})))
+            .create();
+        let chunks: Chunks = vec![Chunk::new("text"); 100];
+        let embeddings = client.generate_embeddings(chunks).await.unwrap();
+        first.assert();
+        second.assert();
+        assert_eq!(embeddings.len(), 100);
+    }
+
+    #[tokio::test]
+    async fn empty_input_is_never_sent() {
+        let (client, mut server) = with_mocked_client().await;
+        let mock = with_mocked_request(&mut server, 200, "{}")
+            .expect(0)
+            .create();
+        let embeddings = client.generate_embeddings(Vec::new()).await.unwrap();
+        mock.assert();
+        assert!(embeddings.is_empty());
+    }
+
+    #[tokio::test]
+    async fn rate_limit_maps_to_429() {
+        let (client, mut server) = with_mocked_client().await;
+        let response = r#"{"id": "1", "message": "You are using a Trial key, which is limited"}"#;
+        let mock = with_mocked_request(&mut server, 429, response).create();
+        let error = client
+            .generate_embedding(Chunk::new("Test-0"))
+            .await
+            .unwrap_err();
+        mock.assert();
+        assert!(matches!(error.kind(), CohereError::CODE429(_)));
+    }
+
+    #[test]
+    fn metadata_matches_the_model() {
+        let client = CohereEmbeddingClient::new_with_secret_provider(
+            CohereEmbeddingModel::EmbedMultilingualV3,
+            crate::clients::EnvSecretProvider,
+        );
+        assert_eq!(client.metadata().dimensions, 1024);
+        assert_eq!(client.dimensions(), Some(1024));
+    }
+
+    fn with_mocked_request(
+        server: &mut ServerGuard,
+        status_code: usize,
+        response_body: &str,
+    ) -> Mock {
+        server
+            .mock("POST", "/v2/embed")
+            .match_header("authorization", "Bearer fake key")
+            .with_status(status_code)
+            .with_header("content-type", "application/json")
+            .with_body(response_body)
+    }
+
+    // This methods returns a client which is pointing at the mocked url
+    // and the mock server which we can orchestrate the stubbings on.
+    async fn with_mocked_client() -> (CohereEmbeddingClient, ServerGuard) {
+        let server = Server::new_async().await;
+        let client = CohereEmbeddingClient {
+            url: format!("{}/v2/embed", server.url()),
+            client: Arc::new(CohereHttpClient::new_with_secret_provider(
+                ScriptedProvider::new(vec!["fake key"]),
+            )),
+            embedding_model: CohereEmbeddingModel::EmbedEnglishV3,
+            input_type: CohereInputType::default(),
+        };
+        (client, server)
+    }
+}
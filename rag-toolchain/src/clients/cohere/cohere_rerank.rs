@@ -0,0 +1,285 @@
+use crate::clients::cohere::cohere_core::CohereHttpClient;
+use crate::clients::cohere::model::errors::CohereError;
+use crate::clients::cohere::model::rerank::{CohereRerankModel, RerankRequest, RerankResponse};
+use crate::clients::SecretProvider;
+use crate::common::{Chunk, ScoredChunk};
+use crate::retrievers::AsyncReranker;
+use std::env::VarError;
+use std::num::NonZeroU32;
+use std::sync::Arc;
+
+const COHERE_RERANK_URL: &str = "https://api.cohere.com/v2/rerank";
+
+/// # [`CohereReranker`]
+/// Reranks retrieved chunks with the Cohere Rerank API. Each chunk is scored by how
+/// relevant it is to the query from 0 to 1.
+///
+/// # Examples
+/// ```
+/// use rag_toolchain::clients::*;
+/// use rag_toolchain::retrievers::*;
+/// use std::num::NonZeroU32;
+///
+/// fn with_reranking<R: AsyncRetriever>(retriever: R) -> RerankingRetriever<R, CohereReranker> {
+///     let reranker: CohereReranker =
+///         CohereReranker::try_new(CohereRerankModel::RerankV3Point5).unwrap();
+///     RerankingRetriever::new(retriever, reranker, NonZeroU32::new(3).unwrap())
+/// }
+/// ```
+/// # Required Environment Variables
+/// COHERE_API_KEY: The API key to use for the Cohere API
+#[derive(Debug, Clone)]
+pub struct CohereReranker {
+    url: String,
+    client: Arc<CohereHttpClient>,
+    model: CohereRerankModel,
+}
+
+impl CohereReranker {
+    /// # [`CohereReranker::try_new`]
+    /// Constructor to create a new CohereReranker.
+    /// This will fail if the COHERE_API_KEY environment variable is not set.
+    ///
+    /// # Arguments
+    /// * `model`: [`CohereRerankModel`] - The model to rerank with
+    ///
+    /// # Errors
+    /// * [`VarError`] - If the COHERE_API_KEY environment variable is not set.
+    ///
+    /// # Returns
+    /// * [`CohereReranker`] - The newly created CohereReranker
+    pub fn try_new(model: CohereRerankModel) -> Result<CohereReranker, VarError> {
+        let client: CohereHttpClient = CohereHttpClient::try_new()?;
+        Ok(CohereReranker {
+            url: COHERE_RERANK_URL.into(),
+            client: Arc::new(client),
+            model,
+        })
+    }
+
+    /// # [`CohereReranker::new_with_secret_provider`]
+    /// Constructor to create a new CohereReranker which fetches the COHERE_API_KEY
+    /// secret from the provider instead of the environment, see [`SecretProvider`].
+    ///
+    /// # Arguments
+    /// * `model`: [`CohereRerankModel`] - The model to rerank with
+    /// * `provider`: impl [`SecretProvider`] - Where the API key is fetched from
+    ///
+    /// # Returns
+    /// * [`CohereReranker`] - The newly created CohereReranker
+    pub fn new_with_secret_provider(
+        model: CohereRerankModel,
+        provider: impl SecretProvider + 'static,
+    ) -> CohereReranker {
+        CohereReranker {
+            url: COHERE_RERANK_URL.into(),
+            client: Arc::new(CohereHttpClient::new_with_secret_provider(Arc::new(
+                provider,
+            ))),
+            model,
+        }
+    }
+}
+
+impl AsyncReranker for CohereReranker {
+    type ErrorType = CohereError;
+
+    /// # [`CohereReranker::rerank`]
+    /// Sends the content of the chunks to Cohere, the chunks are returned in the order
+    /// Cohere ranks them with their relevance scores. No request is sent for no chunks.
+    ///
+    /// # Arguments
+    /// * `query`: &[`str`] - the text the chunks were retrieved for.
+    /// * `chunks`: [`Vec<ScoredChunk>`] - the candidates, their scores are replaced.
+    /// * `top_n`: [`NonZeroU32`] - the number of chunks to keep.
+    ///
+    /// # Errors
+    /// * [`CohereError`] - If the request to Cohere fails.
+    /// * [`CohereError::Undefined`] - If Cohere returns a result for a document it was not sent.
+    ///
+    /// # Returns
+    /// * [`Vec<ScoredChunk>`] - at most `top_n` chunks, most relevant first.
+    async fn rerank(
+        &self,
+        query: &str,
+        chunks: Vec<ScoredChunk>,
+        top_n: NonZeroU32,
+    ) -> Result<Vec<ScoredChunk>, CohereError> {
+        if chunks.is_empty() {
+            return Ok(Vec::new());
+        }
+        let request_body = RerankRequest {
+            model: self.model,
+            query: query.to_string(),
+            documents: chunks
+                .iter()
+                .map(|scored| scored.chunk.content().to_string())
+                .collect(),
+            top_n: top_n.get().min(chunks.len() as u32),
+        };
+        let response: RerankResponse = self.client.send_request(request_body, &self.url).await?;
+        let mut chunks: Vec<Option<Chunk>> = chunks
+            .into_iter()
+            .map(|scored| Some(scored.chunk))
+            .collect();
+        response
+            .results
+            .into_iter()
+            .map(|result| {
+                chunks
+                    .get_mut(result.index)
+                    .and_then(Option::take)
+                    .map(|chunk| ScoredChunk::new(chunk, result.relevance_score))
+                    .ok_or_else(|| {
+                        CohereError::Undefined(
+                            200,
+                            format!("Cohere returned an unknown document {}", result.index),
+                        )
+                    })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clients::secrets::tests::ScriptedProvider;
+    use mockito::{Matcher, Mock, Server, ServerGuard};
+    use serde_json::json;
+
+    const RERANK_RESPONSE: &str = r#"
+    {
+        "id": "07734bd2-2473-4f07-94e1-0d9f0e6843cf",
+        "results": [
+            {"index": 2, "relevance_score": 0.9987},
+            {"index": 0, "relevance_score": 0.0412}
+        ],
+        "meta": {
+            "api_version": {"version": "2"},
+            "billed_units": {"search_units": 1}
+        }
+    }
+    "#;
+
+    fn candidates() -> Vec<ScoredChunk> {
+        vec![
+            ScoredChunk::new(Chunk::new("shipping takes 3 days"), 0.9),
+            ScoredChunk::new(Chunk::new("our office is in Leeds"), 0.8),
+            ScoredChunk::new(Chunk::new("refunds take 5 days"), 0.7),
+        ]
+    }
+
+    #[tokio::test]
+    async fn rerank_orders_chunks_by_relevance() {
+        let (reranker, mut server) = with_mocked_reranker().await;
+        let expected_body = json!({
+            "model": "rerank-v3.5",
+            "query": "how long do refunds take",
+            "documents": ["shipping takes 3 days", "our office is in Leeds", "refunds take 5 days"],
+            "top_n": 2
+        });
+        let mock = with_mocked_request(&mut server, 200, RERANK_RESPONSE)
+            .match_body(Matcher::Json(expected_body))
+            .create();
+        let reranked = reranker
+            .rerank(
+                "how long do refunds take",
+                candidates(),
+                NonZeroU32::new(2).unwrap(),
+            )
+            .await
+            .unwrap();
+        mock.assert();
+        assert_eq!(
+            reranked,
+            vec![
+                ScoredChunk::new(Chunk::new("refunds take 5 days"), 0.9987),
+                ScoredChunk::new(Chunk::new("shipping takes 3 days"), 0.0412),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn top_n_is_capped_at_the_number_of_chunks() {
+        let (reranker, mut server) = with_mocked_reranker().await;
+        let mock = with_mocked_request(&mut server, 200, RERANK_RESPONSE)
+            .match_body(Matcher::PartialJson(json!({"top_n": 3})))
+            .create();
+        reranker
+            .rerank("refunds", candidates(), NonZeroU32::new(10).unwrap())
+            .await
+            .unwrap();
+        mock.assert();
+    }
+
+    #[tokio::test]
+    async fn empty_input_is_never_sent() {
+        let (reranker, mut server) = with_mocked_reranker().await;
+        let mock = with_mocked_request(&mut server, 200, "{}")
+            .expect(0)
+            .create();
+        let reranked = reranker
+            .rerank("refunds", Vec::new(), NonZeroU32::new(2).unwrap())
+            .await
+            .unwrap();
+        mock.assert();
+        assert!(reranked.is_empty());
+    }
+
+    #[tokio::test]
+    async fn unknown_documents_return_an_error() {
+        let (reranker, mut server) = with_mocked_reranker().await;
+        let response = r#"{"id": "1", "results": [{"index": 3, "relevance_score": 0.5}]}"#;
+        let mock = with_mocked_request(&mut server, 200, response).create();
+        let error = reranker
+            .rerank("refunds", candidates(), NonZeroU32::new(1).unwrap())
+            .await
+            .unwrap_err();
+        mock.assert();
+        assert_eq!(
+            error,
+            CohereError::Undefined(200, "Cohere returned an unknown document 3".into())
+        );
+    }
+
+    #[tokio::test]
+    async fn rate_limit_maps_to_429() {
+        let (reranker, mut server) = with_mocked_reranker().await;
+        let response = r#"{"id": "1", "message": "You are using a Trial key, which is limited"}"#;
+        let mock = with_mocked_request(&mut server, 429, response).create();
+        let error = reranker
+            .rerank("refunds", candidates(), NonZeroU32::new(1).unwrap())
+            .await
+            .unwrap_err();
+        mock.assert();
+        assert!(matches!(error.kind(), CohereError::CODE429(_)));
+    }
+
+    fn with_mocked_request(
+        server: &mut ServerGuard,
+        status_code: usize,
+        response_body: &str,
+    ) -> Mock {
+        server
+            .mock("POST", "/v2/rerank")
+            .match_header("authorization", "Bearer fake key")
+            .with_status(status_code)
+            .with_header("content-type", "application/json")
+            .with_body(response_body)
+    }
+
+    // This methods returns a reranker which is pointing at the mocked url
+    // and the mock server which we can orchestrate the stubbings on.
+    async fn with_mocked_reranker() -> (CohereReranker, ServerGuard) {
+        let server = Server::new_async().await;
+        let reranker = CohereReranker {
+            url: format!("{}/v2/rerank", server.url()),
+            client: Arc::new(CohereHttpClient::new_with_secret_provider(
+                ScriptedProvider::new(vec!["fake key"]),
+            )),
+            model: CohereRerankModel::default(),
+        };
+        (reranker, server)
+    }
+}
@@ -0,0 +1,10 @@
+mod cohere_core;
+mod cohere_embeddings;
+mod cohere_rerank;
+mod model;
+
+pub use cohere_embeddings::CohereEmbeddingClient;
+pub use cohere_rerank::CohereReranker;
+pub use model::embeddings::CohereInputType;
+pub use model::errors::CohereError;
+pub use model::rerank::CohereRerankModel;
@@ -0,0 +1,42 @@
+use crate::common::CohereEmbeddingModel;
+use serde::{Deserialize, Serialize};
+
+/// # [`CohereInputType`]
+///
+/// Cohere's v3 embedding models embed text differently depending on what it will be
+/// used for. Documents being stored should be embedded with
+/// [`CohereInputType::SearchDocument`] and the queries searching them with
+/// [`CohereInputType::SearchQuery`], mixing these up measurably hurts retrieval.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone, Copy, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum CohereInputType {
+    /// Text which will be stored and searched
+    #[default]
+    SearchDocument,
+    /// Text which is searched for in the stored documents
+    SearchQuery,
+    /// Text which will be passed to a classifier
+    Classification,
+    /// Text which will be clustered
+    Clustering,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct EmbedRequest {
+    pub model: CohereEmbeddingModel,
+    pub texts: Vec<String>,
+    pub input_type: CohereInputType,
+    pub embedding_types: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct EmbedResponse {
+    pub id: String,
+    pub embeddings: EmbeddingsByType,
+}
+
+/// Cohere returns each of the requested embedding types, only floats are requested
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct EmbeddingsByType {
+    pub float: Vec<Vec<f32>>,
+}
@@ -0,0 +1,99 @@
+use serde::Deserialize;
+use thiserror::Error;
+
+use crate::clients::{RequestContext, SecretError};
+
+/// The body Cohere sends with any error, it only carries a message
+#[derive(Debug, Deserialize, PartialEq, Clone)]
+pub struct CohereErrorBody {
+    pub message: String,
+}
+
+/// # [`CohereError`]
+///
+/// This error type largely mirrors the status codes listed here
+/// <https://docs.cohere.com/reference/errors>.
+#[derive(Error, Debug, PartialEq, Clone)]
+pub enum CohereError {
+    /// # The request was malformed, for example a required field is missing.
+    #[error("Bad Request Error: {0:?}")]
+    CODE400(CohereErrorBody),
+    /// # The API key is missing or invalid.
+    #[error("Unauthorized Error: {0:?}")]
+    CODE401(CohereErrorBody),
+    /// # The account has run out of credit or a trial key has hit its limit.
+    #[error("Payment Required Error: {0:?}")]
+    CODE402(CohereErrorBody),
+    /// # The model or endpoint was not found.
+    #[error("Not Found Error: {0:?}")]
+    CODE404(CohereErrorBody),
+    /// # The request was well formed but could not be processed, for example too many texts.
+    #[error("Unprocessable Entity Error: {0:?}")]
+    CODE422(CohereErrorBody),
+    /// # Too many requests have been sent, slow down.
+    #[error("Rate Limit Error: {0:?}")]
+    CODE429(CohereErrorBody),
+    /// # An unexpected error has occurred internal to Cohere's systems.
+    #[error("Internal Server Error: {0:?}")]
+    CODE500(CohereErrorBody),
+    /// # Cohere's API is temporarily unavailable.
+    #[error("Service Unavailable Error: {0:?}")]
+    CODE503(CohereErrorBody),
+    /// # Missed cases for error codes, includes Status Code and Error Body as a string. These can also represent internal logic errors.
+    #[error("Undefined Error. This should not happen, if this is a missed error please report it: https://github.com/JackMatthewRimmer/rust-rag-toolchain: status code = {0}, error = {1}")]
+    Undefined(u16, String),
+    /// # Carries underlying error that may have occurred during sending the request
+    #[error("Error sending request: {0}")]
+    ErrorSendingRequest(String),
+    /// # Carries underlying error that may have occured when trying to get the response body
+    #[error("Error getting response body: {0}")]
+    ErrorGettingResponseBody(String),
+    // # Carries underlying error and the status code
+    #[error("Error deserializining response body: status code = {0}, error = {1}")]
+    ErrorDeserializingResponseBody(u16, String),
+    /// # The API key could not be fetched from the secret provider, the request was not sent.
+    #[error("Error fetching API key: {0}")]
+    ErrorFetchingApiKey(SecretError),
+    /// # An error from a request sent to Cohere, with the context of the request
+    /// Use [`CohereError::kind`] to match on the underlying error.
+    #[error("{source} ({context})")]
+    Request {
+        context: RequestContext,
+        source: Box<CohereError>,
+    },
+}
+
+impl CohereError {
+    /// # [`CohereError::kind`]
+    ///
+    /// # Returns
+    /// * &[`CohereError`] - the underlying error without any [`RequestContext`].
+    pub fn kind(&self) -> &CohereError {
+        match self {
+            CohereError::Request { source, .. } => source.kind(),
+            error => error,
+        }
+    }
+
+    /// # [`CohereError::context`]
+    ///
+    /// # Returns
+    /// * [`Option<&RequestContext>`] - the context of the request the error came from,
+    ///   [`None`] if the error happened before a request was sent.
+    pub fn context(&self) -> Option<&RequestContext> {
+        match self {
+            CohereError::Request { context, .. } => Some(context),
+            _ => None,
+        }
+    }
+
+    /// # [`CohereError::with_context`]
+    ///
+    /// Attaches the context of the request to the error.
+    pub(crate) fn with_context(self, context: RequestContext) -> Self {
+        CohereError::Request {
+            context,
+            source: Box::new(self),
+        }
+    }
+}
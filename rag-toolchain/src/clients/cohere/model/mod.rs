@@ -0,0 +1,3 @@
+pub mod embeddings;
+pub mod errors;
+pub mod rerank;
@@ -0,0 +1,34 @@
+use serde::{Deserialize, Serialize};
+
+/// # [`CohereRerankModel`]
+/// Top level enum to hold the Cohere rerank model variants.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone, Copy, Default)]
+pub enum CohereRerankModel {
+    #[default]
+    #[serde(rename = "rerank-v3.5")]
+    RerankV3Point5,
+    #[serde(rename = "rerank-english-v3.0")]
+    RerankEnglishV3,
+    #[serde(rename = "rerank-multilingual-v3.0")]
+    RerankMultilingualV3,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct RerankRequest {
+    pub model: CohereRerankModel,
+    pub query: String,
+    pub documents: Vec<String>,
+    pub top_n: u32,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct RerankResponse {
+    pub results: Vec<RerankResult>,
+}
+
+/// A document picked by the reranker, `index` is its position in the request
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct RerankResult {
+    pub index: usize,
+    pub relevance_score: f32,
+}
@@ -0,0 +1,82 @@
+use crate::clients::{AsyncChatClient, DetailedChatResponse, PromptMessage, TokenCounter};
+use crate::common::{DynError, DynFuture, InvocationContext};
+
+/// # [`DynAsyncChatClient`]
+///
+/// An object safe version of [`AsyncChatClient`] so the client can be chosen at runtime, such
+/// as picking the provider from config. Every [`AsyncChatClient`] implements it with its errors
+/// boxed into a [`DynError`], and `Box<dyn DynAsyncChatClient>` implements [`AsyncChatClient`]
+/// so it can be given to a chain, such as a [`crate::chains::BasicRAGChain`]. Its methods take
+/// a `dyn_` prefix to keep them apart from the [`AsyncChatClient`] methods.
+pub trait DynAsyncChatClient: Send + Sync {
+    /// # [`DynAsyncChatClient::dyn_invoke`]
+    ///
+    /// See [`AsyncChatClient::invoke`].
+    fn dyn_invoke(&self, prompt_messages: Vec<PromptMessage>) -> DynFuture<'_, PromptMessage>;
+
+    /// # [`DynAsyncChatClient::dyn_invoke_with_context`]
+    ///
+    /// See [`AsyncChatClient::invoke_with_context`].
+    fn dyn_invoke_with_context<'a>(
+        &'a self,
+        prompt_messages: Vec<PromptMessage>,
+        context: &'a InvocationContext,
+    ) -> DynFuture<'a, DetailedChatResponse>;
+
+    /// # [`DynAsyncChatClient::dyn_count_tokens`]
+    ///
+    /// See [`TokenCounter::count_tokens`].
+    fn dyn_count_tokens(&self, text: &str) -> usize;
+}
+
+impl<T> DynAsyncChatClient for T
+where
+    T: AsyncChatClient,
+    T::ErrorType: 'static,
+{
+    fn dyn_invoke(&self, prompt_messages: Vec<PromptMessage>) -> DynFuture<'_, PromptMessage> {
+        Box::pin(async move {
+            AsyncChatClient::invoke(self, prompt_messages)
+                .await
+                .map_err(DynError::new)
+        })
+    }
+
+    fn dyn_invoke_with_context<'a>(
+        &'a self,
+        prompt_messages: Vec<PromptMessage>,
+        context: &'a InvocationContext,
+    ) -> DynFuture<'a, DetailedChatResponse> {
+        Box::pin(async move {
+            AsyncChatClient::invoke_with_context(self, prompt_messages, context)
+                .await
+                .map_err(DynError::new)
+        })
+    }
+
+    fn dyn_count_tokens(&self, text: &str) -> usize {
+        TokenCounter::count_tokens(self, text)
+    }
+}
+
+impl AsyncChatClient for Box<dyn DynAsyncChatClient> {
+    type ErrorType = DynError;
+
+    async fn invoke(&self, prompt_messages: Vec<PromptMessage>) -> Result<PromptMessage, DynError> {
+        DynAsyncChatClient::dyn_invoke(self.as_ref(), prompt_messages).await
+    }
+
+    async fn invoke_with_context(
+        &self,
+        prompt_messages: Vec<PromptMessage>,
+        context: &InvocationContext,
+    ) -> Result<DetailedChatResponse, DynError> {
+        DynAsyncChatClient::dyn_invoke_with_context(self.as_ref(), prompt_messages, context).await
+    }
+}
+
+impl TokenCounter for Box<dyn DynAsyncChatClient> {
+    fn count_tokens(&self, text: &str) -> usize {
+        DynAsyncChatClient::dyn_count_tokens(self.as_ref(), text)
+    }
+}
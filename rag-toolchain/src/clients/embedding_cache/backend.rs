@@ -0,0 +1,114 @@
+use sha2::{Digest, Sha256};
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::future::Future;
+
+/// # [`CacheKey`]
+///
+/// Identifies a cached vector by the model that generated it and the SHA-256 of the text
+/// that was embedded. Only the content is hashed, so chunks with the same text but
+/// different metadata share an entry.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct CacheKey {
+    model: String,
+    content_hash: String,
+}
+
+impl CacheKey {
+    /// # [`CacheKey::new`]
+    ///
+    /// # Arguments
+    /// * `model`: &[`str`] - the name of the model the vector was generated with.
+    /// * `content`: &[`str`] - the text that was embedded.
+    ///
+    /// # Returns
+    /// * [`CacheKey`] - the key for the text embedded by the model.
+    pub fn new(model: &str, content: &str) -> Self {
+        CacheKey {
+            model: model.to_string(),
+            content_hash: format!("{:x}", Sha256::digest(content.as_bytes())),
+        }
+    }
+
+    /// # [`CacheKey::model`]
+    ///
+    /// # Returns
+    /// * &[`str`] - the name of the model the vector was generated with.
+    pub fn model(&self) -> &str {
+        &self.model
+    }
+
+    /// # [`CacheKey::content_hash`]
+    ///
+    /// # Returns
+    /// * &[`str`] - the hex encoded SHA-256 of the embedded text.
+    pub fn content_hash(&self) -> &str {
+        &self.content_hash
+    }
+}
+
+impl Display for CacheKey {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.model, self.content_hash)
+    }
+}
+
+/// # [`CacheBackend`]
+///
+/// Where an [`crate::clients::EmbeddingCache`] keeps its vectors. Implement this to cache
+/// embeddings somewhere other than [`crate::clients::InMemoryCacheBackend`] or
+/// [`crate::clients::FileCacheBackend`], for example a shared Redis instance.
+pub trait CacheBackend: Send + Sync {
+    type ErrorType: Error + Send + Sync;
+
+    /// # [`CacheBackend::get`]
+    ///
+    /// # Arguments
+    /// * `key`: &[`CacheKey`] - the key to look up.
+    ///
+    /// # Errors
+    /// * [`Self::ErrorType`] - if the cache could not be read.
+    ///
+    /// # Returns
+    /// * [`Option<Vec<f32>>`] - the cached vector, [`None`] if there is not one.
+    fn get(
+        &self,
+        key: &CacheKey,
+    ) -> impl Future<Output = Result<Option<Vec<f32>>, Self::ErrorType>> + Send;
+
+    /// # [`CacheBackend::put`]
+    ///
+    /// Stores the vector under the key, replacing any vector already there.
+    ///
+    /// # Arguments
+    /// * `key`: [`CacheKey`] - the key to store the vector under.
+    /// * `vector`: [`Vec<f32>`] - the generated vector.
+    ///
+    /// # Errors
+    /// * [`Self::ErrorType`] - if the cache could not be written.
+    fn put(
+        &self,
+        key: CacheKey,
+        vector: Vec<f32>,
+    ) -> impl Future<Output = Result<(), Self::ErrorType>> + Send;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn key_hashes_the_content_per_model() {
+        let key = CacheKey::new("text-embedding-3-small", "hello");
+        assert_eq!(
+            key.content_hash(),
+            "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824"
+        );
+        assert_eq!(key, CacheKey::new("text-embedding-3-small", "hello"));
+        assert_ne!(key, CacheKey::new("text-embedding-3-large", "hello"));
+        assert_eq!(
+            key.to_string(),
+            format!("text-embedding-3-small:{}", key.content_hash())
+        );
+    }
+}
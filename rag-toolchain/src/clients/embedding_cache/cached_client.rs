@@ -0,0 +1,481 @@
+use crate::clients::embedding_cache::backend::{CacheBackend, CacheKey};
+use crate::clients::{AsyncEmbeddingClient, EmbeddingTaskType};
+use crate::common::{Chunk, Chunks, Embedding, EmbeddingModel, EmbeddingModelMetadata};
+use std::error::Error;
+use std::sync::atomic::{AtomicU64, Ordering};
+use thiserror::Error;
+
+/// # [`EmbeddingCache`]
+///
+/// An [`AsyncEmbeddingClient`] which checks a [`CacheBackend`] before asking the client it
+/// wraps for an embedding, so re-running a pipeline over unchanged chunks does not pay to
+/// embed them again. Vectors are cached by the name and dimensions of the wrapped client's
+/// model, from its [`EmbeddingModel::metadata`], and the SHA-256 of the chunk's content, see
+/// [`CacheKey`]. Changing the model or the dimensions the client asks for therefore never
+/// returns vectors cached for the old ones. The metadata of the returned embeddings always
+/// comes from the chunks passed in, never from the chunk that was cached.
+///
+/// # Examples
+/// ```
+/// use rag_toolchain::clients::*;
+/// use rag_toolchain::common::*;
+/// use std::num::NonZeroUsize;
+///
+/// async fn cached_embeddings<T: AsyncEmbeddingClient + EmbeddingModel>(client: T, chunks: Chunks) {
+///     let backend = InMemoryCacheBackend::new(NonZeroUsize::new(10_000).unwrap());
+///     let cache = EmbeddingCache::new(client, backend);
+///     let embeddings: Vec<Embedding> = cache.generate_embeddings(chunks).await.unwrap();
+///     println!("{:?}", cache.stats());
+/// }
+/// ```
+#[derive(Debug)]
+pub struct EmbeddingCache<T, B>
+where
+    T: AsyncEmbeddingClient,
+    B: CacheBackend,
+{
+    client: T,
+    model: String,
+    backend: B,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+/// # [`CacheStats`]
+///
+/// How many chunks an [`EmbeddingCache`] has served from the cache and how many it has
+/// sent to the client, counted since it was created.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+}
+
+impl CacheStats {
+    /// # [`CacheStats::hit_rate`]
+    ///
+    /// # Returns
+    /// * [`f64`] - the fraction of chunks served from the cache, 0 if nothing has been embedded.
+    pub fn hit_rate(&self) -> f64 {
+        let total: u64 = self.hits + self.misses;
+        if total == 0 {
+            return 0.0;
+        }
+        self.hits as f64 / total as f64
+    }
+}
+
+impl<T, B> EmbeddingCache<T, B>
+where
+    T: AsyncEmbeddingClient,
+    B: CacheBackend,
+{
+    /// # [`EmbeddingCache::new`]
+    ///
+    /// # Arguments
+    /// * `client`: impl [`AsyncEmbeddingClient`] + [`EmbeddingModel`] - the client chunks missing
+    ///   from the cache are sent to, the name and dimensions of its model are part of each [`CacheKey`].
+    /// * `backend`: impl [`CacheBackend`] - where the vectors are cached.
+    ///
+    /// # Returns
+    /// * [`EmbeddingCache`] - the caching client.
+    pub fn new(client: T, backend: B) -> Self
+    where
+        T: EmbeddingModel,
+    {
+        let metadata: EmbeddingModelMetadata = client.metadata();
+        EmbeddingCache {
+            model: format!("{}-{}", metadata.name, metadata.dimensions),
+            client,
+            backend,
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    /// # [`EmbeddingCache::stats`]
+    ///
+    /// # Returns
+    /// * [`CacheStats`] - the hits and misses so far.
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+        }
+    }
+
+    /// # [`EmbeddingCache::backend`]
+    ///
+    /// # Returns
+    /// * &B - the backend the vectors are cached in.
+    pub fn backend(&self) -> &B {
+        &self.backend
+    }
+
+    /// # [`EmbeddingCache::key`]
+    ///
+    /// Vectors embedded for a task are keyed by the model followed by the task, so
+    /// vectors embedded without one keep the keys they were cached under.
+    fn key(&self, chunk: &Chunk, task: Option<EmbeddingTaskType>) -> CacheKey {
+        match task {
+            None => CacheKey::new(&self.model, chunk.content()),
+            Some(task) => CacheKey::new(
+                &format!("{}#{}", self.model, task.as_str()),
+                chunk.content(),
+            ),
+        }
+    }
+
+    /// # [`EmbeddingCache::cached`]
+    ///
+    /// Looks the chunk up in the cache, counting the hit or miss.
+    async fn cached(&self, key: &CacheKey) -> Result<Option<Vec<f32>>, B::ErrorType> {
+        let vector: Option<Vec<f32>> = self.backend.get(key).await?;
+        let counter: &AtomicU64 = match vector {
+            Some(_) => &self.hits,
+            None => &self.misses,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        Ok(vector)
+    }
+
+    /// # [`EmbeddingCache::embed_one`]
+    ///
+    /// Returns the cached vector for the chunk, or embeds it with the client for the task,
+    /// if there is one, and caches the result.
+    async fn embed_one(
+        &self,
+        text: Chunk,
+        task: Option<EmbeddingTaskType>,
+    ) -> Result<Embedding, EmbeddingCacheError<T::ErrorType, B::ErrorType>> {
+        let key: CacheKey = self.key(&text, task);
+        if let Some(vector) = self
+            .cached(&key)
+            .await
+            .map_err(EmbeddingCacheError::Backend)?
+        {
+            return Ok(Embedding::new(text, vector));
+        }
+        let embedding: Embedding = match task {
+            None => self.client.generate_embedding(text).await,
+            Some(task) => self.client.generate_embedding_for(text, task).await,
+        }
+        .map_err(EmbeddingCacheError::Client)?;
+        self.backend
+            .put(key, embedding.vector())
+            .await
+            .map_err(EmbeddingCacheError::Backend)?;
+        Ok(embedding)
+    }
+
+    /// # [`EmbeddingCache::embed_many`]
+    ///
+    /// Returns the cached vectors for the chunks, the chunks which are not cached are sent
+    /// to the client in a single call for the task, if there is one, and their vectors cached.
+    async fn embed_many(
+        &self,
+        text: Chunks,
+        task: Option<EmbeddingTaskType>,
+    ) -> Result<Vec<Embedding>, EmbeddingCacheError<T::ErrorType, B::ErrorType>> {
+        let mut embeddings: Vec<Option<Embedding>> = Vec::with_capacity(text.len());
+        let mut missing: Vec<(usize, CacheKey)> = Vec::new();
+        let mut missing_chunks: Chunks = Vec::new();
+        for (index, chunk) in text.into_iter().enumerate() {
+            let key: CacheKey = self.key(&chunk, task);
+            match self
+                .cached(&key)
+                .await
+                .map_err(EmbeddingCacheError::Backend)?
+            {
+                Some(vector) => embeddings.push(Some(Embedding::new(chunk, vector))),
+                None => {
+                    embeddings.push(None);
+                    missing.push((index, key));
+                    missing_chunks.push(chunk);
+                }
+            }
+        }
+
+        if !missing_chunks.is_empty() {
+            let generated: Vec<Embedding> = match task {
+                None => self.client.generate_embeddings(missing_chunks).await,
+                Some(task) => {
+                    self.client
+                        .generate_embeddings_for(missing_chunks, task)
+                        .await
+                }
+            }
+            .map_err(EmbeddingCacheError::Client)?;
+            // Zipping would silently drop chunks, so nothing is cached from a short response
+            if generated.len() != missing.len() {
+                return Err(EmbeddingCacheError::CountMismatch {
+                    expected: missing.len(),
+                    found: generated.len(),
+                });
+            }
+            for ((index, key), embedding) in missing.into_iter().zip(generated) {
+                self.backend
+                    .put(key, embedding.vector())
+                    .await
+                    .map_err(EmbeddingCacheError::Backend)?;
+                embeddings[index] = Some(embedding);
+            }
+        }
+        Ok(embeddings.into_iter().flatten().collect())
+    }
+}
+
+impl<T, B> AsyncEmbeddingClient for EmbeddingCache<T, B>
+where
+    T: AsyncEmbeddingClient,
+    B: CacheBackend,
+{
+    type ErrorType = EmbeddingCacheError<T::ErrorType, B::ErrorType>;
+
+    /// # [`EmbeddingCache::generate_embedding`]
+    ///
+    /// Returns the cached vector for the chunk, or embeds it with the client and caches the result.
+    ///
+    /// # Errors
+    /// * [`EmbeddingCacheError::Client`] - if the client failed to embed the chunk.
+    /// * [`EmbeddingCacheError::Backend`] - if the cache could not be read or written.
+    async fn generate_embedding(&self, text: Chunk) -> Result<Embedding, Self::ErrorType> {
+        self.embed_one(text, None).await
+    }
+
+    /// # [`EmbeddingCache::generate_embeddings`]
+    ///
+    /// Returns the cached vectors for the chunks, the chunks which are not cached are sent
+    /// to the client in a single call and their vectors cached. The embeddings are in the
+    /// order of the chunks, if every chunk is cached the client is not called.
+    ///
+    /// # Errors
+    /// * [`EmbeddingCacheError::Client`] - if the client failed to embed the chunks.
+    /// * [`EmbeddingCacheError::CountMismatch`] - if the client returned a different number of embeddings.
+    /// * [`EmbeddingCacheError::Backend`] - if the cache could not be read or written.
+    async fn generate_embeddings(&self, text: Chunks) -> Result<Vec<Embedding>, Self::ErrorType> {
+        self.embed_many(text, None).await
+    }
+
+    /// # [`EmbeddingCache::generate_embedding_for`]
+    ///
+    /// As [`EmbeddingCache::generate_embedding`] but the chunk is embedded for the task,
+    /// vectors are cached separately for each task as the client may embed them differently.
+    async fn generate_embedding_for(
+        &self,
+        text: Chunk,
+        task: EmbeddingTaskType,
+    ) -> Result<Embedding, Self::ErrorType> {
+        self.embed_one(text, Some(task)).await
+    }
+
+    /// # [`EmbeddingCache::generate_embeddings_for`]
+    ///
+    /// As [`EmbeddingCache::generate_embeddings`] but the chunks are embedded for the task,
+    /// vectors are cached separately for each task as the client may embed them differently.
+    async fn generate_embeddings_for(
+        &self,
+        text: Chunks,
+        task: EmbeddingTaskType,
+    ) -> Result<Vec<Embedding>, Self::ErrorType> {
+        self.embed_many(text, Some(task)).await
+    }
+
+    fn dimensions(&self) -> Option<usize> {
+        self.client.dimensions()
+    }
+}
+
+/// The metadata of the wrapped client, so a store can be created with `&cache`.
+impl<T, B> EmbeddingModel for EmbeddingCache<T, B>
+where
+    T: AsyncEmbeddingClient + EmbeddingModel,
+    B: CacheBackend,
+{
+    fn metadata(&self) -> EmbeddingModelMetadata {
+        self.client.metadata()
+    }
+}
+
+/// # [`EmbeddingCacheError`]
+/// The errors that can occur generating embeddings through an [`EmbeddingCache`].
+#[derive(Error, Debug, PartialEq)]
+pub enum EmbeddingCacheError<C, B>
+where
+    C: Error,
+    B: Error,
+{
+    /// Error from the wrapped client when embedding the chunks missing from the cache
+    #[error("Embedding Client Error: {0}")]
+    Client(C),
+    /// Error when the cache could not be read or written
+    #[error("Cache Backend Error: {0}")]
+    Backend(B),
+    /// Error when the wrapped client returns a different number of embeddings than chunks it was sent
+    #[error("Embedding Count Mismatch: sent {expected} chunks but received {found} embeddings")]
+    CountMismatch { expected: usize, found: usize },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clients::embedding_cache::InMemoryCacheBackend;
+    use crate::common::OpenAIEmbeddingModel;
+    use serde_json::json;
+    use std::convert::Infallible;
+    use std::num::NonZeroUsize;
+    use std::sync::Mutex;
+
+    /// Embeds text as its length and records every chunk it was asked to embed
+    #[derive(Default)]
+    struct LengthClient {
+        embedded: Mutex<Vec<String>>,
+    }
+
+    impl AsyncEmbeddingClient for LengthClient {
+        type ErrorType = Infallible;
+
+        async fn generate_embedding(&self, text: Chunk) -> Result<Embedding, Infallible> {
+            Ok(self.generate_embeddings(vec![text]).await?.remove(0))
+        }
+
+        async fn generate_embeddings(&self, text: Chunks) -> Result<Vec<Embedding>, Infallible> {
+            let mut embedded = self.embedded.lock().unwrap();
+            Ok(text
+                .into_iter()
+                .map(|chunk| {
+                    embedded.push(chunk.content().to_string());
+                    let length = chunk.content().len() as f32;
+                    Embedding::new(chunk, vec![length])
+                })
+                .collect())
+        }
+    }
+
+    impl EmbeddingModel for LengthClient {
+        fn metadata(&self) -> EmbeddingModelMetadata {
+            OpenAIEmbeddingModel::TextEmbedding3Small.metadata()
+        }
+    }
+
+    fn cache() -> EmbeddingCache<LengthClient, InMemoryCacheBackend> {
+        let backend = InMemoryCacheBackend::new(NonZeroUsize::new(10).unwrap());
+        EmbeddingCache::new(LengthClient::default(), backend)
+    }
+
+    #[tokio::test]
+    async fn only_missing_chunks_are_sent_and_order_is_kept() {
+        let cache = cache();
+        cache.generate_embedding(Chunk::new("bb")).await.unwrap();
+        let chunks: Chunks = vec![Chunk::new("a"), Chunk::new("bb"), Chunk::new("ccc")];
+        let embeddings = cache.generate_embeddings(chunks.clone()).await.unwrap();
+
+        let returned: Chunks = embeddings.iter().map(|e| e.chunk().clone()).collect();
+        assert_eq!(returned, chunks);
+        assert_eq!(embeddings[1].vector(), vec![2.0]);
+        assert_eq!(embeddings[2].vector(), vec![3.0]);
+        assert_eq!(
+            *cache.client.embedded.lock().unwrap(),
+            vec!["bb", "a", "ccc"]
+        );
+        assert_eq!(cache.stats(), CacheStats { hits: 1, misses: 3 });
+    }
+
+    #[tokio::test]
+    async fn fully_cached_batches_do_not_call_the_client() {
+        let cache = cache();
+        let chunks: Chunks = vec![Chunk::new("a"), Chunk::new("bb")];
+        cache.generate_embeddings(chunks.clone()).await.unwrap();
+        cache.generate_embeddings(chunks).await.unwrap();
+        assert_eq!(cache.client.embedded.lock().unwrap().len(), 2);
+        assert_eq!(cache.stats().hit_rate(), 0.5);
+    }
+
+    #[tokio::test]
+    async fn hits_keep_the_metadata_of_the_incoming_chunk() {
+        let cache = cache();
+        let stored = Chunk::new_with_metadata("text", json!({"source": "old.md"}));
+        cache.generate_embedding(stored).await.unwrap();
+
+        let incoming = Chunk::new_with_metadata("text", json!({"source": "new.md"}));
+        let embedding = cache.generate_embedding(incoming.clone()).await.unwrap();
+        assert_eq!(*embedding.chunk(), incoming);
+        assert_eq!(cache.stats().hits, 1);
+    }
+
+    #[tokio::test]
+    async fn vectors_are_cached_separately_for_each_task() {
+        let cache = cache();
+        cache.generate_embedding(Chunk::new("a")).await.unwrap();
+        cache
+            .generate_embedding_for(Chunk::new("a"), EmbeddingTaskType::Query)
+            .await
+            .unwrap();
+        cache
+            .generate_embeddings_for(vec![Chunk::new("a")], EmbeddingTaskType::Query)
+            .await
+            .unwrap();
+        cache
+            .generate_embeddings_for(vec![Chunk::new("a")], EmbeddingTaskType::Document)
+            .await
+            .unwrap();
+        assert_eq!(cache.client.embedded.lock().unwrap().len(), 3);
+        assert_eq!(cache.stats(), CacheStats { hits: 1, misses: 3 });
+    }
+
+    #[tokio::test]
+    async fn vectors_are_keyed_by_the_client_model_and_dimensions() {
+        let cache = cache();
+        cache.generate_embedding(Chunk::new("a")).await.unwrap();
+        let key = CacheKey::new("text-embedding-3-small-1536", "a");
+        assert!(cache.backend().get(&key).await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn a_short_response_from_the_client_is_an_error() {
+        // Drops the last chunk it is sent
+        struct ShortClient;
+
+        impl AsyncEmbeddingClient for ShortClient {
+            type ErrorType = Infallible;
+
+            async fn generate_embedding(&self, text: Chunk) -> Result<Embedding, Infallible> {
+                Ok(Embedding::new(text, vec![1.0]))
+            }
+
+            async fn generate_embeddings(
+                &self,
+                mut text: Chunks,
+            ) -> Result<Vec<Embedding>, Infallible> {
+                text.pop();
+                Ok(text
+                    .into_iter()
+                    .map(|chunk| Embedding::new(chunk, vec![1.0]))
+                    .collect())
+            }
+        }
+
+        impl EmbeddingModel for ShortClient {
+            fn metadata(&self) -> EmbeddingModelMetadata {
+                OpenAIEmbeddingModel::TextEmbedding3Small.metadata()
+            }
+        }
+
+        let backend = InMemoryCacheBackend::new(NonZeroUsize::new(10).unwrap());
+        let cache = EmbeddingCache::new(ShortClient, backend);
+        let error = cache
+            .generate_embeddings(vec![Chunk::new("a"), Chunk::new("bb")])
+            .await
+            .unwrap_err();
+        assert_eq!(
+            error,
+            EmbeddingCacheError::CountMismatch {
+                expected: 2,
+                found: 1
+            }
+        );
+        // Nothing from the short response is cached
+        let key = CacheKey::new("text-embedding-3-small-1536", "a");
+        assert!(cache.backend().get(&key).await.unwrap().is_none());
+    }
+}
@@ -0,0 +1,136 @@
+use crate::clients::embedding_cache::backend::{CacheBackend, CacheKey};
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+use uuid::Uuid;
+
+/// # [`FileCacheBackend`]
+///
+/// A [`CacheBackend`] which writes each vector to its own JSON file under a directory, so
+/// the cache survives between runs of an indexing pipeline. Files are laid out as
+/// `{directory}/{model}/{sha256 of content}.json` and are never evicted, delete the
+/// directory to clear the cache.
+///
+/// # Examples
+/// ```
+/// use rag_toolchain::clients::*;
+/// use rag_toolchain::common::*;
+///
+/// async fn cached_embeddings<T: AsyncEmbeddingClient + EmbeddingModel>(client: T, chunks: Chunks) {
+///     let backend: FileCacheBackend = FileCacheBackend::new(".embedding-cache");
+///     let cache = EmbeddingCache::new(client, backend);
+///     // Only the chunks which have not been embedded on an earlier run are sent
+///     let embeddings: Vec<Embedding> = cache.generate_embeddings(chunks).await.unwrap();
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct FileCacheBackend {
+    directory: PathBuf,
+}
+
+impl FileCacheBackend {
+    /// # [`FileCacheBackend::new`]
+    ///
+    /// The directory is created when the first vector is written.
+    ///
+    /// # Arguments
+    /// * `directory`: impl [`AsRef<Path>`] - the directory the vectors are written under.
+    ///
+    /// # Returns
+    /// * [`FileCacheBackend`] - the cache backed by the directory.
+    pub fn new(directory: impl AsRef<Path>) -> Self {
+        FileCacheBackend {
+            directory: directory.as_ref().to_path_buf(),
+        }
+    }
+
+    /// # [`FileCacheBackend::path`]
+    ///
+    /// The file the vector for the key is written to. Characters in the model name which
+    /// are not safe in a path are replaced with `_`.
+    fn path(&self, key: &CacheKey) -> PathBuf {
+        let model: String = key
+            .model()
+            .chars()
+            .map(|c| match c {
+                'a'..='z' | 'A'..='Z' | '0'..='9' | '-' | '_' | '.' => c,
+                _ => '_',
+            })
+            .collect();
+        self.directory
+            .join(model)
+            .join(format!("{}.json", key.content_hash()))
+    }
+}
+
+impl CacheBackend for FileCacheBackend {
+    type ErrorType = FileCacheError;
+
+    async fn get(&self, key: &CacheKey) -> Result<Option<Vec<f32>>, FileCacheError> {
+        match tokio::fs::read(self.path(key)).await {
+            Ok(contents) => Ok(Some(serde_json::from_slice(&contents)?)),
+            Err(error) if error.kind() == ErrorKind::NotFound => Ok(None),
+            Err(error) => Err(error.into()),
+        }
+    }
+
+    /// Writes to a temporary file which is then renamed, so a reader never sees a partial vector
+    async fn put(&self, key: CacheKey, vector: Vec<f32>) -> Result<(), FileCacheError> {
+        let path: PathBuf = self.path(&key);
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        let temporary: PathBuf = path.with_extension(format!("{}.tmp", Uuid::new_v4()));
+        tokio::fs::write(&temporary, serde_json::to_vec(&vector)?).await?;
+        tokio::fs::rename(&temporary, &path).await?;
+        Ok(())
+    }
+}
+
+/// # [`FileCacheError`]
+/// The errors that can occur reading or writing a [`FileCacheBackend`].
+#[derive(Error, Debug)]
+pub enum FileCacheError {
+    /// Error when a cache file could not be read or written
+    #[error("IO Error: {0}")]
+    IoError(#[from] std::io::Error),
+    /// Error when a cache file does not hold a vector
+    #[error("Serialization Error: {0}")]
+    SerializationError(#[from] serde_json::Error),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env::temp_dir;
+
+    #[tokio::test]
+    async fn vectors_are_read_back_from_the_directory() {
+        let directory = temp_dir().join(format!("embedding_cache_{}", Uuid::new_v4()));
+        let cache = FileCacheBackend::new(&directory);
+        let key = CacheKey::new("nomic/embed:latest", "hello");
+        assert!(cache.get(&key).await.unwrap().is_none());
+
+        cache.put(key.clone(), vec![0.5, -1.0]).await.unwrap();
+        let reopened = FileCacheBackend::new(&directory);
+        assert_eq!(reopened.get(&key).await.unwrap(), Some(vec![0.5, -1.0]));
+        assert!(directory
+            .join("nomic_embed_latest")
+            .join(format!("{}.json", key.content_hash()))
+            .exists());
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[tokio::test]
+    async fn corrupt_files_are_an_error() {
+        let directory = temp_dir().join(format!("embedding_cache_{}", Uuid::new_v4()));
+        let cache = FileCacheBackend::new(&directory);
+        let key = CacheKey::new("model", "hello");
+        let path = cache.path(&key);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, "[0.5,").unwrap();
+        let error = cache.get(&key).await.unwrap_err();
+        assert!(matches!(error, FileCacheError::SerializationError(_)));
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+}
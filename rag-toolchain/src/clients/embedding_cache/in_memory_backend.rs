@@ -0,0 +1,146 @@
+use crate::clients::embedding_cache::backend::{CacheBackend, CacheKey};
+use std::collections::{BTreeMap, HashMap};
+use std::convert::Infallible;
+use std::num::NonZeroUsize;
+use std::sync::{Arc, Mutex, PoisonError};
+
+/// # [`InMemoryCacheBackend`]
+///
+/// A [`CacheBackend`] which keeps up to `capacity` vectors in memory, evicting the least
+/// recently used vector once it is full. Clones share the same entries.
+#[derive(Debug, Clone)]
+pub struct InMemoryCacheBackend {
+    capacity: NonZeroUsize,
+    entries: Arc<Mutex<LruEntries>>,
+}
+
+#[derive(Debug, Default)]
+struct LruEntries {
+    /// The vector of each key and when it was last used
+    vectors: HashMap<CacheKey, (Vec<f32>, u64)>,
+    /// The keys ordered from least to most recently used
+    recency: BTreeMap<u64, CacheKey>,
+    clock: u64,
+}
+
+impl LruEntries {
+    /// Marks the key as the most recently used and returns its vector
+    fn touch(&mut self, key: &CacheKey) -> Option<Vec<f32>> {
+        self.clock += 1;
+        let (vector, last_used) = self.vectors.get_mut(key)?;
+        self.recency.remove(last_used);
+        *last_used = self.clock;
+        self.recency.insert(self.clock, key.clone());
+        Some(vector.clone())
+    }
+
+    fn insert(&mut self, key: CacheKey, vector: Vec<f32>, capacity: usize) {
+        self.clock += 1;
+        if let Some((_, last_used)) = self.vectors.remove(&key) {
+            self.recency.remove(&last_used);
+        }
+        while self.vectors.len() >= capacity {
+            let Some((_, oldest)) = self.recency.pop_first() else {
+                break;
+            };
+            self.vectors.remove(&oldest);
+        }
+        self.recency.insert(self.clock, key.clone());
+        self.vectors.insert(key, (vector, self.clock));
+    }
+}
+
+impl InMemoryCacheBackend {
+    /// # [`InMemoryCacheBackend::new`]
+    ///
+    /// # Arguments
+    /// * `capacity`: [`NonZeroUsize`] - the most vectors held at once.
+    ///
+    /// # Returns
+    /// * [`InMemoryCacheBackend`] - the empty cache.
+    pub fn new(capacity: NonZeroUsize) -> Self {
+        InMemoryCacheBackend {
+            capacity,
+            entries: Arc::default(),
+        }
+    }
+
+    /// # [`InMemoryCacheBackend::len`]
+    ///
+    /// # Returns
+    /// * [`usize`] - the number of cached vectors.
+    pub fn len(&self) -> usize {
+        self.entries
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .vectors
+            .len()
+    }
+
+    /// # [`InMemoryCacheBackend::is_empty`]
+    ///
+    /// # Returns
+    /// * [`bool`] - true if nothing has been cached.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl CacheBackend for InMemoryCacheBackend {
+    type ErrorType = Infallible;
+
+    async fn get(&self, key: &CacheKey) -> Result<Option<Vec<f32>>, Infallible> {
+        Ok(self
+            .entries
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .touch(key))
+    }
+
+    async fn put(&self, key: CacheKey, vector: Vec<f32>) -> Result<(), Infallible> {
+        self.entries
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(key, vector, self.capacity.get());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn least_recently_used_vector_is_evicted() {
+        let cache = InMemoryCacheBackend::new(NonZeroUsize::new(2).unwrap());
+        let (first, second, third) = (
+            CacheKey::new("model", "first"),
+            CacheKey::new("model", "second"),
+            CacheKey::new("model", "third"),
+        );
+        cache.put(first.clone(), vec![1.0]).await.unwrap();
+        cache.put(second.clone(), vec![2.0]).await.unwrap();
+        // Reading the first vector makes the second the least recently used
+        assert_eq!(cache.get(&first).await.unwrap(), Some(vec![1.0]));
+        cache.put(third.clone(), vec![3.0]).await.unwrap();
+
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.get(&second).await.unwrap(), None);
+        assert_eq!(cache.get(&first).await.unwrap(), Some(vec![1.0]));
+        assert_eq!(cache.get(&third).await.unwrap(), Some(vec![3.0]));
+    }
+
+    #[tokio::test]
+    async fn replacing_a_vector_does_not_evict() {
+        let cache = InMemoryCacheBackend::new(NonZeroUsize::new(2).unwrap());
+        let key = CacheKey::new("model", "text");
+        cache.put(key.clone(), vec![1.0]).await.unwrap();
+        cache
+            .put(CacheKey::new("model", "other"), vec![2.0])
+            .await
+            .unwrap();
+        cache.put(key.clone(), vec![3.0]).await.unwrap();
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.get(&key).await.unwrap(), Some(vec![3.0]));
+    }
+}
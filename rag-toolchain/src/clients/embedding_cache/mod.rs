@@ -0,0 +1,9 @@
+mod backend;
+mod cached_client;
+mod file_backend;
+mod in_memory_backend;
+
+pub use backend::{CacheBackend, CacheKey};
+pub use cached_client::{CacheStats, EmbeddingCache, EmbeddingCacheError};
+pub use file_backend::{FileCacheBackend, FileCacheError};
+pub use in_memory_backend::InMemoryCacheBackend;
@@ -0,0 +1,141 @@
+#[cfg(any(feature = "openai-embeddings", feature = "ollama"))]
+use crate::common::{Chunk, Chunks, Embedding};
+
+/// # [`EmbeddingTaskType`]
+///
+/// What the embedded text will be used for. Instruction tuned models such as e5 expect
+/// queries and documents to be embedded differently, for example prefixed with `query: `
+/// and `passage: `, and retrieval suffers without it. Stores are filled with
+/// [`EmbeddingTaskType::Document`] embeddings and the retrievers embed the text they are
+/// asked for with [`EmbeddingTaskType::Query`], see
+/// [`crate::clients::AsyncEmbeddingClient::generate_embedding_for`].
+///
+/// * `Document` - text which will be stored and searched, the default.
+/// * `Query` - text which is searched for in the stored documents.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum EmbeddingTaskType {
+    #[default]
+    Document,
+    Query,
+}
+
+impl EmbeddingTaskType {
+    /// # [`EmbeddingTaskType::as_str`]
+    ///
+    /// # Returns
+    /// * &[`str`] - the task in snake case.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EmbeddingTaskType::Document => "document",
+            EmbeddingTaskType::Query => "query",
+        }
+    }
+}
+
+/// # [`TaskPrefixes`]
+///
+/// The text a client puts in front of each chunk it embeds for a task. The embeddings
+/// returned still hold the chunks as they were given so the prefix is never stored.
+#[cfg(any(feature = "openai-embeddings", feature = "ollama"))]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct TaskPrefixes {
+    document: Option<String>,
+    query: Option<String>,
+}
+
+#[cfg(any(feature = "openai-embeddings", feature = "ollama"))]
+impl TaskPrefixes {
+    /// # [`TaskPrefixes::set`]
+    ///
+    /// # Arguments
+    /// * `task`: [`EmbeddingTaskType`] - the task the prefix is for.
+    /// * `prefix`: [`String`] - put in front of the text embedded for the task.
+    pub(crate) fn set(&mut self, task: EmbeddingTaskType, prefix: String) {
+        match task {
+            EmbeddingTaskType::Document => self.document = Some(prefix),
+            EmbeddingTaskType::Query => self.query = Some(prefix),
+        }
+    }
+
+    /// # [`TaskPrefixes::apply`]
+    ///
+    /// # Arguments
+    /// * `chunks`: &[`[Chunk]`] - the chunks about to be embedded.
+    /// * `task`: [`EmbeddingTaskType`] - what they are embedded for.
+    ///
+    /// # Returns
+    /// * [`Option<Chunks>`] - copies of the chunks with the prefix in front of their
+    ///   content, or [`None`] if the task has no prefix.
+    pub(crate) fn apply(&self, chunks: &[Chunk], task: EmbeddingTaskType) -> Option<Chunks> {
+        let prefix: &str = match task {
+            EmbeddingTaskType::Document => self.document.as_deref()?,
+            EmbeddingTaskType::Query => self.query.as_deref()?,
+        };
+        Some(
+            chunks
+                .iter()
+                .map(|chunk| {
+                    let content: String = format!("{}{}", prefix, chunk.content());
+                    Chunk::new_with_metadata(content, chunk.metadata().clone())
+                })
+                .collect(),
+        )
+    }
+
+    /// # [`TaskPrefixes::restore`]
+    ///
+    /// Swaps the prefixed chunks back out of the embeddings.
+    ///
+    /// # Arguments
+    /// * `embeddings`: [`Vec<Embedding>`] - the embeddings of the prefixed chunks.
+    /// * `chunks`: [`Chunks`] - the chunks as they were given, in the same order.
+    ///
+    /// # Returns
+    /// * [`Vec<Embedding>`] - the vectors paired with the given chunks.
+    pub(crate) fn restore(embeddings: Vec<Embedding>, chunks: Chunks) -> Vec<Embedding> {
+        embeddings
+            .iter()
+            .zip(chunks)
+            .map(|(embedding, chunk)| Embedding::new(chunk, embedding.vector_slice()))
+            .collect()
+    }
+}
+
+#[cfg(all(test, any(feature = "openai-embeddings", feature = "ollama")))]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn only_tasks_with_a_prefix_are_rewritten() {
+        let mut prefixes = TaskPrefixes::default();
+        prefixes.set(EmbeddingTaskType::Query, "query: ".into());
+        let chunks = vec![Chunk::new_with_metadata("refunds", json!({"id": 1}))];
+        assert_eq!(
+            prefixes.apply(&chunks, EmbeddingTaskType::Query),
+            Some(vec![Chunk::new_with_metadata(
+                "query: refunds",
+                json!({"id": 1})
+            )])
+        );
+        assert_eq!(prefixes.apply(&chunks, EmbeddingTaskType::Document), None);
+    }
+
+    #[test]
+    fn restore_pairs_the_vectors_with_the_given_chunks() {
+        let embeddings = vec![
+            Embedding::new(Chunk::new("passage: a"), vec![1.0]),
+            Embedding::new(Chunk::new("passage: b"), vec![2.0]),
+        ];
+        let restored = TaskPrefixes::restore(embeddings, vec![Chunk::new("a"), Chunk::new("b")]);
+        assert_eq!(
+            restored,
+            vec![
+                Embedding::new(Chunk::new("a"), vec![1.0]),
+                Embedding::new(Chunk::new("b"), vec![2.0]),
+            ]
+        );
+    }
+}
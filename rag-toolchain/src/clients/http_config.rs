@@ -0,0 +1,106 @@
+use reqwest::Client;
+use std::env::VarError;
+use std::time::Duration;
+use thiserror::Error;
+
+/// The longest a request may take by default, from sending it to reading the whole response
+pub const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(120);
+/// The longest establishing a connection may take by default
+pub const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// # [`HttpConfig`]
+///
+/// How a client's HTTP connections behave. A request which takes longer than a timeout
+/// fails with the client's `Timeout` error rather than hanging.
+///
+/// * `timeout` - the longest a request may take from being sent to its response being read.
+///   This is not applied to streamed requests, which can stay open for as long as the model
+///   is generating.
+/// * `connect_timeout` - the longest establishing a connection may take, this is applied to
+///   every request including streamed ones.
+///
+/// # Examples
+/// ```
+/// use rag_toolchain::clients::*;
+/// use std::time::Duration;
+///
+/// let config = HttpConfig {
+///     timeout: Duration::from_secs(30),
+///     ..HttpConfig::default()
+/// };
+/// assert_eq!(config.connect_timeout, DEFAULT_CONNECT_TIMEOUT);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HttpConfig {
+    pub timeout: Duration,
+    pub connect_timeout: Duration,
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        HttpConfig {
+            timeout: DEFAULT_REQUEST_TIMEOUT,
+            connect_timeout: DEFAULT_CONNECT_TIMEOUT,
+        }
+    }
+}
+
+impl HttpConfig {
+    /// # [`HttpConfig::build_client`]
+    ///
+    /// Only the connect timeout is set on the client, the request timeout is set on each
+    /// request which is not streamed.
+    ///
+    /// # Errors
+    /// * [`HttpConfigError::ClientBuildError`] - if the client could not be built, e.g. the
+    ///   TLS backend could not be initialized.
+    ///
+    /// # Returns
+    /// * [`Client`] - the reqwest client to send requests with.
+    pub(crate) fn build_client(&self) -> Result<Client, HttpConfigError> {
+        Client::builder()
+            .connect_timeout(self.connect_timeout)
+            .build()
+            .map_err(|error| HttpConfigError::ClientBuildError(error.to_string()))
+    }
+
+    /// # [`HttpConfig::default_client`]
+    ///
+    /// The client built from [`HttpConfig::default`] for the constructors which cannot fail.
+    /// Like [`Client::new`] this panics if the TLS backend cannot be initialized.
+    ///
+    /// # Returns
+    /// * [`Client`] - the reqwest client to send requests with.
+    pub(crate) fn default_client() -> Client {
+        HttpConfig::default()
+            .build_client()
+            .expect("the default HTTP client could not be built")
+    }
+}
+
+/// # [`HttpConfigError`]
+/// The errors that can occur when creating a client with an [`HttpConfig`].
+#[derive(Error, Debug, PartialEq)]
+pub enum HttpConfigError {
+    /// The HTTP client could not be built from the config, holds the reason
+    #[error("HTTP Client Build Error: {0}")]
+    ClientBuildError(String),
+    #[error("Environment Variable Error: {0}")]
+    EnvVarError(VarError),
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use reqwest::header::HeaderMap;
+
+    // A client which sends a header the mock server can match on, standing in for
+    // the proxy or certificate configuration a caller would set
+    pub(crate) fn with_custom_http_client() -> reqwest::Client {
+        let mut headers = HeaderMap::new();
+        headers.insert("X-Proxy-Authorization", "Basic proxy".parse().unwrap());
+        reqwest::Client::builder()
+            .default_headers(headers)
+            .build()
+            .unwrap()
+    }
+}
@@ -1,28 +1,171 @@
 /// # Clients
 /// This module will contain all of the client code for different services
 /// that can be used to interact with Gen AI models.
-#[cfg(feature = "openai")]
+#[cfg(any(feature = "openai-embeddings", feature = "openai-chat"))]
 mod open_ai;
 
 #[cfg(feature = "anthropic")]
 mod anthropic;
 
+#[cfg(feature = "ollama")]
+mod ollama;
+
+#[cfg(feature = "cohere")]
+mod cohere;
+
+#[cfg(feature = "bedrock")]
+mod bedrock;
+
+#[cfg(feature = "embedding-cache")]
+mod embedding_cache;
+
+#[cfg(any(feature = "openai-chat", feature = "anthropic"))]
+mod additional_config;
+#[cfg(any(feature = "openai-chat", feature = "anthropic"))]
+mod capabilities;
+
+mod dyn_chat_client;
+mod embedding_task;
+mod moderation;
+mod prompt_tokens;
+
+// Test only record / replay layer for the HTTP cores
+#[cfg(all(
+    test,
+    any(
+        feature = "openai-embeddings",
+        feature = "openai-chat",
+        feature = "anthropic"
+    )
+))]
+mod cassette;
+#[cfg(any(
+    feature = "openai-embeddings",
+    feature = "openai-chat",
+    feature = "anthropic"
+))]
+mod http_config;
+#[cfg(any(feature = "openai-embeddings", feature = "openai-chat"))]
+mod rate_limit;
+#[cfg(any(feature = "openai-embeddings", feature = "openai-chat"))]
+mod retry;
+#[cfg(any(
+    feature = "openai-embeddings",
+    feature = "openai-chat",
+    feature = "anthropic",
+    feature = "cohere"
+))]
+mod secrets;
+#[cfg(feature = "openai-stream")]
+mod stop_sequences;
 mod traits;
 mod types;
 
-#[cfg(feature = "openai")]
+#[cfg(any(feature = "openai-embeddings", feature = "openai-chat"))]
+pub use self::open_ai::{OpenAICompatible, OpenAIError};
+
+#[cfg(feature = "openai-embeddings")]
+pub use self::open_ai::{OpenAIEmbeddingClient, OpenAIEmbeddingConfigError};
+
+#[cfg(feature = "openai-chat")]
 pub use self::open_ai::{
-    CompletionStreamValue, OpenAIChatCompletionClient, OpenAICompletionStream,
-    OpenAIEmbeddingClient, OpenAIError, OpenAIModel,
+    ChatOptions, ModerationCategories, ModerationCategoryScores, ModerationResponse,
+    ModerationResult, OpenAIChatCompletionClient, OpenAIModel, OpenAIModerationClient,
+    OpenAIModerationModel,
 };
 
+#[cfg(feature = "openai-stream")]
+pub use self::open_ai::OpenAICompletionStream;
+
 #[cfg(feature = "anthropic")]
-pub use self::anthropic::{AnthropicChatCompletionClient, AnthropicError, AnthropicModel};
+pub use self::anthropic::{
+    AnthropicChatCompletionClient, AnthropicContentBlock, AnthropicError, AnthropicImageSource,
+    AnthropicModel, AnthropicResponse, AnthropicStopReason, SystemPromptMode,
+};
+
+#[cfg(feature = "anthropic-stream")]
+pub use self::anthropic::AnthropicCompletionStream;
+
+#[cfg(feature = "ollama")]
+pub use self::ollama::{
+    OllamaChatCompletionClient, OllamaCompletionStream, OllamaEmbeddingClient, OllamaError,
+    DEFAULT_OLLAMA_BASE_URL, DEFAULT_OLLAMA_MAX_TOKENS,
+};
+
+#[cfg(feature = "cohere")]
+pub use self::cohere::{
+    CohereEmbeddingClient, CohereError, CohereInputType, CohereRerankModel, CohereReranker,
+};
+
+#[cfg(feature = "bedrock")]
+pub use self::bedrock::{
+    BedrockChatCompletionClient, BedrockError, BedrockErrorBody, BedrockModel, BedrockStopReason,
+};
+
+#[cfg(feature = "embedding-cache")]
+pub use self::embedding_cache::{
+    CacheBackend, CacheKey, CacheStats, EmbeddingCache, EmbeddingCacheError, FileCacheBackend,
+    FileCacheError, InMemoryCacheBackend,
+};
+
+#[cfg(any(feature = "openai-chat", feature = "anthropic"))]
+pub(crate) use self::additional_config::check_reserved_keys;
+#[cfg(any(feature = "openai-chat", feature = "anthropic"))]
+pub(crate) use self::additional_config::deep_merge;
+#[cfg(any(feature = "openai-chat", feature = "anthropic"))]
+pub use self::additional_config::AdditionalConfigError;
+#[cfg(any(feature = "openai-chat", feature = "anthropic"))]
+pub use self::capabilities::ModelCapabilities;
+
+#[cfg(any(
+    feature = "openai-embeddings",
+    feature = "openai-chat",
+    feature = "anthropic"
+))]
+pub use self::http_config::{
+    HttpConfig, HttpConfigError, DEFAULT_CONNECT_TIMEOUT, DEFAULT_REQUEST_TIMEOUT,
+};
+
+#[cfg(any(feature = "openai-embeddings", feature = "openai-chat"))]
+pub use self::rate_limit::{RateLimit, RateLimiter};
+
+#[cfg(any(feature = "openai-embeddings", feature = "openai-chat"))]
+pub use self::retry::{RetryPolicy, DEFAULT_BASE_DELAY, DEFAULT_MAX_ATTEMPTS, DEFAULT_MAX_DELAY};
+
+#[cfg(any(
+    feature = "openai-embeddings",
+    feature = "openai-chat",
+    feature = "anthropic",
+    feature = "cohere"
+))]
+pub use self::secrets::{
+    EnvSecretProvider, SecretError, SecretFuture, SecretProvider, SecretString, DEFAULT_SECRET_TTL,
+};
+
+pub use self::dyn_chat_client::DynAsyncChatClient;
+pub use self::embedding_task::EmbeddingTaskType;
+#[cfg(any(feature = "openai-embeddings", feature = "ollama"))]
+pub(crate) use self::embedding_task::TaskPrefixes;
+pub use self::moderation::{ModerationError, ModerationFuture, ModerationVerdict, Moderator};
+pub use self::prompt_tokens::{
+    count_message_tokens, count_prompt_tokens, REPLY_PRIMING_TOKENS, TOKENS_PER_MESSAGE,
+    TOKENS_PER_NAME,
+};
 
 pub use self::traits::{
     AsyncChatClient, AsyncEmbeddingClient, AsyncStreamedChatClient, ChatCompletionStream,
+    StreamedText, TokenCounter,
+};
+#[cfg(any(
+    feature = "openai-stream",
+    feature = "anthropic-stream",
+    feature = "ollama"
+))]
+pub use self::types::CompletionStreamValue;
+pub use self::types::{
+    ContentPart, DetailedChatResponse, FinishReason, ImageSource, MessageBody, MessageMeta,
+    PromptMessage, ReproducibilityReport, RequestContext,
 };
-pub use self::types::PromptMessage;
 
 // Export the trait mocks for use in testing
 #[cfg(test)]
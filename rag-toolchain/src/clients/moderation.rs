@@ -0,0 +1,84 @@
+use std::future::Future;
+use std::pin::Pin;
+use thiserror::Error;
+
+/// The future returned by [`Moderator::moderate`]
+pub type ModerationFuture<'a> =
+    Pin<Box<dyn Future<Output = Result<ModerationVerdict, ModerationError>> + Send + 'a>>;
+
+/// # [`Moderator`]
+///
+/// Checks text for unsafe content such as harassment or self harm, a chain with a
+/// [`crate::chains::ModerationPolicy`] runs each user message through one before it
+/// reaches the chat client. [`crate::clients::OpenAIModerationClient`] implements this
+/// with the OpenAI moderation endpoint, implement it to use any other classifier.
+///
+/// # Examples
+/// ```
+/// use rag_toolchain::clients::{ModerationFuture, ModerationVerdict, Moderator};
+///
+/// struct BlockList(Vec<String>);
+///
+/// impl Moderator for BlockList {
+///     fn moderate<'a>(&'a self, text: &'a str) -> ModerationFuture<'a> {
+///         Box::pin(async move {
+///             let matched: Vec<String> = self
+///                 .0
+///                 .iter()
+///                 .filter(|word| text.contains(word.as_str()))
+///                 .cloned()
+///                 .collect();
+///             Ok(ModerationVerdict::new(matched))
+///         })
+///     }
+/// }
+/// ```
+pub trait Moderator: Send + Sync {
+    /// # [`Moderator::moderate`]
+    ///
+    /// # Arguments
+    /// * `text`: &[`str`] - the text to check.
+    ///
+    /// # Errors
+    /// * [`ModerationError`] - if the text could not be checked.
+    ///
+    /// # Returns
+    /// * [`ModerationFuture`] - resolves to whether the text was flagged and why.
+    fn moderate<'a>(&'a self, text: &'a str) -> ModerationFuture<'a>;
+}
+
+/// # [`ModerationVerdict`]
+///
+/// The result of checking text with a [`Moderator`].
+///
+/// * `flagged` - whether the text should be refused.
+/// * `categories` - the names of the categories the text was flagged for e.g. `harassment`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ModerationVerdict {
+    pub flagged: bool,
+    pub categories: Vec<String>,
+}
+
+impl ModerationVerdict {
+    /// # [`ModerationVerdict::new`]
+    ///
+    /// # Arguments
+    /// * `categories`: [`Vec<String>`] - the categories the text was flagged for.
+    ///
+    /// # Returns
+    /// * [`ModerationVerdict`] - which is flagged if there are any categories.
+    pub fn new(categories: Vec<String>) -> Self {
+        ModerationVerdict {
+            flagged: !categories.is_empty(),
+            categories,
+        }
+    }
+}
+
+/// # [`ModerationError`]
+///
+/// The text could not be checked by a [`Moderator`], this holds the underlying error.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+#[error("Error moderating content: {0}")]
+pub struct ModerationError(pub String);
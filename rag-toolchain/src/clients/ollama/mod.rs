@@ -0,0 +1,9 @@
+mod model;
+mod ollama_chat_completions;
+mod ollama_core;
+mod ollama_embeddings;
+
+pub use model::errors::OllamaError;
+pub use ollama_chat_completions::{OllamaChatCompletionClient, OllamaCompletionStream};
+pub use ollama_core::DEFAULT_OLLAMA_BASE_URL;
+pub use ollama_embeddings::{OllamaEmbeddingClient, DEFAULT_OLLAMA_MAX_TOKENS};
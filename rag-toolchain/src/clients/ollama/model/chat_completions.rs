@@ -0,0 +1,63 @@
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use super::errors::OllamaErrorBody;
+use crate::common::TokenUsage;
+
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct ChatRequest {
+    pub model: String,
+    pub messages: Vec<Message>,
+    /// Ollama streams unless this is explicitly set to false
+    pub stream: bool,
+    /// Inference parameters such as temperature or num_ctx
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub options: Option<Map<String, Value>>,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub struct Message {
+    pub role: Role,
+    pub content: String,
+    /// Base64 encoded images, only read by multimodal models
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub images: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    System,
+    User,
+    Assistant,
+}
+
+/// The whole response when not streaming, or a single line of the stream.
+/// The token counts are only sent once generation is done.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub struct ChatResponse {
+    pub model: String,
+    pub message: Message,
+    pub done: bool,
+    /// Why generation finished, `stop` or `length`, only sent once it is done
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub done_reason: Option<String>,
+    #[serde(default)]
+    pub prompt_eval_count: usize,
+    #[serde(default)]
+    pub eval_count: usize,
+}
+
+impl From<&ChatResponse> for TokenUsage {
+    fn from(response: &ChatResponse) -> Self {
+        TokenUsage::new(response.prompt_eval_count, response.eval_count)
+    }
+}
+
+/// Each line of a streamed response is either part of the reply or an error
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(untagged)]
+pub enum ChatStreamLine {
+    Chunk(ChatResponse),
+    Error(OllamaErrorBody),
+}
@@ -0,0 +1,91 @@
+use serde::Deserialize;
+use thiserror::Error;
+
+use crate::clients::RequestContext;
+
+/// The body Ollama sends with any error, it only carries a message
+#[derive(Debug, Deserialize, PartialEq, Clone)]
+pub struct OllamaErrorBody {
+    pub error: String,
+}
+
+/// # [`OllamaError`]
+///
+/// Ollama only uses a handful of status codes, these are listed here
+/// <https://github.com/ollama/ollama/blob/main/docs/api.md>.
+#[derive(Error, Debug, PartialEq, Clone)]
+pub enum OllamaError {
+    /// # The request was malformed, for example a required field is missing.
+    #[error("Bad Request Error: {0:?}")]
+    CODE400(OllamaErrorBody),
+    /// # The model has not been pulled, run `ollama pull <model>` first.
+    #[error("Model Not Found Error: {0:?}")]
+    CODE404(OllamaErrorBody),
+    /// # Ollama failed to load or run the model.
+    #[error("Internal Server Error: {0:?}")]
+    CODE500(OllamaErrorBody),
+    /// # Missed cases for error codes, includes Status Code and Error Body as a string. These can also represent internal logic errors.
+    #[error("Undefined Error. This should not happen, if this is a missed error please report it: https://github.com/JackMatthewRimmer/rust-rag-toolchain: status code = {0}, error = {1}")]
+    Undefined(u16, String),
+    /// # Carries underlying error that may have occurred during sending the request
+    #[error("Error sending request: {0}")]
+    ErrorSendingRequest(String),
+    /// # Carries underlying error that may have occured when trying to get the response body
+    #[error("Error getting response body: {0}")]
+    ErrorGettingResponseBody(String),
+    // # Carries underlying error and the status code
+    #[error("Error deserializining response body: status code = {0}, error = {1}")]
+    ErrorDeserializingResponseBody(u16, String),
+    /// # Carries underlying error if something went wrong when reading from a stream,
+    /// # or the error Ollama sent part way through the stream
+    #[error("Error reading stream: {0}")]
+    ErrorReadingStream(String),
+    /// # The message content cannot be sent to Ollama, the request was not sent.
+    #[error("Unsupported content: {0}")]
+    UnsupportedContent(String),
+    /// # The model returned embeddings of a different size to the dimensions the client was created with.
+    #[error("Dimensions mismatch: expected embeddings with {expected} dimensions but the model returned {actual}")]
+    DimensionsMismatch { expected: usize, actual: usize },
+    /// # An error from a request sent to Ollama, with the context of the request
+    /// Use [`OllamaError::kind`] to match on the underlying error.
+    #[error("{source} ({context})")]
+    Request {
+        context: RequestContext,
+        source: Box<OllamaError>,
+    },
+}
+
+impl OllamaError {
+    /// # [`OllamaError::kind`]
+    ///
+    /// # Returns
+    /// * &[`OllamaError`] - the underlying error without any [`RequestContext`].
+    pub fn kind(&self) -> &OllamaError {
+        match self {
+            OllamaError::Request { source, .. } => source.kind(),
+            error => error,
+        }
+    }
+
+    /// # [`OllamaError::context`]
+    ///
+    /// # Returns
+    /// * [`Option<&RequestContext>`] - the context of the request the error came from,
+    ///   [`None`] if the error happened before a request was sent.
+    pub fn context(&self) -> Option<&RequestContext> {
+        match self {
+            OllamaError::Request { context, .. } => Some(context),
+            _ => None,
+        }
+    }
+
+    /// # [`OllamaError::with_context`]
+    ///
+    /// Attaches the context of the request to the error.
+    pub(crate) fn with_context(self, context: RequestContext) -> Self {
+        OllamaError::Request {
+            context,
+            source: Box::new(self),
+        }
+    }
+}
@@ -0,0 +1,3 @@
+pub mod chat_completions;
+pub mod embeddings;
+pub mod errors;
@@ -0,0 +1,631 @@
+use crate::clients::ollama::model::chat_completions::{
+    ChatRequest, ChatResponse, ChatStreamLine, Message, Role,
+};
+use crate::clients::ollama::model::errors::OllamaError;
+use crate::clients::ollama::ollama_core::{OllamaHttpClient, DEFAULT_OLLAMA_BASE_URL};
+use crate::clients::{
+    AsyncChatClient, AsyncStreamedChatClient, ChatCompletionStream, CompletionStreamValue,
+    ContentPart, DetailedChatResponse, ImageSource, PromptMessage, TokenCounter,
+};
+use crate::common::{InvocationContext, TokenUsage};
+
+use reqwest::Response;
+use serde_json::{Map, Value};
+
+const OLLAMA_CHAT_PATH: &str = "/api/chat";
+
+/// # [`OllamaChatCompletionClient`]
+/// Allows for interacting with models served locally by Ollama via the chat API.
+/// The model is named as it is in Ollama e.g. `llama3` or `mistral:7b` and must
+/// have already been pulled.
+///
+/// # Examples
+/// ```
+/// use serde_json::{Map, Value};
+/// use rag_toolchain::clients::*;
+///
+/// async fn generate_completion() {
+///     let mut options: Map<String, Value> = Map::new();
+///     options.insert("temperature".into(), 0.5.into());
+///
+///     let client: OllamaChatCompletionClient =
+///         OllamaChatCompletionClient::new_with_options("llama3", options)
+///             .with_base_url("http://192.168.1.20:11434");
+///
+///     let system_message: PromptMessage =
+///         PromptMessage::SystemMessage("You only reply in a bullet point list".into());
+///     let user_message: PromptMessage = PromptMessage::HumanMessage("How does the water flow".into());
+///
+///     let reply = client
+///         .invoke(vec![system_message, user_message])
+///         .await
+///         .unwrap();
+///     println!("{:?}", reply.content());
+/// }
+/// ```
+pub struct OllamaChatCompletionClient {
+    url: String,
+    client: OllamaHttpClient,
+    model: String,
+    options: Option<Map<String, Value>>,
+}
+
+impl OllamaChatCompletionClient {
+    const IMAGE_URL_ERROR: &'static str =
+        "Ollama only accepts base64 encoded images, image urls must be downloaded first";
+
+    /// # [`OllamaChatCompletionClient::new`]
+    ///
+    /// Creates a client for a model on an Ollama server at [`DEFAULT_OLLAMA_BASE_URL`],
+    /// use [`OllamaChatCompletionClient::with_base_url`] to point it elsewhere.
+    /// All inference parameters are left to the model's defaults.
+    ///
+    /// # Arguments
+    /// * `model`: impl [`Into<String>`] - The name of the model in Ollama.
+    ///
+    /// # Returns
+    /// [`OllamaChatCompletionClient`] - The client to interact with Ollama.
+    pub fn new(model: impl Into<String>) -> Self {
+        OllamaChatCompletionClient {
+            url: format!("{}{}", DEFAULT_OLLAMA_BASE_URL, OLLAMA_CHAT_PATH),
+            client: OllamaHttpClient::new(DEFAULT_OLLAMA_BASE_URL),
+            model: model.into(),
+            options: None,
+        }
+    }
+
+    /// # [`OllamaChatCompletionClient::new_with_options`]
+    ///
+    /// Creates a client for a model on an Ollama server at [`DEFAULT_OLLAMA_BASE_URL`]
+    /// which sends inference parameters with each request.
+    ///
+    /// # Arguments
+    /// * `model`: impl [`Into<String>`] - The name of the model in Ollama.
+    /// * `options`: [`Map<String, Value>`] - Sent as the options of each request.
+    ///   Examples of this can be temperature, num_ctx, seed, etc.
+    ///
+    /// # Returns
+    /// [`OllamaChatCompletionClient`] - The client to interact with Ollama.
+    pub fn new_with_options(model: impl Into<String>, options: Map<String, Value>) -> Self {
+        let mut client: OllamaChatCompletionClient = Self::new(model);
+        client.options = Some(options);
+        client
+    }
+
+    /// # [`OllamaChatCompletionClient::with_base_url`]
+    ///
+    /// Points the client at an Ollama server other than [`DEFAULT_OLLAMA_BASE_URL`].
+    ///
+    /// # Arguments
+    /// * `base_url`: &[`str`] - The address of the Ollama server e.g. `http://gpu-box:11434`.
+    ///
+    /// # Returns
+    /// [`OllamaChatCompletionClient`] - the client sending requests to the new server.
+    pub fn with_base_url(mut self, base_url: &str) -> Self {
+        self.client = OllamaHttpClient::new(base_url);
+        self.url = self.client.url(OLLAMA_CHAT_PATH);
+        self
+    }
+
+    /// # [`OllamaChatCompletionClient::build_request`]
+    ///
+    /// Maps the messages and builds the request body, this is shared
+    /// between the streamed and non streamed invocations.
+    ///
+    /// # Arguments
+    /// * `prompt_messages`: [`Vec<PromptMessage>`] - The list of messages to send to Ollama.
+    /// * `stream`: [`bool`] - Whether the response should be streamed.
+    ///
+    /// # Errors
+    /// * [`OllamaError::UnsupportedContent`] - If a message contains an image url.
+    ///
+    /// # Returns
+    /// [`ChatRequest`] - The request to send to Ollama.
+    fn build_request(
+        &self,
+        prompt_messages: Vec<PromptMessage>,
+        stream: bool,
+    ) -> Result<ChatRequest, OllamaError> {
+        let messages: Vec<Message> = prompt_messages
+            .into_iter()
+            .map(Self::map_prompt_message_to_ollama_message)
+            .collect::<Result<Vec<Message>, OllamaError>>()?;
+        Ok(ChatRequest {
+            model: self.model.clone(),
+            messages,
+            stream,
+            options: self.options.clone(),
+        })
+    }
+
+    /// # [`OllamaChatCompletionClient::map_prompt_message_to_ollama_message`]
+    ///
+    /// Helper method to map a prompt message to an Ollama message. Ollama takes the
+    /// images of a message separately to its text, so the text parts of a multimodal
+    /// message are joined with new lines.
+    ///
+    /// # Arguments
+    /// * `prompt_message`: [`PromptMessage`] - The message to map to the Ollama message.
+    ///
+    /// # Errors
+    /// * [`OllamaError::UnsupportedContent`] - If the message contains an image url.
+    ///
+    /// # Returns
+    /// [`Message`] - The message to send to Ollama.
+    fn map_prompt_message_to_ollama_message(
+        prompt_message: PromptMessage,
+    ) -> Result<Message, OllamaError> {
+        let (role, content): (Role, String) = match prompt_message {
+            PromptMessage::SystemMessage(message) => (Role::System, message.content),
+            PromptMessage::HumanMessage(message) => (Role::User, message.content),
+            PromptMessage::AIMessage(message) => (Role::Assistant, message.content),
+            PromptMessage::MultiModalHumanMessage(parts) => {
+                let mut text: Vec<String> = Vec::new();
+                let mut images: Vec<String> = Vec::new();
+                for part in parts {
+                    match part {
+                        ContentPart::Text(part) => text.push(part),
+                        ContentPart::Image(ImageSource::Base64 { data, .. }) => images.push(data),
+                        ContentPart::Image(ImageSource::Url(_)) => {
+                            return Err(OllamaError::UnsupportedContent(
+                                Self::IMAGE_URL_ERROR.to_string(),
+                            ))
+                        }
+                    }
+                }
+                return Ok(Message {
+                    role: Role::User,
+                    content: text.join("\n"),
+                    images,
+                });
+            }
+        };
+        Ok(Message {
+            role,
+            content,
+            images: Vec::new(),
+        })
+    }
+}
+
+impl TokenCounter for OllamaChatCompletionClient {}
+
+impl AsyncChatClient for OllamaChatCompletionClient {
+    type ErrorType = OllamaError;
+
+    /// # [`OllamaChatCompletionClient::invoke`]
+    ///
+    /// Function to send a list of [`PromptMessage`] to Ollama and receive a response.
+    ///
+    /// # Arguments
+    /// * `prompt_messages`: [`Vec<PromptMessage>`] - The list of messages to send to Ollama.
+    ///
+    /// # Errors
+    /// * [`OllamaError::UnsupportedContent`] - If a message contains an image url.
+    /// * [`OllamaError`] - This error is returned when Ollama returns an error.
+    ///
+    /// # Returns
+    /// [`PromptMessage::AIMessage`] - The response from the model.
+    async fn invoke(
+        &self,
+        prompt_messages: Vec<PromptMessage>,
+    ) -> Result<PromptMessage, Self::ErrorType> {
+        let request: ChatRequest = self.build_request(prompt_messages, false)?;
+        let response: ChatResponse = self.client.send_request(request, &self.url).await?;
+        Ok(PromptMessage::AIMessage(response.message.content.into()))
+    }
+
+    /// # [`OllamaChatCompletionClient::invoke_with_context`]
+    ///
+    /// The same as [`OllamaChatCompletionClient::invoke`] but the response carries
+    /// the request id from the context and the token usage Ollama reported.
+    ///
+    /// # Arguments
+    /// * `prompt_messages`: [`Vec<PromptMessage>`] - The list of messages to send to Ollama.
+    /// * `context`: &[`InvocationContext`] - the context of the invocation.
+    ///
+    /// # Errors
+    /// * [`OllamaError`] - for the same reasons as [`OllamaChatCompletionClient::invoke`].
+    ///
+    /// # Returns
+    /// [`DetailedChatResponse`] - The response from the model along with the request id and usage.
+    async fn invoke_with_context(
+        &self,
+        prompt_messages: Vec<PromptMessage>,
+        context: &InvocationContext,
+    ) -> Result<DetailedChatResponse, Self::ErrorType> {
+        let request: ChatRequest = self.build_request(prompt_messages, false)?;
+        let response: ChatResponse = self.client.send_request(request, &self.url).await?;
+        let usage: TokenUsage = TokenUsage::from(&response);
+        Ok(DetailedChatResponse {
+            message: PromptMessage::AIMessage(response.message.content.into()),
+            request_id: context.request_id(),
+            provider_request_id: None,
+            system_fingerprint: None,
+            usage: Some(usage),
+            finish_reason: response.done_reason.and_then(|reason| reason.parse().ok()),
+        })
+    }
+}
+
+impl AsyncStreamedChatClient for OllamaChatCompletionClient {
+    type ErrorType = OllamaError;
+    type Item = OllamaCompletionStream;
+
+    /// # [`OllamaChatCompletionClient::invoke_stream`]
+    ///
+    /// Function to send a list of [`PromptMessage`] to Ollama and stream the response.
+    ///
+    /// # Arguments
+    /// * `prompt_messages`: [`Vec<PromptMessage>`] - The list of messages to send to Ollama.
+    ///
+    /// # Errors
+    /// * [`OllamaError::UnsupportedContent`] - If a message contains an image url.
+    /// * [`OllamaError`] - If the request could not be sent or Ollama rejected it.
+    ///
+    /// # Returns
+    /// [`OllamaCompletionStream`] - The stream of the response from the model.
+    async fn invoke_stream(
+        &self,
+        prompt_messages: Vec<PromptMessage>,
+    ) -> Result<Self::Item, Self::ErrorType> {
+        let request: ChatRequest = self.build_request(prompt_messages, true)?;
+        let response: Response = self.client.send_stream_request(request, &self.url).await?;
+        Ok(OllamaCompletionStream::new(response))
+    }
+}
+
+/// [`OllamaCompletionStream`]
+///
+/// This struct wraps the streamed response and parses each line
+/// of JSON into prompt messages on demand.
+pub struct OllamaCompletionStream {
+    response: Response,
+    buffer: Vec<u8>,
+    finished: bool,
+    usage: Option<TokenUsage>,
+}
+
+impl OllamaCompletionStream {
+    /// # [`OllamaCompletionStream::new`]
+    ///
+    /// This struct just wraps the response when from the
+    /// context of streaming chat completions.
+    pub fn new(response: Response) -> Self {
+        Self {
+            response,
+            buffer: Vec::new(),
+            finished: false,
+            usage: None,
+        }
+    }
+
+    /// # [`OllamaCompletionStream::usage`]
+    ///
+    /// The tokens Ollama reported using, these are sent on the last line of the stream.
+    ///
+    /// # Returns
+    /// * [`Option<TokenUsage>`] - the usage, `None` until the stream has finished.
+    pub fn usage(&self) -> Option<TokenUsage> {
+        self.usage
+    }
+
+    /// # [`OllamaCompletionStream::fail`]
+    ///
+    /// Finishes the stream so nothing further is read and returns the error.
+    fn fail(&mut self, error: OllamaError) -> Option<Result<CompletionStreamValue, OllamaError>> {
+        self.finished = true;
+        Some(Err(error))
+    }
+
+    /// # [`OllamaCompletionStream::next_line`]
+    ///
+    /// Takes the next complete line from the buffer, at the end of the
+    /// body whatever is left is the last line.
+    fn next_line(&mut self, end_of_body: bool) -> Option<Vec<u8>> {
+        match self.buffer.iter().position(|byte| *byte == b'\n') {
+            Some(end) => Some(self.buffer.drain(..=end).collect()),
+            None if end_of_body && !self.buffer.is_empty() => {
+                Some(std::mem::take(&mut self.buffer))
+            }
+            None => None,
+        }
+    }
+}
+
+impl ChatCompletionStream for OllamaCompletionStream {
+    type ErrorType = OllamaError;
+    type Item = CompletionStreamValue;
+
+    /// # [`ChatCompletionStream::next`]
+    ///
+    /// Method to iterate over the completion stream. Note it blocks until the next
+    /// line with some text is received. The stream finishes on the line marked done.
+    ///
+    /// # Examples
+    /// ```
+    /// use rag_toolchain::clients::*;
+    ///
+    /// async fn stream_chat_completions(client: OllamaChatCompletionClient) {
+    ///     let user_message: PromptMessage = PromptMessage::HumanMessage("Please ask me a question".into());
+    ///     let mut stream: OllamaCompletionStream = client.invoke_stream(vec![user_message]).await.unwrap();
+    ///     while let Some(response) = stream.next().await {
+    ///         match response {
+    ///            Ok(CompletionStreamValue::Connecting) => {},
+    ///            Ok(CompletionStreamValue::Message(msg)) => {
+    ///                 println!("{:?}", msg.content());
+    ///            }
+    ///            Ok(CompletionStreamValue::Finished(_)) => {},
+    ///            Err(e) => {
+    ///                 println!("{:?}", e);
+    ///                 break;
+    ///            }
+    ///         }
+    ///     }
+    /// }
+    /// ```
+    ///
+    /// # Errors
+    /// * [`OllamaError::ErrorReadingStream`] - if there was an error reading from the stream,
+    ///   or Ollama sent an error part way through the stream.
+    /// * [`OllamaError::ErrorDeserializingResponseBody`] - if a line could not be deserialized.
+    ///
+    /// # Returns
+    /// * [`Option<Result<CompletionStreamValue, OllamaError>>`] - the response from the chat client.
+    ///   None represents the stream is finished.
+    async fn next(&mut self) -> Option<Result<Self::Item, Self::ErrorType>> {
+        let mut end_of_body: bool = false;
+        while !self.finished {
+            let line: Vec<u8> = match self.next_line(end_of_body) {
+                Some(line) => line,
+                None if end_of_body => break,
+                None => {
+                    match self.response.chunk().await {
+                        Ok(Some(bytes)) => self.buffer.extend_from_slice(&bytes),
+                        Ok(None) => end_of_body = true,
+                        Err(error) => {
+                            return self.fail(OllamaError::ErrorReadingStream(error.to_string()))
+                        }
+                    }
+                    continue;
+                }
+            };
+            if line.iter().all(u8::is_ascii_whitespace) {
+                continue;
+            }
+
+            let chunk: ChatResponse = match serde_json::from_slice(&line) {
+                Ok(ChatStreamLine::Chunk(chunk)) => chunk,
+                Ok(ChatStreamLine::Error(error_body)) => {
+                    return self.fail(OllamaError::ErrorReadingStream(error_body.error));
+                }
+                Err(error) => {
+                    return self.fail(OllamaError::ErrorDeserializingResponseBody(
+                        200,
+                        error.to_string(),
+                    ));
+                }
+            };
+
+            if chunk.done {
+                self.finished = true;
+                self.usage = Some(TokenUsage::from(&chunk));
+            }
+            if !chunk.message.content.is_empty() {
+                return Some(Ok(CompletionStreamValue::Message(
+                    PromptMessage::AIMessage(chunk.message.content.into()),
+                )));
+            }
+        }
+        self.finished = true;
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clients::FinishReason;
+    use mockito::{Matcher, Mock, Server, ServerGuard};
+    use serde_json::json;
+
+    const CHAT_RESPONSE: &str = r#"
+    {
+        "model": "llama3",
+        "created_at": "2024-07-01T10:00:00.000000Z",
+        "message": {
+            "role": "assistant",
+            "content": "The water flows downhill."
+        },
+        "done_reason": "stop",
+        "done": true,
+        "total_duration": 5191566416,
+        "prompt_eval_count": 26,
+        "eval_count": 7
+    }
+    "#;
+
+    const STREAM_RESPONSE: &str = concat!(
+        r#"{"model":"llama3","message":{"role":"assistant","content":"The"},"done":false}"#,
+        "\n",
+        r#"{"model":"llama3","message":{"role":"assistant","content":" water"},"done":false}"#,
+        "\n\n",
+        r#"{"model":"llama3","message":{"role":"assistant","content":""},"done":true,"prompt_eval_count":26,"eval_count":2}"#,
+    );
+
+    const ERROR_RESPONSE: &str = r#"{"error": "model \"llama3\" not found, try pulling it first"}"#;
+
+    #[tokio::test]
+    async fn invoke_sends_messages_and_returns_reply() {
+        let (client, mut server) = with_mocked_client().await;
+        let expected_body = json!({
+            "model": "llama3",
+            "messages": [
+                {"role": "system", "content": "You are a helpful assistant"},
+                {"role": "user", "content": "How does the water flow"},
+                {"role": "assistant", "content": "Downhill"}
+            ],
+            "stream": false
+        });
+        let mock = with_mocked_request(&mut server, 200, CHAT_RESPONSE)
+            .match_body(Matcher::Json(expected_body))
+            .create();
+        let reply = client.invoke(messages()).await.unwrap();
+        mock.assert();
+        assert_eq!(
+            reply,
+            PromptMessage::AIMessage("The water flows downhill.".into())
+        );
+    }
+
+    #[tokio::test]
+    async fn options_are_sent_with_the_request() {
+        let mut server = Server::new_async().await;
+        let mut options: Map<String, Value> = Map::new();
+        options.insert("temperature".into(), 0.5.into());
+        let client = OllamaChatCompletionClient::new_with_options("llama3", options)
+            .with_base_url(&server.url());
+        let mock = with_mocked_request(&mut server, 200, CHAT_RESPONSE)
+            .match_body(Matcher::PartialJson(
+                json!({"options": {"temperature": 0.5}}),
+            ))
+            .create();
+        client.invoke(messages()).await.unwrap();
+        mock.assert();
+    }
+
+    #[tokio::test]
+    async fn invoke_with_context_reports_usage() {
+        let (client, mut server) = with_mocked_client().await;
+        let mock = with_mocked_request(&mut server, 200, CHAT_RESPONSE).create();
+        let context = InvocationContext::new();
+        let response = client
+            .invoke_with_context(messages(), &context)
+            .await
+            .unwrap();
+        mock.assert();
+        assert_eq!(response.request_id, context.request_id());
+        assert_eq!(response.usage, Some(TokenUsage::new(26, 7)));
+        assert_eq!(response.finish_reason, Some(FinishReason::Stop));
+    }
+
+    #[tokio::test]
+    async fn multimodal_message_sends_base64_images() {
+        let (client, mut server) = with_mocked_client().await;
+        let expected_body = json!({
+            "messages": [{
+                "role": "user",
+                "content": "What is in this image?",
+                "images": ["aGVsbG8="]
+            }]
+        });
+        let mock = with_mocked_request(&mut server, 200, CHAT_RESPONSE)
+            .match_body(Matcher::PartialJson(expected_body))
+            .create();
+        let message = PromptMessage::MultiModalHumanMessage(vec![
+            ContentPart::Text("What is in this image?".into()),
+            ContentPart::Image(ImageSource::Base64 {
+                media_type: "image/png".into(),
+                data: "aGVsbG8=".into(),
+            }),
+        ]);
+        client.invoke(vec![message]).await.unwrap();
+        mock.assert();
+    }
+
+    #[tokio::test]
+    async fn image_urls_are_rejected_before_sending() {
+        let client = OllamaChatCompletionClient::new("llava");
+        let message = PromptMessage::MultiModalHumanMessage(vec![ContentPart::Image(
+            ImageSource::Url("https://example.com/image.png".into()),
+        )]);
+        let error = client.invoke(vec![message]).await.unwrap_err();
+        assert!(matches!(error, OllamaError::UnsupportedContent(_)));
+    }
+
+    #[tokio::test]
+    async fn missing_model_maps_to_404() {
+        let (client, mut server) = with_mocked_client().await;
+        let mock = with_mocked_request(&mut server, 404, ERROR_RESPONSE).create();
+        let error = client.invoke(messages()).await.unwrap_err();
+        mock.assert();
+        assert!(matches!(error.kind(), OllamaError::CODE404(_)));
+    }
+
+    #[tokio::test]
+    async fn stream_yields_each_line_until_done() {
+        let (client, mut server) = with_mocked_client().await;
+        let mock = with_mocked_request(&mut server, 200, STREAM_RESPONSE)
+            .match_body(Matcher::PartialJson(json!({"stream": true})))
+            .create();
+        let mut stream = client.invoke_stream(messages()).await.unwrap();
+        let mut content: Vec<String> = Vec::new();
+        while let Some(value) = stream.next().await {
+            match value.unwrap() {
+                CompletionStreamValue::Message(message) => content.push(message.content().into()),
+                CompletionStreamValue::Connecting | CompletionStreamValue::Finished(_) => {}
+            }
+        }
+        mock.assert();
+        assert_eq!(content, vec!["The", " water"]);
+        assert_eq!(stream.usage(), Some(TokenUsage::new(26, 2)));
+        assert!(stream.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn stream_reports_error_sent_part_way_through() {
+        let (client, mut server) = with_mocked_client().await;
+        let body: String = format!(
+            "{}\n{}\n",
+            r#"{"model":"llama3","message":{"role":"assistant","content":"The"},"done":false}"#,
+            r#"{"error":"an unexpected error occurred"}"#
+        );
+        let mock = with_mocked_request(&mut server, 200, &body).create();
+        let mut stream = client.invoke_stream(messages()).await.unwrap();
+        assert!(stream.next().await.unwrap().is_ok());
+        let error = stream.next().await.unwrap().unwrap_err();
+        mock.assert();
+        assert_eq!(
+            error,
+            OllamaError::ErrorReadingStream("an unexpected error occurred".into())
+        );
+        assert!(stream.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn stream_rejected_request_maps_status_code() {
+        let (client, mut server) = with_mocked_client().await;
+        let mock = with_mocked_request(&mut server, 404, ERROR_RESPONSE).create();
+        let error = client.invoke_stream(messages()).await.err().unwrap();
+        mock.assert();
+        assert!(matches!(error.kind(), OllamaError::CODE404(_)));
+    }
+
+    fn messages() -> Vec<PromptMessage> {
+        vec![
+            PromptMessage::SystemMessage("You are a helpful assistant".into()),
+            PromptMessage::HumanMessage("How does the water flow".into()),
+            PromptMessage::AIMessage("Downhill".into()),
+        ]
+    }
+
+    fn with_mocked_request(
+        server: &mut ServerGuard,
+        status_code: usize,
+        response_body: &str,
+    ) -> Mock {
+        server
+            .mock("POST", OLLAMA_CHAT_PATH)
+            .with_status(status_code)
+            .with_header("content-type", "application/x-ndjson")
+            .with_body(response_body)
+    }
+
+    // This methods returns a client which is pointing at the mocked url
+    // and the mock server which we can orchestrate the stubbings on.
+    async fn with_mocked_client() -> (OllamaChatCompletionClient, ServerGuard) {
+        let server = Server::new_async().await;
+        let client = OllamaChatCompletionClient::new("llama3").with_base_url(&server.url());
+        (client, server)
+    }
+}
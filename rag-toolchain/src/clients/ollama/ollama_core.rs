@@ -0,0 +1,290 @@
+use crate::clients::ollama::model::errors::{OllamaError, OllamaErrorBody};
+use crate::clients::RequestContext;
+
+use reqwest::header::{HeaderValue, CONTENT_TYPE};
+use reqwest::{Client, RequestBuilder, Response, StatusCode};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::time::Instant;
+
+/// The address Ollama listens on when it is run with its default settings
+pub const DEFAULT_OLLAMA_BASE_URL: &str = "http://localhost:11434";
+
+#[derive(Debug)]
+pub struct OllamaHttpClient {
+    client: Client,
+    base_url: String,
+}
+
+impl OllamaHttpClient {
+    /// # [`OllamaHttpClient::new`]
+    /// Ollama does not authenticate requests so no API key is needed.
+    ///
+    /// # Arguments
+    /// * `base_url` - The address of the Ollama server e.g. [`DEFAULT_OLLAMA_BASE_URL`]
+    ///
+    /// # Returns
+    /// * [`OllamaHttpClient`] - The newly created OllamaHttpClient
+    pub fn new(base_url: &str) -> OllamaHttpClient {
+        OllamaHttpClient {
+            client: Client::new(),
+            base_url: base_url.trim_end_matches('/').to_string(),
+        }
+    }
+
+    /// # [`OllamaHttpClient::url`]
+    ///
+    /// # Arguments
+    /// * `path` - The path of the endpoint e.g. `/api/chat`
+    ///
+    /// # Returns
+    /// * [`String`] - The full url of the endpoint on the Ollama server
+    pub fn url(&self, path: &str) -> String {
+        format!("{}{}", self.base_url, path)
+    }
+
+    /// # [`OllamaHttpClient::send_request`]
+    /// Sends a request to Ollama and returns the deserialized response.
+    ///
+    /// # Arguments
+    /// * `body` - The body of the request
+    /// * `url` - The url to send the request to
+    ///
+    /// # Errors
+    /// * [`OllamaError::Request`] - wraps any of the errors below with the [`RequestContext`]
+    /// * [`OllamaError::ErrorSendingRequest`] - if request.send() errors
+    /// * [`OllamaError::ErrorGettingResponseBody`] - if response.text() errors
+    /// * [`OllamaError::ErrorDeserializingResponseBody`] - if serde_json::from_str() errors
+    /// * [`OllamaError`] - if the response code is not 200 this can be any of the associated status
+    ///   code errors or [`OllamaError::Undefined`]
+    ///
+    /// # Returns
+    /// [`U`] - The deserialized response from Ollama
+    pub async fn send_request<T, U>(&self, body: T, url: &str) -> Result<U, OllamaError>
+    where
+        T: Serialize,
+        U: DeserializeOwned,
+    {
+        let started: Instant = Instant::now();
+        let result: Result<U, OllamaError> = async {
+            let response: Response = self.execute(self.build_request(&body, url)).await?;
+            let status_code: StatusCode = response.status();
+            let response_body: String = response
+                .text()
+                .await
+                .map_err(|error| OllamaError::ErrorGettingResponseBody(error.to_string()))?;
+            serde_json::from_str(&response_body).map_err(|error| {
+                OllamaError::ErrorDeserializingResponseBody(status_code.as_u16(), error.to_string())
+            })
+        }
+        .await;
+        result.map_err(|error| {
+            error.with_context(RequestContext::new(url, &body, started.elapsed(), 1))
+        })
+    }
+
+    /// # [`OllamaHttpClient::send_stream_request`]
+    /// Sends a request to Ollama and returns the response without reading the body,
+    /// Ollama streams newline delimited JSON which is read as it arrives.
+    ///
+    /// # Arguments
+    /// * `body` - The body of the request, this should have stream set to true
+    /// * `url` - The url to send the request to
+    ///
+    /// # Errors
+    /// * [`OllamaError::Request`] - wraps any of the errors below with the [`RequestContext`]
+    /// * [`OllamaError::ErrorSendingRequest`] - if request.send() errors
+    /// * [`OllamaError`] - if the response code is not 200 this can be any of the associated status
+    ///   code errors or [`OllamaError::Undefined`]
+    ///
+    /// # Returns
+    /// [`Response`] - The response whose body is the stream of JSON lines
+    pub async fn send_stream_request<T>(&self, body: T, url: &str) -> Result<Response, OllamaError>
+    where
+        T: Serialize,
+    {
+        let started: Instant = Instant::now();
+        self.execute(self.build_request(&body, url))
+            .await
+            .map_err(|error| {
+                error.with_context(RequestContext::new(url, &body, started.elapsed(), 1))
+            })
+    }
+
+    /// # [`OllamaHttpClient::execute`]
+    ///
+    /// Sends the request, mapping any error status codes.
+    async fn execute(&self, request: RequestBuilder) -> Result<Response, OllamaError> {
+        let response: Response = request
+            .send()
+            .await
+            .map_err(|error| OllamaError::ErrorSendingRequest(error.to_string()))?;
+        if !response.status().is_success() {
+            return Err(Self::handle_error_response(response).await);
+        }
+        Ok(response)
+    }
+
+    /// # [`OllamaHttpClient::build_request`]
+    ///
+    /// Helper method to build a request with the correct headers and body
+    fn build_request<T>(&self, request_body: &T, url: &str) -> RequestBuilder
+    where
+        T: Serialize,
+    {
+        let content_type = HeaderValue::from_static("application/json");
+        self.client
+            .post(url)
+            .header(CONTENT_TYPE, content_type)
+            .json(&request_body)
+    }
+
+    /// # [`OllamaHttpClient::handle_error_response`]
+    ///
+    /// Explicit error mapping between response codes and error types
+    ///
+    /// # Arguments
+    /// `response` - The reqwest response from Ollama
+    ///
+    /// # Returns
+    /// [`OllamaError`] - The error type that maps to the response code
+    async fn handle_error_response(response: Response) -> OllamaError {
+        let status_code = response.status().as_u16();
+        let body_text = match response.text().await {
+            Ok(text) => text,
+            Err(e) => return OllamaError::Undefined(status_code, e.to_string()),
+        };
+
+        let error_body: OllamaErrorBody = match serde_json::from_str(&body_text) {
+            Ok(error_body) => error_body,
+            Err(e) => {
+                return OllamaError::ErrorDeserializingResponseBody(status_code, e.to_string())
+            }
+        };
+        match status_code {
+            400 => OllamaError::CODE400(error_body),
+            404 => OllamaError::CODE404(error_body),
+            500 => OllamaError::CODE500(error_body),
+            undefined => OllamaError::Undefined(undefined, body_text),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mockito::{Mock, Server, ServerGuard};
+    use serde::{Deserialize, Serialize};
+
+    const ERROR_RESPONSE: &str = r#"{"error": "model \"llama3\" not found, try pulling it first"}"#;
+
+    #[test]
+    fn trailing_slash_is_trimmed_from_base_url() {
+        let client = OllamaHttpClient::new("http://localhost:11434/");
+        assert_eq!(client.url("/api/chat"), "http://localhost:11434/api/chat");
+    }
+
+    #[tokio::test]
+    async fn status_400_maps_correctly() {
+        let expected_error_body = serde_json::from_str(ERROR_RESPONSE).unwrap();
+        let expected_error = OllamaError::CODE400(expected_error_body);
+        assert_status_mapping(400, expected_error).await;
+    }
+
+    #[tokio::test]
+    async fn status_404_maps_correctly() {
+        let expected_error_body = serde_json::from_str(ERROR_RESPONSE).unwrap();
+        let expected_error = OllamaError::CODE404(expected_error_body);
+        assert_status_mapping(404, expected_error).await;
+    }
+
+    #[tokio::test]
+    async fn status_500_maps_correctly() {
+        let expected_error_body = serde_json::from_str(ERROR_RESPONSE).unwrap();
+        let expected_error = OllamaError::CODE500(expected_error_body);
+        assert_status_mapping(500, expected_error).await;
+    }
+
+    #[tokio::test]
+    async fn undefined_maps_correctly() {
+        let expected_error = OllamaError::Undefined(469, ERROR_RESPONSE.into());
+        assert_status_mapping(469, expected_error).await;
+    }
+
+    #[tokio::test]
+    async fn error_deserializing_response_body_maps_correctly() {
+        let status_code: u16 = 200;
+        let (client, mut server) = with_mocked_client().await;
+        let mock = with_mocked_request(&mut server, status_code.into(), "some invalid response");
+        let error = client
+            .send_request::<RequestBody, RequestBody>(request_body(), &server.url())
+            .await
+            .unwrap_err();
+        // The error here is the message from serde
+        let expected_error = OllamaError::ErrorDeserializingResponseBody(
+            status_code,
+            "expected value at line 1 column 1".into(),
+        );
+        mock.assert();
+        assert_eq!(&expected_error, error.kind());
+    }
+
+    #[tokio::test]
+    async fn errors_carry_the_request_context() {
+        let (client, mut server) = with_mocked_client().await;
+        let mock = with_mocked_request(&mut server, 404, ERROR_RESPONSE);
+        let error = client
+            .send_request::<RequestBody, RequestBody>(request_body(), &client.url("/api/chat"))
+            .await
+            .unwrap_err();
+        mock.assert();
+        let context: &RequestContext = error.context().unwrap();
+        assert_eq!(context.endpoint, "/api/chat");
+        assert_eq!(context.model.as_deref(), Some("llama3"));
+        assert_eq!(context.attempt, 1);
+    }
+
+    // Helper method to assert all known status codes are mapped correctly
+    async fn assert_status_mapping(status_code: usize, expected_error: OllamaError) {
+        let (client, mut server) = with_mocked_client().await;
+        let mock = with_mocked_request(&mut server, status_code, ERROR_RESPONSE);
+        let error: OllamaError = client
+            .send_request::<RequestBody, RequestBody>(request_body(), &server.url())
+            .await
+            .unwrap_err();
+        mock.assert();
+        assert_eq!(&expected_error, error.kind());
+    }
+
+    fn with_mocked_request(
+        server: &mut ServerGuard,
+        status_code: usize,
+        response_body: &str,
+    ) -> Mock {
+        server
+            .mock("POST", mockito::Matcher::Any)
+            .with_status(status_code)
+            .with_header("content-type", "application/json")
+            .with_body(response_body)
+            .create()
+    }
+
+    // This methods returns a client which is pointing at the mocked url
+    // and the mock server which we can orchestrate the stubbings on.
+    async fn with_mocked_client() -> (OllamaHttpClient, ServerGuard) {
+        let server = Server::new_async().await;
+        let client = OllamaHttpClient::new(&server.url());
+        (client, server)
+    }
+
+    fn request_body() -> RequestBody {
+        RequestBody {
+            model: "llama3".into(),
+        }
+    }
+
+    #[derive(Debug, Serialize, Deserialize)]
+    struct RequestBody {
+        model: String,
+    }
+}
@@ -0,0 +1,386 @@
+use crate::clients::ollama::model::embeddings::{EmbedRequest, EmbedResponse};
+use crate::clients::ollama::model::errors::OllamaError;
+use crate::clients::ollama::ollama_core::{OllamaHttpClient, DEFAULT_OLLAMA_BASE_URL};
+use crate::clients::traits::AsyncEmbeddingClient;
+use crate::clients::{EmbeddingTaskType, TaskPrefixes};
+use crate::common::{
+    Chunk, Chunks, Embedding, EmbeddingModel, EmbeddingModelMetadata, OpenAITokenizer,
+};
+use std::num::NonZeroUsize;
+use tiktoken_rs::tokenizer::Tokenizer;
+
+const OLLAMA_EMBED_PATH: &str = "/api/embed";
+/// The context length Ollama loads models with unless num_ctx is set
+pub const DEFAULT_OLLAMA_MAX_TOKENS: usize = 2048;
+
+/// # [`OllamaEmbeddingClient`]
+/// Allows for generating embeddings with models served locally by Ollama.
+/// Ollama does not report the size of a model's embeddings up front so they
+/// are declared when the client is created, these are what the client reports
+/// through [`EmbeddingModel::metadata`] so a store created with `&client` sizes
+/// its vectors to match.
+///
+/// # Examples
+/// ```
+/// use std::num::NonZeroUsize;
+/// use rag_toolchain::common::*;
+/// use rag_toolchain::clients::*;
+/// async fn generate_embedding() {
+///     let dimensions: NonZeroUsize = NonZeroUsize::new(768).unwrap();
+///     let client: OllamaEmbeddingClient = OllamaEmbeddingClient::new("nomic-embed-text", dimensions);
+///     let chunk: Chunk = Chunk::new("this would be the text you are embedding");
+///     let embedding: Embedding = client.generate_embedding(chunk).await.unwrap();
+///     // This would be the vector representation of the text
+///     let vector: Vec<f32> = embedding.vector();
+/// }
+/// ```
+pub struct OllamaEmbeddingClient {
+    url: String,
+    client: OllamaHttpClient,
+    model: String,
+    dimensions: NonZeroUsize,
+    max_tokens: usize,
+    task_prefixes: TaskPrefixes,
+}
+
+impl OllamaEmbeddingClient {
+    /// # [`OllamaEmbeddingClient::new`]
+    /// Creates a client for a model on an Ollama server at [`DEFAULT_OLLAMA_BASE_URL`],
+    /// use [`OllamaEmbeddingClient::with_base_url`] to point it elsewhere.
+    ///
+    /// # Arguments
+    /// * `model`: impl [`Into<String>`] - The name of the model in Ollama e.g. `nomic-embed-text`
+    /// * `dimensions`: [`NonZeroUsize`] - The number of dimensions the model's embeddings have
+    ///
+    /// # Returns
+    /// * [`OllamaEmbeddingClient`] - The newly created OllamaEmbeddingClient
+    pub fn new(model: impl Into<String>, dimensions: NonZeroUsize) -> OllamaEmbeddingClient {
+        OllamaEmbeddingClient {
+            url: format!("{}{}", DEFAULT_OLLAMA_BASE_URL, OLLAMA_EMBED_PATH),
+            client: OllamaHttpClient::new(DEFAULT_OLLAMA_BASE_URL),
+            model: model.into(),
+            dimensions,
+            max_tokens: DEFAULT_OLLAMA_MAX_TOKENS,
+            task_prefixes: TaskPrefixes::default(),
+        }
+    }
+
+    /// # [`OllamaEmbeddingClient::with_base_url`]
+    /// Points the client at an Ollama server other than [`DEFAULT_OLLAMA_BASE_URL`].
+    ///
+    /// # Arguments
+    /// * `base_url`: &[`str`] - The address of the Ollama server e.g. `http://gpu-box:11434`
+    ///
+    /// # Returns
+    /// * [`OllamaEmbeddingClient`] - The client sending requests to the new server
+    pub fn with_base_url(mut self, base_url: &str) -> Self {
+        self.client = OllamaHttpClient::new(base_url);
+        self.url = self.client.url(OLLAMA_EMBED_PATH);
+        self
+    }
+
+    /// # [`OllamaEmbeddingClient::with_max_tokens`]
+    /// The most tokens reported through [`EmbeddingModel::metadata`], chunkers use this
+    /// to size chunks for the model. Ollama truncates longer input to the model's context.
+    /// Defaults to [`DEFAULT_OLLAMA_MAX_TOKENS`].
+    ///
+    /// # Arguments
+    /// * `max_tokens`: [`usize`] - The most tokens the model embeds
+    ///
+    /// # Returns
+    /// * [`OllamaEmbeddingClient`] - The client with the max tokens set
+    pub fn with_max_tokens(mut self, max_tokens: usize) -> Self {
+        self.max_tokens = max_tokens;
+        self
+    }
+
+    /// # [`OllamaEmbeddingClient::with_task_prefix`]
+    /// Puts the prefix in front of the text embedded for the task through
+    /// [`AsyncEmbeddingClient::generate_embedding_for`], for models such as e5 which expect
+    /// `query: ` and `passage: `. The embeddings returned hold the chunks without the prefix
+    /// and the plain [`AsyncEmbeddingClient::generate_embedding`] never adds one.
+    ///
+    /// # Arguments
+    /// * `task`: [`EmbeddingTaskType`] - The task the prefix is for
+    /// * `prefix`: impl [`Into<String>`] - Put in front of the text embedded for the task
+    ///
+    /// # Returns
+    /// * [`OllamaEmbeddingClient`] - The client with the prefix set
+    pub fn with_task_prefix(mut self, task: EmbeddingTaskType, prefix: impl Into<String>) -> Self {
+        self.task_prefixes.set(task, prefix.into());
+        self
+    }
+
+    /// # [`OllamaEmbeddingClient::handle_embedding_success_response`]
+    /// Takes a successful response and pairs each embedding with the chunk it was
+    /// generated for, checking each has the dimensions the client was created with.
+    ///
+    /// # Arguments
+    /// * `input_text`: [`Chunks`] - The input text that was sent to Ollama
+    /// * `response`: [`EmbedResponse`] - The deserialized response from Ollama
+    ///
+    /// # Errors
+    /// * [`OllamaError::DimensionsMismatch`] - If an embedding is not of the declared dimensions.
+    ///
+    /// # Returns
+    /// [`Vec<Embedding>`] - A vector of string embedding pairs the can be stored
+    fn handle_embedding_success_response(
+        &self,
+        input_text: Chunks,
+        response: EmbedResponse,
+    ) -> Result<Vec<Embedding>, OllamaError> {
+        let expected: usize = self.dimensions.get();
+        if let Some(vector) = response.embeddings.iter().find(|v| v.len() != expected) {
+            return Err(OllamaError::DimensionsMismatch {
+                expected,
+                actual: vector.len(),
+            });
+        }
+        Ok(response
+            .embeddings
+            .into_iter()
+            .zip(input_text)
+            .map(|(vector, chunk)| Embedding::new(chunk, vector))
+            .collect())
+    }
+}
+
+impl AsyncEmbeddingClient for OllamaEmbeddingClient {
+    type ErrorType = OllamaError;
+
+    /// # [`OllamaEmbeddingClient::generate_embeddings`]
+    /// Function to generate embeddings for [`Chunks`] in a single request.
+    ///
+    /// # Arguments
+    /// * `text`: [`Chunks`] - The text chunks/strings to generate an embeddings for.
+    ///
+    /// # Errors
+    /// * [`OllamaError`] - If the request to Ollama fails.
+    /// * [`OllamaError::DimensionsMismatch`] - If the model's embeddings are not of the declared dimensions.
+    ///
+    /// # Returns
+    /// * [`Vec<Embedding>`] - pairs of the original text and the embedding that was generated.
+    async fn generate_embeddings(&self, text: Chunks) -> Result<Vec<Embedding>, OllamaError> {
+        if text.is_empty() {
+            return Ok(Vec::new());
+        }
+        let request_body = EmbedRequest {
+            model: self.model.clone(),
+            input: text
+                .iter()
+                .map(|chunk| chunk.content().to_string())
+                .collect(),
+        };
+        let response: EmbedResponse = self.client.send_request(request_body, &self.url).await?;
+        self.handle_embedding_success_response(text, response)
+    }
+
+    /// # [`OllamaEmbeddingClient::generate_embedding`]
+    /// Function to generate an embedding for a [`Chunk`].
+    ///
+    /// # Arguments
+    /// * `text`: [`Chunk`] - The text chunk/string to generate an embedding for.
+    ///
+    /// # Errors
+    /// * [`OllamaError`] - If the request to Ollama fails.
+    /// * [`OllamaError::DimensionsMismatch`] - If the model's embeddings are not of the declared dimensions.
+    ///
+    /// # Returns
+    /// * [`Embedding`] - the generated embedding
+    async fn generate_embedding(&self, text: Chunk) -> Result<Embedding, Self::ErrorType> {
+        let mut embeddings: Vec<Embedding> = self.generate_embeddings(vec![text]).await?;
+        embeddings
+            .pop()
+            .ok_or_else(|| OllamaError::Undefined(200, "Ollama returned no embeddings".to_string()))
+    }
+
+    /// # [`OllamaEmbeddingClient::generate_embedding_for`]
+    /// Embeds the chunk with the prefix set for the task, see [`OllamaEmbeddingClient::with_task_prefix`].
+    ///
+    /// # Errors
+    /// * [`OllamaError`] - If the request fails.
+    async fn generate_embedding_for(
+        &self,
+        text: Chunk,
+        task: EmbeddingTaskType,
+    ) -> Result<Embedding, Self::ErrorType> {
+        match self.task_prefixes.apply(std::slice::from_ref(&text), task) {
+            None => self.generate_embedding(text).await,
+            Some(mut prefixed) => {
+                let embedding: Embedding = self.generate_embedding(prefixed.remove(0)).await?;
+                Ok(Embedding::new(text, embedding.vector_slice()))
+            }
+        }
+    }
+
+    /// # [`OllamaEmbeddingClient::generate_embeddings_for`]
+    /// Embeds the chunks with the prefix set for the task, see [`OllamaEmbeddingClient::with_task_prefix`].
+    ///
+    /// # Errors
+    /// * [`OllamaError`] - If a request fails.
+    async fn generate_embeddings_for(
+        &self,
+        text: Chunks,
+        task: EmbeddingTaskType,
+    ) -> Result<Vec<Embedding>, Self::ErrorType> {
+        match self.task_prefixes.apply(&text, task) {
+            None => self.generate_embeddings(text).await,
+            Some(prefixed) => Ok(TaskPrefixes::restore(
+                self.generate_embeddings(prefixed).await?,
+                text,
+            )),
+        }
+    }
+
+    /// # [`OllamaEmbeddingClient::dimensions`]
+    ///
+    /// # Returns
+    /// * [`Option<usize>`] - the dimensions the client was created with.
+    fn dimensions(&self) -> Option<usize> {
+        Some(self.dimensions.get())
+    }
+}
+
+/// The dimensions and max tokens the client was configured with. Ollama models
+/// use a variety of tokenizers which are not available offline, so tokens are
+/// estimated with cl100k which is close enough for sizing chunks.
+impl EmbeddingModel for OllamaEmbeddingClient {
+    fn metadata(&self) -> EmbeddingModelMetadata {
+        EmbeddingModelMetadata {
+            name: self.model.clone(),
+            dimensions: self.dimensions.get(),
+            max_tokens: self.max_tokens,
+            tokenizer: Box::new(OpenAITokenizer::new(Tokenizer::Cl100kBase)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mockito::{Matcher, Mock, Server, ServerGuard};
+    use serde_json::json;
+
+    const EMBED_RESPONSE: &str = r#"
+    {
+        "model": "nomic-embed-text",
+        "embeddings": [
+            [0.010071029, -0.0017594862, 0.05007221, 0.04692972],
+            [-0.009852957, 0.0401542, -0.02187325, 0.03150239]
+        ],
+        "total_duration": 14143917,
+        "load_duration": 1019500,
+        "prompt_eval_count": 8
+    }
+    "#;
+
+    #[tokio::test]
+    async fn generate_embeddings_pairs_vectors_with_chunks() {
+        let (client, mut server) = with_mocked_client(4).await;
+        let expected_body = json!({
+            "model": "nomic-embed-text",
+            "input": ["Test-0", "Test-1"]
+        });
+        let mock = with_mocked_request(&mut server, 200, EMBED_RESPONSE)
+            .match_body(Matcher::Json(expected_body))
+            .create();
+        let chunks: Chunks = vec![Chunk::new("Test-0"), Chunk::new("Test-1")];
+        let embeddings = client.generate_embeddings(chunks).await.unwrap();
+        mock.assert();
+        assert_eq!(embeddings.len(), 2);
+        assert_eq!(*embeddings[0].chunk(), Chunk::new("Test-0"));
+        assert_eq!(
+            embeddings[1].vector(),
+            vec![-0.009852957, 0.0401542, -0.02187325, 0.03150239]
+        );
+    }
+
+    #[tokio::test]
+    async fn generate_embedding_returns_single_embedding() {
+        let (client, mut server) = with_mocked_client(4).await;
+        let response = r#"{"model": "nomic-embed-text", "embeddings": [[0.1, 0.2, 0.3, 0.4]]}"#;
+        let mock = with_mocked_request(&mut server, 200, response).create();
+        let embedding = client
+            .generate_embedding(Chunk::new("Test-0"))
+            .await
+            .unwrap();
+        mock.assert();
+        assert_eq!(*embedding.chunk(), Chunk::new("Test-0"));
+        assert_eq!(embedding.vector(), vec![0.1, 0.2, 0.3, 0.4]);
+    }
+
+    #[tokio::test]
+    async fn empty_input_is_never_sent() {
+        let (client, mut server) = with_mocked_client(4).await;
+        let mock = with_mocked_request(&mut server, 200, EMBED_RESPONSE)
+            .expect(0)
+            .create();
+        let embeddings = client.generate_embeddings(Vec::new()).await.unwrap();
+        mock.assert();
+        assert!(embeddings.is_empty());
+    }
+
+    #[tokio::test]
+    async fn mismatched_dimensions_return_error() {
+        let (client, mut server) = with_mocked_client(768).await;
+        let mock = with_mocked_request(&mut server, 200, EMBED_RESPONSE).create();
+        let chunks: Chunks = vec![Chunk::new("Test-0"), Chunk::new("Test-1")];
+        let error = client.generate_embeddings(chunks).await.unwrap_err();
+        mock.assert();
+        assert_eq!(
+            error,
+            OllamaError::DimensionsMismatch {
+                expected: 768,
+                actual: 4
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn missing_model_maps_to_404() {
+        let (client, mut server) = with_mocked_client(4).await;
+        let response = r#"{"error": "model \"nomic-embed-text\" not found, try pulling it first"}"#;
+        let mock = with_mocked_request(&mut server, 404, response).create();
+        let error = client
+            .generate_embedding(Chunk::new("Test-0"))
+            .await
+            .unwrap_err();
+        mock.assert();
+        assert!(matches!(error.kind(), OllamaError::CODE404(_)));
+    }
+
+    #[test]
+    fn metadata_reports_declared_dimensions() {
+        let client =
+            OllamaEmbeddingClient::new("mxbai-embed-large", NonZeroUsize::new(1024).unwrap())
+                .with_max_tokens(512);
+        let metadata: EmbeddingModelMetadata = client.metadata();
+        assert_eq!(metadata.name, "mxbai-embed-large");
+        assert_eq!(metadata.dimensions, 1024);
+        assert_eq!(metadata.max_tokens, 512);
+        assert!(metadata.tokenizer.tokenize("hello world").is_some());
+        assert_eq!(client.dimensions(), Some(1024));
+    }
+
+    fn with_mocked_request(
+        server: &mut ServerGuard,
+        status_code: usize,
+        response_body: &str,
+    ) -> Mock {
+        server
+            .mock("POST", OLLAMA_EMBED_PATH)
+            .with_status(status_code)
+            .with_header("content-type", "application/json")
+            .with_body(response_body)
+    }
+
+    // This methods returns a client which is pointing at the mocked url
+    // and the mock server which we can orchestrate the stubbings on.
+    async fn with_mocked_client(dimensions: usize) -> (OllamaEmbeddingClient, ServerGuard) {
+        let server = Server::new_async().await;
+        let client =
+            OllamaEmbeddingClient::new("nomic-embed-text", NonZeroUsize::new(dimensions).unwrap())
+                .with_base_url(&server.url());
+        (client, server)
+    }
+}
@@ -0,0 +1,173 @@
+use crate::clients::secrets::SecretString;
+use dotenv::dotenv;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use std::env;
+use std::env::VarError;
+
+/// # [`OpenAICompatible`]
+///
+/// Points the OpenAI clients at another provider with an OpenAI compatible API such as
+/// Groq, Together or a vLLM server, along with the API key and any headers it needs.
+/// The chat client sends requests to `{base_url}/chat/completions` and the embedding client
+/// to `{base_url}/embeddings`.
+///
+/// The key is sent as `Authorization: Bearer <key>` unless another header is set with
+/// [`OpenAICompatible::with_auth_header`].
+///
+/// # Examples
+/// ```
+/// use rag_toolchain::clients::*;
+///
+/// fn groq_client() -> OpenAIChatCompletionClient {
+///     let groq: OpenAICompatible =
+///         OpenAICompatible::try_from_env("https://api.groq.com/openai/v1", "GROQ_API_KEY")
+///             .unwrap();
+///     let model: OpenAIModel = "llama-3.1-8b-instant".parse().unwrap();
+///     OpenAIChatCompletionClient::new_compatible(model, &groq)
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct OpenAICompatible {
+    base_url: String,
+    api_key: ApiKeySource,
+    auth_header: Option<HeaderName>,
+    headers: HeaderMap,
+}
+
+/// Where the API key of an [`OpenAICompatible`] provider comes from
+#[derive(Debug, Clone)]
+pub(crate) enum ApiKeySource {
+    /// The key given by the caller
+    Value(SecretString),
+    /// The environment variable the key was read from along with its value, the
+    /// variable is read again once the cached key expires
+    Env(String, SecretString),
+}
+
+impl OpenAICompatible {
+    /// # [`OpenAICompatible::new`]
+    ///
+    /// # Arguments
+    /// * `base_url`: impl [`Into<String>`] - the url the API paths are appended to e.g.
+    ///   `https://api.groq.com/openai/v1`, a trailing slash is ignored.
+    /// * `api_key`: impl [`Into<String>`] - the API key for the provider.
+    ///
+    /// # Returns
+    /// * [`OpenAICompatible`] - the provider config.
+    pub fn new(base_url: impl Into<String>, api_key: impl Into<String>) -> Self {
+        Self::with_key_source(base_url, ApiKeySource::Value(SecretString::new(api_key)))
+    }
+
+    /// # [`OpenAICompatible::try_from_env`]
+    ///
+    /// Reads the API key from the given environment variable, for example GROQ_API_KEY,
+    /// instead of OPENAI_API_KEY. A `.env` file is loaded if there is one.
+    ///
+    /// # Arguments
+    /// * `base_url`: impl [`Into<String>`] - the url the API paths are appended to.
+    /// * `api_key_var`: &[`str`] - the environment variable holding the API key.
+    ///
+    /// # Errors
+    /// * [`VarError`] - if the environment variable is not set.
+    ///
+    /// # Returns
+    /// * [`OpenAICompatible`] - the provider config.
+    pub fn try_from_env(base_url: impl Into<String>, api_key_var: &str) -> Result<Self, VarError> {
+        dotenv().ok();
+        let api_key: String = env::var(api_key_var)?;
+        Ok(Self::with_key_source(
+            base_url,
+            ApiKeySource::Env(api_key_var.into(), SecretString::new(api_key)),
+        ))
+    }
+
+    fn with_key_source(base_url: impl Into<String>, api_key: ApiKeySource) -> Self {
+        OpenAICompatible {
+            base_url: base_url.into().trim_end_matches('/').into(),
+            api_key,
+            auth_header: None,
+            headers: HeaderMap::new(),
+        }
+    }
+
+    /// # [`OpenAICompatible::with_auth_header`]
+    ///
+    /// Sends the API key as the value of the given header rather than as a bearer token,
+    /// for providers which expect something like `x-api-key: <key>`.
+    ///
+    /// # Arguments
+    /// * `name`: [`HeaderName`] - the header the key is sent in.
+    ///
+    /// # Returns
+    /// * [`OpenAICompatible`] - the config sending the key in the header.
+    pub fn with_auth_header(mut self, name: HeaderName) -> Self {
+        self.auth_header = Some(name);
+        self
+    }
+
+    /// # [`OpenAICompatible::with_header`]
+    ///
+    /// Adds a header which is sent with every request, such as an organisation id.
+    ///
+    /// # Arguments
+    /// * `name`: [`HeaderName`] - the name of the header.
+    /// * `value`: [`HeaderValue`] - the value of the header.
+    ///
+    /// # Returns
+    /// * [`OpenAICompatible`] - the config sending the header.
+    pub fn with_header(mut self, name: HeaderName, value: HeaderValue) -> Self {
+        self.headers.insert(name, value);
+        self
+    }
+
+    /// # [`OpenAICompatible::base_url`]
+    ///
+    /// # Returns
+    /// * &[`str`] - the url the API paths are appended to, without a trailing slash.
+    pub fn base_url(&self) -> &str {
+        &self.base_url
+    }
+
+    /// # [`OpenAICompatible::url`]
+    ///
+    /// # Returns
+    /// * [`String`] - the url of the operation e.g. `chat/completions`.
+    pub(crate) fn url(&self, operation: &str) -> String {
+        format!("{}/{}", self.base_url, operation)
+    }
+
+    pub(crate) fn api_key(&self) -> &ApiKeySource {
+        &self.api_key
+    }
+
+    pub(crate) fn auth_header(&self) -> Option<&HeaderName> {
+        self.auth_header.as_ref()
+    }
+
+    pub(crate) fn headers(&self) -> &HeaderMap {
+        &self.headers
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn urls_are_joined_to_the_base_url() {
+        let config = OpenAICompatible::new("https://api.groq.com/openai/v1/", "key");
+        assert_eq!(config.base_url(), "https://api.groq.com/openai/v1");
+        assert_eq!(
+            config.url("chat/completions"),
+            "https://api.groq.com/openai/v1/chat/completions"
+        );
+    }
+
+    #[test]
+    fn missing_env_var_is_an_error() {
+        std::env::remove_var("COMPATIBLE_API_KEY_UNSET");
+        let error = OpenAICompatible::try_from_env("http://localhost", "COMPATIBLE_API_KEY_UNSET")
+            .unwrap_err();
+        assert_eq!(error, VarError::NotPresent);
+    }
+}
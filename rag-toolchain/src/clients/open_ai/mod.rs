@@ -1,19 +1,42 @@
-#[cfg(feature = "openai")]
+#[cfg(any(feature = "openai-embeddings", feature = "openai-chat"))]
+mod compatible;
+#[cfg(any(feature = "openai-embeddings", feature = "openai-chat"))]
 mod model;
-#[cfg(feature = "openai")]
+#[cfg(feature = "openai-chat")]
 mod open_ai_chat_completions;
-#[cfg(feature = "openai")]
+#[cfg(any(feature = "openai-embeddings", feature = "openai-chat"))]
 mod open_ai_core;
-#[cfg(feature = "openai")]
+#[cfg(feature = "openai-embeddings")]
 mod open_ai_embeddings;
+#[cfg(feature = "openai-chat")]
+mod open_ai_moderation;
 
-#[cfg(feature = "openai")]
-pub use self::model::{chat_completions::OpenAIModel, errors::OpenAIError};
+#[cfg(any(feature = "openai-embeddings", feature = "openai-chat"))]
+pub use self::compatible::OpenAICompatible;
 
-#[cfg(feature = "openai")]
-pub use self::open_ai_chat_completions::{
-    CompletionStreamValue, OpenAIChatCompletionClient, OpenAICompletionStream,
+#[cfg(any(feature = "openai-embeddings", feature = "openai-chat"))]
+pub use self::model::errors::OpenAIError;
+
+#[cfg(feature = "openai-chat")]
+pub use self::model::chat_completions::OpenAIModel;
+
+#[cfg(feature = "openai-chat")]
+pub use self::model::chat_options::ChatOptions;
+
+#[cfg(feature = "openai-chat")]
+pub use self::model::moderations::{
+    ModerationCategories, ModerationCategoryScores, ModerationResponse, ModerationResult,
+    OpenAIModerationModel,
 };
 
-#[cfg(feature = "openai")]
-pub use self::open_ai_embeddings::OpenAIEmbeddingClient;
+#[cfg(feature = "openai-chat")]
+pub use self::open_ai_moderation::OpenAIModerationClient;
+
+#[cfg(feature = "openai-chat")]
+pub use self::open_ai_chat_completions::OpenAIChatCompletionClient;
+
+#[cfg(feature = "openai-stream")]
+pub use self::open_ai_chat_completions::OpenAICompletionStream;
+
+#[cfg(feature = "openai-embeddings")]
+pub use self::open_ai_embeddings::{OpenAIEmbeddingClient, OpenAIEmbeddingConfigError};
@@ -1,8 +1,13 @@
 use serde::{Deserialize, Serialize};
 use serde_json::{Map, Value};
+use std::convert::Infallible;
+use std::fmt::{self, Display, Formatter};
+use std::str::FromStr;
+use tiktoken_rs::tokenizer::Tokenizer;
 use typed_builder::TypedBuilder;
 
-use crate::clients::types::PromptMessage;
+use crate::clients::types::{ContentPart, ImageSource, MessageBody, MessageMeta, PromptMessage};
+use crate::common::{OpenAITokenizer, TokenUsage, TokenizerWrapper};
 
 /// See <https://platform.openai.com/docs/api-reference/embeddings/create>
 #[derive(Debug, Serialize, Deserialize, PartialEq, Eq, TypedBuilder)]
@@ -12,10 +17,20 @@ pub struct ChatCompletionRequest {
     pub messages: Vec<ChatMessage>,
     pub stream: bool,
     #[builder(default, setter(strip_option))]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stream_options: Option<StreamOptions>,
+    #[builder(default, setter(strip_option))]
     #[serde(flatten)]
     pub additional_config: Option<Map<String, Value>>,
 }
 
+/// Options OpenAI only accepts on streamed requests
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone, Copy)]
+pub struct StreamOptions {
+    /// Sends a final chunk with the usage of the request and no choices
+    pub include_usage: bool,
+}
+
 #[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
 pub struct ChatCompletionResponse {
     pub id: String,
@@ -38,8 +53,11 @@ pub enum ChatMessageRole {
 
 /// # [`OpenAIModel`]
 ///
-/// A list of model's available to use in the OpenAI API.
-#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone, Copy)]
+/// A list of model's available to use in the OpenAI API. Any other model, such as a newer
+/// release or a fine-tuned model like `ft:gpt-4o-mini:org::id`, can be used with
+/// [`OpenAIModel::Custom`] which is sent exactly as given. Parsing a name with
+/// [`str::parse`] gives the listed model if there is one and a custom model otherwise.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
 #[serde(rename_all = "snake_case")]
 pub enum OpenAIModel {
     #[serde(rename = "gpt-4o-mini")]
@@ -52,39 +70,222 @@ pub enum OpenAIModel {
     Gpt4,
     #[serde(rename = "gpt-3.5-turbo")]
     Gpt3Point5Turbo,
+    #[serde(rename = "o1")]
+    O1,
+    #[serde(rename = "o1-mini")]
+    O1Mini,
+    #[serde(rename = "o3-mini")]
+    O3Mini,
+    #[serde(untagged)]
+    Custom(String),
+}
+
+impl OpenAIModel {
+    /// # [`OpenAIModel::as_str`]
+    ///
+    /// # Returns
+    /// * &[`str`] - the model name sent to OpenAI.
+    pub fn as_str(&self) -> &str {
+        match self {
+            OpenAIModel::Gpt4oMini => "gpt-4o-mini",
+            OpenAIModel::Gpt4o => "gpt-4o",
+            OpenAIModel::Gpt4Turbo => "gpt-4-turbo",
+            OpenAIModel::Gpt4 => "gpt-4",
+            OpenAIModel::Gpt3Point5Turbo => "gpt-3.5-turbo",
+            OpenAIModel::O1 => "o1",
+            OpenAIModel::O1Mini => "o1-mini",
+            OpenAIModel::O3Mini => "o3-mini",
+            OpenAIModel::Custom(name) => name,
+        }
+    }
+
+    /// # [`OpenAIModel::tokenizer`]
+    ///
+    /// The tokenizer the model counts its tokens with, the gpt-4o and o series models use
+    /// o200k and the older models cl100k. A custom model is assumed to be newer, or fine-tuned
+    /// from a newer model, so uses o200k. Building the tokenizer is slow so keep hold of it
+    /// rather than calling this for every count.
+    ///
+    /// # Returns
+    /// * [`Box<dyn TokenizerWrapper>`] - the tokenizer of the model.
+    pub fn tokenizer(&self) -> Box<dyn TokenizerWrapper> {
+        Box::new(OpenAITokenizer::new(self.tiktoken_tokenizer()))
+    }
+
+    /// The tiktoken encoding behind [`OpenAIModel::tokenizer`]
+    pub(crate) fn tiktoken_tokenizer(&self) -> Tokenizer {
+        match self {
+            OpenAIModel::Custom(_)
+            | OpenAIModel::Gpt4oMini
+            | OpenAIModel::Gpt4o
+            | OpenAIModel::O1
+            | OpenAIModel::O1Mini
+            | OpenAIModel::O3Mini => Tokenizer::O200kBase,
+            OpenAIModel::Gpt4Turbo | OpenAIModel::Gpt4 | OpenAIModel::Gpt3Point5Turbo => {
+                Tokenizer::Cl100kBase
+            }
+        }
+    }
+}
+
+impl Display for OpenAIModel {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for OpenAIModel {
+    type Err = Infallible;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        Ok(match name {
+            "gpt-4o-mini" => OpenAIModel::Gpt4oMini,
+            "gpt-4o" => OpenAIModel::Gpt4o,
+            "gpt-4-turbo" => OpenAIModel::Gpt4Turbo,
+            "gpt-4" => OpenAIModel::Gpt4,
+            "gpt-3.5-turbo" => OpenAIModel::Gpt3Point5Turbo,
+            "o1" => OpenAIModel::O1,
+            "o1-mini" => OpenAIModel::O1Mini,
+            "o3-mini" => OpenAIModel::O3Mini,
+            custom => OpenAIModel::Custom(custom.into()),
+        })
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
 pub struct ChatMessage {
     pub role: ChatMessageRole,
-    pub content: String,
+    pub content: ChatMessageContent,
+    /// The name of the speaker, taken from the [`crate::clients::MessageMeta`] of the message
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+}
+
+/// Text is sent as a plain string, a message with images as a list of parts
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+#[serde(untagged)]
+pub enum ChatMessageContent {
+    Text(String),
+    Parts(Vec<ChatContentPart>),
+}
+
+impl From<String> for ChatMessageContent {
+    fn from(text: String) -> Self {
+        ChatMessageContent::Text(text)
+    }
+}
+
+impl From<&str> for ChatMessageContent {
+    fn from(text: &str) -> Self {
+        ChatMessageContent::Text(text.into())
+    }
+}
+
+/// See <https://platform.openai.com/docs/guides/vision>
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+#[serde(rename_all = "snake_case", tag = "type")]
+pub enum ChatContentPart {
+    Text { text: String },
+    ImageUrl { image_url: ImageUrl },
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub struct ImageUrl {
+    /// Either a link to the image or a base64 data url
+    pub url: String,
+}
+
+impl From<ContentPart> for ChatContentPart {
+    fn from(part: ContentPart) -> Self {
+        match part {
+            ContentPart::Text(text) => ChatContentPart::Text { text },
+            ContentPart::Image(ImageSource::Url(url)) => ChatContentPart::ImageUrl {
+                image_url: ImageUrl { url },
+            },
+            ContentPart::Image(ImageSource::Base64 { media_type, data }) => {
+                ChatContentPart::ImageUrl {
+                    image_url: ImageUrl {
+                        url: format!("data:{};base64,{}", media_type, data),
+                    },
+                }
+            }
+        }
+    }
+}
+
+impl From<ChatContentPart> for ContentPart {
+    fn from(part: ChatContentPart) -> Self {
+        match part {
+            ChatContentPart::Text { text } => ContentPart::Text(text),
+            ChatContentPart::ImageUrl { image_url } => {
+                let base64 = image_url
+                    .url
+                    .strip_prefix("data:")
+                    .and_then(|url| url.split_once(";base64,"));
+                match base64 {
+                    Some((media_type, data)) => ContentPart::Image(ImageSource::Base64 {
+                        media_type: media_type.into(),
+                        data: data.into(),
+                    }),
+                    None => ContentPart::Image(ImageSource::Url(image_url.url)),
+                }
+            }
+        }
+    }
 }
 
 impl From<PromptMessage> for ChatMessage {
     fn from(prompt_message: PromptMessage) -> Self {
-        match prompt_message {
-            PromptMessage::SystemMessage(message) => ChatMessage {
-                role: ChatMessageRole::System,
-                content: message,
-            },
-            PromptMessage::HumanMessage(message) => ChatMessage {
-                role: ChatMessageRole::User,
-                content: message,
-            },
-            PromptMessage::AIMessage(message) => ChatMessage {
-                role: ChatMessageRole::Assistant,
-                content: message,
-            },
+        let (role, body): (ChatMessageRole, MessageBody) = match prompt_message {
+            PromptMessage::SystemMessage(body) => (ChatMessageRole::System, body),
+            PromptMessage::HumanMessage(body) => (ChatMessageRole::User, body),
+            PromptMessage::MultiModalHumanMessage(parts) => {
+                return ChatMessage {
+                    role: ChatMessageRole::User,
+                    content: ChatMessageContent::Parts(
+                        parts.into_iter().map(ChatContentPart::from).collect(),
+                    ),
+                    name: None,
+                }
+            }
+            PromptMessage::AIMessage(body) => (ChatMessageRole::Assistant, body),
+        };
+        ChatMessage {
+            role,
+            content: body.content.into(),
+            name: body.meta.and_then(|meta| meta.name),
         }
     }
 }
 
 impl From<ChatMessage> for PromptMessage {
     fn from(value: ChatMessage) -> Self {
+        let content: String = match value.content {
+            ChatMessageContent::Text(text) => text,
+            ChatMessageContent::Parts(parts) if value.role == ChatMessageRole::User => {
+                return PromptMessage::MultiModalHumanMessage(
+                    parts.into_iter().map(ContentPart::from).collect(),
+                );
+            }
+            // Only user messages can hold images so anything else is just text
+            ChatMessageContent::Parts(parts) => parts
+                .into_iter()
+                .filter_map(|part| match part {
+                    ChatContentPart::Text { text } => Some(text),
+                    ChatContentPart::ImageUrl { .. } => None,
+                })
+                .collect(),
+        };
+        let body: MessageBody = match value.name {
+            Some(name) => {
+                MessageBody::new(content).with_meta(MessageMeta::default().with_name(name))
+            }
+            None => MessageBody::new(content),
+        };
         match value.role {
-            ChatMessageRole::Assistant => PromptMessage::AIMessage(value.content),
-            ChatMessageRole::System => PromptMessage::SystemMessage(value.content),
-            ChatMessageRole::User => PromptMessage::HumanMessage(value.content),
+            ChatMessageRole::Assistant => PromptMessage::AIMessage(body),
+            ChatMessageRole::System => PromptMessage::SystemMessage(body),
+            ChatMessageRole::User => PromptMessage::HumanMessage(body),
         }
     }
 }
@@ -96,6 +297,12 @@ pub struct Usage {
     pub total_tokens: usize,
 }
 
+impl From<&Usage> for TokenUsage {
+    fn from(usage: &Usage) -> Self {
+        TokenUsage::new(usage.prompt_tokens, usage.completion_tokens)
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
 pub struct ChatCompletionChoices {
     pub index: usize,
@@ -105,6 +312,7 @@ pub struct ChatCompletionChoices {
     pub finish_reason: String,
 }
 
+#[cfg(feature = "openai-stream")]
 #[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
 pub struct ChatCompletionStreamedResponse {
     pub id: String,
@@ -114,8 +322,12 @@ pub struct ChatCompletionStreamedResponse {
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub system_fingerprint: Option<String>,
     pub choices: Vec<ChatCompletionStreamedChoices>,
+    /// Only sent on the final chunk when the usage was asked for, see [`StreamOptions`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub usage: Option<Usage>,
 }
 
+#[cfg(feature = "openai-stream")]
 #[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
 pub struct ChatCompletionStreamedChoices {
     pub index: usize,
@@ -126,6 +338,7 @@ pub struct ChatCompletionStreamedChoices {
     pub finish_reason: Option<String>,
 }
 
+#[cfg(feature = "openai-stream")]
 #[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
 pub struct ChatCompletionDelta {
     #[serde(default, skip_serializing_if = "Option::is_none")]
@@ -139,6 +352,7 @@ mod request_model_tests {
     use super::*;
     const CHAT_COMPLETION_REQUEST: &str = r#"{"model":"gpt-4","messages":[{"role":"system","content":"Hello,howareyou?"},{"role":"user","content":"I'mdoinggreat.Howaboutyou?"},{"role":"system","content":"I'mdoingwell.I'mgladtohearyou'redoingwell."}],"stream":false,"temerature":0.7}"#;
     const CHAT_COMPLETION_RESPONSE: &str = r#"{"id":"chatcmpl-123","object":"chat.completion","created":1677652288,"model":"gpt-4","system_fingerprint":"fp_44709d6fcb","choices":[{"index":0,"message":{"role":"assistant","content":"\n\nHello there, how may I assist you today?"},"logprobs":null,"finish_reason":"stop"}],"usage":{"prompt_tokens":9,"completion_tokens":12,"total_tokens":21}}"#;
+    #[cfg(feature = "openai-stream")]
     const CHAT_COMPLETION_STREAMING_RESPONSE: &str = r#"{
         "id": "chatcmpl-9BRO0Nnca1ZtfMkFc5tOpQNSJ2Eo0",
         "object": "chat.completion.chunk",
@@ -169,17 +383,21 @@ mod request_model_tests {
                 ChatMessage {
                     role: ChatMessageRole::System,
                     content: "Hello,howareyou?".into(),
+                    name: None,
                 },
                 ChatMessage {
                     role: ChatMessageRole::User,
                     content: "I'mdoinggreat.Howaboutyou?".into(),
+                    name: None,
                 },
                 ChatMessage {
                     role: ChatMessageRole::System,
                     content: "I'mdoingwell.I'mgladtohearyou'redoingwell.".into(),
+                    name: None,
                 },
             ],
             stream: false,
+            stream_options: None,
             additional_config: Some(additional_config),
         };
 
@@ -203,6 +421,7 @@ mod request_model_tests {
                 message: ChatMessage {
                     role: ChatMessageRole::Assistant,
                     content: "\n\nHello there, how may I assist you today?".into(),
+                    name: None,
                 },
                 logprobs: None,
                 finish_reason: "stop".into(),
@@ -216,6 +435,64 @@ mod request_model_tests {
         assert_eq!(expected_response, response)
     }
 
+    #[test]
+    fn test_multi_modal_message_serializes_as_parts() {
+        let message: ChatMessage = PromptMessage::MultiModalHumanMessage(vec![
+            ContentPart::Text("What is in this image?".into()),
+            ContentPart::Image(ImageSource::Url("https://example.com/a.png".into())),
+            ContentPart::Image(ImageSource::Base64 {
+                media_type: "image/png".into(),
+                data: "iVBORw0KGgo=".into(),
+            }),
+        ])
+        .into();
+        let expected = r#"{"role":"user","content":[{"type":"text","text":"What is in this image?"},{"type":"image_url","image_url":{"url":"https://example.com/a.png"}},{"type":"image_url","image_url":{"url":"data:image/png;base64,iVBORw0KGgo="}}]}"#;
+        assert_eq!(serde_json::to_string(&message).unwrap(), expected);
+
+        let round_trip: ChatMessage = serde_json::from_str(expected).unwrap();
+        assert_eq!(round_trip, message);
+        assert_eq!(
+            PromptMessage::from(round_trip),
+            PromptMessage::MultiModalHumanMessage(vec![
+                ContentPart::Text("What is in this image?".into()),
+                ContentPart::Image(ImageSource::Url("https://example.com/a.png".into())),
+                ContentPart::Image(ImageSource::Base64 {
+                    media_type: "image/png".into(),
+                    data: "iVBORw0KGgo=".into(),
+                }),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_message_name_is_sent_and_read_back() {
+        let meta = MessageMeta::default()
+            .with_name("alice")
+            .with_id("message-1");
+        let message: ChatMessage =
+            PromptMessage::HumanMessage(MessageBody::from("Hello").with_meta(meta)).into();
+        let expected = r#"{"role":"user","content":"Hello","name":"alice"}"#;
+        assert_eq!(serde_json::to_string(&message).unwrap(), expected);
+
+        let round_trip: ChatMessage = serde_json::from_str(expected).unwrap();
+        assert_eq!(
+            PromptMessage::from(round_trip),
+            PromptMessage::HumanMessage(
+                MessageBody::from("Hello").with_meta(MessageMeta::default().with_name("alice"))
+            )
+        );
+    }
+
+    #[test]
+    fn test_text_message_serializes_as_string() {
+        let message: ChatMessage = PromptMessage::HumanMessage("Hello".into()).into();
+        assert_eq!(
+            serde_json::to_string(&message).unwrap(),
+            r#"{"role":"user","content":"Hello"}"#
+        );
+    }
+
+    #[cfg(feature = "openai-stream")]
     #[test]
     fn test_chat_completions_streaming_response_deserializes() {
         let response: ChatCompletionStreamedResponse =
@@ -236,7 +513,50 @@ mod request_model_tests {
                 logprobs: None,
                 finish_reason: None,
             }],
+            usage: None,
         };
         assert_eq!(expected_response, response)
     }
+
+    #[test]
+    fn test_model_names_agree_between_serde_and_display() {
+        let models = [
+            OpenAIModel::Gpt4oMini,
+            OpenAIModel::Gpt4o,
+            OpenAIModel::Gpt4Turbo,
+            OpenAIModel::Gpt4,
+            OpenAIModel::Gpt3Point5Turbo,
+            OpenAIModel::O1,
+            OpenAIModel::O1Mini,
+            OpenAIModel::O3Mini,
+        ];
+        for model in models {
+            let name: Value = serde_json::to_value(&model).unwrap();
+            assert_eq!(name, Value::String(model.to_string()));
+            assert_eq!(serde_json::from_value::<OpenAIModel>(name).unwrap(), model);
+            assert_eq!(model.to_string().parse::<OpenAIModel>().unwrap(), model);
+        }
+    }
+
+    #[test]
+    fn test_custom_model_serializes_untagged() {
+        let model = OpenAIModel::Custom("ft:gpt-4o-mini:org::id".into());
+        assert_eq!(
+            serde_json::to_string(&model).unwrap(),
+            r#""ft:gpt-4o-mini:org::id""#
+        );
+        assert_eq!(
+            serde_json::from_str::<OpenAIModel>(r#""ft:gpt-4o-mini:org::id""#).unwrap(),
+            model
+        );
+        assert_eq!(model.to_string().parse::<OpenAIModel>().unwrap(), model);
+        // A listed model given as a custom name parses back to the listed model
+        assert_eq!(
+            OpenAIModel::Custom("gpt-4o".into())
+                .to_string()
+                .parse::<OpenAIModel>()
+                .unwrap(),
+            OpenAIModel::Gpt4o
+        );
+    }
 }
@@ -0,0 +1,166 @@
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use typed_builder::TypedBuilder;
+
+use crate::clients::deep_merge;
+
+/// # [`ChatOptions`]
+///
+/// The common inference parameters of an OpenAI chat completion as typed fields, so a typo is
+/// a compile error rather than an option OpenAI silently ignores. Options which are not set
+/// are not sent, leaving OpenAI's default. Set them on a client with
+/// [`crate::clients::OpenAIChatCompletionClient::with_chat_options`], options without a field
+/// here can still be sent through the additional config.
+///
+/// * `temperature` - from 0 to 2, higher makes the output more random.
+/// * `top_p` - only sample from the tokens making up this much of the probability mass.
+/// * `max_tokens` - the most tokens the completion may use.
+/// * `stop` - up to 4 sequences which end the completion when generated.
+/// * `presence_penalty` - from -2 to 2, positive values discourage tokens already present.
+/// * `frequency_penalty` - from -2 to 2, positive values discourage tokens by how often they appear.
+/// * `seed` - samples deterministically on a best effort basis.
+/// * `logit_bias` - from -100 to 100, added to the logits of the token ids before sampling.
+///
+/// # Examples
+/// ```
+/// use rag_toolchain::clients::*;
+///
+/// let options: ChatOptions = ChatOptions::builder()
+///     .temperature(0.2)
+///     .max_tokens(256)
+///     .stop(vec!["\n\n".to_string()])
+///     .build();
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, TypedBuilder)]
+#[serde(default)]
+pub struct ChatOptions {
+    #[builder(default, setter(strip_option))]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f64>,
+    #[builder(default, setter(strip_option))]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f64>,
+    #[builder(default, setter(strip_option))]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_tokens: Option<u32>,
+    #[builder(default)]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub stop: Vec<String>,
+    #[builder(default, setter(strip_option))]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub presence_penalty: Option<f64>,
+    #[builder(default, setter(strip_option))]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub frequency_penalty: Option<f64>,
+    #[builder(default, setter(strip_option))]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u64>,
+    /// Keyed by the token id as a string, as OpenAI expects
+    #[builder(default)]
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    pub logit_bias: HashMap<String, i32>,
+}
+
+impl ChatOptions {
+    /// # [`ChatOptions::merge_into`]
+    ///
+    /// Writes the options which are set into the config, replacing the same options if the
+    /// config already has them. The logit bias is merged so biases in the config for other
+    /// tokens are kept.
+    ///
+    /// # Arguments
+    /// * `config`: &mut [`Map<String, Value>`] - the additional config of a request.
+    pub(crate) fn merge_into(&self, config: &mut Map<String, Value>) {
+        if let Ok(Value::Object(options)) = serde_json::to_value(self) {
+            deep_merge(config, options);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn unset_options_are_not_serialized() {
+        assert_eq!(
+            serde_json::to_value(ChatOptions::default()).unwrap(),
+            json!({})
+        );
+        let options = ChatOptions::builder().max_tokens(64).build();
+        assert_eq!(
+            serde_json::to_value(options).unwrap(),
+            json!({"max_tokens": 64})
+        );
+    }
+
+    #[test]
+    fn every_option_round_trips_with_its_wire_name() {
+        let options = ChatOptions::builder()
+            .temperature(0.5)
+            .top_p(0.9)
+            .max_tokens(256)
+            .stop(vec!["END".into(), "\n\n".into()])
+            .presence_penalty(-0.5)
+            .frequency_penalty(1.5)
+            .seed(42)
+            .logit_bias(HashMap::from([("50256".to_string(), -100)]))
+            .build();
+        let json: Value = serde_json::to_value(&options).unwrap();
+        assert_eq!(
+            json,
+            json!({
+                "temperature": 0.5,
+                "top_p": 0.9,
+                "max_tokens": 256,
+                "stop": ["END", "\n\n"],
+                "presence_penalty": -0.5,
+                "frequency_penalty": 1.5,
+                "seed": 42,
+                "logit_bias": {"50256": -100}
+            })
+        );
+        let read_back: ChatOptions = serde_json::from_value(json).unwrap();
+        assert_eq!(read_back, options);
+    }
+
+    #[test]
+    fn missing_options_deserialize_as_unset() {
+        let options: ChatOptions = serde_json::from_value(json!({"seed": 7})).unwrap();
+        assert_eq!(options, ChatOptions::builder().seed(7).build());
+    }
+
+    #[test]
+    fn set_options_replace_the_same_keys_in_the_config() {
+        let mut config: Map<String, Value> = serde_json::from_value(json!({
+            "max_tokens": 10,
+            "temperature": 1.0,
+            "user": "abc"
+        }))
+        .unwrap();
+        ChatOptions::builder()
+            .max_tokens(500)
+            .build()
+            .merge_into(&mut config);
+        assert_eq!(
+            Value::Object(config),
+            json!({"max_tokens": 500, "temperature": 1.0, "user": "abc"})
+        );
+    }
+
+    #[test]
+    fn logit_bias_is_merged_with_the_config() {
+        let mut config: Map<String, Value> =
+            serde_json::from_value(json!({"logit_bias": {"1": 5, "2": 5}})).unwrap();
+        ChatOptions::builder()
+            .logit_bias(HashMap::from([("2".to_string(), -100)]))
+            .build()
+            .merge_into(&mut config);
+        assert_eq!(
+            Value::Object(config),
+            json!({"logit_bias": {"1": 5, "2": -100}})
+        );
+    }
+}
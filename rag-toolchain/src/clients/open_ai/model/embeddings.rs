@@ -1,4 +1,4 @@
-use crate::common::OpenAIEmbeddingModel;
+use crate::common::{OpenAIEmbeddingModel, TokenUsage};
 use serde::{Deserialize, Serialize};
 use typed_builder::TypedBuilder;
 
@@ -8,6 +8,9 @@ use typed_builder::TypedBuilder;
 pub struct BatchEmbeddingRequest {
     pub input: Vec<String>,
     pub model: OpenAIEmbeddingModel,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[builder(default)]
+    pub dimensions: Option<usize>,
     #[serde(rename = "encoding_format", skip_serializing_if = "Option::is_none")]
     #[builder(default, setter(strip_option))]
     pub encoding_format: Option<EncodingFormat>,
@@ -22,6 +25,9 @@ pub struct EmbeddingRequest {
     pub input: String,
     pub model: OpenAIEmbeddingModel,
     #[serde(skip_serializing_if = "Option::is_none")]
+    #[builder(default)]
+    pub dimensions: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     #[builder(default, setter(strip_option))]
     pub encoding_format: Option<EncodingFormat>,
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -53,6 +59,12 @@ pub struct Usage {
     pub total_tokens: usize,
 }
 
+impl From<&Usage> for TokenUsage {
+    fn from(usage: &Usage) -> Self {
+        TokenUsage::new(usage.prompt_tokens, 0)
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
 #[serde(rename_all = "snake_case")]
 pub enum EncodingFormat {
@@ -60,6 +72,125 @@ pub enum EncodingFormat {
     Base64,
 }
 
+/// # [`EmbeddingResponseParser`]
+///
+/// Parses an [`EmbeddingResponse`] from a body which arrives in chunks. Each element of the
+/// `data` array is deserialized as soon as it is complete, so only the embeddings parsed so
+/// far and the element currently being read are held rather than the whole body as text.
+/// Everything outside the `data` array is kept and parsed once the body has ended.
+#[derive(Debug, Default)]
+pub(crate) struct EmbeddingResponseParser {
+    /// The body with the elements of the data array left out
+    skeleton: Vec<u8>,
+    /// The bytes of the data element currently being read
+    element: Vec<u8>,
+    data: Vec<EmbeddingObject>,
+    depth: usize,
+    in_string: bool,
+    escaped: bool,
+    in_data: bool,
+    /// Where the last string in the top level object starts in the skeleton
+    key_start: usize,
+    last_key_is_data: bool,
+}
+
+impl EmbeddingResponseParser {
+    /// # [`EmbeddingResponseParser::push`]
+    ///
+    /// # Arguments
+    /// * `bytes`: &[`[u8]`] - the next chunk of the body.
+    ///
+    /// # Errors
+    /// * [`serde_json::Error`] - if the body is not a valid embedding response.
+    pub(crate) fn push(&mut self, bytes: &[u8]) -> Result<(), serde_json::Error> {
+        for &byte in bytes {
+            if self.in_string {
+                self.sink(byte)?;
+                if self.escaped {
+                    self.escaped = false;
+                } else if byte == b'\\' {
+                    self.escaped = true;
+                } else if byte == b'"' {
+                    self.in_string = false;
+                    if !self.in_data && self.depth == 1 {
+                        self.last_key_is_data = &self.skeleton[self.key_start..] == b"\"data\"";
+                    }
+                }
+                continue;
+            }
+            match byte {
+                b'"' => {
+                    self.in_string = true;
+                    if !self.in_data && self.depth == 1 {
+                        self.key_start = self.skeleton.len();
+                    }
+                    self.sink(byte)?;
+                }
+                b'{' | b'[' => {
+                    let opens_data: bool =
+                        !self.in_data && self.depth == 1 && byte == b'[' && self.last_key_is_data;
+                    self.depth += 1;
+                    if opens_data {
+                        self.in_data = true;
+                        self.skeleton.push(byte);
+                    } else {
+                        self.sink(byte)?;
+                    }
+                }
+                b'}' | b']' => {
+                    self.depth = self
+                        .depth
+                        .checked_sub(1)
+                        .ok_or_else(|| Self::invalid("unbalanced brackets"))?;
+                    match (self.in_data, self.depth) {
+                        (true, 2) => {
+                            self.element.push(byte);
+                            self.data.push(serde_json::from_slice(&self.element)?);
+                            self.element.clear();
+                        }
+                        (true, 1) => {
+                            self.in_data = false;
+                            self.skeleton.push(byte);
+                        }
+                        _ => self.sink(byte)?,
+                    }
+                }
+                _ => self.sink(byte)?,
+            }
+        }
+        Ok(())
+    }
+
+    /// # [`EmbeddingResponseParser::finish`]
+    ///
+    /// # Errors
+    /// * [`serde_json::Error`] - if the body ended early or is not a valid embedding response.
+    ///
+    /// # Returns
+    /// * [`EmbeddingResponse`] - the parsed response.
+    pub(crate) fn finish(self) -> Result<EmbeddingResponse, serde_json::Error> {
+        let mut response: EmbeddingResponse = serde_json::from_slice(&self.skeleton)?;
+        response.data = self.data;
+        Ok(response)
+    }
+
+    /// Writes a byte to the element being read, or to the skeleton outside the data array.
+    /// Between elements only separators are expected and they are dropped.
+    fn sink(&mut self, byte: u8) -> Result<(), serde_json::Error> {
+        match (self.in_data, self.depth) {
+            (false, _) => self.skeleton.push(byte),
+            (true, 3..) => self.element.push(byte),
+            (true, _) if byte == b',' || byte.is_ascii_whitespace() => {}
+            (true, _) => return Err(Self::invalid("data must be an array of objects")),
+        }
+        Ok(())
+    }
+
+    fn invalid(message: &str) -> serde_json::Error {
+        <serde_json::Error as serde::de::Error>::custom(message)
+    }
+}
+
 #[cfg(test)]
 mod request_model_tests {
 
@@ -161,6 +292,32 @@ mod request_model_tests {
         assert_eq!(serialized_batch_embedding_request, BATCH_EMBEDDING_REQUEST);
     }
 
+    #[test]
+    fn test_embedding_requests_serialize_dimensions_when_set() {
+        let embedding_request: EmbeddingRequest = EmbeddingRequest::builder()
+            .input("Your text string goes here".to_string())
+            .model(OpenAIEmbeddingModel::TextEmbedding3Large)
+            .dimensions(Some(1024))
+            .build();
+        let serialized_embedding_request = serde_json::to_string(&embedding_request).unwrap();
+        assert_eq!(
+            serialized_embedding_request,
+            r#"{"input":"Your text string goes here","model":"text-embedding-3-large","dimensions":1024}"#
+        );
+
+        let batch_embedding_request: BatchEmbeddingRequest = BatchEmbeddingRequest::builder()
+            .input(vec!["Your text string goes here".to_string()])
+            .model(OpenAIEmbeddingModel::TextEmbedding3Large)
+            .dimensions(Some(256))
+            .build();
+        let serialized_batch_embedding_request =
+            serde_json::to_string(&batch_embedding_request).unwrap();
+        assert_eq!(
+            serialized_batch_embedding_request,
+            r#"{"input":["Your text string goes here"],"model":"text-embedding-3-large","dimensions":256}"#
+        );
+    }
+
     const EMBEDDING_RESPONSE: &'static str = r#"{"data":[{"embedding":[-0.006929283495992422,-0.005336422007530928,-0.009327292,-0.024047505110502243],"index":0,"object":"embedding"}],"model":"text-embedding-ada-002","object":"list","usage":{"prompt_tokens":5,"total_tokens":5}}"#;
 
     #[test]
@@ -180,3 +337,42 @@ mod request_model_tests {
         assert_eq!(embedding_response.usage.total_tokens, 5);
     }
 }
+
+#[cfg(test)]
+mod response_parser_tests {
+    use super::*;
+
+    const RESPONSE: &str = r#"{
+        "object": "list",
+        "data": [
+            {"object": "embedding", "index": 0, "embedding": [0.5, -1.25e-3]},
+            {"object": "embedding", "index": 1, "embedding": [1, 2]}
+        ],
+        "model": "text-embedding-3-large \"data\" [",
+        "usage": {"prompt_tokens": 5, "total_tokens": 5}
+    }"#;
+
+    fn parse_in_chunks(body: &str, chunk_size: usize) -> Result<EmbeddingResponse, String> {
+        let mut parser = EmbeddingResponseParser::default();
+        for chunk in body.as_bytes().chunks(chunk_size) {
+            parser.push(chunk).map_err(|e| e.to_string())?;
+        }
+        parser.finish().map_err(|e| e.to_string())
+    }
+
+    #[test]
+    fn matches_parsing_the_whole_body_for_any_chunking() {
+        let expected: EmbeddingResponse = serde_json::from_str(RESPONSE).unwrap();
+        for chunk_size in [1, 2, 7, 64, RESPONSE.len()] {
+            assert_eq!(parse_in_chunks(RESPONSE, chunk_size).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_bodies() {
+        assert!(parse_in_chunks(&RESPONSE[..RESPONSE.len() / 2], 16).is_err());
+        assert!(parse_in_chunks(r#"{"data": [1, 2]}"#, 4).is_err());
+        assert!(parse_in_chunks(r#"{"data": []}]"#, 4).is_err());
+        assert!(parse_in_chunks(r#"{"data": [{"index": 0}]}"#, 4).is_err());
+    }
+}
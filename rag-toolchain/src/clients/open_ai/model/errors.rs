@@ -1,6 +1,10 @@
 use serde::Deserialize;
+use std::ops::Range;
 use thiserror::Error;
 
+use crate::clients::{RequestContext, SecretError};
+use crate::common::Embedding;
+
 // This is what is returned from OpenAI
 // when an error occurs
 #[derive(Debug, Deserialize, PartialEq, Clone)]
@@ -44,6 +48,9 @@ pub enum OpenAIError {
     Undefined(u16, String),
     #[error("Error sending request: {0}")]
     ErrorSendingRequest(String),
+    /// # The request or connection took longer than the timeout in the [`crate::clients::HttpConfig`]
+    #[error("Timeout: {0}")]
+    Timeout(String),
     /// # Carries underlying error
     #[error("Error getting response body: {0}")]
     ErrorGettingResponseBody(String),
@@ -53,11 +60,128 @@ pub enum OpenAIError {
     /// # Carries underlying error if something went wrong when reading from a stream
     #[error("Error reading stream: {0}")]
     ErrorReadingStream(String),
+    /// # The additional config sets an option the model does not support, the request was not sent.
+    #[error("Unsupported option: {option} is not supported by {model}")]
+    UnsupportedOption { model: String, option: String },
+    /// # The additional config sets an option the client sets itself for this call, the request was not sent.
+    #[error(
+        "Conflicting option: {0} is set by the client and can not also be in the additional config"
+    )]
+    ConflictingOption(String),
+    /// # The chunk at this index has no content, OpenAI rejects empty text so the request was not sent.
+    #[error("Empty chunk: chunk {0} has no content to embed")]
+    EmptyChunk(usize),
+    /// # The API key could not be fetched from the secret provider, the request was not sent.
+    #[error("Error fetching API key: {0}")]
+    ErrorFetchingApiKey(SecretError),
+    /// # An error from a request sent to OpenAI, with the context of the request
+    /// Use [`OpenAIError::kind`] to match on the underlying error.
+    #[error("{source} ({context})")]
+    Request {
+        context: RequestContext,
+        source: Box<OpenAIError>,
+    },
+    /// # One of the requests an embedding batch was split into failed
+    /// `batch` is the index of the failed request, `failed` is the range of chunks in it and
+    /// `completed` holds the embeddings of every chunk before it, in input order, so they do
+    /// not need to be generated again. Requests still in flight are cancelled.
+    /// Use [`OpenAIError::kind`] to match on the underlying error.
+    #[error("Embedding batch {batch} (chunks {}..{}) failed: {source}", failed.start, failed.end)]
+    BatchFailed {
+        batch: usize,
+        failed: Range<usize>,
+        completed: Vec<Embedding>,
+        source: Box<OpenAIError>,
+    },
+}
+
+impl OpenAIError {
+    /// # [`OpenAIError::kind`]
+    ///
+    /// # Returns
+    /// * &[`OpenAIError`] - the underlying error without any [`RequestContext`] or batch.
+    pub fn kind(&self) -> &OpenAIError {
+        match self {
+            OpenAIError::Request { source, .. } => source.kind(),
+            OpenAIError::BatchFailed { source, .. } => source.kind(),
+            error => error,
+        }
+    }
+
+    /// # [`OpenAIError::context`]
+    ///
+    /// # Returns
+    /// * [`Option<&RequestContext>`] - the context of the request the error came from,
+    ///   [`None`] if the error happened before a request was sent.
+    pub fn context(&self) -> Option<&RequestContext> {
+        match self {
+            OpenAIError::Request { context, .. } => Some(context),
+            OpenAIError::BatchFailed { source, .. } => source.context(),
+            _ => None,
+        }
+    }
+
+    /// # [`OpenAIError::from_reqwest`]
+    ///
+    /// Maps a reqwest error to [`OpenAIError::Timeout`] if it timed out, otherwise to the
+    /// error made by `otherwise`.
+    pub(crate) fn from_reqwest(error: reqwest::Error, otherwise: fn(String) -> Self) -> Self {
+        if error.is_timeout() {
+            return OpenAIError::Timeout(error.to_string());
+        }
+        otherwise(error.to_string())
+    }
+
+    /// # [`OpenAIError::with_context`]
+    ///
+    /// Attaches the context of the request to the error.
+    pub(crate) fn with_context(self, context: RequestContext) -> Self {
+        OpenAIError::Request {
+            context,
+            source: Box::new(self),
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::chains::RagChainError;
+    use std::time::Duration;
+
+    #[test]
+    fn request_context_is_shown_through_the_chain_error() {
+        let context = RequestContext {
+            endpoint: "/v1/chat/completions".into(),
+            model: Some("gpt-4o".into()),
+            elapsed: Duration::from_secs(2),
+            attempt: 1,
+        };
+        let error = OpenAIError::ErrorSendingRequest("connection reset".into())
+            .with_context(context.clone());
+        assert_eq!(error.context(), Some(&context));
+        assert_eq!(
+            error.kind(),
+            &OpenAIError::ErrorSendingRequest("connection reset".into())
+        );
+        let chain_error: RagChainError<OpenAIError, OpenAIError> =
+            RagChainError::ChatClientError(error);
+        assert_eq!(
+            chain_error.to_string(),
+            "Chat Client Error: Error sending request: connection reset \
+            (endpoint = /v1/chat/completions, model = gpt-4o, elapsed = 2s, attempt = 1)"
+        );
+    }
+
+    #[test]
+    fn errors_before_a_request_have_no_context() {
+        let error = OpenAIError::UnsupportedOption {
+            model: "o1-mini".into(),
+            option: "temperature".into(),
+        };
+        assert_eq!(error.context(), None);
+        assert_eq!(error.kind(), &error);
+    }
 
     #[test]
     fn test_code_400_display() {
@@ -1,3 +1,9 @@
+#[cfg(feature = "openai-chat")]
 pub mod chat_completions;
+#[cfg(feature = "openai-chat")]
+pub mod chat_options;
+#[cfg(feature = "openai-embeddings")]
 pub mod embeddings;
 pub mod errors;
+#[cfg(feature = "openai-chat")]
+pub mod moderations;
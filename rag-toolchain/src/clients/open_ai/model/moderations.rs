@@ -0,0 +1,168 @@
+use serde::{Deserialize, Serialize};
+use std::fmt::{self, Display, Formatter};
+
+/// See <https://platform.openai.com/docs/api-reference/moderations/create>
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub struct ModerationRequest {
+    pub model: OpenAIModerationModel,
+    pub input: String,
+}
+
+#[derive(Debug, Deserialize, PartialEq, Clone)]
+pub struct ModerationResponse {
+    pub id: String,
+    pub model: String,
+    pub results: Vec<ModerationResult>,
+}
+
+impl ModerationResponse {
+    /// # [`ModerationResponse::flagged`]
+    ///
+    /// # Returns
+    /// * [`bool`] - whether any of the inputs were flagged.
+    pub fn flagged(&self) -> bool {
+        self.results.iter().any(|result| result.flagged)
+    }
+
+    /// # [`ModerationResponse::flagged_categories`]
+    ///
+    /// # Returns
+    /// * [`Vec<String>`] - the categories any of the inputs were flagged for, without duplicates.
+    pub fn flagged_categories(&self) -> Vec<String> {
+        let mut categories: Vec<String> = Vec::new();
+        for category in self
+            .results
+            .iter()
+            .flat_map(|result| result.categories.flagged())
+        {
+            if !categories.iter().any(|seen| seen == category) {
+                categories.push(category.into());
+            }
+        }
+        categories
+    }
+}
+
+#[derive(Debug, Deserialize, PartialEq, Clone)]
+pub struct ModerationResult {
+    pub flagged: bool,
+    pub categories: ModerationCategories,
+    pub category_scores: ModerationCategoryScores,
+}
+
+/// Whether the input was flagged for each category, the names match those sent by OpenAI.
+/// The illicit categories are only returned by the omni moderation models.
+#[derive(Debug, Deserialize, PartialEq, Eq, Clone, Default)]
+pub struct ModerationCategories {
+    pub harassment: bool,
+    #[serde(rename = "harassment/threatening")]
+    pub harassment_threatening: bool,
+    pub hate: bool,
+    #[serde(rename = "hate/threatening")]
+    pub hate_threatening: bool,
+    #[serde(default)]
+    pub illicit: Option<bool>,
+    #[serde(rename = "illicit/violent", default)]
+    pub illicit_violent: Option<bool>,
+    #[serde(rename = "self-harm")]
+    pub self_harm: bool,
+    #[serde(rename = "self-harm/intent")]
+    pub self_harm_intent: bool,
+    #[serde(rename = "self-harm/instructions")]
+    pub self_harm_instructions: bool,
+    pub sexual: bool,
+    #[serde(rename = "sexual/minors")]
+    pub sexual_minors: bool,
+    pub violence: bool,
+    #[serde(rename = "violence/graphic")]
+    pub violence_graphic: bool,
+}
+
+impl ModerationCategories {
+    /// # [`ModerationCategories::flagged`]
+    ///
+    /// # Returns
+    /// * [`Vec<&str>`] - the OpenAI names of the categories which were flagged.
+    pub fn flagged(&self) -> Vec<&'static str> {
+        [
+            ("harassment", self.harassment),
+            ("harassment/threatening", self.harassment_threatening),
+            ("hate", self.hate),
+            ("hate/threatening", self.hate_threatening),
+            ("illicit", self.illicit.unwrap_or(false)),
+            ("illicit/violent", self.illicit_violent.unwrap_or(false)),
+            ("self-harm", self.self_harm),
+            ("self-harm/intent", self.self_harm_intent),
+            ("self-harm/instructions", self.self_harm_instructions),
+            ("sexual", self.sexual),
+            ("sexual/minors", self.sexual_minors),
+            ("violence", self.violence),
+            ("violence/graphic", self.violence_graphic),
+        ]
+        .into_iter()
+        .filter_map(|(name, flagged)| flagged.then_some(name))
+        .collect()
+    }
+}
+
+/// How confident the model is that the input belongs to each category, between 0 and 1.
+#[derive(Debug, Deserialize, PartialEq, Clone, Default)]
+pub struct ModerationCategoryScores {
+    pub harassment: f32,
+    #[serde(rename = "harassment/threatening")]
+    pub harassment_threatening: f32,
+    pub hate: f32,
+    #[serde(rename = "hate/threatening")]
+    pub hate_threatening: f32,
+    #[serde(default)]
+    pub illicit: Option<f32>,
+    #[serde(rename = "illicit/violent", default)]
+    pub illicit_violent: Option<f32>,
+    #[serde(rename = "self-harm")]
+    pub self_harm: f32,
+    #[serde(rename = "self-harm/intent")]
+    pub self_harm_intent: f32,
+    #[serde(rename = "self-harm/instructions")]
+    pub self_harm_instructions: f32,
+    pub sexual: f32,
+    #[serde(rename = "sexual/minors")]
+    pub sexual_minors: f32,
+    pub violence: f32,
+    #[serde(rename = "violence/graphic")]
+    pub violence_graphic: f32,
+}
+
+/// # [`OpenAIModerationModel`]
+///
+/// The models available to use with the OpenAI moderation endpoint, any other model can
+/// be used with [`OpenAIModerationModel::Custom`] which is sent exactly as given.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone, Default)]
+pub enum OpenAIModerationModel {
+    #[default]
+    #[serde(rename = "omni-moderation-latest")]
+    OmniModerationLatest,
+    #[serde(rename = "text-moderation-latest")]
+    TextModerationLatest,
+    #[serde(untagged)]
+    Custom(String),
+}
+
+impl OpenAIModerationModel {
+    /// # [`OpenAIModerationModel::as_str`]
+    ///
+    /// # Returns
+    /// * &[`str`] - the model name sent to OpenAI.
+    pub fn as_str(&self) -> &str {
+        match self {
+            OpenAIModerationModel::OmniModerationLatest => "omni-moderation-latest",
+            OpenAIModerationModel::TextModerationLatest => "text-moderation-latest",
+            OpenAIModerationModel::Custom(name) => name,
+        }
+    }
+}
+
+impl Display for OpenAIModerationModel {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
@@ -1,19 +1,36 @@
+#[cfg(feature = "openai-stream")]
 use futures::StreamExt;
+#[cfg(feature = "openai-stream")]
 use reqwest_eventsource::{Event, EventSource};
 use serde_json::{Map, Value};
 use std::env::VarError;
+use std::num::NonZeroU8;
+use std::sync::Arc;
 
+use crate::clients::open_ai::compatible::OpenAICompatible;
 use crate::clients::open_ai::model::chat_completions::{
     ChatCompletionChoices, ChatCompletionRequest, ChatCompletionResponse, OpenAIModel,
+    StreamOptions,
 };
-use crate::clients::open_ai::open_ai_core::OpenAIHttpClient;
+use crate::clients::open_ai::open_ai_core::{OpenAIHttpClient, OPENAI_REQUEST_ID_HEADER};
+#[cfg(feature = "openai-stream")]
+use crate::clients::stop_sequences::{StopSequenceMatch, StopSequenceMatcher};
 use crate::clients::{
-    AsyncChatClient, AsyncStreamedChatClient, ChatCompletionStream, PromptMessage,
+    check_reserved_keys, AdditionalConfigError, AsyncChatClient, DetailedChatResponse,
+    FinishReason, HttpConfig, HttpConfigError, ModelCapabilities, PromptMessage, RateLimiter,
+    RetryPolicy, SecretProvider, TokenCounter,
 };
+#[cfg(feature = "openai-stream")]
+use crate::clients::{AsyncStreamedChatClient, ChatCompletionStream, CompletionStreamValue};
+use crate::common::{count_tiktoken_tokens, Clock, InvocationContext, TokenUsage};
+use reqwest::header::HeaderMap;
 
+use super::model::chat_completions::ChatMessage;
+#[cfg(feature = "openai-stream")]
 use super::model::chat_completions::{
-    ChatCompletionDelta, ChatCompletionStreamedResponse, ChatMessage,
+    ChatCompletionDelta, ChatCompletionStreamedChoices, ChatCompletionStreamedResponse,
 };
+use super::model::chat_options::ChatOptions;
 
 use super::model::errors::OpenAIError;
 
@@ -51,10 +68,13 @@ pub struct OpenAIChatCompletionClient {
     client: OpenAIHttpClient,
     model: OpenAIModel,
     additional_config: Option<Map<String, Value>>,
+    include_stream_usage: bool,
 }
 
 impl OpenAIChatCompletionClient {
     const OPENAI_CHAT_COMPLETIONS_URL: &str = "https://api.openai.com/v1/chat/completions";
+    /// The keys the client sets in every request so cannot be in the additional config
+    pub const RESERVED_CONFIG_KEYS: &[&str] = &["model", "messages", "stream", "stream_options"];
 
     /// # [`OpenAIChatCompletionClient::try_new`]
     ///
@@ -76,9 +96,77 @@ impl OpenAIChatCompletionClient {
             client,
             model,
             additional_config: None,
+            include_stream_usage: false,
         })
     }
 
+    /// # [`OpenAIChatCompletionClient::try_new_with_retry`]
+    ///
+    /// This method creates a new OpenAIChatCompletionClient which retries requests that fail
+    /// with a 429, 500 or 503, see [`RetryPolicy`]. Streamed requests are not retried.
+    ///
+    /// # Arguments
+    /// * `model`: [`OpenAIModel`] - The model to use for the chat completion.
+    /// * `retry_policy`: [`RetryPolicy`] - How failed requests are retried.
+    ///
+    /// # Errors
+    /// * [`VarError`] - if the OPENAI_API_KEY environment variable is not set.
+    ///
+    /// # Returns
+    /// * [`OpenAIChatCompletionClient`] - the chat completion client.
+    pub fn try_new_with_retry(
+        model: OpenAIModel,
+        retry_policy: RetryPolicy,
+    ) -> Result<OpenAIChatCompletionClient, VarError> {
+        Ok(Self::try_new(model)?.with_retry_policy(retry_policy))
+    }
+
+    /// # [`OpenAIChatCompletionClient::try_new_with_config`]
+    ///
+    /// This method creates a new OpenAIChatCompletionClient whose requests use the given
+    /// timeouts, see [`HttpConfig`].
+    ///
+    /// # Arguments
+    /// * `model`: [`OpenAIModel`] - The model to use for the chat completion.
+    /// * `http_config`: [`HttpConfig`] - The request and connect timeouts.
+    ///
+    /// # Errors
+    /// * [`HttpConfigError::EnvVarError`] - if the OPENAI_API_KEY environment variable is not set.
+    /// * [`HttpConfigError::ClientBuildError`] - if the HTTP client could not be built.
+    ///
+    /// # Returns
+    /// * [`OpenAIChatCompletionClient`] - the chat completion client.
+    pub fn try_new_with_config(
+        model: OpenAIModel,
+        http_config: HttpConfig,
+    ) -> Result<OpenAIChatCompletionClient, HttpConfigError> {
+        Self::try_new(model)
+            .map_err(HttpConfigError::EnvVarError)?
+            .with_http_config(http_config)
+    }
+
+    /// # [`OpenAIChatCompletionClient::try_new_with_http_client`]
+    ///
+    /// This method creates a new OpenAIChatCompletionClient which sends every request,
+    /// including streamed ones, with the given client. Use this to send requests through
+    /// a proxy or to trust a custom certificate.
+    ///
+    /// # Arguments
+    /// * `model`: [`OpenAIModel`] - The model to use for the chat completion.
+    /// * `http_client`: [`reqwest::Client`] - The preconfigured client to send requests with.
+    ///
+    /// # Errors
+    /// * [`VarError`] - if the OPENAI_API_KEY environment variable is not set.
+    ///
+    /// # Returns
+    /// * [`OpenAIChatCompletionClient`] - the chat completion client.
+    pub fn try_new_with_http_client(
+        model: OpenAIModel,
+        http_client: reqwest::Client,
+    ) -> Result<OpenAIChatCompletionClient, VarError> {
+        Ok(Self::try_new(model)?.with_http_client(http_client))
+    }
+
     /// # [`OpenAIChatCompletionClient::try_new_with_additional_config`]
     ///
     /// This method creates a new OpenAIChatCompletionClient. All inference parameters provided
@@ -86,30 +174,31 @@ impl OpenAIChatCompletionClient {
     /// could be 'temperature', 'top_p', 'seed' etc.
     ///
     /// # Forbidden Properties
-    /// * "stream": this cannot be set as it is used internally by the client.
-    /// * "n": n can be set but will result in wasted tokens as the client is built for single
-    ///        chat completions. We intend to add support for multiple completions in the future.
+    /// * [`OpenAIChatCompletionClient::RESERVED_CONFIG_KEYS`]: "model", "messages", "stream" and
+    ///   "stream_options" are set by the client, the constructor rejects them.
+    /// * "n": use [`OpenAIChatCompletionClient::invoke_n`] for multiple completions, which fails
+    ///   with [`OpenAIError::ConflictingOption`] if n is also set here. Any other call only
+    ///   returns the first completion so setting n wastes tokens.
     ///
     /// # Arguments
     /// * `model`: [`OpenAIModel`] - The model to use for the chat completion.
     /// * `additional_config`: [`Map<String, Value>`] - The additional configuration to use for the chat completion.
     ///
     /// # Errors
-    /// * [`VarError`] - if the OPENAI_API_KEY environment variable is not set.
+    /// * [`AdditionalConfigError::ReservedKeys`] - if the additional config sets any of the reserved keys.
+    /// * [`AdditionalConfigError::EnvVarError`] - if the OPENAI_API_KEY environment variable is not set.
     ///
     /// # Returns
     /// * [`OpenAIChatCompletionClient`] - the chat completion client.
     pub fn try_new_with_additional_config(
         model: OpenAIModel,
         additional_config: Map<String, Value>,
-    ) -> Result<OpenAIChatCompletionClient, VarError> {
-        let client: OpenAIHttpClient = OpenAIHttpClient::try_new()?;
-        Ok(OpenAIChatCompletionClient {
-            url: Self::OPENAI_CHAT_COMPLETIONS_URL.into(),
-            client,
+    ) -> Result<OpenAIChatCompletionClient, AdditionalConfigError> {
+        Self::try_new_with_url_and_additional_config(
             model,
-            additional_config: Some(additional_config),
-        })
+            Self::OPENAI_CHAT_COMPLETIONS_URL.into(),
+            additional_config,
+        )
     }
 
     /// # [`OpenAIChatCompletionClient::try_new_with_url`]
@@ -135,6 +224,7 @@ impl OpenAIChatCompletionClient {
             client,
             model,
             additional_config: None,
+            include_stream_usage: false,
         })
     }
 
@@ -145,9 +235,11 @@ impl OpenAIChatCompletionClient {
     /// could be 'temperature', 'top_p', 'seed' etc. You can pass the url in directly.
     ///
     /// # Forbidden Properties
-    /// * "stream": this cannot be set as it is used internally by the client.
-    /// * "n": n can be set but will result in wasted tokens as the client is built for single
-    ///        chat completions. We intend to add support for multiple completions in the future.
+    /// * [`OpenAIChatCompletionClient::RESERVED_CONFIG_KEYS`]: "model", "messages", "stream" and
+    ///   "stream_options" are set by the client, the constructor rejects them.
+    /// * "n": use [`OpenAIChatCompletionClient::invoke_n`] for multiple completions, which fails
+    ///   with [`OpenAIError::ConflictingOption`] if n is also set here. Any other call only
+    ///   returns the first completion so setting n wastes tokens.
     ///
     /// # Arguments
     /// * `model`: [`OpenAIModel`] - The model to use for the chat completion.
@@ -155,7 +247,8 @@ impl OpenAIChatCompletionClient {
     /// * `additional_config`: [`Map<String, Value>`] - The additional configuration to use for the chat completion.
     ///
     /// # Errors
-    /// * [`VarError`] - if the OPENAI_API_KEY environment variable is not set.
+    /// * [`AdditionalConfigError::ReservedKeys`] - if the additional config sets any of the reserved keys.
+    /// * [`AdditionalConfigError::EnvVarError`] - if the OPENAI_API_KEY environment variable is not set.
     ///
     /// # Returns
     /// * [`OpenAIChatCompletionClient`] - the chat completion client.
@@ -163,57 +256,458 @@ impl OpenAIChatCompletionClient {
         model: OpenAIModel,
         url: String,
         additional_config: Map<String, Value>,
-    ) -> Result<OpenAIChatCompletionClient, VarError> {
-        let client: OpenAIHttpClient = OpenAIHttpClient::try_new()?;
+    ) -> Result<OpenAIChatCompletionClient, AdditionalConfigError> {
+        check_reserved_keys(&additional_config, Self::RESERVED_CONFIG_KEYS)?;
+        let client: OpenAIHttpClient =
+            OpenAIHttpClient::try_new().map_err(AdditionalConfigError::EnvVarError)?;
         Ok(OpenAIChatCompletionClient {
             url,
             client,
             model,
             additional_config: Some(additional_config),
+            include_stream_usage: false,
         })
     }
-}
 
-impl AsyncChatClient for OpenAIChatCompletionClient {
-    type ErrorType = OpenAIError;
+    /// # [`OpenAIChatCompletionClient::try_new_azure`]
+    ///
+    /// This method creates a new OpenAIChatCompletionClient for a model deployed on Azure OpenAI.
+    /// Requests are sent to the deployment on the resource in AZURE_OPENAI_ENDPOINT and
+    /// authenticated with the `api-key` header. Azure picks the model from the deployment, the
+    /// model given here is used to check options and count tokens so should match it.
+    ///
+    /// # Arguments
+    /// * `model`: [`OpenAIModel`] - The model the deployment serves.
+    /// * `deployment`: &[`str`] - The name of the deployment.
+    /// * `api_version`: &[`str`] - The Azure OpenAI API version e.g. `2024-06-01`.
+    ///
+    /// # Errors
+    /// * [`VarError`] - if the AZURE_OPENAI_API_KEY or AZURE_OPENAI_ENDPOINT environment variables are not set.
+    ///
+    /// # Returns
+    /// * [`OpenAIChatCompletionClient`] - the chat completion client.
+    pub fn try_new_azure(
+        model: OpenAIModel,
+        deployment: &str,
+        api_version: &str,
+    ) -> Result<OpenAIChatCompletionClient, VarError> {
+        let client: OpenAIHttpClient = OpenAIHttpClient::try_new_azure()?;
+        Ok(OpenAIChatCompletionClient {
+            url: OpenAIHttpClient::azure_url(deployment, api_version, "chat/completions")?,
+            client,
+            model,
+            additional_config: None,
+            include_stream_usage: false,
+        })
+    }
 
-    /// # [`OpenAIChatCompletionClient::invoke`]
+    /// # [`OpenAIChatCompletionClient::new_with_secret_provider`]
     ///
-    /// function to execute the ChatCompletion given a list of prompt messages.
+    /// This method creates a new OpenAIChatCompletionClient which fetches the OPENAI_API_KEY
+    /// secret from the provider instead of the environment, see [`SecretProvider`].
+    ///
+    /// # Arguments
+    /// * `model`: [`OpenAIModel`] - The model to use for the chat completion.
+    /// * `provider`: impl [`SecretProvider`] - Where the API key is fetched from.
+    ///
+    /// # Returns
+    /// * [`OpenAIChatCompletionClient`] - the chat completion client.
+    pub fn new_with_secret_provider(
+        model: OpenAIModel,
+        provider: impl SecretProvider + 'static,
+    ) -> OpenAIChatCompletionClient {
+        OpenAIChatCompletionClient {
+            url: Self::OPENAI_CHAT_COMPLETIONS_URL.into(),
+            client: OpenAIHttpClient::new_with_secret_provider(Arc::new(provider)),
+            model,
+            additional_config: None,
+            include_stream_usage: false,
+        }
+    }
+
+    /// # [`OpenAIChatCompletionClient::new_compatible`]
+    ///
+    /// This method creates a new OpenAIChatCompletionClient for a provider with an OpenAI
+    /// compatible API, requests are sent to `{base_url}/chat/completions` with the API key
+    /// and headers of the config, see [`OpenAICompatible`].
+    ///
+    /// # Arguments
+    /// * `model`: [`OpenAIModel`] - The model to use for the chat completion, usually
+    ///   [`OpenAIModel::Custom`] with the provider's model name.
+    /// * `config`: &[`OpenAICompatible`] - The provider to send requests to.
+    ///
+    /// # Returns
+    /// * [`OpenAIChatCompletionClient`] - the chat completion client.
+    pub fn new_compatible(
+        model: OpenAIModel,
+        config: &OpenAICompatible,
+    ) -> OpenAIChatCompletionClient {
+        OpenAIChatCompletionClient {
+            url: config.url("chat/completions"),
+            client: OpenAIHttpClient::new_compatible(config),
+            model,
+            additional_config: None,
+            include_stream_usage: false,
+        }
+    }
+
+    /// # [`OpenAIChatCompletionClient::with_seed`]
+    ///
+    /// Sends a seed with every request so OpenAI samples deterministically on a best effort
+    /// basis. Responses carry a `system_fingerprint`, when it changes between two responses the
+    /// backend changed and the outputs may differ even with the same seed, see
+    /// [`crate::clients::ReproducibilityReport`]. This overrides any seed in the additional config.
+    ///
+    /// # Arguments
+    /// * `seed`: [`u64`] - the seed to send.
+    ///
+    /// # Returns
+    /// * [`OpenAIChatCompletionClient`] - the client sending the seed.
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.additional_config
+            .get_or_insert_with(Map::new)
+            .insert("seed".into(), seed.into());
+        self
+    }
+
+    /// # [`OpenAIChatCompletionClient::with_stream_usage`]
+    ///
+    /// Asks OpenAI to report the token usage of streamed requests, it is sent on a final
+    /// chunk and read with [`OpenAICompletionStream::usage`] once the stream has finished.
+    /// Requests which are not streamed always report their usage.
+    ///
+    /// # Returns
+    /// * [`OpenAIChatCompletionClient`] - the client asking for the usage of streamed requests.
+    #[cfg(feature = "openai-stream")]
+    pub fn with_stream_usage(mut self) -> Self {
+        self.include_stream_usage = true;
+        self
+    }
+
+    /// # [`OpenAIChatCompletionClient::with_chat_options`]
+    ///
+    /// Sends the typed options with every request. The options which are set override the
+    /// same options in the additional config, the rest of the additional config is still sent.
+    ///
+    /// # Arguments
+    /// * `options`: [`ChatOptions`] - the options to send.
+    ///
+    /// # Returns
+    /// * [`OpenAIChatCompletionClient`] - the client sending the options.
+    pub fn with_chat_options(mut self, options: ChatOptions) -> Self {
+        options.merge_into(self.additional_config.get_or_insert_with(Map::new));
+        self
+    }
+
+    /// # [`OpenAIChatCompletionClient::with_retry_policy`]
+    ///
+    /// Requests which fail with a 429, 500 or 503 are retried according to the policy, by
+    /// default they are not retried. After the last attempt the error is returned.
+    ///
+    /// # Arguments
+    /// * `retry_policy`: [`RetryPolicy`] - how failed requests are retried.
+    ///
+    /// # Returns
+    /// * [`OpenAIChatCompletionClient`] - the client with the retry policy set.
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.client.set_retry_policy(retry_policy);
+        self
+    }
+
+    /// # [`OpenAIChatCompletionClient::with_clock`]
+    ///
+    /// Sets the clock the cached API key expires and failed requests wait out their retry delay with,
+    /// by default this is [`crate::common::SystemClock`]. Pass a `MockClock` (with the
+    /// `test-utils` feature) to control time in tests.
+    ///
+    /// # Arguments
+    /// * `clock`: [`Arc<dyn Clock>`] - the clock to measure time with.
+    ///
+    /// # Returns
+    /// * [`OpenAIChatCompletionClient`] - the client measuring time with the clock.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.client.set_clock(clock);
+        self
+    }
+
+    /// # [`OpenAIChatCompletionClient::with_http_config`]
+    ///
+    /// Sets the request and connect timeouts, by default these are
+    /// [`crate::clients::DEFAULT_REQUEST_TIMEOUT`] and [`crate::clients::DEFAULT_CONNECT_TIMEOUT`].
+    /// Streamed requests only use the connect timeout.
+    ///
+    /// # Arguments
+    /// * `http_config`: [`HttpConfig`] - the request and connect timeouts.
+    ///
+    /// # Errors
+    /// * [`HttpConfigError::ClientBuildError`] - if the HTTP client could not be built.
+    ///
+    /// # Returns
+    /// * [`OpenAIChatCompletionClient`] - the client with the timeouts set.
+    pub fn with_http_config(mut self, http_config: HttpConfig) -> Result<Self, HttpConfigError> {
+        self.client.set_http_config(http_config)?;
+        Ok(self)
+    }
+
+    /// # [`OpenAIChatCompletionClient::with_http_client`]
+    ///
+    /// Sends every request, including streamed ones, with the given client rather than one
+    /// built from the [`HttpConfig`]. The request timeout is still applied.
+    ///
+    /// # Arguments
+    /// * `http_client`: [`reqwest::Client`] - the preconfigured client to send requests with.
+    ///
+    /// # Returns
+    /// * [`OpenAIChatCompletionClient`] - the client sending requests with the given client.
+    pub fn with_http_client(mut self, http_client: reqwest::Client) -> Self {
+        self.client.set_http_client(http_client);
+        self
+    }
+
+    /// # [`OpenAIChatCompletionClient::with_rate_limiter`]
+    ///
+    /// Each request waits on the limiter until it fits within the budget before it is sent,
+    /// by default requests are not limited. A request is estimated to use the tokens of its
+    /// messages plus the `max_tokens` in the additional config if it is set.
+    ///
+    /// # Arguments
+    /// * `rate_limiter`: [`RateLimiter`] - the budget requests are kept within.
+    ///
+    /// # Returns
+    /// * [`OpenAIChatCompletionClient`] - the client with the rate limiter set.
+    pub fn with_rate_limiter(mut self, rate_limiter: RateLimiter) -> Self {
+        self.client.set_rate_limiter(rate_limiter);
+        self
+    }
+
+    /// # [`OpenAIChatCompletionClient::invoke_n`]
+    ///
+    /// Asks for n completions of the same messages in one request, e.g. to sample several
+    /// answers and pick the most common. The prompt tokens are only paid for once.
     ///
     /// # Arguments
     /// * `prompt_messages`: [`Vec<PromptMessage>`] - the list of prompt messages that will be sent to the LLM.
+    /// * `n`: [`NonZeroU8`] - the number of completions to generate.
     ///
     /// # Errors
+    /// * [`OpenAIError::ConflictingOption`] - if n is also set in the additional config.
+    /// * [`OpenAIError::UnsupportedOption`] - if the additional config or an image is not supported by the model.
     /// * [`OpenAIError`] - if the chat client invocation fails.
     ///
     /// # Returns
-    /// [`PromptMessage::AIMessage`] - the response from the chat client.
-    async fn invoke(
+    /// [`Vec<PromptMessage>`] - a [`PromptMessage::AIMessage`] for each completion, in the order of their choice index.
+    ///
+    /// # Examples
+    /// ```
+    /// use rag_toolchain::clients::*;
+    /// use std::num::NonZeroU8;
+    ///
+    /// async fn sample_answers(client: OpenAIChatCompletionClient) {
+    ///     let question = PromptMessage::HumanMessage("What is 17 * 23?".into());
+    ///     let answers: Vec<PromptMessage> = client
+    ///         .invoke_n(vec![question], NonZeroU8::new(5).unwrap())
+    ///         .await
+    ///         .unwrap();
+    /// }
+    /// ```
+    pub async fn invoke_n(
         &self,
         prompt_messages: Vec<PromptMessage>,
-    ) -> Result<PromptMessage, Self::ErrorType> {
+        n: NonZeroU8,
+    ) -> Result<Vec<PromptMessage>, OpenAIError> {
+        let conflicting: bool = self
+            .additional_config
+            .as_ref()
+            .is_some_and(|config| config.contains_key("n"));
+        if conflicting {
+            return Err(OpenAIError::ConflictingOption("n".into()));
+        }
+        self.check_request(&prompt_messages)?;
+        let estimated_tokens: usize = self.estimate_tokens(&prompt_messages, n.get() as usize);
+        let mut body: ChatCompletionRequest = self.build_request_body(prompt_messages, false);
+        body.additional_config
+            .get_or_insert_with(Map::new)
+            .insert("n".into(), n.get().into());
+        let response: ChatCompletionResponse = self
+            .client
+            .send_request(body, &self.url, estimated_tokens)
+            .await?;
+        let mut choices: Vec<ChatCompletionChoices> = response.choices;
+        choices.sort_by_key(|choice| choice.index);
+        Ok(choices
+            .into_iter()
+            .map(|choice| PromptMessage::from(choice.message))
+            .collect())
+    }
+
+    /// # [`OpenAIChatCompletionClient::estimate_tokens`]
+    ///
+    /// The tokens a request for the messages will use, only counted when a rate limiter needs them.
+    /// Each of the completions can use up to `max_tokens`.
+    fn estimate_tokens(&self, prompt_messages: &[PromptMessage], completions: usize) -> usize {
+        if !self.client.is_rate_limited() {
+            return 0;
+        }
+        let max_tokens: usize = self
+            .additional_config
+            .as_ref()
+            .and_then(|config| config.get("max_tokens"))
+            .and_then(Value::as_u64)
+            .unwrap_or(0) as usize;
+        self.count_prompt_tokens(prompt_messages) + max_tokens * completions
+    }
+
+    /// # [`OpenAIChatCompletionClient::build_request_body`]
+    ///
+    /// Helper method to map the prompt messages into the request body.
+    fn build_request_body(
+        &self,
+        prompt_messages: Vec<PromptMessage>,
+        stream: bool,
+    ) -> ChatCompletionRequest {
         let mapped_messages: Vec<ChatMessage> =
             prompt_messages.into_iter().map(ChatMessage::from).collect();
 
-        let body: ChatCompletionRequest = ChatCompletionRequest {
-            model: self.model,
+        // OpenAI rejects stream options on requests which are not streamed
+        let stream_options: Option<StreamOptions> = (stream && self.include_stream_usage)
+            .then_some(StreamOptions {
+                include_usage: true,
+            });
+        ChatCompletionRequest {
+            model: self.model.clone(),
             messages: mapped_messages,
-            stream: false,
+            stream,
+            stream_options,
             additional_config: self.additional_config.clone(),
-        };
+        }
+    }
+
+    /// # [`OpenAIChatCompletionClient::check_request`]
+    ///
+    /// Rejects additional config or message content the model does not support,
+    /// see [`OpenAIModel::capabilities`].
+    ///
+    /// # Arguments
+    /// * `prompt_messages`: &[`[PromptMessage]`] - the messages that will be sent.
+    ///
+    /// # Errors
+    /// * [`OpenAIError::UnsupportedOption`] - if an option or content is not supported by the model.
+    fn check_request(&self, prompt_messages: &[PromptMessage]) -> Result<(), OpenAIError> {
+        let capabilities: ModelCapabilities = self.model.capabilities();
+        let unsupported: Option<String> = self
+            .additional_config
+            .as_ref()
+            .and_then(|config| capabilities.unsupported_option(config))
+            .or_else(|| capabilities.unsupported_content(prompt_messages));
+        match unsupported {
+            None => Ok(()),
+            Some(option) => Err(OpenAIError::UnsupportedOption {
+                model: self.model.to_string(),
+                option,
+            }),
+        }
+    }
 
-        let response: ChatCompletionResponse = self.client.send_request(body, &self.url).await?;
+    /// # [`OpenAIChatCompletionClient::first_message`]
+    ///
+    /// Helper method to take the first choice from the response as a prompt message.
+    fn first_message(response: ChatCompletionResponse) -> PromptMessage {
         let choices: Vec<ChatCompletionChoices> = response.choices;
         let messages: Vec<PromptMessage> = choices
             .into_iter()
             .map(|x| PromptMessage::from(x.message))
             .collect();
 
-        Ok(messages[0].clone())
+        messages[0].clone()
+    }
+}
+
+impl TokenCounter for OpenAIChatCompletionClient {
+    fn count_tokens(&self, text: &str) -> usize {
+        count_tiktoken_tokens(self.model.tiktoken_tokenizer(), text)
+    }
+}
+
+impl AsyncChatClient for OpenAIChatCompletionClient {
+    type ErrorType = OpenAIError;
+
+    /// # [`OpenAIChatCompletionClient::invoke`]
+    ///
+    /// function to execute the ChatCompletion given a list of prompt messages.
+    ///
+    /// # Arguments
+    /// * `prompt_messages`: [`Vec<PromptMessage>`] - the list of prompt messages that will be sent to the LLM.
+    ///
+    /// # Errors
+    /// * [`OpenAIError::UnsupportedOption`] - if the additional config or an image is not supported by the model.
+    /// * [`OpenAIError`] - if the chat client invocation fails.
+    ///
+    /// # Returns
+    /// [`PromptMessage::AIMessage`] - the response from the chat client.
+    async fn invoke(
+        &self,
+        prompt_messages: Vec<PromptMessage>,
+    ) -> Result<PromptMessage, Self::ErrorType> {
+        self.check_request(&prompt_messages)?;
+        let estimated_tokens: usize = self.estimate_tokens(&prompt_messages, 1);
+        let body: ChatCompletionRequest = self.build_request_body(prompt_messages, false);
+        let response: ChatCompletionResponse = self
+            .client
+            .send_request(body, &self.url, estimated_tokens)
+            .await?;
+        Ok(Self::first_message(response))
+    }
+
+    /// # [`OpenAIChatCompletionClient::invoke_with_context`]
+    ///
+    /// The same as [`OpenAIChatCompletionClient::invoke`] but the request id from the context
+    /// is sent to OpenAI as a correlation header, and the id OpenAI assigned to the request
+    /// is read from the `x-request-id` response header. The token usage is taken from the response.
+    ///
+    /// # Arguments
+    /// * `prompt_messages`: [`Vec<PromptMessage>`] - the list of prompt messages that will be sent to the LLM.
+    /// * `context`: &[`InvocationContext`] - the context of the invocation.
+    ///
+    /// # Errors
+    /// * [`OpenAIError`] - if the chat client invocation fails.
+    ///
+    /// # Returns
+    /// [`DetailedChatResponse`] - the response from the chat client along with the request ids
+    /// and the finish reason of the first choice.
+    async fn invoke_with_context(
+        &self,
+        prompt_messages: Vec<PromptMessage>,
+        context: &InvocationContext,
+    ) -> Result<DetailedChatResponse, Self::ErrorType> {
+        self.check_request(&prompt_messages)?;
+        let estimated_tokens: usize = self.estimate_tokens(&prompt_messages, 1);
+        let body: ChatCompletionRequest = self.build_request_body(prompt_messages, false);
+        let (response, headers): (ChatCompletionResponse, HeaderMap) = self
+            .client
+            .send_request_with_context(body, &self.url, context, estimated_tokens)
+            .await?;
+        let provider_request_id: Option<String> = headers
+            .get(OPENAI_REQUEST_ID_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .map(String::from);
+
+        let finish_reason: Option<FinishReason> = response
+            .choices
+            .first()
+            .and_then(|choice| choice.finish_reason.parse().ok());
+
+        Ok(DetailedChatResponse {
+            system_fingerprint: response.system_fingerprint.clone(),
+            usage: Some(TokenUsage::from(&response.usage)),
+            finish_reason,
+            message: Self::first_message(response),
+            request_id: context.request_id(),
+            provider_request_id,
+        })
     }
 }
 
+#[cfg(feature = "openai-stream")]
 impl AsyncStreamedChatClient for OpenAIChatCompletionClient {
     type ErrorType = OpenAIError;
     type Item = OpenAICompletionStream;
@@ -234,17 +728,32 @@ impl AsyncStreamedChatClient for OpenAIChatCompletionClient {
         &self,
         prompt_messages: Vec<PromptMessage>,
     ) -> Result<Self::Item, Self::ErrorType> {
-        let mapped_messages: Vec<ChatMessage> =
-            prompt_messages.into_iter().map(ChatMessage::from).collect();
-
-        let body: ChatCompletionRequest = ChatCompletionRequest {
-            model: self.model,
-            messages: mapped_messages,
-            stream: true,
-            additional_config: self.additional_config.clone(),
-        };
+        self.check_request(&prompt_messages)?;
+        let estimated_tokens: usize = self.estimate_tokens(&prompt_messages, 1);
+        let body: ChatCompletionRequest = self.build_request_body(prompt_messages, true);
+        let event_source: EventSource = self
+            .client
+            .send_stream_request(body, &self.url, estimated_tokens)
+            .await?;
+        Ok(OpenAICompletionStream::new(event_source))
+    }
 
-        let event_source: EventSource = self.client.send_stream_request(body, &self.url).await?;
+    /// # [`OpenAIChatCompletionClient::invoke_stream_with_context`]
+    ///
+    /// The same as [`OpenAIChatCompletionClient::invoke_stream`] but the request id
+    /// from the context is sent to OpenAI as a correlation header.
+    async fn invoke_stream_with_context(
+        &self,
+        prompt_messages: Vec<PromptMessage>,
+        context: &InvocationContext,
+    ) -> Result<Self::Item, Self::ErrorType> {
+        self.check_request(&prompt_messages)?;
+        let estimated_tokens: usize = self.estimate_tokens(&prompt_messages, 1);
+        let body: ChatCompletionRequest = self.build_request_body(prompt_messages, true);
+        let event_source: EventSource = self
+            .client
+            .send_stream_request_with_context(body, &self.url, context, estimated_tokens)
+            .await?;
         Ok(OpenAICompletionStream::new(event_source))
     }
 }
@@ -252,22 +761,36 @@ impl AsyncStreamedChatClient for OpenAIChatCompletionClient {
 /// [`OpenAICompletionStream`]
 ///
 /// This structs wraps the EventSource and parses returned
-/// messages into prompt messages on demand.
+/// messages into prompt messages on demand. Use [`ChatCompletionStream::into_stream`]
+/// to read it as a [`futures::Stream`].
+///
+/// # Examples
+/// ```
+/// use futures::StreamExt;
+/// use rag_toolchain::clients::*;
+///
+/// async fn stream_chat_completions(client: OpenAIChatCompletionClient) {
+///     let user_message: PromptMessage = PromptMessage::HumanMessage("Please ask me a question".into());
+///     let stream: OpenAICompletionStream = client.invoke_stream(vec![user_message]).await.unwrap();
+///     let mut stream = std::pin::pin!(stream.into_stream());
+///     while let Some(response) = stream.next().await {
+///         if let Ok(CompletionStreamValue::Message(msg)) = response {
+///             println!("{:?}", msg.content());
+///         }
+///     }
+/// }
+/// ```
+#[cfg(feature = "openai-stream")]
 pub struct OpenAICompletionStream {
     event_source: EventSource,
+    stop_sequence_matcher: StopSequenceMatcher,
+    stopped: bool,
+    system_fingerprint: Option<String>,
+    finish_reason: Option<FinishReason>,
+    usage: Option<TokenUsage>,
 }
 
-/// [`CompletionStreamValue`]
-///
-/// Value returned from each iteration of the stream.
-/// Given we wanted to represent connecting as a non-failure
-/// state we had to create a new enum to represent this.
-#[derive(Debug, Clone, PartialEq, Eq)]
-pub enum CompletionStreamValue {
-    Connecting,
-    Message(PromptMessage),
-}
-
+#[cfg(feature = "openai-stream")]
 impl OpenAICompletionStream {
     const STOP_MESSAGE: &'static str = "[DONE]";
 
@@ -276,24 +799,120 @@ impl OpenAICompletionStream {
     /// This struct just wraps the EventSource when from the
     /// context of streaming chat completions.
     pub fn new(event_source: EventSource) -> Self {
-        Self { event_source }
+        Self {
+            event_source,
+            stop_sequence_matcher: StopSequenceMatcher::default(),
+            stopped: false,
+            system_fingerprint: None,
+            finish_reason: None,
+            usage: None,
+        }
     }
 
-    /// # [`ChatCompletionStream::parse_message`]
+    /// # [`OpenAICompletionStream::system_fingerprint`]
     ///
-    /// Helper method to deserialize the raw response message from the event source.
+    /// The fingerprint of the backend configuration serving the stream, taken
+    /// from the first chunk which carried one.
     ///
-    /// # Arguments
-    /// * `msg`: &[`str`] - the raw response from the event source.
-    ///
-    /// # Errors
-    /// * [`OpenAIError`] - if the chat client invocation fails.
+    /// # Returns
+    /// * [`Option<&str>`] - the fingerprint, `None` until a chunk with one has been received.
+    pub fn system_fingerprint(&self) -> Option<&str> {
+        self.system_fingerprint.as_deref()
+    }
+
+    /// # [`OpenAICompletionStream::usage`]
+    ///
+    /// The tokens OpenAI reported using, only sent when the client was built
+    /// with [`OpenAIChatCompletionClient::with_stream_usage`].
     ///
     /// # Returns
-    /// * [`Option<Result<CompletionStreamValue, OpenAIError>`] - the response from the chat client.
-    ///         None represents the stream is finished. and Some(Err) represents an error.
+    /// * [`Option<TokenUsage>`] - the usage, `None` until the final chunk has been received.
+    pub fn usage(&self) -> Option<TokenUsage> {
+        self.usage
+    }
+
+    /// # [`OpenAICompletionStream::with_stop_sequences`]
+    ///
+    /// Enforces stop sequences on the client side. This is useful for OpenAI compatible
+    /// backends which do not honour stop sequences when streaming, or for patterns the
+    /// API does not support. The text is scanned across delta boundaries, once a stop
+    /// sequence is found the text before it is emitted and the stream is closed.
+    ///
+    /// # Arguments
+    /// * `stop_sequences`: [`Vec<String>`] - the sequences to stop the stream on.
+    ///
+    /// # Returns
+    /// * [`OpenAICompletionStream`] - the stream with the stop sequences applied.
+    pub fn with_stop_sequences(mut self, stop_sequences: Vec<String>) -> Self {
+        self.stop_sequence_matcher = StopSequenceMatcher::new(stop_sequences);
+        self
+    }
+
+    /// # [`OpenAICompletionStream::apply_stop_sequences`]
+    ///
+    /// Runs the message through the stop sequence matcher. Returns None
+    /// when all of the text has been held back and nothing can be emitted yet.
+    fn apply_stop_sequences(&mut self, message: PromptMessage) -> Option<CompletionStreamValue> {
+        let text: String = match self.stop_sequence_matcher.push(message.content()) {
+            StopSequenceMatch::Continue(text) => text,
+            StopSequenceMatch::Stopped(text) => {
+                self.stopped = true;
+                self.event_source.close();
+                text
+            }
+        };
+        if text.is_empty() {
+            return None;
+        }
+        Some(CompletionStreamValue::Message(PromptMessage::AIMessage(
+            text.into(),
+        )))
+    }
+
+    /// # [`OpenAICompletionStream::flush`]
+    ///
+    /// Emits any text that was held back by the stop sequence matcher
+    /// once the stream has ended.
+    fn flush(&mut self) -> Option<Result<CompletionStreamValue, OpenAIError>> {
+        let text: String = self.stop_sequence_matcher.flush();
+        if text.is_empty() {
+            return None;
+        }
+        Some(Ok(CompletionStreamValue::Message(
+            PromptMessage::AIMessage(text.into()),
+        )))
+    }
+
+    /// # [`OpenAICompletionStream::finish`]
+    ///
+    /// Called once the stream has ended, emits any held back text and then
+    /// the finish reason if one was received.
+    fn finish(&mut self) -> Option<Result<CompletionStreamValue, OpenAIError>> {
+        self.flush().or_else(|| {
+            self.finish_reason
+                .take()
+                .map(|reason| Ok(CompletionStreamValue::Finished(reason)))
+        })
+    }
+
+    /// # [`ChatCompletionStream::parse_message`]
+    ///
+    /// Helper method to deserialize the raw response message from the event source,
+    /// recording the system fingerprint from the first chunk which has one, the finish
+    /// reason from the chunk which carries it and the usage from the final chunk. Chunks
+    /// without any content, such as the role only first delta or the usage chunk which has
+    /// no choices, are skipped.
+    ///
+    /// # Arguments
+    /// * `msg`: &[`str`] - the raw response from the event source.
+    ///
+    /// # Errors
+    /// * [`OpenAIError::ErrorDeserializingResponseBody`] - if the chunk is malformed.
     ///
-    fn parse_message(msg: &str) -> Option<Result<CompletionStreamValue, OpenAIError>> {
+    /// # Returns
+    /// * [`Option<Result<CompletionStreamValue, OpenAIError>`] - the response from the chat client.
+    ///   None represents the chunk had nothing to emit and Some(Err) represents an error.
+    fn parse_message(&mut self, msg: &str) -> Option<Result<CompletionStreamValue, OpenAIError>> {
         let response: ChatCompletionStreamedResponse = match serde_json::from_str(msg) {
             Ok(response) => response,
             Err(e) => {
@@ -303,17 +922,32 @@ impl OpenAICompletionStream {
                 )));
             }
         };
-        let chat_message: ChatCompletionDelta = response.choices.first().unwrap().delta.clone();
-        match chat_message.content {
-            Some(msg) => {
-                let prompt_message: PromptMessage = PromptMessage::AIMessage(msg);
-                Some(Ok(CompletionStreamValue::Message(prompt_message)))
-            }
-            None => None,
+        if self.system_fingerprint.is_none() {
+            self.system_fingerprint = response.system_fingerprint;
+        }
+        if let Some(usage) = &response.usage {
+            self.usage = Some(TokenUsage::from(usage));
+        }
+        let choice: ChatCompletionStreamedChoices = response.choices.into_iter().next()?;
+        if let Some(reason) = &choice.finish_reason {
+            self.finish_reason = reason.parse().ok();
         }
+        let chat_message: ChatCompletionDelta = choice.delta;
+        let prompt_message: PromptMessage = PromptMessage::AIMessage(chat_message.content?.into());
+        Some(Ok(CompletionStreamValue::Message(prompt_message)))
     }
 }
 
+/// Dropping the stream part way through, such as when a chain's stream is dropped, closes
+/// the connection so OpenAI stops generating and the event source can not reconnect.
+#[cfg(feature = "openai-stream")]
+impl Drop for OpenAICompletionStream {
+    fn drop(&mut self) {
+        self.event_source.close();
+    }
+}
+
+#[cfg(feature = "openai-stream")]
 impl ChatCompletionStream for OpenAICompletionStream {
     type ErrorType = OpenAIError;
     type Item = CompletionStreamValue;
@@ -322,7 +956,8 @@ impl ChatCompletionStream for OpenAICompletionStream {
     ///
     /// Method to iterate over the completion stream. Note it blocks
     /// until the next message is received. At which point it will
-    /// parse the response into a [`CompletionStreamValue`].
+    /// parse the response into a [`CompletionStreamValue`]. Once OpenAI has said why
+    /// generation finished a [`CompletionStreamValue::Finished`] is returned before the end.
     ///
     /// # Examples
     /// ```
@@ -337,6 +972,11 @@ impl ChatCompletionStream for OpenAICompletionStream {
     ///            Ok(CompletionStreamValue::Message(msg)) => {
     ///                 println!("{:?}", msg.content());
     ///            }
+    ///            Ok(CompletionStreamValue::Finished(reason)) => {
+    ///                 if reason.is_truncated() {
+    ///                     println!("The answer was cut short");
+    ///                 }
+    ///            }
     ///            Err(e) => {
     ///                 println!("{:?}", e);
     ///                 break;
@@ -354,34 +994,64 @@ impl ChatCompletionStream for OpenAICompletionStream {
     /// * [`Option<Result<CompletionStreamValue, OpenAIError>>`] - the response from the chat client.
     ///         None represents the stream is finished..
     async fn next(&mut self) -> Option<Result<Self::Item, Self::ErrorType>> {
-        let event: Result<Event, reqwest_eventsource::Error> = self.event_source.next().await?;
-
-        let event: Event = match event {
-            Ok(event) => event,
-            Err(e) => {
-                self.event_source.close();
-                return Some(Err(OpenAIError::ErrorReadingStream(e.to_string())));
+        loop {
+            if self.stopped {
+                return None;
             }
-        };
+            let event: Result<Event, reqwest_eventsource::Error> =
+                match self.event_source.next().await {
+                    Some(event) => event,
+                    None => return self.finish(),
+                };
 
-        match event {
-            Event::Message(msg) => {
-                if msg.data == Self::STOP_MESSAGE {
+            let event: Event = match event {
+                Ok(event) => event,
+                Err(e) => {
                     self.event_source.close();
-                    None
-                } else {
-                    Self::parse_message(&msg.data)
+                    return Some(Err(OpenAIError::ErrorReadingStream(e.to_string())));
+                }
+            };
+
+            let value = match event {
+                Event::Message(msg) => {
+                    if msg.data == Self::STOP_MESSAGE {
+                        self.event_source.close();
+                        return self.finish();
+                    }
+                    self.parse_message(&msg.data)
+                }
+                Event::Open => return Some(Ok(CompletionStreamValue::Connecting)),
+            };
+
+            match value {
+                Some(Ok(CompletionStreamValue::Message(message))) => {
+                    // If everything was held back we keep reading until there is text to emit
+                    if let Some(value) = self.apply_stop_sequences(message) {
+                        return Some(Ok(value));
+                    }
                 }
+                None => continue,
+                other => return other,
             }
-            Event::Open => Some(Ok(CompletionStreamValue::Connecting)),
         }
     }
+
+    fn is_token(item: &Self::Item) -> bool {
+        matches!(item, CompletionStreamValue::Message(_))
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use mockito::{Mock, Server, ServerGuard};
+    use crate::clients::cassette::RecordingHttpClient;
+    use crate::clients::http_config::tests::with_custom_http_client;
+    use crate::clients::{ContentPart, ImageSource};
+    use mockito::{Matcher, Mock, Server, ServerGuard};
+    use reqwest::header::{HeaderName, HeaderValue};
+    use std::collections::HashMap;
+    use std::num::NonZeroU32;
+    use std::time::Duration;
 
     const CHAT_COMPLETION_RESPONSE: &'static str = r#"
     {
@@ -407,6 +1077,26 @@ mod tests {
     }
     "#;
 
+    // The choices are deliberately out of order
+    const MULTIPLE_CHOICES_RESPONSE: &str = r#"
+    {
+        "id": "chatcmpl-456",
+        "object": "chat.completion",
+        "created": 1677652288,
+        "model": "gpt-4o",
+        "choices": [
+          {"index": 2, "message": {"role": "assistant", "content": "391"}, "finish_reason": "stop"},
+          {"index": 0, "message": {"role": "assistant", "content": "391."}, "finish_reason": "stop"},
+          {"index": 1, "message": {"role": "assistant", "content": "It is 391"}, "finish_reason": "stop"}
+        ],
+        "usage": {
+          "prompt_tokens": 9,
+          "completion_tokens": 8,
+          "total_tokens": 17
+        }
+    }
+    "#;
+
     const ERROR_RESPONSE: &'static str = r#"
     {
         "error": {
@@ -418,8 +1108,21 @@ mod tests {
     }
     "#;
 
+    #[cfg(feature = "openai-stream")]
     const STREAMED_CHAT_COMPLETION_RESPONSE: &'static str = "id:1\ndata:{\"id\":\"chatcmpl-9BRO0Nnca1ZtfMkFc5tOpQNSJ2Eo0\",\"object\":\"chat.completion.chunk\",\"created\":1712513908,\"model\":\"gpt-3.5-turbo-0125\",\"system_fingerprint\":\"fp_b28b39ffa8\",\"choices\":[{\"index\":0,\"delta\":{\"role\":\"assistant\",\"content\":\"Hello\"},\"logprobs\":null,\"finish_reason\":null}]}\n\ndata:[DONE]\n\n";
 
+    // A role only first delta, then a usage only chunk with no choices after the finish reason
+    // as sent when stream_options.include_usage is set
+    #[cfg(feature = "openai-stream")]
+    const STREAMED_ROLE_AND_USAGE_RESPONSE: &str = concat!(
+        "data:{\"id\":\"chatcmpl-1\",\"object\":\"chat.completion.chunk\",\"created\":1712513908,\"model\":\"gpt-4o\",\"choices\":[{\"index\":0,\"delta\":{\"role\":\"assistant\"},\"finish_reason\":null}]}\n\n",
+        "data:{\"id\":\"chatcmpl-1\",\"object\":\"chat.completion.chunk\",\"created\":1712513908,\"model\":\"gpt-4o\",\"choices\":[{\"index\":0,\"delta\":{\"content\":\"Hello\"},\"finish_reason\":null}]}\n\n",
+        "data:{\"id\":\"chatcmpl-1\",\"object\":\"chat.completion.chunk\",\"created\":1712513908,\"model\":\"gpt-4o\",\"choices\":[{\"index\":0,\"delta\":{\"content\":\" there\"},\"finish_reason\":null}]}\n\n",
+        "data:{\"id\":\"chatcmpl-1\",\"object\":\"chat.completion.chunk\",\"created\":1712513908,\"model\":\"gpt-4o\",\"choices\":[{\"index\":0,\"delta\":{},\"finish_reason\":\"stop\"}]}\n\n",
+        "data:{\"id\":\"chatcmpl-1\",\"object\":\"chat.completion.chunk\",\"created\":1712513908,\"model\":\"gpt-4o\",\"choices\":[],\"usage\":{\"prompt_tokens\":9,\"completion_tokens\":2,\"total_tokens\":11}}\n\n",
+        "data:[DONE]\n\n"
+    );
+
     #[test]
     fn try_new_with_env_var_succeeds() {
         std::env::set_var("OPENAI_API_KEY", "test");
@@ -433,6 +1136,42 @@ mod tests {
             .expect("Failed to create OpenAIChatCompletionClient");
     }
 
+    #[test]
+    fn try_new_with_additional_config_rejects_each_reserved_key() {
+        for key in ["model", "messages", "stream", "stream_options"] {
+            let mut config: Map<String, Value> = Map::new();
+            config.insert(key.into(), Value::Null);
+            config.insert("temperature".into(), 0.5.into());
+            let result = OpenAIChatCompletionClient::try_new_with_additional_config(
+                OpenAIModel::Gpt4o,
+                config,
+            );
+            assert_eq!(
+                result.err(),
+                Some(AdditionalConfigError::ReservedKeys(vec![key.into()]))
+            );
+        }
+    }
+
+    #[test]
+    fn try_new_with_url_and_additional_config_lists_every_reserved_key() {
+        let mut config: Map<String, Value> = Map::new();
+        config.insert("stream".into(), true.into());
+        config.insert("model".into(), "gpt-4".into());
+        let result = OpenAIChatCompletionClient::try_new_with_url_and_additional_config(
+            OpenAIModel::Gpt4o,
+            "http://localhost".into(),
+            config,
+        );
+        assert_eq!(
+            result.err(),
+            Some(AdditionalConfigError::ReservedKeys(vec![
+                "model".into(),
+                "stream".into()
+            ]))
+        );
+    }
+
     #[tokio::test]
     async fn invoke_correct_response_succeeds() {
         let (client, mut server) = with_mocked_client(None).await;
@@ -445,6 +1184,291 @@ mod tests {
         assert_eq!(expected_response, response);
     }
 
+    #[tokio::test]
+    async fn invoke_n_returns_every_choice_in_index_order() {
+        let mut config = Map::new();
+        config.insert("temperature".into(), 0.9.into());
+        let (client, mut server) = with_mocked_client(Some(config)).await;
+        let mock = server
+            .mock("POST", "/")
+            .match_body(Matcher::PartialJson(
+                serde_json::json!({"n": 3, "temperature": 0.9, "stream": false}),
+            ))
+            .with_status(200)
+            .with_header("Content-Type", "application/json")
+            .with_body(MULTIPLE_CHOICES_RESPONSE)
+            .create();
+        let prompt = PromptMessage::HumanMessage("What is 17 * 23?".into());
+        let responses = client
+            .invoke_n(vec![prompt], NonZeroU8::new(3).unwrap())
+            .await
+            .unwrap();
+        mock.assert();
+        assert_eq!(
+            responses,
+            vec![
+                PromptMessage::AIMessage("391.".into()),
+                PromptMessage::AIMessage("It is 391".into()),
+                PromptMessage::AIMessage("391".into()),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn invoke_n_rejects_n_in_additional_config() {
+        let mut config = Map::new();
+        config.insert("n".into(), 2.into());
+        let (client, mut server) = with_mocked_client(Some(config)).await;
+        let mock = server.mock("POST", "/").expect(0).create();
+        let prompt = PromptMessage::HumanMessage("What is 17 * 23?".into());
+        let error = client
+            .invoke_n(vec![prompt], NonZeroU8::new(3).unwrap())
+            .await
+            .unwrap_err();
+        mock.assert();
+        assert_eq!(error, OpenAIError::ConflictingOption("n".into()));
+    }
+
+    #[tokio::test]
+    async fn azure_client_sends_to_the_deployment_with_the_api_key_header() {
+        std::env::set_var("AZURE_OPENAI_API_KEY", "azure key");
+        std::env::set_var(
+            "AZURE_OPENAI_ENDPOINT",
+            "https://resource.openai.azure.com/",
+        );
+        let mut client =
+            OpenAIChatCompletionClient::try_new_azure(OpenAIModel::Gpt4o, "chat", "2024-06-01")
+                .unwrap();
+        assert_eq!(
+            client.url,
+            "https://resource.openai.azure.com/openai/deployments/chat/chat/completions?api-version=2024-06-01"
+        );
+
+        let mut server = Server::new_async().await;
+        client.url = format!("{}/chat/completions?api-version=2024-06-01", server.url());
+        let mock = server
+            .mock("POST", "/chat/completions")
+            .match_query(Matcher::UrlEncoded(
+                "api-version".into(),
+                "2024-06-01".into(),
+            ))
+            .match_header("api-key", "azure key")
+            .with_status(200)
+            .with_header("Content-Type", "application/json")
+            .with_body(CHAT_COMPLETION_RESPONSE)
+            .create();
+        let prompt = PromptMessage::HumanMessage("Please ask me a question".into());
+        let response = client.invoke(vec![prompt]).await.unwrap();
+        mock.assert();
+        assert_eq!(
+            PromptMessage::AIMessage("Hello there, how may I assist you today?".into()),
+            response
+        );
+    }
+
+    #[tokio::test]
+    async fn compatible_client_sends_the_given_key_as_a_bearer_token() {
+        let mut server = Server::new_async().await;
+        let config = OpenAICompatible::new(format!("{}/openai/v1/", server.url()), "groq key")
+            .with_header(
+                HeaderName::from_static("x-organisation"),
+                HeaderValue::from_static("org-1"),
+            );
+        let model: OpenAIModel = "llama-3.1-8b-instant".parse().unwrap();
+        let client = OpenAIChatCompletionClient::new_compatible(model, &config);
+        let mock = server
+            .mock("POST", "/openai/v1/chat/completions")
+            .match_header("authorization", "Bearer groq key")
+            .match_header("x-organisation", "org-1")
+            .match_body(Matcher::PartialJson(
+                serde_json::json!({"model": "llama-3.1-8b-instant"}),
+            ))
+            .with_status(200)
+            .with_header("Content-Type", "application/json")
+            .with_body(CHAT_COMPLETION_RESPONSE)
+            .create();
+        let prompt = PromptMessage::HumanMessage("Please ask me a question".into());
+        client.invoke(vec![prompt]).await.unwrap();
+        mock.assert();
+    }
+
+    #[tokio::test]
+    async fn compatible_client_sends_the_key_in_a_custom_header() {
+        let mut server = Server::new_async().await;
+        let config = OpenAICompatible::new(server.url(), "gateway key")
+            .with_auth_header(HeaderName::from_static("x-api-key"));
+        let client = OpenAIChatCompletionClient::new_compatible(OpenAIModel::Gpt4o, &config);
+        let mock = server
+            .mock("POST", "/chat/completions")
+            .match_header("x-api-key", "gateway key")
+            .match_header("authorization", Matcher::Missing)
+            .with_status(200)
+            .with_header("Content-Type", "application/json")
+            .with_body(CHAT_COMPLETION_RESPONSE)
+            .create();
+        let prompt = PromptMessage::HumanMessage("Please ask me a question".into());
+        client.invoke(vec![prompt]).await.unwrap();
+        mock.assert();
+    }
+
+    #[tokio::test]
+    async fn compatible_client_reads_the_key_from_the_given_env_var() {
+        std::env::set_var("COMPATIBLE_CHAT_API_KEY", "env key");
+        let mut server = Server::new_async().await;
+        let config =
+            OpenAICompatible::try_from_env(server.url(), "COMPATIBLE_CHAT_API_KEY").unwrap();
+        let client = OpenAIChatCompletionClient::new_compatible(OpenAIModel::Gpt4o, &config);
+        let mock = server
+            .mock("POST", "/chat/completions")
+            .match_header("authorization", "Bearer env key")
+            .with_status(200)
+            .with_header("Content-Type", "application/json")
+            .with_body(CHAT_COMPLETION_RESPONSE)
+            .create();
+        let prompt = PromptMessage::HumanMessage("Please ask me a question".into());
+        client.invoke(vec![prompt]).await.unwrap();
+        mock.assert();
+    }
+
+    #[tokio::test]
+    async fn invoke_retries_rate_limits() {
+        let (client, mut server) = with_mocked_client(None).await;
+        let client = client.with_retry_policy(RetryPolicy {
+            max_attempts: NonZeroU32::new(3).unwrap(),
+            base_delay: Duration::from_millis(1),
+            max_delay: Duration::from_millis(5),
+        });
+        // The expected count has to be set before the mock is created for mockito
+        // to serve it until it has had both requests
+        let limited = server
+            .mock("POST", "/")
+            .with_status(429)
+            .with_header("Content-Type", "application/json")
+            .with_body(ERROR_RESPONSE)
+            .expect(2)
+            .create();
+        let succeeded = with_mocked_request(&mut server, 200, CHAT_COMPLETION_RESPONSE);
+        let prompt = PromptMessage::HumanMessage("Please ask me a question".into());
+        let response = client.invoke(vec![prompt]).await.unwrap();
+        limited.assert();
+        succeeded.assert();
+        assert_eq!(
+            PromptMessage::AIMessage("Hello there, how may I assist you today?".into()),
+            response
+        );
+    }
+
+    #[tokio::test]
+    async fn rate_limited_requests_estimate_messages_and_max_tokens() {
+        let mut config = Map::new();
+        config.insert("max_tokens".into(), 100.into());
+        let (client, _server) = with_mocked_client(Some(config)).await;
+        let messages = vec![PromptMessage::HumanMessage("hello world".into())];
+        assert_eq!(client.estimate_tokens(&messages, 1), 0);
+        let limit = crate::clients::RateLimit { rpm: 10, tpm: 1000 };
+        let client = client.with_rate_limiter(RateLimiter::new(limit));
+        // 2 for the content, 4 for the role and message overhead and 3 for the reply priming
+        assert_eq!(client.estimate_tokens(&messages, 1), 109);
+        assert_eq!(client.estimate_tokens(&messages, 3), 309);
+    }
+
+    #[tokio::test]
+    async fn invoke_with_context_sends_and_returns_request_ids() {
+        let (client, mut server) = with_mocked_client(None).await;
+        let context = InvocationContext::new();
+        let request_id = context.request_id().to_string();
+        let mock = server
+            .mock("POST", "/")
+            .match_header("X-Request-Id", request_id.as_str())
+            .match_header("X-Correlation-Id", request_id.as_str())
+            .with_status(200)
+            .with_header("Content-Type", "application/json")
+            .with_header("x-request-id", "req_abc")
+            .with_body(CHAT_COMPLETION_RESPONSE)
+            .create();
+        let prompt = PromptMessage::HumanMessage("Please ask me a question".into());
+        let response = client
+            .invoke_with_context(vec![prompt], &context)
+            .await
+            .unwrap();
+        mock.assert();
+        assert_eq!(
+            PromptMessage::AIMessage("Hello there, how may I assist you today?".into()),
+            response.message
+        );
+        assert_eq!(context.request_id(), response.request_id);
+        assert_eq!(Some("req_abc".to_string()), response.provider_request_id);
+        assert_eq!(Some(TokenUsage::new(9, 12)), response.usage);
+        assert_eq!(Some(FinishReason::Stop), response.finish_reason);
+    }
+
+    #[tokio::test]
+    async fn invoke_with_context_reports_truncated_responses() {
+        let (client, mut server) = with_mocked_client(None).await;
+        let body = CHAT_COMPLETION_RESPONSE.replace(r#""stop""#, r#""length""#);
+        let mock = with_mocked_request(&mut server, 200, &body);
+        let prompt = PromptMessage::HumanMessage("Please ask me a question".into());
+        let response = client
+            .invoke_with_context(vec![prompt], &InvocationContext::new())
+            .await
+            .unwrap();
+        mock.assert();
+        assert_eq!(Some(FinishReason::Length), response.finish_reason);
+        assert!(response.is_truncated());
+    }
+
+    #[tokio::test]
+    async fn invoke_with_seed_sends_seed_and_returns_fingerprint() {
+        let (client, mut server) = with_mocked_client(None).await;
+        let mock = server
+            .mock("POST", "/")
+            .match_body(Matcher::PartialJson(serde_json::json!({"seed": 42})))
+            .with_status(200)
+            .with_header("Content-Type", "application/json")
+            .with_body(CHAT_COMPLETION_RESPONSE)
+            .create();
+        let client = client.with_seed(42);
+        let prompt = PromptMessage::HumanMessage("Please ask me a question".into());
+        let response = client
+            .invoke_with_context(vec![prompt], &InvocationContext::new())
+            .await
+            .unwrap();
+        mock.assert();
+        assert_eq!(
+            Some("fp_44709d6fcb".to_string()),
+            response.system_fingerprint
+        );
+    }
+
+    #[tokio::test]
+    async fn invoke_with_chat_options_overrides_additional_config() {
+        let mut config: Map<String, Value> = Map::new();
+        config.insert("max_tokens".into(), 10.into());
+        config.insert("user".into(), "abc".into());
+        let (client, mut server) = with_mocked_client(Some(config)).await;
+        let mock = server
+            .mock("POST", "/")
+            .match_body(Matcher::PartialJson(serde_json::json!({
+                "max_tokens": 500,
+                "user": "abc",
+                "stop": ["END"],
+                "logit_bias": {"50256": -100}
+            })))
+            .with_status(200)
+            .with_header("Content-Type", "application/json")
+            .with_body(CHAT_COMPLETION_RESPONSE)
+            .create();
+        let options = ChatOptions::builder()
+            .max_tokens(500)
+            .stop(vec!["END".into()])
+            .logit_bias(HashMap::from([("50256".to_string(), -100)]))
+            .build();
+        let client = client.with_chat_options(options);
+        let prompt = PromptMessage::HumanMessage("Please ask me a question".into());
+        client.invoke(vec![prompt]).await.unwrap();
+        mock.assert();
+    }
+
     #[tokio::test]
     async fn invoke_error_response_maps_correctly() {
         let (client, mut server) = with_mocked_client(Some(Map::new())).await;
@@ -454,9 +1478,115 @@ mod tests {
         let error_body = serde_json::from_str(ERROR_RESPONSE).unwrap();
         let expected_response = OpenAIError::CODE401(error_body);
         mock.assert();
-        assert_eq!(expected_response, response);
+        assert_eq!(&expected_response, response.kind());
+    }
+
+    #[tokio::test]
+    async fn invoke_with_unsupported_option_is_not_sent() {
+        let (client, mut server) = with_mocked_client(None).await;
+        let mock = with_mocked_request(&mut server, 200, CHAT_COMPLETION_RESPONSE).expect(0);
+        let mut config: Map<String, Value> = Map::new();
+        config.insert("temperature".into(), 0.5.into());
+        let client = OpenAIChatCompletionClient::try_new_with_url_and_additional_config(
+            OpenAIModel::O1Mini,
+            client.url,
+            config,
+        )
+        .unwrap();
+
+        let prompt = PromptMessage::HumanMessage("Please ask me a question".into());
+        let response = client.invoke(vec![prompt]).await.unwrap_err();
+
+        mock.assert();
+        assert_eq!(
+            response,
+            OpenAIError::UnsupportedOption {
+                model: "o1-mini".into(),
+                option: "temperature".into()
+            }
+        );
+    }
+
+    #[test]
+    fn count_tokens_uses_the_model_tokenizer() {
+        std::env::set_var("OPENAI_API_KEY", "fake key");
+        let count = |model: OpenAIModel| {
+            let client = OpenAIChatCompletionClient::try_new_with_url_and_additional_config(
+                model,
+                "http://localhost".into(),
+                Map::new(),
+            )
+            .unwrap();
+            client.count_tokens("hello world")
+        };
+        assert_eq!(count(OpenAIModel::Gpt4o), 2);
+        assert_eq!(count(OpenAIModel::Gpt4), 2);
+    }
+
+    #[tokio::test]
+    async fn invoke_with_image_on_model_without_vision_is_not_sent() {
+        let (client, mut server) = with_mocked_client(None).await;
+        let mock = with_mocked_request(&mut server, 200, CHAT_COMPLETION_RESPONSE).expect(0);
+        let client = OpenAIChatCompletionClient::try_new_with_url_and_additional_config(
+            OpenAIModel::Gpt4,
+            client.url,
+            Map::new(),
+        )
+        .unwrap();
+
+        let prompt = PromptMessage::MultiModalHumanMessage(vec![
+            ContentPart::Text("What is in this image?".into()),
+            ContentPart::Image(ImageSource::Url("https://example.com/a.png".into())),
+        ]);
+        let response = client.invoke(vec![prompt]).await.unwrap_err();
+
+        mock.assert();
+        assert_eq!(
+            response,
+            OpenAIError::UnsupportedOption {
+                model: "gpt-4".into(),
+                option: "image content".into()
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn injected_http_client_sends_requests() {
+        let (client, mut server) = with_mocked_client(None).await;
+        let client = client.with_http_client(with_custom_http_client());
+        let mock = server
+            .mock("POST", "/")
+            .match_header("X-Proxy-Authorization", "Basic proxy")
+            .with_status(200)
+            .with_header("Content-Type", "application/json")
+            .with_body(CHAT_COMPLETION_RESPONSE)
+            .create();
+        let prompt = PromptMessage::HumanMessage("Please ask me a question".into());
+        client.invoke(vec![prompt]).await.unwrap();
+        mock.assert();
+    }
+
+    #[cfg(feature = "openai-stream")]
+    #[tokio::test]
+    async fn injected_http_client_sends_streamed_requests() {
+        let (client, mut server) = with_mocked_client(None).await;
+        let client = client.with_http_client(with_custom_http_client());
+        let mock = server
+            .mock("POST", "/")
+            .match_header("X-Proxy-Authorization", "Basic proxy")
+            .with_status(200)
+            .with_header("Content-Type", "text/event-stream")
+            .with_body(STREAMED_CHAT_COMPLETION_RESPONSE)
+            .create();
+        let prompt = PromptMessage::HumanMessage("Please ask me a question".into());
+        let mut stream = client.invoke_stream(vec![prompt]).await.unwrap();
+        while let Some(value) = stream.next().await {
+            value.unwrap();
+        }
+        mock.assert();
     }
 
+    #[cfg(feature = "openai-stream")]
     #[tokio::test]
     async fn invoke_stream_correct_response_succeeds() {
         let (client, mut server) = with_mocked_client(None).await;
@@ -477,9 +1607,302 @@ mod tests {
         );
         let value3 = stream.next().await;
         assert_eq!(value3, None);
+        assert_eq!(stream.system_fingerprint(), Some("fp_b28b39ffa8"));
+        mock.assert();
+    }
+
+    #[cfg(feature = "openai-stream")]
+    #[tokio::test]
+    async fn invoke_stream_skips_role_only_and_usage_only_chunks() {
+        let (client, mut server) = with_mocked_client(None).await;
+        let mock = server
+            .mock("POST", "/")
+            .with_status(200)
+            .with_header("Content-Type", "text/event-stream")
+            .with_body(STREAMED_ROLE_AND_USAGE_RESPONSE)
+            .create();
+        let prompt = PromptMessage::HumanMessage("Please ask me a question".into());
+        let mut stream = client.invoke_stream(vec![prompt]).await.unwrap();
+        let mut values: Vec<CompletionStreamValue> = Vec::new();
+        while let Some(value) = stream.next().await {
+            values.push(value.unwrap());
+        }
+        assert_eq!(
+            values,
+            vec![
+                CompletionStreamValue::Connecting,
+                CompletionStreamValue::Message(PromptMessage::AIMessage("Hello".into())),
+                CompletionStreamValue::Message(PromptMessage::AIMessage(" there".into())),
+                CompletionStreamValue::Finished(FinishReason::Stop),
+            ]
+        );
+        mock.assert();
+    }
+
+    #[cfg(feature = "openai-stream")]
+    #[tokio::test]
+    async fn invoke_stream_composes_with_stream_combinators() {
+        let (client, mut server) = with_mocked_client(None).await;
+        let mock = server
+            .mock("POST", "/")
+            .with_status(200)
+            .with_header("Content-Type", "text/event-stream")
+            .with_body(STREAMED_ROLE_AND_USAGE_RESPONSE)
+            .create();
+        let prompt = PromptMessage::HumanMessage("Please ask me a question".into());
+        let stream = client.invoke_stream(vec![prompt]).await.unwrap();
+        let text: Vec<String> = stream
+            .into_stream()
+            .filter_map(|value| async move {
+                match value.unwrap() {
+                    CompletionStreamValue::Message(message) => Some(message.content().to_string()),
+                    _ => None,
+                }
+            })
+            .collect()
+            .await;
+        assert_eq!(text, vec!["Hello", " there"]);
+        mock.assert();
+    }
+
+    #[cfg(feature = "openai-stream")]
+    #[tokio::test]
+    async fn invoke_stream_reports_usage_when_asked() {
+        let (client, mut server) = with_mocked_client(None).await;
+        let mock = server
+            .mock("POST", "/")
+            .match_body(Matcher::PartialJson(serde_json::json!({
+                "stream": true,
+                "stream_options": {"include_usage": true}
+            })))
+            .with_status(200)
+            .with_header("Content-Type", "text/event-stream")
+            .with_body(STREAMED_ROLE_AND_USAGE_RESPONSE)
+            .create();
+        let client = client.with_stream_usage();
+        let prompt = PromptMessage::HumanMessage("Please ask me a question".into());
+        let mut stream = client.invoke_stream(vec![prompt]).await.unwrap();
+        assert_eq!(stream.usage(), None);
+        while let Some(value) = stream.next().await {
+            value.unwrap();
+        }
+        assert_eq!(stream.usage(), Some(TokenUsage::new(9, 2)));
+        mock.assert();
+    }
+
+    #[cfg(feature = "openai-stream")]
+    #[tokio::test]
+    async fn stream_options_are_only_sent_when_streaming() {
+        let (client, _server) = with_mocked_client(None).await;
+        let prompt = vec![PromptMessage::HumanMessage("hello".into())];
+        assert_eq!(
+            client
+                .build_request_body(prompt.clone(), true)
+                .stream_options,
+            None
+        );
+        let client = client.with_stream_usage();
+        assert_eq!(
+            client
+                .build_request_body(prompt.clone(), false)
+                .stream_options,
+            None
+        );
+        assert_eq!(
+            client.build_request_body(prompt, true).stream_options,
+            Some(StreamOptions {
+                include_usage: true
+            })
+        );
+    }
+
+    #[cfg(feature = "openai-stream")]
+    #[tokio::test]
+    async fn invoke_stream_returns_malformed_chunks_as_errors() {
+        let (client, mut server) = with_mocked_client(None).await;
+        let body = STREAMED_CHAT_COMPLETION_RESPONSE
+            .replace(r#""delta":{"role":"assistant","content":"Hello"},"#, "");
+        let mock = server
+            .mock("POST", "/")
+            .with_status(200)
+            .with_header("Content-Type", "text/event-stream")
+            .with_body(body)
+            .create();
+        let prompt = PromptMessage::HumanMessage("Please ask me a question".into());
+        let mut stream = client.invoke_stream(vec![prompt]).await.unwrap();
+        assert_eq!(
+            stream.next().await.unwrap().unwrap(),
+            CompletionStreamValue::Connecting
+        );
+        let error = stream.next().await.unwrap().unwrap_err();
+        assert!(matches!(
+            error,
+            OpenAIError::ErrorDeserializingResponseBody(200, _)
+        ));
+        mock.assert();
+    }
+
+    #[cfg(feature = "openai-stream")]
+    #[tokio::test]
+    async fn invoke_stream_with_seed_sends_seed() {
+        let (client, mut server) = with_mocked_client(Some(Map::new())).await;
+        let mock = server
+            .mock("POST", "/")
+            .match_body(Matcher::PartialJson(
+                serde_json::json!({"seed": 7, "stream": true}),
+            ))
+            .with_status(200)
+            .with_header("Content-Type", "text/event-stream")
+            .with_body(STREAMED_CHAT_COMPLETION_RESPONSE)
+            .create();
+        let client = client.with_seed(7);
+        let prompt = PromptMessage::HumanMessage("Please ask me a question".into());
+        let mut stream = client.invoke_stream(vec![prompt]).await.unwrap();
+        assert_eq!(stream.system_fingerprint(), None);
+        while stream.next().await.is_some() {}
+        assert_eq!(stream.system_fingerprint(), Some("fp_b28b39ffa8"));
+        mock.assert();
+    }
+
+    #[cfg(feature = "openai-stream")]
+    #[tokio::test]
+    async fn invoke_stream_stop_sequence_split_across_deltas() {
+        let (client, mut server) = with_mocked_client(None).await;
+        let body = streamed_response_body(&["The answer is 42</an", "swer> and then", " more"]);
+        let mock = server
+            .mock("POST", "/")
+            .with_status(200)
+            .with_header("Content-Type", "text/event-stream")
+            .with_body(body)
+            .create();
+        let prompt = PromptMessage::HumanMessage("Please ask me a question".into());
+        let mut stream = client
+            .invoke_stream(vec![prompt])
+            .await
+            .unwrap()
+            .with_stop_sequences(vec!["</answer>".into()]);
+
+        let mut emitted = String::new();
+        while let Some(value) = stream.next().await {
+            if let CompletionStreamValue::Message(message) = value.unwrap() {
+                emitted.push_str(message.content());
+            }
+        }
+        assert_eq!(emitted, "The answer is 42");
+        assert_eq!(stream.next().await, None);
+        mock.assert();
+    }
+
+    #[cfg(feature = "openai-stream")]
+    #[tokio::test]
+    async fn invoke_stream_releases_held_back_text_at_end() {
+        let (client, mut server) = with_mocked_client(None).await;
+        let body = streamed_response_body(&["Hello", " </an"]);
+        let mock = server
+            .mock("POST", "/")
+            .with_status(200)
+            .with_header("Content-Type", "text/event-stream")
+            .with_body(body)
+            .create();
+        let prompt = PromptMessage::HumanMessage("Please ask me a question".into());
+        let mut stream = client
+            .invoke_stream(vec![prompt])
+            .await
+            .unwrap()
+            .with_stop_sequences(vec!["</answer>".into()]);
+
+        let mut emitted: Vec<String> = Vec::new();
+        while let Some(value) = stream.next().await {
+            if let CompletionStreamValue::Message(message) = value.unwrap() {
+                emitted.push(message.content().to_string());
+            }
+        }
+        assert_eq!(emitted, vec!["Hello", " ", "</an"]);
         mock.assert();
     }
 
+    #[cfg(feature = "openai-stream")]
+    #[tokio::test]
+    async fn invoke_stream_ends_with_the_finish_reason() {
+        let (client, mut server) = with_mocked_client(None).await;
+        let finished = serde_json::json!({
+            "id": "chatcmpl-123",
+            "object": "chat.completion.chunk",
+            "created": 1712513908,
+            "model": "gpt-3.5-turbo-0125",
+            "choices": [{"index": 0, "delta": {}, "finish_reason": "length"}]
+        });
+        let body = streamed_response_body(&["Hello", " </an"])
+            .replace("data:[DONE]", &format!("data:{}\n\ndata:[DONE]", finished));
+        let mock = server
+            .mock("POST", "/")
+            .with_status(200)
+            .with_header("Content-Type", "text/event-stream")
+            .with_body(body)
+            .create();
+        let prompt = PromptMessage::HumanMessage("Please ask me a question".into());
+        let mut stream = client
+            .invoke_stream(vec![prompt])
+            .await
+            .unwrap()
+            .with_stop_sequences(vec!["</answer>".into()]);
+
+        let mut values: Vec<CompletionStreamValue> = Vec::new();
+        while let Some(value) = stream.next().await {
+            values.push(value.unwrap());
+        }
+        assert_eq!(
+            values[values.len() - 2..],
+            [
+                CompletionStreamValue::Message(PromptMessage::AIMessage("</an".into())),
+                CompletionStreamValue::Finished(FinishReason::Length),
+            ]
+        );
+        assert_eq!(stream.next().await, None);
+        mock.assert();
+    }
+
+    #[tokio::test]
+    async fn invoke_replays_cassette() {
+        let recorder = Arc::new(RecordingHttpClient::load("open_ai_chat_completions"));
+        let client = with_cassette_client(recorder.clone());
+        let prompt = PromptMessage::HumanMessage("Please ask me a question".into());
+        let response = client.invoke(vec![prompt]).await.unwrap();
+        let expected_response =
+            PromptMessage::AIMessage("Sure! What is your favourite book and why?".into());
+        assert_eq!(expected_response, response);
+        assert_eq!(recorder.unused_interactions(), 0);
+    }
+
+    // Returns a client pointed at the real OpenAI url which serves responses from
+    // the cassette, in record mode the real OPENAI_API_KEY is used.
+    fn with_cassette_client(recorder: Arc<RecordingHttpClient>) -> OpenAIChatCompletionClient {
+        if std::env::var("OPENAI_API_KEY").is_err() {
+            std::env::set_var("OPENAI_API_KEY", "fake key");
+        }
+        let mut client = OpenAIChatCompletionClient::try_new(OpenAIModel::Gpt3Point5Turbo).unwrap();
+        client.client.set_recorder(recorder);
+        client
+    }
+
+    // Builds an SSE response body which streams each delta as a separate event
+    #[cfg(feature = "openai-stream")]
+    fn streamed_response_body(deltas: &[&str]) -> String {
+        let mut body = String::new();
+        for delta in deltas {
+            let chunk = serde_json::json!({
+                "id": "chatcmpl-123",
+                "object": "chat.completion.chunk",
+                "created": 1712513908,
+                "model": "gpt-3.5-turbo-0125",
+                "choices": [{"index": 0, "delta": {"content": delta}, "finish_reason": null}]
+            });
+            body.push_str(&format!("data:{}\n\n", chunk));
+        }
+        body.push_str("data:[DONE]\n\n");
+        body
+    }
+
     // Method which mocks the response the server will give. this
     // allows us to stub the requests instead of sending them to OpenAI
     fn with_mocked_request(
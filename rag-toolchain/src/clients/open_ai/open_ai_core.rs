@@ -1,23 +1,81 @@
+use crate::clients::open_ai::compatible::{ApiKeySource, OpenAICompatible};
+#[cfg(feature = "openai-embeddings")]
+use crate::clients::open_ai::model::embeddings::{EmbeddingResponse, EmbeddingResponseParser};
 use crate::clients::open_ai::model::errors::{OpenAIError, OpenAIErrorBody};
+use crate::clients::secrets::{
+    CachedSecret, EnvSecretProvider, SecretProvider, SecretString, StaticSecretProvider,
+    DEFAULT_SECRET_TTL,
+};
+use crate::clients::{HttpConfig, HttpConfigError, RateLimiter, RequestContext, RetryPolicy};
+#[cfg(feature = "openai-chat")]
+use crate::common::InvocationContext;
+use crate::common::{Clock, SystemClock};
 
 use dotenv::dotenv;
-use reqwest::header::{HeaderValue, CONTENT_TYPE};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, CONTENT_TYPE};
 use reqwest::{Client, RequestBuilder, Response, StatusCode};
+#[cfg(feature = "openai-stream")]
 use reqwest_eventsource::{EventSource, RequestBuilderExt};
 use serde::de::DeserializeOwned;
 use serde::Serialize;
 use std::env;
 use std::env::VarError;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Instant;
+
+#[cfg(test)]
+use crate::clients::cassette::RecordingHttpClient;
+
+/// Header the request id of the [`InvocationContext`] is sent in
+#[cfg(feature = "openai-chat")]
+pub const REQUEST_ID_HEADER: &str = "X-Request-Id";
+/// Header the request id of the [`InvocationContext`] is also sent in for proxies which expect it
+#[cfg(feature = "openai-chat")]
+pub const CORRELATION_ID_HEADER: &str = "X-Correlation-Id";
+/// Header OpenAI returns its own id for the request in
+#[cfg(feature = "openai-chat")]
+pub const OPENAI_REQUEST_ID_HEADER: &str = "x-request-id";
+/// The name of the secret holding the API key
+const API_KEY_SECRET: &str = "OPENAI_API_KEY";
+/// The name of the secret holding the API key for Azure OpenAI
+const AZURE_API_KEY_SECRET: &str = "AZURE_OPENAI_API_KEY";
+/// The environment variable holding the Azure OpenAI resource endpoint
+const AZURE_ENDPOINT_VAR: &str = "AZURE_OPENAI_ENDPOINT";
+/// Header Azure OpenAI expects the API key in
+const AZURE_API_KEY_HEADER: &str = "api-key";
+
+/// How the API key is attached to each request
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum AuthStyle {
+    /// `Authorization: Bearer <key>` as used by OpenAI
+    Bearer,
+    /// `<header>: <key>` such as the `api-key` header used by Azure OpenAI
+    Header(HeaderName),
+}
 
 #[derive(Debug)]
 pub struct OpenAIHttpClient {
     client: Client,
-    api_key: String,
+    /// Set when the client was given by the caller, so it is kept when the timeouts change
+    custom_client: bool,
+    api_key: CachedSecret,
+    auth_style: AuthStyle,
+    /// Sent with every request on top of the auth and content type headers
+    headers: HeaderMap,
+    http_config: HttpConfig,
+    retry_policy: Option<RetryPolicy>,
+    rate_limiter: Option<RateLimiter>,
+    /// Measures the time to live of the API key and waits out the retry delays
+    clock: Arc<dyn Clock>,
+    #[cfg(test)]
+    recorder: Option<Arc<RecordingHttpClient>>,
 }
 
 impl OpenAIHttpClient {
     /// # [`OpenAIHttpClient::try_new`]
-    /// Must have the OPENAI_API_KEY environment variable set
+    /// Must have the OPENAI_API_KEY environment variable set. The variable is read
+    /// again once the cached key expires so a rotated key is picked up.
     ///
     ///
     /// # Errors
@@ -26,13 +84,213 @@ impl OpenAIHttpClient {
     /// # Returns
     /// * [`OpenAIHttpClient`] - The newly created OpenAIHttpClient
     pub fn try_new() -> Result<OpenAIHttpClient, VarError> {
+        Self::try_new_from_env(API_KEY_SECRET, AuthStyle::Bearer)
+    }
+
+    /// # [`OpenAIHttpClient::try_new_azure`]
+    /// Must have the AZURE_OPENAI_API_KEY environment variable set. The key is sent in the
+    /// `api-key` header rather than as a bearer token.
+    ///
+    /// # Errors
+    /// * [`VarError`] - If the AZURE_OPENAI_API_KEY environment variable is not set
+    ///
+    /// # Returns
+    /// * [`OpenAIHttpClient`] - The newly created OpenAIHttpClient
+    pub fn try_new_azure() -> Result<OpenAIHttpClient, VarError> {
+        Self::try_new_from_env(
+            AZURE_API_KEY_SECRET,
+            AuthStyle::Header(HeaderName::from_static(AZURE_API_KEY_HEADER)),
+        )
+    }
+
+    /// # [`OpenAIHttpClient::azure_url`]
+    /// Builds the url of an operation on an Azure OpenAI deployment from the
+    /// AZURE_OPENAI_ENDPOINT environment variable.
+    ///
+    /// # Arguments
+    /// * `deployment` - The name of the deployment, which implies the model
+    /// * `api_version` - The Azure OpenAI API version e.g. `2024-06-01`
+    /// * `operation` - The path of the operation e.g. `chat/completions`
+    ///
+    /// # Errors
+    /// * [`VarError`] - If the AZURE_OPENAI_ENDPOINT environment variable is not set
+    ///
+    /// # Returns
+    /// * [`String`] - `{endpoint}/openai/deployments/{deployment}/{operation}?api-version={api_version}`
+    pub fn azure_url(
+        deployment: &str,
+        api_version: &str,
+        operation: &str,
+    ) -> Result<String, VarError> {
         dotenv().ok();
-        let api_key: String = match env::var::<String>("OPENAI_API_KEY".into()) {
-            Ok(api_key) => api_key,
-            Err(e) => return Err(e),
+        let endpoint: String = env::var(AZURE_ENDPOINT_VAR)?;
+        Ok(format!(
+            "{}/openai/deployments/{}/{}?api-version={}",
+            endpoint.trim_end_matches('/'),
+            deployment,
+            operation,
+            api_version
+        ))
+    }
+
+    /// # [`OpenAIHttpClient::try_new_from_env`]
+    /// Reads the API key from the environment up front so a missing key is reported
+    /// when the client is created rather than on the first request.
+    fn try_new_from_env(
+        secret_name: &str,
+        auth_style: AuthStyle,
+    ) -> Result<OpenAIHttpClient, VarError> {
+        dotenv().ok();
+        let api_key: String = env::var(secret_name)?;
+        let provider: Arc<dyn SecretProvider> = Arc::new(EnvSecretProvider);
+        Ok(OpenAIHttpClient {
+            api_key: CachedSecret::new(provider, secret_name, DEFAULT_SECRET_TTL)
+                .with_initial_value(SecretString::new(api_key)),
+            auth_style,
+            headers: HeaderMap::new(),
+            http_config: HttpConfig::default(),
+            retry_policy: None,
+            rate_limiter: None,
+            clock: Arc::new(SystemClock),
+            client: HttpConfig::default_client(),
+            custom_client: false,
+            #[cfg(test)]
+            recorder: None,
+        })
+    }
+
+    /// # [`OpenAIHttpClient::new_with_secret_provider`]
+    /// The API key is fetched from the provider when the first request is sent
+    /// and cached for [`DEFAULT_SECRET_TTL`].
+    ///
+    /// # Arguments
+    /// * `provider` - Where the OPENAI_API_KEY secret is fetched from
+    ///
+    /// # Returns
+    /// * [`OpenAIHttpClient`] - The newly created OpenAIHttpClient
+    pub fn new_with_secret_provider(provider: Arc<dyn SecretProvider>) -> OpenAIHttpClient {
+        OpenAIHttpClient {
+            api_key: CachedSecret::new(provider, API_KEY_SECRET, DEFAULT_SECRET_TTL),
+            auth_style: AuthStyle::Bearer,
+            headers: HeaderMap::new(),
+            http_config: HttpConfig::default(),
+            retry_policy: None,
+            rate_limiter: None,
+            clock: Arc::new(SystemClock),
+            client: HttpConfig::default_client(),
+            custom_client: false,
+            #[cfg(test)]
+            recorder: None,
+        }
+    }
+
+    /// # [`OpenAIHttpClient::new_compatible`]
+    /// Authenticates with the key and headers of an OpenAI compatible provider,
+    /// see [`OpenAICompatible`].
+    ///
+    /// # Arguments
+    /// * `config` - The provider to send requests to
+    ///
+    /// # Returns
+    /// * [`OpenAIHttpClient`] - The newly created OpenAIHttpClient
+    pub fn new_compatible(config: &OpenAICompatible) -> OpenAIHttpClient {
+        let api_key: CachedSecret = match config.api_key() {
+            ApiKeySource::Value(api_key) => CachedSecret::new(
+                Arc::new(StaticSecretProvider(api_key.clone())),
+                API_KEY_SECRET,
+                DEFAULT_SECRET_TTL,
+            )
+            .with_initial_value(api_key.clone()),
+            ApiKeySource::Env(name, api_key) => {
+                CachedSecret::new(Arc::new(EnvSecretProvider), name, DEFAULT_SECRET_TTL)
+                    .with_initial_value(api_key.clone())
+            }
         };
-        let client: Client = Client::new();
-        Ok(OpenAIHttpClient { api_key, client })
+        let auth_style: AuthStyle = match config.auth_header() {
+            Some(header) => AuthStyle::Header(header.clone()),
+            None => AuthStyle::Bearer,
+        };
+        OpenAIHttpClient {
+            api_key,
+            auth_style,
+            headers: config.headers().clone(),
+            http_config: HttpConfig::default(),
+            retry_policy: None,
+            rate_limiter: None,
+            clock: Arc::new(SystemClock),
+            client: HttpConfig::default_client(),
+            custom_client: false,
+            #[cfg(test)]
+            recorder: None,
+        }
+    }
+
+    /// # [`OpenAIHttpClient::set_http_config`]
+    /// Sets the timeouts requests are sent with, by default these are [`HttpConfig::default`].
+    /// The connect timeout is not applied to a client given to [`OpenAIHttpClient::set_http_client`].
+    ///
+    /// # Arguments
+    /// * `http_config` - The timeouts to send requests with
+    ///
+    /// # Errors
+    /// * [`HttpConfigError::ClientBuildError`] - if the client could not be built, the config is not changed
+    pub fn set_http_config(&mut self, http_config: HttpConfig) -> Result<(), HttpConfigError> {
+        if !self.custom_client {
+            self.client = http_config.build_client()?;
+        }
+        self.http_config = http_config;
+        Ok(())
+    }
+
+    /// # [`OpenAIHttpClient::set_http_client`]
+    /// Sends every request, including streamed ones, with the given client. Use this to
+    /// send requests through a proxy or to trust a custom certificate. The client keeps
+    /// its own connect timeout, the request timeout from [`HttpConfig`] is still applied.
+    ///
+    /// # Arguments
+    /// * `client` - The preconfigured client to send requests with
+    pub fn set_http_client(&mut self, client: Client) {
+        self.client = client;
+        self.custom_client = true;
+    }
+
+    /// # [`OpenAIHttpClient::set_retry_policy`]
+    /// Requests which fail with a 429, 500 or 503 are retried according to the policy,
+    /// by default they are not retried. Streamed requests are never retried.
+    ///
+    /// # Arguments
+    /// * `retry_policy` - How failed requests are retried
+    pub fn set_retry_policy(&mut self, retry_policy: RetryPolicy) {
+        self.retry_policy = Some(retry_policy);
+    }
+
+    /// # [`OpenAIHttpClient::set_rate_limiter`]
+    /// Each request, including retries, waits on the limiter before it is sent,
+    /// by default requests are sent straight away.
+    ///
+    /// # Arguments
+    /// * `rate_limiter` - The budget requests are kept within
+    pub fn set_rate_limiter(&mut self, rate_limiter: RateLimiter) {
+        self.rate_limiter = Some(rate_limiter);
+    }
+
+    /// # [`OpenAIHttpClient::set_clock`]
+    /// Sets the clock the cached API key expires and the retry delays are waited out with,
+    /// by default this is [`SystemClock`].
+    ///
+    /// # Arguments
+    /// * `clock` - The clock to measure time with
+    pub fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        self.api_key.set_clock(clock.clone());
+        self.clock = clock;
+    }
+
+    /// # [`OpenAIHttpClient::is_rate_limited`]
+    ///
+    /// # Returns
+    /// * [`bool`] - whether a rate limiter is set, so callers only estimate tokens when needed
+    pub fn is_rate_limited(&self) -> bool {
+        self.rate_limiter.is_some()
     }
 
     /// # [`OpenAIHttpClient::send_request`]
@@ -41,9 +299,13 @@ impl OpenAIHttpClient {
     /// # Arguments
     /// * `body` - The body of the request
     /// * `url` - The url to send the request to
+    /// * `estimated_tokens` - The tokens the request is expected to use, taken from the rate limit
     ///
     /// # Errors
+    /// * [`OpenAIError::ErrorFetchingApiKey`] - if the API key could not be fetched
+    /// * [`OpenAIError::Request`] - wraps any of the errors below with the [`RequestContext`]
     /// * [`OpenAIError::ErrorSendingRequest`] - if request.send() errors
+    /// * [`OpenAIError::Timeout`] - if the request took longer than the timeouts in the [`HttpConfig`]
     /// * [`OpenAIError::ErrorGettingResponseBody`] - if response.text() errors
     /// * [`OpenAIError::ErrorDeserializingResponseBody`] - if serde_json::from_str() errors
     /// * [`OpenAIError`] - if the response code is not 200 this can be any of the associates status
@@ -51,64 +313,406 @@ impl OpenAIHttpClient {
     ///
     /// # Returns
     /// [`U`] - The deserialized response from OpenAI
-    pub async fn send_request<T, U>(&self, body: T, url: &str) -> Result<U, OpenAIError>
+    #[cfg(any(feature = "openai-chat", test))]
+    pub async fn send_request<T, U>(
+        &self,
+        body: T,
+        url: &str,
+        estimated_tokens: usize,
+    ) -> Result<U, OpenAIError>
     where
         T: Serialize,
         U: DeserializeOwned,
     {
-        let request = self.build_requeset(body, url);
-        let response: reqwest::Response = request
-            .send()
-            .await
-            .map_err(|error| OpenAIError::ErrorSendingRequest(error.to_string()))?;
-
-        let status_code: StatusCode = response.status();
-
-        if !status_code.is_success() {
-            let mapped_error: OpenAIError = Self::handle_error_response(response).await;
-            return Err(mapped_error);
-        }
+        let (body, _headers) = self
+            .send_authorized(
+                &body,
+                url,
+                estimated_tokens,
+                |api_key| self.build_requeset(&body, url, api_key),
+                Self::read_json,
+            )
+            .await?;
+        Ok(body)
+    }
 
-        let response_body: String = response
-            .text()
-            .await
-            .map_err(|error| OpenAIError::ErrorGettingResponseBody(error.to_string()))?;
+    /// # [`OpenAIHttpClient::send_embedding_request`]
+    /// Sends a request to the embeddings endpoint. Responses larger than the threshold,
+    /// or of unknown size, are parsed as they are read so a large batch is never held
+    /// in memory as text as well as parsed.
+    ///
+    /// # Arguments
+    /// * `body` - The body of the request
+    /// * `url` - The url to send the request to
+    /// * `streaming_threshold` - The size in bytes above which the response is parsed as it is read
+    /// * `estimated_tokens` - The tokens the request is expected to use, taken from the rate limit
+    ///
+    /// # Errors
+    /// * [`OpenAIError`] - the same errors as [`OpenAIHttpClient::send_request`]
+    ///
+    /// # Returns
+    /// [`EmbeddingResponse`] - The deserialized response from OpenAI
+    #[cfg(feature = "openai-embeddings")]
+    pub async fn send_embedding_request<T>(
+        &self,
+        body: T,
+        url: &str,
+        streaming_threshold: u64,
+        estimated_tokens: usize,
+    ) -> Result<EmbeddingResponse, OpenAIError>
+    where
+        T: Serialize,
+    {
+        self.send_authorized(
+            &body,
+            url,
+            estimated_tokens,
+            |api_key| self.build_requeset(&body, url, api_key),
+            |response| async move {
+                match response.content_length() {
+                    Some(length) if length <= streaming_threshold => {
+                        let (body, _headers) = Self::read_json(response).await?;
+                        Ok(body)
+                    }
+                    _ => Self::read_embedding_response(response).await,
+                }
+            },
+        )
+        .await
+    }
 
-        serde_json::from_str(&response_body).map_err(|error| {
-            OpenAIError::ErrorDeserializingResponseBody(status_code.as_u16(), error.to_string())
-        })
+    /// # [`OpenAIHttpClient::send_request_with_context`]
+    /// Sends a request to the OpenAI API with the request id from the context attached
+    /// as correlation headers. The response headers are returned alongside the body so
+    /// callers can read values such as OpenAI's own request id.
+    ///
+    /// # Arguments
+    /// * `body` - The body of the request
+    /// * `url` - The url to send the request to
+    /// * `context` - The context of the invocation this request is part of
+    /// * `estimated_tokens` - The tokens the request is expected to use, taken from the rate limit
+    ///
+    /// # Errors
+    /// * [`OpenAIError`] - the same errors as [`OpenAIHttpClient::send_request`]
+    ///
+    /// # Returns
+    /// ([`U`], [`HeaderMap`]) - The deserialized response from OpenAI and the response headers
+    #[cfg(feature = "openai-chat")]
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            name = "openai.request_with_context",
+            skip_all,
+            fields(url = %url, request_id = %context.request_id())
+        )
+    )]
+    pub async fn send_request_with_context<T, U>(
+        &self,
+        body: T,
+        url: &str,
+        context: &InvocationContext,
+        estimated_tokens: usize,
+    ) -> Result<(U, HeaderMap), OpenAIError>
+    where
+        T: Serialize,
+        U: DeserializeOwned,
+    {
+        self.send_authorized(
+            &body,
+            url,
+            estimated_tokens,
+            |api_key| Self::with_context_headers(self.build_requeset(&body, url, api_key), context),
+            Self::read_json,
+        )
+        .await
     }
 
     /// # [`OpenAIHttpClient::send_stream_request`]
     ///
     /// Sends a request to the OpenAI API and returns the response as an EventSource
-    /// this will be used for the streaming implementations that use SSE.
+    /// this will be used for the streaming implementations that use SSE. A 401 is only
+    /// reported once the stream is read so these requests are not retried with a new key.
+    #[cfg(feature = "openai-stream")]
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(name = "openai.stream_request", skip_all, fields(url = %url))
+    )]
     pub async fn send_stream_request<T>(
         &self,
         body: T,
         url: &str,
+        estimated_tokens: usize,
+    ) -> Result<EventSource, OpenAIError>
+    where
+        T: Serialize,
+    {
+        let api_key: SecretString = self.fetch_api_key().await?;
+        self.wait_for_capacity(estimated_tokens).await;
+        let request = self.build_requeset(&body, url, &api_key);
+        let source = request
+            .eventsource()
+            .map_err(|e| OpenAIError::ErrorSendingRequest(e.to_string()))?;
+        Ok(source)
+    }
+
+    /// # [`OpenAIHttpClient::send_stream_request_with_context`]
+    ///
+    /// The same as [`OpenAIHttpClient::send_stream_request`] but with the request id
+    /// from the context attached as correlation headers.
+    #[cfg(feature = "openai-stream")]
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            name = "openai.stream_request",
+            skip_all,
+            fields(url = %url, request_id = %context.request_id())
+        )
+    )]
+    pub async fn send_stream_request_with_context<T>(
+        &self,
+        body: T,
+        url: &str,
+        context: &InvocationContext,
+        estimated_tokens: usize,
     ) -> Result<EventSource, OpenAIError>
     where
         T: Serialize,
     {
-        let request = self.build_requeset(body, url);
+        let api_key: SecretString = self.fetch_api_key().await?;
+        self.wait_for_capacity(estimated_tokens).await;
+        let request =
+            Self::with_context_headers(self.build_requeset(&body, url, &api_key), context);
         let source = request
             .eventsource()
             .map_err(|e| OpenAIError::ErrorSendingRequest(e.to_string()))?;
         Ok(source)
     }
 
+    /// # [`OpenAIHttpClient::set_recorder`]
+    ///
+    /// Test only hook which routes all requests through a cassette instead of the network.
+    #[cfg(test)]
+    pub fn set_recorder(&mut self, recorder: Arc<RecordingHttpClient>) {
+        self.recorder = Some(recorder);
+    }
+
+    /// # [`OpenAIHttpClient::execute`]
+    ///
+    /// Sends the request, unless a recorder has been set in which case
+    /// the recorder decides whether to replay or record the interaction.
+    async fn execute(&self, request: RequestBuilder) -> Result<Response, OpenAIError> {
+        #[cfg(test)]
+        if let Some(recorder) = &self.recorder {
+            return recorder
+                .execute(&self.client, request)
+                .await
+                .map_err(OpenAIError::ErrorSendingRequest);
+        }
+        request
+            .send()
+            .await
+            .map_err(|error| OpenAIError::from_reqwest(error, OpenAIError::ErrorSendingRequest))
+    }
+
+    /// # [`OpenAIHttpClient::fetch_api_key`]
+    ///
+    /// Gets the API key from the cache, fetching it from the provider if it has expired.
+    async fn fetch_api_key(&self) -> Result<SecretString, OpenAIError> {
+        self.api_key
+            .get()
+            .await
+            .map_err(OpenAIError::ErrorFetchingApiKey)
+    }
+
+    /// # [`OpenAIHttpClient::wait_for_capacity`]
+    ///
+    /// Waits on the rate limiter, if one is set, until the request fits in the budget.
+    async fn wait_for_capacity(&self, estimated_tokens: usize) {
+        if let Some(rate_limiter) = &self.rate_limiter {
+            rate_limiter.acquire(estimated_tokens).await;
+        }
+    }
+
+    /// # [`OpenAIHttpClient::send_authorized`]
+    ///
+    /// Builds the request with the current API key, sends it and reads the response. If OpenAI
+    /// rejects the key with a 401 the key is fetched again and, if it has changed, the request is
+    /// retried once with the new key. Rate limits and server errors are retried according to the
+    /// [`RetryPolicy`] if one is set. Any error after the request is built is returned with the
+    /// [`RequestContext`] of the request attached.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            name = "openai.request",
+            skip_all,
+            fields(
+                url = %url,
+                status_code = tracing::field::Empty,
+                attempts = tracing::field::Empty,
+                latency_ms = tracing::field::Empty,
+            )
+        )
+    )]
+    async fn send_authorized<T, R, F>(
+        &self,
+        body: &T,
+        url: &str,
+        estimated_tokens: usize,
+        build: impl Fn(&SecretString) -> RequestBuilder,
+        read: impl FnOnce(Response) -> F,
+    ) -> Result<R, OpenAIError>
+    where
+        T: Serialize,
+        F: Future<Output = Result<R, OpenAIError>>,
+    {
+        let api_key: SecretString = self.fetch_api_key().await?;
+        let started: Instant = Instant::now();
+        let mut attempt: u32 = 1;
+        let result: Result<R, OpenAIError> = async {
+            let response: Response = match self
+                .send(|| build(&api_key), estimated_tokens, &mut attempt)
+                .await
+            {
+                Err(OpenAIError::CODE401(error_body)) => {
+                    let refreshed: SecretString = self
+                        .api_key
+                        .refresh(&api_key)
+                        .await
+                        .map_err(OpenAIError::ErrorFetchingApiKey)?;
+                    if refreshed == api_key {
+                        return Err(OpenAIError::CODE401(error_body));
+                    }
+                    attempt += 1;
+                    self.send(|| build(&refreshed), estimated_tokens, &mut attempt)
+                        .await?
+                }
+                result => result?,
+            };
+            read(response).await
+        }
+        .await;
+        #[cfg(feature = "tracing")]
+        tracing::Span::current()
+            .record("attempts", attempt)
+            .record("latency_ms", started.elapsed().as_millis() as u64);
+        result.map_err(|error| {
+            error.with_context(RequestContext::new(url, body, started.elapsed(), attempt))
+        })
+    }
+
+    /// # [`OpenAIHttpClient::send`]
+    ///
+    /// Sends the built request and maps any error status codes. Retryable errors are sent
+    /// again after the delay from the [`RetryPolicy`], counting each attempt in `attempt`.
+    /// Once the attempts run out the last error is returned. Every attempt waits on the
+    /// rate limiter, if one is set, before it is sent.
+    async fn send(
+        &self,
+        build: impl Fn() -> RequestBuilder,
+        estimated_tokens: usize,
+        attempt: &mut u32,
+    ) -> Result<Response, OpenAIError> {
+        loop {
+            self.wait_for_capacity(estimated_tokens).await;
+            let request: RequestBuilder = build().timeout(self.http_config.timeout);
+            let response: Response = self.execute(request).await?;
+            #[cfg(feature = "tracing")]
+            tracing::Span::current().record("status_code", response.status().as_u16());
+            if response.status().is_success() {
+                return Ok(response);
+            }
+            let headers: HeaderMap = response.headers().clone();
+            let mapped_error: OpenAIError = Self::handle_error_response(response).await;
+            let delay = match (&self.retry_policy, &mapped_error) {
+                (
+                    Some(policy),
+                    OpenAIError::CODE429(_) | OpenAIError::CODE500(_) | OpenAIError::CODE503(_),
+                ) => policy.delay(*attempt, &headers),
+                _ => None,
+            };
+            match delay {
+                Some(delay) => {
+                    self.clock.sleep(delay).await;
+                    *attempt += 1;
+                }
+                None => return Err(mapped_error),
+            }
+        }
+    }
+
+    /// # [`OpenAIHttpClient::read_json`]
+    ///
+    /// Reads and deserializes the body of a successful response.
+    async fn read_json<U>(response: Response) -> Result<(U, HeaderMap), OpenAIError>
+    where
+        U: DeserializeOwned,
+    {
+        let status_code: StatusCode = response.status();
+        let headers: HeaderMap = response.headers().clone();
+        let response_body: String = response.text().await.map_err(|error| {
+            OpenAIError::from_reqwest(error, OpenAIError::ErrorGettingResponseBody)
+        })?;
+
+        let body: U = serde_json::from_str(&response_body).map_err(|error| {
+            OpenAIError::ErrorDeserializingResponseBody(status_code.as_u16(), error.to_string())
+        })?;
+        Ok((body, headers))
+    }
+
+    /// # [`OpenAIHttpClient::read_embedding_response`]
+    ///
+    /// Reads the body chunk by chunk, parsing each embedding as soon as it has arrived
+    /// rather than holding the whole body as text first.
+    #[cfg(feature = "openai-embeddings")]
+    async fn read_embedding_response(
+        mut response: Response,
+    ) -> Result<EmbeddingResponse, OpenAIError> {
+        let status_code: u16 = response.status().as_u16();
+        let to_error = |error: serde_json::Error| {
+            OpenAIError::ErrorDeserializingResponseBody(status_code, error.to_string())
+        };
+        let mut parser = EmbeddingResponseParser::default();
+        while let Some(chunk) = response.chunk().await.map_err(|error| {
+            OpenAIError::from_reqwest(error, OpenAIError::ErrorGettingResponseBody)
+        })? {
+            parser.push(&chunk).map_err(to_error)?;
+        }
+        parser.finish().map_err(to_error)
+    }
+
+    /// # [`OpenAIHttpClient::with_context_headers`]
+    ///
+    /// Attaches the request id of the context to the request as correlation headers
+    #[cfg(feature = "openai-chat")]
+    fn with_context_headers(
+        request: RequestBuilder,
+        context: &InvocationContext,
+    ) -> RequestBuilder {
+        let request_id: String = context.request_id().to_string();
+        request
+            .header(REQUEST_ID_HEADER, request_id.clone())
+            .header(CORRELATION_ID_HEADER, request_id)
+    }
+
     /// # [`OpenAIHttpClient::build_requeset`]
     ///
     /// Helper method to build a request with the correct headers and body
-    fn build_requeset<T>(&self, request_body: T, url: &str) -> RequestBuilder
+    fn build_requeset<T>(
+        &self,
+        request_body: &T,
+        url: &str,
+        api_key: &SecretString,
+    ) -> RequestBuilder
     where
         T: Serialize,
     {
         let content_type = HeaderValue::from_static("application/json");
-        self.client
-            .post(url)
-            .bearer_auth(self.api_key.clone())
+        let request: RequestBuilder = self.client.post(url).headers(self.headers.clone());
+        let request: RequestBuilder = match &self.auth_style {
+            AuthStyle::Bearer => request.bearer_auth(api_key.expose_secret()),
+            AuthStyle::Header(header) => request.header(header, api_key.expose_secret()),
+        };
+        request
             .header(CONTENT_TYPE, content_type)
             .json(&request_body)
     }
@@ -150,8 +754,11 @@ impl OpenAIHttpClient {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use mockito::{Mock, Server, ServerGuard};
+    use crate::clients::secrets::tests::ScriptedProvider;
+    use mockito::{Matcher, Mock, Server, ServerGuard};
     use serde::{Deserialize, Serialize};
+    use std::sync::atomic::Ordering;
+    use std::time::Duration;
 
     const ERROR_RESPONSE: &'static str = r#"
     {
@@ -215,7 +822,7 @@ mod tests {
         let (client, mut server) = with_mocked_client().await;
         let mock = with_mocked_request(&mut server, status_code.into(), response_body);
         let error = client
-            .send_request::<RequestBody, RequestBody>(body, &server.url())
+            .send_request::<RequestBody, RequestBody>(body, &server.url(), 0)
             .await
             .unwrap_err();
         // The error here is the message from serde
@@ -224,7 +831,204 @@ mod tests {
             "expected value at line 1 column 1".into(),
         );
         mock.assert();
-        assert_eq!(expected_error, error);
+        assert_eq!(&expected_error, error.kind());
+    }
+
+    #[tokio::test]
+    async fn rejected_key_is_refreshed_and_retried_once() {
+        let mut server = Server::new_async().await;
+        let provider = ScriptedProvider::new(vec!["old key", "new key"]);
+        let client = OpenAIHttpClient::new_with_secret_provider(provider.clone());
+        let rejected = server
+            .mock("POST", "/")
+            .match_header("Authorization", "Bearer old key")
+            .with_status(401)
+            .with_header("content-type", "application/json")
+            .with_body(ERROR_RESPONSE)
+            .create();
+        let accepted = server
+            .mock("POST", "/")
+            .match_header("Authorization", "Bearer new key")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(r#"{"message": "hello"}"#)
+            .expect(2)
+            .create();
+
+        let body = || RequestBody {
+            message: "hello".into(),
+        };
+        let response: RequestBody = client.send_request(body(), &server.url(), 0).await.unwrap();
+        assert_eq!(response.message, "hello");
+        // The refreshed key is cached for the next request
+        client
+            .send_request::<RequestBody, RequestBody>(body(), &server.url(), 0)
+            .await
+            .unwrap();
+        rejected.assert();
+        accepted.assert();
+        assert_eq!(provider.fetches.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn rejected_key_is_not_retried_if_unchanged() {
+        let mut server = Server::new_async().await;
+        let provider = ScriptedProvider::new(vec!["old key"]);
+        let client = OpenAIHttpClient::new_with_secret_provider(provider.clone());
+        let mock = with_mocked_request(&mut server, 401, ERROR_RESPONSE).expect(1);
+        let body = RequestBody {
+            message: "hello".into(),
+        };
+        let error = client
+            .send_request::<RequestBody, RequestBody>(body, &server.url(), 0)
+            .await
+            .unwrap_err();
+        mock.assert();
+        assert!(matches!(error.kind(), OpenAIError::CODE401(_)));
+        assert_eq!(provider.fetches.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn failed_retry_reports_the_second_attempt() {
+        let mut server = Server::new_async().await;
+        let provider = ScriptedProvider::new(vec!["old key", "new key"]);
+        let client = OpenAIHttpClient::new_with_secret_provider(provider);
+        let _rejected = server
+            .mock("POST", "/v1/embeddings")
+            .match_header("Authorization", "Bearer old key")
+            .with_status(401)
+            .with_body(ERROR_RESPONSE)
+            .create();
+        let _failed = server
+            .mock("POST", "/v1/embeddings")
+            .match_header("Authorization", "Bearer new key")
+            .with_status(500)
+            .with_body(ERROR_RESPONSE)
+            .create();
+        let body = serde_json::json!({"model": "text-embedding-3-small", "input": "secret"});
+        let error = client
+            .send_request::<_, RequestBody>(body, &format!("{}/v1/embeddings", server.url()), 0)
+            .await
+            .unwrap_err();
+        assert!(matches!(error.kind(), OpenAIError::CODE500(_)));
+        let context = error.context().unwrap();
+        assert_eq!(context.endpoint, "/v1/embeddings");
+        assert_eq!(context.model.as_deref(), Some("text-embedding-3-small"));
+        assert_eq!(context.attempt, 2);
+        assert!(!error.to_string().contains("new key"));
+        assert!(!error.to_string().contains("secret"));
+    }
+
+    #[tokio::test]
+    async fn api_key_errors_have_no_context() {
+        let client = OpenAIHttpClient::new_with_secret_provider(Arc::new(EnvSecretProvider));
+        std::env::remove_var("OPENAI_API_KEY_UNSET");
+        let missing = CachedSecret::new(
+            Arc::new(EnvSecretProvider),
+            "OPENAI_API_KEY_UNSET",
+            DEFAULT_SECRET_TTL,
+        );
+        let client = OpenAIHttpClient {
+            api_key: missing,
+            ..client
+        };
+        let error = client
+            .send_request::<_, RequestBody>(serde_json::json!({}), "http://localhost", 0)
+            .await
+            .unwrap_err();
+        assert!(matches!(error, OpenAIError::ErrorFetchingApiKey(_)));
+        assert_eq!(error.context(), None);
+    }
+
+    #[tokio::test]
+    async fn azure_clients_send_the_api_key_header() {
+        std::env::set_var("AZURE_OPENAI_API_KEY", "azure key");
+        let mut server = Server::new_async().await;
+        let client = OpenAIHttpClient::try_new_azure().unwrap();
+        let mock = server
+            .mock("POST", "/")
+            .match_header("api-key", "azure key")
+            .match_header("Authorization", Matcher::Missing)
+            .with_status(429)
+            .with_header("content-type", "application/json")
+            .with_body(ERROR_RESPONSE)
+            .create();
+        let body = RequestBody {
+            message: "hello".into(),
+        };
+        let error = client
+            .send_request::<RequestBody, RequestBody>(body, &server.url(), 0)
+            .await
+            .unwrap_err();
+        mock.assert();
+        assert!(matches!(error.kind(), OpenAIError::CODE429(_)));
+    }
+
+    #[test]
+    fn azure_url_is_built_from_the_endpoint() {
+        std::env::set_var(
+            "AZURE_OPENAI_ENDPOINT",
+            "https://resource.openai.azure.com/",
+        );
+        let url = OpenAIHttpClient::azure_url("gpt-4o", "2024-06-01", "chat/completions");
+        assert_eq!(
+            url.unwrap(),
+            "https://resource.openai.azure.com/openai/deployments/gpt-4o/chat/completions?api-version=2024-06-01"
+        );
+    }
+
+    #[cfg(feature = "tracing")]
+    #[tokio::test]
+    async fn request_span_records_status_and_attempts_but_not_the_key() {
+        use crate::common::span_recorder::SpanRecorder;
+        let mut server = Server::new_async().await;
+        let provider = ScriptedProvider::new(vec!["secret key"]);
+        let client = OpenAIHttpClient::new_with_secret_provider(provider);
+        let mock = with_mocked_request(&mut server, 200, r#"{"message": "hello"}"#);
+        let recorder = SpanRecorder::default();
+        let _guard = tracing::subscriber::set_default(recorder.clone());
+
+        let body = RequestBody {
+            message: "hello".into(),
+        };
+        let _: RequestBody = client.send_request(body, &server.url(), 0).await.unwrap();
+
+        mock.assert();
+        let span = recorder.span("openai.request").unwrap();
+        assert_eq!(span.field("status_code"), Some("200"));
+        assert_eq!(span.field("attempts"), Some("1"));
+        assert!(span.field("latency_ms").is_some());
+        let recorded: String = format!("{:?} {:?}", recorder.spans(), recorder.events());
+        assert!(!recorded.contains("secret key"));
+    }
+
+    #[tokio::test]
+    async fn slow_response_times_out() {
+        let body = RequestBody {
+            message: "hello".into(),
+        };
+        let (mut client, mut server) = with_mocked_client().await;
+        client
+            .set_http_config(HttpConfig {
+                timeout: Duration::from_millis(100),
+                ..HttpConfig::default()
+            })
+            .unwrap();
+        // The request is only counted once the delayed body is written, so it is not asserted
+        let _mock = server
+            .mock("POST", "/")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_chunked_body(|writer| {
+                std::thread::sleep(Duration::from_millis(500));
+                writer.write_all(br#"{"message": "hello"}"#)
+            })
+            .create();
+        let error = client
+            .send_request::<RequestBody, RequestBody>(body, &server.url(), 0)
+            .await
+            .unwrap_err();
+        assert!(matches!(error.kind(), OpenAIError::Timeout(_)));
     }
 
     // Helper method to assert all known status codes are mapped correctly
@@ -235,11 +1039,11 @@ mod tests {
         let (client, mut server) = with_mocked_client().await;
         let mock = with_mocked_request(&mut server, status_code, ERROR_RESPONSE);
         let error: OpenAIError = client
-            .send_request::<RequestBody, RequestBody>(body, &server.url())
+            .send_request::<RequestBody, RequestBody>(body, &server.url(), 0)
             .await
             .unwrap_err();
         mock.assert();
-        assert_eq!(expected_error, error);
+        assert_eq!(&expected_error, error.kind());
     }
 
     // Method which mocks the response the server will give. this
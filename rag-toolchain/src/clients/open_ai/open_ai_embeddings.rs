@@ -1,13 +1,32 @@
+use crate::clients::open_ai::compatible::OpenAICompatible;
 use crate::clients::open_ai::model::embeddings::{
     BatchEmbeddingRequest, EmbeddingObject, EmbeddingRequest, EmbeddingResponse,
 };
 use crate::clients::open_ai::model::errors::OpenAIError;
 use crate::clients::open_ai::open_ai_core::OpenAIHttpClient;
 use crate::clients::traits::AsyncEmbeddingClient;
-use crate::common::{Chunk, Chunks, Embedding, OpenAIEmbeddingModel};
+use crate::clients::{
+    EmbeddingTaskType, HttpConfig, HttpConfigError, RateLimit, RateLimiter, RetryPolicy,
+    SecretProvider, TaskPrefixes,
+};
+use crate::common::{
+    Chunk, Chunks, Clock, Embedding, EmbeddingModel, EmbeddingModelMetadata, OpenAIEmbeddingModel,
+    TokenUsage,
+};
+use futures::stream::{self, StreamExt};
 use std::env::VarError;
+use std::num::NonZeroUsize;
+use std::ops::Range;
+use std::sync::Arc;
+use thiserror::Error;
 
 const OPENAI_EMBEDDING_URL: &str = "https://api.openai.com/v1/embeddings";
+/// Responses larger than this many bytes are parsed as they are read
+pub const DEFAULT_STREAMING_PARSE_THRESHOLD: u64 = 4 * 1024 * 1024;
+/// The most chunks sent in one request by default, the most OpenAI accepts
+pub const DEFAULT_MAX_BATCH_SIZE: usize = 2048;
+/// The most tokens sent in one request by default, the most OpenAI accepts
+pub const DEFAULT_MAX_BATCH_TOKENS: usize = 300_000;
 
 /// # [`OpenAIEmbeddingClient`]
 /// Allows for interacting with the OpenAI API to generate embeddings.
@@ -31,6 +50,12 @@ pub struct OpenAIEmbeddingClient {
     url: String,
     client: OpenAIHttpClient,
     embedding_model: OpenAIEmbeddingModel,
+    dimensions: Option<NonZeroUsize>,
+    streaming_parse_threshold: u64,
+    max_batch_size: NonZeroUsize,
+    max_batch_tokens: NonZeroUsize,
+    max_concurrent_batches: NonZeroUsize,
+    task_prefixes: TaskPrefixes,
 }
 
 impl OpenAIEmbeddingClient {
@@ -54,9 +79,616 @@ impl OpenAIEmbeddingClient {
             url: OPENAI_EMBEDDING_URL.into(),
             client,
             embedding_model,
+            dimensions: None,
+            streaming_parse_threshold: DEFAULT_STREAMING_PARSE_THRESHOLD,
+            max_batch_size: NonZeroUsize::new(DEFAULT_MAX_BATCH_SIZE).unwrap(),
+            max_batch_tokens: NonZeroUsize::new(DEFAULT_MAX_BATCH_TOKENS).unwrap(),
+            max_concurrent_batches: NonZeroUsize::MIN,
+            task_prefixes: TaskPrefixes::default(),
         })
     }
 
+    /// # [`OpenAIEmbeddingClient::try_new_with_retry`]
+    /// Constructor to create a new OpenAIEmbeddingClient which retries requests that fail
+    /// with a 429, 500 or 503, see [`RetryPolicy`].
+    ///
+    /// # Arguments
+    /// * `embedding_model`: [`OpenAIEmbeddingModel`] - The model to use for the embeddings
+    /// * `retry_policy`: [`RetryPolicy`] - How failed requests are retried
+    ///
+    /// # Errors
+    /// * [`VarError`] - If the OPENAI_API_KEY environment variable is not set.
+    ///
+    /// # Returns
+    /// * [`OpenAIEmbeddingClient`] - The newly created OpenAIEmbeddingClient
+    pub fn try_new_with_retry(
+        embedding_model: OpenAIEmbeddingModel,
+        retry_policy: RetryPolicy,
+    ) -> Result<OpenAIEmbeddingClient, VarError> {
+        Ok(Self::try_new(embedding_model)?.with_retry_policy(retry_policy))
+    }
+
+    /// # [`OpenAIEmbeddingClient::try_new_with_config`]
+    /// Constructor to create a new OpenAIEmbeddingClient whose requests use the given
+    /// timeouts, see [`HttpConfig`].
+    ///
+    /// # Arguments
+    /// * `embedding_model`: [`OpenAIEmbeddingModel`] - The model to use for the embeddings
+    /// * `http_config`: [`HttpConfig`] - The request and connect timeouts
+    ///
+    /// # Errors
+    /// * [`HttpConfigError::EnvVarError`] - If the OPENAI_API_KEY environment variable is not set.
+    /// * [`HttpConfigError::ClientBuildError`] - If the HTTP client could not be built.
+    ///
+    /// # Returns
+    /// * [`OpenAIEmbeddingClient`] - The newly created OpenAIEmbeddingClient
+    pub fn try_new_with_config(
+        embedding_model: OpenAIEmbeddingModel,
+        http_config: HttpConfig,
+    ) -> Result<OpenAIEmbeddingClient, HttpConfigError> {
+        Self::try_new(embedding_model)
+            .map_err(HttpConfigError::EnvVarError)?
+            .with_http_config(http_config)
+    }
+
+    /// # [`OpenAIEmbeddingClient::try_new_with_http_client`]
+    /// Constructor to create a new OpenAIEmbeddingClient which sends every request with the
+    /// given client. Use this to send requests through a proxy or to trust a custom certificate.
+    ///
+    /// # Arguments
+    /// * `embedding_model`: [`OpenAIEmbeddingModel`] - The model to use for the embeddings
+    /// * `http_client`: [`reqwest::Client`] - The preconfigured client to send requests with
+    ///
+    /// # Errors
+    /// * [`VarError`] - If the OPENAI_API_KEY environment variable is not set.
+    ///
+    /// # Returns
+    /// * [`OpenAIEmbeddingClient`] - The newly created OpenAIEmbeddingClient
+    pub fn try_new_with_http_client(
+        embedding_model: OpenAIEmbeddingModel,
+        http_client: reqwest::Client,
+    ) -> Result<OpenAIEmbeddingClient, VarError> {
+        Ok(Self::try_new(embedding_model)?.with_http_client(http_client))
+    }
+
+    /// # [`OpenAIEmbeddingClient::try_new_with_limits`]
+    /// Constructor to create a new OpenAIEmbeddingClient which waits before sending a request
+    /// until it fits within the rate limit, see [`RateLimiter`]. The tokens each request uses
+    /// are estimated with the model's tokenizer.
+    ///
+    /// # Arguments
+    /// * `embedding_model`: [`OpenAIEmbeddingModel`] - The model to use for the embeddings
+    /// * `rate_limit`: [`RateLimit`] - The requests and tokens per minute to stay within
+    ///
+    /// # Errors
+    /// * [`VarError`] - If the OPENAI_API_KEY environment variable is not set.
+    ///
+    /// # Returns
+    /// * [`OpenAIEmbeddingClient`] - The newly created OpenAIEmbeddingClient
+    pub fn try_new_with_limits(
+        embedding_model: OpenAIEmbeddingModel,
+        rate_limit: RateLimit,
+    ) -> Result<OpenAIEmbeddingClient, VarError> {
+        Ok(Self::try_new(embedding_model)?.with_rate_limiter(RateLimiter::new(rate_limit)))
+    }
+
+    /// # [`OpenAIEmbeddingClient::try_new_with_dimensions`]
+    /// Constructor to create a new OpenAIEmbeddingClient which asks OpenAI to shorten the
+    /// embeddings to the given number of dimensions. Only the text-embedding-3 models support
+    /// this. The client's [`EmbeddingModel::metadata`] reports the shortened dimensions so a
+    /// store created with `&client` sizes its vectors to match.
+    ///
+    /// # Arguments
+    /// * `embedding_model`: [`OpenAIEmbeddingModel`] - The model to use for the embeddings
+    /// * `dimensions`: [`NonZeroUsize`] - The number of dimensions the embeddings should have
+    ///
+    /// # Errors
+    /// * [`OpenAIEmbeddingConfigError::DimensionsNotSupported`] - If the model does not accept a dimensions parameter.
+    /// * [`OpenAIEmbeddingConfigError::DimensionsTooLarge`] - If the dimensions are larger than the model produces.
+    /// * [`OpenAIEmbeddingConfigError::EnvVarError`] - If the OPENAI_API_KEY environment variable is not set.
+    ///
+    /// # Returns
+    /// * [`OpenAIEmbeddingClient`] - The newly created OpenAIEmbeddingClient
+    pub fn try_new_with_dimensions(
+        embedding_model: OpenAIEmbeddingModel,
+        dimensions: NonZeroUsize,
+    ) -> Result<OpenAIEmbeddingClient, OpenAIEmbeddingConfigError> {
+        let native: usize = embedding_model.metadata().dimensions;
+        if embedding_model == OpenAIEmbeddingModel::TextEmbeddingAda002 {
+            return Err(OpenAIEmbeddingConfigError::DimensionsNotSupported(
+                embedding_model,
+            ));
+        }
+        if dimensions.get() > native {
+            return Err(OpenAIEmbeddingConfigError::DimensionsTooLarge {
+                requested: dimensions.get(),
+                max: native,
+            });
+        }
+        let mut client: OpenAIEmbeddingClient =
+            Self::try_new(embedding_model).map_err(OpenAIEmbeddingConfigError::EnvVarError)?;
+        client.dimensions = Some(dimensions);
+        Ok(client)
+    }
+
+    /// # [`OpenAIEmbeddingClient::try_new_azure`]
+    /// Constructor to create a new OpenAIEmbeddingClient for a model deployed on Azure OpenAI.
+    /// Requests are sent to the deployment on the resource in AZURE_OPENAI_ENDPOINT and
+    /// authenticated with the `api-key` header. Azure picks the model from the deployment, the
+    /// model given here sets the dimensions reported to stores so should match it.
+    ///
+    /// # Arguments
+    /// * `embedding_model`: [`OpenAIEmbeddingModel`] - The model the deployment serves
+    /// * `deployment`: &[`str`] - The name of the deployment
+    /// * `api_version`: &[`str`] - The Azure OpenAI API version e.g. `2024-06-01`
+    ///
+    /// # Errors
+    /// * [`VarError`] - If the AZURE_OPENAI_API_KEY or AZURE_OPENAI_ENDPOINT environment variables are not set.
+    ///
+    /// # Returns
+    /// * [`OpenAIEmbeddingClient`] - The newly created OpenAIEmbeddingClient
+    pub fn try_new_azure(
+        embedding_model: OpenAIEmbeddingModel,
+        deployment: &str,
+        api_version: &str,
+    ) -> Result<OpenAIEmbeddingClient, VarError> {
+        let client: OpenAIHttpClient = OpenAIHttpClient::try_new_azure()?;
+        Ok(OpenAIEmbeddingClient {
+            url: OpenAIHttpClient::azure_url(deployment, api_version, "embeddings")?,
+            client,
+            embedding_model,
+            dimensions: None,
+            streaming_parse_threshold: DEFAULT_STREAMING_PARSE_THRESHOLD,
+            max_batch_size: NonZeroUsize::new(DEFAULT_MAX_BATCH_SIZE).unwrap(),
+            max_batch_tokens: NonZeroUsize::new(DEFAULT_MAX_BATCH_TOKENS).unwrap(),
+            max_concurrent_batches: NonZeroUsize::MIN,
+            task_prefixes: TaskPrefixes::default(),
+        })
+    }
+
+    /// # [`OpenAIEmbeddingClient::new_with_secret_provider`]
+    /// Constructor to create a new OpenAIEmbeddingClient which fetches the OPENAI_API_KEY
+    /// secret from the provider instead of the environment, see [`SecretProvider`].
+    ///
+    /// # Arguments
+    /// * `embedding_model`: [`OpenAIEmbeddingModel`] - The model to use for the embeddings
+    /// * `provider`: impl [`SecretProvider`] - Where the API key is fetched from
+    ///
+    /// # Returns
+    /// * [`OpenAIEmbeddingClient`] - The newly created OpenAIEmbeddingClient
+    pub fn new_with_secret_provider(
+        embedding_model: OpenAIEmbeddingModel,
+        provider: impl SecretProvider + 'static,
+    ) -> OpenAIEmbeddingClient {
+        OpenAIEmbeddingClient {
+            url: OPENAI_EMBEDDING_URL.into(),
+            client: OpenAIHttpClient::new_with_secret_provider(Arc::new(provider)),
+            embedding_model,
+            dimensions: None,
+            streaming_parse_threshold: DEFAULT_STREAMING_PARSE_THRESHOLD,
+            max_batch_size: NonZeroUsize::new(DEFAULT_MAX_BATCH_SIZE).unwrap(),
+            max_batch_tokens: NonZeroUsize::new(DEFAULT_MAX_BATCH_TOKENS).unwrap(),
+            max_concurrent_batches: NonZeroUsize::MIN,
+            task_prefixes: TaskPrefixes::default(),
+        }
+    }
+
+    /// # [`OpenAIEmbeddingClient::try_new_with_url`]
+    /// Constructor to create a new OpenAIEmbeddingClient which sends requests to the given url
+    /// instead of the OpenAI API, authenticated with the OPENAI_API_KEY.
+    ///
+    /// # Arguments
+    /// * `embedding_model`: [`OpenAIEmbeddingModel`] - The model to use for the embeddings
+    /// * `url`: [`String`] - The url to use for the api call
+    ///
+    /// # Errors
+    /// * [`VarError`] - If the OPENAI_API_KEY environment variable is not set.
+    ///
+    /// # Returns
+    /// * [`OpenAIEmbeddingClient`] - The newly created OpenAIEmbeddingClient
+    pub fn try_new_with_url(
+        embedding_model: OpenAIEmbeddingModel,
+        url: String,
+    ) -> Result<OpenAIEmbeddingClient, VarError> {
+        let mut client: OpenAIEmbeddingClient = Self::try_new(embedding_model)?;
+        client.url = url;
+        Ok(client)
+    }
+
+    /// # [`OpenAIEmbeddingClient::new_compatible`]
+    /// Constructor to create a new OpenAIEmbeddingClient for a provider with an OpenAI
+    /// compatible API, requests are sent to `{base_url}/embeddings` with the API key and
+    /// headers of the config, see [`OpenAICompatible`].
+    ///
+    /// # Arguments
+    /// * `embedding_model`: [`OpenAIEmbeddingModel`] - The model to use for the embeddings
+    /// * `config`: &[`OpenAICompatible`] - The provider to send requests to
+    ///
+    /// # Returns
+    /// * [`OpenAIEmbeddingClient`] - The newly created OpenAIEmbeddingClient
+    pub fn new_compatible(
+        embedding_model: OpenAIEmbeddingModel,
+        config: &OpenAICompatible,
+    ) -> OpenAIEmbeddingClient {
+        OpenAIEmbeddingClient {
+            url: config.url("embeddings"),
+            client: OpenAIHttpClient::new_compatible(config),
+            embedding_model,
+            dimensions: None,
+            streaming_parse_threshold: DEFAULT_STREAMING_PARSE_THRESHOLD,
+            max_batch_size: NonZeroUsize::new(DEFAULT_MAX_BATCH_SIZE).unwrap(),
+            max_batch_tokens: NonZeroUsize::new(DEFAULT_MAX_BATCH_TOKENS).unwrap(),
+            max_concurrent_batches: NonZeroUsize::MIN,
+            task_prefixes: TaskPrefixes::default(),
+        }
+    }
+
+    /// # [`OpenAIEmbeddingClient::with_retry_policy`]
+    /// Requests which fail with a 429, 500 or 503 are retried according to the policy,
+    /// by default they are not retried. After the last attempt the error is returned.
+    ///
+    /// # Arguments
+    /// * `retry_policy`: [`RetryPolicy`] - How failed requests are retried
+    ///
+    /// # Returns
+    /// * [`OpenAIEmbeddingClient`] - The client with the retry policy set
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.client.set_retry_policy(retry_policy);
+        self
+    }
+
+    /// # [`OpenAIEmbeddingClient::with_clock`]
+    ///
+    /// Sets the clock the cached API key expires and failed requests wait out their retry delay with,
+    /// by default this is [`crate::common::SystemClock`]. Pass a `MockClock` (with the
+    /// `test-utils` feature) to control time in tests.
+    ///
+    /// # Arguments
+    /// * `clock`: [`Arc<dyn Clock>`] - the clock to measure time with.
+    ///
+    /// # Returns
+    /// * [`OpenAIEmbeddingClient`] - the client measuring time with the clock.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.client.set_clock(clock);
+        self
+    }
+
+    /// # [`OpenAIEmbeddingClient::with_http_config`]
+    /// Sets the request and connect timeouts, by default these are
+    /// [`crate::clients::DEFAULT_REQUEST_TIMEOUT`] and [`crate::clients::DEFAULT_CONNECT_TIMEOUT`].
+    ///
+    /// # Arguments
+    /// * `http_config`: [`HttpConfig`] - The request and connect timeouts
+    ///
+    /// # Errors
+    /// * [`HttpConfigError::ClientBuildError`] - if the HTTP client could not be built.
+    ///
+    /// # Returns
+    /// * [`OpenAIEmbeddingClient`] - The client with the timeouts set
+    pub fn with_http_config(mut self, http_config: HttpConfig) -> Result<Self, HttpConfigError> {
+        self.client.set_http_config(http_config)?;
+        Ok(self)
+    }
+
+    /// # [`OpenAIEmbeddingClient::with_http_client`]
+    /// Sends every request with the given client rather than one built from the
+    /// [`HttpConfig`]. The request timeout is still applied.
+    ///
+    /// # Arguments
+    /// * `http_client`: [`reqwest::Client`] - The preconfigured client to send requests with
+    ///
+    /// # Returns
+    /// * [`OpenAIEmbeddingClient`] - The client sending requests with the given client
+    pub fn with_http_client(mut self, http_client: reqwest::Client) -> Self {
+        self.client.set_http_client(http_client);
+        self
+    }
+
+    /// # [`OpenAIEmbeddingClient::with_rate_limiter`]
+    /// Each request waits on the limiter until it fits within the budget before it is sent.
+    /// Give clones of one limiter to several clients to keep them all within a single budget.
+    /// By default requests are not limited.
+    ///
+    /// # Arguments
+    /// * `rate_limiter`: [`RateLimiter`] - The budget requests are kept within
+    ///
+    /// # Returns
+    /// * [`OpenAIEmbeddingClient`] - The client with the rate limiter set
+    pub fn with_rate_limiter(mut self, rate_limiter: RateLimiter) -> Self {
+        self.client.set_rate_limiter(rate_limiter);
+        self
+    }
+
+    /// # [`OpenAIEmbeddingClient::with_streaming_parse_threshold`]
+    /// Responses larger than the threshold, or which do not state their size, are parsed
+    /// as they are read so each embedding is held once rather than also as JSON text.
+    /// This keeps memory bounded for very large batches with high dimension models.
+    /// Defaults to [`DEFAULT_STREAMING_PARSE_THRESHOLD`].
+    ///
+    /// # Arguments
+    /// * `threshold`: [`u64`] - The response size in bytes above which it is parsed as it is read
+    ///
+    /// # Returns
+    /// * [`OpenAIEmbeddingClient`] - The client with the threshold set
+    pub fn with_streaming_parse_threshold(mut self, threshold: u64) -> Self {
+        self.streaming_parse_threshold = threshold;
+        self
+    }
+
+    /// # [`OpenAIEmbeddingClient::with_max_batch_size`]
+    /// [`AsyncEmbeddingClient::generate_embeddings`] splits the chunks into requests of at
+    /// most this many chunks. Defaults to [`DEFAULT_MAX_BATCH_SIZE`].
+    ///
+    /// # Arguments
+    /// * `max_batch_size`: [`NonZeroUsize`] - The most chunks sent in one request
+    ///
+    /// # Returns
+    /// * [`OpenAIEmbeddingClient`] - The client with the batch size set
+    pub fn with_max_batch_size(mut self, max_batch_size: NonZeroUsize) -> Self {
+        self.max_batch_size = max_batch_size;
+        self
+    }
+
+    /// # [`OpenAIEmbeddingClient::with_max_batch_tokens`]
+    /// [`AsyncEmbeddingClient::generate_embeddings`] splits the chunks into requests of at
+    /// most this many tokens, a chunk larger than this is sent on its own.
+    /// Defaults to [`DEFAULT_MAX_BATCH_TOKENS`].
+    ///
+    /// # Arguments
+    /// * `max_batch_tokens`: [`NonZeroUsize`] - The most tokens sent in one request
+    ///
+    /// # Returns
+    /// * [`OpenAIEmbeddingClient`] - The client with the token limit set
+    pub fn with_max_batch_tokens(mut self, max_batch_tokens: NonZeroUsize) -> Self {
+        self.max_batch_tokens = max_batch_tokens;
+        self
+    }
+
+    /// # [`OpenAIEmbeddingClient::with_max_concurrent_batches`]
+    /// How many of the requests a large batch is split into are sent at once.
+    /// Defaults to one, sending them one after another. To set this for a single call
+    /// use [`OpenAIEmbeddingClient::generate_embeddings_concurrent`].
+    ///
+    /// When a request fails no further requests are sent and the ones still in flight are
+    /// cancelled. Their embeddings are not kept, even if OpenAI had already generated them,
+    /// so with more than one request in flight a failure can cost the tokens of the
+    /// cancelled requests as well as the failed one.
+    ///
+    /// # Arguments
+    /// * `max_concurrent_batches`: [`NonZeroUsize`] - The most requests in flight at once
+    ///
+    /// # Returns
+    /// * [`OpenAIEmbeddingClient`] - The client with the concurrency set
+    pub fn with_max_concurrent_batches(mut self, max_concurrent_batches: NonZeroUsize) -> Self {
+        self.max_concurrent_batches = max_concurrent_batches;
+        self
+    }
+
+    /// # [`OpenAIEmbeddingClient::with_task_prefix`]
+    /// Puts the prefix in front of the text embedded for the task through
+    /// [`AsyncEmbeddingClient::generate_embedding_for`], for models such as e5 which expect
+    /// `query: ` and `passage: `. The embeddings returned hold the chunks without the prefix
+    /// and the plain [`AsyncEmbeddingClient::generate_embedding`] never adds one.
+    ///
+    /// # Arguments
+    /// * `task`: [`EmbeddingTaskType`] - The task the prefix is for
+    /// * `prefix`: impl [`Into<String>`] - Put in front of the text embedded for the task
+    ///
+    /// # Returns
+    /// * [`OpenAIEmbeddingClient`] - The client with the prefix set
+    pub fn with_task_prefix(mut self, task: EmbeddingTaskType, prefix: impl Into<String>) -> Self {
+        self.task_prefixes.set(task, prefix.into());
+        self
+    }
+
+    /// # [`OpenAIEmbeddingClient::batch_ranges`]
+    /// Splits the chunks into ranges which are each within the batch size and token limits.
+    /// The chunks are only tokenized if their total length could exceed the token limit,
+    /// as no token is shorter than a byte.
+    ///
+    /// # Arguments
+    /// * `chunks`: &[`[Chunk]`] - The chunks to split
+    ///
+    /// # Returns
+    /// * [`Vec<Range<usize>>`] - The ranges of chunks to send in each request, in order
+    fn batch_ranges(&self, chunks: &[Chunk]) -> Vec<Range<usize>> {
+        let max_tokens: usize = self.max_batch_tokens.get();
+        let total_bytes: usize = chunks.iter().map(|chunk| chunk.content().len()).sum();
+        let token_counts: Vec<usize> = if total_bytes <= max_tokens {
+            vec![0; chunks.len()]
+        } else {
+            let tokenizer = self.embedding_model.metadata().tokenizer;
+            chunks
+                .iter()
+                .map(|chunk| match tokenizer.tokenize(chunk.content()) {
+                    Some(tokens) => tokens.len(),
+                    None => chunk.content().len(),
+                })
+                .collect()
+        };
+
+        let mut ranges: Vec<Range<usize>> = Vec::new();
+        let mut start: usize = 0;
+        let mut batch_tokens: usize = 0;
+        for (index, tokens) in token_counts.into_iter().enumerate() {
+            let full: bool = index - start == self.max_batch_size.get()
+                || (index > start && batch_tokens + tokens > max_tokens);
+            if full {
+                ranges.push(start..index);
+                start = index;
+                batch_tokens = 0;
+            }
+            batch_tokens += tokens;
+        }
+        if start < chunks.len() {
+            ranges.push(start..chunks.len());
+        }
+        ranges
+    }
+
+    /// # [`OpenAIEmbeddingClient::estimate_tokens`]
+    /// The tokens the chunks will use, only counted when a rate limiter needs them.
+    ///
+    /// # Arguments
+    /// * `chunks`: &[`[Chunk]`] - The chunks that will be sent in one request
+    ///
+    /// # Returns
+    /// * [`usize`] - The estimated tokens, 0 if the client is not rate limited
+    fn estimate_tokens(&self, chunks: &[Chunk]) -> usize {
+        if !self.client.is_rate_limited() {
+            return 0;
+        }
+        let tokenizer = self.embedding_model.metadata().tokenizer;
+        chunks
+            .iter()
+            .map(|chunk| match tokenizer.tokenize(chunk.content()) {
+                Some(tokens) => tokens.len(),
+                None => chunk.content().len(),
+            })
+            .sum()
+    }
+
+    /// # [`OpenAIEmbeddingClient::embed_batch`]
+    /// Sends a single request for the chunks.
+    ///
+    /// # Arguments
+    /// * `text`: [`Chunks`] - The chunks to embed in one request
+    ///
+    /// # Errors
+    /// * [`OpenAIError`] - If the request to OpenAI fails.
+    ///
+    /// # Returns
+    /// * [`Vec<Embedding>`] - The embeddings in the same order as the chunks
+    /// * [`TokenUsage`] - The tokens OpenAI reported for the request
+    async fn embed_batch(&self, text: Chunks) -> Result<(Vec<Embedding>, TokenUsage), OpenAIError> {
+        let input_text: Vec<String> = text
+            .iter()
+            .map(|chunk| (*chunk).content().to_string())
+            .collect();
+
+        let request_body = BatchEmbeddingRequest::builder()
+            .input(input_text)
+            .model(self.embedding_model)
+            .dimensions(self.dimensions.map(NonZeroUsize::get))
+            .build();
+
+        let response: EmbeddingResponse = self
+            .client
+            .send_embedding_request(
+                request_body,
+                &self.url,
+                self.streaming_parse_threshold,
+                self.estimate_tokens(&text),
+            )
+            .await?;
+        let usage: TokenUsage = TokenUsage::from(&response.usage);
+        Ok((
+            Self::handle_embedding_success_response(text, response),
+            usage,
+        ))
+    }
+
+    /// # [`OpenAIEmbeddingClient::generate_embeddings_with_usage`]
+    /// The same as [`OpenAIEmbeddingClient::generate_embeddings`] but also returns the
+    /// tokens OpenAI reported, added up across every request the chunks were split into.
+    ///
+    /// # Arguments
+    /// * `text`: [`Chunks`] - The text chunks/strings to generate an embeddings for.
+    ///
+    /// # Errors
+    /// * [`OpenAIError::EmptyChunk`] - If any chunk has no content, nothing is sent.
+    /// * [`OpenAIError`] - If the request to OpenAI fails.
+    /// * [`OpenAIError::BatchFailed`] - If the chunks were split and one of the requests
+    ///   failed, this holds the embeddings generated before the failure.
+    ///
+    /// # Returns
+    /// * [`Vec<Embedding>`] - The embeddings in the same order as the chunks
+    /// * [`TokenUsage`] - The total tokens used, `output_tokens` is always 0
+    pub async fn generate_embeddings_with_usage(
+        &self,
+        text: Chunks,
+    ) -> Result<(Vec<Embedding>, TokenUsage), OpenAIError> {
+        self.embed_batches(text, self.max_concurrent_batches).await
+    }
+
+    /// # [`OpenAIEmbeddingClient::generate_embeddings_concurrent`]
+    /// The same as [`OpenAIEmbeddingClient::generate_embeddings`] but up to `max_in_flight`
+    /// of the requests the chunks are split into are sent at once, whatever the client's
+    /// [`OpenAIEmbeddingClient::with_max_concurrent_batches`] is. The embeddings are still
+    /// returned in the order of the chunks. If a request fails the requests still in flight
+    /// are cancelled.
+    ///
+    /// # Arguments
+    /// * `text`: [`Chunks`] - The text chunks/strings to generate an embeddings for.
+    /// * `max_in_flight`: [`NonZeroUsize`] - The most requests sent at once
+    ///
+    /// # Errors
+    /// * [`OpenAIError::EmptyChunk`] - If any chunk has no content, nothing is sent.
+    /// * [`OpenAIError`] - If the request to OpenAI fails.
+    /// * [`OpenAIError::BatchFailed`] - If the chunks were split and one of the requests
+    ///   failed, this holds the index of the failed request and the embeddings generated
+    ///   before it.
+    ///
+    /// # Returns
+    /// * [`Vec<Embedding>`] - The embeddings in the same order as the chunks
+    pub async fn generate_embeddings_concurrent(
+        &self,
+        text: Chunks,
+        max_in_flight: NonZeroUsize,
+    ) -> Result<Vec<Embedding>, OpenAIError> {
+        let (embeddings, _usage) = self.embed_batches(text, max_in_flight).await?;
+        Ok(embeddings)
+    }
+
+    /// # [`OpenAIEmbeddingClient::embed_batches`]
+    /// Splits the chunks into requests with [`OpenAIEmbeddingClient::batch_ranges`] and sends
+    /// up to `max_in_flight` of them at once.
+    async fn embed_batches(
+        &self,
+        text: Chunks,
+        max_in_flight: NonZeroUsize,
+    ) -> Result<(Vec<Embedding>, TokenUsage), OpenAIError> {
+        // OpenAI rejects an empty input and empty text, so neither is sent
+        if text.is_empty() {
+            return Ok((Vec::new(), TokenUsage::default()));
+        }
+        if let Some(index) = text.iter().position(|chunk| chunk.content().is_empty()) {
+            return Err(OpenAIError::EmptyChunk(index));
+        }
+        let ranges: Vec<Range<usize>> = self.batch_ranges(&text);
+        if ranges.len() <= 1 {
+            return self.embed_batch(text).await;
+        }
+
+        // Results come back in the order the batches were sent however many are in flight,
+        // returning early drops the stream which cancels the requests still in flight
+        let mut results = stream::iter(ranges.into_iter().enumerate())
+            .map(|(index, range)| {
+                let batch: Chunks = text[range.clone()].to_vec();
+                async move { (index, range, self.embed_batch(batch).await) }
+            })
+            .buffered(max_in_flight.get());
+        let mut embeddings: Vec<Embedding> = Vec::with_capacity(text.len());
+        let mut usage: TokenUsage = TokenUsage::default();
+        while let Some((index, range, result)) = results.next().await {
+            match result {
+                Ok((batch, batch_usage)) => {
+                    embeddings.extend(batch);
+                    usage += batch_usage;
+                }
+                Err(error) => {
+                    return Err(OpenAIError::BatchFailed {
+                        batch: index,
+                        failed: range,
+                        completed: embeddings,
+                        source: Box::new(error),
+                    })
+                }
+            }
+        }
+        Ok((embeddings, usage))
+    }
+
     /// # [`OpenAIEmbeddingClient::handle_embedding_success_response`]
     /// Takes a successful response and maps it into a vector of string embedding pairs
     /// assumption made the two iters will zip up 1:1 (as this should be the case)
@@ -86,30 +718,27 @@ impl AsyncEmbeddingClient for OpenAIEmbeddingClient {
 
     /// # [`OpenAIEmbeddingClient::generate_embeddings`]
     /// Function to generate embeddings for [`Chunks`].
-    /// Allows you to get an embedding for multiple strings.
+    /// Allows you to get an embedding for multiple strings. Chunks beyond the batch size or
+    /// token limits of a single request are split across several requests, see
+    /// [`OpenAIEmbeddingClient::with_max_batch_size`]. To get the token usage as well use
+    /// [`OpenAIEmbeddingClient::generate_embeddings_with_usage`].
     ///
     /// # Arguments
     /// * `text`: [`Chunk`] - The text chunks/strings to generate an embeddings for.
     ///
     /// # Errors
+    /// * [`OpenAIError::EmptyChunk`] - If any chunk has no content, nothing is sent.
     /// * [`OpenAIError`] - If the request to OpenAI fails.
+    /// * [`OpenAIError::BatchFailed`] - If the chunks were split and one of the requests
+    ///   failed, this holds the embeddings generated before the failure. Requests still in
+    ///   flight are cancelled, see [`OpenAIEmbeddingClient::with_max_concurrent_batches`].
     ///
     /// # Returns
     /// * [`Vec<Embedding>`] - A result containing
     ///     pairs of the original text and the embedding that was generated.
     async fn generate_embeddings(&self, text: Chunks) -> Result<Vec<Embedding>, OpenAIError> {
-        let input_text: Vec<String> = text
-            .iter()
-            .map(|chunk| (*chunk).content().to_string())
-            .collect();
-
-        let request_body = BatchEmbeddingRequest::builder()
-            .input(input_text)
-            .model(self.embedding_model)
-            .build();
-
-        let response: EmbeddingResponse = self.client.send_request(request_body, &self.url).await?;
-        Ok(Self::handle_embedding_success_response(text, response))
+        let (embeddings, _usage) = self.generate_embeddings_with_usage(text).await?;
+        Ok(embeddings)
     }
 
     /// # [`OpenAIEmbeddingClient::generate_embedding`]
@@ -120,25 +749,118 @@ impl AsyncEmbeddingClient for OpenAIEmbeddingClient {
     /// * `text`: [`Chunk`] - The text chunk/string to generate an embedding for.
     ///
     /// # Errors
+    /// * [`OpenAIError::EmptyChunk`] - If the chunk has no content, nothing is sent.
     /// * [`OpenAIError`] - If the request to OpenAI fails.
     ///
     /// # Returns
     /// * [`Embedding`] - the generated embedding
     async fn generate_embedding(&self, text: Chunk) -> Result<Embedding, Self::ErrorType> {
+        if text.content().is_empty() {
+            return Err(OpenAIError::EmptyChunk(0));
+        }
+        let estimated_tokens: usize = self.estimate_tokens(std::slice::from_ref(&text));
         let request_body = EmbeddingRequest::builder()
             .input(text.content().to_string())
             .model(self.embedding_model)
+            .dimensions(self.dimensions.map(NonZeroUsize::get))
             .build();
-        let response: EmbeddingResponse = self.client.send_request(request_body, &self.url).await?;
+        let response: EmbeddingResponse = self
+            .client
+            .send_embedding_request(
+                request_body,
+                &self.url,
+                self.streaming_parse_threshold,
+                estimated_tokens,
+            )
+            .await?;
         Ok(Self::handle_embedding_success_response(vec![text], response)[0].clone())
     }
+
+    /// # [`OpenAIEmbeddingClient::generate_embedding_for`]
+    /// Embeds the chunk with the prefix set for the task, see [`OpenAIEmbeddingClient::with_task_prefix`].
+    ///
+    /// # Errors
+    /// * [`OpenAIError`] - If the request fails.
+    async fn generate_embedding_for(
+        &self,
+        text: Chunk,
+        task: EmbeddingTaskType,
+    ) -> Result<Embedding, Self::ErrorType> {
+        match self.task_prefixes.apply(std::slice::from_ref(&text), task) {
+            None => self.generate_embedding(text).await,
+            Some(mut prefixed) => {
+                let embedding: Embedding = self.generate_embedding(prefixed.remove(0)).await?;
+                Ok(Embedding::new(text, embedding.vector_slice()))
+            }
+        }
+    }
+
+    /// # [`OpenAIEmbeddingClient::generate_embeddings_for`]
+    /// Embeds the chunks with the prefix set for the task, see [`OpenAIEmbeddingClient::with_task_prefix`].
+    ///
+    /// # Errors
+    /// * [`OpenAIError`] - If a request fails.
+    async fn generate_embeddings_for(
+        &self,
+        text: Chunks,
+        task: EmbeddingTaskType,
+    ) -> Result<Vec<Embedding>, Self::ErrorType> {
+        match self.task_prefixes.apply(&text, task) {
+            None => self.generate_embeddings(text).await,
+            Some(prefixed) => Ok(TaskPrefixes::restore(
+                self.generate_embeddings(prefixed).await?,
+                text,
+            )),
+        }
+    }
+
+    /// # [`OpenAIEmbeddingClient::dimensions`]
+    ///
+    /// # Returns
+    /// * [`Option<usize>`] - the dimension of the embeddings this client produces.
+    fn dimensions(&self) -> Option<usize> {
+        Some(self.metadata().dimensions)
+    }
+}
+
+/// The metadata of the client's model with the dimensions set by
+/// [`OpenAIEmbeddingClient::try_new_with_dimensions`] if there are any.
+impl EmbeddingModel for OpenAIEmbeddingClient {
+    fn metadata(&self) -> EmbeddingModelMetadata {
+        let mut metadata: EmbeddingModelMetadata = self.embedding_model.metadata();
+        if let Some(dimensions) = self.dimensions {
+            metadata.dimensions = dimensions.get();
+        }
+        metadata
+    }
+}
+
+/// # [`OpenAIEmbeddingConfigError`]
+/// The errors that can occur when creating an [`OpenAIEmbeddingClient`] with dimensions.
+#[derive(Error, Debug, PartialEq)]
+pub enum OpenAIEmbeddingConfigError {
+    /// The model always produces embeddings of its own size
+    #[error("{0:?} does not support the dimensions parameter")]
+    DimensionsNotSupported(OpenAIEmbeddingModel),
+    /// Embeddings can only be shortened, not lengthened
+    #[error("{requested} dimensions is larger than the maximum of {max}")]
+    DimensionsTooLarge { requested: usize, max: usize },
+    #[error("Environment Variable Error: {0}")]
+    EnvVarError(VarError),
 }
 
 #[cfg(test)]
 mod embedding_client_tests {
     use super::*;
+    use crate::clients::cassette::RecordingHttpClient;
     use crate::clients::open_ai::model::errors::{OpenAIErrorBody, OpenAIErrorData};
-    use mockito::{Mock, Server, ServerGuard};
+    use crate::common::MockClock;
+    use mockito::{Matcher, Mock, Server, ServerGuard};
+    use reqwest::header::{HeaderName, HeaderValue};
+    use std::num::NonZeroU32;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
 
     const EMBEDDING_RESPONSE: &'static str = r#"
     {
@@ -214,6 +936,212 @@ mod embedding_client_tests {
         assert_eq!(response.vector(), expected_embedding);
     }
 
+    #[tokio::test]
+    async fn dimensions_come_from_the_model() {
+        let (client, _server) = with_mocked_client().await;
+        assert_eq!(client.dimensions(), Some(1536));
+    }
+
+    #[tokio::test]
+    async fn dimensions_are_sent_and_reported_when_set() {
+        std::env::set_var("OPENAI_API_KEY", "fake key");
+        let mut server = Server::new_async().await;
+        let model = OpenAIEmbeddingModel::TextEmbedding3Large;
+        let dimensions = NonZeroUsize::new(1024).unwrap();
+        let mut client = OpenAIEmbeddingClient::try_new_with_dimensions(model, dimensions).unwrap();
+        client.url = server.url();
+        assert_eq!(client.dimensions(), Some(1024));
+        assert_eq!(client.metadata().dimensions, 1024);
+        assert_eq!(client.metadata().max_tokens, 8192);
+
+        let mock = server
+            .mock("POST", "/")
+            .match_body(Matcher::PartialJson(
+                serde_json::json!({"dimensions": 1024}),
+            ))
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(EMBEDDING_RESPONSE)
+            .expect(2)
+            .create();
+        client
+            .generate_embeddings(vec![Chunk::new("Test-0"), Chunk::new("Test-1")])
+            .await
+            .unwrap();
+        client
+            .generate_embedding(Chunk::new("Test-0"))
+            .await
+            .unwrap();
+        mock.assert();
+    }
+
+    #[tokio::test]
+    async fn task_prefixes_are_sent_but_not_returned() {
+        let (client, mut server) = with_mocked_client().await;
+        let client = client
+            .with_task_prefix(EmbeddingTaskType::Document, "passage: ")
+            .with_task_prefix(EmbeddingTaskType::Query, "query: ");
+        let mock_request = |server: &mut ServerGuard, input: serde_json::Value| {
+            server
+                .mock("POST", "/")
+                .match_body(Matcher::PartialJson(serde_json::json!({ "input": input })))
+                .with_status(200)
+                .with_header("content-type", "application/json")
+                .with_body(EMBEDDING_RESPONSE)
+                .create()
+        };
+        let documents = mock_request(
+            &mut server,
+            serde_json::json!(["passage: Test-0", "passage: Test-1"]),
+        );
+        let query = mock_request(&mut server, serde_json::json!("query: Test-0"));
+        let plain = mock_request(&mut server, serde_json::json!(["Test-0", "Test-1"]));
+        let chunks: Chunks = vec![Chunk::new("Test-0"), Chunk::new("Test-1")];
+
+        let embeddings = client
+            .generate_embeddings_for(chunks.clone(), EmbeddingTaskType::Document)
+            .await
+            .unwrap();
+        assert_eq!(*embeddings[1].chunk(), Chunk::new("Test-1"));
+        let embedding = client
+            .generate_embedding_for(Chunk::new("Test-0"), EmbeddingTaskType::Query)
+            .await
+            .unwrap();
+        assert_eq!(*embedding.chunk(), Chunk::new("Test-0"));
+        client.generate_embeddings(chunks).await.unwrap();
+        documents.assert();
+        query.assert();
+        plain.assert();
+    }
+
+    #[tokio::test]
+    async fn empty_input_is_never_sent() {
+        let (client, mut server) = with_mocked_client().await;
+        let mock = server.mock("POST", "/").expect(0).create();
+
+        let embeddings = client.generate_embeddings(Vec::new()).await.unwrap();
+        assert!(embeddings.is_empty());
+        let error = client.generate_embedding(Chunk::new("")).await.unwrap_err();
+        assert!(matches!(error, OpenAIError::EmptyChunk(0)));
+        let error = client
+            .generate_embeddings(vec![Chunk::new("Test-0"), Chunk::new("")])
+            .await
+            .unwrap_err();
+        assert!(matches!(error, OpenAIError::EmptyChunk(1)));
+        mock.assert();
+    }
+
+    #[test]
+    fn invalid_dimensions_are_rejected() {
+        std::env::set_var("OPENAI_API_KEY", "fake key");
+        let result = OpenAIEmbeddingClient::try_new_with_dimensions(
+            OpenAIEmbeddingModel::TextEmbedding3Small,
+            NonZeroUsize::new(1537).unwrap(),
+        );
+        assert!(matches!(
+            result,
+            Err(OpenAIEmbeddingConfigError::DimensionsTooLarge {
+                requested: 1537,
+                max: 1536
+            })
+        ));
+
+        let result = OpenAIEmbeddingClient::try_new_with_dimensions(
+            OpenAIEmbeddingModel::TextEmbeddingAda002,
+            NonZeroUsize::new(256).unwrap(),
+        );
+        assert!(matches!(
+            result,
+            Err(OpenAIEmbeddingConfigError::DimensionsNotSupported(
+                OpenAIEmbeddingModel::TextEmbeddingAda002
+            ))
+        ));
+    }
+
+    #[tokio::test]
+    async fn azure_client_sends_to_the_deployment() {
+        std::env::set_var("AZURE_OPENAI_API_KEY", "azure key");
+        std::env::set_var(
+            "AZURE_OPENAI_ENDPOINT",
+            "https://resource.openai.azure.com/",
+        );
+        let model = OpenAIEmbeddingModel::TextEmbedding3Small;
+        let mut client =
+            OpenAIEmbeddingClient::try_new_azure(model, "embedder", "2024-06-01").unwrap();
+        assert_eq!(
+            client.url,
+            "https://resource.openai.azure.com/openai/deployments/embedder/embeddings?api-version=2024-06-01"
+        );
+
+        let mut server = Server::new_async().await;
+        client.url = format!("{}/embeddings?api-version=2024-06-01", server.url());
+        let mock = server
+            .mock("POST", "/embeddings")
+            .match_query(Matcher::UrlEncoded(
+                "api-version".into(),
+                "2024-06-01".into(),
+            ))
+            .match_header("api-key", "azure key")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(EMBEDDING_RESPONSE)
+            .create();
+        let response = client
+            .generate_embeddings(vec![Chunk::new("Test-0"), Chunk::new("Test-1")])
+            .await
+            .unwrap();
+        mock.assert();
+        assert_eq!(response.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn compatible_client_sends_the_given_key_and_headers() {
+        let mut server = Server::new_async().await;
+        let config = OpenAICompatible::new(format!("{}/v1", server.url()), "together key")
+            .with_header(
+                HeaderName::from_static("x-organisation"),
+                HeaderValue::from_static("org-1"),
+            );
+        let model = OpenAIEmbeddingModel::TextEmbedding3Small;
+        let client = OpenAIEmbeddingClient::new_compatible(model, &config);
+        let mock = server
+            .mock("POST", "/v1/embeddings")
+            .match_header("authorization", "Bearer together key")
+            .match_header("x-organisation", "org-1")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(EMBEDDING_RESPONSE)
+            .create();
+        let response = client
+            .generate_embeddings(vec![Chunk::new("Test-0"), Chunk::new("Test-1")])
+            .await
+            .unwrap();
+        mock.assert();
+        assert_eq!(response.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn compatible_client_sends_the_key_in_a_custom_header() {
+        let mut server = Server::new_async().await;
+        let config = OpenAICompatible::new(server.url(), "gateway key")
+            .with_auth_header(HeaderName::from_static("x-api-key"));
+        let model = OpenAIEmbeddingModel::TextEmbedding3Small;
+        let client = OpenAIEmbeddingClient::new_compatible(model, &config);
+        let mock = server
+            .mock("POST", "/embeddings")
+            .match_header("x-api-key", "gateway key")
+            .match_header("authorization", Matcher::Missing)
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(EMBEDDING_RESPONSE)
+            .create();
+        client
+            .generate_embeddings(vec![Chunk::new("Test-0"), Chunk::new("Test-1")])
+            .await
+            .unwrap();
+        mock.assert();
+    }
+
     #[tokio::test]
     async fn test_400_gives_correct_error() {
         let (client, mut server) = with_mocked_client().await;
@@ -230,11 +1158,393 @@ mod embedding_client_tests {
         let chunks: Chunks = vec![Chunk::new("Test-0"), Chunk::new("Test-1")];
         let response = client.generate_embeddings(chunks).await.unwrap_err();
         mock.assert();
-        assert_eq!(response, expected_response);
+        assert_eq!(response.kind(), &expected_response);
+        let context = response.context().unwrap();
+        assert_eq!(context.endpoint, "/");
+        assert_eq!(context.model.as_deref(), Some("text-embedding-ada-002"));
+        assert_eq!(context.attempt, 1);
+        assert!(response
+            .to_string()
+            .contains("(endpoint = /, model = text-embedding-ada-002, elapsed = "));
         // Test single request
         let chunk = Chunk::new("Test-0");
         let response = client.generate_embedding(chunk).await.unwrap_err();
-        assert_eq!(response, expected_response);
+        assert_eq!(response.kind(), &expected_response);
+    }
+
+    #[tokio::test]
+    async fn large_responses_parse_the_same_streamed_or_not() {
+        let data: Vec<String> = (0..200)
+            .map(|index| {
+                let vector: Vec<String> = (0..3072)
+                    .map(|i| format!("{}", ((index * 3072 + i) as f32 * 1e-4).sin()))
+                    .collect();
+                format!(
+                    r#"{{"object": "embedding", "index": {}, "embedding": [{}]}}"#,
+                    index,
+                    vector.join(", ")
+                )
+            })
+            .collect();
+        let body: String = format!(
+            r#"{{"object": "list", "data": [{}], "model": "text-embedding-3-large", "usage": {{"prompt_tokens": 200, "total_tokens": 200}}}}"#,
+            data.join(",\n")
+        );
+        let chunks: Chunks = (0..200)
+            .map(|i| Chunk::new(format!("Test-{}", i)))
+            .collect();
+
+        let (client, mut server) = with_mocked_client().await;
+        let mock = with_mocked_request(&mut server, 200, &body).expect(2);
+        let client = client.with_streaming_parse_threshold(u64::MAX);
+        let simple = client.generate_embeddings(chunks.clone()).await.unwrap();
+        let client = client.with_streaming_parse_threshold(0);
+        let streamed = client.generate_embeddings(chunks).await.unwrap();
+        mock.assert();
+        assert_eq!(streamed.len(), 200);
+        assert_eq!(streamed, simple);
+    }
+
+    #[tokio::test]
+    async fn streamed_parse_reports_truncated_bodies() {
+        let (client, mut server) = with_mocked_client().await;
+        let truncated: &str = &EMBEDDING_RESPONSE[..EMBEDDING_RESPONSE.len() / 2];
+        let mock = with_mocked_request(&mut server, 200, truncated);
+        let error = client
+            .with_streaming_parse_threshold(0)
+            .generate_embeddings(vec![Chunk::new("Test-0")])
+            .await
+            .unwrap_err();
+        mock.assert();
+        assert!(matches!(
+            error.kind(),
+            OpenAIError::ErrorDeserializingResponseBody(200, _)
+        ));
+    }
+
+    #[tokio::test]
+    async fn generate_embeddings_replays_cassette() {
+        let recorder = Arc::new(RecordingHttpClient::load("open_ai_embeddings"));
+        let client = with_cassette_client(recorder.clone());
+        let chunks: Chunks = vec![Chunk::new("Test-0"), Chunk::new("Test-1")];
+        let response = client.generate_embeddings(chunks).await.unwrap();
+        assert_eq!(response.len(), 2);
+        assert_eq!(*response[0].chunk(), Chunk::new("Test-0"));
+        assert_eq!(*response[1].chunk(), Chunk::new("Test-1"));
+        assert_eq!(response[1].vector()[0], 0.0023064255);
+        assert_eq!(recorder.unused_interactions(), 0);
+    }
+
+    #[tokio::test]
+    async fn generate_embeddings_rate_limited_then_succeeds_replays_cassette() {
+        let recorder = Arc::new(RecordingHttpClient::load("open_ai_embeddings_rate_limited"));
+        let client = with_cassette_client(recorder.clone());
+        let chunks: Chunks = vec![Chunk::new("Test-0"), Chunk::new("Test-1")];
+        let error = client
+            .generate_embeddings(chunks.clone())
+            .await
+            .unwrap_err();
+        assert!(matches!(error.kind(), OpenAIError::CODE429(_)));
+        let response = client.generate_embeddings(chunks).await.unwrap();
+        assert_eq!(response.len(), 2);
+        assert_eq!(recorder.unused_interactions(), 0);
+    }
+
+    #[tokio::test]
+    async fn rate_limited_request_is_retried_after_retry_after_replays_cassette() {
+        let recorder = Arc::new(RecordingHttpClient::load("open_ai_embeddings_rate_limited"));
+        let client = with_cassette_client(recorder.clone()).with_retry_policy(RetryPolicy {
+            base_delay: Duration::from_secs(60),
+            ..RetryPolicy::default()
+        });
+        let chunks: Chunks = vec![Chunk::new("Test-0"), Chunk::new("Test-1")];
+        let started = Instant::now();
+        let response = client.generate_embeddings(chunks).await.unwrap();
+        // The cassette's Retry-After of 1 second is used instead of the base delay
+        assert!(started.elapsed() >= Duration::from_secs(1));
+        assert!(started.elapsed() < Duration::from_secs(60));
+        assert_eq!(response.len(), 2);
+        assert_eq!(recorder.unused_interactions(), 0);
+    }
+
+    #[tokio::test]
+    async fn retryable_errors_are_retried_until_attempts_run_out() {
+        let (client, mut server) = with_mocked_client().await;
+        let client = client.with_retry_policy(with_fast_retry(3));
+        let mock = with_mocked_request(&mut server, 503, ERROR_RESPONSE).expect(3);
+        let error = client
+            .generate_embeddings(vec![Chunk::new("Test-0")])
+            .await
+            .unwrap_err();
+        mock.assert();
+        assert!(matches!(error.kind(), OpenAIError::CODE503(_)));
+        assert_eq!(error.context().unwrap().attempt, 3);
+    }
+
+    #[tokio::test]
+    async fn retry_stops_once_a_request_succeeds() {
+        let (client, mut server) = with_mocked_client().await;
+        let client = client.with_retry_policy(with_fast_retry(5));
+        // mockito serves the first mock which has not had a request yet
+        let failed = with_mocked_request(&mut server, 500, ERROR_RESPONSE);
+        let succeeded = with_mocked_request(&mut server, 200, EMBEDDING_RESPONSE);
+        let response = client
+            .generate_embeddings(vec![Chunk::new("Test-0"), Chunk::new("Test-1")])
+            .await
+            .unwrap();
+        failed.assert();
+        succeeded.assert();
+        assert_eq!(response.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn non_retryable_errors_are_not_retried() {
+        let (client, mut server) = with_mocked_client().await;
+        let client = client.with_retry_policy(with_fast_retry(3));
+        for status_code in [400, 401] {
+            let mock = with_mocked_request(&mut server, status_code, ERROR_RESPONSE).expect(1);
+            let error = client
+                .generate_embedding(Chunk::new("Test-0"))
+                .await
+                .unwrap_err();
+            mock.assert();
+            mock.remove();
+            assert_eq!(error.context().unwrap().attempt, 1);
+        }
+    }
+
+    #[tokio::test]
+    async fn large_batches_are_split_and_returned_in_order() {
+        let (client, mut server) = with_mocked_client().await;
+        let client = client
+            .with_max_batch_size(NonZeroUsize::new(2).unwrap())
+            .with_max_concurrent_batches(NonZeroUsize::new(2).unwrap());
+        let mock = with_mocked_request(&mut server, 200, EMBEDDING_RESPONSE).expect(3);
+        let chunks: Chunks = (0..6).map(|i| Chunk::new(format!("Test-{}", i))).collect();
+        let (response, usage) = client
+            .generate_embeddings_with_usage(chunks.clone())
+            .await
+            .unwrap();
+        mock.assert();
+        let returned: Chunks = response.iter().map(|e| e.chunk().clone()).collect();
+        assert_eq!(returned, chunks);
+        // Each of the three requests reported 5 prompt tokens
+        assert_eq!(usage, TokenUsage::new(15, 0));
+        assert_eq!(usage.total_tokens(), 15);
+    }
+
+    #[tokio::test]
+    async fn failed_batch_reports_the_range_and_completed_embeddings() {
+        let (client, mut server) = with_mocked_client().await;
+        let client = client.with_max_batch_size(NonZeroUsize::new(2).unwrap());
+        let failed = server
+            .mock("POST", "/")
+            .match_body(Matcher::PartialJson(
+                serde_json::json!({"input": ["Test-2", "Test-3"]}),
+            ))
+            .with_status(500)
+            .with_header("content-type", "application/json")
+            .with_body(ERROR_RESPONSE)
+            .create();
+        let succeeded = with_mocked_request(&mut server, 200, EMBEDDING_RESPONSE);
+        let chunks: Chunks = (0..6).map(|i| Chunk::new(format!("Test-{}", i))).collect();
+        let error = client.generate_embeddings(chunks).await.unwrap_err();
+        failed.assert();
+        succeeded.assert();
+        let OpenAIError::BatchFailed {
+            batch,
+            failed,
+            completed,
+            ..
+        } = &error
+        else {
+            panic!("expected a batch failure, got {error:?}");
+        };
+        assert_eq!(*batch, 1);
+        assert_eq!(*failed, 2..4);
+        assert_eq!(completed.len(), 2);
+        assert_eq!(*completed[1].chunk(), Chunk::new("Test-1"));
+        assert!(matches!(error.kind(), OpenAIError::CODE500(_)));
+        assert_eq!(error.context().unwrap().attempt, 1);
+    }
+
+    #[tokio::test]
+    async fn concurrent_batches_overlap_and_keep_their_order() {
+        let (client, mut server) = with_mocked_client().await;
+        let client = client.with_max_batch_size(NonZeroUsize::new(2).unwrap());
+        // Each request waits for all four to arrive before responding, sent one after
+        // another none of them would see the others so they would give up waiting
+        let arrived = Arc::new(AtomicUsize::new(0));
+        let overlapped = Arc::new(AtomicUsize::new(0));
+        let (arrived_in_mock, overlapped_in_mock) = (arrived.clone(), overlapped.clone());
+        let mock = server
+            .mock("POST", "/")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_chunked_body(move |writer| {
+                arrived_in_mock.fetch_add(1, Ordering::SeqCst);
+                let deadline = Instant::now() + Duration::from_secs(5);
+                while arrived_in_mock.load(Ordering::SeqCst) < 4 && Instant::now() < deadline {
+                    std::thread::sleep(Duration::from_millis(5));
+                }
+                if arrived_in_mock.load(Ordering::SeqCst) == 4 {
+                    overlapped_in_mock.fetch_add(1, Ordering::SeqCst);
+                }
+                writer.write_all(EMBEDDING_RESPONSE.as_bytes())
+            })
+            .expect(4)
+            .create();
+        let chunks: Chunks = (0..8).map(|i| Chunk::new(format!("Test-{}", i))).collect();
+        let response = client
+            .generate_embeddings_concurrent(chunks.clone(), NonZeroUsize::new(4).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(arrived.load(Ordering::SeqCst), 4);
+        assert_eq!(overlapped.load(Ordering::SeqCst), 4);
+        mock.assert();
+        let returned: Chunks = response.iter().map(|e| e.chunk().clone()).collect();
+        assert_eq!(returned, chunks);
+    }
+
+    #[tokio::test]
+    async fn failed_batch_cancels_the_batches_in_flight() {
+        let (client, mut server) = with_mocked_client().await;
+        let client = client
+            .with_max_batch_size(NonZeroUsize::new(2).unwrap())
+            .with_max_concurrent_batches(NonZeroUsize::new(2).unwrap());
+        let failed = server
+            .mock("POST", "/")
+            .match_body(Matcher::PartialJson(
+                serde_json::json!({"input": ["Test-0", "Test-1"]}),
+            ))
+            .with_status(500)
+            .with_header("content-type", "application/json")
+            .with_body(ERROR_RESPONSE)
+            .create();
+        // Sent alongside the failed request but still running when it fails
+        let in_flight = server
+            .mock("POST", "/")
+            .match_body(Matcher::PartialJson(
+                serde_json::json!({"input": ["Test-2", "Test-3"]}),
+            ))
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_chunked_body(|writer| {
+                std::thread::sleep(Duration::from_millis(300));
+                writer.write_all(EMBEDDING_RESPONSE.as_bytes())
+            })
+            .expect_at_most(1)
+            .create();
+        let not_sent = with_mocked_request(&mut server, 200, EMBEDDING_RESPONSE).expect(0);
+        let chunks: Chunks = (0..8).map(|i| Chunk::new(format!("Test-{}", i))).collect();
+
+        let error = client.generate_embeddings(chunks).await.unwrap_err();
+
+        failed.assert();
+        in_flight.assert();
+        not_sent.assert();
+        let OpenAIError::BatchFailed {
+            batch, completed, ..
+        } = &error
+        else {
+            panic!("expected a batch failure, got {error:?}");
+        };
+        assert_eq!(*batch, 0);
+        // The batch in flight was cancelled so none of its embeddings are returned
+        assert!(completed.is_empty());
+    }
+
+    #[tokio::test]
+    async fn batches_are_split_by_tokens() {
+        let (client, _server) = with_mocked_client().await;
+        let client = client.with_max_batch_tokens(NonZeroUsize::new(5).unwrap());
+        let chunks: Chunks = vec![
+            Chunk::new("hello world"),
+            Chunk::new("hello world"),
+            Chunk::new("hello world hello world hello world"),
+            Chunk::new("hello"),
+        ];
+        assert_eq!(client.batch_ranges(&chunks), vec![0..2, 2..3, 3..4]);
+        assert_eq!(client.batch_ranges(&chunks[..1]), vec![0..1]);
+        assert_eq!(client.batch_ranges(&[]), Vec::<Range<usize>>::new());
+    }
+
+    #[tokio::test]
+    async fn rate_limited_requests_wait_for_capacity() {
+        // A mock clock rather than paused time, which would fire the request timeout
+        let (client, mut server) = with_mocked_client().await;
+        let clock = Arc::new(MockClock::new());
+        let limiter = RateLimiter::with_clock(RateLimit { rpm: 1, tpm: 1000 }, clock.clone());
+        let client = client.with_rate_limiter(limiter);
+        let mock = with_mocked_request(&mut server, 200, EMBEDDING_RESPONSE).expect(2);
+        client
+            .generate_embedding(Chunk::new("Test-0"))
+            .await
+            .unwrap();
+        let advance = async {
+            while clock.pending_sleeps() == 0 {
+                tokio::task::yield_now().await;
+            }
+            clock.advance(Duration::from_secs(60));
+        };
+        let (second, _) = tokio::join!(client.generate_embedding(Chunk::new("Test-1")), advance);
+        second.unwrap();
+        mock.assert();
+    }
+
+    #[tokio::test]
+    async fn retry_delays_are_waited_out_on_the_client_clock() {
+        let (client, mut server) = with_mocked_client().await;
+        let clock = Arc::new(MockClock::new());
+        let client = client
+            .with_clock(clock.clone())
+            .with_retry_policy(RetryPolicy {
+                max_attempts: NonZeroU32::new(2).unwrap(),
+                base_delay: Duration::from_secs(3600),
+                max_delay: Duration::from_secs(3600),
+            });
+        let failed = with_mocked_request(&mut server, 503, ERROR_RESPONSE);
+        let succeeded = with_mocked_request(&mut server, 200, EMBEDDING_RESPONSE);
+        let advance = async {
+            while clock.pending_sleeps() == 0 {
+                tokio::task::yield_now().await;
+            }
+            clock.advance(Duration::from_secs(3600));
+        };
+        let (response, _) = tokio::join!(client.generate_embedding(Chunk::new("Test-0")), advance);
+        response.unwrap();
+        failed.assert();
+        succeeded.assert();
+    }
+
+    #[tokio::test]
+    async fn tokens_are_only_estimated_when_rate_limited() {
+        let (client, _server) = with_mocked_client().await;
+        let chunks: Chunks = vec![Chunk::new("hello world"), Chunk::new("hello")];
+        assert_eq!(client.estimate_tokens(&chunks), 0);
+        let limit = RateLimit { rpm: 10, tpm: 1000 };
+        let client = client.with_rate_limiter(RateLimiter::new(limit));
+        assert_eq!(client.estimate_tokens(&chunks), 3);
+    }
+
+    fn with_fast_retry(max_attempts: u32) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: NonZeroU32::new(max_attempts).unwrap(),
+            base_delay: Duration::from_millis(1),
+            max_delay: Duration::from_millis(5),
+        }
+    }
+
+    // Returns a client pointed at the real OpenAI url which serves responses from
+    // the cassette, in record mode the real OPENAI_API_KEY is used.
+    fn with_cassette_client(recorder: Arc<RecordingHttpClient>) -> OpenAIEmbeddingClient {
+        if std::env::var("OPENAI_API_KEY").is_err() {
+            std::env::set_var("OPENAI_API_KEY", "fake key");
+        }
+        let model = OpenAIEmbeddingModel::TextEmbeddingAda002;
+        let mut client = OpenAIEmbeddingClient::try_new(model).unwrap();
+        client.client.set_recorder(recorder);
+        client
     }
 
     // Method which mocks the response the server will give. this
@@ -259,8 +1569,7 @@ mod embedding_client_tests {
         let server = Server::new_async().await;
         let url = server.url();
         let model = OpenAIEmbeddingModel::TextEmbeddingAda002;
-        let mut client = OpenAIEmbeddingClient::try_new(model).unwrap();
-        client.url = url;
+        let client = OpenAIEmbeddingClient::try_new_with_url(model, url).unwrap();
         (client, server)
     }
 }
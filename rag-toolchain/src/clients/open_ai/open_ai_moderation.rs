@@ -0,0 +1,436 @@
+use std::env::VarError;
+use std::sync::Arc;
+
+use crate::clients::open_ai::compatible::OpenAICompatible;
+use crate::clients::open_ai::model::moderations::{
+    ModerationRequest, ModerationResponse, OpenAIModerationModel,
+};
+use crate::clients::open_ai::open_ai_core::OpenAIHttpClient;
+use crate::clients::{
+    HttpConfig, HttpConfigError, ModerationError, ModerationFuture, ModerationVerdict, Moderator,
+    RetryPolicy, SecretProvider,
+};
+use crate::common::Clock;
+
+use super::model::errors::OpenAIError;
+
+/// # [`OpenAIModerationClient`]
+/// Checks text against the OpenAI moderation endpoint, it can be used on its own or as the
+/// [`Moderator`] of a [`crate::chains::ModerationPolicy`] to refuse flagged user messages.
+/// # Examples
+/// ```
+/// use rag_toolchain::clients::*;
+/// async fn check_message() {
+///     let client: OpenAIModerationClient = OpenAIModerationClient::try_new().unwrap();
+///     let response = client.classify("I want to hurt someone").await.unwrap();
+///     println!("{:?}", response.flagged_categories());
+/// }
+/// ```
+/// # Required Environment Variables
+/// OPENAI_API_KEY: The API key to use for the OpenAI API
+pub struct OpenAIModerationClient {
+    url: String,
+    client: OpenAIHttpClient,
+    model: OpenAIModerationModel,
+}
+
+impl OpenAIModerationClient {
+    const OPENAI_MODERATIONS_URL: &str = "https://api.openai.com/v1/moderations";
+
+    /// # [`OpenAIModerationClient::try_new`]
+    ///
+    /// This method creates a new OpenAIModerationClient using
+    /// [`OpenAIModerationModel::OmniModerationLatest`].
+    ///
+    /// # Errors
+    /// * [`VarError`] - if the OPENAI_API_KEY environment variable is not set.
+    ///
+    /// # Returns
+    /// * [`OpenAIModerationClient`] - the moderation client.
+    pub fn try_new() -> Result<OpenAIModerationClient, VarError> {
+        Self::try_new_with_url(Self::OPENAI_MODERATIONS_URL.into())
+    }
+
+    /// # [`OpenAIModerationClient::try_new_with_url`]
+    ///
+    /// This method creates a new OpenAIModerationClient. You can pass the url in directly.
+    ///
+    /// # Arguments
+    /// * `url`: [`String`] - The url to use for the api call.
+    ///
+    /// # Errors
+    /// * [`VarError`] - if the OPENAI_API_KEY environment variable is not set.
+    ///
+    /// # Returns
+    /// * [`OpenAIModerationClient`] - the moderation client.
+    pub fn try_new_with_url(url: String) -> Result<OpenAIModerationClient, VarError> {
+        let client: OpenAIHttpClient = OpenAIHttpClient::try_new()?;
+        Ok(OpenAIModerationClient {
+            url,
+            client,
+            model: OpenAIModerationModel::default(),
+        })
+    }
+
+    /// # [`OpenAIModerationClient::new_with_secret_provider`]
+    ///
+    /// This method creates a new OpenAIModerationClient which fetches the OPENAI_API_KEY
+    /// secret from the provider instead of the environment, see [`SecretProvider`].
+    ///
+    /// # Arguments
+    /// * `provider`: impl [`SecretProvider`] - Where the API key is fetched from.
+    ///
+    /// # Returns
+    /// * [`OpenAIModerationClient`] - the moderation client.
+    pub fn new_with_secret_provider(
+        provider: impl SecretProvider + 'static,
+    ) -> OpenAIModerationClient {
+        OpenAIModerationClient {
+            url: Self::OPENAI_MODERATIONS_URL.into(),
+            client: OpenAIHttpClient::new_with_secret_provider(Arc::new(provider)),
+            model: OpenAIModerationModel::default(),
+        }
+    }
+
+    /// # [`OpenAIModerationClient::new_compatible`]
+    ///
+    /// This method creates a new OpenAIModerationClient for a provider with an OpenAI
+    /// compatible API, requests are sent to `{base_url}/moderations`, see [`OpenAICompatible`].
+    ///
+    /// # Arguments
+    /// * `config`: &[`OpenAICompatible`] - The provider to send requests to.
+    ///
+    /// # Returns
+    /// * [`OpenAIModerationClient`] - the moderation client.
+    pub fn new_compatible(config: &OpenAICompatible) -> OpenAIModerationClient {
+        OpenAIModerationClient {
+            url: config.url("moderations"),
+            client: OpenAIHttpClient::new_compatible(config),
+            model: OpenAIModerationModel::default(),
+        }
+    }
+
+    /// # [`OpenAIModerationClient::with_model`]
+    ///
+    /// # Arguments
+    /// * `model`: [`OpenAIModerationModel`] - the moderation model to use.
+    ///
+    /// # Returns
+    /// * [`OpenAIModerationClient`] - the client using the given model.
+    pub fn with_model(mut self, model: OpenAIModerationModel) -> Self {
+        self.model = model;
+        self
+    }
+
+    /// # [`OpenAIModerationClient::with_retry_policy`]
+    ///
+    /// Requests which fail with a 429, 500 or 503 are retried according to the policy, by
+    /// default they are not retried. After the last attempt the error is returned.
+    ///
+    /// # Arguments
+    /// * `retry_policy`: [`RetryPolicy`] - how failed requests are retried.
+    ///
+    /// # Returns
+    /// * [`OpenAIModerationClient`] - the client with the retry policy set.
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.client.set_retry_policy(retry_policy);
+        self
+    }
+
+    /// # [`OpenAIModerationClient::with_clock`]
+    ///
+    /// Sets the clock the cached API key expires and failed requests wait out their retry delay with,
+    /// by default this is [`crate::common::SystemClock`]. Pass a `MockClock` (with the
+    /// `test-utils` feature) to control time in tests.
+    ///
+    /// # Arguments
+    /// * `clock`: [`Arc<dyn Clock>`] - the clock to measure time with.
+    ///
+    /// # Returns
+    /// * [`OpenAIModerationClient`] - the client measuring time with the clock.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.client.set_clock(clock);
+        self
+    }
+
+    /// # [`OpenAIModerationClient::with_http_config`]
+    ///
+    /// Sets the request and connect timeouts, by default these are
+    /// [`crate::clients::DEFAULT_REQUEST_TIMEOUT`] and [`crate::clients::DEFAULT_CONNECT_TIMEOUT`].
+    ///
+    /// # Arguments
+    /// * `http_config`: [`HttpConfig`] - the request and connect timeouts.
+    ///
+    /// # Errors
+    /// * [`HttpConfigError::ClientBuildError`] - if the HTTP client could not be built.
+    ///
+    /// # Returns
+    /// * [`OpenAIModerationClient`] - the client with the timeouts set.
+    pub fn with_http_config(mut self, http_config: HttpConfig) -> Result<Self, HttpConfigError> {
+        self.client.set_http_config(http_config)?;
+        Ok(self)
+    }
+
+    /// # [`OpenAIModerationClient::model`]
+    ///
+    /// # Returns
+    /// * &[`OpenAIModerationModel`] - the moderation model in use.
+    pub fn model(&self) -> &OpenAIModerationModel {
+        &self.model
+    }
+
+    /// # [`OpenAIModerationClient::classify`]
+    ///
+    /// Sends the text to the moderation endpoint and returns the full response, including
+    /// the score of every category.
+    ///
+    /// # Arguments
+    /// * `input`: &[`str`] - the text to check.
+    ///
+    /// # Errors
+    /// * [`OpenAIError`] - if the request fails.
+    ///
+    /// # Returns
+    /// * [`ModerationResponse`] - whether the text was flagged, for which categories and their scores.
+    pub async fn classify(&self, input: &str) -> Result<ModerationResponse, OpenAIError> {
+        let body = ModerationRequest {
+            model: self.model.clone(),
+            input: input.into(),
+        };
+        self.client.send_request(body, &self.url, 0).await
+    }
+}
+
+impl Moderator for OpenAIModerationClient {
+    /// # [`OpenAIModerationClient::moderate`]
+    ///
+    /// The text is flagged if OpenAI flagged it, the categories are the OpenAI category
+    /// names such as `harassment` or `self-harm/intent`.
+    ///
+    /// # Arguments
+    /// * `text`: &[`str`] - the text to check.
+    ///
+    /// # Errors
+    /// * [`ModerationError`] - holding the [`OpenAIError`] if the request fails.
+    ///
+    /// # Returns
+    /// * [`ModerationFuture`] - resolves to whether the text was flagged and why.
+    fn moderate<'a>(&'a self, text: &'a str) -> ModerationFuture<'a> {
+        Box::pin(async move {
+            let response: ModerationResponse = self
+                .classify(text)
+                .await
+                .map_err(|error| ModerationError(error.to_string()))?;
+            Ok(ModerationVerdict {
+                flagged: response.flagged(),
+                categories: response.flagged_categories(),
+            })
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mockito::{Matcher, Mock, Server, ServerGuard};
+
+    const FLAGGED_RESPONSE: &str = r#"
+    {
+        "id": "modr-970d409ef3bef3b70c73d8232df86e7d",
+        "model": "omni-moderation-latest",
+        "results": [{
+            "flagged": true,
+            "categories": {
+                "sexual": false,
+                "sexual/minors": false,
+                "harassment": true,
+                "harassment/threatening": true,
+                "hate": false,
+                "hate/threatening": false,
+                "illicit": false,
+                "illicit/violent": false,
+                "self-harm": false,
+                "self-harm/intent": false,
+                "self-harm/instructions": false,
+                "violence": true,
+                "violence/graphic": false
+            },
+            "category_scores": {
+                "sexual": 0.0000216,
+                "sexual/minors": 0.0000024,
+                "harassment": 0.8104,
+                "harassment/threatening": 0.7023,
+                "hate": 0.0113,
+                "hate/threatening": 0.0021,
+                "illicit": 0.0347,
+                "illicit/violent": 0.0142,
+                "self-harm": 0.0000062,
+                "self-harm/intent": 0.0000021,
+                "self-harm/instructions": 0.0000011,
+                "violence": 0.9171,
+                "violence/graphic": 0.0031
+            }
+        }]
+    }
+    "#;
+
+    // The text models do not return the illicit categories
+    const NOT_FLAGGED_RESPONSE: &str = r#"
+    {
+        "id": "modr-XXXXX",
+        "model": "text-moderation-007",
+        "results": [{
+            "flagged": false,
+            "categories": {
+                "sexual": false,
+                "hate": false,
+                "harassment": false,
+                "self-harm": false,
+                "sexual/minors": false,
+                "hate/threatening": false,
+                "violence/graphic": false,
+                "self-harm/intent": false,
+                "self-harm/instructions": false,
+                "harassment/threatening": false,
+                "violence": false
+            },
+            "category_scores": {
+                "sexual": 0.0001,
+                "hate": 0.0001,
+                "harassment": 0.0002,
+                "self-harm": 0.0000,
+                "sexual/minors": 0.0000,
+                "hate/threatening": 0.0000,
+                "violence/graphic": 0.0000,
+                "self-harm/intent": 0.0000,
+                "self-harm/instructions": 0.0000,
+                "harassment/threatening": 0.0000,
+                "violence": 0.0003
+            }
+        }]
+    }
+    "#;
+
+    const ERROR_RESPONSE: &str = r#"
+    {
+        "error": {
+            "message": "Incorrect API key provided",
+            "type": "invalid_request_error",
+            "param": null,
+            "code": "invalid_api_key"
+        }
+    }
+    "#;
+
+    #[tokio::test]
+    async fn classify_sends_the_model_and_input() {
+        let (client, mut server) = with_mocked_client().await;
+        let mock = server
+            .mock("POST", "/")
+            .match_body(Matcher::Json(serde_json::json!({
+                "model": "omni-moderation-latest",
+                "input": "I want to hurt someone"
+            })))
+            .with_status(200)
+            .with_header("Content-Type", "application/json")
+            .with_body(FLAGGED_RESPONSE)
+            .create();
+        let response = client.classify("I want to hurt someone").await.unwrap();
+        mock.assert();
+        assert!(response.flagged());
+        let result = &response.results[0];
+        assert!(result.categories.harassment_threatening);
+        assert_eq!(result.categories.illicit, Some(false));
+        assert_eq!(result.category_scores.violence, 0.9171);
+        assert_eq!(
+            response.flagged_categories(),
+            vec!["harassment", "harassment/threatening", "violence"]
+        );
+    }
+
+    #[tokio::test]
+    async fn classify_with_a_custom_model_sends_its_name() {
+        let (client, mut server) = with_mocked_client().await;
+        let client = client.with_model(OpenAIModerationModel::TextModerationLatest);
+        let mock = server
+            .mock("POST", "/")
+            .match_body(Matcher::PartialJson(
+                serde_json::json!({ "model": "text-moderation-latest" }),
+            ))
+            .with_status(200)
+            .with_header("Content-Type", "application/json")
+            .with_body(NOT_FLAGGED_RESPONSE)
+            .create();
+        let response = client.classify("What is the weather like?").await.unwrap();
+        mock.assert();
+        assert!(!response.flagged());
+        assert_eq!(response.results[0].categories.illicit, None);
+        assert!(response.flagged_categories().is_empty());
+    }
+
+    #[tokio::test]
+    async fn moderate_returns_the_flagged_categories() {
+        let (client, mut server) = with_mocked_client().await;
+        let mock = with_mocked_request(&mut server, 200, FLAGGED_RESPONSE);
+        let verdict = client.moderate("I want to hurt someone").await.unwrap();
+        mock.assert();
+        assert_eq!(
+            verdict,
+            ModerationVerdict {
+                flagged: true,
+                categories: vec![
+                    "harassment".into(),
+                    "harassment/threatening".into(),
+                    "violence".into()
+                ],
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn moderate_allows_text_which_is_not_flagged() {
+        let (client, mut server) = with_mocked_client().await;
+        let mock = with_mocked_request(&mut server, 200, NOT_FLAGGED_RESPONSE);
+        let verdict = client.moderate("What is the weather like?").await.unwrap();
+        mock.assert();
+        assert_eq!(verdict, ModerationVerdict::default());
+    }
+
+    #[tokio::test]
+    async fn classify_maps_error_status_codes() {
+        let (client, mut server) = with_mocked_client().await;
+        let mock = with_mocked_request(&mut server, 401, ERROR_RESPONSE);
+        let error = client.classify("hello").await.unwrap_err();
+        mock.assert();
+        assert!(matches!(error.kind(), OpenAIError::CODE401(_)));
+    }
+
+    #[tokio::test]
+    async fn moderate_wraps_the_request_error() {
+        let (client, mut server) = with_mocked_client().await;
+        let mock = with_mocked_request(&mut server, 500, ERROR_RESPONSE);
+        let error = client.moderate("hello").await.unwrap_err();
+        mock.assert();
+        assert!(error.0.contains("Server error"));
+    }
+
+    fn with_mocked_request(
+        server: &mut ServerGuard,
+        status_code: usize,
+        response_body: &str,
+    ) -> Mock {
+        server
+            .mock("POST", "/")
+            .with_status(status_code)
+            .with_header("Content-Type", "application/json")
+            .with_body(response_body)
+            .create()
+    }
+
+    async fn with_mocked_client() -> (OpenAIModerationClient, ServerGuard) {
+        std::env::set_var("OPENAI_API_KEY", "fake key");
+        let server = Server::new_async().await;
+        let client = OpenAIModerationClient::try_new_with_url(server.url()).unwrap();
+        (client, server)
+    }
+}
@@ -0,0 +1,152 @@
+use crate::clients::PromptMessage;
+
+/// The tokens OpenAI adds around every message in a chat request, see
+/// <https://cookbook.openai.com/examples/how_to_count_tokens_with_tiktoken>
+pub const TOKENS_PER_MESSAGE: usize = 3;
+
+/// The tokens OpenAI adds when a message sets the speaker's name
+pub const TOKENS_PER_NAME: usize = 1;
+
+/// The tokens OpenAI adds to every request to prime the assistant's reply
+pub const REPLY_PRIMING_TOKENS: usize = 3;
+
+/// # [`count_message_tokens`]
+///
+/// Estimates the tokens a single message uses in a chat request, this is the content along
+/// with its role, its name if it has one and [`TOKENS_PER_MESSAGE`]. The overhead is the one
+/// OpenAI documents, other providers are close enough to use it as an estimate.
+///
+/// # Arguments
+/// * `message`: &[`PromptMessage`] - the message to count.
+/// * `count_tokens`: impl [`Fn(&str) -> usize`] - counts the tokens in text with the model's
+///   tokenizer, e.g. [`crate::clients::AsyncChatClient::count_tokens`].
+///
+/// # Returns
+/// * [`usize`] - the estimated number of tokens.
+pub fn count_message_tokens(
+    message: &PromptMessage,
+    count_tokens: impl Fn(&str) -> usize,
+) -> usize {
+    let name_tokens: usize = message
+        .meta()
+        .and_then(|meta| meta.name.as_deref())
+        .map_or(0, |name| TOKENS_PER_NAME + count_tokens(name));
+    TOKENS_PER_MESSAGE + count_tokens(message.role()) + count_tokens(&message.text()) + name_tokens
+}
+
+/// # [`count_prompt_tokens`]
+///
+/// Estimates the tokens a chat request for the messages uses, so a prompt can be fitted to a
+/// budget before it is sent. Each message is counted with [`count_message_tokens`] and the
+/// request adds [`REPLY_PRIMING_TOKENS`].
+///
+/// # Examples
+/// ```
+/// use rag_toolchain::clients::*;
+///
+/// let messages = vec![
+///     PromptMessage::SystemMessage("You are a helpful assistant".into()),
+///     PromptMessage::HumanMessage("What is the refund policy?".into()),
+/// ];
+/// let tokenizer = OpenAIModel::Gpt4o.tokenizer();
+/// let tokens: usize = count_prompt_tokens(&messages, |text| tokenizer.count_tokens(text));
+/// ```
+///
+/// # Arguments
+/// * `prompt_messages`: &[`[PromptMessage]`] - the messages that will be sent.
+/// * `count_tokens`: impl [`Fn(&str) -> usize`] - counts the tokens in text with the model's
+///   tokenizer.
+///
+/// # Returns
+/// * [`usize`] - the estimated number of tokens.
+pub fn count_prompt_tokens(
+    prompt_messages: &[PromptMessage],
+    count_tokens: impl Fn(&str) -> usize,
+) -> usize {
+    prompt_messages
+        .iter()
+        .map(|message| count_message_tokens(message, &count_tokens))
+        .sum::<usize>()
+        + REPLY_PRIMING_TOKENS
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clients::{MessageBody, MessageMeta};
+
+    fn words(text: &str) -> usize {
+        text.split_whitespace().count()
+    }
+
+    #[test]
+    fn message_counts_the_role_and_overhead() {
+        let message = PromptMessage::HumanMessage("what is it".into());
+        assert_eq!(count_message_tokens(&message, words), 3 + 1 + 3);
+    }
+
+    #[test]
+    fn message_counts_the_name() {
+        let message = PromptMessage::AIMessage(
+            MessageBody::from("it is").with_meta(MessageMeta::default().with_name("bot")),
+        );
+        assert_eq!(count_message_tokens(&message, words), 3 + 1 + 2 + 1 + 1);
+    }
+
+    #[test]
+    fn prompt_adds_the_reply_priming() {
+        let messages = vec![
+            PromptMessage::SystemMessage("be brief".into()),
+            PromptMessage::HumanMessage("what is it".into()),
+        ];
+        assert_eq!(count_prompt_tokens(&messages, words), 6 + 7 + 3);
+        assert_eq!(count_prompt_tokens(&[], words), REPLY_PRIMING_TOKENS);
+    }
+
+    #[cfg(feature = "openai-chat")]
+    #[test]
+    fn prompt_matches_the_documented_example() {
+        use crate::clients::OpenAIModel;
+
+        let named = |content: &str, name: &str| {
+            PromptMessage::SystemMessage(
+                MessageBody::from(content).with_meta(MessageMeta::default().with_name(name)),
+            )
+        };
+        // The example from the OpenAI cookbook, which the API reports as 129 prompt tokens
+        let messages = vec![
+            PromptMessage::SystemMessage(
+                "You are a helpful, pattern-following assistant that translates corporate \
+                jargon into plain English."
+                    .into(),
+            ),
+            named(
+                "New synergies will help drive top-line growth.",
+                "example_user",
+            ),
+            named(
+                "Things working well together will increase revenue.",
+                "example_assistant",
+            ),
+            named(
+                "Let's circle back when we have more bandwidth to touch base on opportunities \
+                for increased leverage.",
+                "example_user",
+            ),
+            named(
+                "Let's talk later when we're less busy about how to do better.",
+                "example_assistant",
+            ),
+            PromptMessage::HumanMessage(
+                "This late pivot means we don't have time to boil the ocean for the client \
+                deliverable."
+                    .into(),
+            ),
+        ];
+        let tokenizer = OpenAIModel::Gpt4.tokenizer();
+        assert_eq!(
+            count_prompt_tokens(&messages, |text| tokenizer.count_tokens(text)),
+            129
+        );
+    }
+}
@@ -0,0 +1,209 @@
+use crate::common::{Clock, SystemClock};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// # [`RateLimit`]
+///
+/// The budget a client keeps its requests within, matching the limits OpenAI gives
+/// each account. A limit of zero is treated as one.
+///
+/// * `rpm` - the most requests sent per minute.
+/// * `tpm` - the most tokens sent per minute.
+///
+/// # Examples
+/// ```
+/// use rag_toolchain::clients::*;
+///
+/// let limit = RateLimit {
+///     rpm: 3000,
+///     tpm: 1_000_000,
+/// };
+/// let limiter = RateLimiter::new(limit);
+/// assert_eq!(limiter.limit(), limit);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimit {
+    pub rpm: u32,
+    pub tpm: u32,
+}
+
+/// # [`RateLimiter`]
+///
+/// A token bucket limiter which requests wait on before they are sent. Each bucket starts
+/// full and refills continuously over a minute, a request which does not fit waits until
+/// it does. Waiting requests are let through in the order they arrived.
+///
+/// Clones share the same budget, so one limiter can be given to several clients or used
+/// from concurrent tasks and they will stay within a single [`RateLimit`] between them.
+#[derive(Debug, Clone)]
+pub struct RateLimiter {
+    limit: RateLimit,
+    state: Arc<Mutex<Buckets>>,
+    clock: Arc<dyn Clock>,
+}
+
+#[derive(Debug)]
+struct Buckets {
+    requests: Bucket,
+    tokens: Bucket,
+    updated: Instant,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    capacity: f64,
+    /// Can go below zero, which is the capacity already promised to waiting requests
+    available: f64,
+}
+
+impl Bucket {
+    fn new(per_minute: u32) -> Self {
+        let capacity: f64 = per_minute.max(1) as f64;
+        Bucket {
+            capacity,
+            available: capacity,
+        }
+    }
+
+    fn refill(&mut self, elapsed: Duration) {
+        let refilled: f64 = self.capacity * elapsed.as_secs_f64() / 60.0;
+        self.available = (self.available + refilled).min(self.capacity);
+    }
+
+    /// Takes the amount from the bucket and returns how long until it has been refilled.
+    /// An amount larger than the bucket is capped so it can still be sent once the bucket is full.
+    fn take(&mut self, amount: f64) -> Duration {
+        self.available -= amount.min(self.capacity);
+        if self.available >= 0.0 {
+            return Duration::ZERO;
+        }
+        Duration::from_secs_f64(-self.available * 60.0 / self.capacity)
+    }
+}
+
+impl RateLimiter {
+    /// # [`RateLimiter::new`]
+    ///
+    /// # Arguments
+    /// * `limit`: [`RateLimit`] - the budget requests are kept within.
+    ///
+    /// # Returns
+    /// * [`RateLimiter`] - a limiter with its full budget available.
+    pub fn new(limit: RateLimit) -> Self {
+        Self::with_clock(limit, Arc::new(SystemClock))
+    }
+
+    /// # [`RateLimiter::with_clock`]
+    ///
+    /// Creates a limiter which measures time with the clock, [`RateLimiter::new`] uses [`SystemClock`].
+    /// Pass a `MockClock` (with the `test-utils` feature) to control the refill in tests.
+    ///
+    /// # Arguments
+    /// * `limit`: [`RateLimit`] - the budget requests are kept within.
+    /// * `clock`: [`Arc<dyn Clock>`] - the clock the buckets refill with.
+    ///
+    /// # Returns
+    /// * [`RateLimiter`] - a limiter with its full budget available.
+    pub fn with_clock(limit: RateLimit, clock: Arc<dyn Clock>) -> Self {
+        RateLimiter {
+            limit,
+            state: Arc::new(Mutex::new(Buckets {
+                requests: Bucket::new(limit.rpm),
+                tokens: Bucket::new(limit.tpm),
+                updated: clock.now(),
+            })),
+            clock,
+        }
+    }
+
+    /// # [`RateLimiter::limit`]
+    ///
+    /// # Returns
+    /// * [`RateLimit`] - the budget requests are kept within.
+    pub fn limit(&self) -> RateLimit {
+        self.limit
+    }
+
+    /// # [`RateLimiter::acquire`]
+    ///
+    /// Waits until there is capacity for one request using the given number of tokens and
+    /// takes it from the budget.
+    ///
+    /// # Arguments
+    /// * `tokens`: [`usize`] - the estimated tokens the request will use.
+    pub async fn acquire(&self, tokens: usize) {
+        let wait: Duration = self.reserve(tokens);
+        if !wait.is_zero() {
+            self.clock.sleep(wait).await;
+        }
+    }
+
+    /// # [`RateLimiter::reserve`]
+    ///
+    /// Takes the capacity for a request from the budget straight away and returns how long
+    /// the request must wait before that capacity has been refilled.
+    fn reserve(&self, tokens: usize) -> Duration {
+        let now: Instant = self.clock.now();
+        let mut state = self.state.lock().unwrap();
+        let elapsed: Duration = now.saturating_duration_since(state.updated);
+        state.updated = now;
+        state.requests.refill(elapsed);
+        state.tokens.refill(elapsed);
+        let request_wait: Duration = state.requests.take(1.0);
+        let token_wait: Duration = state.tokens.take(tokens as f64);
+        request_wait.max(token_wait)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::time;
+
+    #[tokio::test(start_paused = true)]
+    async fn requests_within_the_limit_are_not_delayed() {
+        let limiter = RateLimiter::new(RateLimit { rpm: 3, tpm: 100 });
+        let started = time::Instant::now();
+        for _ in 0..3 {
+            limiter.acquire(10).await;
+        }
+        assert_eq!(started.elapsed(), Duration::ZERO);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn request_over_the_request_limit_waits_for_a_refill() {
+        let limiter = RateLimiter::new(RateLimit { rpm: 2, tpm: 1000 });
+        let started = time::Instant::now();
+        limiter.acquire(1).await;
+        limiter.acquire(1).await;
+        limiter.acquire(1).await;
+        assert_eq!(started.elapsed(), Duration::from_secs(30));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn request_over_the_token_limit_waits_for_a_refill() {
+        let limiter = RateLimiter::new(RateLimit { rpm: 100, tpm: 60 });
+        let started = time::Instant::now();
+        limiter.acquire(60).await;
+        limiter.acquire(15).await;
+        assert_eq!(started.elapsed(), Duration::from_secs(15));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn request_larger_than_the_bucket_waits_for_a_full_bucket() {
+        let limiter = RateLimiter::new(RateLimit { rpm: 100, tpm: 60 });
+        let started = time::Instant::now();
+        limiter.acquire(30).await;
+        limiter.acquire(500).await;
+        assert_eq!(started.elapsed(), Duration::from_secs(30));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn clones_share_one_budget() {
+        let limiter = RateLimiter::new(RateLimit { rpm: 1, tpm: 1000 });
+        let other = limiter.clone();
+        let started = time::Instant::now();
+        let (_, _) = tokio::join!(limiter.acquire(1), other.acquire(1));
+        assert_eq!(started.elapsed(), Duration::from_secs(60));
+    }
+}
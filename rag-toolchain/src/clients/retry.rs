@@ -0,0 +1,154 @@
+use reqwest::header::{HeaderMap, RETRY_AFTER};
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::num::NonZeroU32;
+use std::time::Duration;
+
+/// # [`RetryPolicy`]
+///
+/// How a client retries requests which failed with a rate limit or a server error. Before each
+/// retry the client waits `base_delay * 2^(retry - 1)`, capped at `max_delay`, with up to half
+/// of the delay taken off at random so clients which failed together do not retry together.
+/// If the response has a `Retry-After` header the client waits that long instead, still
+/// capped at `max_delay` so a server can not stall the client indefinitely.
+///
+/// * `max_attempts` - the most times a request is sent, including the first attempt.
+/// * `base_delay` - the delay before the first retry.
+/// * `max_delay` - the longest delay between two attempts.
+///
+/// # Examples
+/// ```
+/// use rag_toolchain::clients::*;
+/// use std::num::NonZeroU32;
+/// use std::time::Duration;
+///
+/// let policy = RetryPolicy {
+///     max_attempts: NonZeroU32::new(5).unwrap(),
+///     ..RetryPolicy::default()
+/// };
+/// assert_eq!(policy.base_delay, Duration::from_millis(500));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    pub max_attempts: NonZeroU32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+}
+
+/// The number of times a request is sent by default
+pub const DEFAULT_MAX_ATTEMPTS: u32 = 3;
+/// The delay before the first retry by default
+pub const DEFAULT_BASE_DELAY: Duration = Duration::from_millis(500);
+/// The longest delay between two attempts by default
+pub const DEFAULT_MAX_DELAY: Duration = Duration::from_secs(30);
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: NonZeroU32::new(DEFAULT_MAX_ATTEMPTS).unwrap(),
+            base_delay: DEFAULT_BASE_DELAY,
+            max_delay: DEFAULT_MAX_DELAY,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// # [`RetryPolicy::delay`]
+    ///
+    /// # Arguments
+    /// * `attempt`: [`u32`] - the attempt which just failed, starting at 1.
+    /// * `headers`: &[`HeaderMap`] - the headers of the failed response.
+    ///
+    /// # Returns
+    /// * [`Option<Duration>`] - how long to wait before the next attempt, or [`None`] if
+    ///   every attempt has been used.
+    pub(crate) fn delay(&self, attempt: u32, headers: &HeaderMap) -> Option<Duration> {
+        if attempt >= self.max_attempts.get() {
+            return None;
+        }
+        match retry_after(headers) {
+            Some(delay) => Some(delay.min(self.max_delay)),
+            None => Some(self.backoff(attempt)),
+        }
+    }
+
+    /// The exponential delay before the next attempt with jitter applied.
+    fn backoff(&self, attempt: u32) -> Duration {
+        let exponent: u32 = attempt.saturating_sub(1).min(31);
+        let delay: Duration = self
+            .base_delay
+            .saturating_mul(1 << exponent)
+            .min(self.max_delay);
+        let half: u64 = (delay.as_millis() / 2) as u64;
+        let jitter: u64 = RandomState::new().build_hasher().finish() % (half + 1);
+        delay.saturating_sub(Duration::from_millis(jitter))
+    }
+}
+
+/// Reads the `Retry-After` header, only the delay in seconds form is supported.
+fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    headers
+        .get(RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .trim()
+        .parse::<u64>()
+        .ok()
+        .map(Duration::from_secs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::HeaderValue;
+
+    fn policy(max_attempts: u32) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: NonZeroU32::new(max_attempts).unwrap(),
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_millis(1000),
+        }
+    }
+
+    #[test]
+    fn backoff_doubles_with_jitter_up_to_the_max_delay() {
+        let policy = policy(10);
+        for (attempt, full) in [(1, 100), (2, 200), (3, 400), (4, 800), (5, 1000), (9, 1000)] {
+            let delay = policy.delay(attempt, &HeaderMap::new()).unwrap();
+            assert!(delay <= Duration::from_millis(full), "{attempt}: {delay:?}");
+            assert!(
+                delay >= Duration::from_millis(full / 2),
+                "{attempt}: {delay:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn no_delay_once_attempts_are_used() {
+        assert!(policy(3).delay(2, &HeaderMap::new()).is_some());
+        assert_eq!(policy(3).delay(3, &HeaderMap::new()), None);
+        assert_eq!(policy(1).delay(1, &HeaderMap::new()), None);
+    }
+
+    #[test]
+    fn retry_after_header_overrides_backoff() {
+        let mut headers = HeaderMap::new();
+        headers.insert(RETRY_AFTER, HeaderValue::from_static("7"));
+        let policy_with_long_max = RetryPolicy {
+            max_delay: Duration::from_secs(10),
+            ..policy(3)
+        };
+        assert_eq!(
+            policy_with_long_max.delay(1, &headers),
+            Some(Duration::from_secs(7))
+        );
+        // The header is still capped at the max delay
+        assert_eq!(policy(3).delay(1, &headers), Some(Duration::from_secs(1)));
+
+        headers.insert(
+            RETRY_AFTER,
+            HeaderValue::from_static("Wed, 21 Oct 2015 07:28:00 GMT"),
+        );
+        assert!(policy(3).delay(1, &headers).unwrap() <= Duration::from_millis(100));
+    }
+}
@@ -0,0 +1,357 @@
+use crate::common::{Clock, SystemClock};
+use dotenv::dotenv;
+use std::fmt::{Debug, Formatter};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use thiserror::Error;
+use tokio::sync::Mutex;
+
+/// How long a fetched API key is used before the provider is asked again
+pub const DEFAULT_SECRET_TTL: Duration = Duration::from_secs(300);
+
+/// The future returned by [`SecretProvider::get`]
+pub type SecretFuture<'a> =
+    Pin<Box<dyn Future<Output = Result<SecretString, SecretError>> + Send + 'a>>;
+
+/// # [`SecretString`]
+///
+/// A secret such as an API key. The value is never included in the [`Debug`]
+/// output so it cannot leak into logs by accident.
+#[derive(Clone, PartialEq, Eq)]
+pub struct SecretString(String);
+
+impl SecretString {
+    /// # [`SecretString::new`]
+    ///
+    /// # Arguments
+    /// * `secret`: impl [`Into<String>`] - the secret value.
+    ///
+    /// # Returns
+    /// * [`SecretString`] - the wrapped secret.
+    pub fn new(secret: impl Into<String>) -> Self {
+        SecretString(secret.into())
+    }
+
+    /// # [`SecretString::expose_secret`]
+    ///
+    /// # Returns
+    /// * &[`str`] - the secret value.
+    pub fn expose_secret(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Debug for SecretString {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("SecretString([REDACTED])")
+    }
+}
+
+/// # [`SecretError`]
+///
+/// The errors a [`SecretProvider`] can return.
+#[derive(Error, Debug, PartialEq, Eq, Clone)]
+pub enum SecretError {
+    /// # The provider has no secret with the name
+    #[error("Secret not found: {0}")]
+    NotFound(String),
+    /// # The provider failed to fetch the secret e.g. it could not be reached
+    #[error("Error fetching secret: {0}")]
+    Provider(String),
+}
+
+/// # [`SecretProvider`]
+///
+/// Supplies the API keys used by the clients. Keys are fetched lazily and cached
+/// for [`DEFAULT_SECRET_TTL`], so a key rotated in the provider is picked up without
+/// a restart. If the API rejects a key with a 401 the key is fetched again straight
+/// away and the request is retried once with the new key.
+///
+/// [`EnvSecretProvider`] reads keys from environment variables and is used by the
+/// `try_new` constructors. Implement this trait to read keys from a secret store such
+/// as Vault or AWS Secrets Manager.
+///
+/// # Examples
+/// ```
+/// use rag_toolchain::clients::{SecretError, SecretFuture, SecretProvider, SecretString};
+///
+/// struct StaticProvider(String);
+///
+/// impl SecretProvider for StaticProvider {
+///     fn get<'a>(&'a self, _name: &'a str) -> SecretFuture<'a> {
+///         Box::pin(async move { Ok(SecretString::new(self.0.clone())) })
+///     }
+/// }
+/// ```
+pub trait SecretProvider: Send + Sync {
+    /// # [`SecretProvider::get`]
+    ///
+    /// # Arguments
+    /// * `name`: &[`str`] - the name of the secret e.g. `OPENAI_API_KEY`.
+    ///
+    /// # Errors
+    /// * [`SecretError`] - if the secret could not be fetched.
+    ///
+    /// # Returns
+    /// * [`SecretFuture`] - resolves to the current value of the secret.
+    fn get<'a>(&'a self, name: &'a str) -> SecretFuture<'a>;
+}
+
+/// # [`EnvSecretProvider`]
+///
+/// Reads secrets from environment variables, a `.env` file is loaded if there is one.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct EnvSecretProvider;
+
+impl SecretProvider for EnvSecretProvider {
+    fn get<'a>(&'a self, name: &'a str) -> SecretFuture<'a> {
+        Box::pin(async move {
+            dotenv().ok();
+            std::env::var(name)
+                .map(SecretString::new)
+                .map_err(|_| SecretError::NotFound(name.into()))
+        })
+    }
+}
+
+/// # [`StaticSecretProvider`]
+///
+/// Returns the same secret whatever its name, for keys given directly by the caller.
+#[cfg(any(feature = "openai-embeddings", feature = "openai-chat"))]
+pub(crate) struct StaticSecretProvider(pub(crate) SecretString);
+
+#[cfg(any(feature = "openai-embeddings", feature = "openai-chat"))]
+impl SecretProvider for StaticSecretProvider {
+    fn get<'a>(&'a self, _name: &'a str) -> SecretFuture<'a> {
+        Box::pin(async move { Ok(self.0.clone()) })
+    }
+}
+
+/// # [`CachedSecret`]
+///
+/// A secret from a [`SecretProvider`] which is cached for a time to live.
+/// The lock is held while fetching so concurrent requests only fetch once.
+pub(crate) struct CachedSecret {
+    provider: Arc<dyn SecretProvider>,
+    name: String,
+    ttl: Duration,
+    clock: Arc<dyn Clock>,
+    cached: Mutex<Option<(SecretString, Instant)>>,
+}
+
+impl CachedSecret {
+    /// # [`CachedSecret::new`]
+    ///
+    /// # Arguments
+    /// * `provider`: [`Arc<dyn SecretProvider>`] - where the secret is fetched from.
+    /// * `name`: impl [`Into<String>`] - the name of the secret.
+    /// * `ttl`: [`Duration`] - how long a fetched secret is used for.
+    pub(crate) fn new(
+        provider: Arc<dyn SecretProvider>,
+        name: impl Into<String>,
+        ttl: Duration,
+    ) -> Self {
+        CachedSecret {
+            provider,
+            name: name.into(),
+            ttl,
+            clock: Arc::new(SystemClock),
+            cached: Mutex::new(None),
+        }
+    }
+
+    /// # [`CachedSecret::set_clock`]
+    ///
+    /// Sets the clock the time to live is measured with, defaults to [`SystemClock`].
+    ///
+    /// # Arguments
+    /// * `clock`: [`Arc<dyn Clock>`] - the clock to measure the time to live with.
+    #[cfg(any(
+        test,
+        feature = "openai-embeddings",
+        feature = "openai-chat",
+        feature = "anthropic"
+    ))]
+    pub(crate) fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        self.clock = clock;
+    }
+
+    /// # [`CachedSecret::with_initial_value`]
+    ///
+    /// Seeds the cache with a secret which has already been fetched.
+    pub(crate) fn with_initial_value(self, secret: SecretString) -> Self {
+        let fetched_at: Instant = self.clock.now();
+        CachedSecret {
+            cached: Mutex::new(Some((secret, fetched_at))),
+            ..self
+        }
+    }
+
+    /// # [`CachedSecret::get`]
+    ///
+    /// # Errors
+    /// * [`SecretError`] - if the cached secret expired and fetching it again failed.
+    ///
+    /// # Returns
+    /// * [`SecretString`] - the cached secret, fetched again if it has expired.
+    pub(crate) async fn get(&self) -> Result<SecretString, SecretError> {
+        let mut cached = self.cached.lock().await;
+        match cached.as_ref() {
+            Some((secret, fetched_at)) if self.clock.now() - *fetched_at < self.ttl => {
+                Ok(secret.clone())
+            }
+            _ => self.fetch(&mut cached).await,
+        }
+    }
+
+    /// # [`CachedSecret::refresh`]
+    ///
+    /// Fetches the secret again after it was rejected. If another request has already
+    /// replaced the rejected secret the replacement is returned without fetching.
+    ///
+    /// # Arguments
+    /// * `rejected`: &[`SecretString`] - the secret which was rejected.
+    ///
+    /// # Errors
+    /// * [`SecretError`] - if fetching the secret failed.
+    ///
+    /// # Returns
+    /// * [`SecretString`] - the current secret.
+    pub(crate) async fn refresh(
+        &self,
+        rejected: &SecretString,
+    ) -> Result<SecretString, SecretError> {
+        let mut cached = self.cached.lock().await;
+        match cached.as_ref() {
+            Some((secret, _)) if secret != rejected => Ok(secret.clone()),
+            _ => self.fetch(&mut cached).await,
+        }
+    }
+
+    async fn fetch(
+        &self,
+        cached: &mut Option<(SecretString, Instant)>,
+    ) -> Result<SecretString, SecretError> {
+        let secret: SecretString = self.provider.get(&self.name).await?;
+        *cached = Some((secret.clone(), self.clock.now()));
+        Ok(secret)
+    }
+}
+
+impl Debug for CachedSecret {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CachedSecret")
+            .field("name", &self.name)
+            .field("ttl", &self.ttl)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::common::MockClock;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// A provider which returns the next key from a script on each fetch,
+    /// repeating the last key once the script runs out.
+    pub(crate) struct ScriptedProvider {
+        keys: Vec<&'static str>,
+        pub(crate) fetches: AtomicUsize,
+    }
+
+    impl ScriptedProvider {
+        pub(crate) fn new(keys: Vec<&'static str>) -> Arc<Self> {
+            Arc::new(ScriptedProvider {
+                keys,
+                fetches: AtomicUsize::new(0),
+            })
+        }
+    }
+
+    impl SecretProvider for ScriptedProvider {
+        fn get<'a>(&'a self, _name: &'a str) -> SecretFuture<'a> {
+            let fetch: usize = self.fetches.fetch_add(1, Ordering::SeqCst);
+            let key: &str = self.keys[fetch.min(self.keys.len() - 1)];
+            Box::pin(async move { Ok(SecretString::new(key)) })
+        }
+    }
+
+    // Lets the clients which take their provider by value be given a scripted provider
+    impl SecretProvider for Arc<ScriptedProvider> {
+        fn get<'a>(&'a self, name: &'a str) -> SecretFuture<'a> {
+            self.as_ref().get(name)
+        }
+    }
+
+    /// A provider which never has the secret, so tests of a missing API key
+    /// do not have to remove it from the environment other tests are reading.
+    #[cfg(any(feature = "anthropic", feature = "cohere"))]
+    pub(crate) struct MissingProvider;
+
+    #[cfg(any(feature = "anthropic", feature = "cohere"))]
+    impl SecretProvider for MissingProvider {
+        fn get<'a>(&'a self, name: &'a str) -> SecretFuture<'a> {
+            Box::pin(async move { Err(SecretError::NotFound(name.into())) })
+        }
+    }
+
+    #[test]
+    fn secret_string_debug_is_redacted() {
+        let secret = SecretString::new("sk-123");
+        assert_eq!(format!("{:?}", secret), "SecretString([REDACTED])");
+        assert_eq!(secret.expose_secret(), "sk-123");
+    }
+
+    #[tokio::test]
+    async fn env_provider_reads_environment() {
+        std::env::set_var("SECRETS_TEST_KEY", "from env");
+        let secret = EnvSecretProvider.get("SECRETS_TEST_KEY").await.unwrap();
+        assert_eq!(secret.expose_secret(), "from env");
+        let error = EnvSecretProvider.get("SECRETS_TEST_MISSING").await;
+        assert_eq!(
+            error,
+            Err(SecretError::NotFound("SECRETS_TEST_MISSING".into()))
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn cached_secret_is_fetched_again_after_ttl() {
+        let provider = ScriptedProvider::new(vec!["first", "second"]);
+        let secret = CachedSecret::new(provider.clone(), "KEY", Duration::from_secs(60));
+        assert_eq!(secret.get().await.unwrap().expose_secret(), "first");
+        tokio::time::advance(Duration::from_secs(59)).await;
+        assert_eq!(secret.get().await.unwrap().expose_secret(), "first");
+        tokio::time::advance(Duration::from_secs(1)).await;
+        assert_eq!(secret.get().await.unwrap().expose_secret(), "second");
+        assert_eq!(provider.fetches.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn cached_secret_expires_on_a_mock_clock() {
+        let clock = Arc::new(MockClock::new());
+        let provider = ScriptedProvider::new(vec!["first", "second"]);
+        let mut secret = CachedSecret::new(provider.clone(), "KEY", DEFAULT_SECRET_TTL);
+        secret.set_clock(clock.clone());
+        assert_eq!(secret.get().await.unwrap().expose_secret(), "first");
+        clock.advance(DEFAULT_SECRET_TTL - Duration::from_secs(1));
+        assert_eq!(secret.get().await.unwrap().expose_secret(), "first");
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(secret.get().await.unwrap().expose_secret(), "second");
+        assert_eq!(provider.fetches.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn refresh_only_fetches_if_the_rejected_secret_is_cached() {
+        let provider = ScriptedProvider::new(vec!["first", "second"]);
+        let secret = CachedSecret::new(provider.clone(), "KEY", DEFAULT_SECRET_TTL);
+        let rejected = secret.get().await.unwrap();
+        let refreshed = secret.refresh(&rejected).await.unwrap();
+        assert_eq!(refreshed.expose_secret(), "second");
+        // A second request rejected with the old key uses the refreshed one
+        assert_eq!(secret.refresh(&rejected).await.unwrap(), refreshed);
+        assert_eq!(provider.fetches.load(Ordering::SeqCst), 2);
+    }
+}
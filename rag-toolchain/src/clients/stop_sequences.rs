@@ -0,0 +1,168 @@
+/// # [`StopSequenceMatcher`]
+///
+/// Client side enforcement of stop sequences for streamed completions. Deltas are pushed
+/// in as they arrive and the text that is safe to emit is returned. Any trailing text that
+/// could be the start of a stop sequence is held back until the next delta arrives, this
+/// way a stop sequence split across delta boundaries is still caught before any of it is emitted.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct StopSequenceMatcher {
+    stop_sequences: Vec<String>,
+    pending: String,
+}
+
+/// # [`StopSequenceMatch`]
+///
+/// * [`StopSequenceMatch::Continue`] - no stop sequence was found, the text is safe to emit.
+/// * [`StopSequenceMatch::Stopped`] - a stop sequence was found, the text is everything before it
+///   and the stream should be terminated.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum StopSequenceMatch {
+    Continue(String),
+    Stopped(String),
+}
+
+impl StopSequenceMatcher {
+    /// # [`StopSequenceMatcher::new`]
+    ///
+    /// # Arguments
+    /// * `stop_sequences`: [`Vec<String>`] - the sequences to stop on, empty sequences are ignored.
+    pub(crate) fn new(stop_sequences: Vec<String>) -> Self {
+        let stop_sequences: Vec<String> = stop_sequences
+            .into_iter()
+            .filter(|sequence| !sequence.is_empty())
+            .collect();
+        StopSequenceMatcher {
+            stop_sequences,
+            pending: String::new(),
+        }
+    }
+
+    /// # [`StopSequenceMatcher::push`]
+    ///
+    /// Scans the held back text along with the new delta for a stop sequence.
+    ///
+    /// # Arguments
+    /// * `delta`: &[`str`] - the newly received text.
+    ///
+    /// # Returns
+    /// * [`StopSequenceMatch`] - the text that can be emitted and whether the stream should stop.
+    pub(crate) fn push(&mut self, delta: &str) -> StopSequenceMatch {
+        if self.stop_sequences.is_empty() {
+            return StopSequenceMatch::Continue(delta.to_string());
+        }
+        let mut buffer: String = std::mem::take(&mut self.pending);
+        buffer.push_str(delta);
+
+        let first_match: Option<usize> = self
+            .stop_sequences
+            .iter()
+            .filter_map(|sequence| buffer.find(sequence.as_str()))
+            .min();
+        if let Some(index) = first_match {
+            buffer.truncate(index);
+            return StopSequenceMatch::Stopped(buffer);
+        }
+
+        let hold_from: usize = self.partial_match_start(&buffer);
+        self.pending = buffer.split_off(hold_from);
+        StopSequenceMatch::Continue(buffer)
+    }
+
+    /// # [`StopSequenceMatcher::flush`]
+    ///
+    /// Called once the stream has ended, releases any held back text
+    /// as it can no longer turn into a stop sequence.
+    pub(crate) fn flush(&mut self) -> String {
+        std::mem::take(&mut self.pending)
+    }
+
+    /// Finds the start of the longest suffix of the buffer which is the
+    /// beginning of a stop sequence, or the buffer length if there is none.
+    fn partial_match_start(&self, buffer: &str) -> usize {
+        buffer
+            .char_indices()
+            .map(|(index, _)| index)
+            .find(|index| {
+                let suffix: &str = &buffer[*index..];
+                self.stop_sequences
+                    .iter()
+                    .any(|sequence| sequence.starts_with(suffix))
+            })
+            .unwrap_or(buffer.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matcher(stop_sequences: &[&str]) -> StopSequenceMatcher {
+        StopSequenceMatcher::new(stop_sequences.iter().map(|s| s.to_string()).collect())
+    }
+
+    #[test]
+    fn no_stop_sequences_passes_through() {
+        let mut matcher = matcher(&[]);
+        assert_eq!(
+            matcher.push("hello <"),
+            StopSequenceMatch::Continue("hello <".into())
+        );
+        assert_eq!(matcher.flush(), "");
+    }
+
+    #[test]
+    fn stop_sequence_within_single_delta() {
+        let mut matcher = matcher(&["</answer>"]);
+        assert_eq!(
+            matcher.push("yes</answer> more"),
+            StopSequenceMatch::Stopped("yes".into())
+        );
+    }
+
+    #[test]
+    fn stop_sequence_split_across_deltas() {
+        let mut matcher = matcher(&["</answer>"]);
+        assert_eq!(
+            matcher.push("Hello </an"),
+            StopSequenceMatch::Continue("Hello ".into())
+        );
+        assert_eq!(matcher.push("sw"), StopSequenceMatch::Continue("".into()));
+        assert_eq!(
+            matcher.push("er> trailing"),
+            StopSequenceMatch::Stopped("".into())
+        );
+    }
+
+    #[test]
+    fn held_back_text_released_when_not_a_match() {
+        let mut matcher = matcher(&["</answer>"]);
+        assert_eq!(
+            matcher.push("a </a"),
+            StopSequenceMatch::Continue("a ".into())
+        );
+        assert_eq!(
+            matcher.push("b> c <"),
+            StopSequenceMatch::Continue("</ab> c ".into())
+        );
+        assert_eq!(matcher.flush(), "<");
+    }
+
+    #[test]
+    fn earliest_stop_sequence_wins() {
+        let mut matcher = matcher(&["STOP", "END", ""]);
+        assert_eq!(
+            matcher.push("one END two STOP"),
+            StopSequenceMatch::Stopped("one ".into())
+        );
+    }
+
+    #[test]
+    fn multi_byte_characters_are_not_split() {
+        let mut matcher = matcher(&["é!"]);
+        assert_eq!(
+            matcher.push("café"),
+            StopSequenceMatch::Continue("caf".into())
+        );
+        assert_eq!(matcher.push("!"), StopSequenceMatch::Stopped("".into()));
+    }
+}
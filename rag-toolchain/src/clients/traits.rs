@@ -1,13 +1,18 @@
-use crate::common::{Chunk, Chunks, Embedding};
+use crate::common::{count_tiktoken_tokens, Chunk, Chunks, Embedding, InvocationContext};
+use futures::{stream, Stream, StreamExt};
 use std::error::Error;
 use std::future::Future;
+use std::num::NonZeroUsize;
+use tiktoken_rs::tokenizer::Tokenizer;
 
-use super::types::PromptMessage;
+use super::embedding_task::EmbeddingTaskType;
+use super::prompt_tokens::count_prompt_tokens;
+use super::types::{DetailedChatResponse, PromptMessage};
 
 /// # [`AsyncEmbeddingClient`]
 /// Trait for any client that generates embeddings asynchronously
-pub trait AsyncEmbeddingClient {
-    type ErrorType: Error;
+pub trait AsyncEmbeddingClient: Send + Sync {
+    type ErrorType: Error + Send + Sync;
     fn generate_embedding(
         &self,
         text: Chunk,
@@ -16,36 +21,278 @@ pub trait AsyncEmbeddingClient {
         &self,
         text: Chunks,
     ) -> impl Future<Output = Result<Vec<Embedding>, Self::ErrorType>> + Send;
+
+    /// # [`AsyncEmbeddingClient::generate_embedding_for`]
+    ///
+    /// Embeds the chunk for a task, clients whose model embeds queries and documents
+    /// differently should override this. The embedding holds the chunk as it was given.
+    /// The default ignores the task and calls [`AsyncEmbeddingClient::generate_embedding`].
+    ///
+    /// # Arguments
+    /// * `text`: [`Chunk`] - the chunk to embed.
+    /// * `task`: [`EmbeddingTaskType`] - what the embedding will be used for.
+    ///
+    /// # Errors
+    /// * [`Self::ErrorType`] - if the chunk could not be embedded.
+    ///
+    /// # Returns
+    /// * [`Embedding`] - the embedding of the chunk.
+    fn generate_embedding_for(
+        &self,
+        text: Chunk,
+        task: EmbeddingTaskType,
+    ) -> impl Future<Output = Result<Embedding, Self::ErrorType>> + Send {
+        let _ = task;
+        self.generate_embedding(text)
+    }
+
+    /// # [`AsyncEmbeddingClient::generate_embeddings_for`]
+    ///
+    /// Embeds the chunks for a task, see [`AsyncEmbeddingClient::generate_embedding_for`].
+    /// The default ignores the task and calls [`AsyncEmbeddingClient::generate_embeddings`].
+    ///
+    /// # Arguments
+    /// * `text`: [`Chunks`] - the chunks to embed.
+    /// * `task`: [`EmbeddingTaskType`] - what the embeddings will be used for.
+    ///
+    /// # Errors
+    /// * [`Self::ErrorType`] - if the chunks could not be embedded.
+    ///
+    /// # Returns
+    /// * [`Vec<Embedding>`] - the embeddings in the order of the chunks.
+    fn generate_embeddings_for(
+        &self,
+        text: Chunks,
+        task: EmbeddingTaskType,
+    ) -> impl Future<Output = Result<Vec<Embedding>, Self::ErrorType>> + Send {
+        let _ = task;
+        self.generate_embeddings(text)
+    }
+
+    /// # [`AsyncEmbeddingClient::dimensions`]
+    ///
+    /// The dimension of the vectors this client generates if it is known up front,
+    /// this allows stores and retrievers to catch a mismatched model when they are built.
+    ///
+    /// # Returns
+    /// * [`Option<usize>`] - the vector dimension, defaults to [`None`].
+    fn dimensions(&self) -> Option<usize> {
+        None
+    }
+
+    /// # [`AsyncEmbeddingClient::embed_stream`]
+    ///
+    /// Embeds a stream of chunks as documents, batching them into calls to
+    /// [`AsyncEmbeddingClient::generate_embeddings_for`] so at most one batch of chunks
+    /// and embeddings is held at a time. Batches are sent one after the other.
+    ///
+    /// # Arguments
+    /// * `chunks`: impl [`Stream<Item = Chunk>`] - the chunks to embed.
+    /// * `batch_size`: [`NonZeroUsize`] - the number of chunks sent in each request.
+    ///
+    /// # Returns
+    /// * impl [`Stream<Item = Result<Embedding, Self::ErrorType>>`] - the embeddings in the order of
+    ///   the chunks. If a batch fails its error is yielded once in place of its embeddings.
+    fn embed_stream<'a, S>(
+        &'a self,
+        chunks: S,
+        batch_size: NonZeroUsize,
+    ) -> impl Stream<Item = Result<Embedding, Self::ErrorType>> + 'a
+    where
+        Self: Sized,
+        S: Stream<Item = Chunk> + 'a,
+    {
+        chunks
+            .chunks(batch_size.get())
+            .then(move |batch| self.generate_embeddings_for(batch, EmbeddingTaskType::Document))
+            .flat_map(|result| {
+                let embeddings: Vec<Result<Embedding, Self::ErrorType>> = match result {
+                    Ok(embeddings) => embeddings.into_iter().map(Ok).collect(),
+                    Err(error) => vec![Err(error)],
+                };
+                stream::iter(embeddings)
+            })
+    }
+}
+
+/// # [`TokenCounter`]
+/// Counts tokens for a chat client's model, this lets a chain fit its prompt to a budget.
+/// It is shared by [`AsyncChatClient`] and [`AsyncStreamedChatClient`] so a client
+/// implementing both only counts tokens in one place.
+pub trait TokenCounter {
+    /// # [`TokenCounter::count_tokens`]
+    ///
+    /// The number of tokens the text uses for this client's model. The default counts with
+    /// the cl100k tokenizer, clients whose models use a different tokenizer should override this.
+    ///
+    /// # Arguments
+    /// * `text`: &[`str`] - the text to count.
+    ///
+    /// # Returns
+    /// * [`usize`] - the number of tokens.
+    fn count_tokens(&self, text: &str) -> usize {
+        count_cl100k_tokens(text)
+    }
+
+    /// # [`TokenCounter::count_prompt_tokens`]
+    ///
+    /// Estimates the tokens a request for the messages uses with this client's model, see
+    /// [`crate::clients::count_prompt_tokens`]. This lets a prompt be sized before invoking.
+    ///
+    /// # Arguments
+    /// * `prompt_messages`: &[`[PromptMessage]`] - the messages that will be sent.
+    ///
+    /// # Returns
+    /// * [`usize`] - the estimated number of tokens.
+    fn count_prompt_tokens(&self, prompt_messages: &[PromptMessage]) -> usize {
+        count_prompt_tokens(prompt_messages, |text| self.count_tokens(text))
+    }
 }
 
 /// # [`AsyncChatClient`]
 /// Trait for any client that generates chat completions asynchronously
-pub trait AsyncChatClient {
-    type ErrorType: Error;
+pub trait AsyncChatClient: TokenCounter + Send + Sync {
+    type ErrorType: Error + Send + Sync;
     fn invoke(
         &self,
         prompt_messages: Vec<PromptMessage>,
     ) -> impl Future<Output = Result<PromptMessage, Self::ErrorType>> + Send;
+
+    /// # [`AsyncChatClient::invoke_with_context`]
+    ///
+    /// Invokes the client as part of a wider invocation so the request can be correlated.
+    /// Clients which can send the request id to the provider should override this,
+    /// the default just calls [`AsyncChatClient::invoke`].
+    ///
+    /// # Arguments
+    /// * `prompt_messages`: [`Vec<PromptMessage>`] - the messages to send to the LLM.
+    /// * `context`: &[`InvocationContext`] - the context of the invocation.
+    ///
+    /// # Errors
+    /// * [`Self::ErrorType`] - if the chat client invocation fails.
+    ///
+    /// # Returns
+    /// * [`DetailedChatResponse`] - the response along with the request ids and token usage.
+    fn invoke_with_context(
+        &self,
+        prompt_messages: Vec<PromptMessage>,
+        context: &InvocationContext,
+    ) -> impl Future<Output = Result<DetailedChatResponse, Self::ErrorType>> + Send {
+        let request_id = context.request_id();
+        let response = self.invoke(prompt_messages);
+        async move {
+            Ok(DetailedChatResponse {
+                message: response.await?,
+                request_id,
+                provider_request_id: None,
+                system_fingerprint: None,
+                usage: None,
+                finish_reason: None,
+            })
+        }
+    }
 }
 
 /// # [`AsyncStreamedChatClient`]
 /// Trait for any client that generates streamed chat completions asynchronously
-pub trait AsyncStreamedChatClient {
-    type ErrorType: Error;
+pub trait AsyncStreamedChatClient: TokenCounter + Send + Sync {
+    type ErrorType: Error + Send + Sync;
     type Item: ChatCompletionStream;
     fn invoke_stream(
         &self,
         prompt_messages: Vec<PromptMessage>,
     ) -> impl Future<Output = Result<Self::Item, Self::ErrorType>> + Send;
+
+    /// # [`AsyncStreamedChatClient::invoke_stream_with_context`]
+    ///
+    /// Invokes the client as part of a wider invocation so the request can be correlated.
+    /// Clients which can send the request id to the provider should override this,
+    /// the default just calls [`AsyncStreamedChatClient::invoke_stream`].
+    fn invoke_stream_with_context(
+        &self,
+        prompt_messages: Vec<PromptMessage>,
+        _context: &InvocationContext,
+    ) -> impl Future<Output = Result<Self::Item, Self::ErrorType>> + Send {
+        self.invoke_stream(prompt_messages)
+    }
 }
 
 /// # [`ChatCompletionStream`]
 ///
 /// Trait for any stream that generates chat completions
-pub trait ChatCompletionStream {
-    type ErrorType: Error;
-    type Item;
-    fn next(&mut self) -> impl Future<Output = Option<Result<Self::Item, Self::ErrorType>>>;
+pub trait ChatCompletionStream: Send {
+    type ErrorType: Error + Send + Sync;
+    type Item: Send;
+    fn next(&mut self) -> impl Future<Output = Option<Result<Self::Item, Self::ErrorType>>> + Send;
+
+    /// # [`ChatCompletionStream::is_token`]
+    ///
+    /// Whether a value read from the stream carries generated text, this is used to time
+    /// the first token. Streams which also yield other values (e.g. connection events)
+    /// should override this, the default treats every value as a token.
+    fn is_token(_item: &Self::Item) -> bool {
+        true
+    }
+
+    /// # [`ChatCompletionStream::into_stream`]
+    ///
+    /// Adapts the completion stream into a [`Stream`] so it composes with the
+    /// [`StreamExt`] combinators, or can be forwarded on as server sent events.
+    /// The returned stream is not [`Unpin`], pin it before calling [`StreamExt::next`].
+    ///
+    /// # Examples
+    /// ```
+    /// use futures::StreamExt;
+    /// use rag_toolchain::clients::*;
+    ///
+    /// async fn print_stream<S>(stream: S)
+    /// where
+    ///     S: ChatCompletionStream,
+    ///     S::Item: StreamedText,
+    /// {
+    ///     let mut text = std::pin::pin!(stream
+    ///         .into_stream()
+    ///         .take_while(|value| std::future::ready(value.is_ok()))
+    ///         .filter_map(|value| async move { Some(value.ok()?.streamed_text()?.to_string()) }));
+    ///     while let Some(text) = text.next().await {
+    ///         print!("{}", text);
+    ///     }
+    /// }
+    /// ```
+    ///
+    /// # Returns
+    /// * impl [`Stream<Item = Result<Self::Item, Self::ErrorType>>`] - yields each value read
+    ///   with [`ChatCompletionStream::next`] and ends when it does.
+    fn into_stream(self) -> impl Stream<Item = Result<Self::Item, Self::ErrorType>> + Send
+    where
+        Self: Sized,
+    {
+        stream::unfold(self, |mut completion_stream| async move {
+            let value = completion_stream.next().await?;
+            Some((value, completion_stream))
+        })
+    }
+}
+
+/// # [`StreamedText`]
+///
+/// Trait for the values read from a [`ChatCompletionStream`] which carry part of the reply,
+/// this lets a chain put the full reply back together as it passes through.
+pub trait StreamedText {
+    /// # Returns
+    /// * [`Option<&str>`] - the generated text the value carries, `None` if it carries none.
+    fn streamed_text(&self) -> Option<&str>;
+}
+
+impl StreamedText for PromptMessage {
+    fn streamed_text(&self) -> Option<&str> {
+        Some(self.content())
+    }
+}
+
+/// Counts tokens with the cl100k tokenizer, the default for [`AsyncChatClient::count_tokens`].
+fn count_cl100k_tokens(text: &str) -> usize {
+    count_tiktoken_tokens(Tokenizer::Cl100kBase, text)
 }
 
 #[cfg(test)]
@@ -61,6 +308,9 @@ mock! {
             prompt_messages: Vec<PromptMessage>,
         ) -> Result<PromptMessage, <Self as AsyncChatClient>::ErrorType>;
     }
+    impl TokenCounter for AsyncChatClient {
+        fn count_tokens(&self, text: &str) -> usize;
+    }
 }
 
 #[cfg(test)]
@@ -74,7 +324,7 @@ mock! {
             &self,
             prompt_messages: Vec<PromptMessage>,
         ) -> Result<MockChatCompletionStream, <Self as AsyncStreamedChatClient>::ErrorType>;
-    }
+    }    impl TokenCounter for AsyncStreamedChatClient {}
 }
 
 #[cfg(test)]
@@ -86,3 +336,100 @@ mock! {
         async fn next(&mut self) -> Option<Result<PromptMessage, <Self as ChatCompletionStream>::ErrorType>>;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::TryStreamExt;
+    use std::sync::Mutex;
+
+    #[tokio::test]
+    async fn embed_stream_batches_chunks() {
+        let client = BatchRecordingClient::default();
+        let chunks = stream::iter((0..5).map(|i| Chunk::new(i.to_string())));
+
+        let embeddings: Vec<Embedding> = client
+            .embed_stream(chunks, NonZeroUsize::new(2).unwrap())
+            .try_collect()
+            .await
+            .unwrap();
+
+        assert_eq!(*client.batch_sizes.lock().unwrap(), vec![2, 2, 1]);
+        let vectors: Vec<Vec<f32>> = embeddings.iter().map(Embedding::vector).collect();
+        assert_eq!(
+            vectors,
+            vec![vec![0.0], vec![1.0], vec![2.0], vec![3.0], vec![4.0]]
+        );
+    }
+
+    #[tokio::test]
+    async fn embed_stream_yields_batch_errors() {
+        let client = BatchRecordingClient::default();
+        let chunks = stream::iter(vec![Chunk::new("1"), Chunk::new("fail"), Chunk::new("2")]);
+
+        let results: Vec<Result<Embedding, std::io::Error>> = client
+            .embed_stream(chunks, NonZeroUsize::new(2).unwrap())
+            .collect()
+            .await;
+
+        assert_eq!(results.len(), 2);
+        assert!(results[0].is_err());
+        assert_eq!(results[1].as_ref().unwrap().vector(), vec![2.0]);
+    }
+
+    #[tokio::test]
+    async fn into_stream_yields_each_value_until_the_stream_ends() {
+        let mut values = vec![
+            Some(Ok(PromptMessage::AIMessage("Hello".into()))),
+            Some(Err(std::io::Error::other("dropped"))),
+            Some(Ok(PromptMessage::AIMessage(" world".into()))),
+            None,
+        ]
+        .into_iter();
+        let mut completion_stream = MockChatCompletionStream::new();
+        completion_stream
+            .expect_next()
+            .times(4)
+            .returning(move || values.next().unwrap());
+
+        let results: Vec<Result<PromptMessage, std::io::Error>> =
+            completion_stream.into_stream().collect().await;
+
+        assert_eq!(results.len(), 3);
+        assert!(results[1].is_err());
+        assert_eq!(
+            results[2].as_ref().unwrap(),
+            &PromptMessage::AIMessage(" world".into())
+        );
+    }
+
+    // Client which embeds a chunk as its parsed value and records the size of each batch
+    #[derive(Default)]
+    struct BatchRecordingClient {
+        batch_sizes: Mutex<Vec<usize>>,
+    }
+
+    impl AsyncEmbeddingClient for BatchRecordingClient {
+        type ErrorType = std::io::Error;
+
+        async fn generate_embedding(&self, text: Chunk) -> Result<Embedding, Self::ErrorType> {
+            let value: f32 = text
+                .content()
+                .parse()
+                .map_err(|_| std::io::Error::other("not a number"))?;
+            Ok(Embedding::new(text, vec![value]))
+        }
+
+        async fn generate_embeddings(
+            &self,
+            text: Chunks,
+        ) -> Result<Vec<Embedding>, Self::ErrorType> {
+            self.batch_sizes.lock().unwrap().push(text.len());
+            let mut embeddings: Vec<Embedding> = Vec::new();
+            for chunk in text {
+                embeddings.push(self.generate_embedding(chunk).await?);
+            }
+            Ok(embeddings)
+        }
+    }
+}
@@ -1,14 +1,48 @@
+#[cfg(any(
+    feature = "openai-stream",
+    feature = "anthropic-stream",
+    feature = "ollama"
+))]
+use crate::clients::StreamedText;
+use crate::common::TokenUsage;
+use std::borrow::Cow;
+use std::convert::Infallible;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+use std::time::{Duration, SystemTime};
+use uuid::Uuid;
+
 /// # [`PromptMessage`]
 /// This enum is used to represent the different types of messages that can be sent to the LLM.
 /// we will map the PromptMessage within the client into the compatible format.
 /// * [`PromptMessage::SystemMessage`] - This is a message that typically we asign the model a role.
 /// * [`PromptMessage::HumanMessage`] - This is a message that is from a human i.e you.
+/// * [`PromptMessage::MultiModalHumanMessage`] - A message from a human made of text and images,
+///   only models whose [`crate::clients::ModelCapabilities`] support vision accept images.
 /// * [`PromptMessage::AIMessage`] - This is a message that we get back from the LLM.
+///
+/// The text messages hold a [`MessageBody`] which can be created from a string, and can
+/// carry a [`MessageMeta`] such as the name of the speaker.
+///
+/// # Examples
+/// ```
+/// use rag_toolchain::clients::*;
+///
+/// let question = PromptMessage::HumanMessage("What is the refund policy?".into());
+/// let named = PromptMessage::HumanMessage(
+///     MessageBody::from("What is the refund policy?").with_meta(MessageMeta::default().with_name("alice")),
+/// );
+/// assert_eq!(question.content(), named.content());
+/// assert_eq!(named.meta().and_then(|meta| meta.name.as_deref()), Some("alice"));
+/// ```
 #[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
 pub enum PromptMessage {
-    SystemMessage(String),
-    HumanMessage(String),
-    AIMessage(String),
+    SystemMessage(MessageBody),
+    HumanMessage(MessageBody),
+    MultiModalHumanMessage(Vec<ContentPart>),
+    AIMessage(MessageBody),
 }
 
 impl PromptMessage {
@@ -17,35 +51,685 @@ impl PromptMessage {
     /// Given that the clients will return a message that we only care for the message
     /// this function will return the message as a string to avoid pattern matching.
     ///
+    /// For a [`PromptMessage::MultiModalHumanMessage`] this is the first text part,
+    /// or an empty string if there is none.
+    ///
     /// # Returns
     /// * &[`str`] - the message content
     pub fn content(&self) -> &str {
         match self {
-            PromptMessage::SystemMessage(message) => message,
-            PromptMessage::HumanMessage(message) => message,
-            PromptMessage::AIMessage(message) => message,
+            PromptMessage::SystemMessage(message) => &message.content,
+            PromptMessage::HumanMessage(message) => &message.content,
+            PromptMessage::MultiModalHumanMessage(parts) => parts
+                .iter()
+                .find_map(|part| match part {
+                    ContentPart::Text(text) => Some(text.as_str()),
+                    ContentPart::Image(_) => None,
+                })
+                .unwrap_or_default(),
+            PromptMessage::AIMessage(message) => &message.content,
+        }
+    }
+
+    /// # [`PromptMessage::text`]
+    ///
+    /// All of the text in the message. Unlike [`PromptMessage::content`] every text part of a
+    /// [`PromptMessage::MultiModalHumanMessage`] is included, joined by new lines, so this
+    /// should be used whenever the whole message matters such as counting its tokens.
+    ///
+    /// # Returns
+    /// * [`Cow<str>`] - the text of the message, only allocated for a multimodal message.
+    pub fn text(&self) -> Cow<'_, str> {
+        match self {
+            PromptMessage::MultiModalHumanMessage(parts) => Cow::Owned(
+                parts
+                    .iter()
+                    .filter_map(|part| match part {
+                        ContentPart::Text(text) => Some(text.as_str()),
+                        ContentPart::Image(_) => None,
+                    })
+                    .collect::<Vec<&str>>()
+                    .join("\n"),
+            ),
+            _ => Cow::Borrowed(self.content()),
+        }
+    }
+
+    /// # [`PromptMessage::meta`]
+    ///
+    /// # Returns
+    /// * [`Option<&MessageMeta>`] - the metadata of the message if it has any,
+    ///   a [`PromptMessage::MultiModalHumanMessage`] never does.
+    pub fn meta(&self) -> Option<&MessageMeta> {
+        match self {
+            PromptMessage::SystemMessage(message)
+            | PromptMessage::HumanMessage(message)
+            | PromptMessage::AIMessage(message) => message.meta.as_ref(),
+            PromptMessage::MultiModalHumanMessage(_) => None,
+        }
+    }
+
+    /// # [`PromptMessage::has_images`]
+    ///
+    /// # Returns
+    /// * [`bool`] - whether the message contains any images.
+    pub fn has_images(&self) -> bool {
+        match self {
+            PromptMessage::MultiModalHumanMessage(parts) => parts
+                .iter()
+                .any(|part| matches!(part, ContentPart::Image(_))),
+            _ => false,
+        }
+    }
+
+    /// # [`PromptMessage::role`]
+    ///
+    /// # Returns
+    /// * &[`str`] - the OpenAI role of the message, `system`, `user` or `assistant`.
+    pub fn role(&self) -> &'static str {
+        match self {
+            PromptMessage::SystemMessage(_) => "system",
+            PromptMessage::HumanMessage(_) | PromptMessage::MultiModalHumanMessage(_) => "user",
+            PromptMessage::AIMessage(_) => "assistant",
+        }
+    }
+}
+
+/// # [`MessageBody`]
+/// The text of a [`PromptMessage`] and its optional [`MessageMeta`]. This is created
+/// from a [`String`] or &[`str`] so `PromptMessage::HumanMessage("...".into())` works.
+/// With the `serde` feature a body without metadata is written as just its text.
+#[derive(Debug, Default, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(from = "MessageBodyRepr", into = "MessageBodyRepr")
+)]
+pub struct MessageBody {
+    pub content: String,
+    pub meta: Option<MessageMeta>,
+}
+
+impl MessageBody {
+    /// # [`MessageBody::new`]
+    ///
+    /// # Arguments
+    /// * `content`: impl [`Into<String>`] - the text of the message.
+    ///
+    /// # Returns
+    /// * [`MessageBody`] - the body without any metadata.
+    pub fn new(content: impl Into<String>) -> Self {
+        MessageBody {
+            content: content.into(),
+            meta: None,
+        }
+    }
+
+    /// # [`MessageBody::with_meta`]
+    ///
+    /// # Arguments
+    /// * `meta`: [`MessageMeta`] - the metadata of the message.
+    ///
+    /// # Returns
+    /// * [`MessageBody`] - the body with the metadata set.
+    pub fn with_meta(mut self, meta: MessageMeta) -> Self {
+        self.meta = Some(meta);
+        self
+    }
+}
+
+impl From<String> for MessageBody {
+    fn from(content: String) -> Self {
+        MessageBody::new(content)
+    }
+}
+
+impl From<&str> for MessageBody {
+    fn from(content: &str) -> Self {
+        MessageBody::new(content)
+    }
+}
+
+impl From<&String> for MessageBody {
+    fn from(content: &String) -> Self {
+        MessageBody::new(content.as_str())
+    }
+}
+
+impl Display for MessageBody {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.content)
+    }
+}
+
+/// The serialized form of a [`MessageBody`], plain text unless there is metadata so
+/// messages written before metadata existed can still be read.
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(untagged)]
+enum MessageBodyRepr {
+    Text(String),
+    WithMeta { content: String, meta: MessageMeta },
+}
+
+#[cfg(feature = "serde")]
+impl From<MessageBodyRepr> for MessageBody {
+    fn from(repr: MessageBodyRepr) -> Self {
+        match repr {
+            MessageBodyRepr::Text(content) => MessageBody::new(content),
+            MessageBodyRepr::WithMeta { content, meta } => {
+                MessageBody::new(content).with_meta(meta)
+            }
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl From<MessageBody> for MessageBodyRepr {
+    fn from(body: MessageBody) -> Self {
+        match body.meta {
+            None => MessageBodyRepr::Text(body.content),
+            Some(meta) => MessageBodyRepr::WithMeta {
+                content: body.content,
+                meta,
+            },
+        }
+    }
+}
+
+/// # [`MessageMeta`]
+/// Optional details of a message for persisting history and auditing.
+/// * `name` - who sent the message, sent to providers which support it such as OpenAI.
+/// * `id` - an id for the message, never sent to the provider.
+/// * `created_at` - when the message was created, never sent to the provider.
+#[derive(Debug, Default, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MessageMeta {
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub name: Option<String>,
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub id: Option<String>,
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub created_at: Option<SystemTime>,
+}
+
+impl MessageMeta {
+    /// # [`MessageMeta::with_name`]
+    ///
+    /// # Arguments
+    /// * `name`: impl [`Into<String>`] - who sent the message.
+    ///
+    /// # Returns
+    /// * [`MessageMeta`] - the metadata with the name set.
+    pub fn with_name(mut self, name: impl Into<String>) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    /// # [`MessageMeta::with_id`]
+    ///
+    /// # Arguments
+    /// * `id`: impl [`Into<String>`] - an id for the message.
+    ///
+    /// # Returns
+    /// * [`MessageMeta`] - the metadata with the id set.
+    pub fn with_id(mut self, id: impl Into<String>) -> Self {
+        self.id = Some(id.into());
+        self
+    }
+
+    /// # [`MessageMeta::with_created_at`]
+    ///
+    /// # Arguments
+    /// * `created_at`: [`SystemTime`] - when the message was created.
+    ///
+    /// # Returns
+    /// * [`MessageMeta`] - the metadata with the creation time set.
+    pub fn with_created_at(mut self, created_at: SystemTime) -> Self {
+        self.created_at = Some(created_at);
+        self
+    }
+}
+
+/// # [`ContentPart`]
+/// A part of a [`PromptMessage::MultiModalHumanMessage`].
+/// * [`ContentPart::Text`] - Some text.
+/// * [`ContentPart::Image`] - An image, see [`ImageSource`].
+#[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum ContentPart {
+    Text(String),
+    Image(ImageSource),
+}
+
+/// # [`ImageSource`]
+/// Where the model should read an image from.
+/// * [`ImageSource::Url`] - An image the provider will download.
+/// * [`ImageSource::Base64`] - An image sent with the request, `media_type` is
+///   the mime type of the image e.g. `image/png`.
+#[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum ImageSource {
+    Url(String),
+    Base64 { media_type: String, data: String },
+}
+
+/// [`CompletionStreamValue`]
+///
+/// Value returned from each iteration of the stream.
+/// Given we wanted to represent connecting as a non-failure
+/// state we had to create a new enum to represent this.
+#[cfg(any(
+    feature = "openai-stream",
+    feature = "anthropic-stream",
+    feature = "ollama"
+))]
+/// Once the provider says why generation finished a [`CompletionStreamValue::Finished`]
+/// is sent before the stream ends, streams from providers which do not say never send one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum CompletionStreamValue {
+    Connecting,
+    Message(PromptMessage),
+    Finished(FinishReason),
+}
+
+#[cfg(any(
+    feature = "openai-stream",
+    feature = "anthropic-stream",
+    feature = "ollama"
+))]
+impl StreamedText for CompletionStreamValue {
+    fn streamed_text(&self) -> Option<&str> {
+        match self {
+            CompletionStreamValue::Connecting | CompletionStreamValue::Finished(_) => None,
+            CompletionStreamValue::Message(message) => message.streamed_text(),
+        }
+    }
+}
+
+/// # [`FinishReason`]
+/// Why the model stopped generating. A response which finished with [`FinishReason::Length`]
+/// hit the token limit and was cut short.
+/// * `Stop` - the model finished its answer or hit a stop sequence.
+/// * `Length` - the model hit the max tokens or the context window.
+/// * `ContentFilter` - the provider's content filter removed part of the answer.
+/// * `ToolCalls` - the model stopped to call a tool.
+/// * `Other` - a reason this library does not know, as the provider sent it.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum FinishReason {
+    Stop,
+    Length,
+    ContentFilter,
+    ToolCalls,
+    Other(String),
+}
+
+impl FinishReason {
+    /// # [`FinishReason::is_truncated`]
+    ///
+    /// # Returns
+    /// * [`bool`] - whether the answer was cut short by the token limit.
+    pub fn is_truncated(&self) -> bool {
+        matches!(self, FinishReason::Length)
+    }
+}
+
+/// Parses the reasons sent by OpenAI and the APIs compatible with it, anything else
+/// becomes [`FinishReason::Other`].
+impl FromStr for FinishReason {
+    type Err = Infallible;
+
+    fn from_str(reason: &str) -> Result<Self, Self::Err> {
+        Ok(match reason {
+            "stop" => FinishReason::Stop,
+            "length" => FinishReason::Length,
+            "content_filter" => FinishReason::ContentFilter,
+            "tool_calls" | "function_call" => FinishReason::ToolCalls,
+            other => FinishReason::Other(other.into()),
+        })
+    }
+}
+
+/// # [`DetailedChatResponse`]
+/// The response from a chat client along with the details of the request that produced it.
+/// * `message` - the [`PromptMessage::AIMessage`] returned from the LLM.
+/// * `request_id` - the request id from the [`crate::common::InvocationContext`] the client was invoked with.
+/// * `provider_request_id` - the id the provider assigned to the request, if it returned one.
+/// * `system_fingerprint` - identifies the backend configuration that produced the response, if
+///   the provider returned one. A change in fingerprint means outputs may differ even with a seed.
+/// * `usage` - the tokens the provider reported using for the request, if it returned them.
+/// * `finish_reason` - why the model stopped generating, if the provider said.
+#[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DetailedChatResponse {
+    pub message: PromptMessage,
+    pub request_id: Uuid,
+    pub provider_request_id: Option<String>,
+    pub system_fingerprint: Option<String>,
+    pub usage: Option<TokenUsage>,
+    pub finish_reason: Option<FinishReason>,
+}
+
+impl DetailedChatResponse {
+    /// # [`DetailedChatResponse::is_truncated`]
+    ///
+    /// # Returns
+    /// * [`bool`] - whether the message was cut short by the token limit.
+    pub fn is_truncated(&self) -> bool {
+        self.finish_reason
+            .as_ref()
+            .is_some_and(FinishReason::is_truncated)
+    }
+}
+
+/// # [`ReproducibilityReport`]
+/// Compares two responses to the same prompt, sent with the same seed, to check whether the
+/// output was reproduced. This is useful when regression testing prompts.
+/// * `fingerprints_matched` - whether both responses came from the same backend configuration,
+///   `None` if either response has no fingerprint.
+/// * `outputs_matched` - whether the two messages are identical.
+/// * `diverged_at` - the byte offset into the first message's content where the outputs start to
+///   differ, `None` if they match.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ReproducibilityReport {
+    pub fingerprints_matched: Option<bool>,
+    pub outputs_matched: bool,
+    pub diverged_at: Option<usize>,
+}
+
+impl ReproducibilityReport {
+    /// # [`ReproducibilityReport::compare`]
+    ///
+    /// # Arguments
+    /// * `first`: &[`DetailedChatResponse`] - the response to compare against.
+    /// * `second`: &[`DetailedChatResponse`] - the response to compare.
+    ///
+    /// # Returns
+    /// * [`ReproducibilityReport`] - how the two responses differ.
+    pub fn compare(first: &DetailedChatResponse, second: &DetailedChatResponse) -> Self {
+        let fingerprints_matched: Option<bool> =
+            match (&first.system_fingerprint, &second.system_fingerprint) {
+                (Some(first), Some(second)) => Some(first == second),
+                _ => None,
+            };
+        let outputs_matched: bool = first.message == second.message;
+        let diverged_at: Option<usize> = match outputs_matched {
+            true => None,
+            false => {
+                let (first, second) = (first.message.content(), second.message.content());
+                Some(
+                    first
+                        .char_indices()
+                        .zip(second.chars())
+                        .find(|((_, a), b)| a != b)
+                        .map(|((index, _), _)| index)
+                        .unwrap_or(first.len().min(second.len())),
+                )
+            }
+        };
+        ReproducibilityReport {
+            fingerprints_matched,
+            outputs_matched,
+            diverged_at,
+        }
+    }
+
+    /// # [`ReproducibilityReport::is_reproducible`]
+    ///
+    /// # Returns
+    /// * [`bool`] - true if the fingerprints are known to match and the outputs are identical.
+    pub fn is_reproducible(&self) -> bool {
+        self.fingerprints_matched == Some(true) && self.outputs_matched
+    }
+}
+
+/// # [`RequestContext`]
+///
+/// Describes the request a client error came from so it can be correlated with a provider
+/// incident. Only the path of the endpoint is kept and neither the API key nor any message
+/// content is included, so it is safe to log.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RequestContext {
+    /// The path of the endpoint e.g. `/v1/chat/completions`
+    pub endpoint: String,
+    /// The model the request was for, [`None`] if the body did not name one
+    pub model: Option<String>,
+    /// How long after the first attempt was sent the request failed, written as whole
+    /// milliseconds with the `serde` feature
+    #[cfg_attr(feature = "serde", serde(with = "crate::common::duration_millis"))]
+    pub elapsed: Duration,
+    /// Which attempt failed, starting from 1
+    pub attempt: u32,
+}
+
+#[cfg(any(
+    feature = "openai-embeddings",
+    feature = "openai-chat",
+    feature = "anthropic",
+    feature = "ollama",
+    feature = "cohere",
+    feature = "bedrock"
+))]
+impl RequestContext {
+    /// # [`RequestContext::new`]
+    ///
+    /// # Arguments
+    /// * `url`: &[`str`] - the url the request was sent to, only the path is kept.
+    /// * `body`: &impl [`serde::Serialize`] - the body of the request, only the model is read from it.
+    /// * `elapsed`: [`Duration`] - how long the request took before failing.
+    /// * `attempt`: [`u32`] - which attempt failed.
+    ///
+    /// # Returns
+    /// * [`RequestContext`] - the context of the request.
+    pub(crate) fn new(
+        url: &str,
+        body: &impl serde::Serialize,
+        elapsed: Duration,
+        attempt: u32,
+    ) -> Self {
+        let without_scheme: &str = url.split_once("://").map_or(url, |(_, rest)| rest);
+        let path: &str = without_scheme
+            .find('/')
+            .map_or("/", |start| &without_scheme[start..]);
+        let endpoint: &str = path.split(['?', '#']).next().unwrap_or(path);
+        let model: Option<String> = serde_json::to_value(body)
+            .ok()
+            .and_then(|body| body.get("model")?.as_str().map(String::from));
+        RequestContext {
+            endpoint: endpoint.to_string(),
+            model,
+            elapsed,
+            attempt,
         }
     }
 }
 
+impl Display for RequestContext {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "endpoint = {}", self.endpoint)?;
+        if let Some(model) = &self.model {
+            write!(f, ", model = {}", model)?;
+        }
+        write!(
+            f,
+            ", elapsed = {:?}, attempt = {}",
+            self.elapsed, self.attempt
+        )
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    fn detailed(content: &str, fingerprint: Option<&str>) -> DetailedChatResponse {
+        DetailedChatResponse {
+            message: PromptMessage::AIMessage(content.into()),
+            request_id: Uuid::nil(),
+            provider_request_id: None,
+            system_fingerprint: fingerprint.map(String::from),
+            usage: None,
+            finish_reason: None,
+        }
+    }
+
+    #[test]
+    fn finish_reasons_are_parsed_and_unknown_ones_kept() {
+        let parse = |reason: &str| FinishReason::from_str(reason).unwrap();
+        assert_eq!(parse("stop"), FinishReason::Stop);
+        assert_eq!(parse("length"), FinishReason::Length);
+        assert_eq!(parse("content_filter"), FinishReason::ContentFilter);
+        assert_eq!(parse("tool_calls"), FinishReason::ToolCalls);
+        assert_eq!(parse("eos"), FinishReason::Other("eos".into()));
+        assert!(parse("length").is_truncated());
+        assert!(!parse("stop").is_truncated());
+    }
+
+    #[test]
+    fn only_responses_finished_by_length_are_truncated() {
+        let mut response = detailed("The answer is", None);
+        assert!(!response.is_truncated());
+        response.finish_reason = Some(FinishReason::Length);
+        assert!(response.is_truncated());
+    }
+
+    #[test]
+    #[cfg(any(
+        feature = "openai-embeddings",
+        feature = "openai-chat",
+        feature = "anthropic"
+    ))]
+    fn request_context_keeps_only_the_path_and_model() {
+        let body = serde_json::json!({"model": "gpt-4o", "messages": ["secret question"]});
+        let context = RequestContext::new(
+            "https://api.openai.com/v1/chat/completions?key=sk-123",
+            &body,
+            Duration::from_millis(1500),
+            2,
+        );
+        assert_eq!(context.endpoint, "/v1/chat/completions");
+        assert_eq!(context.model.as_deref(), Some("gpt-4o"));
+        assert_eq!(
+            context.to_string(),
+            "endpoint = /v1/chat/completions, model = gpt-4o, elapsed = 1.5s, attempt = 2"
+        );
+
+        let context = RequestContext::new("http://127.0.0.1:1234", &(), Duration::ZERO, 1);
+        assert_eq!(context.endpoint, "/");
+        assert_eq!(context.model, None);
+        assert_eq!(
+            context.to_string(),
+            "endpoint = /, elapsed = 0ns, attempt = 1"
+        );
+    }
+
+    #[test]
+    fn reproducibility_report_compares_fingerprints_and_outputs() {
+        let report = ReproducibilityReport::compare(
+            &detailed("same answer", Some("fp_1")),
+            &detailed("same answer", Some("fp_1")),
+        );
+        assert!(report.is_reproducible());
+        assert_eq!(report.diverged_at, None);
+
+        let report = ReproducibilityReport::compare(
+            &detailed("same answer", Some("fp_1")),
+            &detailed("same reply", Some("fp_2")),
+        );
+        assert_eq!(
+            report,
+            ReproducibilityReport {
+                fingerprints_matched: Some(false),
+                outputs_matched: false,
+                diverged_at: Some(5),
+            }
+        );
+
+        // A prefix diverges where the shorter output ends
+        let report = ReproducibilityReport::compare(
+            &detailed("same", None),
+            &detailed("same answer", Some("fp_1")),
+        );
+        assert_eq!(report.fingerprints_matched, None);
+        assert_eq!(report.diverged_at, Some(4));
+        assert!(!report.is_reproducible());
+    }
+
     #[test]
     fn prompt_message_content() {
         let test_string = String::from("Test String");
         assert_eq!(
             &test_string,
-            PromptMessage::HumanMessage(test_string.clone()).content()
+            PromptMessage::HumanMessage(test_string.clone().into()).content()
         );
         assert_eq!(
             &test_string,
-            PromptMessage::AIMessage(test_string.clone()).content()
+            PromptMessage::AIMessage(test_string.clone().into()).content()
         );
         assert_eq!(
             &test_string,
-            PromptMessage::SystemMessage(test_string.clone()).content()
+            PromptMessage::SystemMessage(test_string.clone().into()).content()
+        );
+    }
+
+    #[test]
+    fn meta_is_only_on_text_messages() {
+        let meta = MessageMeta::default()
+            .with_name("alice")
+            .with_id("message-1")
+            .with_created_at(SystemTime::UNIX_EPOCH);
+        let message = PromptMessage::HumanMessage(MessageBody::from("hi").with_meta(meta.clone()));
+        assert_eq!(message.meta(), Some(&meta));
+        assert_eq!(message.content(), "hi");
+        assert_ne!(message, PromptMessage::HumanMessage("hi".into()));
+        assert_eq!(PromptMessage::AIMessage("hi".into()).meta(), None);
+        assert_eq!(
+            PromptMessage::MultiModalHumanMessage(Vec::new()).meta(),
+            None
         );
     }
+
+    #[test]
+    fn multi_modal_content_is_the_first_text_part() {
+        let image = ContentPart::Image(ImageSource::Url("https://example.com/a.png".into()));
+        let message = PromptMessage::MultiModalHumanMessage(vec![
+            image.clone(),
+            ContentPart::Text("What is this?".into()),
+        ]);
+        assert_eq!(message.content(), "What is this?");
+        assert!(message.has_images());
+        assert_eq!(
+            PromptMessage::MultiModalHumanMessage(vec![image]).content(),
+            ""
+        );
+        assert!(!PromptMessage::HumanMessage("text".into()).has_images());
+    }
+
+    #[test]
+    fn multi_modal_text_joins_every_text_part() {
+        let message = PromptMessage::MultiModalHumanMessage(vec![
+            ContentPart::Text("What is this?".into()),
+            ContentPart::Image(ImageSource::Url("https://example.com/a.png".into())),
+            ContentPart::Text("Be brief".into()),
+        ]);
+        assert_eq!(message.text(), "What is this?\nBe brief");
+        assert_eq!(PromptMessage::HumanMessage("text".into()).text(), "text");
+    }
 }
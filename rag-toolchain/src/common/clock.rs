@@ -0,0 +1,259 @@
+use std::fmt::Debug;
+use std::future::Future;
+use std::pin::Pin;
+use std::time::{Duration, Instant};
+
+#[cfg(any(test, feature = "test-utils"))]
+use std::sync::Mutex;
+#[cfg(any(test, feature = "test-utils"))]
+use std::task::{Context, Poll, Waker};
+
+/// The future returned by [`Clock::sleep`]
+pub type SleepFuture<'a> = Pin<Box<dyn Future<Output = ()> + Send + 'a>>;
+
+/// # [`Clock`]
+///
+/// The source of time for anything which expires or waits, such as the cached API keys.
+/// Components take an [`std::sync::Arc<dyn Clock>`] which defaults to [`SystemClock`] so
+/// tests can swap in a `MockClock` (with the `test-utils` feature) and control time exactly
+/// instead of sleeping.
+pub trait Clock: Debug + Send + Sync {
+    /// # [`Clock::now`]
+    ///
+    /// # Returns
+    /// * [`Instant`] - the current time.
+    fn now(&self) -> Instant;
+
+    /// # [`Clock::sleep`]
+    ///
+    /// # Arguments
+    /// * `duration`: [`Duration`] - how long to wait.
+    ///
+    /// # Returns
+    /// * [`SleepFuture`] - resolves once the duration has passed on this clock.
+    fn sleep(&self, duration: Duration) -> SleepFuture<'_>;
+}
+
+/// # [`SystemClock`]
+///
+/// The default [`Clock`], backed by the tokio timer. This means it also follows tokio's
+/// paused time in tests which use `tokio::time::pause`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        tokio::time::Instant::now().into_std()
+    }
+
+    fn sleep(&self, duration: Duration) -> SleepFuture<'_> {
+        Box::pin(tokio::time::sleep(duration))
+    }
+}
+
+/// # [`MockClock`]
+///
+/// A [`Clock`] which only moves when [`MockClock::advance`] is called. Sleeps resolve as soon
+/// as the clock has been advanced past their deadline, so no real time passes in tests.
+/// Only available with the `test-utils` feature.
+#[cfg(any(test, feature = "test-utils"))]
+#[derive(Debug)]
+pub struct MockClock {
+    state: Mutex<MockClockState>,
+}
+
+#[cfg(any(test, feature = "test-utils"))]
+#[derive(Debug)]
+struct MockClockState {
+    now: Instant,
+    next_id: u64,
+    /// The sleeps which have been polled and not yet finished
+    sleepers: Vec<Sleeper>,
+}
+
+#[cfg(any(test, feature = "test-utils"))]
+#[derive(Debug)]
+struct Sleeper {
+    id: u64,
+    deadline: Instant,
+    waker: Waker,
+}
+
+#[cfg(any(test, feature = "test-utils"))]
+impl MockClock {
+    /// # [`MockClock::new`]
+    ///
+    /// # Returns
+    /// * [`MockClock`] - a clock stopped at the current time.
+    pub fn new() -> Self {
+        MockClock {
+            state: Mutex::new(MockClockState {
+                now: Instant::now(),
+                next_id: 0,
+                sleepers: Vec::new(),
+            }),
+        }
+    }
+
+    /// # [`MockClock::advance`]
+    ///
+    /// Moves the clock forward and wakes every sleep whose deadline has been reached.
+    ///
+    /// # Arguments
+    /// * `duration`: [`Duration`] - how far to move the clock.
+    pub fn advance(&self, duration: Duration) {
+        let woken: Vec<Waker> = {
+            let mut state = self.state.lock().unwrap();
+            state.now += duration;
+            let now: Instant = state.now;
+            let (due, pending): (Vec<Sleeper>, Vec<Sleeper>) = state
+                .sleepers
+                .drain(..)
+                .partition(|sleeper| sleeper.deadline <= now);
+            state.sleepers = pending;
+            due.into_iter().map(|sleeper| sleeper.waker).collect()
+        };
+        // Woken outside the lock so a woken task can use the clock straight away
+        woken.into_iter().for_each(Waker::wake);
+    }
+
+    /// # [`MockClock::pending_sleeps`]
+    ///
+    /// # Returns
+    /// * [`usize`] - how many sleeps are waiting for the clock to be advanced.
+    pub fn pending_sleeps(&self) -> usize {
+        self.state.lock().unwrap().sleepers.len()
+    }
+}
+
+#[cfg(any(test, feature = "test-utils"))]
+impl Default for MockClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(any(test, feature = "test-utils"))]
+impl Clock for MockClock {
+    fn now(&self) -> Instant {
+        self.state.lock().unwrap().now
+    }
+
+    fn sleep(&self, duration: Duration) -> SleepFuture<'_> {
+        let mut state = self.state.lock().unwrap();
+        let id: u64 = state.next_id;
+        state.next_id += 1;
+        let deadline: Instant = state.now + duration;
+        Box::pin(MockSleep {
+            clock: self,
+            id,
+            deadline,
+        })
+    }
+}
+
+/// # [`MockSleep`]
+///
+/// A sleep on a [`MockClock`]. Polling registers the waker with the clock, replacing the
+/// waker from any earlier poll, and dropping the sleep removes it.
+#[cfg(any(test, feature = "test-utils"))]
+struct MockSleep<'a> {
+    clock: &'a MockClock,
+    id: u64,
+    deadline: Instant,
+}
+
+#[cfg(any(test, feature = "test-utils"))]
+impl Future for MockSleep<'_> {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let mut state = self.clock.state.lock().unwrap();
+        if state.now >= self.deadline {
+            state.sleepers.retain(|sleeper| sleeper.id != self.id);
+            return Poll::Ready(());
+        }
+        match state
+            .sleepers
+            .iter_mut()
+            .find(|sleeper| sleeper.id == self.id)
+        {
+            Some(sleeper) => sleeper.waker.clone_from(cx.waker()),
+            None => state.sleepers.push(Sleeper {
+                id: self.id,
+                deadline: self.deadline,
+                waker: cx.waker().clone(),
+            }),
+        }
+        Poll::Pending
+    }
+}
+
+#[cfg(any(test, feature = "test-utils"))]
+impl Drop for MockSleep<'_> {
+    fn drop(&mut self) {
+        if let Ok(mut state) = self.clock.state.lock() {
+            state.sleepers.retain(|sleeper| sleeper.id != self.id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
+    #[test]
+    fn now_only_moves_when_advanced() {
+        let clock = MockClock::new();
+        let start: Instant = clock.now();
+        std::thread::sleep(Duration::from_millis(5));
+        assert_eq!(clock.now(), start);
+        clock.advance(Duration::from_secs(90));
+        assert_eq!(clock.now() - start, Duration::from_secs(90));
+    }
+
+    #[tokio::test]
+    async fn sleeps_wake_once_their_deadline_is_reached() {
+        let clock = Arc::new(MockClock::new());
+        let finished = Arc::new(AtomicBool::new(false));
+        let task = tokio::spawn({
+            let (clock, finished) = (clock.clone(), finished.clone());
+            async move {
+                clock.sleep(Duration::from_secs(10)).await;
+                finished.store(true, Ordering::SeqCst);
+            }
+        });
+        while clock.pending_sleeps() == 0 {
+            tokio::task::yield_now().await;
+        }
+
+        clock.advance(Duration::from_secs(9));
+        tokio::task::yield_now().await;
+        assert!(!finished.load(Ordering::SeqCst));
+        assert_eq!(clock.pending_sleeps(), 1);
+
+        clock.advance(Duration::from_secs(1));
+        task.await.unwrap();
+        assert!(finished.load(Ordering::SeqCst));
+        assert_eq!(clock.pending_sleeps(), 0);
+    }
+
+    #[tokio::test]
+    async fn zero_and_dropped_sleeps_leave_nothing_pending() {
+        let clock = MockClock::new();
+        clock.sleep(Duration::ZERO).await;
+        let sleep = clock.sleep(Duration::from_secs(1));
+        let timed_out = tokio::time::timeout(Duration::from_millis(1), sleep).await;
+        assert!(timed_out.is_err());
+        assert_eq!(clock.pending_sleeps(), 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn system_clock_follows_tokio_time() {
+        let start: Instant = SystemClock.now();
+        SystemClock.sleep(Duration::from_secs(30)).await;
+        assert!(SystemClock.now() - start >= Duration::from_secs(30));
+    }
+}
@@ -0,0 +1,73 @@
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::future::Future;
+use std::pin::Pin;
+
+/// The future returned by the methods of the type erased traits such as
+/// [`crate::stores::DynEmbeddingStore`]
+pub type DynFuture<'a, T> = Pin<Box<dyn Future<Output = Result<T, DynError>> + Send + 'a>>;
+
+/// # [`DynError`]
+///
+/// The error of the type erased traits [`crate::stores::DynEmbeddingStore`],
+/// [`crate::retrievers::DynAsyncRetriever`], [`crate::retrievers::DynAsyncReranker`] and
+/// [`crate::clients::DynAsyncChatClient`], it holds the error of the implementation it was
+/// returned by. The error is transparent, it displays as and has the same source as the error
+/// it holds, use [`DynError::downcast_ref`] to get it back.
+#[derive(Debug)]
+pub struct DynError(Box<dyn Error + Send + Sync>);
+
+impl DynError {
+    /// # [`DynError::new`]
+    ///
+    /// # Arguments
+    /// * `error`: impl [`Error`] - the error to hold.
+    ///
+    /// # Returns
+    /// * [`DynError`] - holding the error.
+    pub fn new(error: impl Error + Send + Sync + 'static) -> Self {
+        DynError(Box::new(error))
+    }
+
+    /// # [`DynError::get_ref`]
+    ///
+    /// # Returns
+    /// * &(dyn [`Error`] + [`Send`] + [`Sync`]) - the error this holds.
+    pub fn get_ref(&self) -> &(dyn Error + Send + Sync + 'static) {
+        self.0.as_ref()
+    }
+
+    /// # [`DynError::downcast_ref`]
+    ///
+    /// # Returns
+    /// * [`Option<&E>`] - the error this holds if it is an `E`.
+    pub fn downcast_ref<E: Error + 'static>(&self) -> Option<&E> {
+        self.0.downcast_ref::<E>()
+    }
+
+    /// # [`DynError::into_inner`]
+    ///
+    /// # Returns
+    /// * [`Box<dyn Error + Send + Sync>`] - the error this holds.
+    pub fn into_inner(self) -> Box<dyn Error + Send + Sync> {
+        self.0
+    }
+}
+
+impl Display for DynError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(&self.0, f)
+    }
+}
+
+impl Error for DynError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        self.0.source()
+    }
+}
+
+impl From<Box<dyn Error + Send + Sync>> for DynError {
+    fn from(error: Box<dyn Error + Send + Sync>) -> Self {
+        DynError(error)
+    }
+}
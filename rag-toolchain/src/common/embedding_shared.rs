@@ -1,6 +1,8 @@
 use serde::{Deserialize, Serialize};
+use std::fmt::{self, Debug, Formatter};
+use std::ptr;
 use tiktoken_rs::tokenizer::Tokenizer;
-use tiktoken_rs::CoreBPE;
+use tiktoken_rs::{cl100k_base_singleton, o200k_base_singleton, CoreBPE};
 
 // ---------------------- Embedding Models ----------------------
 /// # [`EmbeddingModel`]
@@ -11,9 +13,19 @@ pub trait EmbeddingModel {
     fn metadata(&self) -> EmbeddingModelMetadata;
 }
 
+/// Allows a model to be lent to a store while it is still used elsewhere,
+/// e.g. passing `&client` for a client configured with its own dimensions.
+impl<T: EmbeddingModel + ?Sized> EmbeddingModel for &T {
+    fn metadata(&self) -> EmbeddingModelMetadata {
+        (**self).metadata()
+    }
+}
+
 /// # [`EmbeddingModelMetadata`]
 /// Struct to contain all of the relevant metadata for an embedding model
 pub struct EmbeddingModelMetadata {
+    /// The name the provider gives the embedding model, e.g. `text-embedding-3-small`
+    pub name: String,
     /// The dimension of the vectors produced by the embedding model
     pub dimensions: usize,
     /// The maximum amount of tokens that can be sent to the embedding model
@@ -22,12 +34,56 @@ pub struct EmbeddingModelMetadata {
     pub tokenizer: Box<dyn TokenizerWrapper>,
 }
 
+impl EmbeddingModelMetadata {
+    /// # [`EmbeddingModelMetadata::count_tokens`]
+    ///
+    /// # Arguments
+    /// * `text`: &[`str`] - the text to count.
+    ///
+    /// # Returns
+    /// * [`usize`] - the number of tokens the text uses with the model's tokenizer.
+    pub fn count_tokens(&self, text: &str) -> usize {
+        self.tokenizer.count_tokens(text)
+    }
+}
+
 /// # [`TokenizerWrapper`]
 /// We wrap the tokenizer for a specific embedding model to allow
 /// for a common interface for tokenization.
-pub trait TokenizerWrapper {
+pub trait TokenizerWrapper: Send + Sync {
     // This should potentially go back to a Result
     fn tokenize(&self, text: &str) -> Option<Vec<String>>;
+
+    /// # [`TokenizerWrapper::count_tokens`]
+    ///
+    /// The number of tokens in the text, this lets a prompt be sized before it is sent.
+    /// The default counts the tokens from [`TokenizerWrapper::tokenize`] and gives 0 if the
+    /// text could not be tokenized, tokenizers which can count without splitting the text
+    /// should override this.
+    ///
+    /// # Arguments
+    /// * `text`: &[`str`] - the text to count.
+    ///
+    /// # Returns
+    /// * [`usize`] - the number of tokens.
+    fn count_tokens(&self, text: &str) -> usize {
+        self.tokenize(text).map_or(0, |tokens| tokens.len())
+    }
+}
+
+/// Lets the structs holding a tokenizer still derive [`Debug`]
+impl Debug for dyn TokenizerWrapper {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str("TokenizerWrapper")
+    }
+}
+
+/// Tokenizers have no general notion of equality, two are only equal if they are the same
+/// tokenizer. This lets the structs holding them still be compared.
+impl PartialEq for dyn TokenizerWrapper {
+    fn eq(&self, other: &Self) -> bool {
+        ptr::addr_eq(self, other)
+    }
 }
 // -------------------------------------------------------------
 
@@ -53,16 +109,19 @@ impl EmbeddingModel for OpenAIEmbeddingModel {
     fn metadata(&self) -> EmbeddingModelMetadata {
         match self {
             OpenAIEmbeddingModel::TextEmbeddingAda002 => EmbeddingModelMetadata {
+                name: "text-embedding-ada-002".into(),
                 dimensions: 1536,
                 max_tokens: 8192,
                 tokenizer: Box::new(OpenAITokenizer::new(Tokenizer::Cl100kBase)),
             },
             OpenAIEmbeddingModel::TextEmbedding3Small => EmbeddingModelMetadata {
+                name: "text-embedding-3-small".into(),
                 dimensions: 1536,
                 max_tokens: 8192,
                 tokenizer: Box::new(OpenAITokenizer::new(Tokenizer::Cl100kBase)),
             },
             OpenAIEmbeddingModel::TextEmbedding3Large => EmbeddingModelMetadata {
+                name: "text-embedding-3-large".into(),
                 dimensions: 3072,
                 max_tokens: 8192,
                 tokenizer: Box::new(OpenAITokenizer::new(Tokenizer::Cl100kBase)),
@@ -74,7 +133,7 @@ impl EmbeddingModel for OpenAIEmbeddingModel {
 /// We use the tiktoken_rs library to handle the tokenization for OpenAI models.
 /// So this is the struct will implement [`TokenizerWrapper`] which we can then
 /// use in the rest of the library to tokenize text.
-struct OpenAITokenizer {
+pub(crate) struct OpenAITokenizer {
     bpe: CoreBPE,
 }
 
@@ -97,9 +156,55 @@ impl TokenizerWrapper for OpenAITokenizer {
             None
         }
     }
+
+    // Encoding never fails, unlike splitting which needs every token to be valid UTF-8
+    fn count_tokens(&self, text: &str) -> usize {
+        self.bpe.encode_with_special_tokens(text).len()
+    }
+}
+
+/// Counts tokens with the shared tiktoken tokenizers so counting many times does not rebuild
+/// the tokenizer, only cl100k and o200k are shared and any other tokenizer is built to count.
+pub(crate) fn count_tiktoken_tokens(tokenizer: Tokenizer, text: &str) -> usize {
+    let bpe = match tokenizer {
+        Tokenizer::O200kBase => o200k_base_singleton(),
+        Tokenizer::Cl100kBase => cl100k_base_singleton(),
+        tokenizer => return OpenAITokenizer::new(tokenizer).count_tokens(text),
+    };
+    let bpe = bpe.lock();
+    bpe.encode_with_special_tokens(text).len()
 }
 // ------------------ OpenAI Embedding Models ------------------
 
+// ------------------ Cohere Embedding Models ------------------
+/// # [`CohereEmbeddingModel`]
+/// Top level enum to hold the Cohere embedding model variants.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone, Copy)]
+pub enum CohereEmbeddingModel {
+    #[serde(rename = "embed-english-v3.0")]
+    EmbedEnglishV3,
+    #[serde(rename = "embed-multilingual-v3.0")]
+    EmbedMultilingualV3,
+}
+
+/// Cohere's tokenizers are not available offline, so tokens are estimated with
+/// cl100k which is close enough for sizing chunks.
+impl EmbeddingModel for CohereEmbeddingModel {
+    fn metadata(&self) -> EmbeddingModelMetadata {
+        let name: &str = match self {
+            CohereEmbeddingModel::EmbedEnglishV3 => "embed-english-v3.0",
+            CohereEmbeddingModel::EmbedMultilingualV3 => "embed-multilingual-v3.0",
+        };
+        EmbeddingModelMetadata {
+            name: name.into(),
+            dimensions: 1024,
+            max_tokens: 512,
+            tokenizer: Box::new(OpenAITokenizer::new(Tokenizer::Cl100kBase)),
+        }
+    }
+}
+// ------------------ Cohere Embedding Models ------------------
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -107,6 +212,7 @@ mod tests {
     #[test]
     fn openai_ada002_metadata() {
         let metadata: EmbeddingModelMetadata = OpenAIEmbeddingModel::TextEmbeddingAda002.metadata();
+        assert_eq!(metadata.name, "text-embedding-ada-002");
         assert_eq!(metadata.dimensions, 1536);
         assert_eq!(metadata.max_tokens, 8192);
     }
@@ -114,6 +220,7 @@ mod tests {
     #[test]
     fn openai_3_small_metadata() {
         let metadata: EmbeddingModelMetadata = OpenAIEmbeddingModel::TextEmbedding3Small.metadata();
+        assert_eq!(metadata.name, "text-embedding-3-small");
         assert_eq!(metadata.dimensions, 1536);
         assert_eq!(metadata.max_tokens, 8192);
     }
@@ -121,7 +228,59 @@ mod tests {
     #[test]
     fn openai_3_large_metadata() {
         let metadata: EmbeddingModelMetadata = OpenAIEmbeddingModel::TextEmbedding3Large.metadata();
+        assert_eq!(metadata.name, "text-embedding-3-large");
         assert_eq!(metadata.dimensions, 3072);
         assert_eq!(metadata.max_tokens, 8192);
     }
+
+    #[test]
+    fn cohere_v3_metadata() {
+        for model in [
+            CohereEmbeddingModel::EmbedEnglishV3,
+            CohereEmbeddingModel::EmbedMultilingualV3,
+        ] {
+            let metadata: EmbeddingModelMetadata = model.metadata();
+            // The name is the one the model is sent to the API as
+            assert_eq!(serde_json::json!(metadata.name), serde_json::json!(model));
+            assert_eq!(metadata.dimensions, 1024);
+            assert_eq!(metadata.max_tokens, 512);
+        }
+    }
+
+    #[test]
+    fn metadata_counts_tokens_with_the_tokenizer() {
+        let metadata: EmbeddingModelMetadata = OpenAIEmbeddingModel::TextEmbedding3Small.metadata();
+        assert_eq!(metadata.name, "text-embedding-3-small");
+        assert_eq!(metadata.count_tokens("hello world"), 2);
+        assert_eq!(metadata.count_tokens(""), 0);
+        let text = "🦀 日本語";
+        assert_eq!(
+            metadata.count_tokens(text),
+            count_tiktoken_tokens(Tokenizer::Cl100kBase, text)
+        );
+    }
+
+    #[test]
+    fn count_tokens_defaults_to_the_tokenized_length() {
+        struct WordTokenizer;
+        impl TokenizerWrapper for WordTokenizer {
+            fn tokenize(&self, text: &str) -> Option<Vec<String>> {
+                Some(text.split_whitespace().map(String::from).collect())
+            }
+        }
+        assert_eq!(WordTokenizer.count_tokens("one two three"), 3);
+    }
+
+    #[test]
+    fn shared_tokenizers_count_the_same_as_built_ones() {
+        let text = "The quick brown fox jumps over the lazy dog 日本語";
+        assert_eq!(
+            count_tiktoken_tokens(Tokenizer::Cl100kBase, text),
+            OpenAITokenizer::new(Tokenizer::Cl100kBase).count_tokens(text)
+        );
+        assert_eq!(
+            count_tiktoken_tokens(Tokenizer::O200kBase, text),
+            OpenAITokenizer::new(Tokenizer::O200kBase).count_tokens(text)
+        );
+    }
 }
@@ -0,0 +1,971 @@
+use std::error::Error as StdError;
+use thiserror::Error;
+
+use crate::chains::{ChainError, PromptVariableError, RagChainError};
+use crate::chunkers::{
+    ChunkBatchError, ContentDefinedChunkingError, MarkdownChunkingError, RecursiveChunkingError,
+    SentenceChunkingError, TokenChunkingError,
+};
+use crate::clients::ModerationError;
+use crate::evaluation::EvaluationError;
+use crate::formats::FormatError;
+use crate::loaders::DirectorySourceError;
+use crate::retrievers::{
+    BatchRetrieveError, InMemoryRetrieverError, MultiQueryRetrieverError, RerankingRetrieverError,
+};
+use crate::stores::InMemoryVectorStoreError;
+
+#[cfg(feature = "analysis")]
+use crate::analysis::ClusteringError;
+#[cfg(any(feature = "openai-chat", feature = "anthropic"))]
+use crate::clients::AdditionalConfigError;
+#[cfg(feature = "anthropic")]
+use crate::clients::AnthropicError;
+#[cfg(feature = "bedrock")]
+use crate::clients::BedrockError;
+#[cfg(feature = "cohere")]
+use crate::clients::CohereError;
+#[cfg(any(
+    feature = "openai-embeddings",
+    feature = "openai-chat",
+    feature = "anthropic"
+))]
+use crate::clients::HttpConfigError;
+#[cfg(feature = "ollama")]
+use crate::clients::OllamaError;
+#[cfg(feature = "openai-embeddings")]
+use crate::clients::OpenAIEmbeddingConfigError;
+#[cfg(any(feature = "openai-embeddings", feature = "openai-chat"))]
+use crate::clients::OpenAIError;
+#[cfg(any(
+    feature = "openai-embeddings",
+    feature = "openai-chat",
+    feature = "anthropic",
+    feature = "cohere"
+))]
+use crate::clients::SecretError;
+#[cfg(feature = "embedding-cache")]
+use crate::clients::{EmbeddingCacheError, FileCacheError};
+#[cfg(feature = "html")]
+use crate::loaders::HtmlSourceError;
+#[cfg(feature = "pg_vector")]
+use crate::retrievers::PostgresRetrieverError;
+#[cfg(feature = "sqlite_vec")]
+use crate::retrievers::SqliteRetrieverError;
+#[cfg(feature = "sqlite_vec")]
+use crate::stores::SqliteVectorStoreError;
+#[cfg(feature = "pg_vector")]
+use crate::stores::{HookError, PostgresVectorStoreError};
+
+/// The error a generic error is boxed into, it keeps its [`StdError::source`] chain
+type BoxedError = Box<dyn StdError + Send + Sync + 'static>;
+
+/// # [`RagToolchainError`]
+///
+/// Every error in the crate converts into this one, so a function running a whole pipeline
+/// can use `?` across clients, stores, chunkers and chains. The original error is kept as the
+/// [`StdError::source`], so it can still be downcast or printed as part of the chain. Returns
+/// throughout the crate are unchanged, convert into this error only where it is more convenient
+/// than the precise one.
+///
+/// Errors which are generic over the error of a client, such as [`RagChainError`], are boxed
+/// into one variant per kind of component. Whether they can be retried is worked out from the
+/// errors they wrap when converting, see [`Retryable`].
+///
+/// # Examples
+/// ```
+/// use rag_toolchain::chunkers::TokenChunker;
+/// use rag_toolchain::common::{OpenAIEmbeddingModel, RagToolchainError};
+/// use std::num::NonZeroUsize;
+///
+/// fn chunker() -> Result<TokenChunker, RagToolchainError> {
+///     let chunk_size = NonZeroUsize::new(100).unwrap();
+///     Ok(TokenChunker::try_new(chunk_size, 200, OpenAIEmbeddingModel::TextEmbeddingAda002)?)
+/// }
+///
+/// let Err(error) = chunker() else {
+///     panic!("the overlap is larger than the chunk size");
+/// };
+/// assert!(matches!(error, RagToolchainError::TokenChunking(_)));
+/// assert!(!error.is_retryable());
+/// ```
+#[derive(Error, Debug)]
+pub enum RagToolchainError {
+    #[cfg(any(feature = "openai-embeddings", feature = "openai-chat"))]
+    #[error("OpenAI Error: {0}")]
+    OpenAI(#[from] OpenAIError),
+    #[cfg(feature = "openai-embeddings")]
+    #[error("OpenAI Embedding Config Error: {0}")]
+    OpenAIEmbeddingConfig(#[from] OpenAIEmbeddingConfigError),
+    #[cfg(any(feature = "openai-chat", feature = "anthropic"))]
+    #[error("Additional Config Error: {0}")]
+    AdditionalConfig(#[from] AdditionalConfigError),
+    #[cfg(any(
+        feature = "openai-embeddings",
+        feature = "openai-chat",
+        feature = "anthropic"
+    ))]
+    #[error("HTTP Config Error: {0}")]
+    HttpConfig(#[from] HttpConfigError),
+    #[cfg(feature = "anthropic")]
+    #[error("Anthropic Error: {0}")]
+    Anthropic(#[from] AnthropicError),
+    #[cfg(feature = "ollama")]
+    #[error("Ollama Error: {0}")]
+    Ollama(#[from] OllamaError),
+    #[cfg(feature = "cohere")]
+    #[error("Cohere Error: {0}")]
+    Cohere(#[from] CohereError),
+    #[cfg(feature = "bedrock")]
+    #[error("Bedrock Error: {0}")]
+    Bedrock(#[from] BedrockError),
+    #[cfg(any(
+        feature = "openai-embeddings",
+        feature = "openai-chat",
+        feature = "anthropic",
+        feature = "cohere"
+    ))]
+    #[error("Secret Error: {0}")]
+    Secret(#[from] SecretError),
+    #[error("Moderation Error: {0}")]
+    Moderation(#[from] ModerationError),
+    #[cfg(feature = "embedding-cache")]
+    #[error("File Cache Error: {0}")]
+    FileCache(#[from] FileCacheError),
+    #[cfg(feature = "pg_vector")]
+    #[error("Postgres Vector Store Error: {0}")]
+    PostgresVectorStore(#[from] PostgresVectorStoreError),
+    #[cfg(feature = "pg_vector")]
+    #[error("Store Hook Error: {0}")]
+    Hook(#[from] HookError),
+    #[cfg(feature = "sqlite_vec")]
+    #[error("Sqlite Vector Store Error: {0}")]
+    SqliteVectorStore(#[from] SqliteVectorStoreError),
+    #[error("In Memory Vector Store Error: {0}")]
+    InMemoryVectorStore(#[from] InMemoryVectorStoreError),
+    #[error("Token Chunking Error: {0}")]
+    TokenChunking(#[from] TokenChunkingError),
+    #[error("Recursive Chunking Error: {0}")]
+    RecursiveChunking(#[from] RecursiveChunkingError),
+    #[error("Content Defined Chunking Error: {0}")]
+    ContentDefinedChunking(#[from] ContentDefinedChunkingError),
+    #[error("Sentence Chunking Error: {0}")]
+    SentenceChunking(#[from] SentenceChunkingError),
+    #[error("Markdown Chunking Error: {0}")]
+    MarkdownChunking(#[from] MarkdownChunkingError),
+    #[error("Prompt Variable Error: {0}")]
+    PromptVariable(#[from] PromptVariableError),
+    #[error("Format Error: {0}")]
+    Format(#[from] FormatError),
+    #[error("Directory Source Error: {0}")]
+    DirectorySource(#[from] DirectorySourceError),
+    #[cfg(feature = "html")]
+    #[error("HTML Source Error: {0}")]
+    HtmlSource(#[from] HtmlSourceError),
+    #[cfg(feature = "analysis")]
+    #[error("Clustering Error: {0}")]
+    Clustering(#[from] ClusteringError),
+    /// From any of the retriever errors e.g. [`crate::retrievers::PostgresRetrieverError`]
+    #[error("Retriever Error: {source}")]
+    Retriever { source: BoxedError, retryable: bool },
+    /// From [`RagChainError`] or [`ChainError`]
+    #[error("Chain Error: {source}")]
+    Chain { source: BoxedError, retryable: bool },
+    /// From [`ChunkBatchError`]
+    #[error("Chunk Batch Error: {source}")]
+    ChunkBatch { source: BoxedError, retryable: bool },
+    /// From [`EvaluationError`]
+    #[error("Evaluation Error: {source}")]
+    Evaluation { source: BoxedError, retryable: bool },
+    /// From [`crate::clients::EmbeddingCacheError`]
+    #[cfg(feature = "embedding-cache")]
+    #[error("Embedding Cache Error: {source}")]
+    EmbeddingCache { source: BoxedError, retryable: bool },
+}
+
+impl RagToolchainError {
+    /// # [`RagToolchainError::is_retryable`]
+    ///
+    /// # Returns
+    /// * [`bool`] - true if the error came from a rate limit, a timeout or a 5xx style
+    ///   response from a provider, so the same call may succeed if it is tried again.
+    pub fn is_retryable(&self) -> bool {
+        Retryable::is_retryable(self)
+    }
+}
+
+/// # [`Retryable`]
+///
+/// Whether an error is transient so the call which returned it may succeed if it is tried
+/// again, see [`RagToolchainError::is_retryable`]. Every error in the crate implements it,
+/// the generic errors are retryable when the error they wrap is. Implement it for the error of
+/// your own client so its errors can be converted into a [`RagToolchainError`], the default is
+/// to never retry.
+pub trait Retryable: StdError {
+    /// # [`Retryable::is_retryable`]
+    ///
+    /// # Returns
+    /// * [`bool`] - true if the same call may succeed if it is tried again.
+    fn is_retryable(&self) -> bool {
+        false
+    }
+}
+
+/// Rate limits and server side failures are worth retrying
+#[cfg(any(
+    feature = "openai-embeddings",
+    feature = "openai-chat",
+    feature = "anthropic",
+    feature = "ollama",
+    feature = "cohere",
+    feature = "bedrock",
+    feature = "html"
+))]
+fn is_retryable_status(status: u16) -> bool {
+    status == 429 || status >= 500
+}
+
+impl Retryable for RagToolchainError {
+    fn is_retryable(&self) -> bool {
+        match self {
+            #[cfg(any(feature = "openai-embeddings", feature = "openai-chat"))]
+            RagToolchainError::OpenAI(error) => error.is_retryable(),
+            #[cfg(feature = "anthropic")]
+            RagToolchainError::Anthropic(error) => error.is_retryable(),
+            #[cfg(feature = "ollama")]
+            RagToolchainError::Ollama(error) => error.is_retryable(),
+            #[cfg(feature = "cohere")]
+            RagToolchainError::Cohere(error) => error.is_retryable(),
+            #[cfg(feature = "bedrock")]
+            RagToolchainError::Bedrock(error) => error.is_retryable(),
+            #[cfg(feature = "html")]
+            RagToolchainError::HtmlSource(error) => error.is_retryable(),
+            RagToolchainError::Retriever { retryable, .. }
+            | RagToolchainError::Chain { retryable, .. }
+            | RagToolchainError::ChunkBatch { retryable, .. }
+            | RagToolchainError::Evaluation { retryable, .. } => *retryable,
+            #[cfg(feature = "embedding-cache")]
+            RagToolchainError::EmbeddingCache { retryable, .. } => *retryable,
+            _ => false,
+        }
+    }
+}
+
+// --------------------------------------------------------------------------------
+// Providers
+
+#[cfg(any(feature = "openai-embeddings", feature = "openai-chat"))]
+impl Retryable for OpenAIError {
+    fn is_retryable(&self) -> bool {
+        match self.kind() {
+            OpenAIError::CODE429(_)
+            | OpenAIError::CODE500(_)
+            | OpenAIError::CODE503(_)
+            | OpenAIError::Timeout(_) => true,
+            OpenAIError::Undefined(status, _) => is_retryable_status(*status),
+            _ => false,
+        }
+    }
+}
+
+#[cfg(feature = "anthropic")]
+impl Retryable for AnthropicError {
+    fn is_retryable(&self) -> bool {
+        match self.kind() {
+            AnthropicError::CODE429(_)
+            | AnthropicError::CODE500(_)
+            | AnthropicError::CODE503(_)
+            | AnthropicError::Timeout(_) => true,
+            AnthropicError::Undefined(status, _) => is_retryable_status(*status),
+            _ => false,
+        }
+    }
+}
+
+#[cfg(feature = "ollama")]
+impl Retryable for OllamaError {
+    fn is_retryable(&self) -> bool {
+        match self.kind() {
+            OllamaError::CODE500(_) => true,
+            OllamaError::Undefined(status, _) => is_retryable_status(*status),
+            _ => false,
+        }
+    }
+}
+
+#[cfg(feature = "cohere")]
+impl Retryable for CohereError {
+    fn is_retryable(&self) -> bool {
+        match self.kind() {
+            CohereError::CODE429(_) | CohereError::CODE500(_) | CohereError::CODE503(_) => true,
+            CohereError::Undefined(status, _) => is_retryable_status(*status),
+            _ => false,
+        }
+    }
+}
+
+#[cfg(feature = "bedrock")]
+impl Retryable for BedrockError {
+    fn is_retryable(&self) -> bool {
+        match self.kind() {
+            BedrockError::Throttling(_)
+            | BedrockError::ModelTimeout(_)
+            | BedrockError::ModelNotReady(_)
+            | BedrockError::InternalServer(_)
+            | BedrockError::ServiceUnavailable(_) => true,
+            BedrockError::Undefined(status, _) => is_retryable_status(*status),
+            _ => false,
+        }
+    }
+}
+
+#[cfg(feature = "html")]
+impl Retryable for HtmlSourceError {
+    fn is_retryable(&self) -> bool {
+        match self {
+            HtmlSourceError::Timeout { .. } => true,
+            HtmlSourceError::ErrorStatus { status, .. } => is_retryable_status(*status),
+            _ => false,
+        }
+    }
+}
+
+// --------------------------------------------------------------------------------
+// Errors which are never retryable
+
+#[cfg(feature = "openai-embeddings")]
+impl Retryable for OpenAIEmbeddingConfigError {}
+#[cfg(any(feature = "openai-chat", feature = "anthropic"))]
+impl Retryable for AdditionalConfigError {}
+#[cfg(any(
+    feature = "openai-embeddings",
+    feature = "openai-chat",
+    feature = "anthropic"
+))]
+impl Retryable for HttpConfigError {}
+#[cfg(any(
+    feature = "openai-embeddings",
+    feature = "openai-chat",
+    feature = "anthropic",
+    feature = "cohere"
+))]
+impl Retryable for SecretError {}
+impl Retryable for ModerationError {}
+#[cfg(feature = "embedding-cache")]
+impl Retryable for FileCacheError {}
+#[cfg(feature = "pg_vector")]
+impl Retryable for PostgresVectorStoreError {}
+#[cfg(feature = "pg_vector")]
+impl Retryable for HookError {}
+#[cfg(feature = "sqlite_vec")]
+impl Retryable for SqliteVectorStoreError {}
+impl Retryable for InMemoryVectorStoreError {}
+impl Retryable for TokenChunkingError {}
+impl Retryable for RecursiveChunkingError {}
+impl Retryable for ContentDefinedChunkingError {}
+impl Retryable for SentenceChunkingError {}
+impl Retryable for MarkdownChunkingError {}
+impl Retryable for PromptVariableError {}
+impl Retryable for FormatError {}
+impl Retryable for DirectorySourceError {}
+#[cfg(feature = "analysis")]
+impl Retryable for ClusteringError {}
+
+// --------------------------------------------------------------------------------
+// Errors generic over the error of a client
+
+#[cfg(feature = "pg_vector")]
+impl<T: Retryable> Retryable for PostgresRetrieverError<T> {
+    fn is_retryable(&self) -> bool {
+        match self {
+            PostgresRetrieverError::EmbeddingClientError(error) => error.is_retryable(),
+            _ => false,
+        }
+    }
+}
+
+#[cfg(feature = "sqlite_vec")]
+impl<T: Retryable> Retryable for SqliteRetrieverError<T> {
+    fn is_retryable(&self) -> bool {
+        match self {
+            SqliteRetrieverError::EmbeddingClientError(error) => error.is_retryable(),
+            _ => false,
+        }
+    }
+}
+
+impl<T: Retryable> Retryable for InMemoryRetrieverError<T> {
+    fn is_retryable(&self) -> bool {
+        match self {
+            InMemoryRetrieverError::EmbeddingClientError(error) => error.is_retryable(),
+            InMemoryRetrieverError::DimensionMismatch { .. } => false,
+        }
+    }
+}
+
+impl<T: Retryable, U: Retryable> Retryable for MultiQueryRetrieverError<T, U> {
+    fn is_retryable(&self) -> bool {
+        match self {
+            MultiQueryRetrieverError::ChatClientError(error) => error.is_retryable(),
+            MultiQueryRetrieverError::RetrieverError(error) => error.is_retryable(),
+        }
+    }
+}
+
+impl<R: Retryable, K: Retryable> Retryable for RerankingRetrieverError<R, K> {
+    fn is_retryable(&self) -> bool {
+        match self {
+            RerankingRetrieverError::Retriever(error) => error.is_retryable(),
+            RerankingRetrieverError::Reranker(error) => error.is_retryable(),
+        }
+    }
+}
+
+impl<E: Retryable> Retryable for BatchRetrieveError<E> {
+    fn is_retryable(&self) -> bool {
+        match self {
+            BatchRetrieveError::Query { error, .. } => error.is_retryable(),
+            BatchRetrieveError::Batch(error) => error.is_retryable(),
+        }
+    }
+}
+
+impl<T: Retryable, U: Retryable> Retryable for RagChainError<T, U> {
+    fn is_retryable(&self) -> bool {
+        match self {
+            RagChainError::ChatClientError(error) => error.is_retryable(),
+            RagChainError::RetrieverError(error) => error.is_retryable(),
+            RagChainError::Timeout { .. } => true,
+            _ => false,
+        }
+    }
+}
+
+impl<T: Retryable> Retryable for ChainError<T> {
+    fn is_retryable(&self) -> bool {
+        match self {
+            ChainError::ChatClientError(error) => error.is_retryable(),
+            ChainError::Timeout { .. } => true,
+            _ => false,
+        }
+    }
+}
+
+impl<E: Retryable> Retryable for ChunkBatchError<E> {
+    fn is_retryable(&self) -> bool {
+        self.failures.iter().any(|(_, error)| error.is_retryable())
+    }
+}
+
+impl<E: Retryable> Retryable for EvaluationError<E> {
+    fn is_retryable(&self) -> bool {
+        match self {
+            EvaluationError::RetrieverError { error, .. } => error.is_retryable(),
+            _ => false,
+        }
+    }
+}
+
+#[cfg(feature = "embedding-cache")]
+impl<C: Retryable, B: Retryable> Retryable for EmbeddingCacheError<C, B> {
+    fn is_retryable(&self) -> bool {
+        match self {
+            EmbeddingCacheError::Client(error) => error.is_retryable(),
+            EmbeddingCacheError::Backend(error) => error.is_retryable(),
+            EmbeddingCacheError::CountMismatch { .. } => false,
+        }
+    }
+}
+
+// --------------------------------------------------------------------------------
+// Conversions of the generic errors, the boxed error keeps whether it is retryable
+
+#[cfg(feature = "pg_vector")]
+impl<T> From<PostgresRetrieverError<T>> for RagToolchainError
+where
+    T: Retryable + Send + Sync + 'static,
+{
+    fn from(error: PostgresRetrieverError<T>) -> Self {
+        RagToolchainError::Retriever {
+            retryable: error.is_retryable(),
+            source: Box::new(error),
+        }
+    }
+}
+
+#[cfg(feature = "sqlite_vec")]
+impl<T> From<SqliteRetrieverError<T>> for RagToolchainError
+where
+    T: Retryable + Send + Sync + 'static,
+{
+    fn from(error: SqliteRetrieverError<T>) -> Self {
+        RagToolchainError::Retriever {
+            retryable: error.is_retryable(),
+            source: Box::new(error),
+        }
+    }
+}
+
+impl<T> From<InMemoryRetrieverError<T>> for RagToolchainError
+where
+    T: Retryable + Send + Sync + 'static,
+{
+    fn from(error: InMemoryRetrieverError<T>) -> Self {
+        RagToolchainError::Retriever {
+            retryable: error.is_retryable(),
+            source: Box::new(error),
+        }
+    }
+}
+
+impl<T, U> From<MultiQueryRetrieverError<T, U>> for RagToolchainError
+where
+    T: Retryable + Send + Sync + 'static,
+    U: Retryable + Send + Sync + 'static,
+{
+    fn from(error: MultiQueryRetrieverError<T, U>) -> Self {
+        RagToolchainError::Retriever {
+            retryable: error.is_retryable(),
+            source: Box::new(error),
+        }
+    }
+}
+
+impl<R, K> From<RerankingRetrieverError<R, K>> for RagToolchainError
+where
+    R: Retryable + Send + Sync + 'static,
+    K: Retryable + Send + Sync + 'static,
+{
+    fn from(error: RerankingRetrieverError<R, K>) -> Self {
+        RagToolchainError::Retriever {
+            retryable: error.is_retryable(),
+            source: Box::new(error),
+        }
+    }
+}
+
+impl<E> From<BatchRetrieveError<E>> for RagToolchainError
+where
+    E: Retryable + Send + Sync + 'static,
+{
+    fn from(error: BatchRetrieveError<E>) -> Self {
+        RagToolchainError::Retriever {
+            retryable: error.is_retryable(),
+            source: Box::new(error),
+        }
+    }
+}
+
+impl<T, U> From<RagChainError<T, U>> for RagToolchainError
+where
+    T: Retryable + Send + Sync + 'static,
+    U: Retryable + Send + Sync + 'static,
+{
+    fn from(error: RagChainError<T, U>) -> Self {
+        RagToolchainError::Chain {
+            retryable: error.is_retryable(),
+            source: Box::new(error),
+        }
+    }
+}
+
+impl<T> From<ChainError<T>> for RagToolchainError
+where
+    T: Retryable + Send + Sync + 'static,
+{
+    fn from(error: ChainError<T>) -> Self {
+        RagToolchainError::Chain {
+            retryable: error.is_retryable(),
+            source: Box::new(error),
+        }
+    }
+}
+
+impl<E> From<ChunkBatchError<E>> for RagToolchainError
+where
+    E: Retryable + Send + Sync + 'static,
+{
+    fn from(error: ChunkBatchError<E>) -> Self {
+        RagToolchainError::ChunkBatch {
+            retryable: error.is_retryable(),
+            source: Box::new(error),
+        }
+    }
+}
+
+impl<E> From<EvaluationError<E>> for RagToolchainError
+where
+    E: Retryable + Send + Sync + 'static,
+{
+    fn from(error: EvaluationError<E>) -> Self {
+        RagToolchainError::Evaluation {
+            retryable: error.is_retryable(),
+            source: Box::new(error),
+        }
+    }
+}
+
+#[cfg(feature = "embedding-cache")]
+impl<C, B> From<EmbeddingCacheError<C, B>> for RagToolchainError
+where
+    C: Retryable + Send + Sync + 'static,
+    B: Retryable + Send + Sync + 'static,
+{
+    fn from(error: EmbeddingCacheError<C, B>) -> Self {
+        RagToolchainError::EmbeddingCache {
+            retryable: error.is_retryable(),
+            source: Box::new(error),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chains::ChainPhase;
+
+    // A client error which is retryable when it holds true
+    #[derive(Error, Debug, PartialEq)]
+    #[error("Transient: {0}")]
+    struct Transient(bool);
+
+    impl Retryable for Transient {
+        fn is_retryable(&self) -> bool {
+            self.0
+        }
+    }
+
+    #[test]
+    fn every_error_converts() {
+        #[cfg(any(feature = "openai-embeddings", feature = "openai-chat"))]
+        assert!(matches!(
+            RagToolchainError::from(OpenAIError::ErrorSendingRequest("refused".into())),
+            RagToolchainError::OpenAI(_)
+        ));
+        #[cfg(feature = "openai-embeddings")]
+        assert!(matches!(
+            RagToolchainError::from(OpenAIEmbeddingConfigError::DimensionsTooLarge {
+                requested: 2,
+                max: 1
+            }),
+            RagToolchainError::OpenAIEmbeddingConfig(_)
+        ));
+        #[cfg(any(feature = "openai-chat", feature = "anthropic"))]
+        assert!(matches!(
+            RagToolchainError::from(AdditionalConfigError::ReservedKeys(vec!["model".into()])),
+            RagToolchainError::AdditionalConfig(_)
+        ));
+        #[cfg(any(
+            feature = "openai-embeddings",
+            feature = "openai-chat",
+            feature = "anthropic"
+        ))]
+        assert!(matches!(
+            RagToolchainError::from(HttpConfigError::ClientBuildError("no TLS".into())),
+            RagToolchainError::HttpConfig(_)
+        ));
+        #[cfg(feature = "anthropic")]
+        assert!(matches!(
+            RagToolchainError::from(AnthropicError::Timeout("slow".into())),
+            RagToolchainError::Anthropic(_)
+        ));
+        #[cfg(feature = "ollama")]
+        assert!(matches!(
+            RagToolchainError::from(OllamaError::UnsupportedContent("image".into())),
+            RagToolchainError::Ollama(_)
+        ));
+        #[cfg(feature = "cohere")]
+        assert!(matches!(
+            RagToolchainError::from(CohereError::ErrorGettingResponseBody("eof".into())),
+            RagToolchainError::Cohere(_)
+        ));
+        #[cfg(feature = "bedrock")]
+        assert!(matches!(
+            RagToolchainError::from(BedrockError::ErrorLoadingCredentials("none".into())),
+            RagToolchainError::Bedrock(_)
+        ));
+        #[cfg(any(
+            feature = "openai-embeddings",
+            feature = "openai-chat",
+            feature = "anthropic",
+            feature = "cohere"
+        ))]
+        assert!(matches!(
+            RagToolchainError::from(SecretError::NotFound("KEY".into())),
+            RagToolchainError::Secret(_)
+        ));
+        assert!(matches!(
+            RagToolchainError::from(ModerationError("down".into())),
+            RagToolchainError::Moderation(_)
+        ));
+        #[cfg(feature = "embedding-cache")]
+        assert!(matches!(
+            RagToolchainError::from(FileCacheError::IoError(std::io::Error::other("disk"))),
+            RagToolchainError::FileCache(_)
+        ));
+        #[cfg(feature = "pg_vector")]
+        assert!(matches!(
+            RagToolchainError::from(PostgresVectorStoreError::EnvVarError(
+                std::env::VarError::NotPresent
+            )),
+            RagToolchainError::PostgresVectorStore(_)
+        ));
+        #[cfg(feature = "pg_vector")]
+        assert!(matches!(
+            RagToolchainError::from(HookError::new("vetoed")),
+            RagToolchainError::Hook(_)
+        ));
+        #[cfg(feature = "sqlite_vec")]
+        assert!(matches!(
+            RagToolchainError::from(SqliteVectorStoreError::DimensionMismatch {
+                expected: 2,
+                found: 1
+            }),
+            RagToolchainError::SqliteVectorStore(_)
+        ));
+        assert!(matches!(
+            RagToolchainError::from(InMemoryVectorStoreError::DimensionMismatch {
+                expected: 2,
+                found: 1
+            }),
+            RagToolchainError::InMemoryVectorStore(_)
+        ));
+        assert!(matches!(
+            RagToolchainError::from(TokenChunkingError::InvalidChunkSize("0".into())),
+            RagToolchainError::TokenChunking(_)
+        ));
+        assert!(matches!(
+            RagToolchainError::from(RecursiveChunkingError::InvalidChunkSize("0".into())),
+            RagToolchainError::RecursiveChunking(_)
+        ));
+        assert!(matches!(
+            RagToolchainError::from(ContentDefinedChunkingError::InvalidChunkSize("0".into())),
+            RagToolchainError::ContentDefinedChunking(_)
+        ));
+        assert!(matches!(
+            RagToolchainError::from(SentenceChunkingError::InvalidChunkSize("0".into())),
+            RagToolchainError::SentenceChunking(_)
+        ));
+        assert!(matches!(
+            RagToolchainError::from(MarkdownChunkingError::TokenizationError("bad".into())),
+            RagToolchainError::MarkdownChunking(_)
+        ));
+        assert!(matches!(
+            RagToolchainError::from(PromptVariableError::Unresolved(vec!["name".into()])),
+            RagToolchainError::PromptVariable(_)
+        ));
+        assert!(matches!(
+            RagToolchainError::from(FormatError::InvalidDocument("{".into())),
+            RagToolchainError::Format(_)
+        ));
+        assert!(matches!(
+            RagToolchainError::from(DirectorySourceError::ReadingFile {
+                path: "notes.md".into(),
+                source: std::io::Error::other("denied"),
+            }),
+            RagToolchainError::DirectorySource(_)
+        ));
+        #[cfg(feature = "html")]
+        assert!(matches!(
+            RagToolchainError::from(HtmlSourceError::ErrorStatus {
+                url: "https://example.com".into(),
+                status: 404,
+            }),
+            RagToolchainError::HtmlSource(_)
+        ));
+        #[cfg(feature = "analysis")]
+        assert!(matches!(
+            RagToolchainError::from(ClusteringError::NotEnoughEmbeddings { k: 2, found: 1 }),
+            RagToolchainError::Clustering(_)
+        ));
+    }
+
+    #[test]
+    fn every_generic_error_converts() {
+        #[cfg(feature = "pg_vector")]
+        assert!(matches!(
+            RagToolchainError::from(PostgresRetrieverError::<Transient>::EmptyQuery),
+            RagToolchainError::Retriever { .. }
+        ));
+        #[cfg(feature = "sqlite_vec")]
+        assert!(matches!(
+            RagToolchainError::from(SqliteRetrieverError::EmbeddingClientError(Transient(true))),
+            RagToolchainError::Retriever { .. }
+        ));
+        assert!(matches!(
+            RagToolchainError::from(InMemoryRetrieverError::EmbeddingClientError(Transient(
+                true
+            ))),
+            RagToolchainError::Retriever { .. }
+        ));
+        assert!(matches!(
+            RagToolchainError::from(
+                MultiQueryRetrieverError::<Transient, Transient>::RetrieverError(Transient(true))
+            ),
+            RagToolchainError::Retriever { .. }
+        ));
+        assert!(matches!(
+            RagToolchainError::from(RerankingRetrieverError::<Transient, Transient>::Reranker(
+                Transient(true)
+            )),
+            RagToolchainError::Retriever { .. }
+        ));
+        assert!(matches!(
+            RagToolchainError::from(BatchRetrieveError::Batch(Transient(true))),
+            RagToolchainError::Retriever { .. }
+        ));
+        assert!(matches!(
+            RagToolchainError::from(RagChainError::<Transient, Transient>::ContentFlagged(
+                vec![]
+            )),
+            RagToolchainError::Chain { .. }
+        ));
+        assert!(matches!(
+            RagToolchainError::from(ChainError::ChatClientError(Transient(true))),
+            RagToolchainError::Chain { .. }
+        ));
+        assert!(matches!(
+            RagToolchainError::from(ChunkBatchError::<Transient> {
+                chunks: vec![Vec::new()],
+                failures: vec![(0, Transient(false))],
+            }),
+            RagToolchainError::ChunkBatch { .. }
+        ));
+        assert!(matches!(
+            RagToolchainError::from(EvaluationError::RetrieverError {
+                index: 0,
+                error: Transient(true)
+            }),
+            RagToolchainError::Evaluation {
+                retryable: true,
+                ..
+            }
+        ));
+        #[cfg(feature = "embedding-cache")]
+        assert!(matches!(
+            RagToolchainError::from(EmbeddingCacheError::<Transient, Transient>::Backend(
+                Transient(true)
+            )),
+            RagToolchainError::EmbeddingCache { .. }
+        ));
+    }
+
+    #[test]
+    fn source_can_be_downcast() {
+        let error: RagToolchainError = TokenChunkingError::InvalidChunkSize("0".into()).into();
+        let source = error.source().unwrap();
+        assert_eq!(
+            source.downcast_ref::<TokenChunkingError>(),
+            Some(&TokenChunkingError::InvalidChunkSize("0".into()))
+        );
+        assert_eq!(error.to_string(), "Token Chunking Error: 0");
+
+        let error: RagToolchainError =
+            RagChainError::<Transient, Transient>::ChatClientError(Transient(true)).into();
+        let source = error.source().unwrap();
+        assert_eq!(
+            source.downcast_ref::<RagChainError<Transient, Transient>>(),
+            Some(&RagChainError::ChatClientError(Transient(true)))
+        );
+        assert_eq!(
+            error.to_string(),
+            "Chain Error: Chat Client Error: Transient: true"
+        );
+    }
+
+    #[test]
+    fn generic_errors_are_retryable_when_the_wrapped_error_is() {
+        let retryable = |error: RagToolchainError| error.is_retryable();
+        assert!(retryable(
+            RagChainError::<Transient, InMemoryRetrieverError<Transient>>::RetrieverError(
+                InMemoryRetrieverError::EmbeddingClientError(Transient(true))
+            )
+            .into()
+        ));
+        assert!(!retryable(
+            RagChainError::<Transient, InMemoryRetrieverError<Transient>>::RetrieverError(
+                InMemoryRetrieverError::EmbeddingClientError(Transient(false))
+            )
+            .into()
+        ));
+        assert!(!retryable(
+            RagChainError::<Transient, Transient>::TopKTooLarge {
+                requested: 2,
+                max: 1
+            }
+            .into()
+        ));
+        assert!(retryable(
+            RagChainError::<Transient, Transient>::Timeout {
+                phase: ChainPhase::Retrieval
+            }
+            .into()
+        ));
+        assert!(retryable(
+            ChainError::<Transient>::Timeout {
+                phase: ChainPhase::Chat
+            }
+            .into()
+        ));
+        assert!(retryable(
+            ChunkBatchError::<Transient> {
+                chunks: vec![Vec::new(), Vec::new()],
+                failures: vec![(0, Transient(false)), (1, Transient(true))],
+            }
+            .into()
+        ));
+        assert!(!retryable(
+            TokenChunkingError::InvalidChunkSize("0".into()).into()
+        ));
+    }
+
+    #[cfg(any(feature = "openai-embeddings", feature = "openai-chat"))]
+    #[test]
+    fn openai_rate_limits_and_server_errors_are_retryable() {
+        let retryable = |error: OpenAIError| RagToolchainError::from(error).is_retryable();
+        assert!(retryable(OpenAIError::Undefined(429, "slow down".into())));
+        assert!(retryable(OpenAIError::Undefined(502, "bad gateway".into())));
+        assert!(retryable(OpenAIError::Timeout("slow".into())));
+        assert!(!retryable(OpenAIError::Undefined(404, "missing".into())));
+        assert!(!retryable(OpenAIError::EmptyChunk(0)));
+    }
+
+    #[cfg(feature = "anthropic")]
+    #[test]
+    fn anthropic_rate_limits_and_server_errors_are_retryable() {
+        let retryable = |error: AnthropicError| RagToolchainError::from(error).is_retryable();
+        assert!(retryable(AnthropicError::Undefined(
+            529,
+            "overloaded".into()
+        )));
+        assert!(retryable(AnthropicError::Timeout("slow".into())));
+        assert!(!retryable(AnthropicError::Undefined(400, "bad".into())));
+    }
+
+    #[cfg(feature = "bedrock")]
+    #[test]
+    fn bedrock_throttling_and_server_errors_are_retryable() {
+        let body = || crate::clients::BedrockErrorBody {
+            message: "try again".into(),
+        };
+        let retryable = |error: BedrockError| RagToolchainError::from(error).is_retryable();
+        assert!(retryable(BedrockError::Throttling(body())));
+        assert!(retryable(BedrockError::ServiceUnavailable(body())));
+        assert!(!retryable(BedrockError::AccessDenied(body())));
+    }
+
+    #[cfg(feature = "html")]
+    #[test]
+    fn html_error_statuses_are_retryable_when_transient() {
+        let status = |status: u16| {
+            RagToolchainError::from(HtmlSourceError::ErrorStatus {
+                url: "https://example.com".into(),
+                status,
+            })
+            .is_retryable()
+        };
+        assert!(status(503));
+        assert!(status(429));
+        assert!(!status(404));
+    }
+}
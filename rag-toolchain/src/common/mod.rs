@@ -1,7 +1,19 @@
 /// # Common
 /// This module contains common types and traits used across the project
+mod clock;
+#[cfg(feature = "serde")]
+pub(crate) mod duration_millis;
+mod dyn_error;
 mod embedding_shared;
+mod error;
+#[cfg(all(test, feature = "tracing"))]
+pub(crate) mod span_recorder;
 mod types;
 
+#[cfg(any(test, feature = "test-utils"))]
+pub use clock::MockClock;
+pub use clock::{Clock, SleepFuture, SystemClock};
+pub use dyn_error::{DynError, DynFuture};
 pub use embedding_shared::*;
+pub use error::{RagToolchainError, Retryable};
 pub use types::*;
@@ -0,0 +1,148 @@
+use std::fmt::Debug;
+use std::sync::{Arc, Mutex};
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Event, Level, Metadata, Subscriber};
+use tracing_core::span::Current;
+
+/// # [`RecordedSpan`]
+/// A span seen by the [`SpanRecorder`] along with the name of the span it was created in.
+#[derive(Debug, Clone)]
+pub(crate) struct RecordedSpan {
+    pub name: &'static str,
+    metadata: &'static Metadata<'static>,
+    pub parent: Option<&'static str>,
+    pub fields: Vec<(&'static str, String)>,
+}
+
+impl RecordedSpan {
+    pub fn field(&self, name: &str) -> Option<&str> {
+        self.fields
+            .iter()
+            .find(|(field, _)| *field == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// # [`RecordedEvent`]
+/// An event seen by the [`SpanRecorder`] with its fields formatted into one string.
+#[derive(Debug, Clone)]
+pub(crate) struct RecordedEvent {
+    pub level: Level,
+    pub fields: String,
+}
+
+/// # [`SpanRecorder`]
+/// Test only subscriber which keeps every span and event so tests can assert what was
+/// instrumented. Install it with [`tracing::subscriber::set_default`], the current span is
+/// tracked with a single stack so it is only accurate on a current thread runtime.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct SpanRecorder {
+    spans: Arc<Mutex<Vec<RecordedSpan>>>,
+    events: Arc<Mutex<Vec<RecordedEvent>>>,
+    entered: Arc<Mutex<Vec<u64>>>,
+}
+
+impl SpanRecorder {
+    pub fn spans(&self) -> Vec<RecordedSpan> {
+        self.spans.lock().unwrap().clone()
+    }
+
+    pub fn span(&self, name: &str) -> Option<RecordedSpan> {
+        self.spans().into_iter().find(|span| span.name == name)
+    }
+
+    pub fn events(&self) -> Vec<RecordedEvent> {
+        self.events.lock().unwrap().clone()
+    }
+
+    fn name_of(&self, id: &Id) -> &'static str {
+        self.spans.lock().unwrap()[id.into_u64() as usize - 1].name
+    }
+}
+
+struct FieldVisitor<'a>(&'a mut Vec<(&'static str, String)>);
+
+impl Visit for FieldVisitor<'_> {
+    fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+        self.0.retain(|(name, _)| *name != field.name());
+        self.0.push((field.name(), format!("{:?}", value)));
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.retain(|(name, _)| *name != field.name());
+        self.0.push((field.name(), value.to_string()));
+    }
+}
+
+impl Subscriber for SpanRecorder {
+    fn enabled(&self, _metadata: &Metadata<'_>) -> bool {
+        true
+    }
+
+    fn new_span(&self, attributes: &Attributes<'_>) -> Id {
+        let parent: Option<&'static str> = match attributes.parent() {
+            Some(parent) => Some(self.name_of(parent)),
+            None if attributes.is_contextual() => {
+                let current: Option<u64> = self.entered.lock().unwrap().last().copied();
+                current.map(|id| self.name_of(&Id::from_u64(id)))
+            }
+            None => None,
+        };
+        let mut fields: Vec<(&'static str, String)> = Vec::new();
+        attributes.record(&mut FieldVisitor(&mut fields));
+        let mut spans = self.spans.lock().unwrap();
+        spans.push(RecordedSpan {
+            name: attributes.metadata().name(),
+            metadata: attributes.metadata(),
+            parent,
+            fields,
+        });
+        Id::from_u64(spans.len() as u64)
+    }
+
+    fn record(&self, span: &Id, values: &Record<'_>) {
+        let mut spans = self.spans.lock().unwrap();
+        let recorded: &mut RecordedSpan = &mut spans[span.into_u64() as usize - 1];
+        values.record(&mut FieldVisitor(&mut recorded.fields));
+    }
+
+    fn record_follows_from(&self, _span: &Id, _follows: &Id) {}
+
+    fn event(&self, event: &Event<'_>) {
+        let mut fields: Vec<(&'static str, String)> = Vec::new();
+        event.record(&mut FieldVisitor(&mut fields));
+        let fields: String = fields
+            .into_iter()
+            .map(|(name, value)| format!("{}={}", name, value))
+            .collect::<Vec<String>>()
+            .join(" ");
+        self.events.lock().unwrap().push(RecordedEvent {
+            level: *event.metadata().level(),
+            fields,
+        });
+    }
+
+    fn enter(&self, span: &Id) {
+        self.entered.lock().unwrap().push(span.into_u64());
+    }
+
+    fn current_span(&self) -> Current {
+        let current: Option<u64> = self.entered.lock().unwrap().last().copied();
+        match current {
+            Some(id) => {
+                let spans = self.spans.lock().unwrap();
+                let metadata: &'static Metadata<'static> = spans[id as usize - 1].metadata;
+                Current::new(Id::from_u64(id), metadata)
+            }
+            None => Current::none(),
+        }
+    }
+
+    fn exit(&self, span: &Id) {
+        let mut entered = self.entered.lock().unwrap();
+        if let Some(position) = entered.iter().rposition(|id| *id == span.into_u64()) {
+            entered.remove(position);
+        }
+    }
+}
@@ -1,5 +1,7 @@
 use serde::{Deserialize, Serialize};
+use std::ops::{Add, AddAssign};
 use std::sync::Arc;
+use uuid::Uuid;
 
 // ----------------- Embedding -----------------
 /// # [`Embedding`]
@@ -48,6 +50,11 @@ impl Embedding {
     pub fn vector(&self) -> Vec<f32> {
         self.vector.as_ref().to_vec()
     }
+
+    /// Borrows the vector without copying it
+    pub(crate) fn vector_slice(&self) -> &[f32] {
+        &self.vector
+    }
 }
 // ---------------------------------------------
 
@@ -114,10 +121,291 @@ impl Chunk {
     pub fn metadata(&self) -> &serde_json::Value {
         &self.metadata
     }
+
+    /// # [`Chunk::chunk_index`]
+    /// Getter for the position of the chunk in its document, see [`CHUNK_INDEX_KEY`].
+    ///
+    /// # Returns
+    /// * [`Option<u64>`] - the index if the metadata holds one
+    pub fn chunk_index(&self) -> Option<u64> {
+        self.metadata
+            .get(CHUNK_INDEX_KEY)
+            .and_then(|index| index.as_u64())
+    }
+
+    /// # [`Chunk::document_id`]
+    /// Getter for the id of the document the chunk came from, see [`DOCUMENT_ID_KEY`].
+    ///
+    /// # Returns
+    /// * [`Option<&serde_json::Value>`] - the id if the metadata holds one
+    pub fn document_id(&self) -> Option<&serde_json::Value> {
+        self.metadata.get(DOCUMENT_ID_KEY)
+    }
 }
+
+/// The metadata key holding the position of a chunk within its document, counting from 0.
+/// [`crate::chunkers::Chunker::chunk_document_indexed`] stamps it onto every chunk.
+pub const CHUNK_INDEX_KEY: &str = "chunk_index";
+
+/// The metadata key holding the id of the document a chunk came from. Together with
+/// [`CHUNK_INDEX_KEY`] this lets the neighbours of a chunk be found, see
+/// [`crate::chains::NeighborExpansion`].
+pub const DOCUMENT_ID_KEY: &str = "document_id";
 // ------------------------------------------
 
 // ----------------- Chunks -----------------
 /// Type alias for a vector of [`Chunk`]
 pub type Chunks = Vec<Chunk>;
 // -----------------------------------------
+
+// ----------------- Document ---------------
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Eq)]
+/// # [`Document`]
+/// The text read by a loader along with metadata about where it came from, such
+/// as a file path, a URL or when it was last modified. Chunking a document with
+/// [`crate::chunkers::Chunker::chunk_document`] carries its metadata into every chunk.
+pub struct Document {
+    /// This is the text content
+    content: String,
+    /// Any metadata associated with the document such as its path, URL, etc.
+    metadata: serde_json::Value,
+}
+
+impl Document {
+    /// # [`Document::new`]
+    /// This is the constructor to use when we have some text with no metadata.
+    ///
+    /// # Arguments
+    /// * content: [`Into<String>`] - this is the text content of the document
+    ///
+    /// # Returns
+    /// * [`Document`] - a new Document with no metadata
+    pub fn new(content: impl Into<String>) -> Self {
+        Self {
+            content: content.into(),
+            metadata: serde_json::Value::Null,
+        }
+    }
+
+    /// # [`Document::new_with_metadata`]
+    ///
+    /// # Arguments
+    /// * content: [`Into<String>`] - this is the text content of the document
+    /// * metadata: [`serde_json::Value`] - metadata associated with the document
+    ///
+    /// # Returns
+    /// * [`Document`] - a new Document
+    pub fn new_with_metadata(content: impl Into<String>, metadata: serde_json::Value) -> Self {
+        Self {
+            content: content.into(),
+            metadata,
+        }
+    }
+
+    /// # [`Document::content`]
+    /// Getter for the text content.
+    ///
+    /// # Returns
+    /// * &[`str`] - reference to the text of the document
+    pub fn content(&self) -> &str {
+        &self.content
+    }
+
+    /// # [`Document::metadata`]
+    /// Getter for the metadata
+    ///
+    /// # Returns
+    /// * &[`serde_json::Value`] - reference to metadata associated with the document
+    pub fn metadata(&self) -> &serde_json::Value {
+        &self.metadata
+    }
+
+    /// # [`Document::into_chunk`]
+    ///
+    /// # Returns
+    /// * [`Chunk`] - the whole document as a single chunk carrying its metadata.
+    pub fn into_chunk(self) -> Chunk {
+        Chunk::new_with_metadata(self.content, self.metadata)
+    }
+}
+
+impl From<String> for Document {
+    fn from(content: String) -> Self {
+        Document::new(content)
+    }
+}
+// -----------------------------------------
+
+// ----------------- InvocationContext -----------------
+/// # [`InvocationContext`]
+/// Per invocation context which is threaded from the chains through to the
+/// clients and retrievers. Currently this carries a request id which is sent to
+/// providers as a correlation header and attached to queries, so a single chain
+/// invocation can be traced across all of the requests it made.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct InvocationContext {
+    /// The id used to correlate everything done for this invocation
+    request_id: Uuid,
+}
+
+impl InvocationContext {
+    /// # [`InvocationContext::new`]
+    /// Creates a new context with a randomly generated request id.
+    ///
+    /// # Returns
+    /// * [`InvocationContext`] - a new InvocationContext
+    pub fn new() -> Self {
+        Self {
+            request_id: Uuid::new_v4(),
+        }
+    }
+
+    /// # [`InvocationContext::with_request_id`]
+    /// Creates a new context using a request id you already have,
+    /// for example one taken from an incoming HTTP request.
+    ///
+    /// # Arguments
+    /// * request_id: [`Uuid`] - the id to correlate the invocation with
+    ///
+    /// # Returns
+    /// * [`InvocationContext`] - a new InvocationContext
+    pub fn with_request_id(request_id: Uuid) -> Self {
+        Self { request_id }
+    }
+
+    /// # [`InvocationContext::request_id`]
+    /// Getter for the request id
+    ///
+    /// # Returns
+    /// * [`Uuid`] - the request id
+    pub fn request_id(&self) -> Uuid {
+        self.request_id
+    }
+}
+
+impl Default for InvocationContext {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+// -----------------------------------------------------
+
+// ----------------- TokenUsage -----------------
+/// # [`TokenUsage`]
+/// The number of tokens a provider reported using for one or more requests,
+/// this is what the provider bills for.
+/// * `input_tokens` - the tokens in the prompt, or in the text embedded.
+/// * `output_tokens` - the tokens generated in the response, this is 0 for embeddings.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct TokenUsage {
+    pub input_tokens: usize,
+    pub output_tokens: usize,
+}
+
+impl TokenUsage {
+    /// # [`TokenUsage::new`]
+    ///
+    /// # Arguments
+    /// * input_tokens: [`usize`] - the tokens in the prompt
+    /// * output_tokens: [`usize`] - the tokens generated in the response
+    ///
+    /// # Returns
+    /// * [`TokenUsage`] - a new TokenUsage
+    pub fn new(input_tokens: usize, output_tokens: usize) -> Self {
+        Self {
+            input_tokens,
+            output_tokens,
+        }
+    }
+
+    /// # [`TokenUsage::total_tokens`]
+    ///
+    /// # Returns
+    /// * [`usize`] - the input and output tokens added together
+    pub fn total_tokens(&self) -> usize {
+        self.input_tokens + self.output_tokens
+    }
+}
+
+/// Adds the usage of two requests together, for example to total the batches of an embedding call
+impl Add for TokenUsage {
+    type Output = TokenUsage;
+
+    fn add(self, other: TokenUsage) -> TokenUsage {
+        TokenUsage {
+            input_tokens: self.input_tokens + other.input_tokens,
+            output_tokens: self.output_tokens + other.output_tokens,
+        }
+    }
+}
+
+impl AddAssign for TokenUsage {
+    fn add_assign(&mut self, other: TokenUsage) {
+        *self = *self + other;
+    }
+}
+// ----------------------------------------------
+
+// ----------------- ScoredChunk -----------------
+/// # [`ScoredChunk`]
+/// A chunk returned by a retriever along with how similar it is to the text searched for.
+/// A higher score is always more similar, how the score is calculated depends on the
+/// retriever, e.g. see [`crate::retrievers::DistanceFunction::to_score`].
+/// * `chunk` - the retrieved chunk.
+/// * `score` - the similarity of the chunk to the text searched for.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ScoredChunk {
+    pub chunk: Chunk,
+    pub score: f32,
+}
+
+impl ScoredChunk {
+    /// # [`ScoredChunk::new`]
+    ///
+    /// # Arguments
+    /// * chunk: [`Chunk`] - the retrieved chunk
+    /// * score: [`f32`] - the similarity of the chunk to the text searched for
+    ///
+    /// # Returns
+    /// * [`ScoredChunk`] - a new ScoredChunk
+    pub fn new(chunk: Chunk, score: f32) -> Self {
+        Self { chunk, score }
+    }
+}
+// -----------------------------------------------
+
+// ----------------- IdentifiedChunk -----------------
+/// # [`IdentifiedChunk`]
+/// A [`ScoredChunk`] along with the id of the row it was read from, so the same row can be
+/// referred to again, e.g. to fetch the chunks either side of it.
+/// * `id` - the id of the stored row.
+/// * `chunk` - the retrieved chunk.
+/// * `score` - the similarity of the chunk to the text searched for.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct IdentifiedChunk {
+    pub id: i64,
+    pub chunk: Chunk,
+    pub score: f32,
+}
+
+impl IdentifiedChunk {
+    /// # [`IdentifiedChunk::new`]
+    ///
+    /// # Arguments
+    /// * id: [`i64`] - the id of the stored row
+    /// * chunk: [`Chunk`] - the retrieved chunk
+    /// * score: [`f32`] - the similarity of the chunk to the text searched for
+    ///
+    /// # Returns
+    /// * [`IdentifiedChunk`] - a new IdentifiedChunk
+    pub fn new(id: i64, chunk: Chunk, score: f32) -> Self {
+        Self { id, chunk, score }
+    }
+}
+
+impl From<IdentifiedChunk> for ScoredChunk {
+    fn from(identified: IdentifiedChunk) -> Self {
+        ScoredChunk::new(identified.chunk, identified.score)
+    }
+}
+// ---------------------------------------------------
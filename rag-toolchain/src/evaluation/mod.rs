@@ -0,0 +1,10 @@
+/// # Evaluation
+/// This module contains tools for measuring the quality of retrieval over a labelled set of
+/// queries, so the effect of changing chunk sizes, distance functions or retrievers can be
+/// compared between runs.
+mod retrieval_evaluator;
+
+pub use retrieval_evaluator::{
+    CaseResult, EvalCase, EvalReport, EvaluationError, HitRate, RetrievalEvaluator,
+    DEFAULT_MAX_CONCURRENT_QUERIES,
+};
@@ -0,0 +1,499 @@
+use crate::common::{ScoredChunk, DOCUMENT_ID_KEY};
+use crate::retrievers::{AsyncRetriever, MetadataFilter};
+use futures::{stream, StreamExt};
+use serde_json::Value;
+use std::error::Error;
+use std::num::{NonZeroU32, NonZeroUsize};
+use thiserror::Error;
+
+/// The number of queries [`RetrievalEvaluator`] runs at once unless
+/// [`RetrievalEvaluator::with_max_concurrent_queries`] is called.
+pub const DEFAULT_MAX_CONCURRENT_QUERIES: NonZeroUsize = NonZeroUsize::new(4).unwrap();
+
+/// # [`EvalCase`]
+///
+/// A labelled query, the chunks whose metadata matches `relevant` are the ones the retriever
+/// should find for it.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct EvalCase {
+    pub query: String,
+    pub relevant: MetadataFilter,
+}
+
+impl EvalCase {
+    /// # [`EvalCase::new`]
+    ///
+    /// # Arguments
+    /// * `query`: impl [`Into<String>`] - The text to retrieve for.
+    /// * `relevant`: [`MetadataFilter`] - Matches the metadata of the relevant chunks.
+    ///
+    /// # Returns
+    /// * [`EvalCase`] - The labelled query.
+    pub fn new(query: impl Into<String>, relevant: MetadataFilter) -> Self {
+        EvalCase {
+            query: query.into(),
+            relevant,
+        }
+    }
+
+    /// # [`EvalCase::for_document`]
+    ///
+    /// A query whose relevant chunks are those from one document, going by the id under
+    /// [`DOCUMENT_ID_KEY`] in their metadata.
+    ///
+    /// # Arguments
+    /// * `query`: impl [`Into<String>`] - The text to retrieve for.
+    /// * `document_id`: impl [`Into<Value>`] - The id of the expected document.
+    ///
+    /// # Returns
+    /// * [`EvalCase`] - The labelled query.
+    pub fn for_document(query: impl Into<String>, document_id: impl Into<Value>) -> Self {
+        EvalCase::new(query, MetadataFilter::key(DOCUMENT_ID_KEY).eq(document_id))
+    }
+}
+
+/// # [`HitRate`]
+///
+/// The fraction of queries with a relevant chunk in their top `k` results.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct HitRate {
+    pub k: u32,
+    pub hit_rate: f32,
+}
+
+/// # [`CaseResult`]
+///
+/// How retrieval went for a single [`EvalCase`].
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CaseResult {
+    pub query: String,
+    /// The 1 based position of the first relevant chunk, [`None`] if none were retrieved
+    pub rank: Option<u32>,
+    /// The number of chunks retrieved
+    pub retrieved: usize,
+    /// The score of the most similar chunk, [`None`] if nothing was retrieved
+    pub top_score: Option<f32>,
+}
+
+/// # [`EvalReport`]
+///
+/// The metrics from [`RetrievalEvaluator::evaluate`]. With the `serde` feature the report
+/// serializes to JSON so runs can be saved and diffed.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct EvalReport {
+    /// The hit rate at each k, smallest k first
+    pub hit_rates: Vec<HitRate>,
+    /// The mean reciprocal rank of the first relevant chunk within the largest k, a query
+    /// without one counts as 0
+    pub mrr: f32,
+    /// The mean score of every retrieved chunk, [`None`] if nothing was retrieved
+    pub mean_score: Option<f32>,
+    /// The result of each case in the order they were given
+    pub cases: Vec<CaseResult>,
+}
+
+impl EvalReport {
+    /// # [`EvalReport::hit_rate`]
+    ///
+    /// # Arguments
+    /// * `k`: [`u32`] - One of the k values the evaluator was given.
+    ///
+    /// # Returns
+    /// * [`Option<f32>`] - The hit rate at k, [`None`] if k was not evaluated.
+    pub fn hit_rate(&self, k: u32) -> Option<f32> {
+        self.hit_rates
+            .iter()
+            .find(|hit_rate| hit_rate.k == k)
+            .map(|hit_rate| hit_rate.hit_rate)
+    }
+}
+
+/// # [`RetrievalEvaluator`]
+///
+/// Runs a labelled set of queries against a retriever and reports the hit rate at each k, the
+/// mean reciprocal rank and the mean score of the retrieved chunks. Each query is retrieved once
+/// with [`AsyncRetriever::retrieve_with_scores`] at the largest k, the smaller k values are read
+/// from the same results.
+///
+/// # Examples
+/// ```
+/// use rag_toolchain::evaluation::*;
+/// use rag_toolchain::retrievers::AsyncRetriever;
+/// use std::num::NonZeroU32;
+///
+/// async fn evaluate(retriever: impl AsyncRetriever) {
+///     let cases = vec![
+///         EvalCase::for_document("How do I reset my password?", "account.md"),
+///         EvalCase::for_document("What are the opening hours?", "contact.md"),
+///     ];
+///     let ks = [1, 5, 10].map(|k| NonZeroU32::new(k).unwrap());
+///     let report: EvalReport = RetrievalEvaluator::new(retriever, ks)
+///         .evaluate(&cases)
+///         .await
+///         .unwrap();
+///     println!("hit@5 {:?} mrr {}", report.hit_rate(5), report.mrr);
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct RetrievalEvaluator<T: AsyncRetriever> {
+    retriever: T,
+    ks: Vec<NonZeroU32>,
+    max_concurrent_queries: NonZeroUsize,
+}
+
+impl<T: AsyncRetriever> RetrievalEvaluator<T> {
+    /// # [`RetrievalEvaluator::new`]
+    ///
+    /// # Arguments
+    /// * `retriever`: T - The retriever to evaluate.
+    /// * `ks`: impl [`IntoIterator<Item = NonZeroU32>`] - The cut offs to report the hit rate at.
+    ///
+    /// # Returns
+    /// * [`RetrievalEvaluator`] - Running [`DEFAULT_MAX_CONCURRENT_QUERIES`] queries at once.
+    pub fn new(retriever: T, ks: impl IntoIterator<Item = NonZeroU32>) -> Self {
+        let mut ks: Vec<NonZeroU32> = ks.into_iter().collect();
+        ks.sort();
+        ks.dedup();
+        RetrievalEvaluator {
+            retriever,
+            ks,
+            max_concurrent_queries: DEFAULT_MAX_CONCURRENT_QUERIES,
+        }
+    }
+
+    /// # [`RetrievalEvaluator::with_max_concurrent_queries`]
+    ///
+    /// # Arguments
+    /// * `max_concurrent_queries`: [`NonZeroUsize`] - The most queries in flight at once.
+    pub fn with_max_concurrent_queries(mut self, max_concurrent_queries: NonZeroUsize) -> Self {
+        self.max_concurrent_queries = max_concurrent_queries;
+        self
+    }
+
+    /// # [`RetrievalEvaluator::evaluate`]
+    ///
+    /// # Arguments
+    /// * `cases`: &[`[EvalCase]`] - The labelled queries.
+    ///
+    /// # Errors
+    /// * [`EvaluationError::EmptyDataset`] - If there are no cases.
+    /// * [`EvaluationError::NoKValues`] - If the evaluator was given no k values.
+    /// * [`EvaluationError::RetrieverError`] - If a query failed, with its index in `cases`.
+    ///
+    /// # Returns
+    /// * [`EvalReport`] - The metrics over all the cases.
+    pub async fn evaluate(
+        &self,
+        cases: &[EvalCase],
+    ) -> Result<EvalReport, EvaluationError<T::ErrorType>> {
+        if cases.is_empty() {
+            return Err(EvaluationError::EmptyDataset);
+        }
+        let max_k: NonZeroU32 = *self.ks.last().ok_or(EvaluationError::NoKValues)?;
+
+        // Results come back in the order of the cases however many are in flight
+        let mut retrieved = stream::iter(cases.iter().enumerate())
+            .map(|(index, case)| async move {
+                let scored: Vec<ScoredChunk> = self
+                    .retriever
+                    .retrieve_with_scores(&case.query, max_k)
+                    .await
+                    .map_err(|error| EvaluationError::RetrieverError { index, error })?;
+                Ok::<_, EvaluationError<T::ErrorType>>((case, scored))
+            })
+            .buffered(self.max_concurrent_queries.get());
+
+        let mut results: Vec<CaseResult> = Vec::with_capacity(cases.len());
+        let mut scores: Vec<f32> = Vec::new();
+        while let Some(result) = retrieved.next().await {
+            let (case, scored) = result?;
+            let scored: &[ScoredChunk] = &scored[..scored.len().min(max_k.get() as usize)];
+            let rank: Option<u32> = scored
+                .iter()
+                .position(|hit| case.relevant.matches(hit.chunk.metadata()))
+                .map(|position| position as u32 + 1);
+            scores.extend(scored.iter().map(|hit| hit.score));
+            results.push(CaseResult {
+                query: case.query.clone(),
+                rank,
+                retrieved: scored.len(),
+                top_score: scored.first().map(|hit| hit.score),
+            });
+        }
+        Ok(self.report(results, scores))
+    }
+
+    fn report(&self, cases: Vec<CaseResult>, scores: Vec<f32>) -> EvalReport {
+        let total: f32 = cases.len() as f32;
+        let hit_rates: Vec<HitRate> = self
+            .ks
+            .iter()
+            .map(|k| {
+                let hits: usize = cases
+                    .iter()
+                    .filter(|case| case.rank.is_some_and(|rank| rank <= k.get()))
+                    .count();
+                HitRate {
+                    k: k.get(),
+                    hit_rate: hits as f32 / total,
+                }
+            })
+            .collect();
+        let mrr: f32 = cases
+            .iter()
+            .filter_map(|case| case.rank)
+            .map(|rank| 1.0 / rank as f32)
+            .sum::<f32>()
+            / total;
+        let mean_score: Option<f32> = match scores.is_empty() {
+            true => None,
+            false => Some(scores.iter().sum::<f32>() / scores.len() as f32),
+        };
+        EvalReport {
+            hit_rates,
+            mrr,
+            mean_score,
+            cases,
+        }
+    }
+}
+
+/// # [`EvaluationError`]
+///
+/// The error returned by [`RetrievalEvaluator::evaluate`].
+#[derive(Error, Debug, PartialEq)]
+pub enum EvaluationError<E: Error> {
+    #[error("There are no cases to evaluate")]
+    EmptyDataset,
+    #[error("No k values were given to evaluate at")]
+    NoKValues,
+    /// The query of the case at `index` failed
+    #[error("Retriever Error for case {index}: {error}")]
+    RetrieverError { index: usize, error: E },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::{Chunk, Chunks};
+    use crate::retrievers::MockAsyncRetriever;
+    use serde_json::json;
+    use std::io;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    fn hit(document: &str, score: f32) -> ScoredChunk {
+        ScoredChunk::new(
+            Chunk::new_with_metadata(document, json!({ "document_id": document })),
+            score,
+        )
+    }
+
+    fn ks(values: &[u32]) -> Vec<NonZeroU32> {
+        values
+            .iter()
+            .map(|value| NonZeroU32::new(*value).unwrap())
+            .collect()
+    }
+
+    // Each query returns three hits with the relevant document at a known rank
+    fn retriever() -> MockAsyncRetriever {
+        let mut retriever = MockAsyncRetriever::new();
+        retriever
+            .expect_retrieve_with_scores()
+            .returning(|query, _| {
+                let documents: [&str; 3] = match query {
+                    "first" => ["a", "x", "y"],
+                    "third" => ["x", "y", "b"],
+                    "missing" => ["x", "y", "z"],
+                    _ => ["x", "d", "y"],
+                };
+                Ok(vec![
+                    hit(documents[0], 0.9),
+                    hit(documents[1], 0.6),
+                    hit(documents[2], 0.3),
+                ])
+            });
+        retriever
+    }
+
+    #[tokio::test]
+    async fn evaluate_computes_hit_rates_mrr_and_mean_score() {
+        let cases = vec![
+            EvalCase::for_document("first", "a"),
+            EvalCase::for_document("third", "b"),
+            EvalCase::for_document("missing", "c"),
+            EvalCase::for_document("second", "d"),
+        ];
+        let report = RetrievalEvaluator::new(retriever(), ks(&[3, 1, 3]))
+            .evaluate(&cases)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            report.hit_rates,
+            vec![
+                HitRate {
+                    k: 1,
+                    hit_rate: 0.25
+                },
+                HitRate {
+                    k: 3,
+                    hit_rate: 0.75
+                }
+            ]
+        );
+        assert_eq!(report.hit_rate(3), Some(0.75));
+        assert_eq!(report.hit_rate(2), None);
+        // (1 + 1/3 + 0 + 1/2) / 4
+        assert!((report.mrr - 11.0 / 24.0).abs() < 1e-6);
+        assert!((report.mean_score.unwrap() - 0.6).abs() < 1e-6);
+        let ranks: Vec<Option<u32>> = report.cases.iter().map(|case| case.rank).collect();
+        assert_eq!(ranks, vec![Some(1), Some(3), None, Some(2)]);
+        assert_eq!(report.cases[0].top_score, Some(0.9));
+    }
+
+    #[tokio::test]
+    async fn evaluate_only_counts_hits_within_the_largest_k() {
+        let cases = vec![EvalCase::new(
+            "third",
+            MetadataFilter::key("document_id").eq("b"),
+        )];
+        let report = RetrievalEvaluator::new(retriever(), ks(&[2]))
+            .evaluate(&cases)
+            .await
+            .unwrap();
+        assert_eq!(report.hit_rate(2), Some(0.0));
+        assert_eq!(report.mrr, 0.0);
+        assert_eq!(report.cases[0].retrieved, 2);
+    }
+
+    #[tokio::test]
+    async fn evaluate_reports_no_mean_score_when_nothing_is_retrieved() {
+        let mut retriever = MockAsyncRetriever::new();
+        retriever
+            .expect_retrieve_with_scores()
+            .returning(|_, _| Ok(Vec::new()));
+        let report = RetrievalEvaluator::new(retriever, ks(&[1]))
+            .evaluate(&[EvalCase::for_document("query", "a")])
+            .await
+            .unwrap();
+        assert_eq!(report.mean_score, None);
+        assert_eq!(report.cases[0].top_score, None);
+    }
+
+    #[tokio::test]
+    async fn evaluate_rejects_an_empty_dataset_or_no_k_values() {
+        let evaluator = RetrievalEvaluator::new(MockAsyncRetriever::new(), ks(&[1]));
+        assert!(matches!(
+            evaluator.evaluate(&[]).await.unwrap_err(),
+            EvaluationError::EmptyDataset
+        ));
+        let evaluator = RetrievalEvaluator::new(MockAsyncRetriever::new(), ks(&[]));
+        assert!(matches!(
+            evaluator
+                .evaluate(&[EvalCase::for_document("query", "a")])
+                .await
+                .unwrap_err(),
+            EvaluationError::NoKValues
+        ));
+    }
+
+    #[tokio::test]
+    async fn evaluate_returns_the_index_of_a_failed_query() {
+        let mut retriever = MockAsyncRetriever::new();
+        retriever
+            .expect_retrieve_with_scores()
+            .returning(|query, _| match query {
+                "bad" => Err(io::Error::other("failed")),
+                _ => Ok(Vec::new()),
+            });
+        let cases = vec![
+            EvalCase::for_document("good", "a"),
+            EvalCase::for_document("bad", "b"),
+        ];
+        let error = RetrievalEvaluator::new(retriever, ks(&[1]))
+            .evaluate(&cases)
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            error,
+            EvaluationError::RetrieverError { index: 1, .. }
+        ));
+    }
+
+    // Records the most queries it has had in flight at once
+    struct CountingRetriever {
+        in_flight: Arc<AtomicUsize>,
+        max_in_flight: Arc<AtomicUsize>,
+    }
+
+    impl AsyncRetriever for CountingRetriever {
+        type ErrorType = io::Error;
+
+        async fn retrieve(&self, _text: &str, _top_k: NonZeroU32) -> Result<Chunks, io::Error> {
+            Ok(Vec::new())
+        }
+
+        async fn retrieve_with_scores(
+            &self,
+            _text: &str,
+            _top_k: NonZeroU32,
+        ) -> Result<Vec<ScoredChunk>, io::Error> {
+            let current: usize = self.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+            self.max_in_flight.fetch_max(current, Ordering::SeqCst);
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            self.in_flight.fetch_sub(1, Ordering::SeqCst);
+            Ok(Vec::new())
+        }
+
+        async fn fetch_by_metadata(
+            &self,
+            _filter: &MetadataFilter,
+        ) -> Result<Chunks, Self::ErrorType> {
+            Ok(Vec::new())
+        }
+    }
+
+    #[tokio::test]
+    async fn evaluate_bounds_the_queries_in_flight() {
+        let max_in_flight = Arc::new(AtomicUsize::new(0));
+        let retriever = CountingRetriever {
+            in_flight: Arc::new(AtomicUsize::new(0)),
+            max_in_flight: max_in_flight.clone(),
+        };
+        let cases: Vec<EvalCase> = (0..6)
+            .map(|index| EvalCase::for_document(format!("query {}", index), index))
+            .collect();
+        let report = RetrievalEvaluator::new(retriever, ks(&[1]))
+            .with_max_concurrent_queries(NonZeroUsize::new(2).unwrap())
+            .evaluate(&cases)
+            .await
+            .unwrap();
+        assert_eq!(report.cases.len(), 6);
+        assert_eq!(max_in_flight.load(Ordering::SeqCst), 2);
+    }
+
+    #[cfg(feature = "serde")]
+    #[tokio::test]
+    async fn report_round_trips_through_json() {
+        let report = RetrievalEvaluator::new(retriever(), ks(&[1, 3]))
+            .evaluate(&[EvalCase::for_document("first", "a")])
+            .await
+            .unwrap();
+        let json: String = serde_json::to_string(&report).unwrap();
+        assert_eq!(serde_json::from_str::<EvalReport>(&json).unwrap(), report);
+
+        let case: EvalCase = serde_json::from_value(json!({
+            "query": "first",
+            "relevant": {"eq": ["document_id", "a"]}
+        }))
+        .unwrap();
+        assert_eq!(case, EvalCase::for_document("first", "a"));
+    }
+}
@@ -0,0 +1,34 @@
+use thiserror::Error;
+
+/// # [`FormatError`]
+///
+/// The errors that can occur when converting conversations to or from a dataset format.
+/// Errors are reported per conversation, `index` is the position of the conversation
+/// in the input (the line number starting at 0 for JSONL) and `position` is the
+/// position of the message within that conversation.
+#[derive(Error, Debug, PartialEq, Eq, Clone)]
+pub enum FormatError {
+    /// The document as a whole could not be parsed
+    #[error("Invalid Document: {0}")]
+    InvalidDocument(String),
+    /// A single conversation could not be parsed
+    #[error("Conversation {index}: failed to parse: {message}")]
+    InvalidConversation { index: usize, message: String },
+    /// A conversation contained no messages
+    #[error("Conversation {index}: contains no messages")]
+    EmptyConversation { index: usize },
+    /// A message had a role which cannot be mapped to a [`crate::clients::PromptMessage`]
+    #[error("Conversation {index}: message {position} has unknown role {role}")]
+    UnknownRole {
+        index: usize,
+        position: usize,
+        role: String,
+    },
+    /// A message broke the ordering rules of the format
+    #[error("Conversation {index}: message {position} is out of order, {reason}")]
+    InvalidOrdering {
+        index: usize,
+        position: usize,
+        reason: String,
+    },
+}
@@ -0,0 +1,14 @@
+/// # Formats
+/// This module contains conversions between conversations (a [`Vec`] of
+/// [`crate::clients::PromptMessage`]) and common dataset formats. This allows
+/// curated chat history to be exported as fine-tuning data and existing datasets
+/// to be imported for evaluation replays.
+mod errors;
+mod openai_finetune;
+mod share_gpt;
+
+pub use errors::FormatError;
+pub use openai_finetune::{
+    from_openai_finetune_jsonl, to_openai_finetune_jsonl, validate_openai_finetune,
+};
+pub use share_gpt::{from_sharegpt_json, to_sharegpt_json};
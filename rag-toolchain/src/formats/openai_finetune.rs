@@ -0,0 +1,310 @@
+use crate::clients::PromptMessage;
+use crate::formats::FormatError;
+use serde::{Deserialize, Serialize};
+
+/// # [`FineTuneConversation`]
+///
+/// A single line of an OpenAI chat fine-tuning JSONL file.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct FineTuneConversation {
+    messages: Vec<FineTuneMessage>,
+}
+
+/// # [`FineTuneMessage`]
+///
+/// Any other fields on the message (e.g. `name` or `weight`) are ignored on import.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct FineTuneMessage {
+    role: String,
+    content: String,
+}
+
+impl From<&PromptMessage> for FineTuneMessage {
+    fn from(message: &PromptMessage) -> Self {
+        FineTuneMessage {
+            role: message.role().into(),
+            content: message.content().into(),
+        }
+    }
+}
+
+/// # [`to_openai_finetune_jsonl`]
+///
+/// Converts conversations into the OpenAI chat fine-tuning format, one conversation per line.
+/// The role mapping is:
+/// * [`PromptMessage::SystemMessage`] - `system`
+/// * [`PromptMessage::HumanMessage`] - `user`
+/// * [`PromptMessage::MultiModalHumanMessage`] - `user`, only the first text part is exported
+/// * [`PromptMessage::AIMessage`] - `assistant`
+///
+/// No validation is done here so any conversation can be exported,
+/// use [`validate_openai_finetune`] first to check the file will be accepted.
+///
+/// # Arguments
+/// * `conversations`: &[`[Vec<PromptMessage>]`] - the conversations to export.
+///
+/// # Returns
+/// * [`String`] - the JSONL document.
+pub fn to_openai_finetune_jsonl(conversations: &[Vec<PromptMessage>]) -> String {
+    conversations
+        .iter()
+        .map(|conversation| {
+            let line = FineTuneConversation {
+                messages: conversation.iter().map(FineTuneMessage::from).collect(),
+            };
+            // Serializing a struct of strings cannot fail
+            serde_json::to_string(&line).unwrap()
+        })
+        .map(|line| line + "\n")
+        .collect()
+}
+
+/// # [`from_openai_finetune_jsonl`]
+///
+/// Parses an OpenAI chat fine-tuning JSONL document back into conversations using the
+/// same role mapping as [`to_openai_finetune_jsonl`]. Blank lines are skipped and each
+/// conversation is validated with the rules described in [`validate_openai_finetune`].
+///
+/// # Arguments
+/// * `jsonl`: &[`str`] - the JSONL document.
+///
+/// # Errors
+/// * [`Vec<FormatError>`] - an error for every conversation which could not be imported.
+///
+/// # Returns
+/// * [`Vec<Vec<PromptMessage>>`] - the conversations in the order they appear in the document.
+pub fn from_openai_finetune_jsonl(
+    jsonl: &str,
+) -> Result<Vec<Vec<PromptMessage>>, Vec<FormatError>> {
+    let mut conversations: Vec<Vec<PromptMessage>> = Vec::new();
+    let mut errors: Vec<FormatError> = Vec::new();
+    for (index, line) in jsonl.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        match parse_line(index, line) {
+            Ok(conversation) => match validate_conversation(index, &conversation) {
+                Ok(()) => conversations.push(conversation),
+                Err(error) => errors.push(error),
+            },
+            Err(error) => errors.push(error),
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(conversations)
+    } else {
+        Err(errors)
+    }
+}
+
+/// # [`validate_openai_finetune`]
+///
+/// Checks conversations against the rules of the fine-tuning format:
+/// * a conversation must not be empty.
+/// * system messages may only appear at the start of the conversation.
+/// * after the system messages the roles must alternate starting with a user message.
+/// * there must be at least one assistant message to train on.
+///
+/// # Arguments
+/// * `conversations`: &[`[Vec<PromptMessage>]`] - the conversations to validate.
+///
+/// # Errors
+/// * [`Vec<FormatError>`] - an error for every conversation which breaks the rules.
+pub fn validate_openai_finetune(
+    conversations: &[Vec<PromptMessage>],
+) -> Result<(), Vec<FormatError>> {
+    let errors: Vec<FormatError> = conversations
+        .iter()
+        .enumerate()
+        .filter_map(|(index, conversation)| validate_conversation(index, conversation).err())
+        .collect();
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+fn parse_line(index: usize, line: &str) -> Result<Vec<PromptMessage>, FormatError> {
+    let parsed: FineTuneConversation =
+        serde_json::from_str(line).map_err(|error| FormatError::InvalidConversation {
+            index,
+            message: error.to_string(),
+        })?;
+    parsed
+        .messages
+        .into_iter()
+        .enumerate()
+        .map(|(position, message)| match message.role.as_str() {
+            "system" => Ok(PromptMessage::SystemMessage(message.content.into())),
+            "user" => Ok(PromptMessage::HumanMessage(message.content.into())),
+            "assistant" => Ok(PromptMessage::AIMessage(message.content.into())),
+            _ => Err(FormatError::UnknownRole {
+                index,
+                position,
+                role: message.role,
+            }),
+        })
+        .collect()
+}
+
+fn validate_conversation(index: usize, conversation: &[PromptMessage]) -> Result<(), FormatError> {
+    if conversation.is_empty() {
+        return Err(FormatError::EmptyConversation { index });
+    }
+    let out_of_order = |position: usize, reason: &str| FormatError::InvalidOrdering {
+        index,
+        position,
+        reason: reason.into(),
+    };
+
+    let first_turn: usize = conversation
+        .iter()
+        .position(|message| !matches!(message, PromptMessage::SystemMessage(_)))
+        .ok_or_else(|| out_of_order(conversation.len(), "expected a user message"))?;
+
+    for (offset, message) in conversation[first_turn..].iter().enumerate() {
+        let position = first_turn + offset;
+        match (message, offset % 2) {
+            (PromptMessage::SystemMessage(_), _) => {
+                return Err(out_of_order(position, "system messages must come first"))
+            }
+            (PromptMessage::HumanMessage(_) | PromptMessage::MultiModalHumanMessage(_), 0)
+            | (PromptMessage::AIMessage(_), 1) => {}
+            (PromptMessage::HumanMessage(_) | PromptMessage::MultiModalHumanMessage(_), _) => {
+                return Err(out_of_order(position, "expected an assistant message"))
+            }
+            (PromptMessage::AIMessage(_), _) => {
+                return Err(out_of_order(position, "expected a user message"))
+            }
+        }
+    }
+
+    if conversation.len() - first_turn < 2 {
+        return Err(out_of_order(
+            conversation.len(),
+            "expected an assistant message",
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FIXTURE: &str = include_str!("../../tests/formats/openai_finetune.jsonl");
+
+    fn conversation() -> Vec<PromptMessage> {
+        vec![
+            PromptMessage::SystemMessage("You are a helpful assistant".into()),
+            PromptMessage::HumanMessage("What is the capital of France?".into()),
+            PromptMessage::AIMessage("Paris".into()),
+            PromptMessage::HumanMessage("And Germany?".into()),
+            PromptMessage::AIMessage("Berlin".into()),
+        ]
+    }
+
+    #[test]
+    fn export_maps_roles() {
+        let jsonl = to_openai_finetune_jsonl(&[conversation()]);
+        let expected = concat!(
+            r#"{"messages":[{"role":"system","content":"You are a helpful assistant"},"#,
+            r#"{"role":"user","content":"What is the capital of France?"},"#,
+            r#"{"role":"assistant","content":"Paris"},"#,
+            r#"{"role":"user","content":"And Germany?"},"#,
+            r#"{"role":"assistant","content":"Berlin"}]}"#,
+            "\n"
+        );
+        assert_eq!(jsonl, expected);
+    }
+
+    #[test]
+    fn round_trip_is_lossless() {
+        let conversations = vec![
+            conversation(),
+            vec![
+                PromptMessage::HumanMessage("hi".into()),
+                PromptMessage::AIMessage("hello".into()),
+            ],
+        ];
+        let jsonl = to_openai_finetune_jsonl(&conversations);
+        assert_eq!(from_openai_finetune_jsonl(&jsonl).unwrap(), conversations);
+    }
+
+    #[test]
+    fn import_fixture() {
+        let conversations = from_openai_finetune_jsonl(FIXTURE).unwrap();
+        assert_eq!(conversations.len(), 2);
+        assert_eq!(conversations[0], conversation());
+        // Extra fields such as weight are ignored
+        assert_eq!(
+            conversations[1],
+            vec![
+                PromptMessage::HumanMessage("Tell me a joke".into()),
+                PromptMessage::AIMessage("Why did the chicken cross the road?".into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn import_reports_errors_per_conversation() {
+        let jsonl = concat!(
+            r#"{"messages":[{"role":"user","content":"a"},{"role":"assistant","content":"b"}]}"#,
+            "\n",
+            r#"{"messages":[{"role":"tool","content":"a"}]}"#,
+            "\n",
+            "not json\n",
+            r#"{"messages":[]}"#,
+            "\n"
+        );
+        let errors = from_openai_finetune_jsonl(jsonl).unwrap_err();
+        assert_eq!(errors.len(), 3);
+        assert_eq!(
+            errors[0],
+            FormatError::UnknownRole {
+                index: 1,
+                position: 0,
+                role: "tool".into()
+            }
+        );
+        assert!(matches!(
+            errors[1],
+            FormatError::InvalidConversation { index: 2, .. }
+        ));
+        assert_eq!(errors[2], FormatError::EmptyConversation { index: 3 });
+    }
+
+    #[test]
+    fn validate_requires_alternating_roles() {
+        let conversations = vec![
+            conversation(),
+            vec![
+                PromptMessage::HumanMessage("a".into()),
+                PromptMessage::HumanMessage("b".into()),
+            ],
+            vec![
+                PromptMessage::HumanMessage("a".into()),
+                PromptMessage::AIMessage("b".into()),
+                PromptMessage::SystemMessage("c".into()),
+            ],
+            vec![PromptMessage::AIMessage("a".into())],
+            vec![
+                PromptMessage::SystemMessage("a".into()),
+                PromptMessage::HumanMessage("b".into()),
+            ],
+        ];
+        let errors = validate_openai_finetune(&conversations).unwrap_err();
+        let positions: Vec<(usize, usize)> = errors
+            .iter()
+            .map(|error| match error {
+                FormatError::InvalidOrdering {
+                    index, position, ..
+                } => (*index, *position),
+                other => panic!("unexpected error {:?}", other),
+            })
+            .collect();
+        assert_eq!(positions, vec![(1, 1), (2, 2), (3, 0), (4, 2)]);
+    }
+}
@@ -0,0 +1,198 @@
+use crate::clients::PromptMessage;
+use crate::formats::FormatError;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// # [`ShareGptConversation`]
+///
+/// A single entry in a ShareGPT JSON document. Other fields on the entry (e.g. `id`) are ignored on import.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct ShareGptConversation {
+    conversations: Vec<ShareGptMessage>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct ShareGptMessage {
+    from: String,
+    value: String,
+}
+
+impl From<&PromptMessage> for ShareGptMessage {
+    fn from(message: &PromptMessage) -> Self {
+        let from = match message {
+            PromptMessage::SystemMessage(_) => "system",
+            PromptMessage::HumanMessage(_) | PromptMessage::MultiModalHumanMessage(_) => "human",
+            PromptMessage::AIMessage(_) => "gpt",
+        };
+        ShareGptMessage {
+            from: from.into(),
+            value: message.content().into(),
+        }
+    }
+}
+
+/// # [`to_sharegpt_json`]
+///
+/// Converts conversations into a ShareGPT JSON document. The role mapping is:
+/// * [`PromptMessage::SystemMessage`] - `system`
+/// * [`PromptMessage::HumanMessage`] - `human`
+/// * [`PromptMessage::MultiModalHumanMessage`] - `human`, only the first text part is exported
+/// * [`PromptMessage::AIMessage`] - `gpt`
+///
+/// # Arguments
+/// * `conversations`: &[`[Vec<PromptMessage>]`] - the conversations to export.
+///
+/// # Returns
+/// * [`String`] - the JSON document.
+pub fn to_sharegpt_json(conversations: &[Vec<PromptMessage>]) -> String {
+    let document: Vec<ShareGptConversation> = conversations
+        .iter()
+        .map(|conversation| ShareGptConversation {
+            conversations: conversation.iter().map(ShareGptMessage::from).collect(),
+        })
+        .collect();
+    // Serializing a list of structs of strings cannot fail
+    serde_json::to_string_pretty(&document).unwrap()
+}
+
+/// # [`from_sharegpt_json`]
+///
+/// Parses a ShareGPT JSON document into conversations. As datasets in this format
+/// come from a range of sources the role mapping is more lenient than on export:
+/// * `system` - [`PromptMessage::SystemMessage`]
+/// * `human` or `user` - [`PromptMessage::HumanMessage`]
+/// * `gpt`, `chatgpt`, `assistant`, `bing` or `bard` - [`PromptMessage::AIMessage`]
+///
+/// The format has no ordering rules so messages are kept in the order they appear,
+/// this means exporting and importing a conversation is always lossless.
+///
+/// # Arguments
+/// * `json`: &[`str`] - the JSON document.
+///
+/// # Errors
+/// * [`Vec<FormatError>`] - [`FormatError::InvalidDocument`] if the document is not a JSON
+///   array, otherwise an error for every conversation which could not be imported.
+///
+/// # Returns
+/// * [`Vec<Vec<PromptMessage>>`] - the conversations in the order they appear in the document.
+pub fn from_sharegpt_json(json: &str) -> Result<Vec<Vec<PromptMessage>>, Vec<FormatError>> {
+    let entries: Vec<Value> = serde_json::from_str(json)
+        .map_err(|error| vec![FormatError::InvalidDocument(error.to_string())])?;
+
+    let mut conversations: Vec<Vec<PromptMessage>> = Vec::new();
+    let mut errors: Vec<FormatError> = Vec::new();
+    for (index, entry) in entries.into_iter().enumerate() {
+        match parse_entry(index, entry) {
+            Ok(conversation) => conversations.push(conversation),
+            Err(error) => errors.push(error),
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(conversations)
+    } else {
+        Err(errors)
+    }
+}
+
+fn parse_entry(index: usize, entry: Value) -> Result<Vec<PromptMessage>, FormatError> {
+    let parsed: ShareGptConversation =
+        serde_json::from_value(entry).map_err(|error| FormatError::InvalidConversation {
+            index,
+            message: error.to_string(),
+        })?;
+    if parsed.conversations.is_empty() {
+        return Err(FormatError::EmptyConversation { index });
+    }
+    parsed
+        .conversations
+        .into_iter()
+        .enumerate()
+        .map(|(position, message)| match message.from.as_str() {
+            "system" => Ok(PromptMessage::SystemMessage(message.value.into())),
+            "human" | "user" => Ok(PromptMessage::HumanMessage(message.value.into())),
+            "gpt" | "chatgpt" | "assistant" | "bing" | "bard" => {
+                Ok(PromptMessage::AIMessage(message.value.into()))
+            }
+            _ => Err(FormatError::UnknownRole {
+                index,
+                position,
+                role: message.from,
+            }),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FIXTURE: &str = include_str!("../../tests/formats/share_gpt.json");
+
+    #[test]
+    fn round_trip_is_lossless() {
+        let conversations = vec![
+            vec![
+                PromptMessage::SystemMessage("be brief".into()),
+                PromptMessage::HumanMessage("hi".into()),
+                PromptMessage::AIMessage("hello".into()),
+                PromptMessage::SystemMessage("be briefer".into()),
+                PromptMessage::AIMessage("hey".into()),
+            ],
+            vec![PromptMessage::HumanMessage("unanswered".into())],
+        ];
+        let json = to_sharegpt_json(&conversations);
+        assert!(json.contains(r#""from": "gpt""#));
+        assert_eq!(from_sharegpt_json(&json).unwrap(), conversations);
+    }
+
+    #[test]
+    fn import_fixture() {
+        let conversations = from_sharegpt_json(FIXTURE).unwrap();
+        assert_eq!(
+            conversations,
+            vec![
+                vec![
+                    PromptMessage::SystemMessage("You are a helpful assistant".into()),
+                    PromptMessage::HumanMessage("What is the capital of France?".into()),
+                    PromptMessage::AIMessage("Paris".into()),
+                ],
+                vec![
+                    PromptMessage::HumanMessage("Tell me a joke".into()),
+                    PromptMessage::AIMessage("Why did the chicken cross the road?".into()),
+                ],
+            ]
+        );
+    }
+
+    #[test]
+    fn import_reports_errors_per_conversation() {
+        let json = r#"[
+            {"conversations": [{"from": "human", "value": "a"}]},
+            {"conversations": [{"from": "human", "value": "a"}, {"from": "tool", "value": "b"}]},
+            {"conversations": []},
+            {"messages": []}
+        ]"#;
+        let errors = from_sharegpt_json(json).unwrap_err();
+        assert_eq!(errors.len(), 3);
+        assert_eq!(
+            errors[0],
+            FormatError::UnknownRole {
+                index: 1,
+                position: 1,
+                role: "tool".into()
+            }
+        );
+        assert_eq!(errors[1], FormatError::EmptyConversation { index: 2 });
+        assert!(matches!(
+            errors[2],
+            FormatError::InvalidConversation { index: 3, .. }
+        ));
+    }
+
+    #[test]
+    fn import_invalid_document() {
+        let errors = from_sharegpt_json(r#"{"conversations": []}"#).unwrap_err();
+        assert!(matches!(errors[..], [FormatError::InvalidDocument(_)]));
+    }
+}
@@ -13,6 +13,34 @@
 //! and have a play around. However, once you have landed on the features you which to be using
 //! make sure to disable "default-features" in you Cargo.toml and select only the ones you are
 //! using in order to keep binary size down and reduce compilation times.
+//!
+//! * `pg_vector` - the postgres vector store and retriever.
+//! * `openai` - enables all of the OpenAI features below.
+//! * `openai-embeddings` - the OpenAI embedding client.
+//! * `openai-chat` - the OpenAI chat completion client.
+//! * `openai-stream` - streamed OpenAI chat completions, this pulls in the SSE dependencies.
+//! * `anthropic` - the Anthropic chat completion client.
+//! * `anthropic-stream` - streamed Anthropic chat completions, this pulls in the SSE dependencies.
+//! * `ollama` - chat completion and embedding clients for models served locally by Ollama.
+//! * `cohere` - the Cohere embedding client and reranker.
+//! * `bedrock` - the AWS Bedrock chat completion client for Anthropic models, requests are signed
+//!   with SigV4 using the standard AWS credentials chain.
+//! * `embedding-cache` - a wrapper for any embedding client which caches vectors by content hash.
+//! * `html` - a loader which fetches web pages and strips them down to their readable text.
+//! * `sqlite_vec` - an embedded vector store backed by SQLite with the sqlite-vec extension compiled in.
+//! * `analysis` - offline tools for exploring embeddings such as k-means clustering.
+//! * `serde` - `Serialize` and `Deserialize` on the public config and report types such as
+//!   [`chains::Timings`] and [`retrievers::RetrieveExplanation`]. This is off by default.
+//! * `tracing` - spans from the `tracing` crate around requests, store writes, retrievals and
+//!   chain invocations. Prompt text is only ever recorded at trace level. This is off by default.
+
+/// # Analysis
+///
+/// Tools for getting a bird's-eye view of what has been embedded, for example clustering a vector
+/// table and looking at the chunks closest to each cluster's centre. These run offline and need
+/// no extra dependencies.
+#[cfg(feature = "analysis")]
+pub mod analysis;
 
 /// # Chains
 ///
@@ -45,6 +73,20 @@ pub mod clients;
 /// of this would be any domain specific types that can appear across the library such as the [`common::Chunk`] type.
 pub mod common;
 
+/// # Evaluation
+///
+/// Retrieval is only as good as the chunks it finds, this module measures that over a labelled set
+/// of queries with metrics such as hit rate and mean reciprocal rank. Run it before and after
+/// changing chunk sizes or distance functions to see whether the change helped.
+pub mod evaluation;
+
+/// # Formats
+///
+/// Conversations are just a list of prompt messages, this module converts them to and from
+/// common dataset formats such as the OpenAI fine-tuning JSONL format and ShareGPT. Useful for
+/// turning good chat history into training data or replaying existing datasets.
+pub mod formats;
+
 /// # Loaders
 ///
 /// The aim of this module is to provide some easy data integrations for you AI workflows. This could be as simple as
@@ -0,0 +1,486 @@
+use crate::common::{Chunk, Document};
+use crate::loaders::traits::AsyncLoadSource;
+use futures::{stream, Stream, StreamExt};
+use serde_json::json;
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+/// # [`DirectorySource`]
+/// Walks a directory and loads every file whose path, relative to the root, matches a glob.
+/// Files are read one at a time through [`DirectorySource::stream`] so a large corpus is
+/// never held in memory at once.
+///
+/// The glob is matched against the whole relative path using `/` as the separator:
+/// * `*` matches any characters within a single path segment
+/// * `?` matches a single character within a path segment
+/// * `**` as a whole segment matches any number of directories, including none
+///
+/// So `"*.md"` only matches files in the root while `"**/*.md"` matches them at any depth.
+///
+/// # Examples
+/// ```
+/// use rag_toolchain::loaders::*;
+/// use futures::StreamExt;
+///
+/// async fn load_docs() {
+///     let source = DirectorySource::new("./docs", "**/*.md");
+///     let mut files = source.stream();
+///     while let Some(file) = files.next().await {
+///         match file {
+///             Ok(file) => println!("{} is {} bytes", file.path().display(), file.content().len()),
+///             Err(error) => println!("skipping: {}", error),
+///         }
+///     }
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct DirectorySource {
+    root: PathBuf,
+    pattern: Vec<String>,
+    fail_fast: bool,
+}
+
+impl DirectorySource {
+    /// # [`DirectorySource::new`]
+    ///
+    /// # Arguments
+    /// * `root`: impl [`Into<PathBuf>`] - the directory to walk.
+    /// * `pattern`: &[`str`] - the glob the relative file paths must match e.g. `"**/*.md"`.
+    ///
+    /// # Returns
+    /// * [`DirectorySource`] - which reports unreadable files and carries on.
+    pub fn new(root: impl Into<PathBuf>, pattern: &str) -> Self {
+        DirectorySource {
+            root: root.into(),
+            pattern: pattern
+                .split('/')
+                .filter(|segment| !segment.is_empty())
+                .map(String::from)
+                .collect(),
+            fail_fast: false,
+        }
+    }
+
+    /// # [`DirectorySource::with_fail_fast`]
+    ///
+    /// By default an unreadable file or directory is yielded as an error and the walk carries on.
+    /// With fail fast set the stream ends after the first error.
+    ///
+    /// # Arguments
+    /// * `fail_fast`: [`bool`] - whether to stop at the first error.
+    pub fn with_fail_fast(mut self, fail_fast: bool) -> Self {
+        self.fail_fast = fail_fast;
+        self
+    }
+
+    /// # [`DirectorySource::stream`]
+    ///
+    /// Walks the directory lazily, each file is only read when the stream is polled for it.
+    /// Files within a directory are yielded in name order before its subdirectories are walked.
+    ///
+    /// # Returns
+    /// * impl [`Stream<Item = Result<LoadedFile, DirectorySourceError>>`] - the matching files
+    ///   or the errors hit reading them.
+    pub fn stream(
+        &self,
+    ) -> impl Stream<Item = Result<LoadedFile, DirectorySourceError>> + Send + Unpin {
+        let walk = DirectoryWalk {
+            source: self.clone(),
+            directories: vec![self.root.clone()],
+            files: VecDeque::new(),
+            finished: false,
+        };
+        stream::unfold(walk, |mut walk| async move {
+            let next = walk.next().await?;
+            Some((next, walk))
+        })
+        .boxed()
+    }
+
+    fn matches(&self, relative_path: &[String]) -> bool {
+        glob_match(&self.pattern, relative_path)
+    }
+}
+
+impl AsyncLoadSource for DirectorySource {
+    type ErrorType = DirectorySourceError;
+
+    /// Reads every matching file into memory, prefer [`DirectorySource::stream`] for a large
+    /// directory. Unreadable files are left out unless fail fast is set, then the first error
+    /// is returned.
+    async fn load(&self) -> Result<Vec<String>, Self::ErrorType> {
+        let mut files = self.stream();
+        let mut contents: Vec<String> = Vec::new();
+        while let Some(file) = files.next().await {
+            match file {
+                Ok(file) => contents.push(file.content),
+                Err(error) if self.fail_fast => return Err(error),
+                Err(_) => continue,
+            }
+        }
+        Ok(contents)
+    }
+
+    /// The same as [`DirectorySource::load`] but each file is returned as a [`Document`]
+    /// carrying its [`LoadedFile::metadata`].
+    async fn load_documents(&self) -> Result<Vec<Document>, Self::ErrorType> {
+        let mut files = self.stream();
+        let mut documents: Vec<Document> = Vec::new();
+        while let Some(file) = files.next().await {
+            match file {
+                Ok(file) => documents.push(file.into_document()),
+                Err(error) if self.fail_fast => return Err(error),
+                Err(_) => continue,
+            }
+        }
+        Ok(documents)
+    }
+}
+
+/// # [`LoadedFile`]
+/// A file read by a [`DirectorySource`] along with its path relative to the root.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LoadedFile {
+    path: PathBuf,
+    content: String,
+}
+
+impl LoadedFile {
+    /// # [`LoadedFile::path`]
+    ///
+    /// # Returns
+    /// * &[`Path`] - the path of the file relative to the root of the [`DirectorySource`].
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// # [`LoadedFile::content`]
+    ///
+    /// # Returns
+    /// * &[`str`] - the contents of the file.
+    pub fn content(&self) -> &str {
+        &self.content
+    }
+
+    /// # [`LoadedFile::metadata`]
+    ///
+    /// The metadata to attach to the chunks of this file, see [`Chunk::new_with_metadata`].
+    ///
+    /// # Returns
+    /// * [`serde_json::Value`] - `{"path": "<relative path>"}` using `/` as the separator.
+    pub fn metadata(&self) -> serde_json::Value {
+        let path: Vec<String> = segments(&self.path);
+        json!({ "path": path.join("/") })
+    }
+
+    /// # [`LoadedFile::into_document`]
+    ///
+    /// # Returns
+    /// * [`Document`] - the file carrying its [`LoadedFile::metadata`].
+    pub fn into_document(self) -> Document {
+        let metadata: serde_json::Value = self.metadata();
+        Document::new_with_metadata(self.content, metadata)
+    }
+
+    /// # [`LoadedFile::into_chunk`]
+    ///
+    /// # Returns
+    /// * [`Chunk`] - the whole file as a single chunk carrying its [`LoadedFile::metadata`].
+    pub fn into_chunk(self) -> Chunk {
+        let metadata: serde_json::Value = self.metadata();
+        Chunk::new_with_metadata(self.content, metadata)
+    }
+}
+
+/// # [`DirectorySourceError`]
+/// The errors a [`DirectorySource`] can hit while walking a directory.
+#[derive(Error, Debug)]
+pub enum DirectorySourceError {
+    /// A directory could not be listed
+    #[error("Error reading directory {}: {source}", path.display())]
+    ReadingDirectory {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+    /// A file could not be read, this includes files which are not valid UTF-8
+    #[error("Error reading file {}: {source}", path.display())]
+    ReadingFile {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+}
+
+/// The directories still to be listed and the matching files still to be read.
+struct DirectoryWalk {
+    source: DirectorySource,
+    directories: Vec<PathBuf>,
+    files: VecDeque<PathBuf>,
+    finished: bool,
+}
+
+impl DirectoryWalk {
+    async fn next(&mut self) -> Option<Result<LoadedFile, DirectorySourceError>> {
+        while !self.finished {
+            let result: Result<LoadedFile, DirectorySourceError> =
+                if let Some(path) = self.files.pop_front() {
+                    self.read_file(path).await
+                } else if let Some(directory) = self.directories.pop() {
+                    match self.list_directory(&directory).await {
+                        Ok(()) => continue,
+                        Err(source) => Err(DirectorySourceError::ReadingDirectory {
+                            path: directory,
+                            source,
+                        }),
+                    }
+                } else {
+                    return None;
+                };
+            self.finished = result.is_err() && self.source.fail_fast;
+            return Some(result);
+        }
+        None
+    }
+
+    async fn read_file(&self, path: PathBuf) -> Result<LoadedFile, DirectorySourceError> {
+        let relative: PathBuf = path
+            .strip_prefix(&self.source.root)
+            .unwrap_or(&path)
+            .to_path_buf();
+        match tokio::fs::read_to_string(&path).await {
+            Ok(content) => Ok(LoadedFile {
+                path: relative,
+                content,
+            }),
+            Err(source) => Err(DirectorySourceError::ReadingFile {
+                path: relative,
+                source,
+            }),
+        }
+    }
+
+    /// Queues the matching files in the directory and its subdirectories, each in name order.
+    async fn list_directory(&mut self, directory: &Path) -> Result<(), std::io::Error> {
+        let mut entries = tokio::fs::read_dir(directory).await?;
+        let mut files: Vec<PathBuf> = Vec::new();
+        let mut directories: Vec<PathBuf> = Vec::new();
+        while let Some(entry) = entries.next_entry().await? {
+            let path: PathBuf = entry.path();
+            // Symlinked directories are not followed so the walk can not loop
+            if entry.file_type().await?.is_dir() {
+                directories.push(path);
+            } else if is_file_or_unreadable(&path).await {
+                let relative: Vec<String> =
+                    segments(path.strip_prefix(&self.source.root).unwrap_or(&path));
+                if self.source.matches(&relative) {
+                    files.push(path);
+                }
+            }
+        }
+        files.sort();
+        directories.sort();
+        self.files.extend(files);
+        // The directories are a stack so the first by name is walked first
+        self.directories.extend(directories.into_iter().rev());
+        Ok(())
+    }
+}
+
+/// Follows symlinks, an entry which can not be followed is treated as a file so the
+/// error is reported when it is read.
+async fn is_file_or_unreadable(path: &Path) -> bool {
+    tokio::fs::metadata(path)
+        .await
+        .map(|metadata| metadata.is_file())
+        .unwrap_or(true)
+}
+
+fn segments(path: &Path) -> Vec<String> {
+    path.components()
+        .map(|component| component.as_os_str().to_string_lossy().into_owned())
+        .collect()
+}
+
+/// Matches path segments against glob segments, where `**` matches any number of segments.
+fn glob_match(pattern: &[String], path: &[String]) -> bool {
+    match pattern.split_first() {
+        None => path.is_empty(),
+        Some((first, rest)) if first == "**" => {
+            (0..=path.len()).any(|skipped| glob_match(rest, &path[skipped..]))
+        }
+        Some((first, rest)) => match path.split_first() {
+            Some((segment, path)) => segment_match(first, segment) && glob_match(rest, path),
+            None => false,
+        },
+    }
+}
+
+/// Matches a single path segment against a glob segment containing `*` and `?`.
+fn segment_match(pattern: &str, segment: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let segment: Vec<char> = segment.chars().collect();
+    let (mut p, mut s) = (0, 0);
+    // The position of the last `*` and the segment position it was tried at
+    let mut backtrack: Option<(usize, usize)> = None;
+    while s < segment.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, s));
+                p += 1;
+            }
+            Some('?') => {
+                p += 1;
+                s += 1;
+            }
+            Some(c) if *c == segment[s] => {
+                p += 1;
+                s += 1;
+            }
+            _ => match backtrack {
+                Some((star, tried)) => {
+                    backtrack = Some((star, tried + 1));
+                    p = star + 1;
+                    s = tried + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::StreamExt;
+    use std::fs;
+    use uuid::Uuid;
+
+    #[test]
+    fn glob_matching() {
+        let cases: Vec<(&str, &str, bool)> = vec![
+            ("*.md", "readme.md", true),
+            ("*.md", "docs/readme.md", false),
+            ("**/*.md", "readme.md", true),
+            ("**/*.md", "docs/guides/readme.md", true),
+            ("**/*.md", "docs/readme.txt", false),
+            ("docs/**", "docs/a/b.txt", true),
+            ("docs/**/*.md", "src/a.md", false),
+            ("file?.txt", "file1.txt", true),
+            ("file?.txt", "file10.txt", false),
+            ("*a*b", "xaxxab", true),
+            ("*a*b", "xaxxa", false),
+        ];
+        for (pattern, path, expected) in cases {
+            let source = DirectorySource::new(".", pattern);
+            let path: Vec<String> = path.split('/').map(String::from).collect();
+            assert_eq!(source.matches(&path), expected, "{} {:?}", pattern, path);
+        }
+    }
+
+    #[tokio::test]
+    async fn stream_yields_matching_files_with_relative_paths() {
+        let root = TestDirectory::new();
+        root.write("b.md", "b");
+        root.write("a.md", "a");
+        root.write("notes.txt", "ignored");
+        root.write("guides/c.md", "c");
+
+        let source = DirectorySource::new(&root.0, "**/*.md");
+        let files: Vec<LoadedFile> = source
+            .stream()
+            .map(Result::unwrap)
+            .collect::<Vec<LoadedFile>>()
+            .await;
+        let paths: Vec<serde_json::Value> = files.iter().map(LoadedFile::metadata).collect();
+        assert_eq!(
+            paths,
+            vec![
+                json!({"path": "a.md"}),
+                json!({"path": "b.md"}),
+                json!({"path": "guides/c.md"})
+            ]
+        );
+        let chunk: Chunk = files[2].clone().into_chunk();
+        assert_eq!(chunk.content(), "c");
+        assert_eq!(chunk.metadata(), &json!({"path": "guides/c.md"}));
+    }
+
+    #[tokio::test]
+    async fn unreadable_files_are_reported_and_skipped() {
+        let root = TestDirectory::new();
+        root.write("a.txt", "a");
+        fs::write(root.0.join("b.txt"), [0xff, 0xfe]).unwrap();
+        root.write("c.txt", "c");
+
+        let source = DirectorySource::new(&root.0, "*.txt");
+        let results: Vec<Result<LoadedFile, DirectorySourceError>> =
+            source.stream().collect().await;
+        assert_eq!(results.len(), 3);
+        assert!(matches!(
+            &results[1],
+            Err(DirectorySourceError::ReadingFile { path, .. }) if path == Path::new("b.txt")
+        ));
+        assert_eq!(source.load().await.unwrap(), vec!["a", "c"]);
+        assert_eq!(
+            source.load_documents().await.unwrap(),
+            vec![
+                Document::new_with_metadata("a", json!({"path": "a.txt"})),
+                Document::new_with_metadata("c", json!({"path": "c.txt"})),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn fail_fast_stops_at_the_first_error() {
+        let root = TestDirectory::new();
+        root.write("a.txt", "a");
+        fs::write(root.0.join("b.txt"), [0xff, 0xfe]).unwrap();
+        root.write("c.txt", "c");
+
+        let source = DirectorySource::new(&root.0, "*.txt").with_fail_fast(true);
+        let results: Vec<Result<LoadedFile, DirectorySourceError>> =
+            source.stream().collect().await;
+        assert_eq!(results.len(), 2);
+        assert!(results[1].is_err());
+        assert!(source.load().await.is_err());
+        assert!(source.load_documents().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn missing_root_is_an_error() {
+        let source =
+            DirectorySource::new(std::env::temp_dir().join(Uuid::new_v4().to_string()), "**");
+        let results: Vec<Result<LoadedFile, DirectorySourceError>> =
+            source.stream().collect().await;
+        assert!(matches!(
+            results.as_slice(),
+            [Err(DirectorySourceError::ReadingDirectory { .. })]
+        ));
+    }
+
+    // A temporary directory which is removed when dropped
+    struct TestDirectory(PathBuf);
+
+    impl TestDirectory {
+        fn new() -> Self {
+            let path: PathBuf = std::env::temp_dir().join(Uuid::new_v4().to_string());
+            fs::create_dir_all(&path).unwrap();
+            TestDirectory(path)
+        }
+
+        fn write(&self, relative: &str, content: &str) {
+            let path: PathBuf = self.0.join(relative);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, content).unwrap();
+        }
+    }
+
+    impl Drop for TestDirectory {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+}
@@ -0,0 +1,414 @@
+use crate::common::{Chunk, Document};
+use crate::loaders::html_text::{extract, ExtractedHtml};
+use crate::loaders::traits::AsyncLoadSource;
+use reqwest::header::CONTENT_TYPE;
+use reqwest::{Client, Url};
+use serde_json::json;
+use std::time::Duration;
+use thiserror::Error;
+
+/// How long [`HtmlSource`] waits for a page by default, this covers the whole request
+pub const DEFAULT_HTML_SOURCE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The content types which are read as HTML
+const HTML_CONTENT_TYPES: [&str; 2] = ["text/html", "application/xhtml+xml"];
+
+/// # [`HtmlSource`]
+/// Loads the readable text of a web page, either fetched from a URL or given as raw HTML.
+/// Scripts, styles and boilerplate such as `nav`, `header`, `footer` and `aside` elements are
+/// stripped and the remaining text is returned with one line per block element.
+///
+/// # Examples
+/// ```
+/// use rag_toolchain::loaders::*;
+/// use std::time::Duration;
+///
+/// async fn load_page() {
+///     let source = HtmlSource::from_url("https://example.com").with_timeout(Duration::from_secs(5));
+///     let page: HtmlPage = source.fetch().await.unwrap();
+///     println!("{:?} {:?}", page.title(), page.canonical_url());
+///     let chunk = page.into_chunk();
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct HtmlSource {
+    input: HtmlInput,
+    timeout: Duration,
+}
+
+#[derive(Debug, Clone)]
+enum HtmlInput {
+    Url(String),
+    Html(String),
+}
+
+impl HtmlSource {
+    /// # [`HtmlSource::from_url`]
+    ///
+    /// # Arguments
+    /// * `url`: impl [`Into<String>`] - the page to fetch, redirects are followed.
+    ///
+    /// # Returns
+    /// * [`HtmlSource`] - which fetches the page with [`DEFAULT_HTML_SOURCE_TIMEOUT`].
+    pub fn from_url(url: impl Into<String>) -> Self {
+        HtmlSource {
+            input: HtmlInput::Url(url.into()),
+            timeout: DEFAULT_HTML_SOURCE_TIMEOUT,
+        }
+    }
+
+    /// # [`HtmlSource::from_html`]
+    ///
+    /// # Arguments
+    /// * `html`: impl [`Into<String>`] - the HTML document to read, nothing is fetched.
+    ///
+    /// # Returns
+    /// * [`HtmlSource`] - which reads the given HTML.
+    pub fn from_html(html: impl Into<String>) -> Self {
+        HtmlSource {
+            input: HtmlInput::Html(html.into()),
+            timeout: DEFAULT_HTML_SOURCE_TIMEOUT,
+        }
+    }
+
+    /// # [`HtmlSource::with_timeout`]
+    ///
+    /// # Arguments
+    /// * `timeout`: [`Duration`] - how long to wait for the whole request, including redirects.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// # [`HtmlSource::fetch`]
+    ///
+    /// Fetches the page if needed and extracts its title, canonical URL and readable text.
+    ///
+    /// # Errors
+    /// * [`HtmlSourceError::ErrorBuildingClient`] - if the HTTP client could not be created.
+    /// * [`HtmlSourceError::Timeout`] - if the page was not fetched within the timeout.
+    /// * [`HtmlSourceError::RedirectLoop`] - if the redirects loop or there are too many of them.
+    /// * [`HtmlSourceError::ErrorSendingRequest`] - if the request could not be sent.
+    /// * [`HtmlSourceError::ErrorStatus`] - if the response status is not a success.
+    /// * [`HtmlSourceError::UnsupportedContentType`] - if the response is not HTML.
+    /// * [`HtmlSourceError::ErrorGettingResponseBody`] - if the response body could not be read.
+    ///
+    /// # Returns
+    /// * [`HtmlPage`] - the readable parts of the page.
+    pub async fn fetch(&self) -> Result<HtmlPage, HtmlSourceError> {
+        match &self.input {
+            HtmlInput::Html(html) => Ok(HtmlPage::new(extract(html), None)),
+            HtmlInput::Url(url) => self.fetch_url(url).await,
+        }
+    }
+
+    async fn fetch_url(&self, url: &str) -> Result<HtmlPage, HtmlSourceError> {
+        let client: Client = Client::builder()
+            .timeout(self.timeout)
+            .build()
+            .map_err(HtmlSourceError::ErrorBuildingClient)?;
+        let response = client
+            .get(url)
+            .send()
+            .await
+            .map_err(|error| self.request_error(url, error))?;
+
+        let status = response.status();
+        if !status.is_success() {
+            return Err(HtmlSourceError::ErrorStatus {
+                url: url.to_string(),
+                status: status.as_u16(),
+            });
+        }
+        // A response without a content type is given the benefit of the doubt
+        if let Some(content_type) = response.headers().get(CONTENT_TYPE) {
+            let content_type: String = String::from_utf8_lossy(content_type.as_bytes()).into();
+            let mime: &str = content_type.split(';').next().unwrap_or_default().trim();
+            if !HTML_CONTENT_TYPES
+                .iter()
+                .any(|html| mime.eq_ignore_ascii_case(html))
+            {
+                return Err(HtmlSourceError::UnsupportedContentType {
+                    url: url.to_string(),
+                    content_type,
+                });
+            }
+        }
+
+        let final_url: Url = response.url().clone();
+        let html: String = response
+            .text()
+            .await
+            .map_err(|error| self.request_error(url, error))?;
+        Ok(HtmlPage::new(extract(&html), Some(&final_url)))
+    }
+
+    fn request_error(&self, url: &str, error: reqwest::Error) -> HtmlSourceError {
+        let url: String = url.to_string();
+        if error.is_timeout() {
+            HtmlSourceError::Timeout {
+                url,
+                timeout: self.timeout,
+            }
+        } else if error.is_redirect() {
+            HtmlSourceError::RedirectLoop { url, source: error }
+        } else if error.is_body() || error.is_decode() {
+            HtmlSourceError::ErrorGettingResponseBody { url, source: error }
+        } else {
+            HtmlSourceError::ErrorSendingRequest { url, source: error }
+        }
+    }
+}
+
+impl AsyncLoadSource for HtmlSource {
+    type ErrorType = HtmlSourceError;
+
+    /// Returns the readable text of the page, use [`HtmlSource::fetch`] to get its title
+    /// and canonical URL as well.
+    async fn load(&self) -> Result<Vec<String>, Self::ErrorType> {
+        let page: HtmlPage = self.fetch().await?;
+        Ok(vec![page.text])
+    }
+
+    /// Returns the readable text of the page carrying its [`HtmlPage::metadata`].
+    async fn load_documents(&self) -> Result<Vec<Document>, Self::ErrorType> {
+        let page: HtmlPage = self.fetch().await?;
+        Ok(vec![page.into_document()])
+    }
+}
+
+/// # [`HtmlPage`]
+/// The readable parts of a page loaded by a [`HtmlSource`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HtmlPage {
+    title: Option<String>,
+    canonical_url: Option<String>,
+    text: String,
+}
+
+impl HtmlPage {
+    /// The canonical link is resolved against the URL the page was fetched from, if there is
+    /// no canonical link that URL is used instead.
+    fn new(extracted: ExtractedHtml, fetched_from: Option<&Url>) -> Self {
+        let canonical_url: Option<String> = match (extracted.canonical_url, fetched_from) {
+            (Some(canonical), Some(base)) => {
+                Some(base.join(&canonical).map(String::from).unwrap_or(canonical))
+            }
+            (Some(canonical), None) => Some(canonical),
+            (None, base) => base.map(Url::to_string),
+        };
+        HtmlPage {
+            title: extracted.title,
+            canonical_url,
+            text: extracted.text,
+        }
+    }
+
+    /// # [`HtmlPage::title`]
+    ///
+    /// # Returns
+    /// * [`Option<&str>`] - the contents of the page's `<title>` element.
+    pub fn title(&self) -> Option<&str> {
+        self.title.as_deref()
+    }
+
+    /// # [`HtmlPage::canonical_url`]
+    ///
+    /// # Returns
+    /// * [`Option<&str>`] - the page's `<link rel="canonical">`, or the URL it was fetched from.
+    pub fn canonical_url(&self) -> Option<&str> {
+        self.canonical_url.as_deref()
+    }
+
+    /// # [`HtmlPage::text`]
+    ///
+    /// # Returns
+    /// * &[`str`] - the readable text of the page.
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// # [`HtmlPage::metadata`]
+    ///
+    /// The metadata to attach to the chunks of this page, see [`Chunk::new_with_metadata`].
+    ///
+    /// # Returns
+    /// * [`serde_json::Value`] - `{"title": ..., "canonical_url": ...}`, either may be null.
+    pub fn metadata(&self) -> serde_json::Value {
+        json!({ "title": self.title, "canonical_url": self.canonical_url })
+    }
+
+    /// # [`HtmlPage::into_document`]
+    ///
+    /// # Returns
+    /// * [`Document`] - the readable text of the page carrying its [`HtmlPage::metadata`].
+    pub fn into_document(self) -> Document {
+        let metadata: serde_json::Value = self.metadata();
+        Document::new_with_metadata(self.text, metadata)
+    }
+
+    /// # [`HtmlPage::into_chunk`]
+    ///
+    /// # Returns
+    /// * [`Chunk`] - the whole page as a single chunk carrying its [`HtmlPage::metadata`].
+    pub fn into_chunk(self) -> Chunk {
+        let metadata: serde_json::Value = self.metadata();
+        Chunk::new_with_metadata(self.text, metadata)
+    }
+}
+
+/// # [`HtmlSourceError`]
+/// The errors a [`HtmlSource`] can hit while fetching a page.
+#[derive(Error, Debug)]
+pub enum HtmlSourceError {
+    /// The HTTP client could not be created
+    #[error("Error building HTTP client: {0}")]
+    ErrorBuildingClient(reqwest::Error),
+    /// The page was not fetched within the timeout
+    #[error("Timed out after {timeout:?} fetching {url}")]
+    Timeout { url: String, timeout: Duration },
+    /// The redirects looped or there were too many of them
+    #[error("Redirect loop fetching {url}: {source}")]
+    RedirectLoop {
+        url: String,
+        #[source]
+        source: reqwest::Error,
+    },
+    #[error("Error sending request to {url}: {source}")]
+    ErrorSendingRequest {
+        url: String,
+        #[source]
+        source: reqwest::Error,
+    },
+    #[error("{url} returned status {status}")]
+    ErrorStatus { url: String, status: u16 },
+    /// The response was not HTML e.g. a PDF or JSON
+    #[error("{url} returned content type {content_type} which is not HTML")]
+    UnsupportedContentType { url: String, content_type: String },
+    #[error("Error reading response body from {url}: {source}")]
+    ErrorGettingResponseBody {
+        url: String,
+        #[source]
+        source: reqwest::Error,
+    },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mockito::{Server, ServerGuard};
+
+    const PAGE: &str = r#"<html><head><title>Guide</title>
+        <link rel="canonical" href="/docs/guide"></head>
+        <body><nav>Menu</nav><h1>Guide</h1><p>Read me</p><script>x()</script></body></html>"#;
+
+    #[tokio::test]
+    async fn from_html_extracts_page() {
+        let page: HtmlPage = HtmlSource::from_html(PAGE).fetch().await.unwrap();
+        assert_eq!(page.title(), Some("Guide"));
+        assert_eq!(page.canonical_url(), Some("/docs/guide"));
+        assert_eq!(page.text(), "Guide\nRead me");
+        let chunk: Chunk = page.into_chunk();
+        assert_eq!(chunk.content(), "Guide\nRead me");
+        assert_eq!(
+            chunk.metadata(),
+            &json!({"title": "Guide", "canonical_url": "/docs/guide"})
+        );
+    }
+
+    #[tokio::test]
+    async fn from_url_fetches_and_resolves_canonical_url() {
+        let mut server: ServerGuard = Server::new_async().await;
+        let mock = server
+            .mock("GET", "/page")
+            .with_header("content-type", "text/html; charset=utf-8")
+            .with_body(PAGE)
+            .create_async()
+            .await;
+
+        let source = HtmlSource::from_url(format!("{}/page", server.url()));
+        let page: HtmlPage = source.fetch().await.unwrap();
+        mock.assert_async().await;
+        let expected_url: String = format!("{}/docs/guide", server.url());
+        assert_eq!(page.canonical_url(), Some(expected_url.as_str()));
+        assert_eq!(source.load().await.unwrap(), vec!["Guide\nRead me"]);
+        let documents: Vec<Document> = source.load_documents().await.unwrap();
+        assert_eq!(
+            documents,
+            vec![Document::new_with_metadata(
+                "Guide\nRead me",
+                json!({"title": "Guide", "canonical_url": expected_url})
+            )]
+        );
+    }
+
+    #[tokio::test]
+    async fn page_without_canonical_link_uses_fetched_url() {
+        let mut server: ServerGuard = Server::new_async().await;
+        server
+            .mock("GET", "/plain")
+            .with_body("<p>plain</p>")
+            .create_async()
+            .await;
+
+        let url: String = format!("{}/plain", server.url());
+        let page: HtmlPage = HtmlSource::from_url(&url).fetch().await.unwrap();
+        assert_eq!(page.title(), None);
+        assert_eq!(page.canonical_url(), Some(url.as_str()));
+    }
+
+    #[tokio::test]
+    async fn redirect_loop_is_reported() {
+        let mut server: ServerGuard = Server::new_async().await;
+        server
+            .mock("GET", "/loop")
+            .with_status(302)
+            .with_header("location", "/loop")
+            .expect_at_least(1)
+            .create_async()
+            .await;
+
+        let result = HtmlSource::from_url(format!("{}/loop", server.url()))
+            .fetch()
+            .await;
+        assert!(matches!(result, Err(HtmlSourceError::RedirectLoop { .. })));
+    }
+
+    #[tokio::test]
+    async fn non_html_content_type_is_reported() {
+        let mut server: ServerGuard = Server::new_async().await;
+        server
+            .mock("GET", "/data")
+            .with_header("content-type", "application/json")
+            .with_body("{}")
+            .create_async()
+            .await;
+
+        let result = HtmlSource::from_url(format!("{}/data", server.url()))
+            .fetch()
+            .await;
+        assert!(matches!(
+            result,
+            Err(HtmlSourceError::UnsupportedContentType { content_type, .. })
+                if content_type == "application/json"
+        ));
+    }
+
+    #[tokio::test]
+    async fn error_status_is_reported() {
+        let mut server: ServerGuard = Server::new_async().await;
+        server
+            .mock("GET", "/missing")
+            .with_status(404)
+            .create_async()
+            .await;
+
+        let result = HtmlSource::from_url(format!("{}/missing", server.url()))
+            .fetch()
+            .await;
+        assert!(matches!(
+            result,
+            Err(HtmlSourceError::ErrorStatus { status: 404, .. })
+        ));
+    }
+}
@@ -0,0 +1,369 @@
+//! A small, forgiving HTML to text extractor. It does not build a DOM, it walks the tags
+//! in order and keeps the text which is not inside a boilerplate element.
+
+/// Elements whose contents are never part of the readable text
+const SKIPPED_ELEMENTS: [&str; 9] = [
+    "script", "style", "noscript", "template", "svg", "nav", "header", "footer", "aside",
+];
+
+/// Elements whose contents are raw text, the first matching closing tag ends them
+const RAW_TEXT_ELEMENTS: [&str; 4] = ["script", "style", "template", "textarea"];
+
+/// Elements which start a new line in the extracted text
+const BLOCK_ELEMENTS: [&str; 28] = [
+    "address",
+    "article",
+    "blockquote",
+    "br",
+    "dd",
+    "div",
+    "dl",
+    "dt",
+    "figcaption",
+    "figure",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "hr",
+    "li",
+    "main",
+    "ol",
+    "p",
+    "pre",
+    "section",
+    "table",
+    "td",
+    "th",
+    "tr",
+    "ul",
+];
+
+/// The readable parts of an HTML document
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub(crate) struct ExtractedHtml {
+    pub title: Option<String>,
+    pub canonical_url: Option<String>,
+    pub text: String,
+}
+
+/// # [`extract`]
+///
+/// Extracts the title, the canonical url and the readable text from an HTML document.
+/// The text has one line per block element with its whitespace collapsed.
+pub(crate) fn extract(html: &str) -> ExtractedHtml {
+    let mut extracted = ExtractedHtml::default();
+    let mut text = String::new();
+    let mut title: Option<String> = None;
+    let mut in_title = false;
+    // How many skipped elements we are currently inside
+    let mut skip_depth: usize = 0;
+    let mut rest: &str = html;
+
+    while let Some(start) = rest.find('<') {
+        let (before, after) = rest.split_at(start);
+        if skip_depth == 0 {
+            let target: &mut String = match title.as_mut() {
+                Some(title) if in_title => title,
+                _ => &mut text,
+            };
+            target.push_str(&decode_entities(before));
+        }
+        rest = after;
+
+        if let Some(comment) = rest.strip_prefix("<!--") {
+            rest = comment.find("-->").map_or("", |end| &comment[end + 3..]);
+            continue;
+        }
+        let Some(tag) = parse_tag(rest) else {
+            // A lone '<' is just text
+            if skip_depth == 0 {
+                text.push('<');
+            }
+            rest = &rest[1..];
+            continue;
+        };
+        rest = &rest[tag.length..];
+
+        let name: &str = &tag.name;
+        if SKIPPED_ELEMENTS.contains(&name) && !tag.self_closing {
+            if tag.closing {
+                skip_depth = skip_depth.saturating_sub(1);
+            } else {
+                skip_depth += 1;
+            }
+        }
+        if !tag.closing && RAW_TEXT_ELEMENTS.contains(&name) {
+            let end: usize = find_closing_tag(rest, name).unwrap_or(rest.len());
+            if skip_depth == 0 {
+                text.push_str(&decode_entities(&rest[..end]));
+            }
+            rest = &rest[end..];
+            continue;
+        }
+        if skip_depth > 0 {
+            continue;
+        }
+        match name {
+            "title" if extracted.title.is_none() => {
+                in_title = !tag.closing;
+                if in_title {
+                    title = Some(String::new());
+                } else {
+                    extracted.title = title.take().map(|title| collapse_whitespace(&title));
+                }
+                continue;
+            }
+            "link" if extracted.canonical_url.is_none() => {
+                let canonical: bool = tag
+                    .attribute("rel")
+                    .is_some_and(|rel| rel.split_whitespace().any(|rel| rel == "canonical"));
+                if canonical {
+                    extracted.canonical_url = tag.attribute("href").map(decode_entities);
+                }
+            }
+            _ => {}
+        }
+        if BLOCK_ELEMENTS.contains(&name) {
+            text.push('\n');
+        }
+    }
+    if skip_depth == 0 {
+        text.push_str(&decode_entities(rest));
+    }
+    extracted.text = text
+        .lines()
+        .map(collapse_whitespace)
+        .filter(|line| !line.is_empty())
+        .collect::<Vec<String>>()
+        .join("\n");
+    extracted
+}
+
+/// A start or end tag along with the number of bytes it spans
+struct Tag {
+    name: String,
+    closing: bool,
+    self_closing: bool,
+    attributes: Vec<(String, String)>,
+    length: usize,
+}
+
+impl Tag {
+    fn attribute(&self, name: &str) -> Option<&str> {
+        self.attributes
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// Parses the tag at the start of the input, `None` if the '<' does not start a tag.
+/// Declarations such as `<!DOCTYPE html>` are returned as a tag with an empty name.
+fn parse_tag(input: &str) -> Option<Tag> {
+    let body: &str = input.strip_prefix('<')?;
+    let (closing, body) = match body.strip_prefix('/') {
+        Some(body) => (true, body),
+        None => (false, body),
+    };
+    let first: char = body.chars().next()?;
+    if first == '!' || first == '?' {
+        let end: usize = input.find('>').map_or(input.len(), |end| end + 1);
+        return Some(Tag {
+            name: String::new(),
+            closing,
+            self_closing: true,
+            attributes: Vec::new(),
+            length: end,
+        });
+    }
+    if !first.is_ascii_alphabetic() {
+        return None;
+    }
+    let name_end: usize = body
+        .find(|c: char| c.is_whitespace() || c == '>' || c == '/')
+        .unwrap_or(body.len());
+    let name: String = body[..name_end].to_ascii_lowercase();
+    let (attributes, attributes_length, self_closing) = parse_attributes(&body[name_end..]);
+    let prefix: usize = input.len() - body.len();
+    Some(Tag {
+        name,
+        closing,
+        self_closing,
+        attributes,
+        length: prefix + name_end + attributes_length,
+    })
+}
+
+/// Parses attributes up to and including the closing '>'. Returns the attributes, the
+/// number of bytes read and whether the tag ended with "/>".
+fn parse_attributes(input: &str) -> (Vec<(String, String)>, usize, bool) {
+    let mut attributes: Vec<(String, String)> = Vec::new();
+    let mut position: usize = 0;
+    let bytes: &[u8] = input.as_bytes();
+    let mut self_closing = false;
+    while position < bytes.len() {
+        match bytes[position] {
+            b'>' => return (attributes, position + 1, self_closing),
+            b'/' => {
+                self_closing = true;
+                position += 1;
+            }
+            byte if byte.is_ascii_whitespace() => position += 1,
+            _ => {
+                self_closing = false;
+                let key_end: usize = input[position..]
+                    .find(|c: char| c.is_whitespace() || c == '=' || c == '>' || c == '/')
+                    .map_or(input.len(), |end| position + end);
+                let key: String = input[position..key_end].to_ascii_lowercase();
+                position = key_end;
+                let mut value = String::new();
+                if bytes.get(position) == Some(&b'=') {
+                    position += 1;
+                    match bytes.get(position) {
+                        Some(&quote) if quote == b'"' || quote == b'\'' => {
+                            let value_end: usize = input[position + 1..]
+                                .find(quote as char)
+                                .map_or(input.len(), |end| position + 1 + end);
+                            value = input[position + 1..value_end].to_string();
+                            position = (value_end + 1).min(input.len());
+                        }
+                        _ => {
+                            let value_end: usize = input[position..]
+                                .find(|c: char| c.is_whitespace() || c == '>')
+                                .map_or(input.len(), |end| position + end);
+                            value = input[position..value_end].to_string();
+                            position = value_end;
+                        }
+                    }
+                }
+                attributes.push((key, value));
+            }
+        }
+    }
+    (attributes, input.len(), self_closing)
+}
+
+/// Finds the start of the closing tag for a raw text element, ignoring case.
+fn find_closing_tag(input: &str, name: &str) -> Option<usize> {
+    let closing: String = format!("</{}", name);
+    input
+        .char_indices()
+        .filter(|(_, c)| *c == '<')
+        .map(|(index, _)| index)
+        .find(|index| {
+            input
+                .get(*index..*index + closing.len())
+                .is_some_and(|tag| tag.eq_ignore_ascii_case(&closing))
+        })
+}
+
+fn collapse_whitespace(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<&str>>().join(" ")
+}
+
+/// Decodes the common named entities and numeric character references.
+/// Anything unrecognised is left as it is.
+fn decode_entities(text: &str) -> String {
+    let mut decoded = String::with_capacity(text.len());
+    let mut rest: &str = text;
+    while let Some(start) = rest.find('&') {
+        decoded.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let entity: Option<(char, usize)> = rest
+            .find(';')
+            .filter(|end| *end <= 10)
+            .and_then(|end| decode_entity(&rest[1..end]).map(|c| (c, end + 1)));
+        match entity {
+            Some((c, length)) => {
+                decoded.push(c);
+                rest = &rest[length..];
+            }
+            None => {
+                decoded.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    decoded.push_str(rest);
+    decoded
+}
+
+fn decode_entity(entity: &str) -> Option<char> {
+    match entity {
+        "amp" => Some('&'),
+        "lt" => Some('<'),
+        "gt" => Some('>'),
+        "quot" => Some('"'),
+        "apos" => Some('\''),
+        "nbsp" => Some(' '),
+        _ => {
+            let number: &str = entity.strip_prefix('#')?;
+            let code: u32 = match number.strip_prefix(['x', 'X']) {
+                Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+                None => number.parse().ok()?,
+            };
+            char::from_u32(code)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PAGE: &str = r#"<!DOCTYPE html>
+<html>
+<head>
+    <title>  Getting   Started </title>
+    <link rel="canonical" href="https://example.com/docs?a=1&amp;b=2">
+    <style>body { color: red; }</style>
+    <script>if (a < b) { document.write("</p>"); }</script>
+</head>
+<body>
+    <nav><ul><li>Home</li><li>Docs</li></ul></nav>
+    <header>Site header</header>
+    <main>
+        <h1>Install</h1>
+        <p>Add the   crate to <code>Cargo.toml</code> &amp; build.</p>
+        <!-- <p>commented out</p> -->
+        <p>Use 1 &lt; 2 and caf&#233; or &#x2713;<br/>on a new line</p>
+        <nav>nested <nav>boilerplate</nav> still skipped</nav>
+        <img src="a.png" alt="ignored"/>
+    </main>
+    <footer>Copyright</footer>
+    <script type="text/javascript">track();</script>
+</body>
+</html>"#;
+
+    #[test]
+    fn extracts_title_canonical_url_and_text() {
+        let extracted: ExtractedHtml = extract(PAGE);
+        assert_eq!(extracted.title.as_deref(), Some("Getting Started"));
+        assert_eq!(
+            extracted.canonical_url.as_deref(),
+            Some("https://example.com/docs?a=1&b=2")
+        );
+        assert_eq!(
+            extracted.text,
+            "Install\nAdd the crate to Cargo.toml & build.\nUse 1 < 2 and café or ✓\non a new line"
+        );
+    }
+
+    #[test]
+    fn handles_fragments_and_stray_brackets() {
+        let extracted: ExtractedHtml = extract("a < b and <b>bold</b> & more");
+        assert_eq!(extracted.title, None);
+        assert_eq!(extracted.canonical_url, None);
+        assert_eq!(extracted.text, "a < b and bold & more");
+    }
+
+    #[test]
+    fn unclosed_elements_do_not_panic() {
+        assert_eq!(extract("<p>text<script>never closed").text, "text");
+        assert_eq!(extract("<p>text<!-- never closed").text, "text");
+        assert_eq!(extract("<a href=\"never closed").text, "");
+    }
+}
@@ -1,9 +1,17 @@
 /// # Loaders
 /// This modules aims to provide some easy methods of loading in
 /// input data to you Gen AI workflow.
+mod directory_loader;
+#[cfg(feature = "html")]
+mod html_loader;
+#[cfg(feature = "html")]
+mod html_text;
 mod single_file_loader;
 mod traits;
 
+pub use directory_loader::{DirectorySource, DirectorySourceError, LoadedFile};
+#[cfg(feature = "html")]
+pub use html_loader::{HtmlPage, HtmlSource, HtmlSourceError, DEFAULT_HTML_SOURCE_TIMEOUT};
 pub use single_file_loader::SingleFileSource;
 pub use traits::AsyncLoadSource;
 pub use traits::LoadSource;
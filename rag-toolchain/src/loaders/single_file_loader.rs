@@ -1,5 +1,8 @@
+use crate::common::Document;
 use crate::loaders::traits::LoadSource;
-use std::fs::read_to_string;
+use serde_json::json;
+use std::fs::{metadata, read_to_string};
+use std::time::{SystemTime, UNIX_EPOCH};
 
 /// # [`SingleFileSource`]
 /// Reads a single file and returns the contents as a vector with one file
@@ -21,4 +24,52 @@ impl LoadSource for SingleFileSource {
         let file_contents: String = read_to_string(&self.path)?;
         Ok(vec![file_contents])
     }
+
+    /// The document's metadata is `{"path": "<path>", "modified": <seconds>}` where modified
+    /// is the seconds since the unix epoch the file was last modified at, this is null on
+    /// platforms which do not record it.
+    fn load_documents(&self) -> Result<Vec<Document>, Self::ErrorType> {
+        let file_contents: String = read_to_string(&self.path)?;
+        let modified: Option<u64> = metadata(&self.path)?
+            .modified()
+            .ok()
+            .and_then(|modified: SystemTime| modified.duration_since(UNIX_EPOCH).ok())
+            .map(|since_epoch| since_epoch.as_secs());
+        let metadata = json!({ "path": self.path, "modified": modified });
+        Ok(vec![Document::new_with_metadata(file_contents, metadata)])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn load_documents_records_the_path_and_modified_time() {
+        let path = std::env::temp_dir().join(format!("single_file_{}.txt", std::process::id()));
+        std::fs::write(&path, "some text").unwrap();
+        let source = SingleFileSource::new(path.to_string_lossy());
+
+        let documents = source.load_documents().unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(documents.len(), 1);
+        assert_eq!(documents[0].content(), "some text");
+        assert_eq!(
+            documents[0].metadata()["path"],
+            json!(path.to_string_lossy())
+        );
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let modified = documents[0].metadata()["modified"].as_u64().unwrap();
+        assert!(modified <= now && now - modified < 60);
+    }
+
+    #[test]
+    fn missing_files_return_an_error() {
+        let source = SingleFileSource::new("does/not/exist.txt");
+        assert!(source.load_documents().is_err());
+    }
 }
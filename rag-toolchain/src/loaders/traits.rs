@@ -1,18 +1,52 @@
+use crate::common::Document;
 use std::error::Error;
 use std::future::Future;
 
 /// # [`LoadSource`]
 /// Trait that allows reading the raw text for an external source
 pub trait LoadSource {
-    type ErrorType: Error;
+    type ErrorType: Error + Send + Sync;
     /// Called an returns a vector of raw text to generate embeddings for
     fn load(&self) -> Result<Vec<String>, Self::ErrorType>;
+
+    /// # [`LoadSource::load_documents`]
+    ///
+    /// Loads the raw text along with metadata about where it came from. The default
+    /// wraps each string from [`LoadSource::load`] in a [`Document`] with no metadata,
+    /// sources which know more such as a file path override this.
+    ///
+    /// # Errors
+    /// * [`Self::ErrorType`] - if the source could not be loaded
+    ///
+    /// # Returns
+    /// * [`Vec<Document>`] - the loaded documents
+    fn load_documents(&self) -> Result<Vec<Document>, Self::ErrorType> {
+        Ok(self.load()?.into_iter().map(Document::new).collect())
+    }
 }
 
 /// # [`AsyncLoadSource`]
 /// Async version of [`LoadSource`] to support loading raw text from an external source
 pub trait AsyncLoadSource {
-    type ErrorType: Error;
+    type ErrorType: Error + Send + Sync;
     /// Called an returns a vector of raw text to generate embeddings for
     fn load(&self) -> impl Future<Output = Result<Vec<String>, Self::ErrorType>> + Send;
+
+    /// # [`AsyncLoadSource::load_documents`]
+    ///
+    /// Loads the raw text along with metadata about where it came from. The default
+    /// wraps each string from [`AsyncLoadSource::load`] in a [`Document`] with no metadata,
+    /// sources which know more such as a URL override this.
+    ///
+    /// # Errors
+    /// * [`Self::ErrorType`] - if the source could not be loaded
+    ///
+    /// # Returns
+    /// * [`Vec<Document>`] - the loaded documents
+    fn load_documents(
+        &self,
+    ) -> impl Future<Output = Result<Vec<Document>, Self::ErrorType>> + Send {
+        let load = self.load();
+        async move { Ok(load.await?.into_iter().map(Document::new).collect()) }
+    }
 }
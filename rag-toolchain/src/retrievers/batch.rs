@@ -0,0 +1,41 @@
+use std::error::Error;
+use thiserror::Error;
+
+/// # [`BatchRetrieveError`]
+///
+/// The error returned by [`crate::retrievers::AsyncRetriever::retrieve_batch`], saying
+/// which query failed when the failure was down to a single query.
+#[derive(Error, Debug, PartialEq)]
+pub enum BatchRetrieveError<E: Error> {
+    /// The query at `index` in the batch failed
+    #[error("Query {index} failed: {error}")]
+    Query { index: usize, error: E },
+    /// Work shared by every query in the batch failed, such as embedding them together
+    #[error("Batch failed: {0}")]
+    Batch(E),
+}
+
+impl<E: Error> BatchRetrieveError<E> {
+    /// # [`BatchRetrieveError::index`]
+    ///
+    /// # Returns
+    /// * [`Option<usize>`] - the index of the query which failed, or [`None`] if the
+    ///   whole batch failed.
+    pub fn index(&self) -> Option<usize> {
+        match self {
+            BatchRetrieveError::Query { index, .. } => Some(*index),
+            BatchRetrieveError::Batch(_) => None,
+        }
+    }
+
+    /// # [`BatchRetrieveError::into_inner`]
+    ///
+    /// # Returns
+    /// * `E` - the error from the retriever.
+    pub fn into_inner(self) -> E {
+        match self {
+            BatchRetrieveError::Query { error, .. } => error,
+            BatchRetrieveError::Batch(error) => error,
+        }
+    }
+}
@@ -0,0 +1,147 @@
+use std::fmt::{Display, Formatter};
+
+/// # [`DistanceFunction`]
+/// This is an enum for the types of distance functions
+/// that can be used to compare vectors. The operators and operator
+/// classes are pgvector's, the sqlite store supports L2 and cosine.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum DistanceFunction {
+    L2,
+    Cosine,
+    InnerProduct,
+}
+
+impl DistanceFunction {
+    /// # [`DistanceFunction::operator`]
+    ///
+    /// # Returns
+    /// * &[`str`] - the pgvector operator for this distance function e.g. `<=>` for cosine.
+    pub fn operator(&self) -> &'static str {
+        match self {
+            DistanceFunction::L2 => "<->",
+            DistanceFunction::Cosine => "<=>",
+            DistanceFunction::InnerProduct => "<#>",
+        }
+    }
+
+    /// # [`DistanceFunction::to_sql_string`]
+    ///
+    /// The same as [`DistanceFunction::operator`].
+    pub fn to_sql_string(&self) -> &str {
+        self.operator()
+    }
+
+    /// # [`DistanceFunction::operator_class`]
+    ///
+    /// The operator class an index on a `vector` column needs to serve this distance function,
+    /// useful when creating an index by hand through [`crate::stores::PostgresVectorStore::get_pool`].
+    /// For a `halfvec` column see [`crate::stores::VectorPrecision::operator_class`].
+    ///
+    /// # Returns
+    /// * &[`str`] - the operator class e.g. `vector_cosine_ops`.
+    pub fn operator_class(&self) -> &'static str {
+        match self {
+            DistanceFunction::L2 => "vector_l2_ops",
+            DistanceFunction::Cosine => "vector_cosine_ops",
+            DistanceFunction::InnerProduct => "vector_ip_ops",
+        }
+    }
+
+    /// The suffix shared by the operator classes of every column type e.g. `cosine_ops`.
+    #[cfg(feature = "pg_vector")]
+    pub(crate) fn operator_class_suffix(&self) -> &'static str {
+        match self {
+            DistanceFunction::L2 => "l2_ops",
+            DistanceFunction::Cosine => "cosine_ops",
+            DistanceFunction::InnerProduct => "ip_ops",
+        }
+    }
+
+    /// # [`DistanceFunction::to_score`]
+    ///
+    /// Turns the distance the database returns into a score where higher is more similar.
+    /// * [`DistanceFunction::Cosine`] - the cosine similarity `1 - distance`, from -1 to 1.
+    /// * [`DistanceFunction::InnerProduct`] - the inner product, pgvector returns it negated.
+    /// * [`DistanceFunction::L2`] - `1 / (1 + distance)`, from 0 up to 1 for identical vectors.
+    ///
+    /// # Arguments
+    /// * `distance`: [`f64`] - the distance between two vectors.
+    ///
+    /// # Returns
+    /// * [`f32`] - the similarity score.
+    pub fn to_score(&self, distance: f64) -> f32 {
+        let score: f64 = match self {
+            DistanceFunction::L2 => 1.0 / (1.0 + distance),
+            DistanceFunction::Cosine => 1.0 - distance,
+            DistanceFunction::InnerProduct => -distance,
+        };
+        score as f32
+    }
+
+    /// # [`DistanceFunction::distance`]
+    ///
+    /// Computes the distance between two vectors the same way pgvector does, so the inner
+    /// product is negated. A cosine distance involving a zero vector is 1, as if orthogonal.
+    ///
+    /// # Arguments
+    /// * `a`: &[`[f32]`] - the first vector.
+    /// * `b`: &[`[f32]`] - the second vector, of the same dimension.
+    ///
+    /// # Returns
+    /// * [`f64`] - the distance, pass it to [`DistanceFunction::to_score`] for a score.
+    pub(crate) fn distance(&self, a: &[f32], b: &[f32]) -> f64 {
+        let dot = || -> f64 {
+            a.iter()
+                .zip(b)
+                .map(|(x, y)| f64::from(*x) * f64::from(*y))
+                .sum()
+        };
+        let norm =
+            |v: &[f32]| -> f64 { v.iter().map(|x| f64::from(*x).powi(2)).sum::<f64>().sqrt() };
+        match self {
+            DistanceFunction::L2 => a
+                .iter()
+                .zip(b)
+                .map(|(x, y)| (f64::from(*x) - f64::from(*y)).powi(2))
+                .sum::<f64>()
+                .sqrt(),
+            DistanceFunction::Cosine => {
+                let norms: f64 = norm(a) * norm(b);
+                if norms == 0.0 {
+                    return 1.0;
+                }
+                1.0 - dot() / norms
+            }
+            DistanceFunction::InnerProduct => -dot(),
+        }
+    }
+}
+
+/// Displays the pgvector operator, see [`DistanceFunction::operator`].
+impl Display for DistanceFunction {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.operator())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn distances_match_pgvector() {
+        let a: [f32; 2] = [3.0, 0.0];
+        let b: [f32; 2] = [0.0, 4.0];
+        assert_eq!(DistanceFunction::L2.distance(&a, &b), 5.0);
+        assert_eq!(DistanceFunction::Cosine.distance(&a, &b), 1.0);
+        assert_eq!(DistanceFunction::Cosine.distance(&a, &[6.0, 0.0]), 0.0);
+        assert_eq!(DistanceFunction::Cosine.distance(&a, &[0.0, 0.0]), 1.0);
+        // pgvector's <#> returns the negative inner product
+        assert_eq!(
+            DistanceFunction::InnerProduct.distance(&a, &[2.0, 1.0]),
+            -6.0
+        );
+    }
+}
@@ -0,0 +1,291 @@
+use crate::common::{Chunks, DynError, DynFuture, InvocationContext, ScoredChunk};
+use crate::retrievers::{AsyncRetriever, BatchRetrieveError, MetadataFilter};
+use std::future::Future;
+use std::num::NonZeroU32;
+use std::pin::Pin;
+
+/// The future returned by [`DynAsyncRetriever::dyn_retrieve_batch`]
+type BatchFuture<'a> =
+    Pin<Box<dyn Future<Output = Result<Vec<Chunks>, BatchRetrieveError<DynError>>> + Send + 'a>>;
+
+/// # [`DynAsyncRetriever`]
+///
+/// An object safe version of [`AsyncRetriever`] so the retriever can be chosen at runtime.
+/// Every [`AsyncRetriever`] implements it with its errors boxed into a [`DynError`], and
+/// `Box<dyn DynAsyncRetriever>` implements [`AsyncRetriever`] so it can be given to a chain,
+/// such as a [`crate::chains::BasicRAGChain`]. Its methods take a `dyn_` prefix to keep them
+/// apart from the [`AsyncRetriever`] methods.
+pub trait DynAsyncRetriever: Send + Sync {
+    /// # [`DynAsyncRetriever::dyn_retrieve`]
+    ///
+    /// See [`AsyncRetriever::retrieve`].
+    fn dyn_retrieve<'a>(&'a self, text: &'a str, top_k: NonZeroU32) -> DynFuture<'a, Chunks>;
+
+    /// # [`DynAsyncRetriever::dyn_retrieve_with_context`]
+    ///
+    /// See [`AsyncRetriever::retrieve_with_context`].
+    fn dyn_retrieve_with_context<'a>(
+        &'a self,
+        text: &'a str,
+        top_k: NonZeroU32,
+        context: &'a InvocationContext,
+    ) -> DynFuture<'a, Chunks>;
+
+    /// # [`DynAsyncRetriever::dyn_retrieve_batch`]
+    ///
+    /// See [`AsyncRetriever::retrieve_batch`].
+    fn dyn_retrieve_batch<'a>(
+        &'a self,
+        queries: &'a [&'a str],
+        top_k: NonZeroU32,
+    ) -> BatchFuture<'a>;
+
+    /// # [`DynAsyncRetriever::dyn_retrieve_with_scores`]
+    ///
+    /// See [`AsyncRetriever::retrieve_with_scores`].
+    fn dyn_retrieve_with_scores<'a>(
+        &'a self,
+        text: &'a str,
+        top_k: NonZeroU32,
+    ) -> DynFuture<'a, Vec<ScoredChunk>>;
+
+    /// # [`DynAsyncRetriever::dyn_retrieve_with_scores_and_context`]
+    ///
+    /// See [`AsyncRetriever::retrieve_with_scores_and_context`].
+    fn dyn_retrieve_with_scores_and_context<'a>(
+        &'a self,
+        text: &'a str,
+        top_k: NonZeroU32,
+        context: &'a InvocationContext,
+    ) -> DynFuture<'a, Vec<ScoredChunk>>;
+
+    /// # [`DynAsyncRetriever::dyn_retrieve_mmr_with_scores`]
+    ///
+    /// See [`AsyncRetriever::retrieve_mmr_with_scores`].
+    fn dyn_retrieve_mmr_with_scores<'a>(
+        &'a self,
+        text: &'a str,
+        top_k: NonZeroU32,
+        fetch_k: NonZeroU32,
+        lambda: f32,
+    ) -> DynFuture<'a, Vec<ScoredChunk>>;
+
+    /// # [`DynAsyncRetriever::dyn_fetch_by_metadata`]
+    ///
+    /// See [`AsyncRetriever::fetch_by_metadata`].
+    fn dyn_fetch_by_metadata<'a>(&'a self, filter: &'a MetadataFilter) -> DynFuture<'a, Chunks>;
+
+    /// # [`DynAsyncRetriever::dyn_max_top_k`]
+    ///
+    /// See [`AsyncRetriever::max_top_k`].
+    fn dyn_max_top_k(&self) -> Option<NonZeroU32>;
+}
+
+impl<T> DynAsyncRetriever for T
+where
+    T: AsyncRetriever,
+    T::ErrorType: 'static,
+{
+    fn dyn_retrieve<'a>(&'a self, text: &'a str, top_k: NonZeroU32) -> DynFuture<'a, Chunks> {
+        Box::pin(async move {
+            AsyncRetriever::retrieve(self, text, top_k)
+                .await
+                .map_err(DynError::new)
+        })
+    }
+
+    fn dyn_retrieve_with_context<'a>(
+        &'a self,
+        text: &'a str,
+        top_k: NonZeroU32,
+        context: &'a InvocationContext,
+    ) -> DynFuture<'a, Chunks> {
+        Box::pin(async move {
+            AsyncRetriever::retrieve_with_context(self, text, top_k, context)
+                .await
+                .map_err(DynError::new)
+        })
+    }
+
+    fn dyn_retrieve_batch<'a>(
+        &'a self,
+        queries: &'a [&'a str],
+        top_k: NonZeroU32,
+    ) -> BatchFuture<'a> {
+        Box::pin(async move {
+            AsyncRetriever::retrieve_batch(self, queries, top_k)
+                .await
+                .map_err(|error| match error {
+                    BatchRetrieveError::Query { index, error } => BatchRetrieveError::Query {
+                        index,
+                        error: DynError::new(error),
+                    },
+                    BatchRetrieveError::Batch(error) => {
+                        BatchRetrieveError::Batch(DynError::new(error))
+                    }
+                })
+        })
+    }
+
+    fn dyn_retrieve_with_scores<'a>(
+        &'a self,
+        text: &'a str,
+        top_k: NonZeroU32,
+    ) -> DynFuture<'a, Vec<ScoredChunk>> {
+        Box::pin(async move {
+            AsyncRetriever::retrieve_with_scores(self, text, top_k)
+                .await
+                .map_err(DynError::new)
+        })
+    }
+
+    fn dyn_retrieve_with_scores_and_context<'a>(
+        &'a self,
+        text: &'a str,
+        top_k: NonZeroU32,
+        context: &'a InvocationContext,
+    ) -> DynFuture<'a, Vec<ScoredChunk>> {
+        Box::pin(async move {
+            AsyncRetriever::retrieve_with_scores_and_context(self, text, top_k, context)
+                .await
+                .map_err(DynError::new)
+        })
+    }
+
+    fn dyn_retrieve_mmr_with_scores<'a>(
+        &'a self,
+        text: &'a str,
+        top_k: NonZeroU32,
+        fetch_k: NonZeroU32,
+        lambda: f32,
+    ) -> DynFuture<'a, Vec<ScoredChunk>> {
+        Box::pin(async move {
+            AsyncRetriever::retrieve_mmr_with_scores(self, text, top_k, fetch_k, lambda)
+                .await
+                .map_err(DynError::new)
+        })
+    }
+
+    fn dyn_fetch_by_metadata<'a>(&'a self, filter: &'a MetadataFilter) -> DynFuture<'a, Chunks> {
+        Box::pin(async move {
+            AsyncRetriever::fetch_by_metadata(self, filter)
+                .await
+                .map_err(DynError::new)
+        })
+    }
+
+    fn dyn_max_top_k(&self) -> Option<NonZeroU32> {
+        AsyncRetriever::max_top_k(self)
+    }
+}
+
+impl AsyncRetriever for Box<dyn DynAsyncRetriever> {
+    type ErrorType = DynError;
+
+    async fn retrieve(&self, text: &str, top_k: NonZeroU32) -> Result<Chunks, DynError> {
+        DynAsyncRetriever::dyn_retrieve(self.as_ref(), text, top_k).await
+    }
+
+    async fn retrieve_with_context(
+        &self,
+        text: &str,
+        top_k: NonZeroU32,
+        context: &InvocationContext,
+    ) -> Result<Chunks, DynError> {
+        DynAsyncRetriever::dyn_retrieve_with_context(self.as_ref(), text, top_k, context).await
+    }
+
+    async fn retrieve_batch(
+        &self,
+        queries: &[&str],
+        top_k: NonZeroU32,
+    ) -> Result<Vec<Chunks>, BatchRetrieveError<DynError>> {
+        DynAsyncRetriever::dyn_retrieve_batch(self.as_ref(), queries, top_k).await
+    }
+
+    async fn retrieve_with_scores(
+        &self,
+        text: &str,
+        top_k: NonZeroU32,
+    ) -> Result<Vec<ScoredChunk>, DynError> {
+        DynAsyncRetriever::dyn_retrieve_with_scores(self.as_ref(), text, top_k).await
+    }
+
+    async fn retrieve_with_scores_and_context(
+        &self,
+        text: &str,
+        top_k: NonZeroU32,
+        context: &InvocationContext,
+    ) -> Result<Vec<ScoredChunk>, DynError> {
+        DynAsyncRetriever::dyn_retrieve_with_scores_and_context(self.as_ref(), text, top_k, context)
+            .await
+    }
+
+    async fn retrieve_mmr_with_scores(
+        &self,
+        text: &str,
+        top_k: NonZeroU32,
+        fetch_k: NonZeroU32,
+        lambda: f32,
+    ) -> Result<Vec<ScoredChunk>, DynError> {
+        DynAsyncRetriever::dyn_retrieve_mmr_with_scores(self.as_ref(), text, top_k, fetch_k, lambda)
+            .await
+    }
+
+    async fn fetch_by_metadata(&self, filter: &MetadataFilter) -> Result<Chunks, DynError> {
+        DynAsyncRetriever::dyn_fetch_by_metadata(self.as_ref(), filter).await
+    }
+
+    fn max_top_k(&self) -> Option<NonZeroU32> {
+        DynAsyncRetriever::dyn_max_top_k(self.as_ref())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::Chunk;
+    use crate::retrievers::{MockAsyncRetriever, MockFilteredRetriever};
+    use std::io;
+
+    #[tokio::test]
+    async fn retrievers_of_different_types_are_driven_through_the_same_code() {
+        let mut mock = MockAsyncRetriever::new();
+        mock.expect_retrieve()
+            .returning(|_, _| Ok(vec![Chunk::new("mock")]));
+        let mut filtered = MockFilteredRetriever::new();
+        filtered
+            .expect_retrieve()
+            .returning(|_, _| Ok(vec![Chunk::new("filtered")]));
+        let retrievers: Vec<Box<dyn DynAsyncRetriever>> = vec![Box::new(mock), Box::new(filtered)];
+
+        let mut contents: Vec<String> = Vec::new();
+        for retriever in &retrievers {
+            let batch: Vec<Chunks> = retriever
+                .retrieve_batch(&["query"], NonZeroU32::new(1).unwrap())
+                .await
+                .unwrap();
+            contents.push(batch[0][0].content().into());
+        }
+        assert_eq!(contents, vec!["mock", "filtered"]);
+    }
+
+    #[tokio::test]
+    async fn errors_keep_the_error_of_the_retriever() {
+        let mut mock = MockAsyncRetriever::new();
+        mock.expect_retrieve()
+            .returning(|_, _| Err(io::Error::new(io::ErrorKind::TimedOut, "slow")));
+        let retriever: Box<dyn DynAsyncRetriever> = Box::new(mock);
+
+        let error = AsyncRetriever::retrieve_batch(&retriever, &["a"], NonZeroU32::new(1).unwrap())
+            .await
+            .unwrap_err();
+        assert_eq!(error.index(), Some(0));
+        let error: DynError = error.into_inner();
+        assert_eq!(error.to_string(), "slow");
+        assert_eq!(
+            error.downcast_ref::<io::Error>().unwrap().kind(),
+            io::ErrorKind::TimedOut
+        );
+    }
+}
@@ -0,0 +1,164 @@
+use serde_json::Value;
+
+/// # [`RetrieveExplanation`]
+///
+/// The result of [`crate::retrievers::PostgresVectorRetriever::explain_retrieve`].
+/// This is a diagnostic for working out why a search is slow or returns odd results,
+/// it is never produced by the normal retrieve methods.
+///
+/// * `query` - the text that was searched for.
+/// * `rewritten_query` - the text that was embedded after the retriever's
+///   [`crate::retrievers::QueryRewriter`] was applied, the same as `query` if there is none.
+/// * `sql` - the similarity search that was run, the query vector and top_k are left as
+///   the `$1` and `$2` placeholders rather than being written out.
+/// * `distance_operator` - the pgvector operator the results were ordered by e.g. `<=>`.
+/// * `summary` - the parts of the query plan most useful when debugging a search.
+/// * `plan` - the full output of `EXPLAIN (ANALYZE, FORMAT JSON)`.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RetrieveExplanation {
+    pub query: String,
+    pub rewritten_query: String,
+    pub sql: String,
+    pub distance_operator: String,
+    pub summary: QueryPlanSummary,
+    pub plan: Value,
+}
+
+/// # [`QueryPlanSummary`]
+///
+/// A small typed summary of the plan postgres used for a similarity search.
+///
+/// * `index_used` - whether any index was scanned.
+/// * `index_name` - the name of the first index scanned, if there was one.
+/// * `sequential_scan` - whether any table was read with a sequential scan.
+/// * `rows_scanned` - the number of rows read by the scans, including those removed by a filter.
+/// * `execution_ms` - the time postgres took to execute the query in milliseconds.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct QueryPlanSummary {
+    pub index_used: bool,
+    pub index_name: Option<String>,
+    pub sequential_scan: bool,
+    pub rows_scanned: u64,
+    pub execution_ms: f64,
+}
+
+impl QueryPlanSummary {
+    /// # [`QueryPlanSummary::from_explain`]
+    ///
+    /// # Arguments
+    /// * `explain`: &[`Value`] - the output of `EXPLAIN (ANALYZE, FORMAT JSON)`.
+    ///
+    /// # Returns
+    /// * [`Option<QueryPlanSummary>`] - the summary, or `None` if the output is not the shape
+    ///   postgres produces.
+    pub(crate) fn from_explain(explain: &Value) -> Option<Self> {
+        let root: &Value = explain.get(0)?;
+        let mut summary = QueryPlanSummary {
+            index_used: false,
+            index_name: None,
+            sequential_scan: false,
+            rows_scanned: 0,
+            execution_ms: root.get("Execution Time")?.as_f64()?,
+        };
+        summary.visit(root.get("Plan")?);
+        Some(summary)
+    }
+
+    fn visit(&mut self, node: &Value) {
+        let node_type: &str = node
+            .get("Node Type")
+            .and_then(Value::as_str)
+            .unwrap_or_default();
+        if node_type == "Seq Scan" {
+            self.sequential_scan = true;
+        }
+        if let Some(index_name) = node.get("Index Name").and_then(Value::as_str) {
+            self.index_used = true;
+            self.index_name.get_or_insert_with(|| index_name.into());
+        }
+        if node_type.ends_with("Scan") {
+            let count = |key: &str| node.get(key).and_then(Value::as_f64).unwrap_or(0.0);
+            let rows: f64 = count("Actual Rows") + count("Rows Removed by Filter");
+            // Actual Rows is an average per loop
+            self.rows_scanned += (rows * count("Actual Loops").max(1.0)).round() as u64;
+        }
+        if let Some(children) = node.get("Plans").and_then(Value::as_array) {
+            children.iter().for_each(|child| self.visit(child));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn summarises_sequential_scan() {
+        let explain = json!([{
+            "Plan": {
+                "Node Type": "Limit",
+                "Actual Rows": 2,
+                "Actual Loops": 1,
+                "Plans": [{
+                    "Node Type": "Sort",
+                    "Actual Rows": 2,
+                    "Actual Loops": 1,
+                    "Plans": [{
+                        "Node Type": "Seq Scan",
+                        "Relation Name": "embeddings",
+                        "Actual Rows": 3,
+                        "Actual Loops": 1,
+                        "Rows Removed by Filter": 7
+                    }]
+                }]
+            },
+            "Planning Time": 0.1,
+            "Execution Time": 0.25
+        }]);
+        assert_eq!(
+            QueryPlanSummary::from_explain(&explain),
+            Some(QueryPlanSummary {
+                index_used: false,
+                index_name: None,
+                sequential_scan: true,
+                rows_scanned: 10,
+                execution_ms: 0.25,
+            })
+        );
+    }
+
+    #[test]
+    fn summarises_index_scan() {
+        let explain = json!([{
+            "Plan": {
+                "Node Type": "Limit",
+                "Actual Rows": 5,
+                "Actual Loops": 1,
+                "Plans": [{
+                    "Node Type": "Index Scan",
+                    "Index Name": "embeddings_embedding_idx",
+                    "Actual Rows": 5,
+                    "Actual Loops": 1
+                }]
+            },
+            "Execution Time": 1.5
+        }]);
+        let summary = QueryPlanSummary::from_explain(&explain).unwrap();
+        assert!(summary.index_used);
+        assert!(!summary.sequential_scan);
+        assert_eq!(
+            summary.index_name.as_deref(),
+            Some("embeddings_embedding_idx")
+        );
+        assert_eq!(summary.rows_scanned, 5);
+    }
+
+    #[test]
+    fn rejects_unexpected_output() {
+        assert_eq!(QueryPlanSummary::from_explain(&json!({"Plan": {}})), None);
+        assert_eq!(QueryPlanSummary::from_explain(&json!([{"Plan": {}}])), None);
+    }
+}
@@ -0,0 +1,122 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+
+/// The constant added to each rank by reciprocal rank fusion unless configured otherwise
+pub const DEFAULT_RRF_K: u32 = 60;
+
+/// # [`HybridWeights`]
+///
+/// How [`crate::retrievers::PostgresVectorRetriever::retrieve_hybrid`] fuses the vector and
+/// full text rankings with reciprocal rank fusion. A chunk ranked r (starting from 1) by a
+/// search scores `weight / (rrf_k + r)` from it, and the chunks are ordered by their summed
+/// score. Only the ranks are used so the two searches do not need comparable scores.
+///
+/// * `vector` - the weight given to the vector similarity ranking.
+/// * `full_text` - the weight given to the full text ranking.
+/// * `rrf_k` - smooths the ranks, a higher value gives the lower ranks relatively more weight.
+///   Defaults to [`DEFAULT_RRF_K`].
+///
+/// # Examples
+/// ```
+/// use rag_toolchain::retrievers::HybridWeights;
+///
+/// // Favour exact keyword matches
+/// let weights = HybridWeights {
+///     full_text: 2.0,
+///     ..HybridWeights::default()
+/// };
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct HybridWeights {
+    pub vector: f32,
+    pub full_text: f32,
+    pub rrf_k: u32,
+}
+
+impl Default for HybridWeights {
+    fn default() -> Self {
+        HybridWeights {
+            vector: 1.0,
+            full_text: 1.0,
+            rrf_k: DEFAULT_RRF_K,
+        }
+    }
+}
+
+/// # [`reciprocal_rank_fusion`]
+///
+/// Fuses rankings of the same items into one, each item scores the sum of
+/// `weight / (rrf_k + rank)` over the rankings it appears in. Ties keep the order the
+/// items were first seen in, going through the rankings in turn.
+///
+/// # Arguments
+/// * `rankings`: &[`[(Vec<K>, f32)]`] - each ranking best first along with its weight.
+/// * `rrf_k`: [`u32`] - the constant added to each rank.
+///
+/// # Returns
+/// * [`Vec<(K, f32)>`] - every item with its fused score, highest first.
+pub(crate) fn reciprocal_rank_fusion<K: Eq + Hash + Clone>(
+    rankings: &[(Vec<K>, f32)],
+    rrf_k: u32,
+) -> Vec<(K, f32)> {
+    let mut fused: Vec<(K, f32)> = Vec::new();
+    let mut positions: HashMap<K, usize> = HashMap::new();
+    for (ranking, weight) in rankings {
+        for (index, item) in ranking.iter().enumerate() {
+            let score: f32 = weight / (rrf_k as f32 + index as f32 + 1.0);
+            match positions.get(item) {
+                Some(position) => fused[*position].1 += score,
+                None => {
+                    positions.insert(item.clone(), fused.len());
+                    fused.push((item.clone(), score));
+                }
+            }
+        }
+    }
+    // A stable sort so ties keep the order they were first seen in
+    fused.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+    fused
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ids(fused: &[(u32, f32)]) -> Vec<u32> {
+        fused.iter().map(|(id, _)| *id).collect()
+    }
+
+    #[test]
+    fn items_in_both_rankings_rise_to_the_top() {
+        let vector = vec![1, 2, 3];
+        let full_text = vec![4, 3];
+        let fused = reciprocal_rank_fusion(&[(vector, 1.0), (full_text, 1.0)], 60);
+        assert_eq!(ids(&fused), vec![3, 1, 4, 2]);
+        assert_eq!(fused[0].1, 1.0 / 63.0 + 1.0 / 62.0);
+        assert_eq!(fused[1].1, 1.0 / 61.0);
+    }
+
+    #[test]
+    fn weights_decide_between_the_top_of_each_ranking() {
+        let rankings =
+            |vector: f32, full_text: f32| [(vec![1, 2], vector), (vec![3, 4], full_text)];
+        assert_eq!(
+            ids(&reciprocal_rank_fusion(&rankings(1.0, 1.0), 60)),
+            vec![1, 3, 2, 4]
+        );
+        assert_eq!(
+            ids(&reciprocal_rank_fusion(&rankings(1.0, 2.0), 60)),
+            vec![3, 4, 1, 2]
+        );
+        assert_eq!(
+            ids(&reciprocal_rank_fusion(&rankings(0.0, 1.0), 60)),
+            vec![3, 4, 1, 2]
+        );
+    }
+
+    #[test]
+    fn empty_rankings_fuse_to_nothing() {
+        assert!(reciprocal_rank_fusion::<u32>(&[(Vec::new(), 1.0)], 60).is_empty());
+    }
+}
@@ -0,0 +1,595 @@
+use crate::clients::{AsyncEmbeddingClient, EmbeddingTaskType};
+use crate::common::{Chunk, Chunks, Embedding, ScoredChunk};
+use crate::retrievers::distance_function::DistanceFunction;
+use crate::retrievers::metadata_filter::MetadataFilter;
+use crate::retrievers::mmr::select_mmr;
+use crate::retrievers::traits::{AsyncRetriever, FilteredRetriever};
+use std::error::Error;
+use std::num::NonZeroU32;
+use std::sync::{Arc, PoisonError, RwLock};
+use thiserror::Error;
+
+/// # [`InMemoryRetriever`]
+///
+/// This struct allows for the retrieval of similar text from an
+/// [`crate::stores::InMemoryVectorStore`], create one with .as_retriever() on the store.
+/// It is parameterized over a type T which implements the AsyncEmbeddingClient trait
+/// as text needs to be embedded before it can be compared.
+///
+/// Every search is an exact scan of the stored embeddings computed in Rust. Metadata filters
+/// are evaluated with [`MetadataFilter::matches`] and arbitrary conditions on the chunks can be
+/// given as a closure with [`InMemoryRetriever::retrieve_with_predicate`].
+///
+/// # Examples
+/// ```
+/// use rag_toolchain::retrievers::*;
+/// use rag_toolchain::clients::*;
+/// use rag_toolchain::common::*;
+/// use rag_toolchain::stores::*;
+/// use std::num::NonZeroU32;
+///
+/// async fn retrieve(store: InMemoryVectorStore) {
+///     let top_k: NonZeroU32 = NonZeroU32::new(5).unwrap();
+///     let embedding_model: OpenAIEmbeddingModel = OpenAIEmbeddingModel::TextEmbedding3Small;
+///     let client: OpenAIEmbeddingClient = OpenAIEmbeddingClient::try_new(embedding_model).unwrap();
+///     let retriever: InMemoryRetriever<OpenAIEmbeddingClient> = store.as_retriever(client, DistanceFunction::Cosine);
+///     // This will return the top 5 most similar chunks to the input text.
+///     let similar_text: Chunks = retriever.retrieve("text to search for", top_k).await.unwrap();
+/// }
+/// ```
+pub struct InMemoryRetriever<T: AsyncEmbeddingClient> {
+    embeddings: Arc<RwLock<Vec<Embedding>>>,
+    dimensions: usize,
+    embedding_client: T,
+    distance_function: DistanceFunction,
+}
+
+impl<T: AsyncEmbeddingClient> InMemoryRetriever<T> {
+    /// # [`InMemoryRetriever::new`]
+    /// This constructor is only used internally to allow .as_retriever methods to create a retriever.
+    ///
+    /// # Arguments
+    /// * `embeddings`: [`Arc<RwLock<Vec<Embedding>>>`] - The embeddings shared with the store.
+    /// * `dimensions`: [`usize`] - The dimension of the stored vectors.
+    /// * `embedding_client`: [`T`] - An instance of a type which implements the AsyncEmbeddingClient trait.
+    /// * `distance_function`: [`DistanceFunction`] - The distance function to search with.
+    ///
+    /// # Returns
+    /// * [`InMemoryRetriever`] the created struct
+    pub(crate) fn new(
+        embeddings: Arc<RwLock<Vec<Embedding>>>,
+        dimensions: usize,
+        embedding_client: T,
+        distance_function: DistanceFunction,
+    ) -> Self {
+        InMemoryRetriever {
+            embeddings,
+            dimensions,
+            embedding_client,
+            distance_function,
+        }
+    }
+
+    /// # [`InMemoryRetriever::distance_function`]
+    ///
+    /// Getter for the distance function the retriever searches with.
+    ///
+    /// # Returns
+    /// * &[`DistanceFunction`] - The distance function
+    pub fn distance_function(&self) -> &DistanceFunction {
+        &self.distance_function
+    }
+
+    /// # [`InMemoryRetriever::retrieve_with_predicate`]
+    ///
+    /// The same as [`AsyncRetriever::retrieve`] but only chunks the predicate returns true for
+    /// are searched. This is for conditions a [`MetadataFilter`] cannot express.
+    ///
+    /// # Arguments
+    /// * `text`: &[`str`] - The text we are searching for similar text against.
+    /// * `top_k`: [`NonZeroU32`] - The number of results to return.
+    /// * `predicate`: impl [`Fn(&Chunk) -> bool`] - Whether a chunk can be returned.
+    ///
+    /// # Errors
+    /// * [`InMemoryRetrieverError::EmbeddingClientError`] - If the embedding client returns an error.
+    /// * [`InMemoryRetrieverError::DimensionMismatch`] - If the text is embedded with a different dimension to the store.
+    ///
+    /// # Returns
+    /// * [`Chunks`] which are the most similar to the input text and match the predicate.
+    pub async fn retrieve_with_predicate(
+        &self,
+        text: &str,
+        top_k: NonZeroU32,
+        predicate: impl Fn(&Chunk) -> bool + Send + Sync,
+    ) -> Result<Chunks, InMemoryRetrieverError<T::ErrorType>> {
+        Ok(into_chunks(self.search(text, top_k, &predicate).await?))
+    }
+
+    /// # [`InMemoryRetriever::retrieve_with_predicate_and_scores`]
+    ///
+    /// The same as [`InMemoryRetriever::retrieve_with_predicate`] but each chunk comes with a
+    /// score, see [`AsyncRetriever::retrieve_with_scores`].
+    ///
+    /// # Arguments
+    /// * `text`: &[`str`] - The text we are searching for similar text against.
+    /// * `top_k`: [`NonZeroU32`] - The number of results to return.
+    /// * `predicate`: impl [`Fn(&Chunk) -> bool`] - Whether a chunk can be returned.
+    ///
+    /// # Errors
+    /// * [`InMemoryRetrieverError::EmbeddingClientError`] - If the embedding client returns an error.
+    /// * [`InMemoryRetrieverError::DimensionMismatch`] - If the text is embedded with a different dimension to the store.
+    ///
+    /// # Returns
+    /// * [`Vec<ScoredChunk>`] which are the most similar to the input text and match the predicate.
+    pub async fn retrieve_with_predicate_and_scores(
+        &self,
+        text: &str,
+        top_k: NonZeroU32,
+        predicate: impl Fn(&Chunk) -> bool + Send + Sync,
+    ) -> Result<Vec<ScoredChunk>, InMemoryRetrieverError<T::ErrorType>> {
+        self.search(text, top_k, &predicate).await
+    }
+
+    /// # [`InMemoryRetriever::search`]
+    ///
+    /// Embeds the text and ranks the stored embeddings matching the predicate by their
+    /// distance to it, shared by all the retrieve methods. The distance of each embedding
+    /// is turned into a score with [`DistanceFunction::to_score`].
+    ///
+    /// # Arguments
+    /// * `text`: &[`str`] - The text we are searching for similar text against.
+    /// * `top_k`: [`NonZeroU32`] - The number of results to return.
+    /// * `predicate`: &dyn [`Fn(&Chunk) -> bool`] - Whether a chunk can be returned.
+    async fn search(
+        &self,
+        text: &str,
+        top_k: NonZeroU32,
+        predicate: &(dyn Fn(&Chunk) -> bool + Send + Sync),
+    ) -> Result<Vec<ScoredChunk>, InMemoryRetrieverError<T::ErrorType>> {
+        self.search_with(text, top_k, predicate, |distance, embedding| {
+            let score: f32 = self.distance_function.to_score(distance);
+            ScoredChunk::new(embedding.chunk().clone(), score)
+        })
+        .await
+    }
+
+    /// # [`InMemoryRetriever::search_with`]
+    ///
+    /// The same as [`InMemoryRetriever::search`] but each of the top_k embeddings is mapped
+    /// with its distance to the text, so callers can keep the vector as well as the chunk.
+    ///
+    /// # Arguments
+    /// * See [`InMemoryRetriever::search`]
+    /// * `map`: impl [`Fn(f64, &Embedding) -> R`] - Turns each embedding and its distance into a result.
+    async fn search_with<R>(
+        &self,
+        text: &str,
+        top_k: NonZeroU32,
+        predicate: &(dyn Fn(&Chunk) -> bool + Send + Sync),
+        map: impl Fn(f64, &Embedding) -> R + Send,
+    ) -> Result<Vec<R>, InMemoryRetrieverError<T::ErrorType>> {
+        let query: Embedding = self
+            .embedding_client
+            .generate_embedding_for(Chunk::new(text), EmbeddingTaskType::Query)
+            .await
+            .map_err(InMemoryRetrieverError::EmbeddingClientError)?;
+        let query: &[f32] = query.vector_slice();
+        if query.len() != self.dimensions {
+            return Err(InMemoryRetrieverError::DimensionMismatch {
+                expected: self.dimensions,
+                found: query.len(),
+            });
+        }
+
+        let embeddings = self
+            .embeddings
+            .read()
+            .unwrap_or_else(PoisonError::into_inner);
+        let mut ranked: Vec<(f64, &Embedding)> = embeddings
+            .iter()
+            .filter(|embedding| predicate(embedding.chunk()))
+            .map(|embedding| {
+                let distance: f64 = self
+                    .distance_function
+                    .distance(query, embedding.vector_slice());
+                (distance, embedding)
+            })
+            .collect();
+        ranked.sort_by(|(a, _), (b, _)| a.total_cmp(b));
+        Ok(ranked
+            .into_iter()
+            .take(top_k.get() as usize)
+            .map(|(distance, embedding)| map(distance, embedding))
+            .collect())
+    }
+}
+
+impl<T> AsyncRetriever for InMemoryRetriever<T>
+where
+    T: AsyncEmbeddingClient,
+    T::ErrorType: 'static,
+{
+    // We parameterize over the error type of the embedding client.
+    type ErrorType = InMemoryRetrieverError<T::ErrorType>;
+
+    /// # [`InMemoryRetriever::retrieve`]
+    ///
+    /// Implementation of the retrieve function for [`InMemoryRetriever`].
+    ///
+    /// # Arguments
+    /// * `text`: &[`str`] - The text we are searching for similar text against.
+    /// * `top_k`: [`NonZeroU32`] - The number of results to return.
+    ///
+    /// # Errors
+    /// * [`InMemoryRetrieverError::EmbeddingClientError`] - If the embedding client returns an error.
+    /// * [`InMemoryRetrieverError::DimensionMismatch`] - If the text is embedded with a different dimension to the store.
+    ///
+    /// # Returns
+    /// * [`Chunks`] which are the most similar to the input text.
+    async fn retrieve(&self, text: &str, top_k: NonZeroU32) -> Result<Chunks, Self::ErrorType> {
+        Ok(into_chunks(self.search(text, top_k, &|_| true).await?))
+    }
+
+    /// # [`InMemoryRetriever::retrieve_with_scores`]
+    ///
+    /// The same as [`InMemoryRetriever::retrieve`] but each chunk comes with a score
+    /// of how similar it is to the input text, a higher score is more similar.
+    ///
+    /// # Arguments
+    /// * `text`: &[`str`] - The text we are searching for similar text against.
+    /// * `top_k`: [`NonZeroU32`] - The number of results to return.
+    ///
+    /// # Errors
+    /// * [`InMemoryRetrieverError::EmbeddingClientError`] - If the embedding client returns an error.
+    /// * [`InMemoryRetrieverError::DimensionMismatch`] - If the text is embedded with a different dimension to the store.
+    ///
+    /// # Returns
+    /// * [`Vec<ScoredChunk>`] which are the most similar to the input text, most similar first.
+    async fn retrieve_with_scores(
+        &self,
+        text: &str,
+        top_k: NonZeroU32,
+    ) -> Result<Vec<ScoredChunk>, Self::ErrorType> {
+        self.search(text, top_k, &|_| true).await
+    }
+
+    /// # [`InMemoryRetriever::retrieve_mmr_with_scores`]
+    ///
+    /// Ranks the stored embeddings and picks top_k of the fetch_k most similar by maximal
+    /// marginal relevance, see [`AsyncRetriever::retrieve_mmr`]. The similarity between
+    /// chunks is measured with the retrievers distance function.
+    ///
+    /// # Arguments
+    /// * `text`: &[`str`] - The text we are searching for similar text against.
+    /// * `top_k`: [`NonZeroU32`] - The number of results to return.
+    /// * `fetch_k`: [`NonZeroU32`] - The number of chunks to pick from, at least top_k are fetched.
+    /// * `lambda`: [`f32`] - From 0 to 1, the weight given to similarity to the text over diversity.
+    ///
+    /// # Errors
+    /// * [`InMemoryRetrieverError::EmbeddingClientError`] - If the embedding client returns an error.
+    /// * [`InMemoryRetrieverError::DimensionMismatch`] - If the text is embedded with a different dimension to the store.
+    ///
+    /// # Returns
+    /// * [`Vec<ScoredChunk>`] in the order they were picked.
+    async fn retrieve_mmr_with_scores(
+        &self,
+        text: &str,
+        top_k: NonZeroU32,
+        fetch_k: NonZeroU32,
+        lambda: f32,
+    ) -> Result<Vec<ScoredChunk>, Self::ErrorType> {
+        let candidates: Vec<(ScoredChunk, Vec<f32>)> = self
+            .search_with(
+                text,
+                fetch_k.max(top_k),
+                &|_| true,
+                |distance, embedding| {
+                    let score: f32 = self.distance_function.to_score(distance);
+                    let scored: ScoredChunk = ScoredChunk::new(embedding.chunk().clone(), score);
+                    (scored, embedding.vector_slice().to_vec())
+                },
+            )
+            .await?;
+        Ok(select_mmr(
+            candidates,
+            &self.distance_function,
+            top_k.get() as usize,
+            lambda,
+        ))
+    }
+
+    /// # [`InMemoryRetriever::fetch_by_metadata`]
+    ///
+    /// Scans the stored chunks for those whose metadata matches the filter, see
+    /// [`MetadataFilter::matches`]. Nothing is embedded.
+    ///
+    /// # Arguments
+    /// * `filter`: &[`MetadataFilter`] - The filter the metadata of each chunk must match.
+    ///
+    /// # Returns
+    /// * [`Chunks`] which match the filter, in the order they were stored.
+    async fn fetch_by_metadata(&self, filter: &MetadataFilter) -> Result<Chunks, Self::ErrorType> {
+        let embeddings = self
+            .embeddings
+            .read()
+            .unwrap_or_else(PoisonError::into_inner);
+        Ok(embeddings
+            .iter()
+            .map(|embedding| embedding.chunk())
+            .filter(|chunk| filter.matches(chunk.metadata()))
+            .cloned()
+            .collect())
+    }
+}
+
+impl<T> FilteredRetriever for InMemoryRetriever<T>
+where
+    T: AsyncEmbeddingClient,
+    T::ErrorType: 'static,
+{
+    /// # [`InMemoryRetriever::retrieve_with_filter`]
+    ///
+    /// The same as [`InMemoryRetriever::retrieve`] but only chunks whose metadata matches the
+    /// filter are searched, see [`MetadataFilter::matches`].
+    ///
+    /// # Arguments
+    /// * `text`: &[`str`] - The text we are searching for similar text against.
+    /// * `top_k`: [`NonZeroU32`] - The number of results to return.
+    /// * `filter`: &[`MetadataFilter`] - The filter the metadata of each result must match.
+    ///
+    /// # Errors
+    /// * [`InMemoryRetrieverError::EmbeddingClientError`] - If the embedding client returns an error.
+    /// * [`InMemoryRetrieverError::DimensionMismatch`] - If the text is embedded with a different dimension to the store.
+    ///
+    /// # Returns
+    /// * [`Chunks`] which are the most similar to the input text and match the filter.
+    async fn retrieve_with_filter(
+        &self,
+        text: &str,
+        top_k: NonZeroU32,
+        filter: &MetadataFilter,
+    ) -> Result<Chunks, Self::ErrorType> {
+        self.retrieve_with_predicate(text, top_k, |chunk| filter.matches(chunk.metadata()))
+            .await
+    }
+
+    /// # [`InMemoryRetriever::retrieve_with_filter_and_scores`]
+    ///
+    /// The same as [`InMemoryRetriever::retrieve_with_filter`] but each chunk comes with a score.
+    ///
+    /// # Arguments
+    /// * `text`: &[`str`] - The text we are searching for similar text against.
+    /// * `top_k`: [`NonZeroU32`] - The number of results to return.
+    /// * `filter`: &[`MetadataFilter`] - The filter the metadata of each result must match.
+    ///
+    /// # Errors
+    /// * [`InMemoryRetrieverError::EmbeddingClientError`] - If the embedding client returns an error.
+    /// * [`InMemoryRetrieverError::DimensionMismatch`] - If the text is embedded with a different dimension to the store.
+    ///
+    /// # Returns
+    /// * [`Vec<ScoredChunk>`] which are the most similar to the input text and match the filter.
+    async fn retrieve_with_filter_and_scores(
+        &self,
+        text: &str,
+        top_k: NonZeroU32,
+        filter: &MetadataFilter,
+    ) -> Result<Vec<ScoredChunk>, Self::ErrorType> {
+        self.retrieve_with_predicate_and_scores(text, top_k, |chunk| {
+            filter.matches(chunk.metadata())
+        })
+        .await
+    }
+}
+
+fn into_chunks(scored: Vec<ScoredChunk>) -> Chunks {
+    scored.into_iter().map(|scored| scored.chunk).collect()
+}
+
+#[derive(Error, Debug)]
+pub enum InMemoryRetrieverError<T: Error> {
+    #[error("Embedding Client Error: {0}")]
+    EmbeddingClientError(T),
+    #[error("Dimension Mismatch: the store expects {expected} but the embedding client generates {found}")]
+    DimensionMismatch { expected: usize, found: usize },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::OpenAIEmbeddingModel::TextEmbeddingAda002;
+    use crate::retrievers::lookup_embedding_client::LookupEmbeddingClient;
+    use crate::stores::{EmbeddingStore, InMemoryVectorStore};
+    use serde_json::json;
+
+    const DIMENSIONS: usize = 1536;
+
+    // A vector of the given length pointing along the first two axes
+    fn vector(x: f32, y: f32) -> Vec<f32> {
+        let mut vector: Vec<f32> = vec![0.0; DIMENSIONS];
+        vector[0] = x;
+        vector[1] = y;
+        vector
+    }
+
+    async fn store() -> InMemoryVectorStore {
+        let store = InMemoryVectorStore::new(TextEmbeddingAda002);
+        store
+            .store_batch(vec![
+                Embedding::new(
+                    Chunk::new_with_metadata("east", json!({"lang": "en", "year": 2021})),
+                    vector(1.0, 0.0),
+                ),
+                Embedding::new(
+                    Chunk::new_with_metadata("north east", json!({"lang": "de", "year": 2024})),
+                    vector(1.0, 1.0),
+                ),
+                Embedding::new(Chunk::new("north"), vector(0.0, 2.0)),
+            ])
+            .await
+            .unwrap();
+        store
+    }
+
+    fn client() -> LookupEmbeddingClient {
+        LookupEmbeddingClient(vec![("east", vector(2.0, 0.0)), ("short", vec![1.0, 0.0])])
+    }
+
+    fn contents(scored: &[ScoredChunk]) -> Vec<&str> {
+        scored.iter().map(|scored| scored.chunk.content()).collect()
+    }
+
+    #[tokio::test]
+    async fn each_distance_function_ranks_most_similar_first() {
+        let store = store().await;
+        let top_k = NonZeroU32::new(3).unwrap();
+
+        let cosine = store.as_retriever(client(), DistanceFunction::Cosine);
+        let scored = cosine.retrieve_with_scores("east", top_k).await.unwrap();
+        assert_eq!(contents(&scored), vec!["east", "north east", "north"]);
+        assert!((scored[0].score - 1.0).abs() < 1e-6);
+        assert!(scored[2].score.abs() < 1e-6);
+
+        let l2 = store.as_retriever(client(), DistanceFunction::L2);
+        let scored = l2.retrieve_with_scores("east", top_k).await.unwrap();
+        assert_eq!(contents(&scored), vec!["east", "north east", "north"]);
+        assert!((scored[0].score - 0.5).abs() < 1e-6);
+
+        // east and north east tie on the inner product so they keep their stored order
+        let inner_product = store.as_retriever(client(), DistanceFunction::InnerProduct);
+        let scored = inner_product
+            .retrieve_with_scores("east", top_k)
+            .await
+            .unwrap();
+        assert_eq!(scored[0].score, 2.0);
+        assert_eq!(scored[2].score, 0.0);
+        assert_eq!(contents(&scored)[2], "north");
+    }
+
+    #[tokio::test]
+    async fn top_k_limits_the_results_and_sees_new_embeddings() {
+        let store = store().await;
+        let retriever = store.as_retriever(client(), DistanceFunction::L2);
+        let chunks = retriever
+            .retrieve("east", NonZeroU32::new(1).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(
+            chunks,
+            vec![Chunk::new_with_metadata(
+                "east",
+                json!({"lang": "en", "year": 2021})
+            )]
+        );
+
+        store
+            .store(Embedding::new(Chunk::new("exact"), vector(2.0, 0.0)))
+            .await
+            .unwrap();
+        let chunks = retriever
+            .retrieve("east", NonZeroU32::new(1).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(chunks[0].content(), "exact");
+    }
+
+    #[tokio::test]
+    async fn filters_and_predicates_restrict_the_search() {
+        let retriever = store()
+            .await
+            .as_retriever(client(), DistanceFunction::Cosine);
+        let top_k = NonZeroU32::new(3).unwrap();
+
+        let filter = MetadataFilter::key("year").gte(2022);
+        let chunks = retriever
+            .retrieve_with_filter("east", top_k, &filter)
+            .await
+            .unwrap();
+        assert_eq!(chunks.len(), 1);
+        assert_eq!(chunks[0].content(), "north east");
+
+        // Chunks without the key never match a comparison on it
+        let filter = MetadataFilter::key("lang").ne("de");
+        let scored = retriever
+            .retrieve_with_filter_and_scores("east", top_k, &filter)
+            .await
+            .unwrap();
+        assert_eq!(contents(&scored), vec!["east"]);
+
+        let scored = retriever
+            .retrieve_with_predicate_and_scores("east", top_k, |chunk| {
+                chunk.content().starts_with("north")
+            })
+            .await
+            .unwrap();
+        assert_eq!(contents(&scored), vec!["north east", "north"]);
+    }
+
+    #[tokio::test]
+    async fn mmr_skips_near_duplicates() {
+        let store = InMemoryVectorStore::new(TextEmbeddingAda002);
+        store
+            .store_batch(vec![
+                Embedding::new(Chunk::new("east"), vector(1.0, 0.05)),
+                Embedding::new(Chunk::new("east again"), vector(1.0, 0.1)),
+                Embedding::new(Chunk::new("south east"), vector(0.6, -0.8)),
+                Embedding::new(Chunk::new("north"), vector(0.0, 1.0)),
+            ])
+            .await
+            .unwrap();
+        let retriever = store.as_retriever(client(), DistanceFunction::Cosine);
+        let top_k = NonZeroU32::new(2).unwrap();
+        let fetch_k = NonZeroU32::new(3).unwrap();
+
+        let chunks = retriever.retrieve("east", top_k).await.unwrap();
+        assert_eq!(chunks, vec![Chunk::new("east"), Chunk::new("east again")]);
+
+        let chunks = retriever
+            .retrieve_mmr("east", top_k, fetch_k, 0.5)
+            .await
+            .unwrap();
+        assert_eq!(chunks, vec![Chunk::new("east"), Chunk::new("south east")]);
+
+        let chunks = retriever
+            .retrieve_mmr("east", top_k, fetch_k, 1.0)
+            .await
+            .unwrap();
+        assert_eq!(chunks, vec![Chunk::new("east"), Chunk::new("east again")]);
+    }
+
+    #[tokio::test]
+    async fn fetch_by_metadata_returns_matching_chunks_without_embedding() {
+        // The client cannot embed this text so the fetch must not embed anything
+        let retriever = store()
+            .await
+            .as_retriever(LookupEmbeddingClient(Vec::new()), DistanceFunction::Cosine);
+        let chunks = retriever
+            .fetch_by_metadata(&MetadataFilter::key("year").gte(2021))
+            .await
+            .unwrap();
+        let contents: Vec<&str> = chunks.iter().map(|chunk| chunk.content()).collect();
+        assert_eq!(contents, vec!["east", "north east"]);
+        let chunks = retriever
+            .fetch_by_metadata(&MetadataFilter::key("lang").eq("fr"))
+            .await
+            .unwrap();
+        assert!(chunks.is_empty());
+    }
+
+    #[tokio::test]
+    async fn query_with_wrong_dimension_is_rejected() {
+        let retriever = store()
+            .await
+            .as_retriever(client(), DistanceFunction::Cosine);
+        let error = retriever
+            .retrieve("short", NonZeroU32::new(1).unwrap())
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            error,
+            InMemoryRetrieverError::DimensionMismatch {
+                expected: 1536,
+                found: 2
+            }
+        ));
+    }
+}
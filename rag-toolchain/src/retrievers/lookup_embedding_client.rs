@@ -0,0 +1,35 @@
+use crate::clients::{AsyncEmbeddingClient, EmbeddingTaskType};
+use crate::common::{Chunk, Chunks, Embedding};
+
+/// # [`LookupEmbeddingClient`]
+/// Embeds text by looking it up in a fixed list of vectors, shared by the retriever tests
+/// which search a real store. Only queries can be embedded for a task so every search
+/// checks the query is embedded as one.
+pub(crate) struct LookupEmbeddingClient(pub Vec<(&'static str, Vec<f32>)>);
+
+impl AsyncEmbeddingClient for LookupEmbeddingClient {
+    type ErrorType = std::io::Error;
+
+    async fn generate_embedding_for(
+        &self,
+        text: Chunk,
+        task: EmbeddingTaskType,
+    ) -> Result<Embedding, Self::ErrorType> {
+        if task != EmbeddingTaskType::Query {
+            return Err(std::io::Error::other("not embedded as a query"));
+        }
+        self.generate_embedding(text).await
+    }
+
+    async fn generate_embedding(&self, text: Chunk) -> Result<Embedding, Self::ErrorType> {
+        self.0
+            .iter()
+            .find(|(key, _)| *key == text.content())
+            .map(|(_, vector)| Embedding::new(text.clone(), vector.clone()))
+            .ok_or_else(|| std::io::Error::other("unknown text"))
+    }
+
+    async fn generate_embeddings(&self, _text: Chunks) -> Result<Vec<Embedding>, Self::ErrorType> {
+        unimplemented!()
+    }
+}
@@ -0,0 +1,504 @@
+use serde_json::Value;
+
+/// # [`MetadataFilter`]
+///
+/// A filter on the metadata of the stored chunks, applied before the similarity search
+/// so only matching rows are ranked. Build one with [`MetadataFilter::key`] and combine
+/// filters with [`MetadataFilter::and`], [`MetadataFilter::or`] and [`MetadataFilter::not`].
+///
+/// # Missing keys
+/// A comparison on a key the metadata does not have is unknown rather than false, and
+/// an unknown comparison never matches. This means a row without a `lang` key matches
+/// neither `lang = 'de'` nor `lang != 'de'`, and negating an unknown comparison is still
+/// unknown. Use [`MetadataKey::exists`] or [`MetadataKey::not_exists`] to match on
+/// whether a key is present, these are never unknown. A key holding JSON `null` is present.
+///
+/// Numeric comparisons only match keys holding a JSON number, any other value is treated
+/// the same as a missing key.
+///
+/// # Examples
+/// ```
+/// use rag_toolchain::retrievers::MetadataFilter;
+///
+/// // Everything not in German from 2023 onwards, excluding the release notes
+/// let filter: MetadataFilter = MetadataFilter::key("lang")
+///     .ne("de")
+///     .and(MetadataFilter::key("year").gte(2023))
+///     .and(MetadataFilter::key("source").eq("release-notes").not());
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum MetadataFilter {
+    /// The key is equal to the value
+    Eq(String, Value),
+    /// The key is present and not equal to the value
+    Ne(String, Value),
+    /// The key is a number greater than the value
+    Gt(String, f64),
+    /// The key is a number greater than or equal to the value
+    Gte(String, f64),
+    /// The key is a number less than the value
+    Lt(String, f64),
+    /// The key is a number less than or equal to the value
+    Lte(String, f64),
+    /// The metadata contains the given JSON, see the postgres `@>` operator
+    Contains(Value),
+    /// The key is present
+    Exists(String),
+    /// The key is not present
+    NotExists(String),
+    /// The filter does not match
+    Not(Box<MetadataFilter>),
+    /// Every filter matches, an empty list matches everything
+    And(Vec<MetadataFilter>),
+    /// Any filter matches, an empty list matches nothing
+    Or(Vec<MetadataFilter>),
+}
+
+/// # [`MetadataKey`]
+///
+/// A key in the metadata to build a comparison on, see [`MetadataFilter::key`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MetadataKey {
+    key: String,
+}
+
+impl MetadataKey {
+    pub fn eq(self, value: impl Into<Value>) -> MetadataFilter {
+        MetadataFilter::Eq(self.key, value.into())
+    }
+
+    pub fn ne(self, value: impl Into<Value>) -> MetadataFilter {
+        MetadataFilter::Ne(self.key, value.into())
+    }
+
+    pub fn gt(self, value: impl Into<f64>) -> MetadataFilter {
+        MetadataFilter::Gt(self.key, value.into())
+    }
+
+    pub fn gte(self, value: impl Into<f64>) -> MetadataFilter {
+        MetadataFilter::Gte(self.key, value.into())
+    }
+
+    pub fn lt(self, value: impl Into<f64>) -> MetadataFilter {
+        MetadataFilter::Lt(self.key, value.into())
+    }
+
+    pub fn lte(self, value: impl Into<f64>) -> MetadataFilter {
+        MetadataFilter::Lte(self.key, value.into())
+    }
+
+    pub fn exists(self) -> MetadataFilter {
+        MetadataFilter::Exists(self.key)
+    }
+
+    pub fn not_exists(self) -> MetadataFilter {
+        MetadataFilter::NotExists(self.key)
+    }
+}
+
+impl MetadataFilter {
+    /// # [`MetadataFilter::key`]
+    ///
+    /// Starts a comparison on a top level key of the metadata.
+    ///
+    /// # Arguments
+    /// * `key`: impl [`Into<String>`] - the key to compare.
+    ///
+    /// # Returns
+    /// * [`MetadataKey`] - call a comparison on this to get a filter.
+    pub fn key(key: impl Into<String>) -> MetadataKey {
+        MetadataKey { key: key.into() }
+    }
+
+    /// # [`MetadataFilter::contains`]
+    ///
+    /// # Arguments
+    /// * `value`: impl [`Into<Value>`] - the JSON the metadata must contain.
+    ///
+    /// # Returns
+    /// * [`MetadataFilter`] - a filter matching metadata which contains the value.
+    pub fn contains(value: impl Into<Value>) -> MetadataFilter {
+        MetadataFilter::Contains(value.into())
+    }
+
+    /// # [`MetadataFilter::and`]
+    ///
+    /// Combines the filters so both must match, chained calls are flattened into a single [`MetadataFilter::And`].
+    pub fn and(self, other: MetadataFilter) -> MetadataFilter {
+        match self {
+            MetadataFilter::And(mut filters) => {
+                filters.push(other);
+                MetadataFilter::And(filters)
+            }
+            filter => MetadataFilter::And(vec![filter, other]),
+        }
+    }
+
+    /// # [`MetadataFilter::or`]
+    ///
+    /// Combines the filters so either must match, chained calls are flattened into a single [`MetadataFilter::Or`].
+    pub fn or(self, other: MetadataFilter) -> MetadataFilter {
+        match self {
+            MetadataFilter::Or(mut filters) => {
+                filters.push(other);
+                MetadataFilter::Or(filters)
+            }
+            filter => MetadataFilter::Or(vec![filter, other]),
+        }
+    }
+
+    /// # [`MetadataFilter::not`]
+    ///
+    /// Negates the filter, see the type level docs for how missing keys are handled.
+    #[allow(clippy::should_implement_trait)]
+    pub fn not(self) -> MetadataFilter {
+        MetadataFilter::Not(Box::new(self))
+    }
+
+    /// # [`MetadataFilter::matches`]
+    ///
+    /// Evaluates the filter against a chunk's metadata in Rust, following the same rules as the
+    /// compiled sql so an unknown comparison does not match. This is how retrievers without a
+    /// database apply a filter.
+    ///
+    /// # Arguments
+    /// * `metadata`: &[`Value`] - the metadata of the chunk.
+    ///
+    /// # Returns
+    /// * [`bool`] - true if the filter matches.
+    pub fn matches(&self, metadata: &Value) -> bool {
+        self.evaluate(metadata) == Some(true)
+    }
+
+    /// # [`MetadataFilter::evaluate`]
+    ///
+    /// Three valued evaluation of the filter, `None` is unknown. And / Or follow the sql rules
+    /// so a false in an And or a true in an Or decides the result even if another part is unknown.
+    fn evaluate(&self, metadata: &Value) -> Option<bool> {
+        let field = |key: &str| metadata.as_object().and_then(|object| object.get(key));
+        let number = |key: &str| field(key).and_then(Value::as_f64);
+        match self {
+            MetadataFilter::Eq(key, value) => field(key).map(|field| json_eq(field, value)),
+            MetadataFilter::Ne(key, value) => field(key).map(|field| !json_eq(field, value)),
+            MetadataFilter::Gt(key, value) => number(key).map(|number| number > *value),
+            MetadataFilter::Gte(key, value) => number(key).map(|number| number >= *value),
+            MetadataFilter::Lt(key, value) => number(key).map(|number| number < *value),
+            MetadataFilter::Lte(key, value) => number(key).map(|number| number <= *value),
+            // Missing metadata is a NULL column in postgres so containment is unknown
+            MetadataFilter::Contains(value) => match metadata {
+                Value::Null => None,
+                metadata => Some(json_contains(metadata, value)),
+            },
+            MetadataFilter::Exists(key) => Some(field(key).is_some()),
+            MetadataFilter::NotExists(key) => Some(field(key).is_none()),
+            MetadataFilter::Not(filter) => filter.evaluate(metadata).map(|matched| !matched),
+            MetadataFilter::And(filters) => {
+                let results: Vec<Option<bool>> = filters
+                    .iter()
+                    .map(|filter| filter.evaluate(metadata))
+                    .collect();
+                if results.contains(&Some(false)) {
+                    Some(false)
+                } else if results.contains(&None) {
+                    None
+                } else {
+                    Some(true)
+                }
+            }
+            MetadataFilter::Or(filters) => {
+                let results: Vec<Option<bool>> = filters
+                    .iter()
+                    .map(|filter| filter.evaluate(metadata))
+                    .collect();
+                if results.contains(&Some(true)) {
+                    Some(true)
+                } else if results.contains(&None) {
+                    None
+                } else {
+                    Some(false)
+                }
+            }
+        }
+    }
+}
+
+#[cfg(feature = "pg_vector")]
+impl MetadataFilter {
+    /// # [`MetadataFilter::to_sql`]
+    ///
+    /// Compiles the filter into a parameterized sql condition on the `metadata` column.
+    /// Every sub condition is wrapped in parentheses so precedence follows the structure of the filter.
+    ///
+    /// # Arguments
+    /// * `first_param`: [`usize`] - the number of the first placeholder the filter can use.
+    ///
+    /// # Returns
+    /// * ([`String`], [`Vec<FilterParam>`]) - the condition and the values to bind in order.
+    pub(crate) fn to_sql(&self, first_param: usize) -> (String, Vec<FilterParam>) {
+        let mut params: Vec<FilterParam> = Vec::new();
+        let sql: String = self.write_sql(first_param, &mut params);
+        (sql, params)
+    }
+
+    fn write_sql(&self, first_param: usize, params: &mut Vec<FilterParam>) -> String {
+        let mut bind = |param: FilterParam| -> String {
+            params.push(param);
+            format!("${}", first_param + params.len() - 1)
+        };
+        match self {
+            MetadataFilter::Eq(key, value) => {
+                let key = bind(FilterParam::Text(key.clone()));
+                let value = bind(FilterParam::Json(value.clone()));
+                format!("(metadata -> {} = {})", key, value)
+            }
+            MetadataFilter::Ne(key, value) => {
+                let key = bind(FilterParam::Text(key.clone()));
+                let value = bind(FilterParam::Json(value.clone()));
+                format!("(metadata -> {} <> {})", key, value)
+            }
+            MetadataFilter::Gt(key, value) => numeric_sql(key, ">", *value, bind),
+            MetadataFilter::Gte(key, value) => numeric_sql(key, ">=", *value, bind),
+            MetadataFilter::Lt(key, value) => numeric_sql(key, "<", *value, bind),
+            MetadataFilter::Lte(key, value) => numeric_sql(key, "<=", *value, bind),
+            MetadataFilter::Contains(value) => {
+                let value = bind(FilterParam::Json(value.clone()));
+                format!("(metadata @> {})", value)
+            }
+            // COALESCE so a NULL metadata column is a missing key rather than unknown
+            MetadataFilter::Exists(key) => {
+                let key = bind(FilterParam::Text(key.clone()));
+                format!("(COALESCE(metadata ? {}, FALSE))", key)
+            }
+            MetadataFilter::NotExists(key) => {
+                let key = bind(FilterParam::Text(key.clone()));
+                format!("(NOT COALESCE(metadata ? {}, FALSE))", key)
+            }
+            MetadataFilter::Not(filter) => {
+                format!("(NOT {})", filter.write_sql(first_param, params))
+            }
+            MetadataFilter::And(filters) => join_sql(filters, " AND ", "TRUE", first_param, params),
+            MetadataFilter::Or(filters) => join_sql(filters, " OR ", "FALSE", first_param, params),
+        }
+    }
+}
+
+/// JSON equality where numbers compare by value, as jsonb does, so 1 equals 1.0.
+fn json_eq(left: &Value, right: &Value) -> bool {
+    match (left, right) {
+        (Value::Number(left), Value::Number(right)) => left.as_f64() == right.as_f64(),
+        (Value::Array(left), Value::Array(right)) => {
+            left.len() == right.len() && left.iter().zip(right).all(|(l, r)| json_eq(l, r))
+        }
+        (Value::Object(left), Value::Object(right)) => {
+            left.len() == right.len()
+                && left
+                    .iter()
+                    .all(|(key, l)| right.get(key).is_some_and(|r| json_eq(l, r)))
+        }
+        (left, right) => left == right,
+    }
+}
+
+/// JSON containment following the postgres `@>` operator, every key of an object and every
+/// element of an array on the right must be contained on the left.
+fn json_contains(container: &Value, contained: &Value) -> bool {
+    match (container, contained) {
+        (Value::Object(container), Value::Object(contained)) => {
+            contained.iter().all(|(key, contained)| {
+                container
+                    .get(key)
+                    .is_some_and(|container| json_contains(container, contained))
+            })
+        }
+        (Value::Array(container), Value::Array(contained)) => contained.iter().all(|contained| {
+            container
+                .iter()
+                .any(|container| json_contains(container, contained))
+        }),
+        (container, contained) => json_eq(container, contained),
+    }
+}
+
+#[cfg(feature = "pg_vector")]
+fn numeric_sql(
+    key: &str,
+    operator: &str,
+    value: f64,
+    mut bind: impl FnMut(FilterParam) -> String,
+) -> String {
+    let key = bind(FilterParam::Text(key.into()));
+    let value = bind(FilterParam::Number(value));
+    // Non numbers become NULL so they behave like a missing key instead of failing the cast
+    format!(
+        "((CASE WHEN jsonb_typeof(metadata -> {key}) = 'number' THEN (metadata ->> {key})::double precision END) {} {})",
+        operator, value
+    )
+}
+
+#[cfg(feature = "pg_vector")]
+fn join_sql(
+    filters: &[MetadataFilter],
+    separator: &str,
+    empty: &str,
+    first_param: usize,
+    params: &mut Vec<FilterParam>,
+) -> String {
+    if filters.is_empty() {
+        return format!("({})", empty);
+    }
+    let conditions: Vec<String> = filters
+        .iter()
+        .map(|filter| filter.write_sql(first_param, params))
+        .collect();
+    format!("({})", conditions.join(separator))
+}
+
+/// # [`FilterParam`]
+///
+/// A value bound to a placeholder in a compiled [`MetadataFilter`].
+#[cfg(feature = "pg_vector")]
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum FilterParam {
+    Text(String),
+    Json(Value),
+    Number(f64),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    #[cfg(feature = "pg_vector")]
+    fn comparisons_compile_to_parameterized_sql() {
+        let (sql, params) = MetadataFilter::key("lang").eq("en").to_sql(3);
+        assert_eq!(sql, "(metadata -> $3 = $4)");
+        assert_eq!(
+            params,
+            vec![
+                FilterParam::Text("lang".into()),
+                FilterParam::Json(json!("en"))
+            ]
+        );
+
+        let (sql, _) = MetadataFilter::key("lang").ne("de").to_sql(1);
+        assert_eq!(sql, "(metadata -> $1 <> $2)");
+    }
+
+    #[test]
+    #[cfg(feature = "pg_vector")]
+    fn numeric_comparisons_only_cast_numbers() {
+        let (sql, params) = MetadataFilter::key("year").gte(2023).to_sql(3);
+        assert_eq!(
+            sql,
+            "((CASE WHEN jsonb_typeof(metadata -> $3) = 'number' THEN (metadata ->> $3)::double precision END) >= $4)"
+        );
+        assert_eq!(params[1], FilterParam::Number(2023.0));
+        for (filter, operator) in [
+            (MetadataFilter::key("year").gt(1), " > "),
+            (MetadataFilter::key("year").lt(1), " < "),
+            (MetadataFilter::key("year").lte(1), " <= "),
+        ] {
+            assert!(filter.to_sql(1).0.contains(operator));
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "pg_vector")]
+    fn exists_treats_missing_metadata_as_missing_key() {
+        let (sql, _) = MetadataFilter::key("lang").exists().to_sql(1);
+        assert_eq!(sql, "(COALESCE(metadata ? $1, FALSE))");
+        let (sql, _) = MetadataFilter::key("lang").not_exists().to_sql(1);
+        assert_eq!(sql, "(NOT COALESCE(metadata ? $1, FALSE))");
+    }
+
+    #[test]
+    #[cfg(feature = "pg_vector")]
+    fn combinators_keep_precedence_and_numbering() {
+        let filter = MetadataFilter::key("lang")
+            .ne("de")
+            .and(MetadataFilter::key("year").gte(2023))
+            .or(MetadataFilter::contains(json!({"pinned": true})).not());
+        let (sql, params) = filter.to_sql(3);
+        assert_eq!(
+            sql,
+            concat!(
+                "(((metadata -> $3 <> $4) AND ((CASE WHEN jsonb_typeof(metadata -> $5) = 'number' ",
+                "THEN (metadata ->> $5)::double precision END) >= $6)) OR (NOT (metadata @> $7)))"
+            )
+        );
+        assert_eq!(params.len(), 5);
+        assert_eq!(params[4], FilterParam::Json(json!({"pinned": true})));
+    }
+
+    #[test]
+    #[cfg(feature = "pg_vector")]
+    fn chained_combinators_are_flattened() {
+        let filter = MetadataFilter::key("a")
+            .exists()
+            .and(MetadataFilter::key("b").exists())
+            .and(MetadataFilter::key("c").exists());
+        assert!(matches!(filter, MetadataFilter::And(ref filters) if filters.len() == 3));
+        assert_eq!(MetadataFilter::And(vec![]).to_sql(1).0, "(TRUE)");
+        assert_eq!(MetadataFilter::Or(vec![]).to_sql(1).0, "(FALSE)");
+    }
+
+    #[test]
+    fn comparisons_match_in_rust() {
+        let metadata: Value = json!({"lang": "en", "year": 2023, "draft": null});
+        assert!(MetadataFilter::key("lang").eq("en").matches(&metadata));
+        assert!(MetadataFilter::key("lang").ne("de").matches(&metadata));
+        assert!(MetadataFilter::key("year").eq(2023.0).matches(&metadata));
+        assert!(MetadataFilter::key("year").gte(2023).matches(&metadata));
+        assert!(!MetadataFilter::key("year").gt(2023).matches(&metadata));
+        assert!(MetadataFilter::key("draft").exists().matches(&metadata));
+        assert!(MetadataFilter::key("author")
+            .not_exists()
+            .matches(&metadata));
+        // Numeric comparisons on anything but a number are unknown
+        assert!(!MetadataFilter::key("lang").lt(1).matches(&metadata));
+        assert!(!MetadataFilter::key("lang").lt(1).not().matches(&metadata));
+    }
+
+    #[test]
+    fn missing_keys_are_unknown_in_rust() {
+        let metadata: Value = json!({"year": 2023});
+        assert!(!MetadataFilter::key("lang").eq("de").matches(&metadata));
+        assert!(!MetadataFilter::key("lang").ne("de").matches(&metadata));
+        assert!(!MetadataFilter::key("lang")
+            .eq("de")
+            .not()
+            .matches(&metadata));
+        assert!(!MetadataFilter::key("lang").exists().matches(&Value::Null));
+        // A false in an And or a true in an Or decides the result
+        let unknown = MetadataFilter::key("lang").eq("de");
+        assert!(unknown
+            .clone()
+            .and(MetadataFilter::key("year").lt(2000))
+            .not()
+            .matches(&metadata));
+        assert!(unknown
+            .clone()
+            .or(MetadataFilter::key("year").eq(2023))
+            .matches(&metadata));
+        assert!(!unknown
+            .or(MetadataFilter::key("year").eq(1999))
+            .matches(&metadata));
+        assert!(MetadataFilter::And(vec![]).matches(&metadata));
+        assert!(!MetadataFilter::Or(vec![]).matches(&metadata));
+    }
+
+    #[test]
+    fn contains_matches_nested_json_in_rust() {
+        let metadata: Value =
+            json!({"tags": ["rust", "rag"], "source": {"kind": "web", "depth": 1}});
+        assert!(MetadataFilter::contains(json!({"tags": ["rag"]})).matches(&metadata));
+        assert!(MetadataFilter::contains(json!({"source": {"kind": "web"}})).matches(&metadata));
+        assert!(!MetadataFilter::contains(json!({"tags": ["go"]})).matches(&metadata));
+        assert!(!MetadataFilter::contains(json!({"source": {"depth": 2}})).matches(&metadata));
+        assert!(!MetadataFilter::contains(json!({})).matches(&Value::Null));
+    }
+}
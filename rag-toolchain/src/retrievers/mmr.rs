@@ -0,0 +1,151 @@
+use crate::common::ScoredChunk;
+use crate::retrievers::distance_function::DistanceFunction;
+use std::num::NonZeroU32;
+
+/// # [`RetrievalStrategy`]
+///
+/// How a chain picks its supporting chunks from a retriever.
+///
+/// * `Similarity` - the top_k chunks most similar to the query, the default.
+/// * `Mmr` - maximal marginal relevance, the `fetch_k` most similar chunks are fetched and
+///   top_k of them are picked one at a time, trading similarity to the query against
+///   similarity to the chunks already picked, see [`crate::retrievers::AsyncRetriever::retrieve_mmr`].
+///
+/// # Examples
+/// ```
+/// use rag_toolchain::retrievers::RetrievalStrategy;
+/// use std::num::NonZeroU32;
+///
+/// let strategy = RetrievalStrategy::Mmr {
+///     fetch_k: NonZeroU32::new(20).unwrap(),
+///     lambda: 0.5,
+/// };
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum RetrievalStrategy {
+    #[default]
+    Similarity,
+    Mmr {
+        fetch_k: NonZeroU32,
+        lambda: f32,
+    },
+}
+
+/// # [`select_mmr`]
+///
+/// Picks up to top_k of the candidates by maximal marginal relevance. Each pick is the
+/// candidate with the highest `lambda * score - (1 - lambda) * redundancy`, where the
+/// redundancy is its highest similarity to a candidate already picked. A lambda of 1 keeps
+/// the order of the scores and a lambda of 0 only rewards diversity, it is clamped to 0..=1.
+/// Ties go to the earlier candidate.
+///
+/// # Arguments
+/// * `candidates`: [`Vec<(ScoredChunk, V)>`] - each chunk scored against the query along with its vector.
+/// * `distance_function`: &[`DistanceFunction`] - how the similarity between candidates is measured.
+/// * `top_k`: [`usize`] - the most candidates to pick.
+/// * `lambda`: [`f32`] - the weight given to similarity to the query over diversity.
+///
+/// # Returns
+/// * [`Vec<ScoredChunk>`] - the picked chunks in the order they were picked.
+pub(crate) fn select_mmr<V: AsRef<[f32]>>(
+    candidates: Vec<(ScoredChunk, V)>,
+    distance_function: &DistanceFunction,
+    top_k: usize,
+    lambda: f32,
+) -> Vec<ScoredChunk> {
+    let lambda: f32 = lambda.clamp(0.0, 1.0);
+    let mut remaining: Vec<(ScoredChunk, V)> = candidates;
+    // The highest similarity of each remaining candidate to those already picked
+    let mut redundancy: Vec<Option<f32>> = vec![None; remaining.len()];
+    let mut picked: Vec<ScoredChunk> = Vec::with_capacity(top_k.min(remaining.len()));
+
+    while picked.len() < top_k && !remaining.is_empty() {
+        let marginal = |index: usize| -> f32 {
+            let relevance: f32 = lambda * remaining[index].0.score;
+            match redundancy[index] {
+                Some(similarity) => relevance - (1.0 - lambda) * similarity,
+                None => relevance,
+            }
+        };
+        let mut best: usize = 0;
+        for index in 1..remaining.len() {
+            if marginal(index) > marginal(best) {
+                best = index;
+            }
+        }
+        let (chunk, vector) = remaining.remove(best);
+        redundancy.remove(best);
+        for (index, (_, other)) in remaining.iter().enumerate() {
+            let distance: f64 = distance_function.distance(vector.as_ref(), other.as_ref());
+            let similarity: f32 = distance_function.to_score(distance);
+            redundancy[index] = Some(redundancy[index].map_or(similarity, |r| r.max(similarity)));
+        }
+        picked.push(chunk);
+    }
+    picked
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::Chunk;
+
+    // The query is [1, 0], a and b point almost the same way while c points elsewhere
+    fn candidates() -> Vec<(ScoredChunk, Vec<f32>)> {
+        let query: [f32; 2] = [1.0, 0.0];
+        [
+            ("a", vec![1.0, 0.05]),
+            ("b", vec![1.0, 0.1]),
+            ("c", vec![0.6, -0.8]),
+        ]
+        .into_iter()
+        .map(|(content, vector)| {
+            let distance = DistanceFunction::Cosine.distance(&query, &vector);
+            let score = DistanceFunction::Cosine.to_score(distance);
+            (ScoredChunk::new(Chunk::new(content), score), vector)
+        })
+        .collect()
+    }
+
+    fn contents(picked: &[ScoredChunk]) -> Vec<&str> {
+        picked.iter().map(|scored| scored.chunk.content()).collect()
+    }
+
+    #[test]
+    fn mmr_prefers_a_diverse_chunk_over_a_near_duplicate() {
+        let picked = select_mmr(candidates(), &DistanceFunction::Cosine, 2, 0.5);
+        assert_eq!(contents(&picked), vec!["a", "c"]);
+    }
+
+    #[test]
+    fn lambda_of_one_keeps_the_similarity_order() {
+        let picked = select_mmr(candidates(), &DistanceFunction::Cosine, 2, 1.0);
+        assert_eq!(contents(&picked), vec!["a", "b"]);
+    }
+
+    #[test]
+    fn picks_at_most_the_number_of_candidates() {
+        let picked = select_mmr(candidates(), &DistanceFunction::Cosine, 10, 0.5);
+        assert_eq!(contents(&picked), vec!["a", "c", "b"]);
+        assert!(select_mmr(
+            Vec::<(ScoredChunk, Vec<f32>)>::new(),
+            &DistanceFunction::L2,
+            3,
+            0.5
+        )
+        .is_empty());
+    }
+
+    #[test]
+    fn scores_are_kept_from_the_query() {
+        let expected: Vec<f32> = candidates()
+            .iter()
+            .map(|(scored, _)| scored.score)
+            .collect();
+        let picked = select_mmr(candidates(), &DistanceFunction::Cosine, 2, 0.5);
+        assert_eq!(picked[0].score, expected[0]);
+        assert_eq!(picked[1].score, expected[2]);
+    }
+}
@@ -4,15 +4,57 @@
 /// Which allows you given some input text to search for similar text in the store.
 mod traits;
 
+mod batch;
+mod distance_function;
+mod dyn_retriever;
+#[cfg(feature = "pg_vector")]
+mod explain;
+#[cfg(feature = "pg_vector")]
+mod hybrid;
+mod in_memory_retriever;
+#[cfg(test)]
+mod lookup_embedding_client;
+pub(crate) mod metadata_filter;
+mod mmr;
+mod multi_query;
 #[cfg(feature = "pg_vector")]
 mod postgres_vector_retriever;
+mod query_rewriter;
+mod reranker;
+#[cfg(feature = "sqlite_vec")]
+mod sqlite_vector_retriever;
+pub use batch::BatchRetrieveError;
+pub use distance_function::DistanceFunction;
+pub use dyn_retriever::DynAsyncRetriever;
+#[cfg(feature = "pg_vector")]
+pub use explain::{QueryPlanSummary, RetrieveExplanation};
+#[cfg(feature = "pg_vector")]
+pub use hybrid::{HybridWeights, DEFAULT_RRF_K};
+pub use in_memory_retriever::{InMemoryRetriever, InMemoryRetrieverError};
+pub use metadata_filter::{MetadataFilter, MetadataKey};
+pub use mmr::RetrievalStrategy;
+pub use multi_query::{MultiQueryRetriever, MultiQueryRetrieverError};
 #[cfg(feature = "pg_vector")]
 pub use postgres_vector_retriever::{
-    DistanceFunction, PostgresRetrieverError, PostgresVectorRetriever,
+    IndexParameters, PostgresRetrieverError, PostgresVectorRetriever, DEFAULT_MAX_TOP_K,
 };
 
+pub use query_rewriter::{
+    DictionaryExpander, PassThroughRewriter, QueryRewriter, RewritingRetriever,
+};
+pub use reranker::{
+    AsyncReranker, DynAsyncReranker, PassThroughReranker, RerankingRetriever,
+    RerankingRetrieverError,
+};
+#[cfg(feature = "sqlite_vec")]
+pub use sqlite_vector_retriever::{SqliteRetrieverError, SqliteVectorRetriever};
 pub use traits::AsyncRetriever;
+pub use traits::FilteredRetriever;
 
 // export the trait mocks for use in testing
 #[cfg(test)]
+pub use reranker::MockAsyncReranker;
+#[cfg(test)]
 pub use traits::MockAsyncRetriever;
+#[cfg(test)]
+pub use traits::MockFilteredRetriever;
@@ -0,0 +1,460 @@
+use crate::clients::{AsyncChatClient, PromptMessage};
+use crate::common::{Chunks, InvocationContext, ScoredChunk};
+use crate::retrievers::metadata_filter::MetadataFilter;
+use crate::retrievers::traits::AsyncRetriever;
+use futures::future::try_join_all;
+use std::cmp::Ordering;
+use std::error::Error;
+use std::num::NonZeroU32;
+use thiserror::Error;
+
+/// # [`MultiQueryRetriever`]
+///
+/// Wraps any [`AsyncRetriever`] so documents phrased differently from the question are
+/// still found. The chat client is asked for alternative phrasings of the query, the
+/// query and each alternative are searched for concurrently and the results merged.
+///
+/// * A chunk returned for several of the queries appears once.
+/// * Chunks are ranked by how many of the queries returned them, then by their best score.
+/// * At most `top_k` chunks are returned, each with its best score.
+///
+/// # Examples
+/// ```
+/// use rag_toolchain::clients::*;
+/// use rag_toolchain::retrievers::*;
+/// use std::num::NonZeroU32;
+///
+/// fn with_rephrasing<R, C>(retriever: R, chat_client: C) -> MultiQueryRetriever<R, C>
+/// where
+///     R: AsyncRetriever,
+///     C: AsyncChatClient,
+/// {
+///     MultiQueryRetriever::new(retriever, chat_client)
+///         .with_alternatives(NonZeroU32::new(5).unwrap())
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct MultiQueryRetriever<R, C> {
+    retriever: R,
+    chat_client: C,
+    alternatives: NonZeroU32,
+    prompt_template: String,
+}
+
+impl<R, C> MultiQueryRetriever<R, C>
+where
+    R: AsyncRetriever,
+    C: AsyncChatClient,
+{
+    /// The prompt used unless configured otherwise
+    pub const DEFAULT_PROMPT_TEMPLATE: &'static str = "Write {{count}} different versions of \
+        the question below to help find relevant documents in a search index. Put each version \
+        on its own line with no numbering or other text.\nQuestion: {{question}}";
+    /// The number of alternative phrasings asked for unless configured otherwise
+    pub const DEFAULT_ALTERNATIVES: NonZeroU32 = NonZeroU32::new(3).unwrap();
+
+    /// # [`MultiQueryRetriever::new`]
+    ///
+    /// # Arguments
+    /// * `retriever`: [`R`] - the retriever each query is searched with.
+    /// * `chat_client`: [`C`] - asked for the alternative phrasings.
+    ///
+    /// # Returns
+    /// * [`MultiQueryRetriever`] - asking for [`Self::DEFAULT_ALTERNATIVES`] phrasings
+    ///   with [`Self::DEFAULT_PROMPT_TEMPLATE`].
+    pub fn new(retriever: R, chat_client: C) -> Self {
+        MultiQueryRetriever {
+            retriever,
+            chat_client,
+            alternatives: Self::DEFAULT_ALTERNATIVES,
+            prompt_template: Self::DEFAULT_PROMPT_TEMPLATE.into(),
+        }
+    }
+
+    /// # [`MultiQueryRetriever::with_alternatives`]
+    ///
+    /// # Arguments
+    /// * `alternatives`: [`NonZeroU32`] - the number of alternative phrasings to search for
+    ///   alongside the query. Any more the chat client returns are ignored.
+    ///
+    /// # Returns
+    /// * [`MultiQueryRetriever`] - the retriever with the number of alternatives set.
+    pub fn with_alternatives(mut self, alternatives: NonZeroU32) -> Self {
+        self.alternatives = alternatives;
+        self
+    }
+
+    /// # [`MultiQueryRetriever::with_prompt_template`]
+    ///
+    /// # Arguments
+    /// * `prompt_template`: impl [`Into<String>`] - the prompt sent to the chat client,
+    ///   `{{question}}` is replaced with the query and `{{count}}` with the number of
+    ///   alternatives. The chat client should answer with one phrasing per line.
+    ///
+    /// # Returns
+    /// * [`MultiQueryRetriever`] - the retriever with the prompt set.
+    pub fn with_prompt_template(mut self, prompt_template: impl Into<String>) -> Self {
+        self.prompt_template = prompt_template.into();
+        self
+    }
+
+    /// # [`MultiQueryRetriever::prompt`]
+    /// The messages asking the chat client for alternatives to the query.
+    fn prompt(&self, text: &str) -> Vec<PromptMessage> {
+        let content: String = self
+            .prompt_template
+            .replace("{{count}}", &self.alternatives.to_string())
+            .replace("{{question}}", text);
+        vec![PromptMessage::HumanMessage(content.into())]
+    }
+
+    /// # [`MultiQueryRetriever::queries`]
+    ///
+    /// The query followed by the alternatives in the chat client's response. Numbering
+    /// and bullets in front of each line are removed, blank lines and repeats are skipped.
+    fn queries(&self, text: &str, response: &PromptMessage) -> Vec<String> {
+        let mut queries: Vec<String> = vec![text.to_string()];
+        let alternatives = response
+            .content()
+            .lines()
+            .map(strip_list_marker)
+            .filter(|line| !line.is_empty());
+        for alternative in alternatives {
+            if queries.len() > self.alternatives.get() as usize {
+                break;
+            }
+            if !queries.iter().any(|query| query == alternative) {
+                queries.push(alternative.to_string());
+            }
+        }
+        queries
+    }
+}
+
+impl<R, C> AsyncRetriever for MultiQueryRetriever<R, C>
+where
+    R: AsyncRetriever,
+    C: AsyncChatClient,
+{
+    type ErrorType = MultiQueryRetrieverError<C::ErrorType, R::ErrorType>;
+
+    async fn retrieve(&self, text: &str, top_k: NonZeroU32) -> Result<Chunks, Self::ErrorType> {
+        let scored: Vec<ScoredChunk> = self.retrieve_with_scores(text, top_k).await?;
+        Ok(scored.into_iter().map(|scored| scored.chunk).collect())
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            name = "multi_query_retriever.retrieve_with_context",
+            skip_all,
+            fields(top_k = top_k.get(), request_id = %context.request_id())
+        )
+    )]
+    async fn retrieve_with_context(
+        &self,
+        text: &str,
+        top_k: NonZeroU32,
+        context: &InvocationContext,
+    ) -> Result<Chunks, Self::ErrorType> {
+        let scored: Vec<ScoredChunk> = self
+            .retrieve_with_scores_and_context(text, top_k, context)
+            .await?;
+        Ok(scored.into_iter().map(|scored| scored.chunk).collect())
+    }
+
+    async fn retrieve_with_scores(
+        &self,
+        text: &str,
+        top_k: NonZeroU32,
+    ) -> Result<Vec<ScoredChunk>, Self::ErrorType> {
+        let response: PromptMessage = self
+            .chat_client
+            .invoke(self.prompt(text))
+            .await
+            .map_err(MultiQueryRetrieverError::ChatClientError)?;
+        let queries: Vec<String> = self.queries(text, &response);
+        let results: Vec<Vec<ScoredChunk>> = try_join_all(
+            queries
+                .iter()
+                .map(|query| self.retriever.retrieve_with_scores(query, top_k)),
+        )
+        .await
+        .map_err(MultiQueryRetrieverError::RetrieverError)?;
+        Ok(merge(results, top_k))
+    }
+
+    async fn retrieve_with_scores_and_context(
+        &self,
+        text: &str,
+        top_k: NonZeroU32,
+        context: &InvocationContext,
+    ) -> Result<Vec<ScoredChunk>, Self::ErrorType> {
+        let response: PromptMessage = self
+            .chat_client
+            .invoke_with_context(self.prompt(text), context)
+            .await
+            .map_err(MultiQueryRetrieverError::ChatClientError)?
+            .message;
+        let queries: Vec<String> = self.queries(text, &response);
+        let results: Vec<Vec<ScoredChunk>> = try_join_all(queries.iter().map(|query| {
+            self.retriever
+                .retrieve_with_scores_and_context(query, top_k, context)
+        }))
+        .await
+        .map_err(MultiQueryRetrieverError::RetrieverError)?;
+        Ok(merge(results, top_k))
+    }
+
+    /// # [`MultiQueryRetriever::retrieve_mmr_with_scores`]
+    ///
+    /// Each query is searched by maximal marginal relevance with the wrapped retriever, see
+    /// [`AsyncRetriever::retrieve_mmr`], and the results are merged as they are for
+    /// [`AsyncRetriever::retrieve_with_scores`].
+    async fn retrieve_mmr_with_scores(
+        &self,
+        text: &str,
+        top_k: NonZeroU32,
+        fetch_k: NonZeroU32,
+        lambda: f32,
+    ) -> Result<Vec<ScoredChunk>, Self::ErrorType> {
+        let response: PromptMessage = self
+            .chat_client
+            .invoke(self.prompt(text))
+            .await
+            .map_err(MultiQueryRetrieverError::ChatClientError)?;
+        let queries: Vec<String> = self.queries(text, &response);
+        let results: Vec<Vec<ScoredChunk>> = try_join_all(queries.iter().map(|query| {
+            self.retriever
+                .retrieve_mmr_with_scores(query, top_k, fetch_k, lambda)
+        }))
+        .await
+        .map_err(MultiQueryRetrieverError::RetrieverError)?;
+        Ok(merge(results, top_k))
+    }
+
+    async fn fetch_by_metadata(&self, filter: &MetadataFilter) -> Result<Chunks, Self::ErrorType> {
+        self.retriever
+            .fetch_by_metadata(filter)
+            .await
+            .map_err(MultiQueryRetrieverError::RetrieverError)
+    }
+
+    fn max_top_k(&self) -> Option<NonZeroU32> {
+        self.retriever.max_top_k()
+    }
+}
+
+/// Removes a leading `1.`, `2)`, `-` or `*` from a line of the chat client's response
+fn strip_list_marker(line: &str) -> &str {
+    let line: &str = line.trim();
+    let unnumbered: &str = line.trim_start_matches(|c: char| c.is_ascii_digit());
+    let unmarked: Option<&str> = if unnumbered.len() < line.len() {
+        unnumbered
+            .strip_prefix('.')
+            .or_else(|| unnumbered.strip_prefix(')'))
+    } else {
+        line.strip_prefix('-').or_else(|| line.strip_prefix('*'))
+    };
+    unmarked.unwrap_or(line).trim()
+}
+
+/// # [`merge`]
+///
+/// Merges the results of each query. A chunk is counted once per query which returned it
+/// and keeps its best score. Ties on the count are broken by the score, and then by which
+/// chunk was returned first.
+fn merge(results: Vec<Vec<ScoredChunk>>, top_k: NonZeroU32) -> Vec<ScoredChunk> {
+    let mut merged: Vec<(ScoredChunk, usize)> = Vec::new();
+    for query_results in results {
+        let mut seen: Vec<usize> = Vec::with_capacity(query_results.len());
+        for scored in query_results {
+            match merged
+                .iter()
+                .position(|(kept, _)| kept.chunk == scored.chunk)
+            {
+                Some(index) if seen.contains(&index) => {
+                    let kept: &mut ScoredChunk = &mut merged[index].0;
+                    kept.score = kept.score.max(scored.score);
+                }
+                Some(index) => {
+                    let (kept, hits) = &mut merged[index];
+                    kept.score = kept.score.max(scored.score);
+                    *hits += 1;
+                    seen.push(index);
+                }
+                None => {
+                    seen.push(merged.len());
+                    merged.push((scored, 1));
+                }
+            }
+        }
+    }
+    merged.sort_by(|(a, a_hits), (b, b_hits)| {
+        b_hits
+            .cmp(a_hits)
+            .then_with(|| b.score.partial_cmp(&a.score).unwrap_or(Ordering::Equal))
+    });
+    merged
+        .into_iter()
+        .take(top_k.get() as usize)
+        .map(|(scored, _)| scored)
+        .collect()
+}
+
+/// # [`MultiQueryRetrieverError`]
+///
+/// The error of a [`MultiQueryRetriever`], parametrized over the error types of the chat
+/// client and the wrapped retriever so they are preserved.
+///
+/// * `T` - The error type of the chat client
+/// * `U` - The error type of the retriever
+#[derive(Error, Debug, PartialEq)]
+pub enum MultiQueryRetrieverError<T: Error, U: Error> {
+    #[error("Chat Client Error: {0}")]
+    ChatClientError(T),
+    #[error("Retriever Error: {0}")]
+    RetrieverError(U),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clients::MockAsyncChatClient;
+    use crate::common::Chunk;
+    use crate::retrievers::MockAsyncRetriever;
+    use mockall::predicate::eq;
+
+    fn scored(content: &str, score: f32) -> ScoredChunk {
+        ScoredChunk::new(Chunk::new(content), score)
+    }
+
+    fn chat_client(response: &'static str) -> MockAsyncChatClient {
+        let mut chat_client = MockAsyncChatClient::new();
+        chat_client
+            .expect_invoke()
+            .times(1)
+            .returning(move |_| Ok(PromptMessage::AIMessage(response.into())));
+        chat_client
+    }
+
+    #[test]
+    fn list_markers_are_stripped() {
+        assert_eq!(strip_list_marker("1. refund policy"), "refund policy");
+        assert_eq!(strip_list_marker(" 12) refund policy "), "refund policy");
+        assert_eq!(strip_list_marker("- refund policy"), "refund policy");
+        assert_eq!(strip_list_marker("* refund policy"), "refund policy");
+        assert_eq!(
+            strip_list_marker("2024 refund policy"),
+            "2024 refund policy"
+        );
+    }
+
+    #[test]
+    fn the_prompt_asks_for_the_number_of_alternatives() {
+        let retriever =
+            MultiQueryRetriever::new(MockAsyncRetriever::new(), MockAsyncChatClient::new())
+                .with_alternatives(NonZeroU32::new(2).unwrap())
+                .with_prompt_template("{{count}} ways to say: {{question}}");
+        assert_eq!(
+            retriever.prompt("refunds"),
+            vec![PromptMessage::HumanMessage("2 ways to say: refunds".into())]
+        );
+    }
+
+    #[test]
+    fn queries_skip_blank_lines_and_repeats_and_are_capped() {
+        let retriever =
+            MultiQueryRetriever::new(MockAsyncRetriever::new(), MockAsyncChatClient::new())
+                .with_alternatives(NonZeroU32::new(2).unwrap());
+        let response = PromptMessage::AIMessage(
+            "1. refunds\n\n2. money back\n- money back\n3. returns".into(),
+        );
+        assert_eq!(
+            retriever.queries("refunds", &response),
+            vec![
+                "refunds".to_string(),
+                "money back".to_string(),
+                "returns".to_string()
+            ]
+        );
+    }
+
+    #[test]
+    fn merge_ranks_by_hits_then_score_and_keeps_the_best_score() {
+        let results = vec![
+            vec![scored("a", 0.9), scored("b", 0.5)],
+            vec![scored("b", 0.7), scored("c", 0.95)],
+            vec![scored("b", 0.6), scored("a", 0.4), scored("a", 0.8)],
+        ];
+        assert_eq!(
+            merge(results, NonZeroU32::new(10).unwrap()),
+            vec![scored("b", 0.7), scored("a", 0.9), scored("c", 0.95)]
+        );
+    }
+
+    #[tokio::test]
+    async fn each_query_is_searched_and_the_results_merged() {
+        let mut retriever = MockAsyncRetriever::new();
+        let top_k = NonZeroU32::new(2).unwrap();
+        retriever
+            .expect_retrieve_with_scores()
+            .with(eq("refund policy"), eq(top_k))
+            .times(1)
+            .returning(|_, _| Ok(vec![scored("refunds", 0.8), scored("shipping", 0.7)]));
+        retriever
+            .expect_retrieve_with_scores()
+            .with(eq("getting my money back"), eq(top_k))
+            .times(1)
+            .returning(|_, _| Ok(vec![scored("returns", 0.9), scored("refunds", 0.6)]));
+        let retriever = MultiQueryRetriever::new(retriever, chat_client("getting my money back"));
+
+        let chunks = retriever.retrieve("refund policy", top_k).await.unwrap();
+        assert_eq!(chunks, vec![Chunk::new("refunds"), Chunk::new("returns")]);
+    }
+
+    #[tokio::test]
+    async fn each_query_is_searched_by_mmr_and_the_results_merged() {
+        let mut retriever = MockAsyncRetriever::new();
+        let top_k = NonZeroU32::new(2).unwrap();
+        let fetch_k = NonZeroU32::new(5).unwrap();
+        retriever.expect_retrieve_with_scores().never();
+        retriever
+            .expect_retrieve_mmr_with_scores()
+            .with(eq("refund policy"), eq(top_k), eq(fetch_k), eq(0.5))
+            .times(1)
+            .returning(|_, _, _, _| Ok(vec![scored("refunds", 0.8), scored("shipping", 0.7)]));
+        retriever
+            .expect_retrieve_mmr_with_scores()
+            .with(eq("getting my money back"), eq(top_k), eq(fetch_k), eq(0.5))
+            .times(1)
+            .returning(|_, _, _, _| Ok(vec![scored("returns", 0.9), scored("refunds", 0.6)]));
+        let retriever = MultiQueryRetriever::new(retriever, chat_client("getting my money back"));
+
+        let chunks = retriever
+            .retrieve_mmr("refund policy", top_k, fetch_k, 0.5)
+            .await
+            .unwrap();
+        assert_eq!(chunks, vec![Chunk::new("refunds"), Chunk::new("returns")]);
+    }
+
+    #[tokio::test]
+    async fn chat_client_errors_are_returned_without_searching() {
+        let mut chat_client = MockAsyncChatClient::new();
+        chat_client
+            .expect_invoke()
+            .returning(|_| Err(std::io::Error::other("chat failed")));
+        let mut retriever = MockAsyncRetriever::new();
+        retriever.expect_retrieve_with_scores().never();
+        let retriever = MultiQueryRetriever::new(retriever, chat_client);
+
+        let error = retriever
+            .retrieve("refund policy", NonZeroU32::new(2).unwrap())
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            error,
+            MultiQueryRetrieverError::ChatClientError(_)
+        ));
+    }
+}
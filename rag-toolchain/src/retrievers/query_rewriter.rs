@@ -0,0 +1,321 @@
+use crate::common::{Chunks, InvocationContext, ScoredChunk};
+use crate::retrievers::metadata_filter::MetadataFilter;
+use crate::retrievers::traits::AsyncRetriever;
+use std::collections::HashMap;
+use std::future::Future;
+use std::num::NonZeroU32;
+
+/// # [`QueryRewriter`]
+///
+/// Rewrites the text of a query before it is embedded. Internal acronyms and jargon
+/// tend to embed poorly so expanding them first can make retrieval much better.
+/// This is async so a rewriter can call out to an LLM.
+///
+/// A rewriter can be set on a [`crate::retrievers::PostgresVectorRetriever`] with
+/// `with_query_rewriter`, or any [`AsyncRetriever`] can be wrapped in a [`RewritingRetriever`].
+pub trait QueryRewriter: Send + Sync {
+    /// # [`QueryRewriter::rewrite`]
+    ///
+    /// # Arguments
+    /// * `query`: &[`str`] - the text the user searched for.
+    ///
+    /// # Returns
+    /// * [`String`] - the text to embed instead.
+    fn rewrite(&self, query: &str) -> impl Future<Output = String> + Send;
+}
+
+/// # [`PassThroughRewriter`]
+///
+/// The default rewriter which leaves the query unchanged.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PassThroughRewriter;
+
+impl QueryRewriter for PassThroughRewriter {
+    async fn rewrite(&self, query: &str) -> String {
+        query.to_string()
+    }
+}
+
+/// # [`DictionaryExpander`]
+///
+/// Expands known terms in a query using a static dictionary. Each match is kept
+/// and its expansion added after it in brackets, so "PTO policy" with `PTO` mapped
+/// to `paid time off` becomes "PTO (paid time off) policy".
+///
+/// * Matching is case-insensitive and the query's own casing is kept.
+/// * Terms only match whole words, so `PTO` does not match inside "PTOs".
+/// * Where terms overlap the longest match wins, so `RFC-123` is preferred over `RFC`.
+///
+/// # Examples
+/// ```
+/// use rag_toolchain::retrievers::DictionaryExpander;
+/// use std::collections::HashMap;
+///
+/// let expander = DictionaryExpander::new(HashMap::from([
+///     ("PTO".to_string(), "paid time off".to_string()),
+/// ]));
+/// assert_eq!(expander.expand("how much pto do I get"), "how much pto (paid time off) do I get");
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DictionaryExpander {
+    /// Sorted longest first so the first match is the longest
+    terms: Vec<(Vec<char>, String)>,
+}
+
+impl DictionaryExpander {
+    /// # [`DictionaryExpander::new`]
+    ///
+    /// # Arguments
+    /// * `dictionary`: [`HashMap<String, String>`] - each term mapped to its expansion,
+    ///   empty terms are ignored.
+    ///
+    /// # Returns
+    /// * [`DictionaryExpander`] - the expander.
+    pub fn new(dictionary: HashMap<String, String>) -> Self {
+        let mut terms: Vec<(Vec<char>, String)> = dictionary
+            .into_iter()
+            .filter(|(term, _)| !term.is_empty())
+            .map(|(term, expansion)| (term.chars().collect(), expansion))
+            .collect();
+        terms.sort_by(|(a, _), (b, _)| b.len().cmp(&a.len()).then_with(|| a.cmp(b)));
+        DictionaryExpander { terms }
+    }
+
+    /// # [`DictionaryExpander::expand`]
+    ///
+    /// # Arguments
+    /// * `query`: &[`str`] - the text to expand.
+    ///
+    /// # Returns
+    /// * [`String`] - the text with every known term followed by its expansion.
+    pub fn expand(&self, query: &str) -> String {
+        let chars: Vec<char> = query.chars().collect();
+        let mut expanded = String::with_capacity(query.len());
+        let mut index: usize = 0;
+        while index < chars.len() {
+            let starts_word: bool = index == 0 || !chars[index - 1].is_alphanumeric();
+            let found = starts_word
+                .then(|| {
+                    self.terms
+                        .iter()
+                        .find(|(term, _)| Self::matches_at(&chars, index, term))
+                })
+                .flatten();
+            match found {
+                Some((term, expansion)) => {
+                    expanded.extend(&chars[index..index + term.len()]);
+                    expanded.push_str(&format!(" ({})", expansion));
+                    index += term.len();
+                }
+                None => {
+                    expanded.push(chars[index]);
+                    index += 1;
+                }
+            }
+        }
+        expanded
+    }
+
+    /// Whether the term matches the text at the index, ignoring case and ending on a word boundary
+    fn matches_at(chars: &[char], index: usize, term: &[char]) -> bool {
+        let end: usize = index + term.len();
+        end <= chars.len()
+            && chars[index..end]
+                .iter()
+                .zip(term)
+                .all(|(a, b)| a.to_lowercase().eq(b.to_lowercase()))
+            && chars.get(end).is_none_or(|next| !next.is_alphanumeric())
+    }
+}
+
+impl QueryRewriter for DictionaryExpander {
+    async fn rewrite(&self, query: &str) -> String {
+        self.expand(query)
+    }
+}
+
+/// # [`RewritingRetriever`]
+///
+/// Wraps any [`AsyncRetriever`] so the query is rewritten before it is passed on.
+///
+/// # Examples
+/// ```
+/// use rag_toolchain::retrievers::*;
+/// use std::collections::HashMap;
+///
+/// fn with_acronyms<R: AsyncRetriever>(retriever: R) -> RewritingRetriever<R, DictionaryExpander> {
+///     let expander = DictionaryExpander::new(HashMap::from([
+///         ("PTO".to_string(), "paid time off".to_string()),
+///     ]));
+///     RewritingRetriever::new(retriever, expander)
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct RewritingRetriever<R, W> {
+    retriever: R,
+    rewriter: W,
+}
+
+impl<R, W> RewritingRetriever<R, W>
+where
+    R: AsyncRetriever,
+    W: QueryRewriter,
+{
+    /// # [`RewritingRetriever::new`]
+    ///
+    /// # Arguments
+    /// * `retriever`: [`R`] - the retriever to search with.
+    /// * `rewriter`: [`W`] - rewrites each query before it is passed to the retriever.
+    ///
+    /// # Returns
+    /// * [`RewritingRetriever`] - the wrapped retriever.
+    pub fn new(retriever: R, rewriter: W) -> Self {
+        RewritingRetriever {
+            retriever,
+            rewriter,
+        }
+    }
+}
+
+impl<R, W> AsyncRetriever for RewritingRetriever<R, W>
+where
+    R: AsyncRetriever,
+    W: QueryRewriter,
+{
+    type ErrorType = R::ErrorType;
+
+    async fn retrieve(&self, text: &str, top_k: NonZeroU32) -> Result<Chunks, Self::ErrorType> {
+        let rewritten: String = self.rewriter.rewrite(text).await;
+        self.retriever.retrieve(&rewritten, top_k).await
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            name = "rewriting_retriever.retrieve_with_context",
+            skip_all,
+            fields(top_k = top_k.get(), request_id = %context.request_id())
+        )
+    )]
+    async fn retrieve_with_context(
+        &self,
+        text: &str,
+        top_k: NonZeroU32,
+        context: &InvocationContext,
+    ) -> Result<Chunks, Self::ErrorType> {
+        let rewritten: String = self.rewriter.rewrite(text).await;
+        self.retriever
+            .retrieve_with_context(&rewritten, top_k, context)
+            .await
+    }
+
+    async fn retrieve_with_scores(
+        &self,
+        text: &str,
+        top_k: NonZeroU32,
+    ) -> Result<Vec<ScoredChunk>, Self::ErrorType> {
+        let rewritten: String = self.rewriter.rewrite(text).await;
+        self.retriever.retrieve_with_scores(&rewritten, top_k).await
+    }
+
+    async fn retrieve_with_scores_and_context(
+        &self,
+        text: &str,
+        top_k: NonZeroU32,
+        context: &InvocationContext,
+    ) -> Result<Vec<ScoredChunk>, Self::ErrorType> {
+        let rewritten: String = self.rewriter.rewrite(text).await;
+        self.retriever
+            .retrieve_with_scores_and_context(&rewritten, top_k, context)
+            .await
+    }
+
+    async fn retrieve_mmr_with_scores(
+        &self,
+        text: &str,
+        top_k: NonZeroU32,
+        fetch_k: NonZeroU32,
+        lambda: f32,
+    ) -> Result<Vec<ScoredChunk>, Self::ErrorType> {
+        let rewritten: String = self.rewriter.rewrite(text).await;
+        self.retriever
+            .retrieve_mmr_with_scores(&rewritten, top_k, fetch_k, lambda)
+            .await
+    }
+
+    async fn fetch_by_metadata(&self, filter: &MetadataFilter) -> Result<Chunks, Self::ErrorType> {
+        self.retriever.fetch_by_metadata(filter).await
+    }
+
+    fn max_top_k(&self) -> Option<NonZeroU32> {
+        self.retriever.max_top_k()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::retrievers::MockAsyncRetriever;
+    use mockall::predicate::eq;
+
+    fn expander(terms: &[(&str, &str)]) -> DictionaryExpander {
+        DictionaryExpander::new(
+            terms
+                .iter()
+                .map(|(term, expansion)| (term.to_string(), expansion.to_string()))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn expands_case_insensitively_keeping_the_query_casing() {
+        let expander = expander(&[("PTO", "paid time off")]);
+        assert_eq!(expander.expand("PTO policy"), "PTO (paid time off) policy");
+        assert_eq!(
+            expander.expand("my pto, your Pto"),
+            "my pto (paid time off), your Pto (paid time off)"
+        );
+    }
+
+    #[test]
+    fn only_whole_words_are_expanded() {
+        let expander = expander(&[("PTO", "paid time off")]);
+        assert_eq!(expander.expand("PTOs and laptop"), "PTOs and laptop");
+        assert_eq!(expander.expand("(PTO)"), "(PTO (paid time off))");
+    }
+
+    #[test]
+    fn longest_match_wins() {
+        let expander = expander(&[("RFC", "request for comments"), ("RFC-123", "the auth RFC")]);
+        assert_eq!(
+            expander.expand("see rfc-123 and RFC-12"),
+            "see rfc-123 (the auth RFC) and RFC (request for comments)-12"
+        );
+    }
+
+    #[test]
+    fn empty_terms_and_queries_are_ignored() {
+        let expander = expander(&[("", "nothing"), ("é", "e acute")]);
+        assert_eq!(expander.expand(""), "");
+        assert_eq!(expander.expand("É é"), "É (e acute) é (e acute)");
+    }
+
+    #[tokio::test]
+    async fn rewriting_retriever_passes_the_rewritten_query() {
+        let mut retriever = MockAsyncRetriever::new();
+        retriever
+            .expect_retrieve()
+            .with(
+                eq("PTO (paid time off) policy"),
+                eq(NonZeroU32::new(3).unwrap()),
+            )
+            .times(1)
+            .returning(|_, _| Ok(Vec::new()));
+        let retriever = RewritingRetriever::new(retriever, expander(&[("PTO", "paid time off")]));
+        let chunks = retriever
+            .retrieve("PTO policy", NonZeroU32::new(3).unwrap())
+            .await
+            .unwrap();
+        assert!(chunks.is_empty());
+    }
+}
@@ -0,0 +1,396 @@
+use crate::common::{Chunks, DynError, DynFuture, InvocationContext, ScoredChunk};
+use crate::retrievers::metadata_filter::MetadataFilter;
+use crate::retrievers::traits::AsyncRetriever;
+use std::error::Error;
+use std::future::Future;
+use std::num::NonZeroU32;
+use thiserror::Error;
+
+/// # [`AsyncReranker`]
+///
+/// Reorders retrieved chunks by how relevant they are to the query. Vector search is
+/// good at finding candidates but less good at ordering them, so retrieving more chunks
+/// than are needed and reranking them with a model which reads the query and each chunk
+/// together usually gives a much better top few.
+///
+/// Any [`AsyncRetriever`] can be wrapped in a [`RerankingRetriever`] to rerank what it returns.
+pub trait AsyncReranker: Send + Sync {
+    type ErrorType: Error + Send + Sync;
+
+    /// # [`AsyncReranker::rerank`]
+    ///
+    /// # Arguments
+    /// * `query`: &[`str`] - the text the chunks were retrieved for.
+    /// * `chunks`: [`Vec<ScoredChunk>`] - the candidates with their retrieval scores.
+    /// * `top_n`: [`NonZeroU32`] - the number of chunks to keep.
+    ///
+    /// # Errors
+    /// * [`Self::ErrorType`] - If the chunks could not be reranked.
+    ///
+    /// # Returns
+    /// * [`Vec<ScoredChunk>`] - at most `top_n` chunks, most relevant first, each scored
+    ///   by the reranker.
+    fn rerank(
+        &self,
+        query: &str,
+        chunks: Vec<ScoredChunk>,
+        top_n: NonZeroU32,
+    ) -> impl Future<Output = Result<Vec<ScoredChunk>, Self::ErrorType>> + Send;
+}
+
+/// # [`PassThroughReranker`]
+///
+/// A reranker which keeps the retrieval order and scores and only drops the chunks
+/// past `top_n`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PassThroughReranker;
+
+impl AsyncReranker for PassThroughReranker {
+    type ErrorType = std::convert::Infallible;
+
+    async fn rerank(
+        &self,
+        _query: &str,
+        mut chunks: Vec<ScoredChunk>,
+        top_n: NonZeroU32,
+    ) -> Result<Vec<ScoredChunk>, Self::ErrorType> {
+        chunks.truncate(top_n.get() as usize);
+        Ok(chunks)
+    }
+}
+
+/// # [`DynAsyncReranker`]
+///
+/// An object safe version of [`AsyncReranker`] so the reranker can be chosen at runtime.
+/// Every [`AsyncReranker`] implements it with its errors boxed into a [`DynError`], and
+/// `Box<dyn DynAsyncReranker>` implements [`AsyncReranker`].
+pub trait DynAsyncReranker: Send + Sync {
+    /// # [`DynAsyncReranker::dyn_rerank`]
+    ///
+    /// See [`AsyncReranker::rerank`].
+    fn dyn_rerank<'a>(
+        &'a self,
+        query: &'a str,
+        chunks: Vec<ScoredChunk>,
+        top_n: NonZeroU32,
+    ) -> DynFuture<'a, Vec<ScoredChunk>>;
+}
+
+impl<T> DynAsyncReranker for T
+where
+    T: AsyncReranker,
+    T::ErrorType: 'static,
+{
+    fn dyn_rerank<'a>(
+        &'a self,
+        query: &'a str,
+        chunks: Vec<ScoredChunk>,
+        top_n: NonZeroU32,
+    ) -> DynFuture<'a, Vec<ScoredChunk>> {
+        Box::pin(async move {
+            AsyncReranker::rerank(self, query, chunks, top_n)
+                .await
+                .map_err(DynError::new)
+        })
+    }
+}
+
+impl AsyncReranker for Box<dyn DynAsyncReranker> {
+    type ErrorType = DynError;
+
+    async fn rerank(
+        &self,
+        query: &str,
+        chunks: Vec<ScoredChunk>,
+        top_n: NonZeroU32,
+    ) -> Result<Vec<ScoredChunk>, DynError> {
+        DynAsyncReranker::dyn_rerank(self.as_ref(), query, chunks, top_n).await
+    }
+}
+
+/// # [`RerankingRetriever`]
+///
+/// Wraps any [`AsyncRetriever`] so what it returns is reranked. The `top_k` each method is
+/// called with becomes the number of candidates retrieved, and at most `top_n` of them are
+/// returned after reranking. The scores returned are the reranker's rather than the
+/// retriever's, so a [`crate::chains::BasicRAGChain`] minimum score applies to those.
+///
+/// # Examples
+/// ```
+/// use rag_toolchain::chains::*;
+/// use rag_toolchain::clients::*;
+/// use rag_toolchain::retrievers::*;
+/// use std::num::NonZeroU32;
+///
+/// async fn rerank_twenty_candidates<T, R, K>(chat_client: T, retriever: R, reranker: K)
+/// where
+///     T: AsyncChatClient,
+///     R: AsyncRetriever,
+///     K: AsyncReranker,
+/// {
+///     let retriever = RerankingRetriever::new(retriever, reranker, NonZeroU32::new(3).unwrap());
+///     let chain = BasicRAGChain::builder()
+///         .chat_client(chat_client)
+///         .retriever(retriever)
+///         .build();
+///     // Twenty candidates are retrieved and the best three are put in the prompt
+///     let message = PromptMessage::HumanMessage("how do refunds work".into());
+///     let _ = chain.invoke_chain(message, NonZeroU32::new(20).unwrap()).await;
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct RerankingRetriever<R, K> {
+    retriever: R,
+    reranker: K,
+    top_n: NonZeroU32,
+}
+
+impl<R, K> RerankingRetriever<R, K>
+where
+    R: AsyncRetriever,
+    K: AsyncReranker,
+{
+    /// # [`RerankingRetriever::new`]
+    ///
+    /// # Arguments
+    /// * `retriever`: [`R`] - the retriever the candidates are fetched with.
+    /// * `reranker`: [`K`] - reranks the candidates.
+    /// * `top_n`: [`NonZeroU32`] - the number of chunks kept after reranking.
+    ///
+    /// # Returns
+    /// * [`RerankingRetriever`] - the wrapped retriever.
+    pub fn new(retriever: R, reranker: K, top_n: NonZeroU32) -> Self {
+        RerankingRetriever {
+            retriever,
+            reranker,
+            top_n,
+        }
+    }
+
+    /// # [`RerankingRetriever::rerank`]
+    ///
+    /// Reranks the candidates, an empty set of candidates is never sent to the reranker.
+    async fn rerank(
+        &self,
+        text: &str,
+        candidates: Vec<ScoredChunk>,
+    ) -> Result<Vec<ScoredChunk>, RerankingRetrieverError<R::ErrorType, K::ErrorType>> {
+        if candidates.is_empty() {
+            return Ok(candidates);
+        }
+        self.reranker
+            .rerank(text, candidates, self.top_n)
+            .await
+            .map_err(RerankingRetrieverError::Reranker)
+    }
+}
+
+impl<R, K> AsyncRetriever for RerankingRetriever<R, K>
+where
+    R: AsyncRetriever,
+    K: AsyncReranker,
+{
+    type ErrorType = RerankingRetrieverError<R::ErrorType, K::ErrorType>;
+
+    async fn retrieve(&self, text: &str, top_k: NonZeroU32) -> Result<Chunks, Self::ErrorType> {
+        let scored: Vec<ScoredChunk> = self.retrieve_with_scores(text, top_k).await?;
+        Ok(into_chunks(scored))
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            name = "reranking_retriever.retrieve_with_context",
+            skip_all,
+            fields(top_k = top_k.get(), request_id = %context.request_id())
+        )
+    )]
+    async fn retrieve_with_context(
+        &self,
+        text: &str,
+        top_k: NonZeroU32,
+        context: &InvocationContext,
+    ) -> Result<Chunks, Self::ErrorType> {
+        let scored: Vec<ScoredChunk> = self
+            .retrieve_with_scores_and_context(text, top_k, context)
+            .await?;
+        Ok(into_chunks(scored))
+    }
+
+    async fn retrieve_with_scores(
+        &self,
+        text: &str,
+        top_k: NonZeroU32,
+    ) -> Result<Vec<ScoredChunk>, Self::ErrorType> {
+        let candidates: Vec<ScoredChunk> = self
+            .retriever
+            .retrieve_with_scores(text, top_k)
+            .await
+            .map_err(RerankingRetrieverError::Retriever)?;
+        self.rerank(text, candidates).await
+    }
+
+    async fn retrieve_with_scores_and_context(
+        &self,
+        text: &str,
+        top_k: NonZeroU32,
+        context: &InvocationContext,
+    ) -> Result<Vec<ScoredChunk>, Self::ErrorType> {
+        let candidates: Vec<ScoredChunk> = self
+            .retriever
+            .retrieve_with_scores_and_context(text, top_k, context)
+            .await
+            .map_err(RerankingRetrieverError::Retriever)?;
+        self.rerank(text, candidates).await
+    }
+
+    async fn retrieve_mmr_with_scores(
+        &self,
+        text: &str,
+        top_k: NonZeroU32,
+        fetch_k: NonZeroU32,
+        lambda: f32,
+    ) -> Result<Vec<ScoredChunk>, Self::ErrorType> {
+        let candidates: Vec<ScoredChunk> = self
+            .retriever
+            .retrieve_mmr_with_scores(text, top_k, fetch_k, lambda)
+            .await
+            .map_err(RerankingRetrieverError::Retriever)?;
+        self.rerank(text, candidates).await
+    }
+
+    async fn fetch_by_metadata(&self, filter: &MetadataFilter) -> Result<Chunks, Self::ErrorType> {
+        self.retriever
+            .fetch_by_metadata(filter)
+            .await
+            .map_err(RerankingRetrieverError::Retriever)
+    }
+
+    fn max_top_k(&self) -> Option<NonZeroU32> {
+        self.retriever.max_top_k()
+    }
+}
+
+fn into_chunks(scored: Vec<ScoredChunk>) -> Chunks {
+    scored.into_iter().map(|scored| scored.chunk).collect()
+}
+
+#[derive(Error, Debug)]
+pub enum RerankingRetrieverError<R: Error, K: Error> {
+    #[error("Retriever Error: {0}")]
+    Retriever(R),
+    #[error("Reranker Error: {0}")]
+    Reranker(K),
+}
+
+#[cfg(test)]
+use mockall::*;
+#[cfg(test)]
+mock! {
+    pub AsyncReranker {}
+    impl AsyncReranker for AsyncReranker {
+        type ErrorType = std::io::Error;
+        async fn rerank(&self, query: &str, chunks: Vec<ScoredChunk>, top_n: NonZeroU32) -> Result<Vec<ScoredChunk>, <Self as AsyncReranker>::ErrorType>;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::Chunk;
+    use crate::retrievers::MockAsyncRetriever;
+    use mockall::predicate::eq;
+
+    fn scored(content: &str, score: f32) -> ScoredChunk {
+        ScoredChunk::new(Chunk::new(content), score)
+    }
+
+    #[tokio::test]
+    async fn pass_through_keeps_the_order_and_drops_the_rest() {
+        let chunks = vec![scored("a", 0.9), scored("b", 0.8), scored("c", 0.7)];
+        let reranked = PassThroughReranker
+            .rerank("query", chunks, NonZeroU32::new(2).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(reranked, vec![scored("a", 0.9), scored("b", 0.8)]);
+    }
+
+    #[tokio::test]
+    async fn boxed_rerankers_box_their_errors() {
+        let mut reranker = MockAsyncReranker::new();
+        reranker
+            .expect_rerank()
+            .returning(|_, _, _| Err(std::io::Error::other("rerank failed")));
+        let reranker: Box<dyn DynAsyncReranker> = Box::new(reranker);
+
+        let error = reranker
+            .rerank("query", vec![scored("a", 0.9)], NonZeroU32::new(1).unwrap())
+            .await
+            .unwrap_err();
+        assert_eq!(error.to_string(), "rerank failed");
+        assert!(error.downcast_ref::<std::io::Error>().is_some());
+    }
+
+    #[tokio::test]
+    async fn candidates_are_retrieved_with_top_k_and_reranked_to_top_n() {
+        let mut retriever = MockAsyncRetriever::new();
+        retriever
+            .expect_retrieve_with_scores()
+            .with(eq("refunds"), eq(NonZeroU32::new(3).unwrap()))
+            .times(1)
+            .returning(|_, _| Ok(vec![scored("a", 0.9), scored("b", 0.8), scored("c", 0.7)]));
+        let mut reranker = MockAsyncReranker::new();
+        reranker
+            .expect_rerank()
+            .with(
+                eq("refunds"),
+                eq(vec![scored("a", 0.9), scored("b", 0.8), scored("c", 0.7)]),
+                eq(NonZeroU32::new(1).unwrap()),
+            )
+            .times(1)
+            .returning(|_, _, _| Ok(vec![scored("c", 0.99)]));
+        let retriever = RerankingRetriever::new(retriever, reranker, NonZeroU32::new(1).unwrap());
+
+        let chunks = retriever
+            .retrieve("refunds", NonZeroU32::new(3).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(chunks, vec![Chunk::new("c")]);
+    }
+
+    #[tokio::test]
+    async fn no_candidates_are_never_reranked() {
+        let mut retriever = MockAsyncRetriever::new();
+        retriever
+            .expect_retrieve_with_scores()
+            .returning(|_, _| Ok(Vec::new()));
+        let mut reranker = MockAsyncReranker::new();
+        reranker.expect_rerank().never();
+        let retriever = RerankingRetriever::new(retriever, reranker, NonZeroU32::new(1).unwrap());
+
+        let scored = retriever
+            .retrieve_with_scores("refunds", NonZeroU32::new(3).unwrap())
+            .await
+            .unwrap();
+        assert!(scored.is_empty());
+    }
+
+    #[tokio::test]
+    async fn reranker_errors_are_returned() {
+        let mut retriever = MockAsyncRetriever::new();
+        retriever
+            .expect_retrieve_with_scores()
+            .returning(|_, _| Ok(vec![scored("a", 0.9)]));
+        let mut reranker = MockAsyncReranker::new();
+        reranker
+            .expect_rerank()
+            .returning(|_, _, _| Err(std::io::Error::other("rerank failed")));
+        let retriever = RerankingRetriever::new(retriever, reranker, NonZeroU32::new(1).unwrap());
+
+        let error = retriever
+            .retrieve("refunds", NonZeroU32::new(3).unwrap())
+            .await
+            .unwrap_err();
+        assert!(matches!(error, RerankingRetrieverError::Reranker(_)));
+    }
+}
@@ -0,0 +1,488 @@
+use crate::clients::{AsyncEmbeddingClient, EmbeddingTaskType};
+use crate::common::{Chunk, Chunks, Embedding, ScoredChunk};
+use crate::retrievers::distance_function::DistanceFunction;
+use crate::retrievers::metadata_filter::MetadataFilter;
+use crate::retrievers::mmr::select_mmr;
+use crate::retrievers::traits::AsyncRetriever;
+use crate::stores::sqlite_vector_store::{metadata_from_text, vector_from_blob, vector_to_blob};
+use futures::TryStreamExt;
+use sqlx::{Pool, Sqlite};
+use std::error::Error;
+use std::num::NonZeroU32;
+use thiserror::Error;
+
+/// # [`SqliteVectorRetriever`]
+///
+/// This struct allows for the retrieval of similar text from a SQLite database with the
+/// sqlite-vec extension. It is parameterized over a type T which implements the
+/// AsyncEmbeddingClient trait as text needs to be embedded before it can be compared.
+/// Create a [`crate::stores::SqliteVectorStore`] first and then call .as_retriever() on it.
+///
+/// The search is an exact scan of the table, sqlite-vec supports [`DistanceFunction::L2`]
+/// and [`DistanceFunction::Cosine`], searching with [`DistanceFunction::InnerProduct`]
+/// returns [`SqliteRetrieverError::UnsupportedDistanceFunction`].
+///
+/// # Examples
+/// ```
+/// use rag_toolchain::retrievers::*;
+/// use rag_toolchain::clients::*;
+/// use rag_toolchain::common::*;
+/// use rag_toolchain::stores::*;
+/// use std::num::NonZeroU32;
+///
+/// async fn retrieve() {
+///     let top_k: NonZeroU32 = NonZeroU32::new(5).unwrap();
+///     let embedding_model: OpenAIEmbeddingModel = OpenAIEmbeddingModel::TextEmbedding3Small;
+///     let client: OpenAIEmbeddingClient = OpenAIEmbeddingClient::try_new(embedding_model).unwrap();
+///     let store: SqliteVectorStore = SqliteVectorStore::try_new(":memory:", "table_name", embedding_model).await.unwrap();
+///     let retriever: SqliteVectorRetriever<OpenAIEmbeddingClient> = store.as_retriever(client, DistanceFunction::Cosine);
+///     // This will return the top 5 most similar chunks to the input text.
+///     let similar_text: Chunks = retriever.retrieve("text to search for", top_k).await.unwrap();
+/// }
+/// ```
+pub struct SqliteVectorRetriever<T: AsyncEmbeddingClient> {
+    pool: Pool<Sqlite>,
+    table_name: String,
+    embedding_client: T,
+    distance_function: DistanceFunction,
+}
+
+impl<T: AsyncEmbeddingClient> SqliteVectorRetriever<T> {
+    /// # [`SqliteVectorRetriever::new`]
+    /// This constructor is only used internally to allow .as_retriever methods to create a retriever.
+    ///
+    /// # Arguments
+    /// * `pool`: [`sqlx::Pool<Sqlite>`] - Which we can use to interact with the database.
+    /// * `table_name`: [`String`] - The name of the table which contains the vectors.
+    /// * `embedding_client`: [`T`] - An instance of a type which implements the AsyncEmbeddingClient trait.
+    /// * `distance_function`: [`DistanceFunction`] - The distance function to search with.
+    ///
+    /// # Returns
+    /// * [`SqliteVectorRetriever`] the created struct
+    pub(crate) fn new(
+        pool: Pool<Sqlite>,
+        table_name: String,
+        embedding_client: T,
+        distance_function: DistanceFunction,
+    ) -> Self {
+        SqliteVectorRetriever {
+            pool,
+            table_name,
+            embedding_client,
+            distance_function,
+        }
+    }
+
+    /// # [`SqliteVectorRetriever::distance_function`]
+    ///
+    /// Getter for the distance function the retriever searches with.
+    ///
+    /// # Returns
+    /// * &[`DistanceFunction`] - The distance function
+    pub fn distance_function(&self) -> &DistanceFunction {
+        &self.distance_function
+    }
+
+    /// # [`SqliteVectorRetriever::select_row_sql`]
+    ///
+    /// Helper function to generate the sql query for a similarity search, the vector is ?1
+    /// and top_k is ?2. `None` if sqlite-vec has no function for the distance.
+    fn select_row_sql(table_name: &str, distance_function: &DistanceFunction) -> Option<String> {
+        Self::select_sql("content, metadata", table_name, distance_function)
+    }
+
+    /// # [`SqliteVectorRetriever::select_row_with_embedding_sql`]
+    ///
+    /// The same as [`SqliteVectorRetriever::select_row_sql`] but the embedding of each row is
+    /// also selected, so the rows can be compared to each other for maximal marginal relevance.
+    fn select_row_with_embedding_sql(
+        table_name: &str,
+        distance_function: &DistanceFunction,
+    ) -> Option<String> {
+        Self::select_sql(
+            "content, metadata, embedding",
+            table_name,
+            distance_function,
+        )
+    }
+
+    /// # [`SqliteVectorRetriever::select_sql`]
+    /// Helper function to select the columns of the rows nearest to ?1, ordered by distance.
+    fn select_sql(
+        columns: &str,
+        table_name: &str,
+        distance_function: &DistanceFunction,
+    ) -> Option<String> {
+        let function: &str = match distance_function {
+            DistanceFunction::L2 => "vec_distance_l2",
+            DistanceFunction::Cosine => "vec_distance_cosine",
+            DistanceFunction::InnerProduct => return None,
+        };
+        Some(format!(
+            "SELECT {}, {}(embedding, ?1) AS distance FROM {} ORDER BY distance LIMIT ?2",
+            columns, function, table_name
+        ))
+    }
+
+    /// # [`SqliteVectorRetriever::select_all_rows_sql`]
+    /// Helper function to generate the sql query selecting every row in the order they were stored
+    fn select_all_rows_sql(table_name: &str) -> String {
+        format!(
+            "SELECT content, metadata FROM {} ORDER BY rowid",
+            table_name
+        )
+    }
+
+    /// # [`SqliteVectorRetriever::embed_query`]
+    ///
+    /// Checks the distance function can be searched with before embedding the text, so an
+    /// unsupported distance costs nothing, and returns the query with the embedding to bind.
+    async fn embed_query(
+        &self,
+        text: &str,
+        query: Option<String>,
+    ) -> Result<(String, Vec<u8>), SqliteRetrieverError<T::ErrorType>> {
+        let query: String = query.ok_or_else(|| {
+            SqliteRetrieverError::UnsupportedDistanceFunction(self.distance_function.clone())
+        })?;
+        let embedding: Embedding = self
+            .embedding_client
+            .generate_embedding_for(Chunk::new(text), EmbeddingTaskType::Query)
+            .await
+            .map_err(SqliteRetrieverError::EmbeddingClientError)?;
+        Ok((query, vector_to_blob(&embedding.vector())))
+    }
+
+    /// # [`SqliteVectorRetriever::search`]
+    ///
+    /// Embeds the text and runs the similarity search, shared by the retrieve methods.
+    /// The distance of each row is turned into a score with [`DistanceFunction::to_score`].
+    ///
+    /// # Arguments
+    /// * `text`: &[`str`] - The text we are searching for similar text against.
+    /// * `top_k`: [`NonZeroU32`] - The number of results to return.
+    async fn search(
+        &self,
+        text: &str,
+        top_k: NonZeroU32,
+    ) -> Result<Vec<ScoredChunk>, SqliteRetrieverError<T::ErrorType>> {
+        let select: Option<String> =
+            Self::select_row_sql(&self.table_name, &self.distance_function);
+        let (query, embedding) = self.embed_query(text, select).await?;
+
+        sqlx::query_as::<_, (String, Option<String>, f64)>(&query)
+            .bind(embedding)
+            .bind(i64::from(top_k.get()))
+            .fetch(&self.pool)
+            .map_ok(|(content, metadata, distance)| {
+                let chunk: Chunk = Chunk::new_with_metadata(content, metadata_from_text(metadata));
+                ScoredChunk::new(chunk, self.distance_function.to_score(distance))
+            })
+            .try_collect()
+            .await
+            .map_err(SqliteRetrieverError::QueryError)
+    }
+}
+
+impl<T> AsyncRetriever for SqliteVectorRetriever<T>
+where
+    T: AsyncEmbeddingClient,
+    T::ErrorType: 'static,
+{
+    // We parameterize over the error type of the embedding client.
+    type ErrorType = SqliteRetrieverError<T::ErrorType>;
+
+    /// # [`SqliteVectorRetriever::retrieve`]
+    ///
+    /// Implementation of the retrieve function for [`SqliteVectorRetriever`].
+    /// This allows us to retrieve similar text from the vector database.
+    ///
+    /// # Arguments
+    /// * `text`: &[`str`] - The text we are searching for similar text against.
+    /// * `top_k`: [`NonZeroU32`] - The number of results to return.
+    ///
+    /// # Errors
+    /// * [`SqliteRetrieverError::UnsupportedDistanceFunction`] - If sqlite-vec cannot search with the distance function.
+    /// * [`SqliteRetrieverError::EmbeddingClientError`] - If the embedding client returns an error.
+    /// * [`SqliteRetrieverError::QueryError`] - If there is an error querying the database.
+    ///
+    /// # Returns
+    /// * [`Chunks`] which are the most similar to the input text.
+    async fn retrieve(&self, text: &str, top_k: NonZeroU32) -> Result<Chunks, Self::ErrorType> {
+        Ok(self
+            .search(text, top_k)
+            .await?
+            .into_iter()
+            .map(|scored| scored.chunk)
+            .collect())
+    }
+
+    /// # [`SqliteVectorRetriever::retrieve_with_scores`]
+    ///
+    /// The same as [`SqliteVectorRetriever::retrieve`] but each chunk comes with a score
+    /// of how similar it is to the input text, a higher score is more similar.
+    ///
+    /// # Arguments
+    /// * `text`: &[`str`] - The text we are searching for similar text against.
+    /// * `top_k`: [`NonZeroU32`] - The number of results to return.
+    ///
+    /// # Errors
+    /// * [`SqliteRetrieverError::UnsupportedDistanceFunction`] - If sqlite-vec cannot search with the distance function.
+    /// * [`SqliteRetrieverError::EmbeddingClientError`] - If the embedding client returns an error.
+    /// * [`SqliteRetrieverError::QueryError`] - If there is an error querying the database.
+    ///
+    /// # Returns
+    /// * [`Vec<ScoredChunk>`] which are the most similar to the input text, most similar first.
+    async fn retrieve_with_scores(
+        &self,
+        text: &str,
+        top_k: NonZeroU32,
+    ) -> Result<Vec<ScoredChunk>, Self::ErrorType> {
+        self.search(text, top_k).await
+    }
+
+    /// # [`SqliteVectorRetriever::retrieve_mmr_with_scores`]
+    ///
+    /// Fetches the fetch_k most similar rows along with their embeddings and picks top_k of
+    /// them by maximal marginal relevance, see [`AsyncRetriever::retrieve_mmr`]. The similarity
+    /// between chunks is measured with the retrievers distance function.
+    ///
+    /// # Arguments
+    /// * `text`: &[`str`] - The text we are searching for similar text against.
+    /// * `top_k`: [`NonZeroU32`] - The number of results to return.
+    /// * `fetch_k`: [`NonZeroU32`] - The number of rows to pick from, at least top_k are fetched.
+    /// * `lambda`: [`f32`] - From 0 to 1, the weight given to similarity to the text over diversity.
+    ///
+    /// # Errors
+    /// * [`SqliteRetrieverError::UnsupportedDistanceFunction`] - If sqlite-vec cannot search with the distance function.
+    /// * [`SqliteRetrieverError::EmbeddingClientError`] - If the embedding client returns an error.
+    /// * [`SqliteRetrieverError::QueryError`] - If there is an error querying the database.
+    ///
+    /// # Returns
+    /// * [`Vec<ScoredChunk>`] in the order they were picked.
+    async fn retrieve_mmr_with_scores(
+        &self,
+        text: &str,
+        top_k: NonZeroU32,
+        fetch_k: NonZeroU32,
+        lambda: f32,
+    ) -> Result<Vec<ScoredChunk>, Self::ErrorType> {
+        let select: Option<String> =
+            Self::select_row_with_embedding_sql(&self.table_name, &self.distance_function);
+        let (query, embedding) = self.embed_query(text, select).await?;
+
+        let candidates: Vec<(ScoredChunk, Vec<f32>)> =
+            sqlx::query_as::<_, (String, Option<String>, Vec<u8>, f64)>(&query)
+                .bind(embedding)
+                .bind(i64::from(fetch_k.max(top_k).get()))
+                .fetch(&self.pool)
+                .map_ok(|(content, metadata, vector, distance)| {
+                    let chunk: Chunk =
+                        Chunk::new_with_metadata(content, metadata_from_text(metadata));
+                    let score: f32 = self.distance_function.to_score(distance);
+                    (ScoredChunk::new(chunk, score), vector_from_blob(&vector))
+                })
+                .try_collect()
+                .await
+                .map_err(SqliteRetrieverError::QueryError)?;
+        Ok(select_mmr(
+            candidates,
+            &self.distance_function,
+            top_k.get() as usize,
+            lambda,
+        ))
+    }
+
+    /// # [`SqliteVectorRetriever::fetch_by_metadata`]
+    ///
+    /// Scans the table for the rows whose metadata matches the filter, see
+    /// [`MetadataFilter::matches`]. The metadata is stored as text so the filter is applied
+    /// as the rows are read rather than in the query. Nothing is embedded.
+    ///
+    /// # Arguments
+    /// * `filter`: &[`MetadataFilter`] - The filter the metadata of each chunk must match.
+    ///
+    /// # Errors
+    /// * [`SqliteRetrieverError::QueryError`] - If there is an error querying the database.
+    ///
+    /// # Returns
+    /// * [`Chunks`] which match the filter, in the order they were stored.
+    async fn fetch_by_metadata(&self, filter: &MetadataFilter) -> Result<Chunks, Self::ErrorType> {
+        let query: String = Self::select_all_rows_sql(&self.table_name);
+        sqlx::query_as::<_, (String, Option<String>)>(&query)
+            .fetch(&self.pool)
+            .try_filter_map(|(content, metadata)| async move {
+                let chunk: Chunk = Chunk::new_with_metadata(content, metadata_from_text(metadata));
+                Ok(filter.matches(chunk.metadata()).then_some(chunk))
+            })
+            .try_collect()
+            .await
+            .map_err(SqliteRetrieverError::QueryError)
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum SqliteRetrieverError<T: Error> {
+    #[error("Embedding Client Error: {0}")]
+    EmbeddingClientError(T),
+    #[error("Embedding Retrieving Similar Text: {0}")]
+    QueryError(sqlx::Error),
+    #[error("Unsupported Distance Function: sqlite-vec cannot search with {0:?}")]
+    UnsupportedDistanceFunction(DistanceFunction),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::OpenAIEmbeddingModel::TextEmbeddingAda002;
+    use crate::retrievers::lookup_embedding_client::LookupEmbeddingClient;
+    use crate::stores::{EmbeddingStore, SqliteVectorStore};
+    use serde_json::json;
+    use sqlx::sqlite::SqlitePoolOptions;
+
+    const DIMENSIONS: usize = 1536;
+
+    // A vector pointing mostly along one axis
+    fn axis(index: usize, other: f32) -> Vec<f32> {
+        let mut vector: Vec<f32> = vec![other; DIMENSIONS];
+        vector[index] = 1.0;
+        vector
+    }
+
+    fn lookup_client() -> LookupEmbeddingClient {
+        LookupEmbeddingClient(vec![("first", axis(0, 0.0)), ("second", axis(1, 0.0))])
+    }
+
+    #[test]
+    fn select_row_sql_for_each_distance_function() {
+        type Retriever = SqliteVectorRetriever<LookupEmbeddingClient>;
+        assert_eq!(
+            Retriever::select_row_sql("docs", &DistanceFunction::Cosine).unwrap(),
+            "SELECT content, metadata, vec_distance_cosine(embedding, ?1) AS distance FROM docs ORDER BY distance LIMIT ?2"
+        );
+        assert_eq!(
+            Retriever::select_row_sql("docs", &DistanceFunction::L2).unwrap(),
+            "SELECT content, metadata, vec_distance_l2(embedding, ?1) AS distance FROM docs ORDER BY distance LIMIT ?2"
+        );
+        assert_eq!(
+            Retriever::select_row_sql("docs", &DistanceFunction::InnerProduct),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn mmr_prefers_a_diverse_chunk_over_a_near_duplicate() {
+        let store = SqliteVectorStore::try_new(":memory:", "docs", TextEmbeddingAda002)
+            .await
+            .unwrap();
+        // The two chunks about first point almost the same way
+        let mut near_duplicate: Vec<f32> = axis(0, 0.0);
+        near_duplicate[2] = 0.05;
+        let embeddings: Vec<Embedding> = vec![
+            Embedding::new(Chunk::new("about first"), axis(0, 0.0)),
+            Embedding::new(Chunk::new("also about first"), near_duplicate),
+            Embedding::new(Chunk::new("about second"), axis(1, 0.0)),
+        ];
+        store.store_batch(embeddings).await.unwrap();
+
+        let retriever = store.as_retriever(lookup_client(), DistanceFunction::Cosine);
+        let top_k = NonZeroU32::new(2).unwrap();
+        let fetch_k = NonZeroU32::new(3).unwrap();
+        let diverse: Chunks = retriever
+            .retrieve_mmr("first", top_k, fetch_k, 0.5)
+            .await
+            .unwrap();
+        let contents: Vec<&str> = diverse.iter().map(|chunk| chunk.content()).collect();
+        assert_eq!(contents, vec!["about first", "about second"]);
+
+        let similar: Chunks = retriever
+            .retrieve_mmr("first", top_k, fetch_k, 1.0)
+            .await
+            .unwrap();
+        let contents: Vec<&str> = similar.iter().map(|chunk| chunk.content()).collect();
+        assert_eq!(contents, vec!["about first", "also about first"]);
+    }
+
+    #[tokio::test]
+    async fn fetch_by_metadata_returns_matching_chunks_without_embedding() {
+        let store = SqliteVectorStore::try_new(":memory:", "docs", TextEmbeddingAda002)
+            .await
+            .unwrap();
+        let section = |index: u32| {
+            Chunk::new_with_metadata(
+                format!("section {}", index),
+                json!({"document_id": "guide", "chunk_index": index}),
+            )
+        };
+        let embeddings: Vec<Embedding> = vec![
+            Embedding::new(section(0), axis(0, 0.0)),
+            Embedding::new(Chunk::new("no metadata"), axis(1, 0.0)),
+            Embedding::new(section(1), axis(2, 0.0)),
+            Embedding::new(section(2), axis(3, 0.0)),
+        ];
+        store.store_batch(embeddings).await.unwrap();
+
+        // The client cannot embed anything so the fetch must not embed
+        let retriever =
+            store.as_retriever(LookupEmbeddingClient(Vec::new()), DistanceFunction::Cosine);
+        let filter: MetadataFilter = MetadataFilter::key("document_id")
+            .eq("guide")
+            .and(MetadataFilter::key("chunk_index").lte(1));
+        let chunks: Chunks = retriever.fetch_by_metadata(&filter).await.unwrap();
+        assert_eq!(chunks, vec![section(0), section(1)]);
+    }
+
+    #[tokio::test]
+    async fn inner_product_is_rejected_before_embedding() {
+        let pool = SqlitePoolOptions::new().connect_lazy(":memory:").unwrap();
+        let retriever = SqliteVectorRetriever::new(
+            pool,
+            "docs".into(),
+            LookupEmbeddingClient(Vec::new()),
+            DistanceFunction::InnerProduct,
+        );
+        let error = retriever
+            .retrieve("unknown", NonZeroU32::new(1).unwrap())
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            error,
+            SqliteRetrieverError::UnsupportedDistanceFunction(DistanceFunction::InnerProduct)
+        ));
+    }
+
+    #[tokio::test]
+    async fn stored_embeddings_are_retrieved_most_similar_first() {
+        let store = SqliteVectorStore::try_new(":memory:", "docs", TextEmbeddingAda002)
+            .await
+            .unwrap();
+        let embeddings: Vec<Embedding> = vec![
+            Embedding::new(
+                Chunk::new_with_metadata("about first", json!({"page": 1})),
+                axis(0, 0.1),
+            ),
+            Embedding::new(Chunk::new("about second"), axis(1, 0.1)),
+        ];
+        store.store_batch(embeddings).await.unwrap();
+
+        for distance_function in [DistanceFunction::Cosine, DistanceFunction::L2] {
+            let retriever = store.as_retriever(lookup_client(), distance_function);
+            let scored: Vec<ScoredChunk> = retriever
+                .retrieve_with_scores("first", NonZeroU32::new(2).unwrap())
+                .await
+                .unwrap();
+            let contents: Vec<&str> = scored.iter().map(|scored| scored.chunk.content()).collect();
+            assert_eq!(contents, vec!["about first", "about second"]);
+            assert_eq!(scored[0].chunk.metadata(), &json!({"page": 1}));
+            assert_eq!(scored[1].chunk.metadata(), &serde_json::Value::Null);
+            assert!(scored[0].score > scored[1].score);
+
+            let chunks: Chunks = retriever
+                .retrieve("second", NonZeroU32::new(1).unwrap())
+                .await
+                .unwrap();
+            assert_eq!(chunks.len(), 1);
+            assert_eq!(chunks[0].content(), "about second");
+        }
+    }
+}
@@ -1,4 +1,5 @@
-use crate::common::Chunks;
+use crate::common::{Chunks, InvocationContext, ScoredChunk};
+use crate::retrievers::{BatchRetrieveError, MetadataFilter};
 use std::future::Future;
 use std::{error::Error, num::NonZeroU32};
 
@@ -8,9 +9,9 @@ use std::{error::Error, num::NonZeroU32};
 /// The common workflow is to instaniate a store and then call .as_retriever()
 /// to get a retriever for that vector database. Then you can call the methods
 /// described by this trait to retrieve similar text.
-pub trait AsyncRetriever {
+pub trait AsyncRetriever: Send + Sync {
     /// Custom error type for the retriever
-    type ErrorType: Error;
+    type ErrorType: Error + Send + Sync;
     /// # [`AsyncRetriever::retrieve`]
     ///
     /// This method is used to retrieve similar text from the store.
@@ -32,6 +33,298 @@ pub trait AsyncRetriever {
         text: &str,
         top_k: NonZeroU32,
     ) -> impl Future<Output = Result<Chunks, Self::ErrorType>> + Send;
+
+    /// # [`AsyncRetriever::retrieve_with_context`]
+    ///
+    /// The same as [`AsyncRetriever::retrieve`] but with an [`InvocationContext`] so the
+    /// retriever can tag the work it does with the request id. By default the context is
+    /// ignored and [`AsyncRetriever::retrieve`] is called.
+    ///
+    /// # Arguments
+    /// * `text`: &[`str`] - The input text to search for similar text.
+    /// * `top_k`: [`NonZeroU32`] - The number of similar text to return.
+    /// * `context`: &[`InvocationContext`] - The context of the invocation.
+    ///
+    /// # Errors
+    /// * [`Self::ErrorType`] - If the operation failed.
+    ///
+    /// # Returns
+    /// * [`Chunks`] - The most similar text to the input text.
+    fn retrieve_with_context(
+        &self,
+        text: &str,
+        top_k: NonZeroU32,
+        _context: &InvocationContext,
+    ) -> impl Future<Output = Result<Chunks, Self::ErrorType>> + Send {
+        self.retrieve(text, top_k)
+    }
+
+    /// # [`AsyncRetriever::retrieve_batch`]
+    ///
+    /// Retrieves for many queries at once, such as when evaluating retrieval over a set of
+    /// questions. By default [`AsyncRetriever::retrieve`] is called for each query in turn,
+    /// retrievers which can embed or search for several queries together override this.
+    ///
+    /// # Arguments
+    /// * `queries`: &[`[&str]`] - The input texts to search for similar text.
+    /// * `top_k`: [`NonZeroU32`] - The number of similar text to return for each query.
+    ///
+    /// # Errors
+    /// * [`BatchRetrieveError::Query`] - If a query failed, with its index in `queries`.
+    /// * [`BatchRetrieveError::Batch`] - If work shared by all the queries failed.
+    ///
+    /// # Returns
+    /// * [`Vec<Chunks>`] - The most similar text to each query, in the order of `queries`.
+    fn retrieve_batch(
+        &self,
+        queries: &[&str],
+        top_k: NonZeroU32,
+    ) -> impl Future<Output = Result<Vec<Chunks>, BatchRetrieveError<Self::ErrorType>>> + Send {
+        async move {
+            let mut results: Vec<Chunks> = Vec::with_capacity(queries.len());
+            for (index, text) in queries.iter().enumerate() {
+                let chunks: Chunks = self
+                    .retrieve(text, top_k)
+                    .await
+                    .map_err(|error| BatchRetrieveError::Query { index, error })?;
+                results.push(chunks);
+            }
+            Ok(results)
+        }
+    }
+
+    /// # [`AsyncRetriever::retrieve_with_scores`]
+    ///
+    /// The same as [`AsyncRetriever::retrieve`] but each chunk comes with a score of how
+    /// similar it is to the input text, a higher score is more similar. This allows
+    /// results to be thresholded on their similarity.
+    ///
+    /// By default [`AsyncRetriever::retrieve`] is called and each chunk is scored by its rank,
+    /// `1 / rank` starting from a rank of 1. This keeps the order but is not a similarity, so
+    /// retrievers which know how similar their results are should override this.
+    ///
+    /// # Arguments
+    /// * `text`: &[`str`] - The input text to search for similar text.
+    /// * `top_k`: [`NonZeroU32`] - The number of similar text to return.
+    ///
+    /// # Errors
+    /// * [`Self::ErrorType`] - If the operation failed.
+    ///
+    /// # Returns
+    /// * [`Vec<ScoredChunk>`] - The most similar text to the input text, most similar first.
+    fn retrieve_with_scores(
+        &self,
+        text: &str,
+        top_k: NonZeroU32,
+    ) -> impl Future<Output = Result<Vec<ScoredChunk>, Self::ErrorType>> + Send {
+        let chunks = self.retrieve(text, top_k);
+        async move {
+            Ok(chunks
+                .await?
+                .into_iter()
+                .enumerate()
+                .map(|(rank, chunk)| ScoredChunk::new(chunk, 1.0 / (rank + 1) as f32))
+                .collect())
+        }
+    }
+
+    /// # [`AsyncRetriever::retrieve_with_scores_and_context`]
+    ///
+    /// The same as [`AsyncRetriever::retrieve_with_scores`] but with an [`InvocationContext`].
+    /// By default the context is ignored and [`AsyncRetriever::retrieve_with_scores`] is called.
+    ///
+    /// # Arguments
+    /// * `text`: &[`str`] - The input text to search for similar text.
+    /// * `top_k`: [`NonZeroU32`] - The number of similar text to return.
+    /// * `context`: &[`InvocationContext`] - The context of the invocation.
+    ///
+    /// # Errors
+    /// * [`Self::ErrorType`] - If the operation failed.
+    ///
+    /// # Returns
+    /// * [`Vec<ScoredChunk>`] - The most similar text to the input text, most similar first.
+    fn retrieve_with_scores_and_context(
+        &self,
+        text: &str,
+        top_k: NonZeroU32,
+        _context: &InvocationContext,
+    ) -> impl Future<Output = Result<Vec<ScoredChunk>, Self::ErrorType>> + Send {
+        self.retrieve_with_scores(text, top_k)
+    }
+
+    /// # [`AsyncRetriever::retrieve_mmr`]
+    ///
+    /// Retrieves chunks which are similar to the input text but not to each other, by
+    /// maximal marginal relevance. The fetch_k most similar chunks are fetched along with
+    /// their vectors and top_k of them are picked one at a time, each time taking the chunk
+    /// which best balances its similarity to the input text against its similarity to the
+    /// chunks already picked. By default retrievers which cannot compare their chunks return
+    /// the top_k most similar chunks as [`AsyncRetriever::retrieve`] does.
+    ///
+    /// # Arguments
+    /// * `text`: &[`str`] - The input text to search for similar text.
+    /// * `top_k`: [`NonZeroU32`] - The number of similar text to return.
+    /// * `fetch_k`: [`NonZeroU32`] - The number of candidates to pick from, at least top_k are fetched.
+    /// * `lambda`: [`f32`] - From 0 to 1, the weight given to similarity to the input text over
+    ///   diversity. 1 gives the same chunks as [`AsyncRetriever::retrieve`].
+    ///
+    /// # Errors
+    /// * [`Self::ErrorType`] - If the operation failed.
+    ///
+    /// # Returns
+    /// * [`Chunks`] - The chunks in the order they were picked.
+    fn retrieve_mmr(
+        &self,
+        text: &str,
+        top_k: NonZeroU32,
+        fetch_k: NonZeroU32,
+        lambda: f32,
+    ) -> impl Future<Output = Result<Chunks, Self::ErrorType>> + Send {
+        async move {
+            let scored: Vec<ScoredChunk> = self
+                .retrieve_mmr_with_scores(text, top_k, fetch_k, lambda)
+                .await?;
+            Ok(scored.into_iter().map(|scored| scored.chunk).collect())
+        }
+    }
+
+    /// # [`AsyncRetriever::retrieve_mmr_with_scores`]
+    ///
+    /// The same as [`AsyncRetriever::retrieve_mmr`] but each chunk comes with its score against
+    /// the input text, see [`AsyncRetriever::retrieve_with_scores`]. As the chunks are in the
+    /// order they were picked the scores are not necessarily descending. By default
+    /// [`AsyncRetriever::retrieve_with_scores`] is called.
+    ///
+    /// # Arguments
+    /// * `text`: &[`str`] - The input text to search for similar text.
+    /// * `top_k`: [`NonZeroU32`] - The number of similar text to return.
+    /// * `fetch_k`: [`NonZeroU32`] - The number of candidates to pick from, at least top_k are fetched.
+    /// * `lambda`: [`f32`] - From 0 to 1, the weight given to similarity to the input text over diversity.
+    ///
+    /// # Errors
+    /// * [`Self::ErrorType`] - If the operation failed.
+    ///
+    /// # Returns
+    /// * [`Vec<ScoredChunk>`] - The chunks in the order they were picked.
+    fn retrieve_mmr_with_scores(
+        &self,
+        text: &str,
+        top_k: NonZeroU32,
+        _fetch_k: NonZeroU32,
+        _lambda: f32,
+    ) -> impl Future<Output = Result<Vec<ScoredChunk>, Self::ErrorType>> + Send {
+        self.retrieve_with_scores(text, top_k)
+    }
+
+    /// # [`AsyncRetriever::fetch_by_metadata`]
+    ///
+    /// Fetches every chunk whose metadata matches the filter without a similarity search,
+    /// such as the chunks either side of a retrieved chunk, see
+    /// [`crate::chains::NeighborExpansion`]. The filter should be narrow as nothing bounds how
+    /// many chunks are returned. There is no default, a retriever which cannot look up its
+    /// chunks by metadata should return an error rather than no chunks so a chain relying on
+    /// it fails instead of silently going without.
+    ///
+    /// # Arguments
+    /// * `filter`: &[`MetadataFilter`] - The filter the metadata of each chunk must match.
+    ///
+    /// # Errors
+    /// * [`Self::ErrorType`] - If the operation failed.
+    ///
+    /// # Returns
+    /// * [`Chunks`] - The matching chunks, in no particular order.
+    fn fetch_by_metadata(
+        &self,
+        filter: &MetadataFilter,
+    ) -> impl Future<Output = Result<Chunks, Self::ErrorType>> + Send;
+
+    /// # [`AsyncRetriever::max_top_k`]
+    ///
+    /// The largest top_k the retriever will accept, chains use this to reject
+    /// a bad top_k before doing any work. Defaults to no bound.
+    ///
+    /// # Returns
+    /// * [`Option<NonZeroU32>`] - The bound if the retriever has one.
+    fn max_top_k(&self) -> Option<NonZeroU32> {
+        None
+    }
+}
+
+/// # [`FilteredRetriever`]
+///
+/// A retriever which can restrict its search to chunks whose metadata matches a
+/// [`MetadataFilter`]. This is a separate trait so retrievers without metadata only
+/// have to implement [`AsyncRetriever`].
+pub trait FilteredRetriever: AsyncRetriever {
+    /// # [`FilteredRetriever::retrieve_with_filter`]
+    ///
+    /// The same as [`AsyncRetriever::retrieve`] but only chunks whose metadata
+    /// matches the filter are searched.
+    ///
+    /// # Arguments
+    /// * `text`: &[`str`] - The input text to search for similar text.
+    /// * `top_k`: [`NonZeroU32`] - The number of similar text to return.
+    /// * `filter`: &[`MetadataFilter`] - The filter the metadata of each result must match.
+    ///
+    /// # Errors
+    /// * [`Self::ErrorType`] - If the operation failed.
+    ///
+    /// # Returns
+    /// * [`Chunks`] - The most similar text to the input text which matches the filter.
+    fn retrieve_with_filter(
+        &self,
+        text: &str,
+        top_k: NonZeroU32,
+        filter: &MetadataFilter,
+    ) -> impl Future<Output = Result<Chunks, Self::ErrorType>> + Send;
+
+    /// # [`FilteredRetriever::retrieve_with_filter_and_scores`]
+    ///
+    /// The same as [`FilteredRetriever::retrieve_with_filter`] but each chunk comes with a
+    /// score, see [`AsyncRetriever::retrieve_with_scores`].
+    ///
+    /// # Arguments
+    /// * `text`: &[`str`] - The input text to search for similar text.
+    /// * `top_k`: [`NonZeroU32`] - The number of similar text to return.
+    /// * `filter`: &[`MetadataFilter`] - The filter the metadata of each result must match.
+    ///
+    /// # Errors
+    /// * [`Self::ErrorType`] - If the operation failed.
+    ///
+    /// # Returns
+    /// * [`Vec<ScoredChunk>`] - The most similar text to the input text which matches the filter.
+    fn retrieve_with_filter_and_scores(
+        &self,
+        text: &str,
+        top_k: NonZeroU32,
+        filter: &MetadataFilter,
+    ) -> impl Future<Output = Result<Vec<ScoredChunk>, Self::ErrorType>> + Send;
+
+    /// # [`FilteredRetriever::retrieve_with_filter_and_context`]
+    ///
+    /// The same as [`FilteredRetriever::retrieve_with_filter`] but with an [`InvocationContext`].
+    /// By default the context is ignored and [`FilteredRetriever::retrieve_with_filter`] is called.
+    ///
+    /// # Arguments
+    /// * `text`: &[`str`] - The input text to search for similar text.
+    /// * `top_k`: [`NonZeroU32`] - The number of similar text to return.
+    /// * `filter`: &[`MetadataFilter`] - The filter the metadata of each result must match.
+    /// * `context`: &[`InvocationContext`] - The context of the invocation.
+    ///
+    /// # Errors
+    /// * [`Self::ErrorType`] - If the operation failed.
+    ///
+    /// # Returns
+    /// * [`Chunks`] - The most similar text to the input text which matches the filter.
+    fn retrieve_with_filter_and_context(
+        &self,
+        text: &str,
+        top_k: NonZeroU32,
+        filter: &MetadataFilter,
+        _context: &InvocationContext,
+    ) -> impl Future<Output = Result<Chunks, Self::ErrorType>> + Send {
+        self.retrieve_with_filter(text, top_k, filter)
+    }
 }
 
 #[cfg(test)]
@@ -42,5 +335,109 @@ mock! {
     impl AsyncRetriever for AsyncRetriever {
         type ErrorType = std::io::Error;
         async fn retrieve(&self, text: &str, top_k: NonZeroU32) -> Result<Chunks, <Self as AsyncRetriever>::ErrorType>;
+        async fn retrieve_with_scores(&self, text: &str, top_k: NonZeroU32) -> Result<Vec<ScoredChunk>, <Self as AsyncRetriever>::ErrorType>;
+        async fn retrieve_mmr_with_scores(&self, text: &str, top_k: NonZeroU32, fetch_k: NonZeroU32, lambda: f32) -> Result<Vec<ScoredChunk>, <Self as AsyncRetriever>::ErrorType>;
+        async fn fetch_by_metadata(&self, filter: &MetadataFilter) -> Result<Chunks, <Self as AsyncRetriever>::ErrorType>;
+    }
+}
+#[cfg(test)]
+mock! {
+    pub FilteredRetriever {}
+    impl AsyncRetriever for FilteredRetriever {
+        type ErrorType = std::io::Error;
+        async fn retrieve(&self, text: &str, top_k: NonZeroU32) -> Result<Chunks, <Self as AsyncRetriever>::ErrorType>;
+        async fn retrieve_with_scores(&self, text: &str, top_k: NonZeroU32) -> Result<Vec<ScoredChunk>, <Self as AsyncRetriever>::ErrorType>;
+        async fn fetch_by_metadata(&self, filter: &MetadataFilter) -> Result<Chunks, <Self as AsyncRetriever>::ErrorType>;
+    }
+    impl FilteredRetriever for FilteredRetriever {
+        async fn retrieve_with_filter(&self, text: &str, top_k: NonZeroU32, filter: &MetadataFilter) -> Result<Chunks, <Self as AsyncRetriever>::ErrorType>;
+        async fn retrieve_with_filter_and_scores(&self, text: &str, top_k: NonZeroU32, filter: &MetadataFilter) -> Result<Vec<ScoredChunk>, <Self as AsyncRetriever>::ErrorType>;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::Chunk;
+    use mockall::predicate::eq;
+
+    #[tokio::test]
+    async fn retrieve_batch_retrieves_each_query_in_order() {
+        let mut retriever = MockAsyncRetriever::new();
+        for query in ["first", "second"] {
+            retriever
+                .expect_retrieve()
+                .with(eq(query), eq(NonZeroU32::new(2).unwrap()))
+                .times(1)
+                .returning(move |text, _| Ok(vec![Chunk::new(text)]));
+        }
+        let results = retriever
+            .retrieve_batch(&["first", "second"], NonZeroU32::new(2).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(
+            results,
+            vec![vec![Chunk::new("first")], vec![Chunk::new("second")]]
+        );
+    }
+
+    #[tokio::test]
+    async fn retrieve_batch_reports_the_failed_query() {
+        let mut retriever = MockAsyncRetriever::new();
+        retriever
+            .expect_retrieve()
+            .with(eq("bad"), eq(NonZeroU32::new(1).unwrap()))
+            .returning(|_, _| Err(std::io::Error::other("search failed")));
+        retriever
+            .expect_retrieve()
+            .returning(|text, _| Ok(vec![Chunk::new(text)]));
+        let error = retriever
+            .retrieve_batch(&["good", "bad", "good"], NonZeroU32::new(1).unwrap())
+            .await
+            .unwrap_err();
+        assert_eq!(error.index(), Some(1));
+        assert_eq!(error.to_string(), "Query 1 failed: search failed");
+    }
+
+    #[tokio::test]
+    async fn retrieve_with_scores_defaults_to_rank_scores() {
+        let scored = RankedRetriever
+            .retrieve_with_scores("query", NonZeroU32::new(3).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(
+            scored,
+            vec![
+                ScoredChunk::new(Chunk::new("first"), 1.0),
+                ScoredChunk::new(Chunk::new("second"), 0.5),
+                ScoredChunk::new(Chunk::new("third"), 1.0 / 3.0),
+            ]
+        );
+    }
+
+    // Only implements what has no default so the default scores are used
+    struct RankedRetriever;
+
+    impl AsyncRetriever for RankedRetriever {
+        type ErrorType = std::io::Error;
+
+        async fn retrieve(
+            &self,
+            _text: &str,
+            _top_k: NonZeroU32,
+        ) -> Result<Chunks, Self::ErrorType> {
+            Ok(vec![
+                Chunk::new("first"),
+                Chunk::new("second"),
+                Chunk::new("third"),
+            ])
+        }
+
+        async fn fetch_by_metadata(
+            &self,
+            _filter: &MetadataFilter,
+        ) -> Result<Chunks, Self::ErrorType> {
+            Ok(Vec::new())
+        }
     }
 }
@@ -0,0 +1,197 @@
+use crate::common::{DynError, DynFuture, Embedding};
+use crate::stores::{EmbeddingStore, StoredEmbeddingId};
+
+/// # [`DynEmbeddingStore`]
+///
+/// An object safe version of [`EmbeddingStore`] so the store can be chosen at runtime, for
+/// example from config. Every [`EmbeddingStore`] implements it with its errors boxed into a
+/// [`DynError`], and `Box<dyn DynEmbeddingStore>` implements [`EmbeddingStore`] so it can be
+/// used anywhere a store can. The methods are prefixed with `dyn_` so they do not clash with
+/// those of [`EmbeddingStore`] when both traits are in scope.
+///
+/// # Examples
+/// ```
+/// use rag_toolchain::stores::{DynEmbeddingStore, EmbeddingStore, InMemoryVectorStore};
+/// use rag_toolchain::common::OpenAIEmbeddingModel::TextEmbeddingAda002;
+///
+/// fn choose_store(name: &str) -> Box<dyn DynEmbeddingStore> {
+///     match name {
+///         // A PostgresVectorStore can be boxed in the same way
+///         _ => Box::new(InMemoryVectorStore::new(TextEmbeddingAda002)),
+///     }
+/// }
+///
+/// async fn ingest(store: &impl EmbeddingStore, embeddings: Vec<rag_toolchain::common::Embedding>) {
+///     store.store_batch(embeddings).await.unwrap();
+/// }
+///
+/// async fn run(embeddings: Vec<rag_toolchain::common::Embedding>) {
+///     let store: Box<dyn DynEmbeddingStore> = choose_store("memory");
+///     ingest(&store, embeddings).await;
+/// }
+/// ```
+pub trait DynEmbeddingStore: Send + Sync {
+    /// # [`DynEmbeddingStore::dyn_store`]
+    ///
+    /// See [`EmbeddingStore::store`].
+    fn dyn_store(&self, embedding: Embedding) -> DynFuture<'_, ()>;
+
+    /// # [`DynEmbeddingStore::dyn_store_batch`]
+    ///
+    /// See [`EmbeddingStore::store_batch`].
+    fn dyn_store_batch(&self, embeddings: Vec<Embedding>) -> DynFuture<'_, ()>;
+
+    /// # [`DynEmbeddingStore::dyn_store_with_id`]
+    ///
+    /// See [`EmbeddingStore::store_with_id`].
+    fn dyn_store_with_id(&self, embedding: Embedding) -> DynFuture<'_, StoredEmbeddingId>;
+
+    /// # [`DynEmbeddingStore::dyn_store_batch_with_ids`]
+    ///
+    /// See [`EmbeddingStore::store_batch_with_ids`].
+    fn dyn_store_batch_with_ids(
+        &self,
+        embeddings: Vec<Embedding>,
+    ) -> DynFuture<'_, Vec<StoredEmbeddingId>>;
+}
+
+impl<T> DynEmbeddingStore for T
+where
+    T: EmbeddingStore,
+    T::ErrorType: 'static,
+{
+    fn dyn_store(&self, embedding: Embedding) -> DynFuture<'_, ()> {
+        Box::pin(async move {
+            EmbeddingStore::store(self, embedding)
+                .await
+                .map_err(DynError::new)
+        })
+    }
+
+    fn dyn_store_batch(&self, embeddings: Vec<Embedding>) -> DynFuture<'_, ()> {
+        Box::pin(async move {
+            EmbeddingStore::store_batch(self, embeddings)
+                .await
+                .map_err(DynError::new)
+        })
+    }
+
+    fn dyn_store_with_id(&self, embedding: Embedding) -> DynFuture<'_, StoredEmbeddingId> {
+        Box::pin(async move {
+            EmbeddingStore::store_with_id(self, embedding)
+                .await
+                .map_err(DynError::new)
+        })
+    }
+
+    fn dyn_store_batch_with_ids(
+        &self,
+        embeddings: Vec<Embedding>,
+    ) -> DynFuture<'_, Vec<StoredEmbeddingId>> {
+        Box::pin(async move {
+            EmbeddingStore::store_batch_with_ids(self, embeddings)
+                .await
+                .map_err(DynError::new)
+        })
+    }
+}
+
+impl EmbeddingStore for Box<dyn DynEmbeddingStore> {
+    type ErrorType = DynError;
+
+    async fn store(&self, embedding: Embedding) -> Result<(), DynError> {
+        DynEmbeddingStore::dyn_store(self.as_ref(), embedding).await
+    }
+
+    async fn store_batch(&self, embeddings: Vec<Embedding>) -> Result<(), DynError> {
+        DynEmbeddingStore::dyn_store_batch(self.as_ref(), embeddings).await
+    }
+
+    async fn store_with_id(&self, embedding: Embedding) -> Result<StoredEmbeddingId, DynError> {
+        DynEmbeddingStore::dyn_store_with_id(self.as_ref(), embedding).await
+    }
+
+    async fn store_batch_with_ids(
+        &self,
+        embeddings: Vec<Embedding>,
+    ) -> Result<Vec<StoredEmbeddingId>, DynError> {
+        DynEmbeddingStore::dyn_store_batch_with_ids(self.as_ref(), embeddings).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::Chunk;
+    use crate::common::OpenAIEmbeddingModel::TextEmbeddingAda002;
+    use crate::stores::InMemoryVectorStore;
+    use std::sync::Mutex;
+
+    // Store with its own error type which remembers what it was given
+    #[derive(Default)]
+    struct RecordingStore(Mutex<Vec<String>>);
+
+    impl EmbeddingStore for RecordingStore {
+        type ErrorType = std::io::Error;
+
+        async fn store(&self, embedding: Embedding) -> Result<(), Self::ErrorType> {
+            self.store_batch(vec![embedding]).await
+        }
+
+        async fn store_batch(&self, embeddings: Vec<Embedding>) -> Result<(), Self::ErrorType> {
+            let mut stored = self.0.lock().unwrap();
+            for embedding in embeddings {
+                if embedding.chunk().content().is_empty() {
+                    return Err(std::io::Error::other("empty chunk"));
+                }
+                stored.push(embedding.chunk().content().into());
+            }
+            Ok(())
+        }
+    }
+
+    // The same code path for any store
+    async fn ingest(store: &impl EmbeddingStore, contents: &[&str]) -> Result<(), String> {
+        let embeddings: Vec<Embedding> = contents
+            .iter()
+            .map(|content| Embedding::new(Chunk::new(*content), vec![0.5; 1536]))
+            .collect();
+        store
+            .store_batch(embeddings)
+            .await
+            .map_err(|error| error.to_string())
+    }
+
+    #[tokio::test]
+    async fn stores_of_different_types_are_driven_through_the_same_code() {
+        let stores: Vec<Box<dyn DynEmbeddingStore>> = vec![
+            Box::new(InMemoryVectorStore::new(TextEmbeddingAda002)),
+            Box::new(RecordingStore::default()),
+        ];
+        for store in &stores {
+            ingest(store, &["first", "second"]).await.unwrap();
+            let ids: Vec<StoredEmbeddingId> = store
+                .store_batch_with_ids(vec![Embedding::new(Chunk::new("third"), vec![0.5; 1536])])
+                .await
+                .unwrap();
+            assert_eq!(ids.len(), 1);
+        }
+    }
+
+    #[tokio::test]
+    async fn errors_keep_the_error_of_the_store() {
+        let store: Box<dyn DynEmbeddingStore> = Box::new(RecordingStore::default());
+        let error: DynError = EmbeddingStore::store(&store, Embedding::new(Chunk::new(""), vec![]))
+            .await
+            .unwrap_err();
+        assert_eq!(error.to_string(), "empty chunk");
+        assert_eq!(
+            error.downcast_ref::<std::io::Error>().unwrap().kind(),
+            std::io::ErrorKind::Other
+        );
+        assert_eq!(
+            ingest(&store, &["ok", ""]).await.unwrap_err(),
+            "empty chunk".to_string()
+        );
+    }
+}
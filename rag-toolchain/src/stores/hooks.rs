@@ -0,0 +1,163 @@
+use crate::common::Embedding;
+use std::any::Any;
+use std::fmt::{Debug, Formatter};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::sync::Arc;
+use thiserror::Error;
+
+/// # [`HookError`]
+///
+/// Returned by a before store hook to veto a write, a hook which panics
+/// is also reported as one of these.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+#[error("{0}")]
+pub struct HookError(String);
+
+impl HookError {
+    /// # [`HookError::new`]
+    ///
+    /// # Arguments
+    /// * `message`: impl [`Into<String>`] - why the hook failed.
+    ///
+    /// # Returns
+    /// * [`HookError`] - the error.
+    pub fn new(message: impl Into<String>) -> Self {
+        HookError(message.into())
+    }
+}
+
+/// # [`StoreOutcome`]
+///
+/// What was written by a successful store, passed to the after store hook
+/// once the rows have been committed.
+///
+/// * `rows_written` - the number of rows inserted.
+/// * `ids` - the ids of the inserted rows in the order the embeddings were given.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct StoreOutcome {
+    pub rows_written: usize,
+    pub ids: Vec<i32>,
+}
+
+pub(crate) type BeforeStoreHook = Arc<dyn Fn(&[Embedding]) -> Result<(), HookError> + Send + Sync>;
+pub(crate) type AfterStoreHook = Arc<dyn Fn(&StoreOutcome) + Send + Sync>;
+
+/// # [`StoreHooks`]
+///
+/// The hooks registered on a store. The hooks are shared so cloning
+/// a store keeps its hooks.
+#[derive(Clone, Default)]
+pub(crate) struct StoreHooks {
+    pub before_store: Option<BeforeStoreHook>,
+    pub after_store: Option<AfterStoreHook>,
+}
+
+impl StoreHooks {
+    /// # [`StoreHooks::run_before_store`]
+    ///
+    /// Runs the before store hook if there is one.
+    ///
+    /// # Errors
+    /// * [`HookError`] - if the hook vetoed the write or panicked.
+    pub(crate) fn run_before_store(&self, embeddings: &[Embedding]) -> Result<(), HookError> {
+        match &self.before_store {
+            None => Ok(()),
+            Some(hook) => catch_unwind(AssertUnwindSafe(|| hook(embeddings)))
+                .unwrap_or_else(|panic| Err(panicked("before store", panic))),
+        }
+    }
+
+    /// # [`StoreHooks::run_after_store`]
+    ///
+    /// Runs the after store hook if there is one.
+    ///
+    /// # Errors
+    /// * [`HookError`] - if the hook panicked.
+    pub(crate) fn run_after_store(&self, outcome: &StoreOutcome) -> Result<(), HookError> {
+        match &self.after_store {
+            None => Ok(()),
+            Some(hook) => catch_unwind(AssertUnwindSafe(|| hook(outcome)))
+                .map_err(|panic| panicked("after store", panic)),
+        }
+    }
+}
+
+impl Debug for StoreHooks {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("StoreHooks")
+            .field("before_store", &self.before_store.is_some())
+            .field("after_store", &self.after_store.is_some())
+            .finish()
+    }
+}
+
+/// Converts a caught panic into an error, keeping the message if it had one.
+fn panicked(hook: &str, panic: Box<dyn Any + Send>) -> HookError {
+    let message: &str = panic
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| panic.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("no message");
+    HookError::new(format!("the {} hook panicked: {}", hook, message))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::Chunk;
+
+    #[test]
+    fn missing_hooks_always_succeed() {
+        let hooks = StoreHooks::default();
+        assert_eq!(hooks.run_before_store(&[]), Ok(()));
+        let outcome = StoreOutcome {
+            rows_written: 0,
+            ids: Vec::new(),
+        };
+        assert_eq!(hooks.run_after_store(&outcome), Ok(()));
+    }
+
+    #[test]
+    fn before_store_hook_can_veto() {
+        let hooks = StoreHooks {
+            before_store: Some(Arc::new(|embeddings: &[Embedding]| {
+                match embeddings.len() {
+                    0 => Ok(()),
+                    count => Err(HookError::new(format!("{} embeddings rejected", count))),
+                }
+            })),
+            after_store: None,
+        };
+        assert_eq!(hooks.run_before_store(&[]), Ok(()));
+        let embedding = Embedding::new(Chunk::new("text"), vec![1.0]);
+        assert_eq!(
+            hooks.run_before_store(&[embedding]),
+            Err(HookError::new("1 embeddings rejected"))
+        );
+    }
+
+    #[test]
+    fn panics_are_converted_to_errors() {
+        let hooks = StoreHooks {
+            before_store: Some(Arc::new(|_: &[Embedding]| panic!("boom"))),
+            after_store: Some(Arc::new(|outcome: &StoreOutcome| {
+                panic!("{} rows", outcome.rows_written)
+            })),
+        };
+        assert_eq!(
+            hooks.run_before_store(&[]),
+            Err(HookError::new("the before store hook panicked: boom"))
+        );
+        let outcome = StoreOutcome {
+            rows_written: 2,
+            ids: vec![1, 2],
+        };
+        assert_eq!(
+            hooks.run_after_store(&outcome),
+            Err(HookError::new("the after store hook panicked: 2 rows"))
+        );
+        // The hooks are still usable after a panic
+        assert!(hooks.run_before_store(&[]).is_err());
+    }
+}
@@ -0,0 +1,322 @@
+use crate::clients::AsyncEmbeddingClient;
+use crate::common::{Embedding, EmbeddingModel};
+use crate::retrievers::{DistanceFunction, InMemoryRetriever};
+use crate::stores::traits::EmbeddingStore;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::sync::{Arc, PoisonError, RwLock};
+use thiserror::Error;
+
+/// # [`InMemoryVectorStore`]
+///
+/// This is the implementation of [`EmbeddingStore`] which keeps the embeddings in memory,
+/// useful for prototyping, tests and small corpora where running a database is not worth it.
+/// Clones of the store share the same embeddings, as do the retrievers created with
+/// [`InMemoryVectorStore::as_retriever`] so they see everything stored after they were created.
+///
+/// The contents can be checkpointed to a JSON file with [`InMemoryVectorStore::save`] and
+/// read back with [`InMemoryVectorStore::try_load`].
+///
+/// # Examples
+/// ```
+/// use rag_toolchain::stores::*;
+/// use rag_toolchain::common::*;
+///
+/// async fn store(embeddings: Vec<Embedding>) {
+///     let embedding_model: OpenAIEmbeddingModel = OpenAIEmbeddingModel::TextEmbedding3Small;
+///     let store: InMemoryVectorStore = InMemoryVectorStore::new(embedding_model);
+///     store.store_batch(embeddings).await.unwrap();
+///     store.save("embeddings.json").await.unwrap();
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct InMemoryVectorStore {
+    /// The stored embeddings, shared with any retrievers
+    embeddings: Arc<RwLock<Vec<Embedding>>>,
+    /// The dimension every stored vector must have
+    dimensions: usize,
+}
+
+impl InMemoryVectorStore {
+    /// # [`InMemoryVectorStore::new`]
+    ///
+    /// Creates an empty store for embeddings generated by the given model.
+    ///
+    /// # Arguments
+    /// * `embedding_model`: impl [`EmbeddingModel`] - The embedding model used for the generated embeddings.
+    ///
+    /// # Returns
+    /// * [`InMemoryVectorStore`] - the empty store.
+    pub fn new(embedding_model: impl EmbeddingModel) -> Self {
+        InMemoryVectorStore {
+            embeddings: Arc::default(),
+            dimensions: embedding_model.metadata().dimensions,
+        }
+    }
+
+    /// # [`InMemoryVectorStore::try_load`]
+    ///
+    /// Reads a store back from a file written by [`InMemoryVectorStore::save`].
+    ///
+    /// # Arguments
+    /// * `path`: impl [`AsRef<Path>`] - The file to read.
+    ///
+    /// # Errors
+    /// * [`InMemoryVectorStoreError::IoError`] if the file could not be read.
+    /// * [`InMemoryVectorStoreError::SerializationError`] if the file is not a saved store.
+    /// * [`InMemoryVectorStoreError::DimensionMismatch`] if any embedding does not match the saved dimension.
+    ///
+    /// # Returns
+    /// * [`InMemoryVectorStore`] - the store holding the saved embeddings.
+    pub async fn try_load(path: impl AsRef<Path>) -> Result<Self, InMemoryVectorStoreError> {
+        let contents: Vec<u8> = tokio::fs::read(path).await?;
+        let snapshot: Snapshot = serde_json::from_slice(&contents)?;
+        for embedding in snapshot.embeddings.iter() {
+            check_dimensions(snapshot.dimensions, embedding)?;
+        }
+        Ok(InMemoryVectorStore {
+            embeddings: Arc::new(RwLock::new(snapshot.embeddings)),
+            dimensions: snapshot.dimensions,
+        })
+    }
+
+    /// # [`InMemoryVectorStore::save`]
+    ///
+    /// Writes the dimension and every stored embedding to a JSON file, replacing it if it exists.
+    ///
+    /// # Arguments
+    /// * `path`: impl [`AsRef<Path>`] - The file to write.
+    ///
+    /// # Errors
+    /// * [`InMemoryVectorStoreError::SerializationError`] if the embeddings could not be serialized.
+    /// * [`InMemoryVectorStoreError::IoError`] if the file could not be written.
+    pub async fn save(&self, path: impl AsRef<Path>) -> Result<(), InMemoryVectorStoreError> {
+        // Serialized up front so the lock is not held while writing
+        let contents: Vec<u8> = {
+            let embeddings = self
+                .embeddings
+                .read()
+                .unwrap_or_else(PoisonError::into_inner);
+            serde_json::to_vec(&SnapshotRef {
+                dimensions: self.dimensions,
+                embeddings: &embeddings,
+            })?
+        };
+        tokio::fs::write(path, contents).await?;
+        Ok(())
+    }
+
+    /// # [`InMemoryVectorStore::dimensions`]
+    ///
+    /// Getter for the dimension of the vectors the store holds.
+    ///
+    /// # Returns
+    /// * [`usize`] - The dimension every stored vector has
+    pub fn dimensions(&self) -> usize {
+        self.dimensions
+    }
+
+    /// # [`InMemoryVectorStore::len`]
+    ///
+    /// # Returns
+    /// * [`usize`] - The number of stored embeddings
+    pub fn len(&self) -> usize {
+        self.embeddings
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .len()
+    }
+
+    /// # [`InMemoryVectorStore::is_empty`]
+    ///
+    /// # Returns
+    /// * [`bool`] - true if nothing has been stored
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// # [`InMemoryVectorStore::as_retriever`]
+    ///
+    /// This function allows us to convert the store into a retriever.
+    /// Note that the returned retriever searches the same embeddings as the store.
+    ///
+    /// # Arguments
+    /// * `embedding_client`: [`AsyncEmbeddingClient`] - The client we use to embed
+    ///   income text before the similarity search.
+    /// * `distance_function`: [`DistanceFunction`] - The distance function to use to
+    ///   compare the embeddings
+    ///
+    /// # Returns
+    /// [`InMemoryRetriever`] - The retriever that can be used to search for similar text.
+    pub fn as_retriever<T: AsyncEmbeddingClient>(
+        &self,
+        embedding_client: T,
+        distance_function: DistanceFunction,
+    ) -> InMemoryRetriever<T> {
+        InMemoryRetriever::new(
+            self.embeddings.clone(),
+            self.dimensions,
+            embedding_client,
+            distance_function,
+        )
+    }
+}
+
+impl EmbeddingStore for InMemoryVectorStore {
+    type ErrorType = InMemoryVectorStoreError;
+
+    /// # [`InMemoryVectorStore::store`]
+    ///
+    /// # Arguments
+    /// * `embedding`: [`Embedding`] - to store
+    ///
+    /// # Errors
+    /// * [`InMemoryVectorStoreError::DimensionMismatch`] if the embedding does not match the store
+    ///
+    /// # Returns
+    /// * [`()`] if the embedding was stored
+    async fn store(&self, embedding: Embedding) -> Result<(), InMemoryVectorStoreError> {
+        self.store_batch(vec![embedding]).await
+    }
+
+    /// # [`InMemoryVectorStore::store_batch`]
+    ///
+    /// # Arguments
+    /// * `embeddings`: [`Vec<Embedding>`] - A vector of embeddings to store
+    ///
+    /// # Errors
+    /// * [`InMemoryVectorStoreError::DimensionMismatch`] if any embedding does not match the store,
+    ///   in which case nothing is stored
+    ///
+    /// # Returns
+    /// * [`()`] if the embeddings were stored
+    async fn store_batch(
+        &self,
+        embeddings: Vec<Embedding>,
+    ) -> Result<(), InMemoryVectorStoreError> {
+        for embedding in embeddings.iter() {
+            check_dimensions(self.dimensions, embedding)?;
+        }
+        self.embeddings
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .extend(embeddings);
+        Ok(())
+    }
+}
+
+/// # [`check_dimensions`]
+/// Checks the embedding has the dimension the store expects
+///
+/// # Errors
+/// * [`InMemoryVectorStoreError::DimensionMismatch`] if the dimensions differ.
+fn check_dimensions(
+    expected: usize,
+    embedding: &Embedding,
+) -> Result<(), InMemoryVectorStoreError> {
+    let found: usize = embedding.vector_slice().len();
+    if found != expected {
+        return Err(InMemoryVectorStoreError::DimensionMismatch { expected, found });
+    }
+    Ok(())
+}
+
+/// The file format of a saved store
+#[derive(Deserialize)]
+struct Snapshot {
+    dimensions: usize,
+    embeddings: Vec<Embedding>,
+}
+
+/// Borrowed [`Snapshot`] so saving does not copy the embeddings
+#[derive(Serialize)]
+struct SnapshotRef<'a> {
+    dimensions: usize,
+    embeddings: &'a [Embedding],
+}
+
+/// # [`InMemoryVectorStoreError`]
+/// This Error enum wraps all the errors that can occur when using
+/// the [`InMemoryVectorStore`] with contextual meaning.
+#[derive(Error, Debug)]
+pub enum InMemoryVectorStoreError {
+    /// Error when an embedding does not have the same dimension as the store
+    #[error("Dimension Mismatch: expected {expected} but found {found}")]
+    DimensionMismatch { expected: usize, found: usize },
+    /// Error when a saved store could not be read or written
+    #[error("IO Error: {0}")]
+    IoError(#[from] std::io::Error),
+    /// Error when a saved store could not be serialized or deserialized
+    #[error("Serialization Error: {0}")]
+    SerializationError(#[from] serde_json::Error),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::Chunk;
+    use crate::common::OpenAIEmbeddingModel::TextEmbeddingAda002;
+    use serde_json::json;
+    use std::env::temp_dir;
+    use uuid::Uuid;
+
+    fn embedding(content: &str, value: f32) -> Embedding {
+        let chunk: Chunk = Chunk::new_with_metadata(content, json!({ "content": content }));
+        Embedding::new(chunk, vec![value; 1536])
+    }
+
+    #[tokio::test]
+    async fn store_batch_rejects_mismatched_dimensions() {
+        let store = InMemoryVectorStore::new(TextEmbeddingAda002);
+        let embeddings = vec![
+            embedding("kept out", 1.0),
+            Embedding::new(Chunk::new("short"), vec![1.0, 2.0]),
+        ];
+        let error = store.store_batch(embeddings).await.unwrap_err();
+        assert!(matches!(
+            error,
+            InMemoryVectorStoreError::DimensionMismatch {
+                expected: 1536,
+                found: 2
+            }
+        ));
+        assert!(store.is_empty());
+    }
+
+    #[tokio::test]
+    async fn saved_store_loads_with_the_same_contents() {
+        let store = InMemoryVectorStore::new(TextEmbeddingAda002);
+        store.store(embedding("first", 0.5)).await.unwrap();
+        store
+            .store_batch(vec![embedding("second", -0.25)])
+            .await
+            .unwrap();
+        let path = temp_dir().join(format!("in_memory_store_{}.json", Uuid::new_v4()));
+        store.save(&path).await.unwrap();
+
+        let loaded = InMemoryVectorStore::try_load(&path).await.unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.dimensions(), 1536);
+        assert_eq!(loaded.len(), 2);
+        assert_eq!(
+            *loaded.embeddings.read().unwrap(),
+            *store.embeddings.read().unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn loading_a_corrupt_file_fails() {
+        let path = temp_dir().join(format!("in_memory_store_{}.json", Uuid::new_v4()));
+        std::fs::write(&path, r#"{"dimensions": 2, "embeddings": [{"chunk": "#).unwrap();
+        let error = InMemoryVectorStore::try_load(&path).await.unwrap_err();
+        std::fs::remove_file(&path).unwrap();
+        assert!(matches!(
+            error,
+            InMemoryVectorStoreError::SerializationError(_)
+        ));
+
+        let missing = temp_dir().join(format!("in_memory_store_{}.json", Uuid::new_v4()));
+        let error = InMemoryVectorStore::try_load(&missing).await.unwrap_err();
+        assert!(matches!(error, InMemoryVectorStoreError::IoError(_)));
+    }
+}
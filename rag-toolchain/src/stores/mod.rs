@@ -7,10 +7,30 @@
 /// Once you have stored them you should be able to call as_retriever
 /// to get a retriever (See retrievers module) to preform similarity searches
 /// on incoming text.
+mod dyn_store;
 #[cfg(feature = "pg_vector")]
-mod postgres_vector_store;
+mod hooks;
+mod in_memory_vector_store;
+#[cfg(feature = "pg_vector")]
+mod postgres_config;
+#[cfg(feature = "pg_vector")]
+pub(crate) mod postgres_vector_store;
+#[cfg(feature = "sqlite_vec")]
+pub(crate) mod sqlite_vector_store;
 mod traits;
 
+pub use dyn_store::DynEmbeddingStore;
+#[cfg(feature = "pg_vector")]
+pub use hooks::{HookError, StoreOutcome};
+pub use in_memory_vector_store::{InMemoryVectorStore, InMemoryVectorStoreError};
+#[cfg(feature = "pg_vector")]
+pub use postgres_config::{PostgresConfig, DEFAULT_MAX_CONNECTIONS, DEFAULT_POSTGRES_PORT};
 #[cfg(feature = "pg_vector")]
-pub use postgres_vector_store::{PostgresVectorStore, PostgresVectorStoreError};
-pub use traits::EmbeddingStore;
+pub use postgres_vector_store::{
+    DeleteOptions, DeleteReport, IndexType, PostgresVectorStore, PostgresVectorStoreBuilder,
+    PostgresVectorStoreError, VectorPrecision, DEFAULT_DELETE_BATCH_SIZE, DEFAULT_DELETE_LIMIT,
+    DEFAULT_FULL_TEXT_LANGUAGE,
+};
+#[cfg(feature = "sqlite_vec")]
+pub use sqlite_vector_store::{SqliteVectorStore, SqliteVectorStoreError};
+pub use traits::{EmbeddingStore, StoredEmbeddingId};
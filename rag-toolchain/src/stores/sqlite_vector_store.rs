@@ -0,0 +1,565 @@
+use crate::clients::AsyncEmbeddingClient;
+use crate::common::{Chunk, Embedding, EmbeddingModel};
+use crate::retrievers::{DistanceFunction, SqliteVectorRetriever};
+use crate::stores::traits::EmbeddingStore;
+use libsqlite3_sys::{sqlite3, sqlite3_api_routines, sqlite3_auto_extension};
+use serde_json::Value;
+use sqlite_vec::sqlite3_vec_init;
+use sqlx::sqlite::{SqliteArguments, SqliteConnectOptions, SqlitePoolOptions, SqliteQueryResult};
+use sqlx::{Pool, Sqlite};
+use std::borrow::Cow;
+use std::ffi::{c_char, c_int};
+use std::str::FromStr;
+use std::sync::Once;
+use thiserror::Error;
+
+/// # [`SqliteVectorStore`]
+///
+/// This is the implementation of [`EmbeddingStore`] for a SQLite database with the
+/// sqlite-vec extension. It is an embedded alternative to
+/// [`crate::stores::PostgresVectorStore`] for command line tools and tests, the database is
+/// just a file or `:memory:`. This store takes a path, a table name and an embedding model.
+/// If a table already exists with the same name and does not have the expected columns
+/// any calls to [`SqliteVectorStore::store`] or [`SqliteVectorStore::store_batch`]
+/// will fail.
+///
+/// The sqlite-vec extension is compiled into the crate and registered with SQLite the first
+/// time a store is created, so nothing needs to be installed.
+///
+/// # Output table format
+/// A vec0 virtual table with the columns: | rowid | embedding (float\[n\]) | content (text) | metadata (text) |
+///
+/// The metadata is stored as JSON text.
+///
+/// # Examples
+/// ```
+/// use rag_toolchain::stores::*;
+/// use rag_toolchain::common::*;
+///
+/// async fn store(embeddings: Vec<Embedding>) {
+///     let embedding_model: OpenAIEmbeddingModel = OpenAIEmbeddingModel::TextEmbedding3Small;
+///     let store: SqliteVectorStore = SqliteVectorStore::try_new("embeddings.db", "table_name", embedding_model)
+///         .await.unwrap();
+///     store.store_batch(embeddings).await.unwrap();
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct SqliteVectorStore {
+    /// We hold a connection pool to the database
+    pool: Pool<Sqlite>,
+    /// The name of the table we are operating on
+    table_name: String,
+    /// The dimension of the vectors the table holds
+    dimensions: usize,
+}
+
+impl SqliteVectorStore {
+    /// # [`SqliteVectorStore::try_new`]
+    ///
+    /// This constructor is used to create a new SqliteVectorStore. It will register the bundled
+    /// sqlite-vec extension, open the database, creating the file if it does not exist, and then
+    /// create a table with the given name and the expected columns. If the table already exists with the
+    /// same name it will not be re-created.
+    ///
+    /// # Arguments
+    /// * `path`: &[`str`] - The path of the database file, or `:memory:` for an in memory database.
+    /// * `table_name`: &[`str`] - The name of the table to store the embeddings in.
+    /// * `embedding_model`: impl [`EmbeddingModel`] - The embedding model to use to store the embeddings
+    ///
+    /// # Errors
+    /// * [`SqliteVectorStoreError::ConnectionError`] if the database could not be opened.
+    /// * [`SqliteVectorStoreError::TableCreationError`] if the table could not be created.
+    ///
+    /// # Returns
+    /// * [`SqliteVectorStore`] if the connection and table creation is successful
+    pub async fn try_new(
+        path: &str,
+        table_name: &str,
+        embedding_model: impl EmbeddingModel,
+    ) -> Result<Self, SqliteVectorStoreError> {
+        Self::register_extension();
+        let pool = Self::connect(path, None)
+            .await
+            .map_err(SqliteVectorStoreError::ConnectionError)?;
+        Self::try_new_with_pool(pool, table_name, embedding_model).await
+    }
+
+    /// # [`SqliteVectorStore::try_new_with_extension`]
+    ///
+    /// The same as [`SqliteVectorStore::try_new`] but loads the sqlite-vec extension from a
+    /// shared library instead of registering the bundled one, for example to use a newer
+    /// build of the extension.
+    ///
+    /// # Arguments
+    /// * `path`: &[`str`] - The path of the database file, or `:memory:` for an in memory database.
+    /// * `table_name`: &[`str`] - The name of the table to store the embeddings in.
+    /// * `embedding_model`: impl [`EmbeddingModel`] - The embedding model to use to store the embeddings
+    /// * `extension`: &[`str`] - The name or path of the sqlite-vec extension.
+    ///
+    /// # Errors
+    /// * [`SqliteVectorStoreError::ConnectionError`] if the database could not be opened or the extension could not be loaded.
+    /// * [`SqliteVectorStoreError::TableCreationError`] if the table could not be created.
+    ///
+    /// # Returns
+    /// * [`SqliteVectorStore`] if the connection and table creation is successful
+    pub async fn try_new_with_extension(
+        path: &str,
+        table_name: &str,
+        embedding_model: impl EmbeddingModel,
+        extension: &str,
+    ) -> Result<Self, SqliteVectorStoreError> {
+        let pool = Self::connect(path, Some(extension))
+            .await
+            .map_err(SqliteVectorStoreError::ConnectionError)?;
+        Self::try_new_with_pool(pool, table_name, embedding_model).await
+    }
+
+    /// # [`SqliteVectorStore::try_new_with_pool`]
+    ///
+    /// This is an alternative constructor that allows you to pass in a connection pool.
+    /// Every connection in the pool must have the sqlite-vec extension loaded, which is the
+    /// case for any connection opened after [`SqliteVectorStore::register_extension`].
+    ///
+    /// # Arguments
+    /// * `pool`: [`sqlx::Pool<Sqlite>`] - a pre established connection pool.
+    /// * `table_name`: &[`str`] - The name of the table to store the embeddings in.
+    /// * `embedding_model`: impl[`EmbeddingModel`] - The embedding model used for the genrated embeddings.
+    ///
+    /// # Errors
+    /// * [`SqliteVectorStoreError::TableCreationError`] if the table could not be created
+    ///
+    /// # Returns
+    /// * [`SqliteVectorStore`] if the table creation is successful.
+    pub async fn try_new_with_pool(
+        pool: Pool<Sqlite>,
+        table_name: &str,
+        embedding_model: impl EmbeddingModel,
+    ) -> Result<Self, SqliteVectorStoreError> {
+        let embedding_diminsions = embedding_model.metadata().dimensions;
+
+        // Create the table
+        SqliteVectorStore::create_table(&pool, table_name, embedding_diminsions)
+            .await
+            .map_err(SqliteVectorStoreError::TableCreationError)?;
+
+        Ok(SqliteVectorStore {
+            pool,
+            table_name: table_name.into(),
+            dimensions: embedding_diminsions,
+        })
+    }
+
+    /// # [`SqliteVectorStore::register_extension`]
+    ///
+    /// Registers the bundled sqlite-vec extension with SQLite so that it is loaded into every
+    /// connection opened afterwards, in this process. This is called by
+    /// [`SqliteVectorStore::try_new`] and only needs calling directly before building a pool
+    /// for [`SqliteVectorStore::try_new_with_pool`]. Calling it more than once does nothing.
+    pub fn register_extension() {
+        static REGISTER: Once = Once::new();
+        REGISTER.call_once(|| {
+            // SAFETY: sqlite3_vec_init is the extension entry point sqlite-vec exports, it has
+            // the signature sqlite3_auto_extension expects but is declared without arguments
+            unsafe {
+                sqlite3_auto_extension(Some(std::mem::transmute::<
+                    *const (),
+                    unsafe extern "C" fn(
+                        *mut sqlite3,
+                        *mut *mut c_char,
+                        *const sqlite3_api_routines,
+                    ) -> c_int,
+                >(sqlite3_vec_init as *const ())));
+            }
+        });
+    }
+
+    /// # [`SqliteVectorStore::get_pool`]
+    ///
+    /// Getter for the internal connection pool.
+    /// This is useful if you want to do any further operations on the database.
+    ///
+    /// # Returns
+    /// * [`Pool`] - The connection pool
+    pub fn get_pool(&self) -> Pool<Sqlite> {
+        self.pool.clone()
+    }
+
+    /// # [`SqliteVectorStore::dimensions`]
+    ///
+    /// Getter for the dimension of the vectors the table holds.
+    ///
+    /// # Returns
+    /// * [`usize`] - The dimension of the embedding column
+    pub fn dimensions(&self) -> usize {
+        self.dimensions
+    }
+
+    /// # [`SqliteVectorStore::as_retriever`]
+    ///
+    /// This function allows us to convert the store into a retriever.
+    /// Note that the returned retriever is bound to the same table as the store.
+    ///
+    /// # Arguments
+    /// * `embedding_client`: [`AsyncEmbeddingClient`] - The client we use to embed
+    ///   income text before the similarity search.
+    /// * `distance_function`: [`DistanceFunction`] - The distance function to use to
+    ///   compare the embeddings, sqlite-vec supports [`DistanceFunction::L2`] and
+    ///   [`DistanceFunction::Cosine`].
+    ///
+    /// # Returns
+    /// [`SqliteVectorRetriever`] - The retriever that can be used to search for similar text.
+    pub fn as_retriever<T: AsyncEmbeddingClient>(
+        &self,
+        embedding_client: T,
+        distance_function: DistanceFunction,
+    ) -> SqliteVectorRetriever<T> {
+        SqliteVectorRetriever::new(
+            self.pool.clone(),
+            self.table_name.clone(),
+            embedding_client,
+            distance_function,
+        )
+    }
+
+    /// # [`SqliteVectorStore::connect`]
+    /// Opens the database, loading the extension into every connection when one is given.
+    ///
+    /// An in memory database only lives as long as a connection to it is open, so it is
+    /// given a single connection which is never closed.
+    ///
+    /// # Arguments
+    /// * `path`: &[`str`] - The path of the database file, or `:memory:`
+    /// * `extension`: [`Option<&str>`] - The name or path of a sqlite-vec shared library to load
+    ///
+    /// # Errors
+    /// * [`sqlx::Error`] if the connection could not be established.
+    ///
+    /// # Returns
+    /// * [`Pool`] which can be used to query the database
+    async fn connect(path: &str, extension: Option<&str>) -> Result<Pool<Sqlite>, sqlx::Error> {
+        let mut options = if path == ":memory:" {
+            SqliteConnectOptions::from_str(path)?
+        } else {
+            SqliteConnectOptions::new()
+                .filename(path)
+                .create_if_missing(true)
+        };
+        if let Some(extension) = extension {
+            let extension: Cow<'static, str> = Cow::Owned(extension.to_string());
+            options = options.extension(extension);
+        }
+        if path == ":memory:" {
+            return SqlitePoolOptions::new()
+                .max_connections(1)
+                .min_connections(1)
+                .idle_timeout(None)
+                .max_lifetime(None)
+                .connect_with(options)
+                .await;
+        }
+        SqlitePoolOptions::new()
+            .max_connections(5)
+            .connect_with(options)
+            .await
+    }
+
+    /// # [`SqliteVectorStore::check_dimensions`]
+    /// Checks the embedding has the same dimension as the table before it is sent to the database
+    ///
+    /// # Errors
+    /// * [`SqliteVectorStoreError::DimensionMismatch`] if the dimensions differ.
+    fn check_dimensions(&self, embedding: &Embedding) -> Result<(), SqliteVectorStoreError> {
+        let found: usize = embedding.vector().len();
+        if found != self.dimensions {
+            return Err(SqliteVectorStoreError::DimensionMismatch {
+                expected: self.dimensions,
+                found,
+            });
+        }
+        Ok(())
+    }
+
+    /// # [`SqliteVectorStore::create_table`]
+    /// We call the create table automatically when the struct is created
+    ///
+    /// # Arguments
+    /// * `pool`: [`sqlx::Pool<Sqlite>`] - The connection pool to use to create the table
+    /// * `table_name`: &[`str`] - The name of the table to create
+    /// * `vector_dimension`: [`usize`] - The dimension of the vector to store
+    ///
+    /// # Errors
+    /// * [`sqlx::Error`] if the table could not be created.
+    ///
+    /// # Returns
+    /// * [`SqliteQueryResult`] which can be used to check if the table was created successfully
+    async fn create_table(
+        pool: &Pool<Sqlite>,
+        table_name: &str,
+        vector_dimension: usize,
+    ) -> Result<SqliteQueryResult, sqlx::Error> {
+        let statement = Self::create_table_sql(table_name, vector_dimension);
+        sqlx::query(&statement).execute(pool).await
+    }
+
+    /// # [`SqliteVectorStore::create_table_sql`]
+    /// Helper function to generate the sql statement for creating the table, content and
+    /// metadata are auxiliary columns so they are stored alongside the vector
+    fn create_table_sql(table_name: &str, vector_dimension: usize) -> String {
+        format!(
+            "CREATE VIRTUAL TABLE IF NOT EXISTS {} USING vec0(embedding float[{}], +content text, +metadata text)",
+            table_name, vector_dimension
+        )
+    }
+
+    /// # [`SqliteVectorStore::insert_row_sql`]
+    /// Helper function to generate the sql query for inserting a new row
+    ///
+    /// # Arguments
+    /// * `table_name`: &[`str`] - The name of the table to insert into
+    ///
+    /// # Returns
+    /// * [`String`] - The sql query
+    fn insert_row_sql(table_name: &str) -> String {
+        format!(
+            "INSERT INTO {} (content, embedding, metadata) VALUES (?1, ?2, ?3)",
+            table_name
+        )
+    }
+
+    /// # [`SqliteVectorStore::bind_to_query`]
+    /// Helper function to bind an [`Embedding`] to an [`sqlx::query::Query`]
+    /// the retuned query can then have [`sqlx::query::Query::execute`] called on it to insert the row.
+    fn bind_to_query<'q>(
+        query: &'q str,
+        embedding: Embedding,
+    ) -> sqlx::query::Query<'q, Sqlite, SqliteArguments<'q>> {
+        let chunk: &Chunk = embedding.chunk();
+        let text: String = chunk.content().to_string();
+        let metadata: String = chunk.metadata().to_string();
+        sqlx::query(query)
+            .bind(text)
+            .bind(vector_to_blob(&embedding.vector()))
+            .bind(metadata)
+    }
+}
+
+impl EmbeddingStore for SqliteVectorStore {
+    type ErrorType = SqliteVectorStoreError;
+    /// # [`SqliteVectorStore::store`]
+    /// This is done as a single insert statement.
+    ///
+    /// # Arguments
+    /// * `embedding`: [`Embedding`] - to insert
+    ///
+    /// # Errors
+    /// * [`SqliteVectorStoreError::DimensionMismatch`] if the embedding does not match the table
+    /// * [`SqliteVectorStoreError::InsertError`] if the insert fails
+    ///
+    /// # Returns
+    /// * [`()`] if the insert succeeds
+    async fn store(&self, embedding: Embedding) -> Result<(), SqliteVectorStoreError> {
+        self.check_dimensions(&embedding)?;
+        let query: String = SqliteVectorStore::insert_row_sql(&self.table_name);
+        SqliteVectorStore::bind_to_query(&query, embedding)
+            .execute(&self.pool)
+            .await
+            .map_err(SqliteVectorStoreError::InsertError)?;
+        Ok(())
+    }
+
+    /// # [`SqliteVectorStore::store_batch`]
+    /// This is done as a single transaction with multiple insert statements. An empty batch
+    /// returns straight away without a transaction.
+    ///
+    /// # Arguments
+    /// * `embeddings`: [`Vec<Embedding>`] - A vector of embeddings to insert
+    ///
+    /// # Errors
+    /// * [`SqliteVectorStoreError::DimensionMismatch`] if any embedding does not match the table,
+    ///   in which case nothing is inserted
+    /// * [`SqliteVectorStoreError::InsertError`] if an insert fails, in which case nothing is inserted
+    /// * [`SqliteVectorStoreError::TransactionError`] if the transaction fails
+    ///
+    /// # Returns
+    /// * [`()`] if the transaction succeeds
+    async fn store_batch(&self, embeddings: Vec<Embedding>) -> Result<(), SqliteVectorStoreError> {
+        if embeddings.is_empty() {
+            return Ok(());
+        }
+        for embedding in embeddings.iter() {
+            self.check_dimensions(embedding)?;
+        }
+        let query: String = SqliteVectorStore::insert_row_sql(&self.table_name);
+        let mut transaction = self
+            .pool
+            .begin()
+            .await
+            .map_err(SqliteVectorStoreError::TransactionError)?;
+
+        for embedding in embeddings {
+            SqliteVectorStore::bind_to_query(&query, embedding)
+                .execute(&mut *transaction)
+                .await
+                .map_err(SqliteVectorStoreError::InsertError)?;
+        }
+
+        transaction
+            .commit()
+            .await
+            .map_err(SqliteVectorStoreError::TransactionError)?;
+        Ok(())
+    }
+}
+
+/// # [`vector_to_blob`]
+/// sqlite-vec reads a float vector as a BLOB of little endian f32s.
+pub(crate) fn vector_to_blob(vector: &[f32]) -> Vec<u8> {
+    vector
+        .iter()
+        .flat_map(|value| value.to_le_bytes())
+        .collect()
+}
+
+/// # [`vector_from_blob`]
+/// Reads back a float vector stored by sqlite-vec, the inverse of [`vector_to_blob`].
+pub(crate) fn vector_from_blob(blob: &[u8]) -> Vec<f32> {
+    blob.chunks_exact(4)
+        .map(|bytes| f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+        .collect()
+}
+
+/// # [`metadata_from_text`]
+/// Reads back the metadata stored as JSON text, anything which is missing or
+/// not valid JSON is returned as [`Value::Null`].
+pub(crate) fn metadata_from_text(metadata: Option<String>) -> Value {
+    metadata
+        .and_then(|metadata| serde_json::from_str(&metadata).ok())
+        .unwrap_or_default()
+}
+
+/// # [`SqliteVectorStoreError`]
+/// This Error enum wraps all the errors that can occur when using
+/// the [`SqliteVectorStore`] with contextual meaning.
+#[derive(Error, Debug)]
+pub enum SqliteVectorStoreError {
+    /// Error when the database could not be opened or an extension could not be loaded
+    #[error("Connection Error: {0}")]
+    ConnectionError(sqlx::Error),
+    /// Error when the table could not be created
+    #[error("Table Creation Error: {0}")]
+    TableCreationError(sqlx::Error),
+    /// Error when inserting a row fails
+    #[error("Insert Error: {0}")]
+    InsertError(sqlx::Error),
+    /// Error when the transaction of [`SqliteVectorStore::store_batch()`] fails
+    #[error("Transaction Error: {0}")]
+    TransactionError(sqlx::Error),
+    /// Error when an embedding does not have the same dimension as the table
+    #[error("Dimension Mismatch: expected {expected} but found {found}")]
+    DimensionMismatch { expected: usize, found: usize },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::OpenAIEmbeddingModel::TextEmbeddingAda002;
+    use serde_json::json;
+
+    #[test]
+    fn create_table_sql_declares_the_dimensions() {
+        assert_eq!(
+            SqliteVectorStore::create_table_sql("docs", 1536),
+            "CREATE VIRTUAL TABLE IF NOT EXISTS docs USING vec0(embedding float[1536], +content text, +metadata text)"
+        );
+    }
+
+    #[test]
+    fn insert_row_sql_binds_content_embedding_and_metadata() {
+        assert_eq!(
+            SqliteVectorStore::insert_row_sql("docs"),
+            "INSERT INTO docs (content, embedding, metadata) VALUES (?1, ?2, ?3)"
+        );
+    }
+
+    #[test]
+    fn vectors_are_encoded_as_little_endian_f32s() {
+        let blob: Vec<u8> = vector_to_blob(&[1.0, -2.5]);
+        assert_eq!(blob.len(), 8);
+        assert_eq!(&blob[0..4], &1.0_f32.to_le_bytes());
+        assert_eq!(&blob[4..8], &(-2.5_f32).to_le_bytes());
+        assert_eq!(vector_from_blob(&blob), vec![1.0, -2.5]);
+    }
+
+    #[test]
+    fn metadata_round_trips_through_text() {
+        let metadata: Value = json!({"source": "a.txt", "page": 2});
+        assert_eq!(metadata_from_text(Some(metadata.to_string())), metadata);
+        assert_eq!(metadata_from_text(Some("null".into())), Value::Null);
+        assert_eq!(metadata_from_text(None), Value::Null);
+        assert_eq!(metadata_from_text(Some("{not json".into())), Value::Null);
+    }
+
+    #[tokio::test]
+    async fn missing_extension_is_a_connection_error() {
+        let error = SqliteVectorStore::try_new_with_extension(
+            ":memory:",
+            "docs",
+            TextEmbeddingAda002,
+            "/nonexistent/sqlite-vec",
+        )
+        .await
+        .unwrap_err();
+        assert!(matches!(error, SqliteVectorStoreError::ConnectionError(_)));
+    }
+
+    #[tokio::test]
+    async fn bundled_extension_is_loaded_into_new_connections() {
+        let store = SqliteVectorStore::try_new(":memory:", "docs", TextEmbeddingAda002)
+            .await
+            .unwrap();
+        let (version,): (String,) = sqlx::query_as("SELECT vec_version()")
+            .fetch_one(&store.get_pool())
+            .await
+            .unwrap();
+        assert!(version.starts_with('v'));
+    }
+
+    #[tokio::test]
+    async fn store_rejects_mismatched_dimensions() {
+        // The dimensions are checked before the database is touched
+        let store = SqliteVectorStore {
+            pool: SqlitePoolOptions::new().connect_lazy(":memory:").unwrap(),
+            table_name: "docs".into(),
+            dimensions: 3,
+        };
+        let embedding = Embedding::new(Chunk::new("text"), vec![1.0, 2.0]);
+        let error = store.store_batch(vec![embedding]).await.unwrap_err();
+        assert!(matches!(
+            error,
+            SqliteVectorStoreError::DimensionMismatch {
+                expected: 3,
+                found: 2
+            }
+        ));
+    }
+
+    #[tokio::test]
+    async fn empty_batch_never_touches_the_database() {
+        // Opening a database in a missing directory fails, so any query would error
+        let store = SqliteVectorStore {
+            pool: SqlitePoolOptions::new()
+                .connect_lazy("sqlite:/nonexistent/dir/docs.db")
+                .unwrap(),
+            table_name: "docs".into(),
+            dimensions: 3,
+        };
+        store.store_batch(Vec::new()).await.unwrap();
+        assert!(store
+            .store_batch(vec![Embedding::new(
+                Chunk::new("text"),
+                vec![1.0, 2.0, 3.0]
+            )])
+            .await
+            .is_err());
+    }
+}
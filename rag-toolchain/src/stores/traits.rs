@@ -1,13 +1,16 @@
 use crate::common::Embedding;
+use futures::{Stream, StreamExt};
 use std::error::Error;
 use std::future::Future;
+use std::num::NonZeroUsize;
+use std::pin::pin;
 
 /// # [`EmbeddingStore`]
 /// This is the trait defined for abstracting storing embeddings
 /// into a vector database.
-pub trait EmbeddingStore {
+pub trait EmbeddingStore: Send + Sync {
     /// The custom error type for the store
-    type ErrorType: Error;
+    type ErrorType: Error + Send + Sync;
     /// # [`EmbeddingStore::store`]
     /// This method is used to store a single embedding in the store.
     ///
@@ -27,4 +30,187 @@ pub trait EmbeddingStore {
         &self,
         embeddings: Vec<Embedding>,
     ) -> impl Future<Output = Result<(), Self::ErrorType>> + Send;
+
+    /// # [`EmbeddingStore::store_with_id`]
+    /// The same as [`EmbeddingStore::store`] but returns the id of the stored row so it can
+    /// be correlated with the document it came from. By default [`EmbeddingStore::store`] is
+    /// called and [`StoredEmbeddingId::Unknown`] is returned, stores which know their ids
+    /// override this.
+    ///
+    /// # Arguments
+    /// * `embedding`: [`Embedding`] - The embedding to store
+    ///
+    /// # Errors
+    /// * [`Self::ErrorType`] - If the operation failed.
+    ///
+    /// # Returns
+    /// * [`StoredEmbeddingId`] - the id of the stored row
+    fn store_with_id(
+        &self,
+        embedding: Embedding,
+    ) -> impl Future<Output = Result<StoredEmbeddingId, Self::ErrorType>> + Send {
+        async move {
+            self.store(embedding).await?;
+            Ok(StoredEmbeddingId::Unknown)
+        }
+    }
+
+    /// # [`EmbeddingStore::store_batch_with_ids`]
+    /// The same as [`EmbeddingStore::store_batch`] but returns the id of each stored row. By
+    /// default [`EmbeddingStore::store_batch`] is called and [`StoredEmbeddingId::Unknown`] is
+    /// returned for each embedding.
+    ///
+    /// # Arguments
+    /// * `embeddings`: [`Vec<Embedding>`] - The embeddings to store
+    ///
+    /// # Errors
+    /// * [`Self::ErrorType`] - If the operation failed.
+    ///
+    /// # Returns
+    /// * [`Vec<StoredEmbeddingId>`] - the ids of the stored rows in the order the embeddings were given
+    fn store_batch_with_ids(
+        &self,
+        embeddings: Vec<Embedding>,
+    ) -> impl Future<Output = Result<Vec<StoredEmbeddingId>, Self::ErrorType>> + Send {
+        async move {
+            let stored: usize = embeddings.len();
+            self.store_batch(embeddings).await?;
+            Ok(vec![StoredEmbeddingId::Unknown; stored])
+        }
+    }
+
+    /// # [`EmbeddingStore::store_stream`]
+    /// This method is used to store a stream of embeddings without collecting it first.
+    /// Embeddings are buffered into batches of at most `batch_size` which are each
+    /// stored with [`EmbeddingStore::store_batch`] before the next batch is read.
+    ///
+    /// # Arguments
+    /// * `embeddings`: impl [`Stream<Item = Embedding>`] - The embeddings to store
+    /// * `batch_size`: [`NonZeroUsize`] - The maximum number of embeddings held and stored at once
+    ///
+    /// # Errors
+    /// * [`Self::ErrorType`] - If storing a batch failed, batches before it will have been stored.
+    ///
+    /// # Returns
+    /// * [`()`] - indicating success
+    fn store_stream<S>(
+        &self,
+        embeddings: S,
+        batch_size: NonZeroUsize,
+    ) -> impl Future<Output = Result<(), Self::ErrorType>> + Send
+    where
+        S: Stream<Item = Embedding> + Send,
+    {
+        async move {
+            let mut batches = pin!(embeddings.chunks(batch_size.get()));
+            while let Some(batch) = batches.next().await {
+                self.store_batch(batch).await?;
+            }
+            Ok(())
+        }
+    }
+}
+
+/// # [`StoredEmbeddingId`]
+///
+/// The id of a row written by an [`EmbeddingStore`].
+///
+/// * [`StoredEmbeddingId::Int`] - the integer primary key of the row.
+/// * [`StoredEmbeddingId::Unknown`] - the store does not report the ids it writes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum StoredEmbeddingId {
+    Int(i64),
+    Unknown,
+}
+
+impl StoredEmbeddingId {
+    /// # [`StoredEmbeddingId::as_int`]
+    ///
+    /// # Returns
+    /// * [`Option<i64>`] - the id if the store reported one.
+    pub fn as_int(&self) -> Option<i64> {
+        match self {
+            StoredEmbeddingId::Int(id) => Some(*id),
+            StoredEmbeddingId::Unknown => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::Chunk;
+    use futures::stream;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+
+    #[tokio::test]
+    async fn store_stream_stores_bounded_batches() {
+        let produced = Arc::new(AtomicUsize::new(0));
+        let store = RecordingStore {
+            produced: produced.clone(),
+            batches: Mutex::new(Vec::new()),
+        };
+        let embeddings = stream::iter(0..7).map(move |i| {
+            produced.fetch_add(1, Ordering::SeqCst);
+            Embedding::new(Chunk::new(i.to_string()), vec![i as f32])
+        });
+
+        store
+            .store_stream(embeddings, NonZeroUsize::new(3).unwrap())
+            .await
+            .unwrap();
+
+        let batches = store.batches.lock().unwrap();
+        let sizes: Vec<usize> = batches.iter().map(|(batch, _)| batch.len()).collect();
+        assert_eq!(sizes, vec![3, 3, 1]);
+        // Each batch is stored before any more of the stream is read
+        let produced_when_stored: Vec<usize> = batches.iter().map(|(_, seen)| *seen).collect();
+        assert_eq!(produced_when_stored, vec![3, 6, 7]);
+        let contents: Vec<&str> = batches
+            .iter()
+            .flat_map(|(batch, _)| batch.iter().map(|embedding| embedding.chunk().content()))
+            .collect();
+        assert_eq!(contents, vec!["0", "1", "2", "3", "4", "5", "6"]);
+    }
+
+    #[tokio::test]
+    async fn ids_default_to_unknown() {
+        let store = RecordingStore {
+            produced: Arc::new(AtomicUsize::new(0)),
+            batches: Mutex::new(Vec::new()),
+        };
+        let embedding = Embedding::new(Chunk::new("text"), vec![1.0]);
+        let id = store.store_with_id(embedding.clone()).await.unwrap();
+        assert_eq!(id, StoredEmbeddingId::Unknown);
+        assert_eq!(id.as_int(), None);
+        let ids = store
+            .store_batch_with_ids(vec![embedding.clone(), embedding])
+            .await
+            .unwrap();
+        assert_eq!(ids, vec![StoredEmbeddingId::Unknown; 2]);
+        assert_eq!(store.batches.lock().unwrap().len(), 2);
+    }
+
+    // Store which records each batch along with how much of the stream had been produced
+    struct RecordingStore {
+        produced: Arc<AtomicUsize>,
+        batches: Mutex<Vec<(Vec<Embedding>, usize)>>,
+    }
+
+    impl EmbeddingStore for RecordingStore {
+        type ErrorType = std::io::Error;
+
+        async fn store(&self, embedding: Embedding) -> Result<(), Self::ErrorType> {
+            self.store_batch(vec![embedding]).await
+        }
+
+        async fn store_batch(&self, embeddings: Vec<Embedding>) -> Result<(), Self::ErrorType> {
+            let produced: usize = self.produced.load(Ordering::SeqCst);
+            self.batches.lock().unwrap().push((embeddings, produced));
+            Ok(())
+        }
+    }
 }
@@ -0,0 +1,273 @@
+#![cfg(feature = "serde")]
+/// Serde Round Trip Test
+///
+/// Checks that the config and report types survive a trip through JSON when the `serde` feature
+/// is enabled, and pins down the representation of the values users are most likely to write by
+/// hand in a config file.
+use rag_toolchain::chains::*;
+use rag_toolchain::chunkers::*;
+use rag_toolchain::clients::*;
+use rag_toolchain::common::{Chunk, ScoredChunk, TokenUsage};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::{json, Value};
+use std::fmt::Debug;
+use std::num::NonZeroU32;
+use std::time::Duration;
+use uuid::Uuid;
+
+fn round_trip<T>(value: T) -> Value
+where
+    T: Serialize + DeserializeOwned + PartialEq + Debug,
+{
+    let json: Value = serde_json::to_value(&value).unwrap();
+    let read_back: T = serde_json::from_value(json.clone()).unwrap();
+    assert_eq!(read_back, value);
+    json
+}
+
+#[test]
+fn client_types_round_trip() {
+    round_trip(PromptMessage::SystemMessage("system".into()));
+    // A chat history is saved as a list of messages
+    round_trip(vec![
+        PromptMessage::HumanMessage("question".into()),
+        PromptMessage::AIMessage("answer".into()),
+    ]);
+    // Messages without metadata are written as they were before metadata existed
+    assert_eq!(
+        round_trip(PromptMessage::HumanMessage("question".into())),
+        json!({"human_message": "question"})
+    );
+    assert_eq!(
+        round_trip(PromptMessage::HumanMessage(
+            MessageBody::from("question").with_meta(MessageMeta::default().with_name("alice"))
+        )),
+        json!({"human_message": {"content": "question", "meta": {"name": "alice"}}})
+    );
+    round_trip(PromptMessage::AIMessage(
+        MessageBody::from("answer").with_meta(
+            MessageMeta::default()
+                .with_id("message-1")
+                .with_created_at(std::time::SystemTime::now()),
+        ),
+    ));
+    round_trip(PromptMessage::MultiModalHumanMessage(vec![
+        ContentPart::Text("what is this".into()),
+        ContentPart::Image(ImageSource::Url("https://example.com/cat.png".into())),
+        ContentPart::Image(ImageSource::Base64 {
+            media_type: "image/png".into(),
+            data: "aGVsbG8=".into(),
+        }),
+    ]));
+    round_trip(DetailedChatResponse {
+        message: PromptMessage::AIMessage("answer".into()),
+        request_id: Uuid::new_v4(),
+        provider_request_id: Some("req_123".into()),
+        system_fingerprint: None,
+        usage: Some(TokenUsage::new(12, 6)),
+        finish_reason: Some(FinishReason::Other("eos".into())),
+    });
+    round_trip(ScoredChunk::new(Chunk::new("text"), 0.75));
+    round_trip(ReproducibilityReport {
+        fingerprints_matched: Some(true),
+        outputs_matched: false,
+        diverged_at: Some(4),
+    });
+
+    let json: Value = round_trip(RequestContext {
+        endpoint: "https://api.openai.com/v1/chat/completions".into(),
+        model: Some("gpt-4o".into()),
+        elapsed: Duration::from_millis(1500),
+        attempt: 2,
+    });
+    assert_eq!(json["elapsed"], json!(1500));
+    assert_eq!(round_trip(EmbeddingTaskType::Query), json!("query"));
+}
+
+#[test]
+fn chain_types_round_trip() {
+    let timings = Timings {
+        retrieval: Some(Duration::from_millis(12)),
+        time_to_first_token: None,
+        generation: Some(Duration::from_millis(340)),
+    };
+    let json: Value = round_trip(timings);
+    assert_eq!(
+        json,
+        json!({"retrieval": 12, "time_to_first_token": null, "generation": 340})
+    );
+
+    round_trip(ChainResponse {
+        message: PromptMessage::AIMessage("answer".into()),
+        request_id: Uuid::new_v4(),
+        provider_request_id: None,
+        timings,
+        chunks_used: 3,
+        chunks_dropped: 2,
+        usage: Some(TokenUsage::new(120, 40)),
+        finish_reason: Some(FinishReason::Length),
+        warnings: vec![ChainWarning::SummaryFailed("rate limited".into())],
+    });
+    round_trip(RagResponse {
+        message: PromptMessage::AIMessage("answer".into()),
+        sources: vec![ScoredChunk::new(Chunk::new("source"), 0.75)],
+        chunks_dropped: 1,
+    });
+    round_trip(RetrievalLimit::TopK(NonZeroU32::new(5).unwrap()));
+    round_trip(RetrievalLimit::Budget(
+        ContextBudget::new(4000).with_fetch_k(NonZeroU32::new(30).unwrap()),
+    ));
+    round_trip(
+        PromptTemplate::new("Sources:\n{{context}}Question: {{question}}")
+            .with_chunk_template("[{{index}}] {{content}}\n"),
+    );
+    assert_eq!(
+        round_trip(ConcurrencyMode::Interleaved),
+        json!("interleaved")
+    );
+    assert_eq!(
+        round_trip(UnresolvedVariableMode::LeaveAsIs),
+        json!("leave_as_is")
+    );
+    assert_eq!(
+        round_trip(HistoryPolicy::TokenBudget(6000)),
+        json!({"token_budget": 6000})
+    );
+    assert_eq!(
+        round_trip(HistoryPolicy::Summarize {
+            budget_tokens: 6000,
+            keep_last_n: 4
+        }),
+        json!({"summarize": {"budget_tokens": 6000, "keep_last_n": 4}})
+    );
+}
+
+#[test]
+fn chunker_types_round_trip() {
+    assert_eq!(round_trip(ChunkSizeUnit::Tokens), json!("tokens"));
+    assert_eq!(round_trip(HeadingLevel::H2), json!("h2"));
+}
+
+#[test]
+#[cfg(any(feature = "openai-chat", feature = "anthropic"))]
+fn capabilities_round_trip() {
+    round_trip(ModelCapabilities {
+        supports_temperature: true,
+        supports_json_mode: true,
+        supports_tools: false,
+        supports_logprobs: false,
+        supports_vision: true,
+        max_context: 128_000,
+        max_output: 4_096,
+    });
+}
+
+#[test]
+#[cfg(feature = "openai-chat")]
+fn openai_chat_types_round_trip() {
+    assert_eq!(round_trip(ChatOptions::default()), json!({}));
+    assert_eq!(
+        round_trip(
+            ChatOptions::builder()
+                .temperature(0.5)
+                .max_tokens(256)
+                .stop(vec!["END".into()])
+                .build()
+        ),
+        json!({"temperature": 0.5, "max_tokens": 256, "stop": ["END"]})
+    );
+}
+
+#[test]
+#[cfg(feature = "anthropic")]
+fn anthropic_types_round_trip() {
+    assert_eq!(
+        round_trip(SystemPromptMode::Concatenated),
+        json!("concatenated")
+    );
+}
+
+#[test]
+#[cfg(feature = "pg_vector")]
+fn pg_vector_types_round_trip() {
+    use rag_toolchain::retrievers::*;
+    use rag_toolchain::stores::*;
+    use std::num::NonZeroUsize;
+
+    assert_eq!(round_trip(DistanceFunction::Cosine), json!("cosine"));
+    assert_eq!(round_trip(DistanceFunction::L2), json!("l2"));
+    assert_eq!(
+        round_trip(DistanceFunction::InnerProduct),
+        json!("inner_product")
+    );
+    assert_eq!(round_trip(VectorPrecision::F16), json!("f16"));
+    assert_eq!(
+        round_trip(IndexType::Hnsw {
+            m: 16,
+            ef_construction: 64
+        }),
+        json!({"hnsw": {"m": 16, "ef_construction": 64}})
+    );
+    round_trip(IndexType::IvfFlat { lists: 100 });
+    round_trip(IndexParameters {
+        hnsw_ef_search: NonZeroU32::new(80),
+        ivfflat_probes: None,
+    });
+    assert_eq!(
+        round_trip(HybridWeights::default()),
+        json!({"vector": 1.0, "full_text": 1.0, "rrf_k": 60})
+    );
+
+    round_trip(DeleteOptions {
+        dry_run: true,
+        limit: NonZeroUsize::new(50).unwrap(),
+        batch_size: NonZeroUsize::new(10).unwrap(),
+    });
+    round_trip(DeleteReport {
+        matched: 7,
+        deleted: 0,
+    });
+    round_trip(StoreOutcome {
+        rows_written: 2,
+        ids: vec![1, 2],
+    });
+    round_trip(
+        MetadataFilter::key("source")
+            .eq("docs")
+            .and(MetadataFilter::key("year").gte(2020))
+            .or(MetadataFilter::contains(json!({"tags": ["rust"]})).not()),
+    );
+    round_trip(RetrieveExplanation {
+        query: "query".into(),
+        rewritten_query: "query".into(),
+        sql: "SELECT 1".into(),
+        distance_operator: "<=>".into(),
+        summary: QueryPlanSummary {
+            index_used: true,
+            index_name: Some("embeddings_hnsw".into()),
+            sequential_scan: false,
+            rows_scanned: 10,
+            execution_ms: 0.25,
+        },
+        plan: json!([{"Plan": {"Node Type": "Index Scan"}}]),
+    });
+}
+
+#[test]
+#[cfg(feature = "analysis")]
+fn analysis_types_round_trip() {
+    use rag_toolchain::analysis::*;
+    use rag_toolchain::common::Embedding;
+    use std::num::NonZeroUsize;
+
+    let kmeans = KMeans::new(NonZeroUsize::new(2).unwrap())
+        .with_seed(7)
+        .with_representatives(1);
+    round_trip(kmeans.clone());
+
+    let embeddings: Vec<Embedding> = [[0.0, 0.0], [0.1, 0.0], [5.0, 5.0], [5.1, 5.0]]
+        .into_iter()
+        .map(|vector| Embedding::new(Chunk::new("text"), vector.to_vec()))
+        .collect();
+    round_trip(kmeans.fit(&embeddings).unwrap());
+}
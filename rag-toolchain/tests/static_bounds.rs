@@ -0,0 +1,337 @@
+/// Static Bounds Test
+///
+/// Compile time checks that the public types, and the futures returned by the public async APIs,
+/// are Send and Sync so they can be used on multi-threaded runtimes e.g. inside `tokio::spawn`.
+///
+/// The generic functions below are never called, they only need to compile. As they are generic
+/// over the traits they also check that the bounds on the traits are enough for any implementation.
+use rag_toolchain::chains::*;
+use rag_toolchain::chunkers::*;
+use rag_toolchain::clients::*;
+use rag_toolchain::common::*;
+use rag_toolchain::loaders::*;
+use rag_toolchain::retrievers::*;
+use rag_toolchain::stores::*;
+use std::num::{NonZeroU32, NonZeroUsize};
+
+fn assert_send<T: Send>(_: &T) {}
+fn assert_send_sync<T: Send + Sync>() {}
+
+#[test]
+fn public_types_are_send_and_sync() {
+    assert_send_sync::<Chunk>();
+    assert_send_sync::<Embedding>();
+    assert_send_sync::<InvocationContext>();
+    assert_send_sync::<EmbeddingModelMetadata>();
+    assert_send_sync::<Box<dyn TokenizerWrapper>>();
+    assert_send_sync::<SystemClock>();
+    assert_send_sync::<std::sync::Arc<dyn Clock>>();
+    assert_send_sync::<PromptMessage>();
+    assert_send_sync::<DetailedChatResponse>();
+    assert_send_sync::<FinishReason>();
+    assert_send_sync::<ReproducibilityReport>();
+    assert_send_sync::<RequestContext>();
+    assert_send_sync::<EmbeddingTaskType>();
+    assert_send_sync::<ChainResponse>();
+    assert_send_sync::<RagResponse>();
+    assert_send_sync::<ContextBudget>();
+    assert_send_sync::<RetrievalLimit>();
+    assert_send_sync::<RetrievalStrategy>();
+    assert_send_sync::<PromptTemplate>();
+    assert_send_sync::<DeduplicateBySimilarity>();
+    assert_send_sync::<GroupByMetadataKey>();
+    assert_send_sync::<std::sync::Arc<dyn ChunkPostProcessor>>();
+    assert_send_sync::<PromptVariables>();
+    assert_send_sync::<PromptVariableError>();
+    assert_send_sync::<Timings>();
+    assert_send_sync::<CharacterChunker>();
+    assert_send_sync::<TokenChunker>();
+    assert_send_sync::<ChunkBatchError<TokenChunkingError>>();
+    assert_send_sync::<TokenChunkingError>();
+    assert_send_sync::<ContentDefinedChunker>();
+    assert_send_sync::<RecursiveCharacterChunker>();
+    assert_send_sync::<RecursiveChunkingError>();
+    assert_send_sync::<SentenceChunker>();
+    assert_send_sync::<SentenceChunkingError>();
+    assert_send_sync::<MarkdownChunker>();
+    assert_send_sync::<MarkdownChunkingError>();
+    assert_send_sync::<ContentDefinedChunkingError>();
+    assert_send_sync::<SingleFileSource>();
+    assert_send_sync::<DirectorySource>();
+    assert_send_sync::<DirectorySourceError>();
+    assert_send_sync::<LoadedFile>();
+    assert_send_sync::<DictionaryExpander>();
+    assert_send_sync::<PassThroughRewriter>();
+}
+
+#[test]
+#[cfg(any(
+    feature = "openai-embeddings",
+    feature = "openai-chat",
+    feature = "anthropic"
+))]
+fn secret_types_are_send_and_sync() {
+    assert_send_sync::<SecretString>();
+    assert_send_sync::<SecretError>();
+    assert_send_sync::<EnvSecretProvider>();
+    assert_send_sync::<std::sync::Arc<dyn SecretProvider>>();
+}
+
+#[test]
+#[cfg(feature = "openai-embeddings")]
+fn openai_embedding_types_are_send_and_sync() {
+    assert_send_sync::<OpenAIEmbeddingClient>();
+    assert_send_sync::<OpenAIEmbeddingConfigError>();
+    assert_send_sync::<RetryPolicy>();
+    assert_send_sync::<HttpConfig>();
+    assert_send_sync::<HttpConfigError>();
+    assert_send_sync::<OpenAIError>();
+}
+
+#[test]
+#[cfg(feature = "openai-chat")]
+fn openai_chat_types_are_send_and_sync() {
+    assert_send_sync::<OpenAIChatCompletionClient>();
+    assert_send_sync::<OpenAIError>();
+    assert_send_sync::<ChatOptions>();
+    assert_send_sync::<ChatHistoryChain<OpenAIChatCompletionClient>>();
+}
+
+#[test]
+#[cfg(feature = "openai-stream")]
+fn openai_stream_types_are_send() {
+    // The stream is read through &mut so only needs to move between threads
+    fn assert_send_type<T: Send>() {}
+    assert_send_type::<OpenAICompletionStream>();
+    assert_send_type::<ChatHistoryStream<OpenAICompletionStream>>();
+    assert_send_sync::<CompletionStreamValue>();
+    assert_send_sync::<StreamedChatHistoryChain<OpenAIChatCompletionClient>>();
+}
+
+#[test]
+#[cfg(feature = "anthropic")]
+fn anthropic_types_are_send_and_sync() {
+    assert_send_sync::<AnthropicChatCompletionClient>();
+    assert_send_sync::<AnthropicError>();
+    assert_send_sync::<AnthropicResponse>();
+    assert_send_sync::<ChatHistoryChain<AnthropicChatCompletionClient>>();
+}
+
+#[test]
+#[cfg(feature = "anthropic-stream")]
+fn anthropic_stream_types_are_send() {
+    fn assert_send_type<T: Send>() {}
+    assert_send_type::<AnthropicCompletionStream>();
+    assert_send_sync::<CompletionStreamValue>();
+}
+
+#[test]
+#[cfg(feature = "ollama")]
+fn ollama_types_are_send_and_sync() {
+    fn assert_send_type<T: Send>() {}
+    assert_send_sync::<OllamaChatCompletionClient>();
+    assert_send_sync::<OllamaEmbeddingClient>();
+    assert_send_sync::<OllamaError>();
+    assert_send_type::<OllamaCompletionStream>();
+    assert_send_sync::<ChatHistoryChain<OllamaChatCompletionClient>>();
+    assert_send_sync::<StreamedChatHistoryChain<OllamaChatCompletionClient>>();
+}
+
+#[test]
+#[cfg(feature = "html")]
+fn html_types_are_send_and_sync() {
+    assert_send_sync::<HtmlSource>();
+    assert_send_sync::<HtmlPage>();
+    assert_send_sync::<HtmlSourceError>();
+}
+
+#[test]
+#[cfg(feature = "sqlite_vec")]
+fn sqlite_vec_types_are_send_and_sync() {
+    assert_send_sync::<SqliteVectorStore>();
+    assert_send_sync::<SqliteVectorStoreError>();
+    assert_send_sync::<SqliteRetrieverError<std::io::Error>>();
+    assert_send_sync::<DistanceFunction>();
+}
+
+#[test]
+#[cfg(feature = "analysis")]
+fn analysis_types_are_send_and_sync() {
+    use rag_toolchain::analysis::*;
+    assert_send_sync::<KMeans>();
+    assert_send_sync::<ClusterReport>();
+    assert_send_sync::<ClusteringError>();
+}
+
+#[test]
+fn in_memory_types_are_send_and_sync() {
+    assert_send_sync::<InMemoryVectorStore>();
+    assert_send_sync::<InMemoryVectorStoreError>();
+    assert_send_sync::<InMemoryRetrieverError<std::io::Error>>();
+    assert_send_sync::<DistanceFunction>();
+    assert_send_sync::<MetadataFilter>();
+}
+
+#[test]
+#[cfg(feature = "pg_vector")]
+fn pg_vector_types_are_send_and_sync() {
+    assert_send_sync::<PostgresVectorStore>();
+    assert_send_sync::<PostgresVectorStoreError>();
+    assert_send_sync::<HookError>();
+    assert_send_sync::<StoreOutcome>();
+    assert_send_sync::<DeleteOptions>();
+    assert_send_sync::<DeleteReport>();
+    assert_send_sync::<VectorPrecision>();
+    assert_send_sync::<DistanceFunction>();
+    assert_send_sync::<IndexType>();
+    assert_send_sync::<IndexParameters>();
+    assert_send_sync::<HybridWeights>();
+    assert_send_sync::<MetadataFilter>();
+    assert_send_sync::<RetrieveExplanation>();
+}
+
+#[allow(dead_code)]
+fn chain_futures_are_send<T, S, U>(
+    chain: &BasicRAGChain<T, U>,
+    streamed_chain: &BasicStreamedRAGChain<S, U>,
+    history_chain: &ChatHistoryChain<T>,
+    streamed_history_chain: &StreamedChatHistoryChain<S>,
+    message: PromptMessage,
+    context: &InvocationContext,
+) where
+    T: AsyncChatClient,
+    S: AsyncStreamedChatClient,
+    <S::Item as ChatCompletionStream>::Item: StreamedText,
+    U: AsyncRetriever,
+{
+    let top_k = NonZeroU32::new(2).unwrap();
+    assert_send(&chain.invoke_chain(message.clone(), top_k));
+    assert_send(&chain.invoke_chain_with_context(message.clone(), top_k, context));
+    assert_send(&streamed_chain.invoke_chain(message.clone(), top_k));
+    assert_send(&streamed_chain.invoke_chain_with_context(message.clone(), top_k, context));
+    assert_send(&streamed_history_chain.invoke_chain(message.clone()));
+    assert_send(&history_chain.invoke_chain(message.clone()));
+    assert_send(&history_chain.invoke_chain_with_context(message, context));
+    assert_send(&history_chain.history_snapshot());
+    assert_send(&history_chain.reset());
+}
+
+#[allow(dead_code)]
+fn trait_futures_are_send<E, C, S, R, St, L>(
+    embedding_client: &E,
+    chat_client: &C,
+    stream: &mut S,
+    retriever: &R,
+    store: &St,
+    loader: &L,
+    context: &InvocationContext,
+) where
+    E: AsyncEmbeddingClient,
+    C: AsyncChatClient + AsyncStreamedChatClient,
+    S: ChatCompletionStream,
+    R: AsyncRetriever,
+    St: EmbeddingStore,
+    L: AsyncLoadSource,
+{
+    let top_k = NonZeroU32::new(2).unwrap();
+    assert_send(&embedding_client.generate_embedding(Chunk::new("text")));
+    assert_send(&embedding_client.generate_embeddings(vec![Chunk::new("text")]));
+    assert_send(&AsyncChatClient::invoke(chat_client, Vec::new()));
+    assert_send(&AsyncChatClient::invoke_with_context(
+        chat_client,
+        Vec::new(),
+        context,
+    ));
+    assert_send(&chat_client.invoke_stream(Vec::new()));
+    assert_send(&chat_client.invoke_stream_with_context(Vec::new(), context));
+    assert_send(&stream.next());
+    assert_send(&retriever.retrieve("text", top_k));
+    assert_send(&retriever.retrieve_with_context("text", top_k, context));
+    assert_send(&store.store(Embedding::new(Chunk::new("text"), vec![0.0])));
+    assert_send(&store.store_batch(Vec::new()));
+    assert_send(&store.store_stream(
+        futures::stream::empty::<Embedding>(),
+        NonZeroUsize::new(1).unwrap(),
+    ));
+    assert_send(&loader.load());
+}
+
+#[allow(dead_code)]
+fn rewriting_futures_are_send<R: AsyncRetriever, W: QueryRewriter>(
+    retriever: &RewritingRetriever<R, W>,
+    rewriter: &W,
+) {
+    let top_k = NonZeroU32::new(2).unwrap();
+    assert_send(&retriever.retrieve("text", top_k));
+    assert_send(&rewriter.rewrite("text"));
+}
+
+#[allow(dead_code)]
+fn reranking_futures_are_send<R: AsyncRetriever, K: AsyncReranker>(
+    retriever: &RerankingRetriever<R, K>,
+    reranker: &K,
+) {
+    let top_k = NonZeroU32::new(2).unwrap();
+    assert_send(&retriever.retrieve("text", top_k));
+    assert_send(&reranker.rerank("text", Vec::new(), top_k));
+}
+
+#[allow(dead_code)]
+fn multi_query_futures_are_send<R: AsyncRetriever, C: AsyncChatClient>(
+    retriever: &MultiQueryRetriever<R, C>,
+    context: &InvocationContext,
+) {
+    let top_k = NonZeroU32::new(2).unwrap();
+    assert_send(&retriever.retrieve("text", top_k));
+    assert_send(&retriever.retrieve_with_context("text", top_k, context));
+}
+
+#[allow(dead_code)]
+fn in_memory_futures_are_send<T>(
+    retriever: &InMemoryRetriever<T>,
+    store: &InMemoryVectorStore,
+    filter: &MetadataFilter,
+) where
+    T: AsyncEmbeddingClient,
+    T::ErrorType: 'static,
+{
+    let top_k = NonZeroU32::new(2).unwrap();
+    assert_send(&retriever.retrieve("text", top_k));
+    assert_send(&retriever.retrieve_with_filter("text", top_k, filter));
+    assert_send(&retriever.retrieve_with_predicate("text", top_k, |_| true));
+    assert_send(&store.save("store.json"));
+    assert_send(&InMemoryVectorStore::try_load("store.json"));
+}
+
+#[cfg(feature = "pg_vector")]
+#[allow(dead_code)]
+fn postgres_futures_are_send<T, W>(
+    retriever: &PostgresVectorRetriever<T, W>,
+    store: &PostgresVectorStore,
+    filter: &MetadataFilter,
+) where
+    T: AsyncEmbeddingClient,
+    T::ErrorType: 'static,
+    W: QueryRewriter,
+{
+    let top_k = NonZeroU32::new(2).unwrap();
+    assert_send(&retriever.retrieve("text", top_k));
+    assert_send(&retriever.retrieve_with_filter("text", top_k, filter));
+    assert_send(&retriever.explain_retrieve("text", top_k));
+    assert_send(&PostgresVectorStore::try_open("table"));
+    assert_send(&store.sample(NonZeroUsize::new(1).unwrap(), 0));
+    assert_send(&store.delete_by_metadata(filter, DeleteOptions::default()));
+    assert_send(&PostgresVectorStore::try_new(
+        "table",
+        OpenAIEmbeddingModel::TextEmbedding3Small,
+    ));
+}
+
+#[cfg(all(feature = "analysis", feature = "pg_vector"))]
+#[allow(dead_code)]
+fn analysis_futures_are_send(store: &PostgresVectorStore) {
+    use rag_toolchain::analysis::*;
+    let k = NonZeroUsize::new(2).unwrap();
+    assert_send(&cluster_table(store, k, k));
+    assert_send(&KMeans::new(k).cluster_table(store, k));
+}